@@ -0,0 +1,338 @@
+use crate::block::Block;
+use crate::wallet::Address;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+pub const CSV_HEADER: &str =
+    "timestamp,block_height,txn_id,direction,counterparty,amount,running_balance";
+// The most events kept for an address, the oldest are folded into a single
+// Carried event past it.
+pub const MAX_ADDRESS_EVENTS: usize = 1000;
+
+/// How an event moved coins relative to the address it was recorded for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+    Reward,
+    Burn,
+    Fee,
+    // The balance the events trimmed off the start of the history sum to.
+    Carried,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// A single change to an address' balance, recorded as blocks are applied to
+/// the ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressEvent {
+    pub timestamp: u128,
+    pub block_height: u128,
+    pub txn_id: String,
+    pub direction: Direction,
+    pub counterparty: String,
+    pub amount: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityRow {
+    pub timestamp: u128,
+    pub block_height: u128,
+    pub txn_id: String,
+    pub direction: Direction,
+    pub counterparty: String,
+    pub amount: u128,
+    pub running_balance: u128,
+}
+
+/// The full activity of an address with a running balance, reconciled against
+/// the balance held in the ledger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityReport {
+    pub address: String,
+    pub rows: Vec<ActivityRow>,
+    pub final_balance: u128,
+    pub ledger_balance: u128,
+    pub reconciled: bool,
+}
+
+#[derive(Debug)]
+pub struct ActivityError(String);
+
+impl Direction {
+    pub fn to_str(&self) -> &str {
+        match self {
+            Self::In => "in",
+            Self::Out => "out",
+            Self::Reward => "reward",
+            Self::Burn => "burn",
+            Self::Fee => "fee",
+            Self::Carried => "carried",
+        }
+    }
+
+    /// Whether the event adds to the address' balance.
+    pub fn is_credit(&self) -> bool {
+        match self {
+            Self::In | Self::Reward | Self::Carried => true,
+            Self::Out | Self::Burn | Self::Fee => false,
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn from_str(format: &str) -> Option<ExportFormat> {
+        match format.to_lowercase().as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl AddressEvent {
+    /// Returns the events in a block keyed by the canonical address they belong to.
+    /// Transfers produce an `Out` event for the sender and an `In` event for the
+    /// receiver, the block reward and the fees the miner collects produce `Reward`
+    /// events and each fee a `Fee` event for its payer. A coinbase txn only
+    /// records the block reward, so produces none of its own, and evidence
+    /// against a claim and downtime announcements move no coins.
+    pub fn from_block(block: &Block) -> Vec<(String, AddressEvent)> {
+        let mut events = vec![];
+        let block_height = block.header.block_height;
+
+        let miner = block
+            .header
+            .block_reward
+            .miner
+            .as_ref()
+            .map(|miner| Address::canonicalize(miner));
+        block.txns.iter().for_each(|(txn_id, txn)| {
+            if txn.is_coinbase() || txn.is_slashing_evidence() || txn.is_planned_downtime() {
+                return;
+            }
+            // Every txn pays its fee to the miner in the native coin.
+            if let Some(miner) = miner.as_ref().filter(|_| txn.txn_fee > 0) {
+                let sender = Address::canonicalize(&txn.sender_address);
+                events.push((
+                    sender.clone(),
+                    AddressEvent {
+                        timestamp: txn.txn_timestamp,
+                        block_height,
+                        txn_id: txn_id.clone(),
+                        direction: Direction::Fee,
+                        counterparty: miner.clone(),
+                        amount: txn.txn_fee,
+                    },
+                ));
+                events.push((
+                    miner.clone(),
+                    AddressEvent {
+                        timestamp: txn.txn_timestamp,
+                        block_height,
+                        txn_id: txn_id.clone(),
+                        direction: Direction::Reward,
+                        counterparty: sender,
+                        amount: txn.txn_fee,
+                    },
+                ));
+            }
+            // Reinstatement bonds are recorded by the ledger when they are locked
+            // and released, since a rejected request moves no coins.
+            if txn.is_claim_reinstatement() {
+                return;
+            }
+            // Activity reconciles against the native balance, token txns don't
+            // move the native coin.
+            if txn.is_token_txn() {
+                return;
+            }
+
+            let receiver = Address::canonicalize(&txn.receiver_address);
+
+            let sender = Address::canonicalize(&txn.sender_address);
+            events.push((
+                sender.clone(),
+                AddressEvent {
+                    timestamp: txn.txn_timestamp,
+                    block_height,
+                    txn_id: txn_id.clone(),
+                    direction: Direction::Out,
+                    counterparty: receiver.clone(),
+                    amount: txn.txn_amount,
+                },
+            ));
+            events.push((
+                receiver,
+                AddressEvent {
+                    timestamp: txn.txn_timestamp,
+                    block_height,
+                    txn_id: txn_id.clone(),
+                    direction: Direction::In,
+                    counterparty: sender,
+                    amount: txn.txn_amount,
+                },
+            ));
+        });
+
+        if let Some(miner) = block.header.block_reward.miner.as_ref() {
+            events.push((
+                Address::canonicalize(miner),
+                AddressEvent {
+                    timestamp: block.header.timestamp,
+                    block_height,
+                    txn_id: String::new(),
+                    direction: Direction::Reward,
+                    counterparty: String::new(),
+                    amount: block.header.block_reward.amount,
+                },
+            ));
+        }
+
+        events
+    }
+}
+
+/// Appends `event` to an address' events, folding the oldest of them into a
+/// Carried event so that no more than MAX_ADDRESS_EVENTS are kept and the
+/// history still reconciles with the balance.
+pub fn record_event(events: &mut Vec<AddressEvent>, event: AddressEvent) {
+    events.push(event);
+    if events.len() <= MAX_ADDRESS_EVENTS {
+        return;
+    }
+
+    let excess = events.len() - MAX_ADDRESS_EVENTS + 1;
+    let folded: Vec<AddressEvent> = events.drain(..excess).collect();
+    let carried = folded.iter().fold(0u128, |balance, event| {
+        if event.direction.is_credit() {
+            balance.saturating_add(event.amount)
+        } else {
+            balance.saturating_sub(event.amount)
+        }
+    });
+    let last = folded.last().unwrap();
+    events.insert(
+        0,
+        AddressEvent {
+            timestamp: last.timestamp,
+            block_height: last.block_height,
+            txn_id: String::new(),
+            direction: Direction::Carried,
+            counterparty: String::new(),
+            amount: carried,
+        },
+    );
+}
+
+impl ActivityRow {
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.timestamp,
+            self.block_height,
+            self.txn_id,
+            self.direction.to_str(),
+            self.counterparty,
+            self.amount,
+            self.running_balance
+        )
+    }
+}
+
+impl ActivityReport {
+    /// Builds the report, failing if the history would take the balance below
+    /// zero or if the final running balance doesn't match the ledger balance.
+    pub fn new(
+        address: &str,
+        events: &[AddressEvent],
+        ledger_balance: u128,
+    ) -> Result<ActivityReport, ActivityError> {
+        let mut balance: u128 = 0;
+        let mut rows = vec![];
+        for event in events.iter() {
+            balance = if event.direction.is_credit() {
+                balance.checked_add(event.amount)
+            } else {
+                balance.checked_sub(event.amount)
+            }
+            .ok_or_else(|| {
+                ActivityError(format!(
+                    "activity for {} is inconsistent at block {}",
+                    address, event.block_height
+                ))
+            })?;
+
+            rows.push(ActivityRow {
+                timestamp: event.timestamp,
+                block_height: event.block_height,
+                txn_id: event.txn_id.clone(),
+                direction: event.direction,
+                counterparty: event.counterparty.clone(),
+                amount: event.amount,
+                running_balance: balance,
+            });
+        }
+
+        if balance != ledger_balance {
+            return Err(ActivityError(format!(
+                "activity for {} sums to {} but the ledger balance is {}",
+                address, balance, ledger_balance
+            )));
+        }
+
+        Ok(ActivityReport {
+            address: address.to_string(),
+            rows,
+            final_balance: balance,
+            ledger_balance,
+            reconciled: true,
+        })
+    }
+
+    /// Renders the rows as CSV. The last line is a comment recording the
+    /// reconciliation against the ledger balance.
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec![CSV_HEADER.to_string()];
+        self.rows.iter().for_each(|row| lines.push(row.to_csv()));
+        lines.push(format!(
+            "# reconciled={} final_balance={} ledger_balance={}",
+            self.reconciled, self.final_balance, self.ledger_balance
+        ));
+        lines.join("\n")
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Writes the report to `path` and returns the number of rows written.
+    pub fn write(&self, format: ExportFormat, path: &str) -> Result<usize, Box<dyn Error>> {
+        let contents = match format {
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Json => self.to_string(),
+        };
+        fs::write(path, contents)?;
+        Ok(self.rows.len())
+    }
+}
+
+impl fmt::Display for ActivityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ActivityError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
@@ -0,0 +1,93 @@
+use crate::utils::take_switch;
+use std::fmt;
+
+pub const VERIFY_ON_START_FLAG: &str = "--verify-on-start";
+
+/// The first problem an audit of the chain db against the ledger found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditFinding {
+    Ok,
+    // The block on the chain at this height isn't in the chain db.
+    MissingBlock(u128),
+    // The block at this height isn't stored under its own hash or doesn't
+    // build on the block below it.
+    BrokenLink(u128),
+    // Replaying the chain gives a ledger that differs from the live one in
+    // these keys, the first of them written at this height.
+    LedgerDivergence { height: u128, keys: Vec<String> },
+    // The audit was cancelled before the block at this height.
+    Cancelled(u128),
+}
+
+/// The outcome of `Blockchain::audit`, the node's chain db and ledger are
+/// left as they were whatever it found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditReport {
+    pub blocks_checked: u128,
+    pub finding: AuditFinding,
+}
+
+impl AuditReport {
+    pub fn is_ok(&self) -> bool {
+        self.finding == AuditFinding::Ok
+    }
+
+    /// What the operator could do about the finding. The audit never repairs
+    /// anything itself.
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match self.finding {
+            AuditFinding::MissingBlock(_) | AuditFinding::BrokenLink(_) => {
+                Some("resync the chain from the network")
+            }
+            AuditFinding::LedgerDivergence { .. } => Some(
+                "rebuild the ledger by replaying the archive, or resync state from the network",
+            ),
+            AuditFinding::Ok | AuditFinding::Cancelled(_) => None,
+        }
+    }
+
+    /// Removes the verify on start flag from the command line arguments and
+    /// returns whether it was set along with the remaining arguments.
+    pub fn verify_on_start_from_args(mut args: Vec<String>) -> (bool, Vec<String>) {
+        let verify = take_switch(&mut args, VERIFY_ON_START_FLAG);
+        (verify, args)
+    }
+}
+
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.finding {
+            AuditFinding::Ok => write!(
+                f,
+                "Audit ok: {} blocks checked, the ledger matches the chain",
+                self.blocks_checked
+            )?,
+            AuditFinding::MissingBlock(height) => write!(
+                f,
+                "Audit failed: block {} is missing from the chain db",
+                height
+            )?,
+            AuditFinding::BrokenLink(height) => write!(
+                f,
+                "Audit failed: block {} doesn't link to the chain below it",
+                height
+            )?,
+            AuditFinding::LedgerDivergence { height, keys } => write!(
+                f,
+                "Audit failed: the ledger diverges from the chain at height {} in {}",
+                height,
+                keys.join(", ")
+            )?,
+            AuditFinding::Cancelled(height) => write!(
+                f,
+                "Audit cancelled at height {} after {} blocks",
+                height, self.blocks_checked
+            )?,
+        }
+        if let Some(suggestion) = self.suggestion() {
+            write!(f, ", {}", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use crate::state::NetworkState;
+use crate::wallet::Address;
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+use sha256::digest_bytes;
+
+// How many proofs a node holds until it validates the header they're made
+// against.
+pub const MAX_PENDING_PROOFS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EntryKind {
+    Credit,
+    Debit,
+}
+
+/// An entry of the ledger and the hashes along the way from it to the root.
+/// Entries are sorted by kind and then address, the index is the entry's
+/// position in that order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerLeaf {
+    pub kind: EntryKind,
+    pub address: String,
+    pub amount: u128,
+    pub index: u64,
+    pub branch: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryProof {
+    Present(LedgerLeaf),
+    // The entries either side of where the missing one would sort, with no
+    // entry in between. None past either end of the ledger.
+    Absent {
+        before: Option<LedgerLeaf>,
+        after: Option<LedgerLeaf>,
+    },
+}
+
+/// An address's credits and debits proven against the ledger root in the
+/// header of the block at `block_height`, for a light client to check
+/// against the headers it has instead of trusting the node that sent it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceProof {
+    pub address: String,
+    pub block_height: u128,
+    pub leaf_count: u64,
+    pub root: String,
+    pub credit: EntryProof,
+    pub debit: EntryProof,
+}
+
+/// The balances the header of the block at `block_height` commits to, those
+/// of the ledger the block is applied to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommittedLedger {
+    pub block_height: u128,
+    pub credits: LinkedHashMap<String, u128>,
+    pub debits: LinkedHashMap<String, u128>,
+}
+
+fn leaf_hash(kind: EntryKind, address: &str, amount: u128) -> String {
+    digest_bytes(format!("leaf,{:?},{},{}", kind, address, amount).as_bytes())
+}
+
+fn node_hash(left: &str, right: &str) -> String {
+    digest_bytes(format!("node,{},{}", left, right).as_bytes())
+}
+
+// The leaf count is committed to along with the tree, so an entry can be
+// proven to be the first or the last one.
+fn commit(leaf_count: u64, tree_root: &str) -> String {
+    digest_bytes(format!("{},{}", leaf_count, tree_root).as_bytes())
+}
+
+fn sorted_entries(
+    credits: &LinkedHashMap<String, u128>,
+    debits: &LinkedHashMap<String, u128>,
+) -> Vec<(EntryKind, String, u128)> {
+    let mut entries: Vec<(EntryKind, String, u128)> = credits
+        .iter()
+        .map(|(address, amount)| (EntryKind::Credit, address.clone(), *amount))
+        .chain(
+            debits
+                .iter()
+                .map(|(address, amount)| (EntryKind::Debit, address.clone(), *amount)),
+        )
+        .collect();
+    entries.sort();
+    entries
+}
+
+// Every level of the tree from the leaves up. A node without a sibling is
+// carried up to the next level as it is.
+fn tree_levels(entries: &[(EntryKind, String, u128)]) -> Vec<Vec<String>> {
+    let mut levels = vec![entries
+        .iter()
+        .map(|(kind, address, amount)| leaf_hash(*kind, address, *amount))
+        .collect::<Vec<String>>()];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+
+    levels
+}
+
+fn branch(levels: &[Vec<String>], index: usize) -> Vec<String> {
+    let mut index = index;
+    let mut branch = vec![];
+    for level in &levels[..levels.len() - 1] {
+        let sibling = if index % 2 == 1 { index - 1 } else { index + 1 };
+        if let Some(hash) = level.get(sibling) {
+            branch.push(hash.clone());
+        }
+        index /= 2;
+    }
+
+    branch
+}
+
+/// The root of the sorted-entry Merkle tree over the credits and debits.
+pub fn ledger_root(
+    credits: &LinkedHashMap<String, u128>,
+    debits: &LinkedHashMap<String, u128>,
+) -> String {
+    let entries = sorted_entries(credits, debits);
+    let levels = tree_levels(&entries);
+    let tree_root = levels.last().unwrap().first().cloned().unwrap_or_default();
+    commit(entries.len() as u64, &tree_root)
+}
+
+// The root a leaf hashes up to through its branch, None if the branch doesn't
+// fit a tree of leaf_count leaves.
+fn root_from_branch(leaf: &LedgerLeaf, leaf_count: u64) -> Option<String> {
+    if leaf.index >= leaf_count {
+        return None;
+    }
+
+    let mut hash = leaf_hash(leaf.kind, &leaf.address, leaf.amount);
+    let mut index = leaf.index;
+    let mut width = leaf_count;
+    let mut siblings = leaf.branch.iter();
+    while width > 1 {
+        if index % 2 == 1 {
+            hash = node_hash(siblings.next()?, &hash);
+        } else if index + 1 < width {
+            hash = node_hash(&hash, siblings.next()?);
+        }
+        index /= 2;
+        width = (width + 1) / 2;
+    }
+    if siblings.next().is_some() {
+        return None;
+    }
+
+    Some(commit(leaf_count, &hash))
+}
+
+// The amount of the entry `entry` proves, 0 if it proves there's no entry.
+fn verify_entry(
+    entry: &EntryProof,
+    key: (EntryKind, &str),
+    leaf_count: u64,
+    root: &str,
+) -> Option<u128> {
+    let proven = |leaf: &LedgerLeaf| root_from_branch(leaf, leaf_count).as_deref() == Some(root);
+    match entry {
+        EntryProof::Present(leaf) => {
+            if (leaf.kind, leaf.address.as_str()) == key && proven(leaf) {
+                Some(leaf.amount)
+            } else {
+                None
+            }
+        }
+        EntryProof::Absent { before, after } => {
+            let before_sorts_first = before.as_ref().map_or(true, |leaf| {
+                proven(leaf) && (leaf.kind, leaf.address.as_str()) < key
+            });
+            let after_sorts_last = after.as_ref().map_or(true, |leaf| {
+                proven(leaf) && (leaf.kind, leaf.address.as_str()) > key
+            });
+            let adjacent = match (before, after) {
+                (Some(before), Some(after)) => after.index == before.index + 1,
+                (None, Some(after)) => after.index == 0,
+                (Some(before), None) => before.index + 1 == leaf_count,
+                (None, None) => leaf_count == 0 && root == commit(0, ""),
+            };
+            if before_sorts_first && after_sorts_last && adjacent {
+                Some(0)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// The balance a proof proves against `committed_root`, the ledger root in
+/// the header of the block at the proof's height. None if the proof doesn't
+/// hold, an address with no entries has a balance of 0. Needs no db.
+pub fn verify_balance_proof(proof: &BalanceProof, committed_root: &str) -> Option<u128> {
+    if proof.root != committed_root {
+        return None;
+    }
+
+    let address = proof.address.as_str();
+    let credits = verify_entry(
+        &proof.credit,
+        (EntryKind::Credit, address),
+        proof.leaf_count,
+        committed_root,
+    )?;
+    let debits = verify_entry(
+        &proof.debit,
+        (EntryKind::Debit, address),
+        proof.leaf_count,
+        committed_root,
+    )?;
+
+    Some(credits.checked_sub(debits).unwrap_or(0))
+}
+
+impl CommittedLedger {
+    /// Proves the credits and debits of `address` against the ledger root.
+    pub fn prove(&self, address: &str) -> BalanceProof {
+        let address = Address::canonicalize(address);
+        let entries = sorted_entries(&self.credits, &self.debits);
+        let levels = tree_levels(&entries);
+        let tree_root = levels.last().unwrap().first().cloned().unwrap_or_default();
+        let leaf = |index: usize| {
+            let (kind, address, amount) = &entries[index];
+            LedgerLeaf {
+                kind: *kind,
+                address: address.clone(),
+                amount: *amount,
+                index: index as u64,
+                branch: branch(&levels, index),
+            }
+        };
+        let entry = |kind: EntryKind| {
+            let key = (kind, address.as_str());
+            match entries.binary_search_by(|(k, a, _)| (*k, a.as_str()).cmp(&key)) {
+                Ok(index) => EntryProof::Present(leaf(index)),
+                Err(index) => EntryProof::Absent {
+                    before: index.checked_sub(1).map(leaf),
+                    after: Some(index).filter(|index| *index < entries.len()).map(leaf),
+                },
+            }
+        };
+
+        BalanceProof {
+            address: address.clone(),
+            block_height: self.block_height,
+            leaf_count: entries.len() as u64,
+            root: commit(entries.len() as u64, &tree_root),
+            credit: entry(EntryKind::Credit),
+            debit: entry(EntryKind::Debit),
+        }
+    }
+}
+
+impl NetworkState {
+    /// Proves the balance of `address` against the root of the ledger as it
+    /// is, which the header of the next block commits to. None until the
+    /// genesis block is applied.
+    pub fn balance_proof(&self, address: &str) -> Option<BalanceProof> {
+        // The genesis block takes the reward state from block 0 to 1.
+        let block_height = self.get_reward_state().current_block;
+        if block_height == 0 {
+            return None;
+        }
+        let committed = CommittedLedger {
+            block_height,
+            credits: self.get_credits(),
+            debits: self.get_debits(),
+        };
+
+        Some(committed.prove(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(
+        credits: &[(&str, u128)],
+        debits: &[(&str, u128)],
+        block_height: u128,
+    ) -> CommittedLedger {
+        let map = |entries: &[(&str, u128)]| {
+            entries
+                .iter()
+                .map(|(address, amount)| (address.to_string(), *amount))
+                .collect::<LinkedHashMap<String, u128>>()
+        };
+        CommittedLedger {
+            block_height,
+            credits: map(credits),
+            debits: map(debits),
+        }
+    }
+
+    fn committed_root(ledger: &CommittedLedger) -> String {
+        ledger_root(&ledger.credits, &ledger.debits)
+    }
+
+    #[test]
+    fn proof_verifies_to_the_balance() {
+        let ledger = ledger(
+            &[
+                ("alice", 100),
+                ("bob", 40),
+                ("carol", 7),
+                ("dave", 1),
+                ("erin", 3),
+            ],
+            &[("alice", 30), ("carol", 7)],
+            5,
+        );
+        let root = committed_root(&ledger);
+        for (address, balance) in [("alice", 70), ("bob", 40), ("carol", 0), ("erin", 3)].iter() {
+            let proof = ledger.prove(address);
+            assert_eq!(proof.root, root);
+            assert_eq!(verify_balance_proof(&proof, &root), Some(*balance));
+        }
+    }
+
+    #[test]
+    fn tampered_leaf_or_branch_fails() {
+        let ledger = ledger(
+            &[("alice", 100), ("bob", 40), ("carol", 7)],
+            &[("alice", 30)],
+            5,
+        );
+        let root = committed_root(&ledger);
+        let proof = ledger.prove("alice");
+
+        let mut inflated = proof.clone();
+        if let EntryProof::Present(leaf) = &mut inflated.credit {
+            leaf.amount = 1000;
+        }
+        assert_eq!(verify_balance_proof(&inflated, &root), None);
+
+        let mut forged = proof.clone();
+        if let EntryProof::Present(leaf) = &mut forged.debit {
+            leaf.branch[0] = digest_bytes(b"forged");
+        }
+        assert_eq!(verify_balance_proof(&forged, &root), None);
+
+        // Nor can a debit be hidden by claiming it's absent.
+        let mut hidden = proof.clone();
+        hidden.debit = EntryProof::Absent {
+            before: None,
+            after: None,
+        };
+        assert_eq!(verify_balance_proof(&hidden, &root), None);
+
+        // Or another address's entry passed off as the one asked about.
+        let mut swapped = ledger.prove("bob");
+        swapped.address = "alice".to_string();
+        assert_eq!(verify_balance_proof(&swapped, &root), None);
+    }
+
+    #[test]
+    fn absent_address_proves_a_zero_balance() {
+        let ledger = ledger(&[("alice", 100), ("carol", 7)], &[("alice", 30)], 5);
+        let root = committed_root(&ledger);
+        for address in ["aaron", "bob", "zoe"].iter() {
+            let proof = ledger.prove(address);
+            assert!(matches!(proof.credit, EntryProof::Absent { .. }));
+            assert!(matches!(proof.debit, EntryProof::Absent { .. }));
+            assert_eq!(verify_balance_proof(&proof, &root), Some(0));
+        }
+
+        // A gap is only proven between neighbouring entries.
+        let mut gapped = ledger.prove("bob");
+        gapped.credit = EntryProof::Absent {
+            before: None,
+            after: match ledger.prove("carol").credit {
+                EntryProof::Present(leaf) => Some(leaf),
+                _ => None,
+            },
+        };
+        assert_eq!(verify_balance_proof(&gapped, &root), None);
+
+        let empty = ledger(&[], &[], 0);
+        let proof = empty.prove("alice");
+        assert_eq!(
+            verify_balance_proof(&proof, &committed_root(&empty)),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn proof_fails_once_the_root_moves_to_a_newer_block() {
+        let older = ledger(&[("alice", 100), ("bob", 40)], &[], 5);
+        let newer = ledger(&[("alice", 100), ("bob", 40)], &[("alice", 10)], 6);
+        let proof = older.prove("bob");
+        assert_eq!(
+            verify_balance_proof(&proof, &committed_root(&older)),
+            Some(40)
+        );
+        assert_eq!(verify_balance_proof(&proof, &committed_root(&newer)), None);
+    }
+}
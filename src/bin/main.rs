@@ -1,36 +1,67 @@
 use hex;
 use libp2p::gossipsub::IdentTopic as Topic;
-use libp2p::multiaddr::multiaddr;
-use libp2p::Multiaddr;
-use log::info;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{Multiaddr, PeerId};
+use log::{error, info, warn, LevelFilter};
 use rand::Rng;
 use ritelinked::LinkedHashMap;
-use simplelog::{Config, LevelFilter, WriteLogger};
 use std::fs::File;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
-use tokio::io::AsyncBufReadExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
-use vrrb_lib::block::Block;
-use vrrb_lib::blockchain::{Blockchain, InvalidBlockErrorReason, StateComponent};
-use vrrb_lib::handler::{CommandHandler, MessageHandler};
-use vrrb_lib::miner::Miner;
+use vrrb_lib::audit::AuditReport;
+use vrrb_lib::balance_proof::{verify_balance_proof, BalanceProof, MAX_PENDING_PROOFS};
+use vrrb_lib::blockchain::{Blockchain, StateComponent};
+use vrrb_lib::blockchain_handler::{BlockchainHandler, BlockchainSenders, Handled};
+use vrrb_lib::checkpoint::Checkpoint;
+use vrrb_lib::claim::{PlannedDowntime, MAX_DOWNTIME};
+#[cfg(feature = "cli")]
+use vrrb_lib::cli;
+use vrrb_lib::entropy;
+use vrrb_lib::handler::{
+    bounded_channel, send_or_dead_letter, ChannelConfig, CommandHandler, Inbox, MessageHandler,
+    THREAD_WAKEUP,
+};
+use vrrb_lib::logging::{LogOptions, BLOCKCHAIN_TARGET, MINER_TARGET, STATE_TARGET, SWARM_TARGET};
+use vrrb_lib::miner::{
+    AbandonmentConfig, AssemblyWindow, ElectionPacing, Miner, MiningCancel, QuorumConfig,
+};
 use vrrb_lib::network::chunkable::Chunkable;
 use vrrb_lib::network::command_utils::Command;
-use vrrb_lib::network::config_utils;
+use vrrb_lib::network::compact_block::{
+    CompactBlock, CompactBlockCache, Reconstruction, COMPACT_BLOCK_TIMEOUT,
+};
+use vrrb_lib::network::config_utils::{self, ListenConfig};
+use vrrb_lib::network::envelope;
+use vrrb_lib::network::forwarding::{SeenTxns, SEEN_TXNS_CAPACITY};
 use vrrb_lib::network::message_types::MessageType;
 use vrrb_lib::network::node::{Node, NodeAuth};
+use vrrb_lib::network::peer_table;
+use vrrb_lib::network::protocol::{EventLog, VrrbNetworkEvent, EVENT_FLUSH_INTERVAL};
+use vrrb_lib::network::supervisor::ConnectionSupervisor;
+use vrrb_lib::network::topics::{GossipTopic, LEGACY_TOPIC, NETWORK_ID};
+use vrrb_lib::replay::{self, CommandRecorder, Handler, MinerOutcome, Outcome, REPLAY_COMMAND};
 use vrrb_lib::reward::Category;
 use vrrb_lib::reward::RewardState;
+use vrrb_lib::schedule::{ScheduleEvent, Schedules};
+use vrrb_lib::snapshot::{DataDirLock, NodeSnapshot};
 use vrrb_lib::state::Components;
-use vrrb_lib::state::Ledger;
-use vrrb_lib::state::NetworkState;
-use vrrb_lib::wallet::WalletAccount;
+use vrrb_lib::state::{NetworkState, SharedNetworkState};
+use vrrb_lib::txn::{Txn, MIN_TXN_FEE};
+use vrrb_lib::verifiable::Verifiable;
+use vrrb_lib::wallet::{Address, TxnStatus, WalletAccount, MAX_ADDRESSES};
 
-pub const VALIDATOR_THRESHOLD: f64 = 0.60;
 pub const NANO: u128 = 1;
 pub const MICRO: u128 = NANO * 1000;
 pub const MILLI: u128 = MICRO * 1000;
 pub const SECOND: u128 = MILLI * 1000;
+// How long QUIT waits for the miner and blockchain threads to flush, in seconds.
+pub const SHUTDOWN_TIMEOUT: u64 = 5;
+// Where the node keeps its dbs, peer table and wallet schedules.
+pub const DATA_DIR: &str = "./data/vrrb";
 
 #[async_std::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -38,47 +69,191 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Setup log file and db files
     let mut rng = rand::thread_rng();
     let node_type = NodeAuth::Full;
+    // Light nodes don't keep the ledger up, they have full nodes prove balances instead.
+    let light_client = matches!(node_type, NodeAuth::Light | NodeAuth::UltraLight);
+    // --log-level and --log-format may appear anywhere, the remaining arguments are positional.
+    let (log_options, args) = LogOptions::from_args(std::env::args().collect());
+    // --bootstrap <file> starts the chain from a published checkpoint instead of syncing it.
+    let (bootstrap_path, args) = Checkpoint::path_from_args(args);
+    // --validator-threshold <share> sets the share of active validators needed to confirm a txn.
+    let (quorum, args) = QuorumConfig::from_args(args);
+    // --min-peers <n> sets how many connections the node re-dials known peers to keep.
+    let (min_peers, args) = ConnectionSupervisor::min_peers_from_args(args);
+    // --min-relay-fee <fee> sets the lowest fee a txn has to pay to enter the pool.
+    let (min_relay_fee, args) = Miner::min_relay_fee_from_args(args);
+    // --max-state-response <bytes> caps the ledger or archive the node sends a syncing peer.
+    let (max_state_response, args) = Blockchain::max_state_response_from_args(args);
+    // --assembly-window <ms> and --assembly-min-txns <n> set how long a miner that wins with
+    // too few confirmed txns waits for more.
+    let (assembly, args) = AssemblyWindow::from_args(args);
+    // --election-retry <ms> sets how long a miner that lost the pointer election waits before
+    // checking it again.
+    let (pacing, args) = ElectionPacing::from_args(args);
+    // --channel-capacity <n> and --backpressure-wait <ms> size the bounded channels between
+    // threads and set how long a send to a full one waits before dropping the message.
+    let (channel_config, args) = ChannelConfig::from_args(args);
+    // --verify-on-start audits the chain db against the ledger before the node joins the network.
+    let (verify_on_start, args) = AuditReport::verify_on_start_from_args(args);
+    // --record <log> records the commands the blockchain, miner and state threads receive.
+    let (record_path, args) = CommandRecorder::path_from_args(args);
+    // --genesis-hash <header hash> accepts only that genesis block from peers.
+    let (network_genesis, args) = Blockchain::network_genesis_from_args(args);
+    // --addresses <n> derives the wallet's first n receive addresses.
+    let (address_count, args) = WalletAccount::address_count_from_args(args);
+    // --reject-legacy-addresses rejects the old unchecksummed addresses instead of resolving
+    // them to their canonical form.
+    let (accept_legacy, args) = Address::accept_legacy_from_args(args);
+    Address::set_accepts_legacy(accept_legacy);
+    // --listen <ip>[:<port>|:<from>-<to>] and --external-addr <multiaddr>, both repeatable, set
+    // where the swarm listens and what it announces. --topic-namespace <name> keeps the node's
+    // topics apart from nodes in other namespaces, --legacy-topic also uses the legacy topic.
+    let (listen_config, args) = ListenConfig::from_args(args)?;
+    // --import-snapshot <archive> [--force] restores the data dir from a snapshot and exits
+    // instead of starting a node, a snapshot is never imported under a running one.
+    let (snapshot_import, args) = NodeSnapshot::import_from_args(args);
+    // `vrrb replay <log>` replays a recorded run on temp dbs instead of starting a node.
+    if args.get(1).map(|arg| arg.as_str()) == Some(REPLAY_COMMAND) {
+        let log_path = args.get(2).cloned().ok_or("usage: vrrb replay <log>")?;
+        let replay_dir = std::env::temp_dir().join(format!("vrrb_replay_{}", rng.gen::<u32>()));
+        std::fs::create_dir_all(&replay_dir)?;
+        let replayed = replay::replay(&log_path, &replay_dir);
+        let _ = std::fs::remove_dir_all(&replay_dir);
+        match replayed {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if let Some((archive_path, force)) = snapshot_import {
+        match NodeSnapshot::import(&archive_path, DATA_DIR, force) {
+            Ok(manifest) => {
+                manifest
+                    .members
+                    .iter()
+                    .for_each(|member| println!("Imported {}", member.name));
+                println!("Start the node to run from the imported files");
+            }
+            Err(e) => {
+                println!("Error importing snapshot: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    std::fs::create_dir_all(DATA_DIR)?;
+    // Held until the node exits, see NodeSnapshot::import.
+    let _data_dir_lock = DataDirLock::acquire(DATA_DIR)?;
+    // --abandonment-rounds <n> sets how many of the network's abandonment timeouts pass without
+    // a block before a claim is voted abandoned.
+    let (abandonment, args) = AbandonmentConfig::from_args(args);
     let log_file_suffix: u8 = rng.gen();
-    let log_file_path = if let Some(path) = std::env::args().nth(3) {
+    let log_file_path = if let Some(path) = args.get(3).cloned() {
         path
     } else {
-        std::fs::create_dir_all("./data/vrrb")?;
-        format!("./data/vrrb/vrrb_log_file_{}.log", log_file_suffix)
+        format!("{}/vrrb_log_file_{}.log", DATA_DIR, log_file_suffix)
+    };
+    // LOGLEVEL changes the levels set here while the node runs.
+    let log_levels = log_options.init(File::create(log_file_path).unwrap()).ok();
+    // The miner and blockchain threads draw from the recorded seed so a replay draws the same.
+    let recorder = match record_path {
+        Some(path) => Some(CommandRecorder::create(&path, rng.gen())?),
+        None => None,
     };
-    let _ = WriteLogger::init(
-        LevelFilter::Info,
-        Config::default(),
-        File::create(log_file_path).unwrap(),
-    );
     //____________________________________________________________________________________________________
 
     // ___________________________________________________________________________________________________
     // setup message and command sender/receiver channels for communication betwen various threads
-    let (to_blockchain_sender, mut to_blockchain_receiver) = mpsc::unbounded_channel();
-    let (to_miner_sender, mut to_miner_receiver) = mpsc::unbounded_channel();
+    let (to_blockchain_sender, to_blockchain_receiver) = mpsc::unbounded_channel();
+    let (to_miner_sender, to_miner_receiver) = mpsc::unbounded_channel();
     let (to_message_sender, to_message_receiver) = mpsc::unbounded_channel();
     let (from_message_sender, from_message_receiver) = mpsc::unbounded_channel();
     let (command_sender, command_receiver) = mpsc::unbounded_channel();
     let (to_swarm_sender, mut to_swarm_receiver) = mpsc::unbounded_channel();
-    let (to_state_sender, mut to_state_receiver) = mpsc::unbounded_channel();
+    let (to_state_sender, mut to_state_receiver) = bounded_channel("state", channel_config);
+    let (to_wallet_sender, mut to_wallet_receiver) = mpsc::unbounded_channel();
+    // The blockchain and miner threads block on their channels between commands.
+    let mut to_blockchain_receiver = Inbox::new(to_blockchain_receiver);
+    let mut to_miner_receiver = Inbox::new(to_miner_receiver);
+    let wakeup = Duration::from_millis(THREAD_WAKEUP);
+    // The miner and blockchain threads report back here once they've flushed on QUIT.
+    let (shutdown_sender, shutdown_receiver) = std::sync::mpsc::channel();
     //____________________________________________________________________________________________________
 
-    let wallet = if let Some(secret_key) = std::env::args().nth(4) {
+    let mut wallet = if let Some(secret_key) = args.get(4).cloned() {
         WalletAccount::restore_from_private_key(secret_key)
     } else {
         WalletAccount::new()
     };
+    if let Some(address_count) = address_count {
+        wallet.get_new_addresses(address_count);
+    }
+    // Addresses derived with NEWADDR before the node last stopped.
+    let addresses_path = format!("{}/addresses_{}.json", DATA_DIR, wallet.get_address(1));
+    wallet.restore_addresses(&addresses_path);
+    let contacts_path = format!("{}/contacts_{}.json", DATA_DIR, wallet.get_address(1));
+    wallet.restore_contacts(&contacts_path);
+    let outbox_path = format!("{}/outbox_{}.json", DATA_DIR, wallet.get_address(1));
+    wallet.restore_outbox(&outbox_path);
 
-    let mut rng = rand::thread_rng();
-    let file_suffix: u32 = rng.gen();
-    let path = if let Some(path) = std::env::args().nth(2) {
+    // The dbs are kept at the same paths across restarts, so the node picks up
+    // where it stopped and a snapshot of the data dir holds only the live ones.
+    let path = if let Some(path) = args.get(2).cloned() {
         path
     } else {
-        format!("./data/vrrb/test_{}.db", file_suffix)
+        format!("{}/ledger.db", DATA_DIR)
     };
 
-    let network_state = NetworkState::restore(&path);
-    let reward_state = RewardState::start();
+    let mut network_state = NetworkState::restore(&path);
+    let mut reward_state = RewardState::start();
+
+    // Bootstrap before the swarm starts listening so peers never see the node
+    // without its checkpointed chain.
+    let mut blockchain = Blockchain::open(&format!("{}/chain.db", DATA_DIR));
+    blockchain.network_genesis = network_genesis;
+    // A restarted node goes on from the chain in its chain db, a checkpoint
+    // only bootstraps an empty one.
+    if blockchain.genesis.is_some() {
+        if let Some(bootstrap_path) = bootstrap_path {
+            println!(
+                "The chain db already has a chain, not bootstrapping from {}",
+                bootstrap_path
+            );
+        }
+    } else if let Some(bootstrap_path) = bootstrap_path {
+        let tip = blockchain.bootstrap_from_file(&bootstrap_path, &mut network_state)?;
+        println!(
+            "Bootstrapped chain from {} up to height {}",
+            bootstrap_path, tip
+        );
+    }
+    if let Some(block) = blockchain.tip().cloned() {
+        reward_state = network_state.reward_state;
+        if let Err(e) = send_or_dead_letter(&to_miner_sender, Command::ConfirmedBlock(block)) {
+            println!("Error sending the chain's tip to miner: {:?}", e);
+        }
+    }
+    // A node doesn't run on a chain whose headers don't link.
+    if let Err(index) = blockchain.verify_header_chain() {
+        let height = blockchain
+            .chain
+            .iter()
+            .nth(index)
+            .map_or(0, |h| h.block_height);
+        return Err(format!(
+            "header chain broken at height {}, bootstrap the node again or remove {}/chain.db",
+            height, DATA_DIR
+        )
+        .into());
+    }
+    if verify_on_start {
+        println!("{}", blockchain.audit(&network_state));
+    }
+    // Shared by the blockchain thread, the miner and the wallet loop, the
+    // blockchain thread is the only one that writes to it.
+    let network_state = SharedNetworkState::new(network_state);
 
     //____________________________________________________________________________________________________
     // Node initialization
@@ -93,7 +268,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         command_receiver,
     );
 
-    let mut node = Node::new(node_type.clone(), command_handler, to_message_handler);
+    let events = EventLog::open(&format!("{}/events.db", DATA_DIR));
+    // The swarm thread flushes the log, the blockchain thread logs the blocks it finalizes.
+    let blockchain_events = events.clone();
+    let mut node = Node::new(
+        node_type.clone(),
+        command_handler,
+        to_message_handler,
+        events.clone(),
+        Node::load_key(&format!("{}/node.key", DATA_DIR))?,
+    );
+    node.topics = listen_config.topics(NETWORK_ID);
     let node_id = node.id.clone();
     let node_key = node.key.clone();
     //____________________________________________________________________________________________________
@@ -105,26 +290,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         command_sender.clone(),
         node_id.clone(),
         node_key.clone(),
+        node_type.clone(),
         wallet.pubkey.clone().to_string(),
         wallet.clone().get_address(1),
-        "events.db".to_string(),
+        events.clone(),
+        format!("{}/peers.json", DATA_DIR),
+        &listen_config,
     )
-    .await;
-
-    let port = rand::thread_rng().gen_range(9292, 19292);
-    let addr: Multiaddr = multiaddr!(Ip4([0, 0, 0, 0]), Tcp(port as u16));
-    println!("{:?}", &addr);
-
-    swarm.listen_on(addr.clone()).unwrap();
-    swarm
-        .behaviour_mut()
-        .kademlia
-        .add_address(&node.id.clone(), addr.clone());
+    .await?;
     //____________________________________________________________________________________________________
 
     //____________________________________________________________________________________________________
     // Dial peer if provided
-    if let Some(to_dial) = std::env::args().nth(1) {
+    if let Some(to_dial) = args.get(1).cloned() {
         let dialing = to_dial.clone();
         match to_dial.parse() {
             Ok(to_dial) => match swarm.dial_addr(to_dial) {
@@ -136,42 +314,220 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             Err(err) => println!("Failed to parse address to dial {:?}", err), //
         }
     }
+
+    // Warm start from the peers seen before the node was last stopped.
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let known_peers = swarm.behaviour().peer_table.reachable(now, "");
+    peer_table::add_peers(&mut swarm, &known_peers);
+
+    // Re-dial the bootstrap peer and known peers whenever connections drop below the minimum.
+    let mut supervisor = ConnectionSupervisor::new(min_peers);
+    if let Some(to_dial) = args.get(1) {
+        supervisor.add_target(to_dial);
+    }
+    known_peers
+        .iter()
+        .for_each(|(_, addr)| supervisor.add_target(addr));
     //____________________________________________________________________________________________________
 
     //____________________________________________________________________________________________________
     // Swarm event thread
     tokio::task::spawn(async move {
+        let mut redial = tokio::time::interval(Duration::from_secs(1));
+        let mut flush_events = tokio::time::interval(Duration::from_secs(EVENT_FLUSH_INTERVAL));
         loop {
             let evt = {
                 tokio::select! {
-                    event = swarm.next() => {
-                        info!("Unhandled Swarm Event: {:?}", event);
-                        None
+                    event = swarm.next_event() => {
+                        // Peers rejected by the version handshake while the event was processed.
+                        let rejected: Vec<PeerId> = swarm.behaviour_mut().rejected_peers.drain().collect();
+                        rejected.into_iter().for_each(|peer_id| swarm.ban_peer_id(peer_id));
+                        match event {
+                            SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established } => {
+                                if num_established.get() == 1 {
+                                    events.log(VrrbNetworkEvent::PeerConnected {
+                                        peer_id: peer_id.to_string(),
+                                    });
+                                }
+                                supervisor.connected(&peer_id.to_string());
+                                if endpoint.is_dialer() {
+                                    supervisor.dial_succeeded(&endpoint.get_remote_address().to_string());
+                                }
+                                // Ask peers this node dialed for more peers once they subscribe,
+                                // so it doesn't rely on a single one while syncing.
+                                if endpoint.is_dialer() && num_established.get() == 1 {
+                                    swarm.behaviour_mut().awaiting_peers.insert(peer_id);
+                                }
+                                None
+                            }
+                            SwarmEvent::ConnectionClosed { peer_id, num_established, .. } => {
+                                if num_established == 0 {
+                                    supervisor.disconnected(&peer_id.to_string());
+                                    events.log(VrrbNetworkEvent::PeerDisconnected {
+                                        peer_id: peer_id.to_string(),
+                                    });
+                                    info!(
+                                        target: SWARM_TARGET,
+                                        "Disconnected from {}, {} of {} peers connected",
+                                        peer_id,
+                                        supervisor.peer_count(),
+                                        supervisor.min_peers
+                                    );
+                                }
+                                None
+                            }
+                            SwarmEvent::UnreachableAddr { address, .. }
+                            | SwarmEvent::UnknownPeerUnreachableAddr { address, .. } => {
+                                let now = SystemTime::now()
+                                    .duration_since(UNIX_EPOCH)
+                                    .unwrap()
+                                    .as_nanos();
+                                supervisor.dial_failed(&address.to_string(), now);
+                                None
+                            }
+                            event => {
+                                info!(target: SWARM_TARGET, "Unhandled Swarm Event: {:?}", event);
+                                None
+                            }
+                        }
                     },
                     command = to_swarm_receiver.recv() => {
-                        if let Some(command) = command {
-                            match command {
-                                Command::SendMessage(message) => {
-                                    Some(message)
+                        command
+                    }
+                    _ = flush_events.tick() => {
+                        events.flush();
+                        None
+                    }
+                    _ = redial.tick() => {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_nanos();
+                        if let Some(to_dial) = supervisor.next_dial(now) {
+                            match to_dial.parse::<Multiaddr>() {
+                                Ok(addr) => match swarm.dial_addr(addr) {
+                                    Ok(_) => info!(target: SWARM_TARGET, "Re-dialing {}", to_dial),
+                                    Err(e) => {
+                                        info!(
+                                            target: SWARM_TARGET,
+                                            "Re-dial {} failed: {:?}",
+                                            to_dial,
+                                            e
+                                        );
+                                        supervisor.dial_failed(&to_dial, now);
+                                    }
+                                },
+                                Err(e) => {
+                                    info!(
+                                        target: SWARM_TARGET,
+                                        "Failed to parse address to re-dial {:?}",
+                                        e
+                                    );
+                                    supervisor.dial_failed(&to_dial, now);
                                 }
-                                _ => {None}
                             }
-                        } else {
-                            None
                         }
+                        None
                     }
                 }
             };
 
-            if let Some(message) = evt {
-                let encoded = hex::encode(message);
-                if let Err(e) = swarm
-                    .behaviour_mut()
-                    .gossipsub
-                    .publish(Topic::new("test-net"), encoded)
-                {
-                    info!("Error sending to network: {:?}", e);
+            let message = match evt {
+                Some(Command::SendMessage(message)) => Some(message),
+                Some(Command::SendPeers(requestor)) => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_nanos();
+                    let message = MessageType::PeersResponseMessage {
+                        peers: swarm.behaviour().peer_table.reachable(now, &requestor),
+                        requestor,
+                        sender_id: swarm.behaviour().node_id.clone(),
+                    };
+                    Some(message.as_bytes())
+                }
+                Some(Command::DialPeers(peers, sender_id)) => {
+                    let peers = swarm
+                        .behaviour_mut()
+                        .peer_exchange
+                        .accept(&sender_id, &peers);
+                    peer_table::add_peers(&mut swarm, &peers);
+                    peers
+                        .iter()
+                        .for_each(|(_, addr)| supervisor.add_target(addr));
+                    None
+                }
+                Some(Command::GetPeers) => {
+                    println!(
+                        "Connected to {} peers, minimum {}",
+                        supervisor.peer_count(),
+                        supervisor.min_peers
+                    );
+                    None
+                }
+                Some(Command::ShowKnownPeers) => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_nanos();
+                    let peer_table = &swarm.behaviour().peer_table;
+                    println!("{} known peers", peer_table.peers.len());
+                    peer_table.peers.iter().rev().for_each(|(peer_id, record)| {
+                        println!(
+                            "{} at {}, last seen {}s ago",
+                            peer_id,
+                            record.addr,
+                            now.saturating_sub(record.last_seen) / SECOND
+                        );
+                    });
+                    None
+                }
+                _ => None,
+            };
+
+            if let Some(message) = message {
+                let message_type = if let Some(message_type) = MessageType::from_bytes(&message) {
+                    message_type
+                } else {
+                    info!(target: SWARM_TARGET, "Unable to route unknown message, dropping it");
+                    continue;
+                };
+
+                let topics = swarm.behaviour().topics.clone();
+                let topic = topics.for_message(&message_type);
+                // Older nodes don't subscribe to peer topics, an answer to a
+                // peer isn't gossiped to all of them on the legacy topic.
+                let addressed = GossipTopic::recipient(&message_type).is_some();
+                let payloads = match envelope::encode(message_type, &swarm.behaviour().node_id) {
+                    Ok(payloads) => payloads,
+                    Err(e) => {
+                        info!(target: SWARM_TARGET, "Unable to send to network: {}", e);
+                        continue;
+                    }
                 };
+
+                for encoded in payloads {
+                    if let Err(e) = swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .publish(Topic::new(topic.clone()), encoded.clone())
+                    {
+                        error!(target: SWARM_TARGET, "Error sending to network: {:?}", e);
+                    };
+
+                    if topics.legacy && !addressed {
+                        if let Err(e) = swarm
+                            .behaviour_mut()
+                            .gossipsub
+                            .publish(Topic::new(LEGACY_TOPIC), encoded)
+                        {
+                            error!(target: SWARM_TARGET, "Error sending to legacy topic: {:?}", e);
+                        };
+                    }
+                }
             }
         }
     });
@@ -188,272 +544,285 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     //____________________________________________________________________________________________________
     // Blockchain thread
-    let mut blockchain_network_state = network_state.clone();
-    let mut blockchain_reward_state = reward_state.clone();
+    // Raised as blocks are confirmed, so the miner drops a block it's still
+    // assembling at a height that's already confirmed.
+    let mining_cancel = MiningCancel::new();
+    let blockchain_senders = BlockchainSenders {
+        miner: to_miner_sender.clone(),
+        swarm: to_swarm_sender.clone(),
+        blockchain: to_blockchain_sender.clone(),
+        state: to_state_sender.clone(),
+        wallet: to_wallet_sender.clone(),
+    };
+    let mut blockchain_handler = BlockchainHandler::new(
+        blockchain,
+        network_state.clone(),
+        reward_state.clone(),
+        node_key.clone(),
+        node_type.clone(),
+        blockchain_senders,
+    );
+    blockchain_handler.max_state_response = max_state_response;
+    blockchain_handler.mining_cancel = mining_cancel.clone();
+    blockchain_handler.events = Some(blockchain_events);
     let blockchain_to_miner_sender = to_miner_sender.clone();
-    let blockchain_to_swarm_sender = to_swarm_sender.clone();
-    let blockchain_to_blockchain_sender = to_blockchain_sender.clone();
-    let blockchain_to_state_sender = to_state_sender.clone();
+    let blockchain_shutdown_sender = shutdown_sender.clone();
+    let blockchain_recorder = recorder.clone();
     thread::spawn(move || {
-        let mut rng = rand::thread_rng();
-        let file_suffix: u32 = rng.gen();
-        let mut blockchain = Blockchain::new(&format!("./data/vrrb/test_{}.db", file_suffix));
+        if let Some(recorder) = &blockchain_recorder {
+            entropy::seed(recorder.seed());
+        }
+        // Raised by VERIFY CANCEL to stop a running audit.
+        let verify_cancel = Arc::new(AtomicBool::new(false));
+        let mut pending_proofs: Vec<(BalanceProof, String)> = vec![];
+        // Only a proof against the ledger root in a header this node has
+        // validated is shown, whichever peer sent it.
+        fn show_balance(blockchain: &Blockchain, proof: &BalanceProof, sender_id: &str) {
+            let balance = blockchain
+                .chain
+                .iter()
+                .rev()
+                .find(|header| header.block_height == proof.block_height)
+                .and_then(|header| header.ledger_root.as_ref())
+                .and_then(|root| verify_balance_proof(proof, root));
+            match balance {
+                Some(balance) => println!(
+                    "Balance of {} at height {}: {}",
+                    proof.address, proof.block_height, balance
+                ),
+                None => warn!(
+                    target: BLOCKCHAIN_TARGET,
+                    "balance proof doesn't verify peer={} address={} height={}",
+                    sender_id,
+                    proof.address,
+                    proof.block_height
+                ),
+            }
+        }
         loop {
-            let miner_sender = blockchain_to_miner_sender.clone();
-            let swarm_sender = blockchain_to_swarm_sender.clone();
-            let state_sender = blockchain_to_state_sender.clone();
-            let blockchain_sender = blockchain_to_blockchain_sender.clone();
-            // let blockchain_sender = blockchain_to_blockchain_sender.clone();
-            if let Ok(command) = to_blockchain_receiver.try_recv() {
+            blockchain_handler.check_sync_timeout();
+            if let Ok(command) = to_blockchain_receiver.recv_timeout(wakeup) {
+                let step = blockchain_recorder
+                    .as_ref()
+                    .map(|recorder| recorder.record(Handler::Blockchain, &command));
+                // The commands that change the chain or answer peers are the
+                // handler's, replay runs them through it too.
+                let command = match blockchain_handler.handle(command) {
+                    Handled::Applied(block) => {
+                        if let (Some(recorder), Some(step)) = (&blockchain_recorder, step) {
+                            let outcome =
+                                Outcome::of(&block, &blockchain_handler.network_state.read());
+                            recorder.applied(step, &outcome);
+                        }
+                        let (due, pending): (Vec<_>, Vec<_>) =
+                            pending_proofs.drain(..).partition(|(proof, _)| {
+                                proof.block_height <= block.header.block_height
+                            });
+                        pending_proofs = pending;
+                        due.iter().for_each(|(proof, sender_id)| {
+                            show_balance(&blockchain_handler.blockchain, proof, sender_id)
+                        });
+                        continue;
+                    }
+                    Handled::Done => continue,
+                    Handled::Unhandled(command) => command,
+                };
+                let blockchain = &mut blockchain_handler.blockchain;
+                let blockchain_network_state = &blockchain_handler.network_state;
                 match command {
-                    Command::PendingBlock(block, sender_id) => {
-                        if blockchain.updating_state {
-                            blockchain
-                                .future_blocks
-                                .insert(block.clone().header.last_hash, block.clone());
-                        } else {
-                            if let Err(e) = blockchain.process_block(
-                                &blockchain_network_state,
-                                &blockchain_reward_state,
-                                &block,
-                            ) {
-                                match e.details {
-                                    InvalidBlockErrorReason::BlockOutOfSequence => {
-                                        // Stash block in blockchain.future_blocks
-                                        // Request state update once. Set "updating_state" field
-                                        // in blockchain to true, so that it doesn't request it on
-                                        // receipt of new future blocks which will also be invalid.
-                                        if !blockchain.updating_state {
-                                            // send state request and set blockchain.updating state to true;
-                                            println!("Error: {:?}", e);
-                                            if let Some((_, v)) = blockchain.future_blocks.front() {
-                                                let message = MessageType::GetNetworkStateMessage {
-                                                    sender_id: node_id.clone().to_string(),
-                                                    requested_from: sender_id,
-                                                    requestor_node_type: node_type.clone(),
-                                                    lowest_block: v.header.block_height,
-                                                    component: StateComponent::All,
-                                                };
-
-                                                if let Err(e) = swarm_sender
-                                                    .send(Command::SendMessage(message.as_bytes()))
-                                                {
-                                                    println!("Error sending state update request to swarm sender: {:?}", e);
-                                                };
-
-                                                blockchain.updating_state = true;
-                                            }
-                                        }
-                                    }
-                                    _ => {
-                                        if !blockchain.updating_state {
-                                            let lowest_block = {
-                                                if let Some(block) = blockchain.child.clone() {
-                                                    block.clone()
-                                                } else {
-                                                    blockchain.genesis.clone().unwrap()
-                                                }
-                                            };
-                                            println!("Error: {:?}", e);
-                                            if block.header.block_height
-                                                > lowest_block.header.block_height + 1
-                                            {
-                                                let message = MessageType::GetNetworkStateMessage {
-                                                    sender_id: node_id.clone().to_string(),
-                                                    requested_from: sender_id,
-                                                    requestor_node_type: node_type.clone(),
-                                                    lowest_block: lowest_block.header.block_height,
-                                                    component: StateComponent::All,
-                                                };
-
-                                                if let Err(e) = swarm_sender
-                                                    .send(Command::SendMessage(message.as_bytes()))
-                                                {
-                                                    println!("Error sending state update request to swarm sender: {:?}", e);
-                                                };
-
-                                                blockchain.updating_state = true;
-                                            } else {
-                                                // Miner is out of consensus tell them to update their state.
-                                                let message = MessageType::InvalidBlockMessage {
-                                                    block_height: block.header.block_height,
-                                                    reason: e.details,
-                                                    miner_id: sender_id,
-                                                    sender_id: node_id.clone().to_string(),
-                                                };
-                                                if let Err(e) = swarm_sender
-                                                    .send(Command::SendMessage(message.as_bytes()))
-                                                {
-                                                    println!("Error sending state update request to swarm sender: {:?}", e);
-                                                };
-
-                                                blockchain
-                                                    .invalid
-                                                    .insert(block.hash.clone(), block.clone());
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Err(_) =
-                                    miner_sender.send(Command::InvalidBlock(block.clone()))
-                                {
-                                    println!("Error sending command to receiver");
-                                };
-                            } else {
-                                blockchain_network_state.dump(&block);
-                                if let Err(_) =
-                                    miner_sender.send(Command::ConfirmedBlock(block.clone()))
-                                {
-                                    println!("Error sending command to receiver");
-                                }
-
-                                if let Err(_) = miner_sender.send(Command::StateUpdateCompleted(
-                                    blockchain_network_state.clone(),
-                                )) {
-                                    println!(
-                                        "Error sending state update completed command to receiver"
-                                    );
-                                }
+                    Command::GetHeight => {
+                        println!("Blockchain Height: {}", blockchain.chain.len());
+                    }
+                    Command::GetMetrics => match blockchain_handler.block_metrics.summary() {
+                        Some(summary) => println!("Block metrics, {}", summary),
+                        None => println!("No blocks received from peers yet"),
+                    },
+                    Command::Quit => {
+                        if let Err(e) = blockchain_network_state.flush() {
+                            error!(target: BLOCKCHAIN_TARGET, "Error flushing state: {:?}", e);
+                        }
+                        let _ = blockchain_shutdown_sender.send("blockchain");
+                        break;
+                    }
+                    // A proof is made against the ledger the next block's header commits
+                    // to, it's held until this node validates that header.
+                    Command::BalanceProof(proof, sender_id) => {
+                        if blockchain
+                            .tip_height()
+                            .map_or(true, |height| height < proof.block_height)
+                        {
+                            if pending_proofs.len() < MAX_PENDING_PROOFS {
+                                pending_proofs.push((proof, sender_id));
                             }
+                        } else {
+                            show_balance(blockchain, &proof, &sender_id);
                         }
                     }
-                    Command::GetStateComponents(requestor, components) => match components {
-                        StateComponent::All => {
-                            let genesis_bytes = if let Some(genesis) = blockchain.clone().genesis {
-                                Some(genesis.clone().as_bytes())
-                            } else {
-                                None
-                            };
-                            let child_bytes = if let Some(block) = blockchain.clone().child {
-                                Some(block.clone().as_bytes())
-                            } else {
-                                None
-                            };
-                            let parent_bytes = if let Some(block) = blockchain.clone().parent {
-                                Some(block.clone().as_bytes())
-                            } else {
-                                None
-                            };
-                            let current_ledger =
-                                Some(blockchain_network_state.clone().db_to_ledger().as_bytes());
-                            let current_network_state =
-                                Some(blockchain_network_state.clone().as_bytes());
-                            let components = Components {
-                                genesis: genesis_bytes,
-                                child: child_bytes,
-                                parent: parent_bytes,
-                                blockchain: None,
-                                ledger: current_ledger,
-                                network_state: current_network_state,
-                                archive: None,
-                            };
-
-                            if let Err(e) = state_sender
-                                .send(Command::RequestedComponents(requestor, components))
+                    Command::GetClaim(key) => match blockchain_network_state.read().get_claim(&key)
+                    {
+                        Some(claim) => {
+                            // The pointer the claim has in the election for the next block.
+                            let pointer = blockchain.tip().and_then(|tip| {
+                                claim.get_pointer(u128::from(tip.header.next_block_nonce))
+                            });
+                            println!(
+                                "Claim {}: address={} hash={} nonce={} eligible={} pointer={:?}",
+                                claim.pubkey,
+                                claim.address,
+                                claim.hash,
+                                claim.nonce,
+                                claim.eligible,
+                                pointer
+                            );
+                        }
+                        None => println!("No claim with pubkey or hash {}", key),
+                    },
+                    Command::ShowSlashed => {
+                        let network_state = blockchain_network_state.read();
+                        let ineligible = network_state.get_ineligible_claims();
+                        println!("Ineligible claims: {}", ineligible.len());
+                        ineligible.iter().for_each(|claim| {
+                            if let Some(history) = network_state.get_offense_history(&claim.pubkey)
                             {
+                                let reinstatement = if let Some(pending) = history.pending {
+                                    format!("pending until height {}", pending.release_height)
+                                } else {
+                                    "none".to_string()
+                                };
                                 println!(
-                                    "Error sending requested components to state receiver: {:?}",
-                                    e
+                                    "{} ({}): slashed at heights {:?}, reinstatement: {}",
+                                    claim.pubkey, claim.address, history.offenses, reinstatement
+                                );
+                            } else {
+                                println!(
+                                    "{} ({}): no offenses recorded",
+                                    claim.pubkey, claim.address
+                                );
+                            }
+                        });
+                    }
+                    Command::RewardState => println!(
+                        "{}",
+                        blockchain_network_state
+                            .read()
+                            .get_reward_state()
+                            .emission_progress()
+                    ),
+                    Command::SupplyAudit => match blockchain_network_state.read().audit_supply() {
+                        Some(audit) => {
+                            println!(
+                                "Supply at height {}: {}, expected {} (schedule allows {} to {})",
+                                audit.height,
+                                audit.total_supply,
+                                audit.expected,
+                                audit.bounds.0,
+                                audit.bounds.1
+                            );
+                            if audit.diverged() {
+                                warn!(
+                                    target: BLOCKCHAIN_TARGET,
+                                    "supply diverged height={} total_supply={} bounds={:?}",
+                                    audit.height,
+                                    audit.total_supply,
+                                    audit.bounds
                                 );
                             }
                         }
-                        _ => {}
+                        None => println!("No supply to audit before the genesis block"),
                     },
-                    Command::StateUpdateComponents(components) => {
-                        if let Some(bytes) = components.genesis {
-                            blockchain.genesis = Some(Block::from_bytes(&bytes))
-                        }
-
-                        if let Some(bytes) = components.child {
-                            blockchain.child = Some(Block::from_bytes(&bytes))
-                        }
-                        if let Some(bytes) = components.parent {
-                            blockchain.parent = Some(Block::from_bytes(&bytes))
+                    Command::ShowForks => {
+                        let fork_events = blockchain.fork_events();
+                        if fork_events.is_empty() {
+                            println!("No competing tips seen");
                         }
-                        if let Some(bytes) = components.blockchain {
-                            let mut new_blockchain = Blockchain::from_bytes(&bytes);
-                            new_blockchain.future_blocks = blockchain.clone().future_blocks;
-                            new_blockchain.chain_db = blockchain.clone().chain_db;
-                            blockchain = new_blockchain;
+                        fork_events.iter().for_each(|event| println!("{}", event));
+                    }
+                    Command::ExportState(snapshot_path) => {
+                        match blockchain_network_state
+                            .read()
+                            .export_snapshot(&snapshot_path)
+                        {
+                            Ok(hash) => {
+                                println!("Exported state to {} with hash: {}", snapshot_path, hash)
+                            }
+                            Err(e) => println!("Error exporting state snapshot: {:?}", e),
                         }
-                        if let Some(bytes) = components.network_state {
-                            let mut new_network_state = NetworkState::from_bytes(&bytes);
-                            new_network_state.path = blockchain_network_state.path;
-                            blockchain_reward_state = new_network_state.reward_state;
-                            blockchain_network_state = new_network_state;
+                    }
+                    Command::ExportCheckpoint(checkpoint_path, up_to_height) => {
+                        match blockchain.export_checkpoint(
+                            &checkpoint_path,
+                            up_to_height,
+                            &blockchain_network_state.read(),
+                        ) {
+                            Ok(hash) => println!(
+                                "Exported checkpoint to {} with hash: {}",
+                                checkpoint_path, hash
+                            ),
+                            Err(e) => println!("Error exporting checkpoint: {:?}", e),
                         }
-
-                        if let Some(bytes) = components.ledger {
-                            let new_ledger = Ledger::from_bytes(&bytes);
-                            blockchain_network_state
-                                .update_ledger(new_ledger, blockchain_reward_state);
+                    }
+                    Command::PruneArchive(keep_from_height) => {
+                        match blockchain.prune_archive(keep_from_height) {
+                            Ok(n_pruned) => println!(
+                                "Pruned {} blocks, archive kept from height {:?}",
+                                n_pruned, blockchain.pruned_below
+                            ),
+                            Err(e) => println!("Error pruning archive: {:?}", e),
                         }
-
-                        if let Some(bytes) = components.archive {
-                            let mut new_db = blockchain.chain_db_from_bytes(&bytes);
-                            if let Err(e) = new_db.dump() {
-                                println!("Error dumping db update: {:?}", e);
+                    }
+                    Command::Verify => {
+                        // The audit runs against a copy of the ledger db, taken
+                        // under the read lock, so the thread goes on applying
+                        // blocks while it runs.
+                        verify_cancel.store(false, Ordering::SeqCst);
+                        let live_state = blockchain_network_state.read();
+                        let verify_path = format!("{}.verify", live_state.path);
+                        let copied = std::fs::copy(&live_state.path, &verify_path);
+                        let mut audited_state = live_state.clone();
+                        drop(live_state);
+                        audited_state.path = verify_path.clone();
+                        match copied {
+                            Ok(_) => {
+                                let audited_chain = blockchain.clone();
+                                let cancel = verify_cancel.clone();
+                                thread::spawn(move || {
+                                    let report = audited_chain.audit_with(&audited_state, || {
+                                        cancel.load(Ordering::SeqCst)
+                                    });
+                                    println!("{}", report);
+                                    let _ = std::fs::remove_file(&verify_path);
+                                });
                             }
-                        }
-
-                        if let Err(e) = blockchain_sender.send(Command::ProcessBacklog) {
-                            println!("Error sending process backlog command to blockchain receiver: {:?}", e);
+                            Err(e) => println!("Error copying ledger to verify it: {:?}", e),
                         }
                     }
-                    Command::ProcessBacklog => {
-                        let last_block = blockchain.clone().child.unwrap();
-                        while let Some((_, block)) = blockchain.future_blocks.pop_front() {
-                            if last_block.header.block_height == block.header.block_height {
-                                println!("Block already processed, skipping")
-                            } else {
-                                if let Err(e) = blockchain.process_block(
-                                    &blockchain_network_state,
-                                    &blockchain_reward_state,
-                                    &block,
-                                ) {
-                                    println!(
-                                        "Error trying to process backlogged future blocks: {:?}",
-                                        e
-                                    );
-                                } else {
-                                    blockchain_network_state.dump(&block);
-                                    if let Err(e) =
-                                        miner_sender.send(Command::ConfirmedBlock(block.clone()))
-                                    {
-                                        println!(
-                                            "Error sending confirmed backlog block to miner: {:?}",
-                                            e
-                                        );
-                                    }
-                                }
+                    Command::CancelVerify => verify_cancel.store(true, Ordering::SeqCst),
+                    Command::ExportAddressActivity(address, format, path) => {
+                        match blockchain_network_state
+                            .read()
+                            .export_address_activity(&address, format, &path)
+                        {
+                            Ok(n_rows) => {
+                                println!(
+                                    "Exported {} rows of activity for {} to {}",
+                                    n_rows, address, path
+                                )
                             }
+                            Err(e) => println!("Error exporting address activity: {:?}", e),
                         }
-                        println!("Backlog processed");
-                        if let Err(e) = miner_sender.send(Command::StateUpdateCompleted(
-                            blockchain_network_state.clone(),
-                        )) {
-                            println!("Error sending updated network state to miner: {:?}", e);
-                        }
-                        blockchain.updating_state = false;
                     }
-                    Command::StateUpdateCompleted(network_state) => {
-                        blockchain_network_state = network_state.clone();
-                    }
-                    Command::ClaimAbandoned(_, claim) => {
-                        blockchain_network_state.abandoned_claim(claim.hash);
-                        if let Err(e) = miner_sender.send(Command::StateUpdateCompleted(
-                            blockchain_network_state.clone(),
-                        )) {
-                            println!("Error sending updated network state to miner: {:?}", e);
+                    // The node's own state is left as it is, the chain it holds
+                    // is on top of it. A node is moved to another state by
+                    // starting it with --import-snapshot.
+                    Command::ImportState(snapshot_path, db_path) => {
+                        match NetworkState::import_snapshot(&snapshot_path, &db_path) {
+                            Ok(_) => println!(
+                                "Imported and verified state from {} into {}",
+                                snapshot_path, db_path
+                            ),
+                            Err(e) => println!("Error importing state snapshot: {:?}", e),
                         }
                     }
-                    Command::SlashClaims(bad_validators) => {
-                        blockchain_network_state.slash_claims(bad_validators);
-                    }
-                    Command::GetHeight => {
-                        println!("Blockchain Height: {}", blockchain.chain.len());
-                    }
                     _ => {}
                 }
             }
@@ -469,7 +838,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let miner_to_miner_sender = to_miner_sender.clone();
     let miner_to_blockchain_sender = to_blockchain_sender.clone();
     let miner_to_swarm_sender = to_swarm_sender.clone();
+    let miner_to_wallet_sender = to_wallet_sender.clone();
+    let miner_shutdown_sender = shutdown_sender.clone();
+    let pools_path = format!("{}/pools_{}.json", DATA_DIR, wallet.get_address(1));
+    let miner_recorder = recorder.clone();
     thread::spawn(move || {
+        // Block nonces follow the recorded seed.
+        if let Some(recorder) = &miner_recorder {
+            entropy::seed(recorder.seed());
+        }
         let mut miner = Miner::start(
             mining_wallet.clone().get_secretkey(),
             mining_wallet.clone().get_pubkey(),
@@ -478,31 +855,114 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             miner_network_state,
             0,
         );
+        miner.quorum = quorum;
+        miner.min_relay_fee = min_relay_fee;
+        miner.assembly = assembly;
+        miner.pacing = pacing;
+        miner.abandonment = abandonment;
+        // Txns and claims still pending when the node last quit.
+        if miner.restore_pools(&pools_path) {
+            info!(target: MINER_TARGET, "restored pools path={}", pools_path);
+        }
+        let mut compact_blocks = CompactBlockCache::new();
+        // Txns some miner has acked already, only the first to accept a txn acks it.
+        let mut acked_txns = SeenTxns::new(SEEN_TXNS_CAPACITY);
         loop {
             let blockchain_sender = miner_to_blockchain_sender.clone();
             let swarm_sender = miner_to_swarm_sender.clone();
             let miner_sender = miner_to_miner_sender.clone();
-            if let Ok(command) = to_miner_receiver.try_recv() {
+            let wallet_sender = miner_to_wallet_sender.clone();
+            // Peers that didn't send the missing txns in time are asked for the full block.
+            compact_blocks
+                .expired(miner.get_timestamp(), COMPACT_BLOCK_TIMEOUT)
+                .iter()
+                .for_each(|pending| {
+                    info!(
+                        target: MINER_TARGET,
+                        "compact block timed out height={}, requesting the full block",
+                        pending.compact.header.block_height
+                    );
+                    let message = MessageType::NeedBlockMessage {
+                        block_last_hash: pending.compact.header.last_hash.clone(),
+                        sender_id: node_id.clone().to_string(),
+                        requested_from: pending.sender_id.clone(),
+                    };
+                    if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes())) {
+                        println!("Error sending NeedBlock message to swarm: {:?}", e);
+                    }
+                });
+            // A miner waiting for txns mines once its assembly window runs out.
+            if miner.should_mine() && miner.assembly_wait_over(miner.get_timestamp()) {
+                if let Err(e) = send_or_dead_letter(&miner_sender, Command::MineBlock) {
+                    println!("Error sending miner sender MineBlock: {:?}", e);
+                }
+            }
+            // A miner that lost the election checks it again once its retry interval runs out.
+            if miner.should_mine() && miner.pacing.retry_due(miner.get_timestamp()) {
+                if let Err(e) = send_or_dead_letter(&miner_sender, Command::MineBlock) {
+                    println!("Error sending miner sender MineBlock: {:?}", e);
+                }
+            }
+            if let Ok(command) = to_miner_receiver.recv_timeout(wakeup) {
+                let step = miner_recorder
+                    .as_ref()
+                    .map(|recorder| recorder.record(Handler::Miner, &command));
                 match command {
                     Command::SendMessage(message) => {
                         if let Err(e) = swarm_sender.send(Command::SendMessage(message)) {
                             println!("Error sending to swarm receiver: {:?}", e);
                         }
                     }
+                    Command::Quit => {
+                        if let Err(e) = miner.save_pools(&pools_path) {
+                            error!(target: MINER_TARGET, "Error saving pools: {:?}", e);
+                        }
+                        let _ = miner_shutdown_sender.send("miner");
+                        break;
+                    }
+                    // A paused miner drops the requests to mine, resuming asks again.
+                    Command::MineBlock if miner.paused => {}
+                    // A miner that lost the election waits for the block it lost to or its retry.
+                    Command::MineBlock if miner.pacing.sitting_out(miner.get_timestamp()) => {}
                     Command::MineBlock => {
                         miner.mining = true;
                         if let Some(last_block) = miner.last_block.clone() {
                             if let Some(claim) =
                                 miner.clone().claim_map.get(&miner.clone().claim.pubkey)
                             {
-                                let lowest_pointer = miner
-                                    .get_lowest_pointer(last_block.header.next_block_nonce as u128);
+                                let lowest_pointer = miner.get_lowest_pointer(u128::from(
+                                    last_block.header.next_block_nonce,
+                                ));
                                 if let Some((hash, _)) = lowest_pointer.clone() {
-                                    if hash == claim.hash.clone() {
-                                        let block = miner.mine();
-                                        if let Some(block) = block {
-                                            let message = MessageType::BlockMessage {
-                                                block: block.clone(),
+                                    let height = last_block.header.block_height + 1;
+                                    if hash == claim.hash.clone()
+                                        && !miner.ready_to_assemble(height, miner.get_timestamp())
+                                    {
+                                        // Mining resumes when enough txns are confirmed or the
+                                        // window runs out.
+                                        info!(
+                                            target: MINER_TARGET,
+                                            "waiting for txns height={} confirmed={}",
+                                            height,
+                                            miner.txn_pool.confirmed.len()
+                                        );
+                                    } else if hash == claim.hash.clone() {
+                                        let block = miner.mine(&mining_cancel);
+                                        // A valid block from another miner at this height makes
+                                        // ours a duplicate, it's only broadcast if theirs isn't.
+                                        let suppressed = block.as_ref().map_or(false, |block| {
+                                            !miner.pacing.should_broadcast(block)
+                                        });
+                                        if suppressed {
+                                            info!(
+                                                target: MINER_TARGET,
+                                                "block broadcast suppressed height={}",
+                                                height
+                                            );
+                                        } else if let Some(block) = block {
+                                            // Peers rebuild the block from their own txn pool.
+                                            let message = MessageType::CompactBlockMessage {
+                                                block: CompactBlock::from_block(&block),
                                                 sender_id: node_id.clone().to_string(),
                                             };
 
@@ -512,16 +972,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                                 println!("Error sending SendMessage command to swarm: {:?}", e);
                                             }
 
-                                            if let Err(_) =
-                                                blockchain_sender.send(Command::PendingBlock(
+                                            if let Err(_) = send_or_dead_letter(
+                                                &blockchain_sender,
+                                                Command::PendingBlock(
                                                     block.clone(),
                                                     node_id.clone().to_string(),
-                                                ))
-                                            {
+                                                ),
+                                            ) {
                                                 println!("Error sending PendingBlock command to blockchain");
                                             }
                                         } else {
-                                            if let Err(e) = miner_sender.send(Command::MineBlock) {
+                                            if let Err(e) = send_or_dead_letter(
+                                                &miner_sender,
+                                                Command::MineBlock,
+                                            ) {
                                                 println!(
                                                     "Error sending miner sender MineBlock: {:?}",
                                                     e
@@ -529,100 +993,289 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             }
                                         }
                                     } else {
-                                        if miner.check_time_elapsed() > 30 {
-                                            miner.current_nonce_timer = miner.get_timestamp();
-                                            let mut abandoned_claim_map = miner.claim_map.clone();
-                                            abandoned_claim_map.retain(|_, v| v.hash == hash);
-
-                                            if let Some((_, v)) = abandoned_claim_map.front() {
-                                                let message = MessageType::ClaimAbandonedMessage {
-                                                    claim: v.clone(),
-                                                    sender_id: miner.claim.pubkey.clone(),
-                                                };
-
-                                                miner
-                                                    .abandoned_claim_counter
-                                                    .insert(miner.claim.pubkey.clone(), v.clone());
-                                                if let Err(e) = swarm_sender
-                                                    .send(Command::SendMessage(message.as_bytes()))
-                                                {
-                                                    println!("Error sending ClaimAbandoned message to swarm: {:?}", e);
-                                                }
+                                        // At most one vote per claim and timeout, none for
+                                        // claims whose owners announced downtime.
+                                        if let Some(v) = miner.abandonment_vote(&hash) {
+                                            info!(
+                                                target: MINER_TARGET,
+                                                "claim abandoned hash={} pubkey={}",
+                                                v.hash,
+                                                v.pubkey
+                                            );
+                                            let message = MessageType::ClaimAbandonedMessage {
+                                                claim: v.clone(),
+                                                sender_id: miner.claim.pubkey.clone(),
+                                            };
+
+                                            if let Err(e) = swarm_sender
+                                                .send(Command::SendMessage(message.as_bytes()))
+                                            {
+                                                println!("Error sending ClaimAbandoned message to swarm: {:?}", e);
+                                            }
 
-                                                let mut abandoned_claim_map =
-                                                    miner.abandoned_claim_counter.clone();
-                                                abandoned_claim_map
-                                                    .retain(|_, claim| v.hash == claim.hash);
+                                            let mut abandoned_claim_map =
+                                                miner.abandoned_claim_counter.clone();
+                                            abandoned_claim_map
+                                                .retain(|_, claim| v.hash == claim.hash);
 
-                                                if abandoned_claim_map.len() as f64
-                                                    / (miner.claim_map.len() as f64 - 1.0)
-                                                    > VALIDATOR_THRESHOLD
+                                            if abandoned_claim_map.len() as f64
+                                                / (miner.claim_map.len() as f64 - 1.0)
+                                                > miner.reward_state.params.validator_share()
+                                            {
+                                                miner.retain_claims(|_, v| v.hash != hash);
+                                                if let Err(e) =
+                                                    blockchain_sender.send(Command::ClaimAbandoned(
+                                                        miner.claim.pubkey.clone(),
+                                                        v.clone(),
+                                                    ))
                                                 {
-                                                    miner.claim_map.retain(|_, v| v.hash != hash);
-                                                    if let Err(e) = blockchain_sender.send(
-                                                        Command::ClaimAbandoned(
-                                                            miner.claim.pubkey.clone(),
-                                                            v.clone(),
-                                                        ),
-                                                    ) {
-                                                        println!("Error forwarding confirmed abandoned claim to blockchain: {:?}", e);
-                                                    }
+                                                    println!("Error forwarding confirmed abandoned claim to blockchain: {:?}", e);
                                                 }
                                             }
                                         }
-                                        if let Err(e) = miner_sender.send(Command::MineBlock) {
-                                            println!(
-                                                "Error sending miner sender MineBlock: {:?}",
-                                                e
-                                            );
-                                        }
+                                        // Checked again once the block at this height is
+                                        // confirmed or the retry interval runs out.
+                                        miner.pacing.lost(height, miner.get_timestamp());
                                     }
                                 } else {
-                                    if let Err(e) = miner_sender.send(Command::NonceUp) {
-                                        println!("Error sending NonceUp command to miner: {:?}", e);
-                                    }
+                                    // The ledger moves to the next nonce epoch with the
+                                    // block that leaves no claim a pointer.
+                                    warn!(
+                                        target: MINER_TARGET,
+                                        "no claim has a pointer height={} epoch={}",
+                                        last_block.header.block_height + 1,
+                                        miner.nonce_epoch
+                                    );
                                 }
                             }
                         } else {
-                            if let Err(e) = miner_sender.send(Command::MineGenesis) {
+                            if let Err(e) = send_or_dead_letter(&miner_sender, Command::MineGenesis)
+                            {
                                 println!("Error sending mine genesis command to miner: {:?}", e);
                             };
                         }
                     }
                     Command::ConfirmedBlock(block) => {
-                        miner.current_nonce_timer = block.header.timestamp;
+                        mining_cancel.block_confirmed(block.header.block_height);
 
                         if let Category::Motherlode(_) = block.header.block_reward.category {
                             println!("*****{:?}*****\n", &block.header.block_reward.category);
                         }
-                        miner.last_block = Some(block.clone());
+                        miner.confirm_block(&block);
+                        if miner.gc_due() {
+                            miner.collect_garbage();
+                        }
+                        if let (Some(recorder), Some(step)) = (&miner_recorder, step) {
+                            recorder.confirmed(step, &MinerOutcome::of(&block, &miner));
+                        }
                         block.txns.iter().for_each(|(k, _)| {
-                            miner.txn_pool.confirmed.remove(&k.clone());
-                        });
-                        let mut new_claims = block.claims.clone();
-                        new_claims = new_claims
-                            .iter()
-                            .map(|(k, v)| {
-                                return (k.clone(), v.clone());
-                            })
-                            .collect();
-                        new_claims.iter().for_each(|(k, v)| {
-                            miner.claim_pool.confirmed.remove(k);
-                            miner.claim_map.insert(k.clone(), v.clone());
+                            let status = TxnStatus::Confirmed {
+                                block_height: block.header.block_height,
+                            };
+                            if let Err(e) =
+                                wallet_sender.send(Command::TxnStatus(k.clone(), status))
+                            {
+                                println!("Error sending txn status to wallet: {:?}", e);
+                            }
                         });
-
-                        // Check if the miner's claim nonce changed,
-                        // if it did change, make sure that it HAD to change.
-                        // If it did have to change (nonce up) and your local claim map is different
-                        // nonce up the local claim map until it is in consensus.
-                        miner.claim_map.replace(
-                            block.header.claim.clone().pubkey,
-                            block.header.claim.clone(),
-                        );
+                        if let Err(e) =
+                            wallet_sender.send(Command::ConfirmedHeight(block.header.block_height))
+                        {
+                            println!("Error sending confirmed height to wallet: {:?}", e);
+                        }
                     }
+                    Command::TxnsFinalized(txn_ids) => miner.finalize_txns(&txn_ids),
+                    Command::GetMetrics => println!("Pool metrics, {}", miner.pool_metrics()),
+                    Command::ProcessCompactBlock(compact, sender_id, source) => {
+                        let now = miner.get_timestamp();
+                        // Sampled by the peer id gossipsub authenticated, a
+                        // peer can't take more samples by claiming other
+                        // sender ids.
+                        if let Some(peer_id) = source {
+                            if let Some(skew) =
+                                miner.clock.record(&peer_id, compact.header.timestamp, now)
+                            {
+                                warn!(
+                                    target: MINER_TARGET,
+                                    "clock skew detected skew_ms={}",
+                                    skew / MILLI as i128
+                                );
+                                println!(
+                                    "\n*** WARNING: the local clock is {}s off from the network ***\n",
+                                    skew / SECOND as i128
+                                );
+                            }
+                        }
+                        if !miner.clock.valid_timestamp(compact.header.timestamp, now) {
+                            warn!(
+                                target: MINER_TARGET,
+                                "block timestamp too far ahead height={} timestamp={} tolerance_ms={}",
+                                compact.header.block_height,
+                                compact.header.timestamp,
+                                miner.clock.timestamp_tolerance() / MILLI
+                            );
+                            continue;
+                        }
+                        match compact.reconstruct(&miner.txn_pool, &LinkedHashMap::new()) {
+                            Reconstruction::Complete(block) => {
+                                miner.saw_block(&block);
+                                if let Err(e) = send_or_dead_letter(
+                                    &blockchain_sender,
+                                    Command::PendingBlock(block, sender_id),
+                                ) {
+                                    println!(
+                                        "Error sending PendingBlock command to blockchain: {:?}",
+                                        e
+                                    );
+                                }
+                            }
+                            Reconstruction::Missing(txn_ids) => {
+                                let message = MessageType::GetTxnsMessage {
+                                    txn_ids,
+                                    sender_id: node_id.clone().to_string(),
+                                    requested_from: sender_id.clone(),
+                                };
+                                compact_blocks.insert(compact, sender_id, miner.get_timestamp());
+                                if let Err(e) =
+                                    swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!("Error sending GetTxns message to swarm: {:?}", e);
+                                }
+                            }
+                            Reconstruction::Mismatch => {
+                                let message = MessageType::NeedBlockMessage {
+                                    block_last_hash: compact.header.last_hash.clone(),
+                                    sender_id: node_id.clone().to_string(),
+                                    requested_from: sender_id,
+                                };
+                                if let Err(e) =
+                                    swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!("Error sending NeedBlock message to swarm: {:?}", e);
+                                }
+                            }
+                        }
+                    }
+                    Command::ProcessFetchedTxns(txns) => {
+                        // Txns no block is waiting on were fetched to sync the pool,
+                        // they're validated like txns gossiped to the node.
+                        let (txns, synced): (Vec<Txn>, Vec<Txn>) = txns
+                            .into_iter()
+                            .partition(|txn| compact_blocks.wants(&txn.txn_id));
+                        miner
+                            .process_synced_txns(synced)
+                            .into_iter()
+                            .for_each(|txn_validator| {
+                                let message = MessageType::TxnValidatorMessage {
+                                    txn_validator,
+                                    sender_id: node_id.to_string().clone(),
+                                };
+                                if let Err(e) =
+                                    miner_sender.send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!("Error sending SendMessage command to swarm: {:?}", e);
+                                }
+                            });
+                        compact_blocks
+                            .add_txns(&txns, &miner.txn_pool)
+                            .into_iter()
+                            .for_each(|(pending, reconstruction)| match reconstruction {
+                                Reconstruction::Complete(block) => {
+                                    miner.saw_block(&block);
+                                    if let Err(e) = send_or_dead_letter(
+                                        &blockchain_sender,
+                                        Command::PendingBlock(block, pending.sender_id),
+                                    ) {
+                                        println!(
+                                            "Error sending PendingBlock command to blockchain: {:?}",
+                                            e
+                                        );
+                                    }
+                                }
+                                _ => {
+                                    let message = MessageType::NeedBlockMessage {
+                                        block_last_hash: pending.compact.header.last_hash.clone(),
+                                        sender_id: node_id.clone().to_string(),
+                                        requested_from: pending.sender_id,
+                                    };
+                                    if let Err(e) =
+                                        swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                                    {
+                                        println!(
+                                            "Error sending NeedBlock message to swarm: {:?}",
+                                            e
+                                        );
+                                    }
+                                }
+                            });
+                    }
+                    Command::SendTxns(txn_ids, requestor) => {
+                        let message = MessageType::TxnsResponseMessage {
+                            txns: miner.txns_for(&txn_ids),
+                            requestor,
+                            sender_id: node_id.clone().to_string(),
+                        };
+                        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                        {
+                            println!("Error sending TxnsResponse message to swarm: {:?}", e);
+                        }
+                    }
+                    Command::SyncPool(peer_id) => {
+                        let txn_ids = miner.txn_pool.pending_ids();
+                        if txn_ids.is_empty() {
+                            continue;
+                        }
+                        let message = MessageType::PoolTxnIdsMessage {
+                            txn_ids,
+                            sender_id: node_id.clone().to_string(),
+                            requested_from: peer_id,
+                        };
+                        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                        {
+                            println!("Error sending PoolTxnIds message to swarm: {:?}", e);
+                        }
+                    }
+                    // Light nodes don't keep a txn pool to sync.
+                    Command::ProcessPoolTxnIds(..) if light_client => {}
+                    Command::ProcessPoolTxnIds(txn_ids, sender_id) => {
+                        let txn_ids = miner.txn_pool.missing(&txn_ids);
+                        if txn_ids.is_empty() {
+                            continue;
+                        }
+                        let message = MessageType::GetTxnsMessage {
+                            txn_ids,
+                            sender_id: node_id.clone().to_string(),
+                            requested_from: sender_id,
+                        };
+                        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                        {
+                            println!("Error sending GetTxns message to swarm: {:?}", e);
+                        }
+                    }
+                    // Txns paying less than the min relay fee are dropped without a vote.
+                    Command::ProcessTxn(txn) if !miner.admits_txn(&txn) => {}
                     Command::ProcessTxn(txn) => {
+                        let new_txn = miner.txn_pool.get(&txn.txn_id).is_none();
                         let txn_validator = miner.process_txn(txn.clone());
                         miner.check_confirmed(txn.txn_id.clone());
+                        if new_txn && txn_validator.vote && acked_txns.insert(&txn.txn_id) {
+                            let message = MessageType::TxnAckMessage {
+                                txn_validator: txn_validator.clone(),
+                                sender_id: node_id.to_string(),
+                            };
+                            if let Err(e) =
+                                swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                            {
+                                println!("Error sending TxnAck message to swarm: {:?}", e);
+                            }
+                        }
+                        if let Some(status) = miner.txn_status(&txn.txn_id) {
+                            if let Err(e) =
+                                wallet_sender.send(Command::TxnStatus(txn.txn_id.clone(), status))
+                            {
+                                println!("Error sending txn status to wallet: {:?}", e);
+                            }
+                        }
                         let message = MessageType::TxnValidatorMessage {
                             txn_validator,
                             sender_id: node_id.to_string().clone(),
@@ -632,6 +1285,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             println!("Error sending SendMessage command to swarm: {:?}", e);
                         }
                     }
+                    Command::TxnAck(txn_validator, sender_id) => {
+                        acked_txns.insert(&txn_validator.txn.txn_id);
+                        if let Err(e) =
+                            wallet_sender.send(Command::TxnAck(txn_validator, sender_id))
+                        {
+                            println!("Error sending txn ack to wallet: {:?}", e);
+                        }
+                    }
+                    Command::ProcessClaim(claim) if !miner.admits_claim(&claim) => {}
                     Command::ProcessClaim(claim) => {
                         miner
                             .claim_pool
@@ -640,37 +1302,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                     Command::ProcessTxnValidator(validator) => {
                         miner.process_txn_validator(validator.clone());
-                        if let Some(bad_validators) =
-                            miner.check_rejected(validator.txn.txn_id.clone())
-                        {
-                            if let Err(e) =
-                                blockchain_sender.send(Command::SlashClaims(bad_validators.clone()))
-                            {
-                                println!(
-                                    "Error sending SlashClaims command to blockchain thread: {:?}",
-                                    e
-                                );
+                        let txn_id = validator.txn.txn_id.clone();
+                        if let Some(evidence) = miner.check_rejected(validator.txn.txn_id.clone()) {
+                            let status = TxnStatus::Rejected {
+                                reason: miner.rejection_reason(&txn_id),
+                            };
+                            if let Err(e) = wallet_sender.send(Command::TxnStatus(txn_id, status)) {
+                                println!("Error sending txn status to wallet: {:?}", e);
                             }
-
-                            bad_validators.iter().for_each(|k| {
-                                miner.slash_claim(k.to_string());
+                            // The yes voters are slashed once a block carries the evidence.
+                            evidence.iter().for_each(|evidence| {
+                                let txn = Txn::slashing_evidence(evidence);
+                                if let Err(e) = miner_sender.send(Command::ProcessTxn(txn.clone()))
+                                {
+                                    println!("Error sending slashing evidence to miner: {:?}", e);
+                                }
+                                let message = MessageType::TxnMessage {
+                                    txn,
+                                    sender_id: node_id.to_string(),
+                                };
+                                if let Err(e) =
+                                    swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!(
+                                        "Error sending slashing evidence to swarm sender: {:?}",
+                                        e
+                                    );
+                                }
                             });
                         } else {
                             miner.check_confirmed(validator.txn.txn_id.clone());
+                            if let Some(status) = miner.txn_status(&txn_id) {
+                                if let Err(e) =
+                                    wallet_sender.send(Command::TxnStatus(txn_id, status))
+                                {
+                                    println!("Error sending txn status to wallet: {:?}", e);
+                                }
+                            }
+                            // A miner waiting for txns mines as soon as enough are confirmed.
+                            if miner.should_mine()
+                                && miner.assembly_wait_over(miner.get_timestamp())
+                            {
+                                if let Err(e) =
+                                    send_or_dead_letter(&miner_sender, Command::MineBlock)
+                                {
+                                    println!("Error sending miner sender MineBlock: {:?}", e);
+                                }
+                            }
                         }
                     }
                     Command::InvalidBlock(_) => {}
-                    Command::StateUpdateCompleted(network_state) => {
-                        miner.network_state = network_state.clone();
-                        miner.claim_map = miner.network_state.get_claims();
-                        if miner.mining {
-                            if let Err(e) = miner_sender.send(Command::MineBlock) {
+                    Command::StateChanged => {
+                        if let Some(last_block) = &miner.last_block {
+                            mining_cancel.block_confirmed(last_block.header.block_height);
+                        }
+                        let claim_map = miner.network_state.get_claims();
+                        miner.set_claim_map(claim_map);
+                        if miner.should_mine() {
+                            if let Err(e) = send_or_dead_letter(&miner_sender, Command::MineBlock) {
                                 println!("Error sending MineBlock command to miner: {:?}", e);
                             }
                         }
                     }
                     Command::MineGenesis => {
                         if let Some(block) = miner.genesis() {
+                            // Peers joining this network pass it as --genesis-hash.
+                            info!(
+                                target: MINER_TARGET,
+                                "genesis mined header_hash={}",
+                                block.header.hash()
+                            );
                             miner.last_block = Some(block.clone());
                             let message = MessageType::BlockMessage {
                                 block: block.clone(),
@@ -682,10 +1383,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             {
                                 println!("Error sending SendMessage command to swarm: {:?}", e);
                             }
-                            if let Err(_) = blockchain_sender.send(Command::PendingBlock(
-                                block.clone(),
-                                node_id.clone().to_string(),
-                            )) {
+                            if let Err(_) = send_or_dead_letter(
+                                &blockchain_sender,
+                                Command::PendingBlock(block.clone(), node_id.clone().to_string()),
+                            ) {
                                 println!("Error sending to command receiver")
                             }
                         }
@@ -701,12 +1402,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             println!("Error sending SendMessage command to swarm: {:?}", e);
                         }
                     }
-                    Command::NonceUp => {
-                        miner.nonce_up();
-                        if let Err(e) = miner_sender.send(Command::MineBlock) {
-                            println!("Error sending MineBlock command to miner: {:?}", e);
+                    Command::PauseMining => {
+                        miner.pause();
+                        info!(target: MINER_TARGET, "mining paused");
+                    }
+                    Command::ResumeMining => {
+                        if let Some(message) = miner.resume(node_id.clone().to_string()) {
+                            info!(target: MINER_TARGET, "mining resumed");
+                            if let Err(e) =
+                                miner_sender.send(Command::SendMessage(message.as_bytes()))
+                            {
+                                println!("Error sending SendMessage command to swarm: {:?}", e);
+                            }
+                            if miner.should_mine() {
+                                if let Err(e) =
+                                    send_or_dead_letter(&miner_sender, Command::MineBlock)
+                                {
+                                    println!("Error sending MineBlock command to miner: {:?}", e);
+                                }
+                            }
                         }
                     }
+                    Command::ClaimAbandoned(pubkey, claim)
+                        if miner
+                            .abandonment_suppressed(&claim.pubkey, miner.network_timestamp()) =>
+                    {
+                        info!(
+                            target: MINER_TARGET,
+                            "abandonment vote ignored during downtime pubkey={} voter={}",
+                            claim.pubkey,
+                            pubkey
+                        );
+                    }
                     Command::ClaimAbandoned(pubkey, claim) => {
                         if let Some(_) = miner.claim_map.get(&pubkey) {
                             miner
@@ -717,9 +1444,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                             if abandoned_claim_map.len() as f64
                                 / (miner.claim_map.len() as f64 - 1.0)
-                                > VALIDATOR_THRESHOLD
+                                > miner.reward_state.params.validator_share()
                             {
-                                miner.claim_map.retain(|_, v| v.hash != claim.hash);
+                                miner.retain_claims(|_, v| v.hash != claim.hash);
                                 if let Err(e) =
                                     blockchain_sender.send(Command::ClaimAbandoned(pubkey, claim))
                                 {
@@ -729,11 +1456,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         }
                     }
                     Command::GetBalance(address_number) => {
+                        let address = mining_wallet.get_address(address_number);
+                        if light_client {
+                            // Printed by the blockchain thread once a proof verifies.
+                            let message = MessageType::BalanceProofRequest {
+                                address,
+                                sender_id: node_id.clone().to_string(),
+                            };
+                            if let Err(e) =
+                                swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                            {
+                                println!("Error sending BalanceProofRequest to swarm: {:?}", e);
+                            }
+                        } else {
+                            println!("Balance: {}", miner.network_state.get_balance(&address))
+                        }
+                    }
+                    Command::RebroadcastTxn(txn_id) => {
+                        if let Some(message) = miner.rebroadcast_txn(&txn_id, &node_id.to_string())
+                        {
+                            info!(target: MINER_TARGET, "rebroadcasting txn txn_id={}", txn_id);
+                            if let Err(e) =
+                                swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                            {
+                                println!("Error sending SendMessage command to swarm: {:?}", e);
+                            }
+                        } else {
+                            println!("Txn {} isn't pending, not rebroadcasting it", txn_id);
+                        }
+                    }
+                    Command::EstimateFee(target_blocks) => {
                         println!(
-                            "Balance: {}",
-                            miner
-                                .network_state
-                                .get_balance(&mining_wallet.get_address(address_number))
+                            "Estimated fee for inclusion within {} blocks: {}",
+                            target_blocks,
+                            miner.txn_pool.estimate_fee(target_blocks)
+                        )
+                    }
+                    Command::GetRewardInfo => {
+                        miner.reward_state.category_distribution().iter().for_each(
+                            |(category, p)| println!("{:?}: {:.4}%", category, p * 100f64),
+                        );
+                        println!(
+                            "Expected block reward: {:.2}",
+                            miner.reward_state.expected_reward()
                         )
                     }
                     _ => {}
@@ -747,10 +1512,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let state_to_swarm_sender = to_swarm_sender.clone();
     let state_to_blockchain_sender = to_blockchain_sender.clone();
     let mut state_chunk_cache = LinkedHashMap::new();
+    let state_recorder = recorder.clone();
     thread::spawn(move || loop {
         let blockchain_sender = state_to_blockchain_sender.clone();
         let swarm_sender = state_to_swarm_sender.clone();
-        if let Ok(command) = to_state_receiver.try_recv() {
+        if let Ok(command) = to_state_receiver.recv_timeout(wakeup) {
+            if let Some(recorder) = &state_recorder {
+                recorder.record(Handler::State, &command);
+            }
             match command {
                 Command::SendStateComponents(requestor, components) => {
                     if let Err(e) =
@@ -763,7 +1532,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 }
                 Command::RequestedComponents(requestor, components) => {
-                    println!("Sending state components");
+                    info!(
+                        target: STATE_TARGET,
+                        "sending state components requestor={}",
+                        requestor
+                    );
                     if let Some(chunks) = components.chunk() {
                         let mut idx = 0;
                         let total_chunks = chunks.clone().len() as u32;
@@ -794,14 +1567,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             component_bytes.extend(v);
                         });
 
-                        let components = Components::from_bytes(&component_bytes);
-                        if let Err(e) =
-                            blockchain_sender.send(Command::StateUpdateComponents(components))
-                        {
-                            println!(
-                                "Error sending state update componetns to blockchain thread: {:?}",
-                                e
-                            );
+                        state_chunk_cache.clear();
+                        match Components::try_from_bytes(&component_bytes) {
+                            Ok(components) => {
+                                let command = if let Err(e) = components.verify() {
+                                    warn!(
+                                        target: STATE_TARGET,
+                                        "rejecting inconsistent state components: {}",
+                                        e
+                                    );
+                                    Command::InvalidStateComponents
+                                } else {
+                                    Command::StateUpdateComponents(components)
+                                };
+                                if let Err(e) = blockchain_sender.send(command) {
+                                    println!(
+                                        "Error sending state update componetns to blockchain thread: {:?}",
+                                        e
+                                    );
+                                }
+                            }
+                            Err(e) => {
+                                warn!(
+                                    target: STATE_TARGET,
+                                    "Error parsing state components: {:?}",
+                                    e
+                                );
+                                if let Err(e) =
+                                    blockchain_sender.send(Command::InvalidStateComponents)
+                                {
+                                    println!(
+                                        "Error sending InvalidStateComponents to blockchain thread: {:?}",
+                                        e
+                                    );
+                                }
+                            }
                         }
                     } else {
                         state_chunk_cache.insert(chunk_number, data);
@@ -816,48 +1616,327 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     //____________________________________________________________________________________________________
-    // Terminal Interface loop
-    let terminal_to_swarm_sender = to_swarm_sender.clone();
-    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    // Terminal, left out of headless builds which are driven by their config and the network.
+    #[cfg(feature = "cli")]
+    cli::Terminal::new(to_wallet_sender.clone(), command_sender.clone()).spawn();
+    //____________________________________________________________________________________________________
+
+    //____________________________________________________________________________________________________
+    // Wallet loop
+    let wallet_to_swarm_sender = to_swarm_sender.clone();
+    let wallet_to_miner_sender = to_miner_sender.clone();
+    let wallet_to_blockchain_sender = to_blockchain_sender.clone();
+    // Recurring payments are saved per wallet and made as blocks are confirmed,
+    // from the balances in the ledger db.
+    let schedules_path = format!("{}/schedules_{}.json", DATA_DIR, wallet.get_address(1));
+    let mut schedules = Schedules::restore(&schedules_path);
+    let schedule_network_state = network_state.clone();
+    let mut last_height = 0;
+    fn save_outbox(wallet: &WalletAccount, outbox_path: &str) {
+        if let Err(e) = wallet.save_outbox(outbox_path) {
+            println!("Error saving the outbox: {:?}", e);
+        }
+    }
     loop {
-        let swarm_sender = terminal_to_swarm_sender.clone();
-        let evt = {
-            tokio::select! {
-                // await an input from the user
-                line = stdin.next_line() => Some(
-                    line.expect("can get line").expect("can read line from stdin")
-                )
-            }
-        };
-        if let Some(line) = evt {
-            if line == "QUIT" {
-                // Clean up and inform the network that you are no longer mining so that
-                // claim lowest pointers will be properly calculated.
-                break;
-            }
-            // If there is some input from the user, attemmpt to convert the input to
-            // a command and send to the command handler.
-            if let Some(command) = Command::from_str(&line) {
-                match command.clone() {
-                    Command::SendTxn(addr_num, receiver, amount) => {
-                        let txn = wallet.clone().send_txn(addr_num, receiver, amount);
-                        if let Ok(txn) = txn {
-                            let message = MessageType::TxnMessage {
-                                txn,
-                                sender_id: node_id.to_string().clone(),
-                            };
-                            if let Err(e) =
-                                swarm_sender.send(Command::SendMessage(message.as_bytes()))
-                            {
-                                println!("Error sending to command receiver: {:?}", e);
-                            };
+        let swarm_sender = wallet_to_swarm_sender.clone();
+        tokio::select! {
+            command = to_wallet_receiver.recv() => {
+                match command {
+                    Some(Command::TxnStatus(txn_id, status)) => {
+                        if let Some(pending) = wallet.update_txn_status(&txn_id, status) {
+                            println!("Txn {}: {}", txn_id, pending.status);
+                            save_outbox(&wallet, &outbox_path);
+                        }
+                    }
+                    Some(Command::TxnAck(txn_validator, sender_id)) => {
+                        let eligible = schedule_network_state
+                            .read()
+                            .eligible_validators(last_height + 1);
+                        if let Some(pending) = wallet.ack_txn(&txn_validator, &eligible) {
+                            println!(
+                                "Txn {}: {}, accepted by {}",
+                                pending.txn_id, pending.status, sender_id
+                            );
+                            save_outbox(&wallet, &outbox_path);
+                        }
+                    }
+                    Some(Command::ConfirmedHeight(height)) => {
+                        last_height = height;
+                        let events = schedules.run(height, &mut wallet, |address| {
+                            schedule_network_state.get_balance(address)
+                        });
+                        for event in events.iter() {
+                            match event {
+                                ScheduleEvent::Sent(id, pending) => {
+                                    println!(
+                                        "Schedule {}: txn {}: {}",
+                                        id, pending.txn_id, pending.status
+                                    );
+                                    let message = MessageType::TxnMessage {
+                                        txn: pending.txn.clone(),
+                                        sender_id: node_id.to_string().clone(),
+                                    };
+                                    if let Err(e) = swarm_sender
+                                        .send(Command::SendMessage(message.as_bytes()))
+                                    {
+                                        println!("Error sending to command receiver: {:?}", e);
+                                    }
+                                }
+                                ScheduleEvent::Paused(id, reason) => {
+                                    warn!(
+                                        "schedule paused id={} height={} reason={}",
+                                        id, height, reason
+                                    );
+                                    println!("Schedule {} paused: {}", id, reason);
+                                }
+                            }
+                        }
+                        if !events.is_empty() {
+                            if let Err(e) = schedules.save(&schedules_path) {
+                                error!("Error saving schedules: {:?}", e);
+                            }
+                            save_outbox(&wallet, &outbox_path);
                         }
                     }
-                    _ => {
-                        if let Err(_) = command_sender.send(command) {
-                            println!("Error sending command to command receiver");
+                    Some(Command::Quit) => {
+                        // Clean up and inform the network that you are no longer mining so that
+                        // claim lowest pointers will be properly calculated.
+                        for sender in [&wallet_to_miner_sender, &wallet_to_blockchain_sender] {
+                            if let Err(e) = send_or_dead_letter(sender, Command::Quit) {
+                                println!("Error sending Quit: {:?}", e);
+                            }
+                        }
+                        let timeout = Duration::from_secs(SHUTDOWN_TIMEOUT);
+                        for _ in 0..2 {
+                            match shutdown_receiver.recv_timeout(timeout) {
+                                Ok(thread) => info!("{} thread flushed", thread),
+                                Err(_) => {
+                                    warn!("shutdown timed out before every thread flushed");
+                                    break;
+                                }
+                            }
+                        }
+                        break;
+                    }
+                    Some(Command::SendTxn(addr_num, receiver, amount, fee)) => {
+                        let pending = wallet.send_txn(addr_num, receiver, amount, fee);
+                        match pending {
+                            Ok(pending) => {
+                                println!("Txn {}: {}", pending.txn_id, pending.status);
+                                save_outbox(&wallet, &outbox_path);
+                                let message = MessageType::TxnMessage {
+                                    txn: pending.txn,
+                                    sender_id: node_id.to_string().clone(),
+                                };
+                                if let Err(e) =
+                                    swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!("Error sending to command receiver: {:?}", e);
+                                };
+                            }
+                            Err(e) => println!("Error creating txn: {}", e),
+                        }
+                    }
+                    Some(Command::RequestReinstatement(addr_num, bond, fee)) => {
+                        let txn = wallet.clone().request_reinstatement(addr_num, bond, fee);
+                        let message = MessageType::TxnMessage {
+                            txn,
+                            sender_id: node_id.to_string().clone(),
+                        };
+                        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                        {
+                            println!("Error sending to command receiver: {:?}", e);
+                        };
+                    }
+                    Some(Command::AnnounceDowntime(seconds)) => {
+                        if seconds.saturating_mul(SECOND) > MAX_DOWNTIME {
+                            println!(
+                                "Downtime is capped at {} seconds, peers will cut it short",
+                                MAX_DOWNTIME / SECOND
+                            );
+                        }
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_nanos();
+                        // The claim is away from the timestamp of the block carrying it.
+                        let downtime = PlannedDowntime::sign(
+                            &wallet,
+                            now,
+                            now + seconds.saturating_mul(SECOND),
+                        );
+                        let txn = Txn::planned_downtime(&downtime);
+                        if let Err(e) = send_or_dead_letter(
+                            &wallet_to_miner_sender,
+                            Command::ProcessTxn(txn.clone()),
+                        ) {
+                            println!("Error sending planned downtime to miner: {:?}", e);
+                        }
+                        let message = MessageType::TxnMessage {
+                            txn,
+                            sender_id: node_id.to_string().clone(),
+                        };
+                        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes()))
+                        {
+                            println!("Error sending to command receiver: {:?}", e);
                         };
                     }
+                    Some(Command::NewAddress) => match wallet.new_address() {
+                        Some((address_number, address)) => {
+                            if let Err(e) = wallet.save_addresses(&addresses_path) {
+                                println!("Error saving wallet addresses: {:?}", e);
+                            }
+                            println!("Address {}: {}", address_number, address);
+                        }
+                        None => println!(
+                            "The wallet already has the most addresses it can derive ({})",
+                            MAX_ADDRESSES
+                        ),
+                    },
+                    Some(Command::SetLogLevel(target, level)) => {
+                        match (&log_levels, LevelFilter::from_str(&level)) {
+                            (Some(log_levels), Ok(level)) => {
+                                log_levels.set(&target, level);
+                                println!("Logging {} at {}", target, level);
+                            }
+                            (None, _) => println!("Logging isn't enabled"),
+                            (_, Err(_)) => println!("Invalid log level {:?}", level),
+                        }
+                    }
+                    Some(Command::RebroadcastTxn(txn_id)) => {
+                        match wallet.outbox.get(&txn_id).map(|pending| &pending.status) {
+                            Some(TxnStatus::Confirmed { .. }) => {
+                                println!("Txn {} is already confirmed", txn_id)
+                            }
+                            _ => {
+                                if let Err(_) = command_sender.send(Command::RebroadcastTxn(txn_id))
+                                {
+                                    println!("Error sending command to command receiver");
+                                }
+                            }
+                        }
+                    }
+                    Some(Command::AddSchedule(
+                        addr_num,
+                        receiver,
+                        amount,
+                        interval_blocks,
+                        payments,
+                    )) => {
+                        let id = schedules.add(
+                            addr_num,
+                            receiver,
+                            amount,
+                            MIN_TXN_FEE,
+                            interval_blocks,
+                            payments,
+                            last_height,
+                        );
+                        if let Some(schedule) = schedules.get(id) {
+                            println!("Schedule {}", schedule);
+                        }
+                        if let Err(e) = schedules.save(&schedules_path) {
+                            println!("Error saving schedules: {:?}", e);
+                        }
+                    }
+                    Some(Command::ListSchedules) => {
+                        schedules
+                            .iter()
+                            .for_each(|schedule| println!("Schedule {}", schedule));
+                    }
+                    Some(Command::CancelSchedule(id)) => {
+                        if schedules.cancel(id).is_none() {
+                            println!("No schedule {}", id);
+                        } else if let Err(e) = schedules.save(&schedules_path) {
+                            println!("Error saving schedules: {:?}", e);
+                        }
+                    }
+                    Some(Command::ResumeSchedule(id)) => {
+                        if !schedules.resume(id) {
+                            println!("Schedule {} isn't paused", id);
+                        } else if let Err(e) = schedules.save(&schedules_path) {
+                            println!("Error saving schedules: {:?}", e);
+                        }
+                    }
+                    Some(Command::ExportSnapshot(path)) => {
+                        match NodeSnapshot::export(DATA_DIR, &path) {
+                            Ok(manifest) => println!(
+                                "Exported {} files from {} to {}",
+                                manifest.members.len(),
+                                DATA_DIR,
+                                path
+                            ),
+                            Err(e) => println!("Error exporting snapshot: {}", e),
+                        }
+                    }
+                    Some(Command::SetContact(name, address)) => {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_nanos();
+                        match wallet.set_contact(&name, &address, now) {
+                            Ok(()) => {
+                                if let Err(e) = wallet.save_contacts(&contacts_path) {
+                                    println!("Error saving contacts: {:?}", e);
+                                }
+                                println!("Contact {}: {}", name, address);
+                            }
+                            Err(e) => println!("Error setting contact: {}", e),
+                        }
+                    }
+                    Some(Command::ExportSync(path, passphrase)) => {
+                        let blob = wallet.export_sync_blob(&schedules, &passphrase);
+                        match std::fs::write(&path, blob) {
+                            Ok(()) => println!("Exported wallet sync file to {}", path),
+                            Err(e) => println!("Error writing wallet sync file: {}", e),
+                        }
+                    }
+                    Some(Command::ImportSync(path, passphrase)) => {
+                        let merged = std::fs::read(&path)
+                            .map_err(|e| e.to_string())
+                            .and_then(|blob| {
+                                wallet
+                                    .merge_sync_blob(&mut schedules, &blob, &passphrase)
+                                    .map_err(|e| e.to_string())
+                            });
+                        match merged {
+                            Ok(()) => {
+                                if let Err(e) = wallet.save_addresses(&addresses_path) {
+                                    println!("Error saving wallet addresses: {:?}", e);
+                                }
+                                if let Err(e) = wallet.save_contacts(&contacts_path) {
+                                    println!("Error saving contacts: {:?}", e);
+                                }
+                                save_outbox(&wallet, &outbox_path);
+                                if let Err(e) = schedules.save(&schedules_path) {
+                                    println!("Error saving schedules: {:?}", e);
+                                }
+                                println!("Merged wallet sync file {}", path);
+                            }
+                            Err(e) => println!("Error importing wallet sync file: {}", e),
+                        }
+                    }
+                    Some(Command::GetTxnStatus(txn_id)) => {
+                        if let Some(pending) = wallet.outbox.get(&txn_id) {
+                            println!("Txn {}: {}", txn_id, pending.status);
+                        } else {
+                            println!("Txn {} wasn't sent from this wallet", txn_id);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos();
+                let expired = wallet.expire_pending_txns(now);
+                for txn_id in expired.iter() {
+                    println!("Txn {}: {}", txn_id, TxnStatus::TimedOut);
+                }
+                if !expired.is_empty() {
+                    save_outbox(&wallet, &outbox_path);
                 }
             }
         }
@@ -1,14 +1,19 @@
 use crate::blockchain::{InvalidBlockError, InvalidBlockErrorReason};
 use crate::header::BlockHeader;
+use crate::logging::BLOCKCHAIN_TARGET;
+use crate::miner::{ConfirmationStatus, QuorumConfig};
 use crate::network::chunkable::Chunkable;
-use crate::network::node::MAX_TRANSMIT_SIZE;
+use crate::params::{Activations, ProtocolParams, MAX_TRANSMIT_SIZE};
+use crate::reward::{Category, Reward};
 use crate::state::NetworkState;
+use crate::txn::txn_cost;
 use crate::verifiable::Verifiable;
 use crate::{claim::Claim, reward::RewardState, txn::Txn};
-use log::info;
+use log::debug;
 use ritelinked::LinkedHashMap;
 use serde::{Deserialize, Serialize};
 use sha256::digest_bytes;
+use std::collections::HashSet;
 use std::fmt;
 
 pub const NANO: u128 = 1;
@@ -16,8 +21,6 @@ pub const MICRO: u128 = NANO * 1000;
 pub const MILLI: u128 = MICRO * 1000;
 pub const SECOND: u128 = MILLI * 1000;
 
-const VALIDATOR_THRESHOLD: f64 = 0.60;
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[repr(C)]
 pub struct Block {
@@ -37,14 +40,7 @@ impl Block {
     // updated account state (if successful) or an error (if unsuccessful)
     pub fn genesis(reward_state: &RewardState, claim: Claim, secret_key: String) -> Option<Block> {
         let header = BlockHeader::genesis(0, reward_state, claim.clone(), secret_key);
-        let state_hash = digest_bytes(
-            format!(
-                "{},{}",
-                header.last_hash,
-                digest_bytes("Genesis_State_Hash".as_bytes())
-            )
-            .as_bytes(),
-        );
+        let state_hash = Block::genesis_hash(&header.last_hash);
 
         let mut claims = LinkedHashMap::new();
         claims.insert(claim.clone().pubkey.clone(), claim);
@@ -68,28 +64,60 @@ impl Block {
         Some(genesis)
     }
 
+    /// The hash of a genesis block, derived from the last hash in its header.
+    pub fn genesis_hash(last_hash: &str) -> String {
+        digest_bytes(
+            format!(
+                "{},{}",
+                last_hash,
+                digest_bytes("Genesis_State_Hash".as_bytes())
+            )
+            .as_bytes(),
+        )
+    }
+
+    /// Hashes the txns in a block in the order they appear in the block, this is
+    /// the txn_hash committed to in the block header. Only the id and the signed
+    /// payload of each txn are hashed, the validator votes a txn gathered differ
+    /// from node to node.
+    pub fn txn_hash(txns: &LinkedHashMap<String, Txn>) -> String {
+        let mut txn_vec = vec![];
+        txns.iter().for_each(|(txn_id, txn)| {
+            txn_vec.extend(format!("{},{};", txn_id, txn.txn_payload).as_bytes());
+        });
+        digest_bytes(&txn_vec)
+    }
+
+    /// The summed cost of the txns in a block, declared in its header.
+    pub fn block_cost(txns: &LinkedHashMap<String, Txn>) -> u64 {
+        txns.values()
+            .fold(0u64, |cost, txn| cost.saturating_add(txn_cost(txn)))
+    }
+
     /// The mine method is used to generate a new block (and an updated account state with the reward set
     /// to the miner wallet's balance), this will also update the network state with a new confirmed state.
+    /// `cancelled` is checked before the header is signed, before the block is hashed and after, the
+    /// block is dropped once it returns true. No block is mined less than a second after the last one
+    /// by `timestamp`.
     pub fn mine(
         claim: Claim,      // The claim entitling the miner to mine the block.
         last_block: Block, // The last block, which contains the current block reward.
         txns: LinkedHashMap<String, Txn>,
         claims: LinkedHashMap<String, Claim>,
         claim_map_hash: Option<String>,
-        reward_state: &RewardState,
-        network_state: &NetworkState,
+        network_state: &NetworkState, // The ledger at the last block, sets the next reward.
         neighbors: Option<Vec<BlockHeader>>,
         abandoned_claim: Option<Claim>,
         signature: String,
+        timestamp: u128, // The time on the miner's clock.
+        cancelled: impl Fn() -> bool,
     ) -> Option<Block> {
 
-        let txn_hash = {
-            let mut txn_vec = vec![];
-            txns.iter().for_each(|(_, v)| {
-                txn_vec.extend(v.as_bytes());
-            });
-            digest_bytes(&txn_vec)
-        };
+        let txn_hash = Block::txn_hash(&txns);
+        let block_cost = Block::block_cost(&txns);
+        if cancelled() {
+            return None;
+        }
 
         let neighbors_hash = {
             let mut neighbors_vec = vec![];
@@ -105,12 +133,16 @@ impl Block {
 
         let header = BlockHeader::new(
             last_block.clone(),
-            reward_state,
+            &network_state.reward_state,
             claim,
             txn_hash,
+            block_cost,
             claim_map_hash,
             neighbors_hash,
+            network_state.nonce_epoch(),
+            network_state.ledger_root.clone(),
             signature,
+            timestamp,
         );
 
         if let Some(time) = header.timestamp.checked_sub(last_block.header.timestamp) {
@@ -121,6 +153,10 @@ impl Block {
             return None
         }
 
+        if cancelled() {
+            return None;
+        }
+
         let height = last_block.height.clone() + 1;
 
         let mut block = Block {
@@ -139,9 +175,45 @@ impl Block {
 
         let hash = hashable_state.hash(block.clone());
         block.hash = hash;
+        if cancelled() {
+            return None;
+        }
         Some(block)
     }
 
+    /// The claim map hash of a block mined at `block_height` with `claim`,
+    /// `claims` and `txns` on top of the ledger in `network_state`, see
+    /// `compute_expected_claim_map_hash`.
+    pub fn claim_map_hash_after(
+        network_state: &NetworkState,
+        claim: &Claim,
+        claims: &LinkedHashMap<String, Claim>,
+        txns: &LinkedHashMap<String, Txn>,
+        block_height: u128,
+    ) -> String {
+        network_state.claim_map_hash_after_block(claim, claims, txns, block_height)
+    }
+
+    /// The checks a block built on `parent` has to pass whatever ledger it is
+    /// applied to: it's signed by its claim, links to the parent and pays no
+    /// genesis reward. A block competing with one already built on the same
+    /// parent can't be checked against the ledger, which has moved past it.
+    pub fn valid_competitor(&self, parent: &Block) -> bool {
+        self.valid_block_height(parent)
+            && self.valid_last_hash(parent)
+            && self.valid_block_nonce(parent)
+            && self.valid_genesis_reward()
+            && self.valid_block_signature()
+    }
+
+    /// Whether the block is invalid whatever ledger it's applied to: its header
+    /// isn't signed by its claim, or its txns aren't the ones the header
+    /// commits to.
+    pub fn invalid_in_itself(&self) -> bool {
+        !self.valid_block_signature()
+            || (self.header.block_height > 0 && Block::txn_hash(&self.txns) != self.header.txn_hash)
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         self.to_string().as_bytes().to_vec()
     }
@@ -161,6 +233,21 @@ impl Block {
     }
 }
 
+/// The claim map hash a block's header has to carry: the claims on the ledger
+/// the block is applied to, with the evidence, claim transfers, claims and
+/// header claim of the block applied by `NetworkState::apply_block_claims`,
+/// the function `NetworkState::dump` applies them with. The claim map is
+/// hashed with ClaimMapHash, which doesn't depend on the map's order.
+pub fn compute_expected_claim_map_hash(network_state: &NetworkState, block: &Block) -> String {
+    Block::claim_map_hash_after(
+        network_state,
+        &block.header.claim,
+        &block.claims,
+        &block.txns,
+        block.header.block_height,
+    )
+}
+
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -177,8 +264,21 @@ impl Verifiable for Block {
         true
     }
 
-    fn valid_genesis(&self, _network_state: &NetworkState, _reward_state: &RewardState) -> bool {
-        true
+    /// A genesis block is only valid alongside the reward state every node
+    /// starts from, so whoever mines it can't change the initial emission
+    /// schedule, and its own reward has to be the genesis reward. Its last
+    /// hash is the one for the params, see BlockHeader::genesis_last_hash.
+    fn valid_genesis(&self, _network_state: &NetworkState, reward_state: &RewardState) -> bool {
+        let genesis_reward = Reward::genesis(
+            Some(self.header.claim.address.clone()),
+            &reward_state.params,
+        );
+        *reward_state == RewardState::start_with(reward_state.params)
+            && self.header.last_hash == BlockHeader::genesis_last_hash(&reward_state.params)
+            && self.header.block_reward == genesis_reward
+            && (!Activations::active(Activations::default().next_block_reward, 0)
+                || self.valid_next_block_reward(reward_state))
+            && self.hash == Block::genesis_hash(&self.header.last_hash)
     }
 
     fn valid_block(
@@ -187,13 +287,34 @@ impl Verifiable for Block {
         network_state: &NetworkState,
         reward_state: &RewardState,
     ) -> Result<(), InvalidBlockError> {
+        let activations = Activations::default();
+        if !self.valid_block_height(last_block) {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidBlockHeight,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if !self.valid_genesis_reward() {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidBlockReward,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
         if !self.valid_last_hash(last_block) {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidLastHash,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
@@ -201,9 +322,51 @@ impl Verifiable for Block {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidBlockNonce,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if !self.valid_nonce_epoch(network_state) {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidNonceEpoch,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if !self.valid_block_claims(network_state) {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidClaim,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if !self.valid_claim_map_hash(network_state) {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidClaimMapHash,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if Activations::active(activations.ledger_root, self.header.block_height)
+            && !self.valid_ledger_root(network_state)
+        {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidLedgerRoot,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
@@ -211,9 +374,9 @@ impl Verifiable for Block {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidStateHash,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
@@ -221,29 +384,55 @@ impl Verifiable for Block {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidBlockReward,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
-        if !self.valid_next_block_reward(reward_state) {
+        // The next block's reward is derived from the ledger's reward state,
+        // which moves with every block applied.
+        if Activations::active(activations.next_block_reward, self.header.block_height)
+            && !self.valid_next_block_reward(&network_state.reward_state)
+        {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidBlockReward,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if !self.valid_txns(network_state, &reward_state.params) {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidTxns,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
-        if !self.valid_txns() {
+        if !self.valid_coinbase(reward_state) {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidTxns,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
+            return e;
+        }
+
+        if Activations::active(activations.block_cost, self.header.block_height)
+            && !self.valid_block_cost(&reward_state.params)
+        {
+            let e = Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::BlockCostExceeded,
+            });
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
@@ -251,9 +440,9 @@ impl Verifiable for Block {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidClaimPointers,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
@@ -261,15 +450,19 @@ impl Verifiable for Block {
             let e = Err(InvalidBlockError {
                 details: InvalidBlockErrorReason::InvalidClaim,
             });
-            info!("Invalid block: {:?}", e);
-            info!("Block that's invalid: {:?}", self);
-            info!("Last Valid Block: {:?}", &last_block);
+            debug!(target: BLOCKCHAIN_TARGET, "Invalid block: {:?}", e);
+            debug!(target: BLOCKCHAIN_TARGET, "Block that's invalid: {:?}", self);
+            debug!(target: BLOCKCHAIN_TARGET, "Last Valid Block: {:?}", &last_block);
             return e;
         }
 
         Ok(())
     }
 
+    fn valid_block_height(&self, last_block: &Block) -> bool {
+        self.header.block_height == last_block.header.block_height + 1
+    }
+
     fn valid_last_hash(&self, last_block: &Block) -> bool {
         self.header.last_hash == last_block.hash
     }
@@ -280,6 +473,12 @@ impl Verifiable for Block {
         self.hash == hash
     }
 
+    // Light clients trust the ledger root in the header, so it has to be the
+    // root of the ledger the block is applied to.
+    fn valid_ledger_root(&self, network_state: &NetworkState) -> bool {
+        self.header.ledger_root.is_some() && self.header.ledger_root == network_state.ledger_root
+    }
+
     fn valid_block_reward(&self, reward_state: &RewardState) -> bool {
         if let Some(true) = reward_state.valid_reward(self.header.block_reward.category) {
             return true;
@@ -288,20 +487,108 @@ impl Verifiable for Block {
         false
     }
 
+    // Only the block at height 0 can be paid the genesis reward, and no block
+    // can name it as the next block's reward.
+    fn valid_genesis_reward(&self) -> bool {
+        let is_genesis = |reward: &Reward| matches!(reward.category, Category::Genesis(_));
+        (self.header.block_height == 0 || !is_genesis(&self.header.block_reward))
+            && !is_genesis(&self.header.next_block_reward)
+    }
+
+    // The next block's reward has to have the category and amount the reward
+    // state and the header derive, not ones the miner picked.
     fn valid_next_block_reward(&self, reward_state: &RewardState) -> bool {
-        if let Some(true) = reward_state.valid_reward(self.header.next_block_reward.category) {
+        let reward = &self.header.next_block_reward;
+        let seed = BlockHeader::reward_seed(
+            &self.header.last_hash,
+            self.header.block_nonce,
+            self.header.block_height,
+        );
+        let derived = Reward::new(reward.miner.clone(), reward_state, &seed);
+        if *reward != derived {
+            return false;
+        }
+        if let Some(true) = reward_state.valid_reward(reward.category) {
             return true;
         }
 
         false
     }
 
-    fn valid_txns(&self) -> bool {
+    /// A block carries at most one coinbase txn, the record of its own reward.
+    /// It pays the header's reward to the miner, an amount the reward's
+    /// category holds and the reward state allows. The ledger credits the
+    /// reward from the header, never from the coinbase.
+    fn valid_coinbase(&self, reward_state: &RewardState) -> bool {
+        let mut coinbases = self.txns.values().filter(|txn| txn.is_coinbase());
+        let coinbase = match coinbases.next() {
+            Some(coinbase) => coinbase,
+            None => return true,
+        };
+        if coinbases.next().is_some() {
+            return false;
+        }
+        let reward = &self.header.block_reward;
+
+        coinbase.valid_coinbase_for(reward).is_ok()
+            && reward.category.amount() == Some(reward.amount)
+            && reward_state.valid_reward(reward.category) == Some(true)
+    }
+
+    /// Every txn but the coinbase has to have reached quorum: a signed yes
+    /// vote from at least the share of the claims eligible at the block's
+    /// height, other than the miner's, that the miner needed to confirm it.
+    /// Votes from pubkeys without an eligible claim don't count, and a vote
+    /// whose signature doesn't verify fails the block.
+    fn valid_txns(&self, network_state: &NetworkState, params: &ProtocolParams) -> bool {
         let mut valid_data: bool = true;
+        let eligible = network_state.eligible_validators(self.header.block_height);
+        let quorum = QuorumConfig {
+            threshold: params.validator_share(),
+        };
+        // The pubkeys of the claims sold in the block and of their buyers.
+        let mut transferred: HashSet<String> = HashSet::new();
 
         self.txns.iter().for_each(|(_, txn)| {
-            let n_valid = txn.validators.iter().filter(|(_, &valid)| valid).count();
-            if (n_valid as f64 / txn.validators.len() as f64) < VALIDATOR_THRESHOLD {
+            // Checked against the block's reward by valid_coinbase.
+            if txn.is_coinbase() {
+                return;
+            }
+            if txn
+                .validators
+                .keys()
+                .any(|pubkey| !txn.valid_vote_signature(pubkey))
+            {
+                valid_data = false
+            }
+            if quorum.status(txn, &eligible, &self.header.claim.pubkey)
+                != ConfirmationStatus::Confirmed
+            {
+                valid_data = false
+            }
+            // A claim is sold at most once a block, to a buyer no other sale
+            // in the block is to.
+            if txn.is_claim_transfer() {
+                let unique = txn.claim_transfer_data().map_or(false, |transfer| {
+                    transferred.insert(transfer.claim_pubkey)
+                        && transferred.insert(transfer.to.pubkey)
+                });
+                if !unique || txn.valid_claim_transfer(network_state).is_err() {
+                    valid_data = false
+                }
+            }
+            if txn.is_slashing_evidence() {
+                // Unsigned, the evidence it carries is checked instead.
+                if txn.valid_slashing_evidence(network_state).is_err() {
+                    valid_data = false
+                }
+            } else if txn.is_planned_downtime() {
+                // Unsigned, the announcement it carries is checked instead.
+                if txn.valid_planned_downtime(network_state).is_err() {
+                    valid_data = false
+                }
+            } else if !txn.valid_txn_signature() {
+                // Signatures the validators checked are found in the signature cache.
                 valid_data = false
             }
         });
@@ -313,15 +600,50 @@ impl Verifiable for Block {
         self.header.block_nonce == last_block.header.next_block_nonce
     }
 
+    /// A block mined on another nonce epoch than the ledger's was mined with
+    /// other claim pointers than the ones it's checked against.
+    fn valid_nonce_epoch(&self, network_state: &NetworkState) -> bool {
+        self.header.nonce_epoch == network_state.nonce_epoch()
+    }
+
+    /// A claim the ledger holds as ineligible can't be announced again in a
+    /// block, it's only made eligible by waiting out its reinstatement.
+    fn valid_block_claims(&self, network_state: &NetworkState) -> bool {
+        if self.claims.is_empty() {
+            return true;
+        }
+        let ineligible: Vec<String> = network_state
+            .get_ineligible_claims()
+            .into_iter()
+            .map(|claim| claim.pubkey)
+            .collect();
+        self.claims.iter().all(|(pubkey, claim)| {
+            !ineligible.contains(pubkey) && !ineligible.contains(&claim.pubkey)
+        })
+    }
+
+    /// Checked against the claims on the ledger rather than a miner's claim
+    /// map, so a node whose claim map is behind doesn't reject a block it
+    /// should accept.
+    fn valid_claim_map_hash(&self, network_state: &NetworkState) -> bool {
+        self.header.claim_map_hash.as_deref()
+            == Some(compute_expected_claim_map_hash(network_state, self).as_str())
+    }
+
+    fn valid_block_cost(&self, params: &ProtocolParams) -> bool {
+        let block_cost = Block::block_cost(&self.txns);
+        block_cost == self.header.block_cost && block_cost <= params.block_cost_limit
+    }
+
     fn valid_claim_pointer(&self, network_state: &NetworkState) -> bool {
         if let Some((hash, pointers)) =
-            network_state.get_lowest_pointer(self.header.block_nonce as u128)
+            network_state.get_lowest_pointer(u128::from(self.header.block_nonce))
         {
             if hash == self.header.claim.hash {
                 if let Some(claim_pointer) = self
                     .header
                     .claim
-                    .get_pointer(self.header.block_nonce as u128)
+                    .get_pointer(u128::from(self.header.block_nonce))
                 {
                     if pointers == claim_pointer {
                         return true;
@@ -339,7 +661,7 @@ impl Verifiable for Block {
         }
     }
 
-    fn valid_block_signature(&self) -> bool {
+    pub fn valid_block_signature(&self) -> bool {
         if let Ok(true) = self.header.verify() {
             return true;
         } else {
@@ -359,28 +681,31 @@ impl Verifiable for Block {
         );
 
         if recreated_claim.hash != self.header.claim.hash {
-            info!("Claim hash is incorrect, doesn't match recreated claim hash");
+            debug!(
+                target: BLOCKCHAIN_TARGET,
+                "Claim hash is incorrect, doesn't match recreated claim hash"
+            );
             return false;
         }
 
         let network_state_claim = claims.get(&self.header.claim.pubkey).unwrap();
 
         if network_state_claim.pubkey != self.header.claim.pubkey {
-            info!("Claim pubkey doesn't match records");
+            debug!(target: BLOCKCHAIN_TARGET, "Claim pubkey doesn't match records");
             return false;
         }
         if network_state_claim.address != self.header.claim.address {
-            info!("Claim address doesn't match records");
+            debug!(target: BLOCKCHAIN_TARGET, "Claim address doesn't match records");
             return false;
         }
 
         if network_state_claim.hash != self.header.claim.hash {
-            info!("Claim hash doesn't match records");
+            debug!(target: BLOCKCHAIN_TARGET, "Claim hash doesn't match records");
             return false;
         }
 
         if network_state_claim.nonce != self.header.claim.nonce {
-            info!("Claim nonce doesn't match records");
+            debug!(target: BLOCKCHAIN_TARGET, "Claim nonce doesn't match records");
             return false;
         }
 
@@ -416,3 +741,397 @@ impl Chunkable for Block {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slashing::SlashingEvidence;
+    use crate::state::Ledger;
+    use crate::token::TokenLedger;
+    use crate::validator::TxnValidator;
+    use crate::wallet::{Address, AddressNetwork, WalletAccount};
+    use std::sync::{Arc, Mutex};
+
+    // A ledger with an eligible claim for the miner and each validator.
+    fn network_state(name: &str, validators: &[&WalletAccount]) -> NetworkState {
+        let db_path = std::env::temp_dir()
+            .join(name)
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&db_path);
+        let mut claims = LinkedHashMap::new();
+        std::iter::once("miner".to_string())
+            .chain(validators.iter().map(|validator| validator.get_pubkey()))
+            .for_each(|pubkey| {
+                let claim = Claim::new(pubkey.clone(), format!("address_{}", pubkey), 1);
+                claims.insert(pubkey, claim);
+            });
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        network_state
+    }
+
+    fn block_with(txn: Txn) -> Block {
+        let reward = Reward {
+            miner: None,
+            category: Category::Flake(Some(10)),
+            amount: 10,
+        };
+        let header = BlockHeader {
+            last_hash: String::new(),
+            block_nonce: 0,
+            next_block_nonce: 0,
+            block_height: 1,
+            timestamp: 0,
+            txn_hash: String::new(),
+            block_cost: 0,
+            claim: Claim::new("miner".to_string(), "address_miner".to_string(), 1),
+            claim_map_hash: None,
+            block_reward: reward.clone(),
+            next_block_reward: reward,
+            neighbor_hash: None,
+            nonce_epoch: 0,
+            ledger_root: None,
+            signature: String::new(),
+        };
+        let mut txns = LinkedHashMap::new();
+        txns.insert(txn.txn_id.clone(), txn);
+        Block {
+            header,
+            neighbors: None,
+            height: 1,
+            txns,
+            claims: LinkedHashMap::new(),
+            hash: String::new(),
+            received_at: None,
+            received_from: None,
+            abandoned_claim: None,
+        }
+    }
+
+    // Derives the next block's reward again after a test changes the header
+    // it's derived from.
+    fn derive_next_reward(block: &mut Block) {
+        let header = &block.header;
+        let seed =
+            BlockHeader::reward_seed(&header.last_hash, header.block_nonce, header.block_height);
+        block.header.next_block_reward = Reward::new(None, &RewardState::start(), &seed);
+    }
+
+    fn signed_txn() -> Txn {
+        let mut wallet = WalletAccount::new();
+        let address = wallet.get_address(1);
+        let receiver = Address::from_pubkey(AddressNetwork::default(), "receiver", 1).to_string();
+        Txn::new(Arc::new(Mutex::new(wallet)), address, receiver, 1, 0, 0)
+    }
+
+    fn vote(txn: &mut Txn, validator: &WalletAccount, signature: String) {
+        txn.validators.insert(validator.get_pubkey(), true);
+        txn.validator_signatures
+            .insert(validator.get_pubkey(), signature);
+    }
+
+    #[test]
+    fn txn_without_validators_is_rejected() {
+        let validator = WalletAccount::new();
+        let network_state = network_state("vrrb_test_block_no_validators.db", &[&validator]);
+        let block = block_with(signed_txn());
+        assert!(!block.valid_txns(&network_state, &ProtocolParams::default()));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn forged_validator_signature_is_rejected() {
+        let validator = WalletAccount::new();
+        let network_state = network_state("vrrb_test_block_forged_vote.db", &[&validator]);
+        let mut txn = signed_txn();
+        let forged = TxnValidator::sign_vote(&txn, true, &WalletAccount::new().get_secretkey());
+        vote(&mut txn, &validator, forged);
+        assert!(!block_with(txn).valid_txns(&network_state, &ProtocolParams::default()));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn txn_validated_by_a_quorum_passes() {
+        let validators = [WalletAccount::new(), WalletAccount::new()];
+        let network_state = network_state(
+            "vrrb_test_block_quorum.db",
+            &validators.iter().collect::<Vec<_>>(),
+        );
+        let mut txn = signed_txn();
+        let signature = TxnValidator::sign_vote(&txn, true, &validators[0].get_secretkey());
+        vote(&mut txn, &validators[0], signature);
+        // One of two validators is short of the 60% quorum.
+        assert!(!block_with(txn.clone()).valid_txns(&network_state, &ProtocolParams::default()));
+
+        let signature = TxnValidator::sign_vote(&txn, true, &validators[1].get_secretkey());
+        vote(&mut txn, &validators[1], signature);
+        // A yes vote from a pubkey without a claim doesn't count but is allowed.
+        let outsider = WalletAccount::new();
+        let signature = TxnValidator::sign_vote(&txn, true, &outsider.get_secretkey());
+        vote(&mut txn, &outsider, signature);
+        assert!(block_with(txn).valid_txns(&network_state, &ProtocolParams::default()));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_with_a_doctored_claim_map_hash_is_rejected() {
+        let network_state = network_state("vrrb_test_block_claim_map_hash.db", &[]);
+        let mut last_block = block_with(Txn::unsigned_transfer(1, 0));
+        last_block.header.block_height = 0;
+        last_block.hash = "last_block".to_string();
+        let mut block = block_with(Txn::unsigned_transfer(1, 0));
+        block.header.last_hash = last_block.hash.clone();
+        derive_next_reward(&mut block);
+        let new_claim = Claim::new("new".to_string(), "address_new".to_string(), 1);
+        block.claims.insert(new_claim.pubkey.clone(), new_claim);
+
+        // The hash of the ledger's claims without the block's claims applied.
+        let mut doctored = block.clone();
+        doctored.claims = LinkedHashMap::new();
+        block.header.claim_map_hash =
+            Some(compute_expected_claim_map_hash(&network_state, &doctored));
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        assert!(matches!(
+            e.details,
+            InvalidBlockErrorReason::InvalidClaimMapHash
+        ));
+
+        block.header.claim_map_hash = Some(compute_expected_claim_map_hash(&network_state, &block));
+        assert!(block.valid_claim_map_hash(&network_state));
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        assert!(!matches!(
+            e.details,
+            InvalidBlockErrorReason::InvalidClaimMapHash
+        ));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_committing_another_ledger_root_is_rejected() {
+        let network_state = network_state("vrrb_test_block_ledger_root.db", &[]);
+        let mut last_block = block_with(Txn::unsigned_transfer(1, 0));
+        last_block.header.block_height = 0;
+        last_block.hash = "last_block".to_string();
+        let mut block = block_with(Txn::unsigned_transfer(1, 0));
+        block.header.last_hash = last_block.hash.clone();
+        derive_next_reward(&mut block);
+        block.header.claim_map_hash = Some(compute_expected_claim_map_hash(&network_state, &block));
+
+        block.header.ledger_root = Some(digest_bytes(b"another ledger"));
+        assert!(!block.valid_ledger_root(&network_state));
+        // Only rejected once the rule is active at the block's height.
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        let active = Activations::active(
+            Activations::default().ledger_root,
+            block.header.block_height,
+        );
+        assert_eq!(
+            matches!(e.details, InvalidBlockErrorReason::InvalidLedgerRoot),
+            active
+        );
+
+        block.header.ledger_root = network_state.ledger_root.clone();
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        assert!(!matches!(
+            e.details,
+            InvalidBlockErrorReason::InvalidLedgerRoot
+        ));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_reintroducing_a_slashed_claim_is_rejected() {
+        let validator = WalletAccount::new();
+        let mut network_state = network_state("vrrb_test_block_slashed_claim.db", &[&validator]);
+        network_state.slash_claims(vec![validator.get_pubkey()], 0);
+        let mut last_block = block_with(Txn::unsigned_transfer(1, 0));
+        last_block.header.block_height = 0;
+        last_block.hash = "last_block".to_string();
+        let mut block = block_with(Txn::unsigned_transfer(1, 0));
+        block.header.last_hash = last_block.hash.clone();
+        derive_next_reward(&mut block);
+        let pubkey = validator.get_pubkey();
+        let announced = Claim::new(pubkey.clone(), format!("address_{}", pubkey), 1);
+        assert!(announced.eligible);
+        block.claims.insert(pubkey, announced);
+        block.header.claim_map_hash = Some(compute_expected_claim_map_hash(&network_state, &block));
+
+        assert!(!block.valid_block_claims(&network_state));
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        assert!(matches!(e.details, InvalidBlockErrorReason::InvalidClaim));
+
+        let new_claim = Claim::new("new".to_string(), "address_new".to_string(), 1);
+        block.claims = LinkedHashMap::new();
+        block.claims.insert(new_claim.pubkey.clone(), new_claim);
+        assert!(block.valid_block_claims(&network_state));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn yes_vote_is_slashed_only_against_a_quorum_of_no_votes() {
+        let validators = [
+            WalletAccount::new(),
+            WalletAccount::new(),
+            WalletAccount::new(),
+            WalletAccount::new(),
+        ];
+        let mut network_state = network_state(
+            "vrrb_test_block_rejected_vote.db",
+            &validators.iter().collect::<Vec<_>>(),
+        );
+        let mut txn = signed_txn();
+        let cast = |txn: &mut Txn, validator: &WalletAccount, vote: bool| {
+            let signature = TxnValidator::sign_vote(txn, vote, &validator.get_secretkey());
+            txn.validators.insert(validator.get_pubkey(), vote);
+            txn.validator_signatures
+                .insert(validator.get_pubkey(), signature);
+        };
+        cast(&mut txn, &validators[0], true);
+        cast(&mut txn, &validators[1], false);
+        let offender = validators[0].get_pubkey();
+        assert!(SlashingEvidence::rejected_txn_vote(&txn, &validators[1].get_pubkey()).is_none());
+
+        // One no vote of the four other claims is short of the 60% quorum.
+        let evidence = SlashingEvidence::rejected_txn_vote(&txn, &offender).unwrap();
+        assert!(evidence.verify().is_ok());
+        let short = Txn::slashing_evidence(&evidence);
+        assert!(short.valid_slashing_evidence(&network_state).is_err());
+
+        // A forged no vote doesn't verify.
+        let mut forged = txn.clone();
+        forged.validators.insert(validators[2].get_pubkey(), false);
+        forged.validator_signatures.insert(
+            validators[2].get_pubkey(),
+            TxnValidator::sign_vote(&txn, false, &WalletAccount::new().get_secretkey()),
+        );
+        let evidence = SlashingEvidence::rejected_txn_vote(&forged, &offender).unwrap();
+        assert!(evidence.verify().is_err());
+
+        cast(&mut txn, &validators[2], false);
+        cast(&mut txn, &validators[3], false);
+        let evidence = SlashingEvidence::rejected_txn_vote(&txn, &offender).unwrap();
+        assert_eq!(evidence.offender(), offender);
+        let evidence_txn = Txn::slashing_evidence(&evidence);
+        assert!(evidence_txn.valid_slashing_evidence(&network_state).is_ok());
+        assert_eq!(network_state.is_claim_eligible(&offender), Some(true));
+
+        // The claim is slashed once a block carrying the evidence is applied.
+        network_state.dump(&block_with(evidence_txn.clone()));
+        assert_eq!(network_state.is_claim_eligible(&offender), Some(false));
+        assert!(evidence_txn
+            .valid_slashing_evidence(&network_state)
+            .is_err());
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_mined_on_a_stale_nonce_epoch_is_rejected() {
+        let mut network_state = network_state("vrrb_test_block_nonce_epoch.db", &[]);
+        assert_eq!(network_state.nonce_up(), 1);
+        let mut last_block = block_with(Txn::unsigned_transfer(1, 0));
+        last_block.header.block_height = 0;
+        last_block.header.next_block_nonce = 7;
+        last_block.hash = "last_block".to_string();
+        let mut block = block_with(Txn::unsigned_transfer(1, 0));
+        block.header.last_hash = last_block.hash.clone();
+        block.header.block_nonce = 7;
+        derive_next_reward(&mut block);
+
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        assert!(matches!(
+            e.details,
+            InvalidBlockErrorReason::InvalidNonceEpoch
+        ));
+
+        // On the ledger's epoch the block gets past the epoch check.
+        block.header.nonce_epoch = 1;
+        let e = block
+            .valid_block(&last_block, &network_state, &RewardState::start())
+            .unwrap_err();
+        assert!(!matches!(
+            e.details,
+            InvalidBlockErrorReason::InvalidNonceEpoch
+        ));
+        let _ = std::fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn next_reward_with_a_picked_amount_is_rejected() {
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let reward_state = RewardState::start();
+        let genesis = Block::genesis(&reward_state, claim, wallet.get_secretkey()).unwrap();
+        assert!(genesis.valid_next_block_reward(&reward_state));
+
+        // Another amount in the category's range isn't the one the header derives.
+        let derived = genesis.header.next_block_reward.clone();
+        let (low, high) = derived.category.reward_range(&reward_state.params).unwrap();
+        let amount = (low..high)
+            .find(|amount| *amount != derived.amount)
+            .unwrap();
+        let category = match derived.category {
+            Category::Flake(_) => Category::Flake(Some(amount)),
+            Category::Grain(_) => Category::Grain(Some(amount)),
+            Category::Nugget(_) => Category::Nugget(Some(amount)),
+            Category::Vein(_) => Category::Vein(Some(amount)),
+            Category::Motherlode(_) => Category::Motherlode(Some(amount)),
+            Category::Genesis(_) => unreachable!(),
+        };
+        let mut picked = genesis.clone();
+        picked.header.next_block_reward = Reward {
+            miner: None,
+            category,
+            amount,
+        };
+        assert!(reward_state.valid_reward(category).unwrap());
+        assert!(!picked.valid_next_block_reward(&reward_state));
+    }
+
+    #[test]
+    fn next_reward_with_a_picked_category_is_rejected() {
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let reward_state = RewardState::start();
+        let genesis = Block::genesis(&reward_state, claim, wallet.get_secretkey()).unwrap();
+
+        // Another category the reward state allows, with the amount the header
+        // derives for it.
+        let derived = genesis.header.next_block_reward.category;
+        let category = match derived {
+            Category::Flake(_) => Category::Grain(None),
+            _ => Category::Flake(None),
+        };
+        let header = &genesis.header;
+        let seed =
+            BlockHeader::reward_seed(&header.last_hash, header.block_nonce, header.block_height);
+        let mut picked = genesis.clone();
+        picked.header.next_block_reward =
+            Reward::from_category(None, category, &seed, &reward_state.params);
+        assert!(reward_state
+            .valid_reward(picked.header.next_block_reward.category)
+            .unwrap());
+        assert!(!picked.valid_next_block_reward(&reward_state));
+    }
+}
@@ -1,21 +1,74 @@
+use crate::audit::{AuditFinding, AuditReport};
 use crate::block::Block;
+use crate::checkpoint::{Checkpoint, CheckpointError};
 use crate::fields::GettableFields;
 use crate::header::BlockHeader;
+use crate::logging::BLOCKCHAIN_TARGET;
 use crate::network::chunkable::Chunkable;
 use crate::network::command_utils::Command;
 use crate::network::message_types::MessageType;
-use crate::network::node::MAX_TRANSMIT_SIZE;
-use crate::reward::RewardState;
-use crate::state::NetworkState;
+use crate::network::node::NodeAuth;
+use crate::network::topics::NETWORK_ID;
+use crate::params::MAX_TRANSMIT_SIZE;
+use crate::reward::{Category, RewardState};
+use crate::slashing::SlashingEvidence;
+use crate::state::{Components, NetworkState, StateSnapshot};
+use crate::utils::take_flag;
 use crate::verifiable::Verifiable;
+use log::{debug, error, info, warn};
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
 use ritelinked::LinkedHashMap;
 use serde::{Deserialize, Serialize};
-use std::collections::LinkedList;
+use sha256::digest_bytes;
+use std::collections::{BTreeMap, HashMap, LinkedList};
 use std::error::Error;
 use std::fmt;
+use std::fs;
 use std::thread;
 
+/// The number of blocks that must be built on top of a block before it is
+/// considered irreversible. Forks may never unwind past a final block.
+pub const FINALITY_DEPTH: u128 = 100;
+/// The default cap on stashed future blocks. A node further behind than the
+/// finality depth syncs state instead of replaying stashed blocks, so there is
+/// no need to keep more than that window.
+pub const MAX_FUTURE_BLOCKS: usize = FINALITY_DEPTH as usize;
+/// The most blocks served for a single block range request, a node further
+/// behind asks again from where the last range ended.
+pub const MAX_BLOCK_RANGE: u128 = 100;
+/// The most missing heights requested for a block ahead of the tip, the rest
+/// are requested once those are applied.
+pub const MAX_SYNC_RANGE: u128 = MAX_BLOCK_RANGE;
+/// The most recently confirmed blocks kept by hash, so one gossiped again or
+/// replayed from the backlog isn't validated twice.
+pub const BLOCK_CACHE_SIZE: usize = 100;
+/// The most blocks remembered as invalid by hash. Invalid blocks are cheap to
+/// gossip, so more of them are kept than confirmed ones.
+pub const INVALID_CACHE_SIZE: usize = 1000;
+// Version 1 keys blocks by their own hash with an index of the hashes at each
+// height. Older dbs keyed blocks by their last hash, so competing blocks with
+// the same parent overwrote each other.
+pub const CHAIN_DB_VERSION: u32 = 1;
+const CHAIN_DB_VERSION_KEY: &str = "db_version";
+// The height the archive was pruned below, see Blockchain::prune_archive.
+const PRUNED_BELOW_KEY: &str = "pruned_below";
+// The headers of the pruned blocks and the genesis block, kept when their
+// bodies are pruned so the chain is rebuilt with them, see Blockchain::open.
+const PRUNED_HEADERS_KEY: &str = "pruned_headers";
+const PRUNED_GENESIS_KEY: &str = "pruned_genesis";
+/// The default cap on a whole ledger or archive sent to a peer, in bytes. A
+/// chain past it is synced by block ranges instead.
+pub const MAX_STATE_RESPONSE_SIZE: usize = 50 * 1024 * 1024;
+pub const MAX_STATE_RESPONSE_FLAG: &str = "--max-state-response";
+pub const NETWORK_GENESIS_FLAG: &str = "--genesis-hash";
+const CHAIN_DB_HEIGHT_PREFIX: &str = "height:";
+/// The most heights competing tips are remembered for, the oldest are
+/// forgotten first.
+pub const MAX_COMPETING_TIPS: usize = 100;
+/// The most recent blocks whose heights and txns are indexed in memory. Older
+/// blocks are final, their heights are looked up in the chain db instead.
+pub const MAX_INDEXED_BLOCKS: usize = 10 * FINALITY_DEPTH as usize;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blockchain {
     pub genesis: Option<Block>,
@@ -25,9 +78,86 @@ pub struct Blockchain {
     pub chain_db: String, // Path to the chain database.
     pub block_cache: LinkedHashMap<String, Block>,
     pub future_blocks: LinkedHashMap<String, Block>,
-    pub invalid: LinkedHashMap<String, Block>,
+    pub invalid: LinkedHashMap<String, Block>, // block digest -> block
     pub updating_state: bool,
     pub state_update_cache: LinkedHashMap<u128, LinkedHashMap<u128, Vec<u8>>>,
+    pub block_heights: LinkedHashMap<String, u128>, // block hash -> block height
+    pub txn_blocks: LinkedHashMap<String, String>,  // txn id -> block hash
+    pub last_finalized: Option<u128>,
+    pub max_future_blocks: usize,
+    // Block bodies below this height have been pruned from the chain db.
+    #[serde(default)]
+    pub pruned_below: Option<u128>,
+    // Blocks run through validation since the node started.
+    #[serde(skip)]
+    pub validations: u64,
+    // The header hash of the network's genesis block. While the chain has no
+    // genesis block, genesis blocks other peers mined are rejected. None
+    // accepts the first valid one.
+    #[serde(default)]
+    pub network_genesis: Option<String>,
+    // The blocks seen at heights where more than one block was built on the
+    // same parent, by block hash, see fork_events.
+    #[serde(default)]
+    pub competing_tips: LinkedHashMap<u128, LinkedHashMap<String, BlockHeader>>,
+    // The hash of the block that got built on at a competing height, and the
+    // timestamp of the block built on it.
+    #[serde(default)]
+    pub fork_resolutions: LinkedHashMap<u128, (String, u128)>,
+    // The txns in each indexed block, so they leave txn_blocks with it.
+    #[serde(default)]
+    pub block_txns: LinkedHashMap<String, Vec<String>>,
+}
+
+/// A height where competing blocks were seen, see Blockchain::fork_events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkEvent {
+    pub block_height: u128,
+    // Every competing block's hash, in the order they were seen.
+    pub tips: Vec<String>,
+    // None until one of the tips is built on.
+    pub winner: Option<String>,
+    pub losers: Vec<String>,
+    // Nanoseconds from the earliest tip to the block built on the winner, by
+    // their header timestamps.
+    pub resolution_delay: Option<u128>,
+}
+
+/// Why a node declined to send a peer the state it requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeclineReason {
+    // Only full nodes send a whole ledger or archive.
+    NotFullNode,
+    // The node is syncing its own state and may be behind.
+    UpdatingState,
+    // The node's archive is pruned, it can't send the blocks from genesis.
+    Pruned,
+    // Over the node's max state response size, the requester should sync by
+    // block ranges instead.
+    TooLarge,
+}
+
+/// What processing a block that passed or was already known did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOutcome {
+    // The block was validated and appended to the chain.
+    Confirmed,
+    // The block was confirmed or rejected before, it wasn't validated again.
+    AlreadyKnown,
+}
+
+/// Where a block with an invalid height sits relative to the local tip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockHeightCheck {
+    // The block is ahead of the tip, the blocks in between are missing.
+    Future(Vec<u128>),
+    // The block is at or below the tip, the sender is behind.
+    Stale(u128),
+    // The block is built on the tip but skips heights, it will never apply.
+    Skipped,
+    // The block isn't signed by its claim, its height can't be trusted.
+    Unsigned,
+    Next,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +171,18 @@ pub enum InvalidBlockErrorReason {
     InvalidBlockReward,
     InvalidTxns,
     InvalidClaimPointers,
+    // The txns cost more than the block cost limit, or not what the header declares.
+    BlockCostExceeded,
+    // The block was mined on another nonce epoch than the ledger is on.
+    InvalidNonceEpoch,
+    // The header's claim map hash isn't the ledger's claims with the block's
+    // claims applied.
+    InvalidClaimMapHash,
+    // The header's ledger root isn't the root of the ledger the block is
+    // applied to.
+    InvalidLedgerRoot,
+    // The block's claim already signed the confirmed block at its height.
+    SelfConflict,
     General,
 }
 
@@ -71,12 +213,122 @@ impl Blockchain {
             invalid: LinkedHashMap::new(),
             updating_state: false,
             state_update_cache: LinkedHashMap::new(),
+            block_heights: LinkedHashMap::new(),
+            txn_blocks: LinkedHashMap::new(),
+            last_finalized: None,
+            max_future_blocks: MAX_FUTURE_BLOCKS,
+            pruned_below: None,
+            validations: 0,
+            network_genesis: None,
+            competing_tips: LinkedHashMap::new(),
+            fork_resolutions: LinkedHashMap::new(),
+            block_txns: LinkedHashMap::new(),
+        }
+    }
+
+    /// A chain backed by the chain db at `path`, rebuilt from the blocks in
+    /// it so a restarted node picks up at the tip it stopped at, with the
+    /// height its archive was pruned below read back from the db.
+    pub fn open(path: &str) -> Blockchain {
+        let mut blockchain = Blockchain::new(path);
+        let db = blockchain.get_chain_db();
+        blockchain.pruned_below = db.get::<Option<u128>>(PRUNED_BELOW_KEY).flatten();
+        blockchain.restore_chain(&db);
+        blockchain
+    }
+
+    /// Rebuilds the chain from the blocks in the chain db: the longest run of
+    /// linked blocks up from genesis, or from the lowest height a pruned
+    /// archive kept, taking the block stored first where competing blocks
+    /// are as long. The recent blocks are indexed again, and the blocks final
+    /// before the restart aren't reported as newly finalized.
+    fn restore_chain(&mut self, db: &PickleDb) {
+        let mut heights: Vec<(u128, Vec<String>)> = db
+            .get_all()
+            .into_iter()
+            .filter_map(|key| {
+                let height = key
+                    .strip_prefix(CHAIN_DB_HEIGHT_PREFIX)?
+                    .parse::<u128>()
+                    .ok()?;
+                Some((height, db.get::<Vec<String>>(&key).unwrap_or_default()))
+            })
+            .collect();
+        heights.sort_by_key(|(height, _)| *height);
+
+        let lowest = self.pruned_below.unwrap_or(0);
+        let mut linked: HashMap<String, Block> = HashMap::new();
+        let mut tip = None;
+        let mut next_height = lowest;
+        for (height, hashes) in heights.into_iter().filter(|(height, _)| *height >= lowest) {
+            if height != next_height {
+                break;
+            }
+            let mut reached = None;
+            for hash in hashes.iter() {
+                let block = match db.get::<Block>(hash) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                let parent_linked = linked
+                    .get(&block.header.last_hash)
+                    .map_or(false, |parent| parent.header.block_height + 1 == height);
+                if height == lowest || parent_linked {
+                    reached.get_or_insert_with(|| hash.clone());
+                    linked.insert(hash.clone(), block);
+                }
+            }
+            match reached {
+                Some(hash) => tip = Some(hash),
+                None => break,
+            }
+            next_height = height + 1;
+        }
+
+        let mut blocks = vec![];
+        let mut next = tip;
+        while let Some(block) = next.take().and_then(|hash| linked.remove(&hash)) {
+            next = Some(block.header.last_hash.clone());
+            blocks.push(block);
+        }
+        blocks.reverse();
+        if blocks.is_empty() {
+            return;
         }
+
+        self.genesis = db
+            .get::<Block>(PRUNED_GENESIS_KEY)
+            .or_else(|| blocks.first().cloned());
+        self.chain = db
+            .get::<Vec<BlockHeader>>(PRUNED_HEADERS_KEY)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(blocks.iter().map(|block| block.header.clone()))
+            .collect();
+        let recent = blocks.split_off(blocks.len().saturating_sub(MAX_INDEXED_BLOCKS));
+        recent.iter().for_each(|block| self.index_block(block));
+        self.child = recent.last().cloned();
+        self.parent = recent.iter().rev().nth(1).cloned();
+        self.last_finalized = self.finalized_height();
+
+        info!(
+            target: BLOCKCHAIN_TARGET,
+            "Restored chain up to height {} from {}",
+            self.tip_height().unwrap_or(0),
+            self.chain_db
+        );
+    }
+
+    /// Whether the body of the block at `block_height` was pruned from the
+    /// chain db.
+    pub fn is_pruned(&self, block_height: u128) -> bool {
+        self.pruned_below
+            .map_or(false, |pruned| block_height < pruned)
     }
 
     pub fn check_next_block_height(&self, block: &Block) -> bool {
         if let Some(_) = self.genesis.as_ref() {
-            if let Some(child) = self.child.as_ref() {
+            if let Some(child) = self.tip() {
                 if child.header.block_height + 1 != block.header.block_height {
                     return false;
                 } else {
@@ -100,18 +352,89 @@ impl Blockchain {
 
     pub fn get_chain_db(&self) -> PickleDb {
         match PickleDb::load_bin(self.chain_db.clone(), PickleDbDumpPolicy::DumpUponRequest) {
-            Ok(nst) => nst,
-            Err(_) => PickleDb::new(
-                self.chain_db.clone(),
-                PickleDbDumpPolicy::DumpUponRequest,
-                SerializationMethod::Bin,
-            ),
+            Ok(mut nst) => {
+                if nst.get::<u32>(CHAIN_DB_VERSION_KEY) != Some(CHAIN_DB_VERSION) {
+                    if let Err(e) = Blockchain::migrate_chain_db(&mut nst) {
+                        println!("Error migrating chain db: {:?}", e);
+                    }
+                }
+                nst
+            }
+            Err(_) => {
+                let mut db = PickleDb::new(
+                    self.chain_db.clone(),
+                    PickleDbDumpPolicy::DumpUponRequest,
+                    SerializationMethod::Bin,
+                );
+                if let Err(e) = db.set(CHAIN_DB_VERSION_KEY, &CHAIN_DB_VERSION) {
+                    println!("Error setting chain db version: {:?}", e);
+                }
+                db
+            }
+        }
+    }
+
+    /// Rewrites a chain db with blocks keyed by their last hash to one keyed
+    /// by block hash, indexing the blocks by height.
+    fn migrate_chain_db(db: &mut PickleDb) -> Result<(), Box<dyn Error>> {
+        let blocks: Vec<(String, Block)> = Blockchain::block_keys(db)
+            .into_iter()
+            .filter_map(|key| db.get::<Block>(&key).map(|block| (key, block)))
+            .collect();
+
+        for (key, _) in blocks.iter() {
+            db.rem(key)?;
+        }
+        for (_, block) in blocks.iter() {
+            Blockchain::store_block(db, block)?;
+        }
+        db.set(CHAIN_DB_VERSION_KEY, &CHAIN_DB_VERSION)?;
+        db.dump()?;
+
+        info!(
+            target: BLOCKCHAIN_TARGET,
+            "Migrated {} blocks to chain db version {}",
+            blocks.len(),
+            CHAIN_DB_VERSION
+        );
+        Ok(())
+    }
+
+    /// The keys of the blocks in the chain db, leaving out the version, what
+    /// is kept of the pruned blocks and the height index.
+    fn block_keys(db: &PickleDb) -> Vec<String> {
+        db.get_all()
+            .into_iter()
+            .filter(|key| {
+                key != CHAIN_DB_VERSION_KEY
+                    && key != PRUNED_BELOW_KEY
+                    && key != PRUNED_HEADERS_KEY
+                    && key != PRUNED_GENESIS_KEY
+                    && !key.starts_with(CHAIN_DB_HEIGHT_PREFIX)
+            })
+            .collect()
+    }
+
+    fn height_key(block_height: u128) -> String {
+        format!("{}{}", CHAIN_DB_HEIGHT_PREFIX, block_height)
+    }
+
+    /// Stores a block under its hash and adds it to the candidates at its height.
+    fn store_block(db: &mut PickleDb, block: &Block) -> Result<(), Box<dyn Error>> {
+        db.set(&block.hash, block)?;
+        let key = Blockchain::height_key(block.header.block_height);
+        let mut hashes = db.get::<Vec<String>>(&key).unwrap_or_default();
+        if !hashes.contains(&block.hash) {
+            hashes.push(block.hash.clone());
+            db.set(&key, &hashes)?;
         }
+
+        Ok(())
     }
 
     pub fn clone_chain_db(&self) -> PickleDb {
         let db = self.get_chain_db();
-        let keys = db.get_all();
+        let keys = Blockchain::block_keys(&db);
 
         let mut cloned_db = PickleDb::new(
             format!("temp_{}.db", self.chain_db.clone()),
@@ -123,10 +446,7 @@ impl Blockchain {
             let block = db.get::<Block>(k);
             if let Some(block) = block {
                 if let Err(e) = cloned_db.set(k, &block) {
-                    println!(
-                        "Error setting block with last_hash {} to cloned_db: {:?}",
-                        k, e
-                    );
+                    println!("Error setting block {} to cloned_db: {:?}", k, e);
                 }
             }
         });
@@ -138,7 +458,7 @@ impl Blockchain {
     pub fn chain_db_to_string(&self) -> String {
         let db = self.clone_chain_db();
         let mut db_map = LinkedHashMap::new();
-        let keys = db.get_all();
+        let keys = Blockchain::block_keys(&db);
 
         for key in keys.iter() {
             let value = db.get::<Block>(&key).unwrap();
@@ -153,13 +473,18 @@ impl Blockchain {
         self.chain_db_to_string().as_bytes().to_vec()
     }
 
+    /// Builds a chain db from an archive sent by a peer. Blocks are keyed by
+    /// their own hash whatever keys the peer's db used.
     pub fn chain_db_from_bytes(&self, data: &[u8]) -> PickleDb {
         let db_map = serde_json::from_slice::<LinkedHashMap<String, Block>>(data).unwrap();
 
         let mut db = PickleDb::new_bin(self.clone().chain_db, PickleDbDumpPolicy::DumpUponRequest);
+        if let Err(e) = db.set(CHAIN_DB_VERSION_KEY, &CHAIN_DB_VERSION) {
+            println!("Error setting chain db version: {:?}", e);
+        }
 
-        db_map.iter().for_each(|(k, v)| {
-            if let Err(e) = db.set(&k, &v) {
+        db_map.values().for_each(|v| {
+            if let Err(e) = Blockchain::store_block(&mut db, v) {
                 println!("Error setting block in database: {:?}", e);
             };
         });
@@ -169,9 +494,7 @@ impl Blockchain {
 
     pub fn dump(&self, block: &Block) -> Result<(), Box<dyn Error>> {
         let mut db = self.get_chain_db();
-        if let Err(e) = db.set(&block.header.last_hash, block) {
-            return Err(Box::new(e));
-        }
+        Blockchain::store_block(&mut db, block)?;
 
         if let Err(e) = db.dump() {
             return Err(Box::new(e));
@@ -180,9 +503,298 @@ impl Blockchain {
         Ok(())
     }
 
-    pub fn get_block(&self, last_hash: &str) -> Option<Block> {
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<Block> {
+        let db = self.get_chain_db();
+        db.get::<Block>(hash)
+    }
+
+    /// Every stored block at `block_height`, including competing blocks that
+    /// aren't on the current chain.
+    pub fn get_blocks_at(&self, block_height: u128) -> Vec<Block> {
         let db = self.get_chain_db();
-        db.get::<Block>(last_hash)
+        db.get::<Vec<String>>(&Blockchain::height_key(block_height))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|hash| db.get::<Block>(hash))
+            .collect()
+    }
+
+    /// The hashes of the blocks on the current chain in height order. Headers
+    /// only link to their parent, so each block's hash is the next header's
+    /// last hash and the tip's is taken from the child block.
+    fn canonical_hashes(&self) -> Vec<(u128, String)> {
+        self.iter_canonical_hashes().collect()
+    }
+
+    // The same hashes as `canonical_hashes`, read off the headers as they're
+    // iterated.
+    fn iter_canonical_hashes(&self) -> impl Iterator<Item = (u128, String)> + '_ {
+        self.chain
+            .iter()
+            .zip(self.chain.iter().skip(1))
+            .map(|(header, next)| (header.block_height, next.last_hash.clone()))
+            .chain(
+                self.tip()
+                    .map(|child| (child.header.block_height, child.hash.clone())),
+            )
+    }
+
+    /// The block at `block_height` on the current chain, None if it was pruned.
+    pub fn get_canonical_block_at(&self, block_height: u128) -> Option<Block> {
+        if self.is_pruned(block_height) {
+            return None;
+        }
+        let (_, hash) = self
+            .canonical_hashes()
+            .into_iter()
+            .find(|(height, _)| *height == block_height)?;
+        self.get_block_by_hash(&hash)
+    }
+
+    /// The blocks on the current chain from `from_height` to `to_height`
+    /// inclusive in height order, at most MAX_BLOCK_RANGE of them. Heights
+    /// above the tip or pruned from the archive are left out.
+    pub fn get_block_range(&self, from_height: u128, to_height: u128) -> Vec<Block> {
+        let to_height = std::cmp::min(to_height, from_height.saturating_add(MAX_BLOCK_RANGE - 1));
+        self.canonical_hashes()
+            .into_iter()
+            .filter(|(height, _)| *height >= from_height && *height <= to_height)
+            .filter(|(height, _)| !self.is_pruned(*height))
+            .filter_map(|(_, hash)| self.get_block_by_hash(&hash))
+            .collect()
+    }
+
+    /// The block on the current chain built on the block with hash `last_hash`.
+    pub fn get_canonical_child(&self, last_hash: &str) -> Option<Block> {
+        let header = self
+            .chain
+            .iter()
+            .find(|header| header.last_hash == last_hash)?;
+        self.get_canonical_block_at(header.block_height)
+    }
+
+    /// Removes the bodies of blocks below `keep_from_height` from the chain db,
+    /// including competing blocks at those heights, and returns how many were
+    /// removed. Headers stay in `chain`. Only finalized blocks are pruned, so a
+    /// fork never needs a block that is gone. State can then only be rebuilt
+    /// from a snapshot taken at or above the pruned height. The pruned height
+    /// is kept in the chain db, see Blockchain::open.
+    pub fn prune_archive(&mut self, keep_from_height: u128) -> Result<usize, Box<dyn Error>> {
+        let finalized = if let Some(height) = self.finalized_height() {
+            height
+        } else {
+            return Ok(0);
+        };
+        let prune_below = std::cmp::min(keep_from_height, finalized + 1);
+
+        let mut db = self.get_chain_db();
+        let height_keys: Vec<String> = db
+            .get_all()
+            .into_iter()
+            .filter(|key| {
+                key.strip_prefix(CHAIN_DB_HEIGHT_PREFIX)
+                    .and_then(|height| height.parse::<u128>().ok())
+                    .map_or(false, |height| height < prune_below)
+            })
+            .collect();
+
+        // The hashes at each height are kept, they link the headers left in
+        // `chain` to the pruned blocks.
+        let mut n_pruned = 0;
+        for key in height_keys.iter() {
+            for hash in db.get::<Vec<String>>(key).unwrap_or_default() {
+                if db.rem(&hash)? {
+                    n_pruned += 1;
+                }
+            }
+        }
+        if self
+            .pruned_below
+            .map_or(true, |pruned| pruned < prune_below)
+        {
+            self.pruned_below = Some(prune_below);
+        }
+        let pruned_headers: Vec<&BlockHeader> = self
+            .chain
+            .iter()
+            .filter(|header| self.is_pruned(header.block_height))
+            .collect();
+        db.set(PRUNED_HEADERS_KEY, &pruned_headers)?;
+        if let Some(genesis) = &self.genesis {
+            db.set(PRUNED_GENESIS_KEY, genesis)?;
+        }
+        db.set(PRUNED_BELOW_KEY, &self.pruned_below)?;
+        db.dump()?;
+
+        info!(
+            target: BLOCKCHAIN_TARGET,
+            "Pruned {} block bodies below height {}",
+            n_pruned, prune_below
+        );
+        Ok(n_pruned)
+    }
+
+    /// Writes the blocks from genesis up to `up_to_height` to a checkpoint file
+    /// that new nodes can bootstrap from, and returns the checkpoint's hash. The
+    /// ledger can't be rewound, so a snapshot is only included at the tip.
+    pub fn export_checkpoint(
+        &self,
+        path: &str,
+        up_to_height: u128,
+        network_state: &NetworkState,
+    ) -> Result<String, Box<dyn Error>> {
+        let tip = self
+            .tip_height()
+            .ok_or_else(|| CheckpointError("the chain has no genesis block".to_string()))?;
+        if up_to_height > tip {
+            return Err(Box::new(CheckpointError(format!(
+                "height {} is above the tip at {}",
+                up_to_height, tip
+            ))));
+        }
+        if let Some(pruned) = self.pruned_below.filter(|pruned| *pruned > 0) {
+            return Err(Box::new(CheckpointError(format!(
+                "the archive is pruned below height {}",
+                pruned
+            ))));
+        }
+
+        let mut blocks = vec![];
+        for (block_height, hash) in self
+            .canonical_hashes()
+            .into_iter()
+            .filter(|(block_height, _)| *block_height <= up_to_height)
+        {
+            match self.get_block_by_hash(&hash) {
+                Some(block) => blocks.push(block),
+                None => {
+                    return Err(Box::new(CheckpointError(format!(
+                        "block {} is missing from the chain db",
+                        block_height
+                    ))))
+                }
+            }
+        }
+
+        let snapshot = if up_to_height == tip {
+            Some(StateSnapshot::new(
+                network_state.db_to_ledger(),
+                network_state.get_reward_state(),
+                network_state.state_hash.clone(),
+            ))
+        } else {
+            None
+        };
+
+        let checkpoint = Checkpoint::new(NETWORK_ID, blocks, snapshot);
+        fs::write(path, checkpoint.to_string())?;
+
+        Ok(checkpoint.hash)
+    }
+
+    /// Initializes a fresh chain from a checkpoint file written by
+    /// `export_checkpoint` and returns the height of the new tip. Every block is
+    /// validated and applied to the ledger as if it had been received from a
+    /// peer, the checkpoint's snapshot, if it has one, has to match the ledger
+    /// they produce. A node whose bootstrap failed is left with the blocks that
+    /// were valid and refuses to start.
+    pub fn bootstrap_from_file(
+        &mut self,
+        path: &str,
+        network_state: &mut NetworkState,
+    ) -> Result<u128, Box<dyn Error>> {
+        if self.genesis.is_some() {
+            return Err(Box::new(CheckpointError(
+                "the chain already has a genesis block".to_string(),
+            )));
+        }
+
+        let checkpoint = Checkpoint::from_bytes(&fs::read(path)?)?;
+        checkpoint.verify(NETWORK_ID, &network_state.get_reward_state().params)?;
+
+        for block in checkpoint.blocks.iter() {
+            let reward_state = network_state.get_reward_state();
+            if let Err(e) = self.process_block(network_state, &reward_state, block) {
+                return Err(Box::new(CheckpointError(format!(
+                    "block {} is invalid: {:?}",
+                    block.header.block_height, e.details
+                ))));
+            }
+            network_state.dump(block);
+        }
+
+        if let Some(snapshot) = &checkpoint.snapshot {
+            if network_state.db_to_ledger().canonical_hash() != snapshot.ledger.canonical_hash() {
+                return Err(Box::new(CheckpointError(
+                    "the ledger snapshot doesn't match the checkpoint's blocks".to_string(),
+                )));
+            }
+        }
+
+        let tip = self
+            .tip_height()
+            .ok_or_else(|| CheckpointError("the checkpoint has no blocks".to_string()))?;
+        info!(target: BLOCKCHAIN_TARGET, "Bootstrapped chain from {} up to height {}", path, tip);
+
+        Ok(tip)
+    }
+
+    /// Appends an already verified block to the tip of the chain.
+    fn append_block(&mut self, block: &Block) {
+        if self.genesis.is_none() {
+            self.genesis = Some(block.clone());
+        }
+        self.parent = self.child.take();
+        self.child = Some(block.clone());
+        self.chain.push_back(block.header.clone());
+        self.index_block(block);
+        self.cache_block(block);
+        self.resolve_fork(block);
+        if let Err(e) = self.dump(block) {
+            error!(target: BLOCKCHAIN_TARGET, "Error dumping block to chain db: {:?}", e);
+        };
+    }
+
+    /// Whether a block with the hash of `block` was confirmed, or `block`
+    /// itself was rejected before. Every genesis block hashes the same, so
+    /// they're never known.
+    pub fn is_known(&self, block: &Block) -> bool {
+        if matches!(block.header.block_reward.category, Category::Genesis(_)) {
+            return false;
+        }
+
+        self.block_cache.contains_key(&block.hash)
+            || self.invalid.contains_key(&Blockchain::block_digest(block))
+            || self.block_heights.contains_key(&block.hash)
+    }
+
+    /// The digest of the whole of `block`, which invalid blocks are remembered
+    /// by. The hash a block carries is set by its sender, keyed on it an
+    /// invalid block would hide the valid one sent with its hash.
+    pub fn block_digest(block: &Block) -> String {
+        digest_bytes(&block.as_bytes())
+    }
+
+    fn cache_block(&mut self, block: &Block) {
+        self.block_cache.insert(block.hash.clone(), block.clone());
+        while self.block_cache.len() > BLOCK_CACHE_SIZE {
+            self.block_cache.pop_front();
+        }
+    }
+
+    /// Records `block` as invalid so the next copy of it is dropped without
+    /// being validated, forgetting the oldest past INVALID_CACHE_SIZE. Only a
+    /// block invalid in itself is recorded, one that failed against the ledger
+    /// could pass against the ledger of another tip or after a sync.
+    pub fn remember_invalid(&mut self, block: &Block) {
+        if !block.invalid_in_itself() {
+            return;
+        }
+        self.invalid
+            .insert(Blockchain::block_digest(block), block.clone());
+        while self.invalid.len() > INVALID_CACHE_SIZE {
+            self.invalid.pop_front();
+        }
     }
 
     pub fn process_block(
@@ -190,26 +802,95 @@ impl Blockchain {
         network_state: &NetworkState,
         reward_state: &RewardState,
         block: &Block,
+    ) -> Result<BlockOutcome, InvalidBlockError> {
+        if self.is_known(block) {
+            debug!(
+                target: BLOCKCHAIN_TARGET,
+                "known block skipped height={} hash={}",
+                block.header.block_height,
+                block.hash
+            );
+            return Ok(BlockOutcome::AlreadyKnown);
+        }
+
+        self.validations += 1;
+        let result = self.apply_block(network_state, reward_state, block);
+        match &result {
+            Ok(()) => info!(
+                target: BLOCKCHAIN_TARGET,
+                "block confirmed height={} hash={} last_hash={}",
+                block.header.block_height,
+                block.hash,
+                block.header.last_hash
+            ),
+            Err(e) => warn!(
+                target: BLOCKCHAIN_TARGET,
+                "invalid block height={} hash={} reason={:?}",
+                block.header.block_height,
+                block.hash,
+                e.details
+            ),
+        }
+
+        result.map(|()| BlockOutcome::Confirmed)
+    }
+
+    fn apply_block(
+        &mut self,
+        network_state: &NetworkState,
+        reward_state: &RewardState,
+        block: &Block,
     ) -> Result<(), InvalidBlockError> {
+        // Only a block signed by its claim can be held against the claim, an
+        // unsigned copy of another block is checked like any other block.
+        let self_conflict = if block.valid_block_signature() {
+            self.self_conflicting(block)
+        } else {
+            None
+        };
+        if let Some(first_hash) = self_conflict {
+            warn!(
+                target: BLOCKCHAIN_TARGET,
+                "claim signed competing blocks pubkey={} height={} first_hash={}",
+                block.header.claim.pubkey,
+                block.header.block_height,
+                first_hash
+            );
+            return Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::SelfConflict,
+            });
+        }
+
+        // The genesis reward is only ever paid once, whatever height a block
+        // paying it again claims to be at. Every genesis block hashes the same,
+        // so it isn't recorded as invalid by its hash.
+        if let Some(genesis_block) = &self.genesis {
+            if matches!(block.header.block_reward.category, Category::Genesis(_)) {
+                warn!(
+                    target: BLOCKCHAIN_TARGET,
+                    "second genesis rejected height={} hash={} genesis_hash={}",
+                    block.header.block_height,
+                    block.hash,
+                    genesis_block.hash
+                );
+                return Err(InvalidBlockError {
+                    details: InvalidBlockErrorReason::InvalidBlockReward,
+                });
+            }
+        }
+
         if let Some(genesis_block) = &self.genesis {
             if let Some(last_block) = &self.child {
                 if let Err(e) = block.valid_block(&last_block, network_state, reward_state) {
-                    self.future_blocks
-                        .insert(block.clone().header.last_hash, block.clone());
+                    if block.invalid_in_itself() {
+                        self.remember_invalid(block);
+                    } else if block.header.last_hash != last_block.hash {
+                        self.record_competing_tip(block);
+                        self.stash_future_blocks(block);
+                    }
                     return Err(e);
                 } else {
-                    self.parent = self.child.clone();
-                    self.child = Some(block.clone());
-                    self.chain.push_back(block.header.clone());
-                    if self.block_cache.len() == 100 {
-                        self.block_cache.pop_back();
-                        self.block_cache.insert(block.hash.clone(), block.clone());
-                    }
-
-                    if let Err(e) = self.dump(&block) {
-                        println!("Error dumping block to chain db: {:?}", e);
-                    };
-
+                    self.append_block(block);
                     return Ok(());
                 }
             } else {
@@ -218,8 +899,14 @@ impl Blockchain {
                 } else {
                     self.child = Some(block.clone());
                     self.chain.push_back(block.header.clone());
+                    self.index_block(&block);
+                    self.cache_block(block);
                     if let Err(e) = self.dump(&block) {
-                        println!("Error dumping block to chain db: {:?}", e);
+                        error!(
+                            target: BLOCKCHAIN_TARGET,
+                            "Error dumping block to chain db: {:?}",
+                            e
+                        );
                     };
                     Ok(())
                 }
@@ -227,25 +914,48 @@ impl Blockchain {
         } else {
             // check that this is a valid genesis block.
             if block.header.block_height == 0 {
+                if !self.is_network_genesis(block) {
+                    // Not remembered as invalid, every genesis block has the
+                    // same hash as the network's own.
+                    warn!(
+                        target: BLOCKCHAIN_TARGET,
+                        "genesis of another network rejected header_hash={} network_genesis={:?}",
+                        block.header.hash(),
+                        self.network_genesis
+                    );
+                    return Err(InvalidBlockError {
+                        details: InvalidBlockErrorReason::General,
+                    });
+                }
                 if block.valid_genesis(network_state, reward_state) {
                     self.genesis = Some(block.clone());
                     self.child = Some(block.clone());
-                    self.block_cache.insert(block.hash.clone(), block.clone());
                     self.chain.push_back(block.header.clone());
+                    self.index_block(&block);
+                    self.cache_block(block);
                     if let Err(e) = self.dump(&block) {
-                        println!("Error dumping block to chain db: {:?}", e);
+                        error!(
+                            target: BLOCKCHAIN_TARGET,
+                            "Error dumping block to chain db: {:?}",
+                            e
+                        );
                     };
                     Ok(())
                 } else {
-                    self.invalid.insert(block.hash.clone(), block.clone());
                     Err(InvalidBlockError {
                         details: InvalidBlockErrorReason::General,
                     })
                 }
             } else {
-                // request genesis block.
-                self.future_blocks
-                    .insert(block.clone().header.last_hash, block.clone());
+                // Kept until the genesis block is synced, the caller requests
+                // it from sync_start_height.
+                info!(
+                    target: BLOCKCHAIN_TARGET,
+                    "block before genesis stashed height={} hash={}",
+                    block.header.block_height,
+                    block.hash
+                );
+                self.stash_future_blocks(block);
                 Err(InvalidBlockError {
                     details: InvalidBlockErrorReason::BlockOutOfSequence,
                 })
@@ -253,273 +963,2803 @@ impl Blockchain {
         }
     }
 
-    pub fn stash_future_blocks(&mut self, block: &Block) {
-        self.future_blocks
-            .insert(block.clone().header.last_hash, block.clone());
+    /// The hash of the confirmed block at the height of `block` if the same
+    /// claim signed it and it isn't `block` itself. That's one wallet mining
+    /// on two machines, the block confirmed first is kept.
+    pub fn self_conflicting(&self, block: &Block) -> Option<String> {
+        self.conflicting_header(block).map(|(hash, _)| hash)
     }
 
-    pub fn send_invalid_block_message(
-        &self,
-        block: &Block,
-        reason: InvalidBlockErrorReason,
-        miner_id: String,
-        sender_id: String,
-        swarm_sender: tokio::sync::mpsc::UnboundedSender<Command>,
-    ) {
-        let message = MessageType::InvalidBlockMessage {
-            block_height: block.clone().header.block_height,
-            reason,
-            miner_id,
-            sender_id,
-        };
+    /// The evidence against the claim of a self conflicting block: its header
+    /// and the header of the confirmed block the claim signed at its height.
+    pub fn slashing_evidence(&self, block: &Block) -> Option<SlashingEvidence> {
+        self.conflicting_header(block).map(|(_, header)| {
+            SlashingEvidence::conflicting_headers(header.clone(), block.header.clone())
+        })
+    }
 
-        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes())) {
-            println!(
-                "Error sending InvalidBlockMessage InvalidBlockHeight to swarm sender: {:?}",
-                e
-            );
+    fn conflicting_header(&self, block: &Block) -> Option<(String, &BlockHeader)> {
+        let block_height = block.header.block_height;
+        // Each header's hash is the last hash of the header above it.
+        let mut hash = self.tip()?.hash.clone();
+        for header in self.chain.iter().rev() {
+            if header.block_height < block_height {
+                return None;
+            }
+            if header.block_height == block_height {
+                if hash != block.hash && header.claim.pubkey == block.header.claim.pubkey {
+                    return Some((hash, header));
+                }
+                return None;
+            }
+            hash = header.last_hash.clone();
         }
+
+        None
     }
 
-    pub fn send_missing_blocks_message(
-        &self,
-        block: &Block,
-        sender_id: String,
-        swarm_sender: tokio::sync::mpsc::UnboundedSender<Command>,
-    ) {
-        let missing_blocks: Vec<u128> =
-            (self.chain.len() as u128 - 1u128..block.clone().header.block_height).collect();
+    /// Checks that every header in `chain` links to the one before it, one
+    /// height above it and with a last hash that is the hash of the block the
+    /// header before it belongs to. Blocks still in the chain db are compared
+    /// header to header, pruned ones by the hashes stored at their height.
+    /// Returns the index of the first header that doesn't link.
+    pub fn verify_header_chain(&self) -> Result<(), usize> {
+        self.verify_header_chain_in(&self.get_chain_db())
+    }
 
-        let message = MessageType::NeedBlocksMessage {
-            blocks_needed: missing_blocks,
-            sender_id,
+    fn verify_header_chain_in(&self, db: &PickleDb) -> Result<(), usize> {
+        let mut headers = self.chain.iter().enumerate();
+        let mut prev = match headers.next() {
+            Some((_, header)) => header,
+            None => return Ok(()),
         };
-
-        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes())) {
-            println!("Error sending NeedBlocksMessage to swarm sender: {:?}", e);
+        for (index, header) in headers {
+            let linked = header.block_height == prev.block_height + 1
+                && match db.get::<Block>(&header.last_hash) {
+                    Some(block) => block.header.get_payload() == prev.get_payload(),
+                    None => db
+                        .get::<Vec<String>>(&Blockchain::height_key(prev.block_height))
+                        .map_or(false, |hashes| hashes.contains(&header.last_hash)),
+                };
+            if !linked {
+                return Err(index);
+            }
+            prev = header;
         }
-    }
-
-    pub fn send_state(
-        &self,
-        requested_from: String,
-        lowest_block: u128,
-        node_id: String,
-        db: PickleDb,
-        swarm_sender: tokio::sync::mpsc::UnboundedSender<Command>,
-    ) {
-        println!(
-            "Received a state update request, send blocks {} -> {} to: {:?}",
-            0,
-            &lowest_block - 1,
-            &requested_from
-        );
-        let current_blockchain = self.clone();
-        let thread_swarm_sender = swarm_sender.clone();
-        thread::spawn(move || {
-            let mut iter = current_blockchain.chain.iter();
-            let mut idx = 0;
-            while idx < lowest_block {
-                if let Some(header) = iter.next() {
-                    if let Some(block) = db.get::<Block>(&header.last_hash) {
-                        if let Some(chunks) = block.clone().chunk() {
-                            for (idx, chunk) in chunks.iter().enumerate() {
-                                let message = MessageType::BlockChunkMessage {
-                                    sender_id: node_id.clone().to_string(),
-                                    requestor: requested_from.clone(),
-                                    block_height: block.clone().header.block_height,
-                                    chunk_number: idx as u128 + 1u128,
-                                    total_chunks: chunks.len() as u128,
-                                    data: chunk.to_vec(),
-                                };
 
-                                if let Err(e) = thread_swarm_sender
-                                    .send(Command::SendMessage(message.as_bytes()))
-                                {
-                                    println!("Error sending block chunk message to swarm: {:?}", e);
-                                }
-                            }
-                        }
-                    }
-
-                    idx += 1;
-                }
-            }
-        });
+        Ok(())
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        self.to_string().as_bytes().to_vec()
+    /// Checks the chain db against the ledger without changing either. The
+    /// blocks on the current chain are read one at a time from genesis, each
+    /// checked to be stored under its own hash and to build on the block below
+    /// it, and replayed into a scratch ledger next to `network_state`'s. The
+    /// replayed ledger is then compared to `network_state`'s, unless the
+    /// archive has been pruned. The report names the first problem found.
+    pub fn audit(&self, network_state: &NetworkState) -> AuditReport {
+        self.audit_with(network_state, || false)
     }
 
-    pub fn from_bytes(data: &[u8]) -> Blockchain {
-        serde_json::from_slice::<Blockchain>(&data).unwrap()
+    /// `audit`, stopping before the next block once `cancelled` returns true.
+    pub fn audit_with<F: Fn() -> bool>(
+        &self,
+        network_state: &NetworkState,
+        cancelled: F,
+    ) -> AuditReport {
+        let replay_path = format!("{}.audit", network_state.path);
+        let remove_replay = || {
+            let _ = fs::remove_file(&replay_path);
+            let _ = fs::remove_file(format!("{}.wal", replay_path));
+        };
+        remove_replay();
+        let report = self.replay_audit(network_state, &replay_path, cancelled);
+        remove_replay();
+        if report.is_ok() {
+            info!(target: BLOCKCHAIN_TARGET, "{}", report);
+        } else {
+            warn!(target: BLOCKCHAIN_TARGET, "{}", report);
+        }
+
+        report
     }
 
-    pub fn to_string(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    fn replay_audit<F: Fn() -> bool>(
+        &self,
+        network_state: &NetworkState,
+        replay_path: &str,
+        cancelled: F,
+    ) -> AuditReport {
+        let mut blocks_checked = 0;
+        let report = |blocks_checked, finding| AuditReport {
+            blocks_checked,
+            finding,
+        };
+        // The chain db is loaded once, and each block decoded from it only
+        // when it's replayed, then dropped.
+        let db = self.get_chain_db();
+        if let Err(index) = self.verify_header_chain_in(&db) {
+            let height = self.chain.iter().nth(index).map_or(0, |h| h.block_height);
+            return report(blocks_checked, AuditFinding::BrokenLink(height));
+        }
+
+        let mut replayed = NetworkState::restore(replay_path);
+        // The height each ledger entry was last written at, a diverged entry
+        // went wrong at or after it.
+        let mut written_at: HashMap<String, u128> = HashMap::new();
+        let mut entries = Blockchain::ledger_entries(&replayed);
+        let mut prev: Option<(u128, String)> = None;
+        let mut tip_height = 0;
+        for (height, hash) in self.iter_canonical_hashes() {
+            if cancelled() {
+                return report(blocks_checked, AuditFinding::Cancelled(height));
+            }
+            // Pruned bodies are gone, their headers were checked above.
+            if self.pruned_below.map_or(false, |pruned| height < pruned) {
+                continue;
+            }
+            let block = match db.get::<Block>(&hash) {
+                Some(block) => block,
+                None => return report(blocks_checked, AuditFinding::MissingBlock(height)),
+            };
+            let linked = block.hash == hash
+                && block.header.block_height == height
+                && prev.as_ref().map_or(true, |(prev_height, prev_hash)| {
+                    block.header.last_hash == *prev_hash && height == prev_height + 1
+                });
+            if !linked {
+                return report(blocks_checked, AuditFinding::BrokenLink(height));
+            }
+
+            replayed.dump(&block);
+            let next_entries = Blockchain::ledger_entries(&replayed);
+            Blockchain::changed_entries(&entries, &next_entries)
+                .into_iter()
+                .for_each(|key| {
+                    written_at.insert(key, height);
+                });
+            entries = next_entries;
+            blocks_checked += 1;
+            tip_height = height;
+            prev = Some((height, hash));
+        }
+
+        // A pruned chain can't be replayed from genesis, so there is no ledger
+        // to compare with.
+        if self.pruned_below.map_or(false, |pruned| pruned > 0) {
+            return report(blocks_checked, AuditFinding::Ok);
+        }
+        let live = Blockchain::ledger_entries(network_state);
+        let mut diverged = Blockchain::changed_entries(&entries, &live);
+        // The state hash is only known while the node that applied the blocks
+        // is running, it isn't restored from the ledger db.
+        if network_state.state_hash.is_some() && network_state.state_hash != replayed.state_hash {
+            diverged.push("state_hash".to_string());
+            written_at.insert("state_hash".to_string(), tip_height);
+        }
+        if diverged.is_empty() {
+            return report(blocks_checked, AuditFinding::Ok);
+        }
+        let height = diverged
+            .iter()
+            .map(|key| written_at.get(key).copied().unwrap_or(0))
+            .min()
+            .unwrap_or(0);
+
+        report(
+            blocks_checked,
+            AuditFinding::LedgerDivergence {
+                height,
+                keys: diverged,
+            },
+        )
     }
 
-    pub fn from_string(data: &str) -> Blockchain {
-        serde_json::from_str(data).unwrap()
+    // The entries of a ledger as `credits:<address>`, `debits:<address>` and
+    // `claims:<pubkey>` keys, along with its reward state, so two ledgers can
+    // be compared entry by entry.
+    fn ledger_entries(network_state: &NetworkState) -> BTreeMap<String, String> {
+        let mut entries = BTreeMap::new();
+        for (address, amount) in network_state.get_credits() {
+            entries.insert(format!("credits:{}", address), amount.to_string());
+        }
+        for (address, amount) in network_state.get_debits() {
+            entries.insert(format!("debits:{}", address), amount.to_string());
+        }
+        for (pubkey, claim) in network_state.get_claims() {
+            entries.insert(format!("claims:{}", pubkey), format!("{:?}", claim));
+        }
+        entries.insert(
+            "reward_state".to_string(),
+            format!("{:?}", network_state.get_reward_state()),
+        );
+
+        entries
     }
 
-    pub fn get_field_names(&self) -> Vec<String> {
-        return vec![
-            "genesis".to_string(),
-            "child".to_string(),
-            "parent".to_string(),
-            "chain".to_string(),
-            "chain_db".to_string(),
-            "block_cache".to_string(),
-            "future_blocks".to_string(),
-            "invalid".to_string(),
-            "updating_state".to_string(),
-            "state_update_cache".to_string(),
-        ];
+    // The keys that are in one of `before` and `after` and not the other, or
+    // that are in both with different values, in order.
+    fn changed_entries(
+        before: &BTreeMap<String, String>,
+        after: &BTreeMap<String, String>,
+    ) -> Vec<String> {
+        let mut changed: Vec<String> = after
+            .iter()
+            .filter(|(key, value)| before.get(*key) != Some(*value))
+            .chain(before.iter().filter(|(key, _)| !after.contains_key(*key)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        changed.sort();
+
+        changed
     }
-}
 
-impl InvalidBlockErrorReason {
-    pub fn to_str(&self) -> &str {
-        match self {
-            Self::BlockOutOfSequence => "block out of sequence",
-            Self::General => "general invalid block",
-            Self::InvalidBlockHeight => "invalid block height",
-            Self::InvalidClaim => "invalid claim",
-            Self::InvalidLastHash => "invalid last hash",
-            Self::InvalidStateHash => "invalid state hash",
-            Self::InvalidBlockNonce => "invalid block nonce",
-            Self::InvalidBlockReward => "invalid block reward",
-            Self::InvalidTxns => "invalid txns in block",
-            Self::InvalidClaimPointers => "invalid claim pointers",
+    /// Records the height of a newly appended block and the txns it contains so
+    /// that confirmations can be looked up without walking the chain db.
+    fn index_block(&mut self, block: &Block) {
+        let txn_ids: Vec<String> = block.txns.keys().cloned().collect();
+        txn_ids.iter().for_each(|txn_id| {
+            self.txn_blocks.insert(txn_id.clone(), block.hash.clone());
+        });
+        self.block_txns.insert(block.hash.clone(), txn_ids);
+        self.index_height(&block.hash, block.header.block_height);
+    }
+
+    /// Indexes a block's height, dropping the oldest indexed block and its
+    /// txns past MAX_INDEXED_BLOCKS.
+    fn index_height(&mut self, block_hash: &str, block_height: u128) {
+        self.block_heights
+            .insert(block_hash.to_string(), block_height);
+        while self.block_heights.len() > MAX_INDEXED_BLOCKS {
+            if let Some((hash, _)) = self.block_heights.pop_front() {
+                self.block_txns
+                    .remove(&hash)
+                    .unwrap_or_default()
+                    .iter()
+                    .for_each(|txn_id| {
+                        self.txn_blocks.remove(txn_id);
+                    });
+            }
         }
     }
-}
 
-impl fmt::Display for Blockchain {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Blockchain")
+    /// The height of the block with `block_hash`, from the index for recent
+    /// blocks and from the chain db for older ones.
+    pub fn block_height_of(&self, block_hash: &str) -> Option<u128> {
+        if let Some(height) = self.block_heights.get(block_hash) {
+            return Some(*height);
+        }
+
+        self.get_block_by_hash(block_hash)
+            .map(|block| block.header.block_height)
     }
-}
 
-impl Error for Blockchain {}
+    /// The most recently confirmed block, None until the genesis block is
+    /// confirmed or after the chain is loaded from components without it.
+    pub fn tip(&self) -> Option<&Block> {
+        self.child.as_ref()
+    }
 
-impl fmt::Display for InvalidBlockError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
+    /// The height of the most recently confirmed block.
+    pub fn tip_height(&self) -> Option<u128> {
+        if let Some(child) = self.tip() {
+            Some(child.header.block_height)
+        } else {
+            self.block_heights.back().map(|(_, height)| *height)
+        }
     }
-}
 
-impl Error for InvalidBlockError {
-    fn description(&self) -> &str {
-        &self.details.to_str()
+    /// Returns the number of blocks built on top of the block with the given hash,
+    /// i.e. how far behind the tip it is. The tip itself has 0 confirmations.
+    pub fn confirmations(&self, block_hash: &str) -> Option<u128> {
+        let height = self.block_height_of(block_hash)?;
+        let tip = self.tip_height()?;
+        Some(tip.saturating_sub(height))
     }
-}
 
-impl Error for InvalidBlockErrorReason {
-    fn description(&self) -> &str {
-        &self.to_str()
+    /// Returns the hash of the block containing the txn and that block's
+    /// confirmations, None for txns older than the indexed blocks.
+    pub fn find_txn(&self, txn_id: &str) -> Option<(String, u128)> {
+        let block_hash = self.txn_blocks.get(txn_id)?;
+        let confirmations = self.confirmations(block_hash)?;
+        Some((block_hash.clone(), confirmations))
     }
-}
 
-impl Chunkable for Blockchain {
-    fn chunk(&self) -> Option<Vec<Vec<u8>>> {
-        let bytes_len = self.as_bytes().len();
-        if bytes_len > MAX_TRANSMIT_SIZE {
-            let mut n_chunks = bytes_len / MAX_TRANSMIT_SIZE;
-            if bytes_len % MAX_TRANSMIT_SIZE != 0 {
-                n_chunks += 1;
-            }
-            let mut chunks_vec = vec![];
-            let mut last_slice_end = 0;
-            (1..=bytes_len)
-                .map(|n| n * MAX_TRANSMIT_SIZE)
-                .enumerate()
-                .for_each(|(index, slice_end)| {
-                    if index + 1 == n_chunks {
-                        chunks_vec.push(self.clone().as_bytes()[last_slice_end..].to_vec());
-                    } else {
-                        chunks_vec
-                            .push(self.clone().as_bytes()[last_slice_end..slice_end].to_vec());
-                        last_slice_end = slice_end;
-                    }
-                });
-            Some(chunks_vec)
+    pub fn is_final(&self, block_hash: &str) -> bool {
+        if let Some(confirmations) = self.confirmations(block_hash) {
+            confirmations >= FINALITY_DEPTH
         } else {
-            Some(vec![self.clone().as_bytes()])
+            false
         }
     }
-}
 
-impl fmt::Display for InvalidBlockErrorReason {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::InvalidBlockHeight => {
-                write!(f, "invalid block height")
-            }
-            Self::InvalidClaim => {
-                write!(f, "invalid claim")
-            }
-            Self::InvalidLastHash => {
-                write!(f, "invalid last hash")
-            }
-            Self::InvalidStateHash => {
-                write!(f, "invalid state hash")
-            }
-            Self::BlockOutOfSequence => {
-                write!(f, "block out of sequence")
-            }
-            Self::InvalidBlockNonce => {
-                write!(f, "invalid block nonce")
-            }
-            Self::InvalidBlockReward => {
-                write!(f, "invalid block reward")
-            }
-            Self::InvalidTxns => {
-                write!(f, "invalid txns in block")
+    /// The height of the highest block that is buried at least FINALITY_DEPTH deep.
+    pub fn finalized_height(&self) -> Option<u128> {
+        self.tip_height()?.checked_sub(FINALITY_DEPTH)
+    }
+
+    /// Whether a fork may replace every block above `block_height`. Any fork
+    /// handling must check this before unwinding the chain.
+    pub fn can_unwind_to(&self, block_height: u128) -> bool {
+        if let Some(finalized) = self.finalized_height() {
+            block_height >= finalized
+        } else {
+            true
+        }
+    }
+
+    /// The height of the highest header this chain shares with `other`, None
+    /// if they don't even share a genesis block.
+    pub fn fork_height(&self, other: &Blockchain) -> Option<u128> {
+        let theirs: HashMap<u128, String> = other
+            .chain
+            .iter()
+            .map(|header| (header.block_height, header.hash()))
+            .collect();
+        self.chain
+            .iter()
+            .rev()
+            .find(|header| theirs.get(&header.block_height) == Some(&header.hash()))
+            .map(|header| header.block_height)
+    }
+
+    /// Whether the chain may be replaced with `other`, a chain synced from a
+    /// peer, without unwinding a final block.
+    pub fn can_switch_to(&self, other: &Blockchain) -> bool {
+        if self.chain.is_empty() {
+            return true;
+        }
+        match self.fork_height(other) {
+            Some(block_height) => self.can_unwind_to(block_height),
+            None => self.finalized_height().is_none(),
+        }
+    }
+
+    /// Returns the hash and height of blocks that have crossed the finality depth since the
+    /// last call, in height order. Each block is returned exactly once.
+    pub fn newly_finalized(&mut self) -> Vec<(String, u128)> {
+        let finalized = if let Some(height) = self.finalized_height() {
+            height
+        } else {
+            return vec![];
+        };
+
+        if let Some(last) = self.last_finalized {
+            if last >= finalized {
+                return vec![];
             }
-            Self::InvalidClaimPointers => {
-                write!(f, "invalid claim pointers")
+        }
+
+        let last_finalized = self.last_finalized;
+        let blocks = self
+            .block_heights
+            .iter()
+            .filter(|(_, height)| {
+                **height <= finalized && last_finalized.map_or(true, |last| **height > last)
+            })
+            .map(|(hash, height)| (hash.clone(), *height))
+            .collect();
+
+        self.last_finalized = Some(finalized);
+        blocks
+    }
+
+    /// The ids of the txns in the given blocks, as returned by newly_finalized,
+    /// looked up in the index of each block's txns. A txn indexed under a later
+    /// block since isn't reported with this one.
+    pub fn finalized_txns(&self, blocks: &[(String, u128)]) -> Vec<String> {
+        blocks
+            .iter()
+            .filter_map(|(block_hash, _)| {
+                self.block_txns
+                    .get(block_hash)
+                    .map(|txn_ids| (block_hash, txn_ids))
+            })
+            .flat_map(|(block_hash, txn_ids)| {
+                txn_ids
+                    .iter()
+                    .filter(move |txn_id| self.txn_blocks.get(*txn_id) == Some(block_hash))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Records `block` as a competing tip if a block on the chain is at its
+    /// height and built on the same parent, and `block` is valid on top of
+    /// that parent. Only observed, the chain keeps the block it has.
+    fn record_competing_tip(&mut self, block: &Block) {
+        let block_height = block.header.block_height;
+        let rival = self
+            .child
+            .iter()
+            .chain(self.parent.iter())
+            .find(|known| {
+                known.header.block_height == block_height
+                    && known.header.last_hash == block.header.last_hash
+                    && known.hash != block.hash
+            })
+            .cloned();
+        let rival = if let Some(rival) = rival {
+            rival
+        } else {
+            return;
+        };
+        let valid = self
+            .get_block_by_hash(&block.header.last_hash)
+            .map_or(false, |parent| block.valid_competitor(&parent));
+        if !valid {
+            warn!(
+                target: BLOCKCHAIN_TARGET,
+                "invalid competing tip ignored height={} hash={}", block_height, block.hash
+            );
+            return;
+        }
+
+        let tips = self
+            .competing_tips
+            .entry(block_height)
+            .or_insert_with(LinkedHashMap::new);
+        [&rival, block].iter().for_each(|tip| {
+            if !tips.contains_key(&tip.hash) {
+                tips.insert(tip.hash.clone(), tip.header.clone());
             }
-            Self::General => {
-                write!(f, "general invalid block error")
+        });
+        info!(
+            target: BLOCKCHAIN_TARGET,
+            "competing tip seen height={} hash={} tip_hash={}",
+            block_height,
+            block.hash,
+            rival.hash
+        );
+
+        while self.competing_tips.len() > MAX_COMPETING_TIPS {
+            if let Some((block_height, _)) = self.competing_tips.pop_front() {
+                self.fork_resolutions.remove(&block_height);
             }
         }
+
+        // The rival may have been built on already.
+        if let Some(child) = self.child.clone() {
+            self.resolve_fork(&child);
+        }
     }
-}
 
-impl GettableFields for Blockchain {
-    fn get_field(&self, field: &str) -> Option<String> {
-        match field {
-            "genesis" => {
-                if let Some(genesis) = self.genesis.clone() {
-                    return Some(genesis.to_string());
-                }
-                return None;
-            }
-            "child" => {
-                if let Some(child) = self.child.clone() {
-                    return Some(child.to_string());
+    /// Notes the tip `block` is built on as the winner of a competing height.
+    fn resolve_fork(&mut self, block: &Block) {
+        let block_height = match block.header.block_height.checked_sub(1) {
+            Some(block_height) => block_height,
+            None => return,
+        };
+        if self.fork_resolutions.contains_key(&block_height) {
+            return;
+        }
+
+        let extends_a_tip = self
+            .competing_tips
+            .get(&block_height)
+            .map_or(false, |tips| tips.contains_key(&block.header.last_hash));
+        if extends_a_tip {
+            self.fork_resolutions.insert(
+                block_height,
+                (block.header.last_hash.clone(), block.header.timestamp),
+            );
+        }
+    }
+
+    /// The heights competing tips were seen at, oldest first, with the tip
+    /// that was built on once one was.
+    pub fn fork_events(&self) -> Vec<ForkEvent> {
+        self.competing_tips
+            .iter()
+            .map(|(block_height, tips)| {
+                let resolution = self.fork_resolutions.get(block_height);
+                let winner = resolution.map(|(hash, _)| hash.clone());
+                let losers = match &winner {
+                    Some(winner) => tips
+                        .keys()
+                        .filter(|hash| *hash != winner)
+                        .cloned()
+                        .collect(),
+                    None => vec![],
+                };
+                let first_seen = tips.values().map(|header| header.timestamp).min();
+                ForkEvent {
+                    block_height: *block_height,
+                    tips: tips.keys().cloned().collect(),
+                    winner,
+                    losers,
+                    resolution_delay: resolution.map(|(_, extended_at)| {
+                        extended_at.saturating_sub(first_seen.unwrap_or(0))
+                    }),
                 }
-                return None;
+            })
+            .collect()
+    }
+
+    /// Stashes a block that can't be applied yet. When the stash grows past
+    /// `max_future_blocks` the lowest stashed blocks are dropped.
+    pub fn stash_future_blocks(&mut self, block: &Block) {
+        self.future_blocks
+            .insert(block.clone().header.last_hash, block.clone());
+
+        while self.future_blocks.len() > self.max_future_blocks {
+            let lowest = self
+                .future_blocks
+                .iter()
+                .min_by_key(|(_, block)| block.header.block_height)
+                .map(|(key, block)| (key.clone(), block.header.block_height));
+            if let Some((key, block_height)) = lowest {
+                info!(
+                    target: BLOCKCHAIN_TARGET,
+                    "Future block cap of {} reached, dropping stashed block at height {}",
+                    self.max_future_blocks, block_height
+                );
+                self.future_blocks.remove(&key);
+            } else {
+                break;
             }
-            "parent" => {
-                if let Some(parent) = self.parent.clone() {
-                    return Some(parent.to_string());
+        }
+    }
+
+    /// The height a state sync has to start from to apply the stashed future
+    /// blocks: 0 while the chain has no genesis block, otherwise the lowest
+    /// stashed block. None if nothing is stashed.
+    pub fn sync_start_height(&self) -> Option<u128> {
+        let lowest = self
+            .future_blocks
+            .values()
+            .map(|block| block.header.block_height)
+            .min()?;
+        if self.genesis.is_none() {
+            return Some(0);
+        }
+
+        Some(lowest)
+    }
+
+    /// Compares the height of a block with the local tip. Only a block signed by
+    /// its claim is compared, and at most MAX_SYNC_RANGE missing heights are
+    /// listed for a block ahead of the tip.
+    pub fn check_block_height(&self, block: &Block) -> BlockHeightCheck {
+        if !block.valid_block_signature() {
+            return BlockHeightCheck::Unsigned;
+        }
+        let tip = if let Some(tip) = self.tip_height() {
+            tip
+        } else {
+            return BlockHeightCheck::Next;
+        };
+
+        let on_tip = self
+            .tip()
+            .map_or(false, |tip| block.header.last_hash == tip.hash);
+        if on_tip && block.header.block_height != tip + 1 {
+            BlockHeightCheck::Skipped
+        } else if block.header.block_height > tip + 1 {
+            let to_height = std::cmp::min(block.header.block_height, tip + 1 + MAX_SYNC_RANGE);
+            BlockHeightCheck::Future((tip + 1..to_height).collect())
+        } else if block.header.block_height <= tip {
+            BlockHeightCheck::Stale(tip)
+        } else {
+            BlockHeightCheck::Next
+        }
+    }
+
+    /// Handles a block rejected with InvalidBlockHeight and returns the messages to
+    /// send. A block ahead of the tip is stashed and the missing range is requested,
+    /// along with the block after the tip by hash from the sender. A block at or
+    /// below the tip means the sender is behind, so they are told the tip height to
+    /// sync up to. A block built on the tip at any height but the next is only
+    /// reported back, there is nothing to request for it, and an unsigned block is
+    /// dropped.
+    pub fn handle_invalid_height(
+        &mut self,
+        block: &Block,
+        sender_id: String,
+        node_id: String,
+    ) -> Vec<MessageType> {
+        let check = self.check_block_height(block);
+        // Only a stale block's miner is behind, a skipped one is on the tip.
+        let tip_height = match check {
+            BlockHeightCheck::Stale(tip) => Some(tip),
+            _ => None,
+        };
+        match check {
+            BlockHeightCheck::Future(blocks_needed) => {
+                self.stash_future_blocks(block);
+                let mut messages = vec![MessageType::NeedBlocksMessage {
+                    blocks_needed,
+                    sender_id: node_id.clone(),
+                }];
+                if let Some(tip) = self.tip() {
+                    messages.push(MessageType::NeedBlockMessage {
+                        block_last_hash: tip.hash.clone(),
+                        sender_id: node_id,
+                        requested_from: sender_id,
+                    });
                 }
-                return None;
+                messages
             }
-            "chain" => return Some(serde_json::to_string(&self.chain).unwrap()),
-            "chain_db" => Some(self.chain_db.clone()),
-            "block_cache" => return Some(serde_json::to_string(&self.block_cache).unwrap()),
-            "future_blocks" => return Some(serde_json::to_string(&self.future_blocks).unwrap()),
-            "invalid" => return Some(serde_json::to_string(&self.invalid).unwrap()),
-            "updating_state" => return Some(format!("{}", self.updating_state)),
-            "state_update_cache" => {
-                return Some(serde_json::to_string(&self.state_update_cache).unwrap())
+            BlockHeightCheck::Stale(_) | BlockHeightCheck::Skipped => {
+                // process_block stashes every rejected block, a stale one will never apply.
+                self.future_blocks.remove(&block.header.last_hash);
+                vec![MessageType::InvalidBlockMessage {
+                    block_height: block.header.block_height,
+                    reason: InvalidBlockErrorReason::InvalidBlockHeight,
+                    miner_id: sender_id,
+                    sender_id: node_id,
+                    tip_height,
+                }]
             }
-            _ => None,
+            BlockHeightCheck::Unsigned | BlockHeightCheck::Next => vec![],
         }
     }
+
+    /// The blocks to request from a peer that rejected a block of this node's as
+    /// stale, its chain reaching `tip_height`. The peer sends at most
+    /// MAX_BLOCK_RANGE of them. None if this node's chain reaches that height
+    /// too, or it's syncing already.
+    pub fn catch_up_request(
+        &self,
+        tip_height: u128,
+        peer_id: String,
+        node_id: String,
+    ) -> Option<MessageType> {
+        let from_height = self.tip_height().map_or(0, |tip| tip + 1);
+        if self.updating_state || from_height > tip_height {
+            return None;
+        }
+
+        Some(MessageType::GetBlockRangeMessage {
+            from_height,
+            to_height: std::cmp::min(tip_height, from_height + MAX_BLOCK_RANGE - 1),
+            requestor: node_id,
+            requested_from: peer_id,
+        })
+    }
+
+    /// The blocks to request from a peer whose state was too large to send,
+    /// from the tip up to the lowest stashed future block. The peer sends at
+    /// most MAX_BLOCK_RANGE of them, the rest are requested a block at a time
+    /// as the gap above the tip closes. None if no blocks are missing below
+    /// the stashed ones.
+    pub fn range_sync_request(&self, peer_id: String, node_id: String) -> Option<MessageType> {
+        let lowest = self
+            .future_blocks
+            .values()
+            .map(|block| block.header.block_height)
+            .min()?;
+        let from_height = self.tip_height().map_or(0, |tip| tip + 1);
+        if from_height >= lowest {
+            return None;
+        }
+
+        Some(MessageType::GetBlockRangeMessage {
+            from_height,
+            to_height: std::cmp::min(lowest - 1, from_height + MAX_BLOCK_RANGE - 1),
+            requestor: node_id,
+            requested_from: peer_id,
+        })
+    }
+
+    /// Whether stashed future blocks are waiting on a block after the tip that
+    /// hasn't arrived yet.
+    pub fn has_gap_above_tip(&self) -> bool {
+        if let Some(tip) = self.tip() {
+            !self.future_blocks.contains_key(&tip.hash)
+                && self
+                    .future_blocks
+                    .values()
+                    .any(|block| block.header.block_height > tip.header.block_height + 1)
+        } else {
+            false
+        }
+    }
+
+    /// Whether `block` is the genesis block of the network this node is
+    /// configured for, any genesis block if none is configured.
+    pub fn is_network_genesis(&self, block: &Block) -> bool {
+        match &self.network_genesis {
+            Some(header_hash) => block.header.hash() == *header_hash,
+            None => true,
+        }
+    }
+
+    /// Splits `--genesis-hash <header hash>` out of the arguments.
+    pub fn network_genesis_from_args(mut args: Vec<String>) -> (Option<String>, Vec<String>) {
+        let network_genesis = take_flag(&mut args, NETWORK_GENESIS_FLAG);
+        (network_genesis, args)
+    }
+
+    /// Splits `--max-state-response <bytes>` out of the arguments.
+    pub fn max_state_response_from_args(mut args: Vec<String>) -> (usize, Vec<String>) {
+        let max_state_response = take_flag(&mut args, MAX_STATE_RESPONSE_FLAG)
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(MAX_STATE_RESPONSE_SIZE);
+
+        (max_state_response, args)
+    }
+
+    /// The components to send a peer that requested `component`, None for the
+    /// components this node doesn't send. A whole ledger or archive is only
+    /// sent by a full node that isn't syncing itself, and only if its db fits
+    /// in `max_size` bytes, otherwise the reason is sent back so the peer can
+    /// ask another node, or sync by block ranges, instead of waiting out its
+    /// sync timeout.
+    pub fn requested_components(
+        &self,
+        node_type: &NodeAuth,
+        network_state: &NetworkState,
+        component: &StateComponent,
+        max_size: usize,
+    ) -> Result<Option<Components>, DeclineReason> {
+        if !matches!(component, StateComponent::All | StateComponent::Archive) {
+            return Ok(None);
+        }
+        if !matches!(node_type, NodeAuth::Full) {
+            return Err(DeclineReason::NotFullNode);
+        }
+        if self.updating_state {
+            return Err(DeclineReason::UpdatingState);
+        }
+        if let StateComponent::Archive = component {
+            if self.pruned_below.map_or(false, |pruned| pruned > 0) {
+                return Err(DeclineReason::Pruned);
+            }
+        }
+        if self.estimated_response_size(network_state, component) > max_size as u64 {
+            return Err(DeclineReason::TooLarge);
+        }
+
+        let components = if let StateComponent::Archive = component {
+            Components {
+                genesis: None,
+                child: None,
+                parent: None,
+                blockchain: None,
+                ledger: None,
+                network_state: None,
+                archive: Some(self.chain_db_to_bytes()),
+            }
+        } else {
+            Components {
+                genesis: self.genesis.as_ref().map(Block::as_bytes),
+                child: self.tip().map(Block::as_bytes),
+                parent: self.parent.as_ref().map(Block::as_bytes),
+                blockchain: None,
+                ledger: Some(network_state.db_to_ledger().as_bytes()),
+                network_state: Some(network_state.as_bytes()),
+                archive: None,
+            }
+        };
+
+        Ok(Some(components))
+    }
+
+    // The size of the db a requested ledger or archive is read from, which
+    // the serialized ledger or archive is about the size of, so a state too
+    // large to send isn't serialized only to be declined.
+    fn estimated_response_size(
+        &self,
+        network_state: &NetworkState,
+        component: &StateComponent,
+    ) -> u64 {
+        let path = if let StateComponent::Archive = component {
+            &self.chain_db
+        } else {
+            &network_state.path
+        };
+
+        fs::metadata(path).map_or(0, |metadata| metadata.len())
+    }
+
+    pub fn send_invalid_block_message(
+        &self,
+        block: &Block,
+        reason: InvalidBlockErrorReason,
+        miner_id: String,
+        sender_id: String,
+        swarm_sender: tokio::sync::mpsc::UnboundedSender<Command>,
+    ) {
+        let message = MessageType::InvalidBlockMessage {
+            block_height: block.clone().header.block_height,
+            reason,
+            miner_id,
+            sender_id,
+            tip_height: None,
+        };
+
+        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes())) {
+            println!(
+                "Error sending InvalidBlockMessage InvalidBlockHeight to swarm sender: {:?}",
+                e
+            );
+        }
+    }
+
+    pub fn send_missing_blocks_message(
+        &self,
+        block: &Block,
+        sender_id: String,
+        swarm_sender: tokio::sync::mpsc::UnboundedSender<Command>,
+    ) {
+        let missing_blocks: Vec<u128> =
+            (self.chain.len() as u128 - 1u128..block.clone().header.block_height).collect();
+
+        let message = MessageType::NeedBlocksMessage {
+            blocks_needed: missing_blocks,
+            sender_id,
+        };
+
+        if let Err(e) = swarm_sender.send(Command::SendMessage(message.as_bytes())) {
+            println!("Error sending NeedBlocksMessage to swarm sender: {:?}", e);
+        }
+    }
+
+    pub fn send_state(
+        &self,
+        requested_from: String,
+        lowest_block: u128,
+        node_id: String,
+        db: PickleDb,
+        swarm_sender: tokio::sync::mpsc::UnboundedSender<Command>,
+    ) {
+        println!(
+            "Received a state update request, send blocks {} -> {} to: {:?}",
+            0,
+            &lowest_block - 1,
+            &requested_from
+        );
+        let current_blockchain = self.clone();
+        let thread_swarm_sender = swarm_sender.clone();
+        thread::spawn(move || {
+            let mut iter = current_blockchain.chain.iter();
+            let mut idx = 0;
+            while idx < lowest_block {
+                if let Some(header) = iter.next() {
+                    // The header links to its parent, whose body may be pruned,
+                    // the peer has to sync those elsewhere.
+                    if current_blockchain.is_pruned(header.block_height.saturating_sub(1)) {
+                        idx += 1;
+                        continue;
+                    }
+                    if let Some(block) = db.get::<Block>(&header.last_hash) {
+                        if let Some(chunks) = block.clone().chunk() {
+                            for (idx, chunk) in chunks.iter().enumerate() {
+                                let message = MessageType::BlockChunkMessage {
+                                    sender_id: node_id.clone().to_string(),
+                                    requestor: requested_from.clone(),
+                                    block_height: block.clone().header.block_height,
+                                    chunk_number: idx as u128 + 1u128,
+                                    total_chunks: chunks.len() as u128,
+                                    data: chunk.to_vec(),
+                                };
+
+                                if let Err(e) = thread_swarm_sender
+                                    .send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!("Error sending block chunk message to swarm: {:?}", e);
+                                }
+                            }
+                        }
+                    }
+
+                    idx += 1;
+                }
+            }
+        });
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_string().as_bytes().to_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Blockchain {
+        serde_json::from_slice::<Blockchain>(&data).unwrap()
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_string(data: &str) -> Blockchain {
+        serde_json::from_str(data).unwrap()
+    }
+
+    pub fn get_field_names(&self) -> Vec<String> {
+        return vec![
+            "genesis".to_string(),
+            "child".to_string(),
+            "parent".to_string(),
+            "chain".to_string(),
+            "chain_db".to_string(),
+            "block_cache".to_string(),
+            "future_blocks".to_string(),
+            "invalid".to_string(),
+            "updating_state".to_string(),
+            "state_update_cache".to_string(),
+            "block_heights".to_string(),
+            "txn_blocks".to_string(),
+            "last_finalized".to_string(),
+            "max_future_blocks".to_string(),
+            "pruned_below".to_string(),
+            "validations".to_string(),
+            "network_genesis".to_string(),
+            "competing_tips".to_string(),
+            "fork_resolutions".to_string(),
+            "block_txns".to_string(),
+        ];
+    }
+}
+
+impl InvalidBlockErrorReason {
+    pub fn to_str(&self) -> &str {
+        match self {
+            Self::BlockOutOfSequence => "block out of sequence",
+            Self::General => "general invalid block",
+            Self::InvalidBlockHeight => "invalid block height",
+            Self::InvalidClaim => "invalid claim",
+            Self::InvalidLastHash => "invalid last hash",
+            Self::InvalidStateHash => "invalid state hash",
+            Self::InvalidBlockNonce => "invalid block nonce",
+            Self::InvalidBlockReward => "invalid block reward",
+            Self::InvalidTxns => "invalid txns in block",
+            Self::InvalidClaimPointers => "invalid claim pointers",
+            Self::BlockCostExceeded => "block cost exceeded",
+            Self::InvalidNonceEpoch => "block mined on another nonce epoch",
+            Self::InvalidClaimMapHash => "invalid claim map hash",
+            Self::InvalidLedgerRoot => "invalid ledger root",
+            Self::SelfConflict => "claim already signed a block at this height",
+        }
+    }
+}
+
+/// A block's height for display, with how far behind the tip it's been
+/// confirmed when that's known.
+pub fn format_block_height(block_height: u128, confirmations: Option<u128>) -> String {
+    if let Some(confirmations) = confirmations {
+        format!(
+            "height {} ({} confirmations behind tip)",
+            block_height, confirmations
+        )
+    } else {
+        format!("height {}", block_height)
+    }
+}
+
+impl fmt::Display for Blockchain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Blockchain")
+    }
+}
+
+impl Error for Blockchain {}
+
+impl fmt::Display for ForkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "height {}: tips {}",
+            self.block_height,
+            self.tips.join(", ")
+        )?;
+        match (&self.winner, self.resolution_delay) {
+            (Some(winner), Some(delay)) => write!(
+                f,
+                ", winner {} after {}ms, losers {}",
+                winner,
+                delay / 1_000_000,
+                self.losers.join(", ")
+            ),
+            _ => write!(f, ", unresolved"),
+        }
+    }
+}
+
+impl fmt::Display for InvalidBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for InvalidBlockError {
+    fn description(&self) -> &str {
+        &self.details.to_str()
+    }
+}
+
+impl Error for InvalidBlockErrorReason {
+    fn description(&self) -> &str {
+        &self.to_str()
+    }
+}
+
+impl Chunkable for Blockchain {
+    fn chunk(&self) -> Option<Vec<Vec<u8>>> {
+        let bytes_len = self.as_bytes().len();
+        if bytes_len > MAX_TRANSMIT_SIZE {
+            let mut n_chunks = bytes_len / MAX_TRANSMIT_SIZE;
+            if bytes_len % MAX_TRANSMIT_SIZE != 0 {
+                n_chunks += 1;
+            }
+            let mut chunks_vec = vec![];
+            let mut last_slice_end = 0;
+            (1..=bytes_len)
+                .map(|n| n * MAX_TRANSMIT_SIZE)
+                .enumerate()
+                .for_each(|(index, slice_end)| {
+                    if index + 1 == n_chunks {
+                        chunks_vec.push(self.clone().as_bytes()[last_slice_end..].to_vec());
+                    } else {
+                        chunks_vec
+                            .push(self.clone().as_bytes()[last_slice_end..slice_end].to_vec());
+                        last_slice_end = slice_end;
+                    }
+                });
+            Some(chunks_vec)
+        } else {
+            Some(vec![self.clone().as_bytes()])
+        }
+    }
+}
+
+impl fmt::Display for DeclineReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFullNode => write!(f, "not a full node"),
+            Self::UpdatingState => write!(f, "updating state"),
+            Self::Pruned => write!(f, "archive pruned"),
+            Self::TooLarge => write!(f, "state too large, sync by block ranges"),
+        }
+    }
+}
+
+impl fmt::Display for InvalidBlockErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidBlockHeight => {
+                write!(f, "invalid block height")
+            }
+            Self::InvalidClaim => {
+                write!(f, "invalid claim")
+            }
+            Self::InvalidLastHash => {
+                write!(f, "invalid last hash")
+            }
+            Self::InvalidStateHash => {
+                write!(f, "invalid state hash")
+            }
+            Self::BlockOutOfSequence => {
+                write!(f, "block out of sequence")
+            }
+            Self::InvalidBlockNonce => {
+                write!(f, "invalid block nonce")
+            }
+            Self::InvalidBlockReward => {
+                write!(f, "invalid block reward")
+            }
+            Self::InvalidTxns => {
+                write!(f, "invalid txns in block")
+            }
+            Self::InvalidClaimPointers => {
+                write!(f, "invalid claim pointers")
+            }
+            Self::BlockCostExceeded => {
+                write!(f, "block cost exceeded")
+            }
+            Self::InvalidNonceEpoch => {
+                write!(f, "block mined on another nonce epoch")
+            }
+            Self::InvalidClaimMapHash => {
+                write!(f, "invalid claim map hash")
+            }
+            Self::InvalidLedgerRoot => {
+                write!(f, "invalid ledger root")
+            }
+            Self::SelfConflict => {
+                write!(f, "claim already signed a block at this height")
+            }
+            Self::General => {
+                write!(f, "general invalid block error")
+            }
+        }
+    }
+}
+
+impl GettableFields for Blockchain {
+    fn get_field(&self, field: &str) -> Option<String> {
+        match field {
+            "genesis" => {
+                if let Some(genesis) = self.genesis.clone() {
+                    return Some(genesis.to_string());
+                }
+                return None;
+            }
+            "child" => {
+                if let Some(child) = self.child.clone() {
+                    return Some(child.to_string());
+                }
+                return None;
+            }
+            "parent" => {
+                if let Some(parent) = self.parent.clone() {
+                    return Some(parent.to_string());
+                }
+                return None;
+            }
+            "chain" => return Some(serde_json::to_string(&self.chain).unwrap()),
+            "chain_db" => Some(self.chain_db.clone()),
+            "block_cache" => return Some(serde_json::to_string(&self.block_cache).unwrap()),
+            "future_blocks" => return Some(serde_json::to_string(&self.future_blocks).unwrap()),
+            "invalid" => return Some(serde_json::to_string(&self.invalid).unwrap()),
+            "updating_state" => return Some(format!("{}", self.updating_state)),
+            "state_update_cache" => {
+                return Some(serde_json::to_string(&self.state_update_cache).unwrap())
+            }
+            "block_heights" => return Some(serde_json::to_string(&self.block_heights).unwrap()),
+            "txn_blocks" => return Some(serde_json::to_string(&self.txn_blocks).unwrap()),
+            "last_finalized" => return Some(format!("{:?}", self.last_finalized)),
+            "max_future_blocks" => return Some(self.max_future_blocks.to_string()),
+            "pruned_below" => return Some(format!("{:?}", self.pruned_below)),
+            "validations" => return Some(self.validations.to_string()),
+            "network_genesis" => return Some(format!("{:?}", self.network_genesis)),
+            "competing_tips" => return Some(serde_json::to_string(&self.competing_tips).unwrap()),
+            "fork_resolutions" => {
+                return Some(serde_json::to_string(&self.fork_resolutions).unwrap())
+            }
+            "block_txns" => return Some(serde_json::to_string(&self.block_txns).unwrap()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claim::Claim;
+    use crate::params::{Activations, ProtocolParams, BLOCK_COST_LIMIT};
+    use crate::reward::Reward;
+    use crate::test_utils::{temp_path, test_block};
+    use crate::txn::{Txn, BASE_TXN_COST};
+    use crate::wallet::WalletAccount;
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    thread_local! {
+        static CAPTURED: RefCell<Vec<(Level, String, String)>> = RefCell::new(vec![]);
+    }
+
+    // Keeps the records logged on each thread, so tests running in parallel
+    // only see their own.
+    struct CaptureLogger;
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED.with(|captured| {
+                captured.borrow_mut().push((
+                    record.level(),
+                    record.target().to_string(),
+                    record.args().to_string(),
+                ))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let _ = log::set_boxed_logger(Box::new(CaptureLogger));
+            log::set_max_level(LevelFilter::Trace);
+        });
+        CAPTURED.with(|captured| captured.borrow_mut().clear());
+    }
+
+    // The records at info or above logged to a target since capture_logs.
+    fn captured_records(target: &str) -> Vec<(Level, String)> {
+        CAPTURED.with(|captured| {
+            captured
+                .borrow()
+                .iter()
+                .filter(|(level, record_target, _)| {
+                    record_target == target && *level <= Level::Info
+                })
+                .map(|(level, _, message)| (*level, message.clone()))
+                .collect()
+        })
+    }
+
+    // Signs the block's header as mined with a claim of `wallet`'s.
+    fn sign_block(block: &mut Block, wallet: &WalletAccount) {
+        block.header.claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        block.header.signature =
+            BlockHeader::sign(&block.header.get_payload(), wallet.get_secretkey())
+                .unwrap()
+                .to_string();
+    }
+
+    // A genesis block signed by a new wallet and one block on top of it, along
+    // with the ledger they produce.
+    fn checkpoint_chain(name: &str) -> (Blockchain, NetworkState) {
+        let chain_path = temp_path(&format!("{}_chain.db", name));
+        let state_path = temp_path(&format!("{}_state.db", name));
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let genesis = Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap();
+        let mut block = test_block(1, "block_1");
+        block.header.last_hash = genesis.hash.clone();
+        block.header.block_reward = genesis.header.next_block_reward.clone();
+        block.header.block_reward.miner = Some("address".to_string());
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut network_state = NetworkState::restore(&state_path);
+        [genesis, block].iter().for_each(|block| {
+            blockchain.append_block(block);
+            network_state.dump(block);
+        });
+
+        (blockchain, network_state)
+    }
+
+    fn indexed_chain(n_blocks: u128) -> Blockchain {
+        let mut blockchain = Blockchain::new(&temp_path("test_finality.db"));
+        (0..n_blocks).for_each(|height| {
+            blockchain.index_height(&format!("block_{}", height), height);
+        });
+        blockchain
+    }
+
+    #[test]
+    fn tip_is_set_once_genesis_is_confirmed() {
+        let chain_path = temp_path("test_tip_chain.db");
+        let state_path = temp_path("test_tip_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let genesis = Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap();
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        assert!(blockchain.tip().is_none());
+        assert_eq!(blockchain.tip_height(), None);
+
+        blockchain
+            .process_block(&network_state, &RewardState::start(), &genesis)
+            .unwrap();
+        assert_eq!(blockchain.tip().unwrap().hash, genesis.hash);
+        assert_eq!(blockchain.tip_height(), Some(0));
+
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn only_the_configured_genesis_is_accepted() {
+        let chain_path = temp_path("test_network_genesis_chain.db");
+        let state_path = temp_path("test_network_genesis_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let mine_genesis = || {
+            let wallet = WalletAccount::new();
+            let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+            Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap()
+        };
+        let (other, configured) = (mine_genesis(), mine_genesis());
+        let mut blockchain = Blockchain::new(&chain_path);
+        blockchain.network_genesis = Some(configured.header.hash());
+        let network_state = NetworkState::restore(&state_path);
+
+        assert!(matches!(
+            blockchain.process_block(&network_state, &RewardState::start(), &other),
+            Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::General
+            })
+        ));
+        assert!(blockchain.genesis.is_none());
+
+        blockchain
+            .process_block(&network_state, &RewardState::start(), &configured)
+            .unwrap();
+        assert_eq!(
+            blockchain.genesis.unwrap().header.signature,
+            configured.header.signature
+        );
+
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn nodes_on_different_params_reject_each_others_genesis() {
+        let node = |name: &str, params: ProtocolParams| {
+            let chain_path = temp_path(&format!("test_params_{}_chain.db", name));
+            let state_path = temp_path(&format!("test_params_{}_state.db", name));
+            let _ = fs::remove_file(&chain_path);
+            let _ = fs::remove_file(&state_path);
+
+            let wallet = WalletAccount::new();
+            let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+            let reward_state = RewardState::start_with(params);
+            let genesis = Block::genesis(&reward_state, claim, wallet.get_secretkey()).unwrap();
+            let blockchain = Blockchain::new(&chain_path);
+            let network_state = NetworkState::restore(&state_path);
+            (blockchain, network_state, reward_state, genesis)
+        };
+        let testnet = node("testnet", ProtocolParams::testnet());
+        let local = node("local", ProtocolParams::local());
+        assert_ne!(testnet.3.hash, local.3.hash);
+        // Chains already running on the testnet params keep their genesis.
+        assert_eq!(
+            testnet.3.header.last_hash,
+            digest_bytes("Genesis_Last_Hash".as_bytes())
+        );
+
+        let (testnet_genesis, local_genesis) = (testnet.3.clone(), local.3.clone());
+        for ((mut blockchain, network_state, reward_state, ours), theirs) in
+            vec![(testnet, local_genesis), (local, testnet_genesis)]
+        {
+            assert!(blockchain
+                .process_block(&network_state, &reward_state, &theirs)
+                .is_err());
+            assert!(blockchain.genesis.is_none());
+            blockchain
+                .process_block(&network_state, &reward_state, &ours)
+                .unwrap();
+
+            let _ = fs::remove_file(&blockchain.chain_db);
+            let _ = fs::remove_file(&network_state.path);
+        }
+    }
+
+    #[test]
+    fn genesis_with_a_tampered_reward_state_is_rejected() {
+        let chain_path = temp_path("test_tampered_genesis_chain.db");
+        let state_path = temp_path("test_tampered_genesis_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let mut tampered = RewardState::start();
+        tampered.n_motherlodes_current_epoch += 1000;
+        let genesis = Block::genesis(&tampered, claim, wallet.get_secretkey()).unwrap();
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        assert!(blockchain
+            .process_block(&network_state, &tampered, &genesis)
+            .is_err());
+        assert!(blockchain.genesis.is_none());
+        assert!(blockchain.invalid.is_empty());
+
+        let mut inflated = genesis.clone();
+        inflated.header.block_reward.amount *= 2;
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &inflated)
+            .is_err());
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &genesis)
+            .is_ok());
+        assert_eq!(blockchain.genesis.unwrap().hash, genesis.hash);
+
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn processed_blocks_are_logged_with_height_and_hash() {
+        capture_logs();
+        let chain_path = temp_path("test_block_logging_chain.db");
+        let state_path = temp_path("test_block_logging_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let reward_state = RewardState::start();
+        let genesis = Block::genesis(&reward_state, claim, wallet.get_secretkey()).unwrap();
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        assert!(blockchain
+            .process_block(&network_state, &reward_state, &genesis)
+            .is_ok());
+        let invalid = test_block(5, "invalid");
+        assert!(blockchain
+            .process_block(&network_state, &reward_state, &invalid)
+            .is_err());
+
+        assert_eq!(
+            captured_records(BLOCKCHAIN_TARGET),
+            vec![
+                (
+                    Level::Info,
+                    format!(
+                        "block confirmed height=0 hash={} last_hash={}",
+                        genesis.hash, genesis.header.last_hash
+                    )
+                ),
+                (
+                    Level::Warn,
+                    "invalid block height=5 hash=invalid reason=InvalidBlockHeight".to_string()
+                ),
+            ]
+        );
+
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn too_high_block_requests_missing_range() {
+        let mut blockchain = Blockchain::new(&temp_path("test_invalid_height.db"));
+        let mut tip = test_block(4, "tip");
+        tip.header.last_hash = "parent".to_string();
+        blockchain.child = Some(tip.clone());
+        blockchain.index_height(&tip.hash, 4);
+
+        let wallet = WalletAccount::new();
+        let mut future = test_block(8, "future");
+        sign_block(&mut future, &wallet);
+        let messages =
+            blockchain.handle_invalid_height(&future, "peer".to_string(), "node".to_string());
+
+        assert!(blockchain
+            .future_blocks
+            .contains_key(&future.header.last_hash));
+        assert!(blockchain.has_gap_above_tip());
+        match &messages[0] {
+            MessageType::NeedBlocksMessage { blocks_needed, .. } => {
+                assert_eq!(blocks_needed, &vec![5, 6, 7]);
+            }
+            message => panic!("expected a range request, got {:?}", message),
+        }
+        match &messages[1] {
+            MessageType::NeedBlockMessage {
+                block_last_hash,
+                requested_from,
+                ..
+            } => {
+                assert_eq!(block_last_hash, "tip");
+                assert_eq!(requested_from, "peer");
+            }
+            message => panic!("expected a block request, got {:?}", message),
+        }
+
+        let mut stale = test_block(3, "stale");
+        sign_block(&mut stale, &wallet);
+        assert_eq!(
+            blockchain.check_block_height(&stale),
+            BlockHeightCheck::Stale(4)
+        );
+        let messages =
+            blockchain.handle_invalid_height(&stale, "peer".to_string(), "node".to_string());
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(
+            messages[0],
+            MessageType::InvalidBlockMessage {
+                tip_height: Some(4),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn far_future_blocks_request_a_bounded_range_only_when_signed() {
+        let mut blockchain = Blockchain::new(&temp_path("test_far_future_height.db"));
+        let tip = test_block(4, "tip");
+        blockchain.child = Some(tip.clone());
+        blockchain.index_height(&tip.hash, 4);
+
+        let mut far = test_block(u128::MAX, "far");
+        assert_eq!(
+            blockchain.check_block_height(&far),
+            BlockHeightCheck::Unsigned
+        );
+        assert!(blockchain
+            .handle_invalid_height(&far, "peer".to_string(), "node".to_string())
+            .is_empty());
+        assert!(blockchain.future_blocks.is_empty());
+
+        sign_block(&mut far, &WalletAccount::new());
+        match blockchain.check_block_height(&far) {
+            BlockHeightCheck::Future(blocks_needed) => {
+                assert_eq!(blocks_needed.len() as u128, MAX_SYNC_RANGE);
+                assert_eq!(blocks_needed[0], 5);
+            }
+            check => panic!("expected a bounded range, got {:?}", check),
+        }
+    }
+
+    #[test]
+    fn block_skipping_a_height_is_rejected() {
+        let chain_path = temp_path("vrrb_test_skipped_height_chain.db");
+        let state_path = temp_path("vrrb_test_skipped_height_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let reward_state = RewardState::start();
+        let mut genesis =
+            Block::genesis(&reward_state, claim.clone(), wallet.get_secretkey()).unwrap();
+        genesis.header.timestamp -= 2 * crate::miner::SECOND;
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        assert!(blockchain
+            .process_block(&network_state, &reward_state, &genesis)
+            .is_ok());
+
+        let mut skipped = Block::mine(
+            claim,
+            genesis.clone(),
+            LinkedHashMap::new(),
+            LinkedHashMap::new(),
+            None,
+            &network_state,
+            None,
+            None,
+            wallet.get_secretkey(),
+            genesis.header.timestamp + 2 * crate::miner::SECOND,
+            || false,
+        )
+        .unwrap();
+        assert_eq!(skipped.header.block_height, 1);
+        skipped.header.block_height = 2;
+        skipped.height = 2;
+        sign_block(&mut skipped, &wallet);
+
+        let e = blockchain
+            .process_block(&network_state, &reward_state, &skipped)
+            .unwrap_err();
+        assert!(matches!(
+            e.details,
+            InvalidBlockErrorReason::InvalidBlockHeight
+        ));
+        assert_eq!(blockchain.tip().unwrap().hash, genesis.hash);
+        // Failed against the ledger, not in itself, so it isn't remembered.
+        assert!(!blockchain.is_known(&skipped));
+
+        // Built on the tip, so no blocks are missing, the miner is told.
+        assert_eq!(
+            blockchain.check_block_height(&skipped),
+            BlockHeightCheck::Skipped
+        );
+        let messages =
+            blockchain.handle_invalid_height(&skipped, "peer".to_string(), "node".to_string());
+        assert!(blockchain.future_blocks.is_empty());
+        assert_eq!(messages.len(), 1);
+        match &messages[0] {
+            MessageType::InvalidBlockMessage {
+                block_height,
+                miner_id,
+                tip_height,
+                ..
+            } => {
+                assert_eq!(*block_height, 2);
+                assert_eq!(miner_id, "peer");
+                // The miner isn't behind, it isn't sent blocks to catch up on.
+                assert_eq!(*tip_height, None);
+            }
+            message => panic!("expected an invalid block message, got {:?}", message),
+        }
+
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn node_behind_a_peer_requests_the_blocks_it_is_missing() {
+        let mut blockchain = indexed_chain(5);
+        match blockchain.catch_up_request(8, "peer".to_string(), "node".to_string()) {
+            Some(MessageType::GetBlockRangeMessage {
+                from_height,
+                to_height,
+                requested_from,
+                ..
+            }) => {
+                assert_eq!((from_height, to_height), (5, 8));
+                assert_eq!(requested_from, "peer");
+            }
+            message => panic!("expected a block range request, got {:?}", message),
+        }
+
+        assert!(blockchain
+            .catch_up_request(4, "peer".to_string(), "node".to_string())
+            .is_none());
+        match blockchain.catch_up_request(500, "peer".to_string(), "node".to_string()) {
+            Some(MessageType::GetBlockRangeMessage {
+                from_height,
+                to_height,
+                ..
+            }) => assert_eq!((from_height, to_height), (5, 4 + MAX_BLOCK_RANGE)),
+            message => panic!("expected a block range request, got {:?}", message),
+        }
+        blockchain.updating_state = true;
+        assert!(blockchain
+            .catch_up_request(8, "peer".to_string(), "node".to_string())
+            .is_none());
+    }
+
+    #[test]
+    fn exceeding_future_block_cap_evicts_lowest_block() {
+        let mut blockchain = Blockchain::new(&temp_path("test_future_cap.db"));
+        blockchain.max_future_blocks = 3;
+        [7, 5, 6].iter().for_each(|height| {
+            blockchain.stash_future_blocks(&test_block(*height, &format!("block_{}", height)));
+        });
+        assert_eq!(blockchain.future_blocks.len(), 3);
+
+        blockchain.stash_future_blocks(&test_block(8, "block_8"));
+        let mut heights: Vec<u128> = blockchain
+            .future_blocks
+            .values()
+            .map(|block| block.header.block_height)
+            .collect();
+        heights.sort();
+        assert_eq!(heights, vec![6, 7, 8]);
+    }
+
+    #[test]
+    fn bootstrapped_chain_matches_exported_checkpoint() {
+        let chain_path = temp_path("vrrb_test_checkpoint_export_chain.db");
+        let state_path = temp_path("vrrb_test_checkpoint_export_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let genesis = Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap();
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut network_state = NetworkState::restore(&state_path);
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &genesis)
+            .is_ok());
+        network_state.dump(&genesis);
+
+        let checkpoint_path = temp_path("vrrb_test_checkpoint.json");
+        let bootstrap_chain_path = temp_path("vrrb_test_checkpoint_bootstrap_chain.db");
+        let bootstrap_state_path = temp_path("vrrb_test_checkpoint_bootstrap_state.db");
+        let _ = fs::remove_file(&bootstrap_chain_path);
+        let _ = fs::remove_file(&bootstrap_state_path);
+        blockchain
+            .export_checkpoint(&checkpoint_path, 0, &network_state)
+            .unwrap();
+
+        let mut bootstrapped = Blockchain::new(&bootstrap_chain_path);
+        let mut bootstrapped_state = NetworkState::restore(&bootstrap_state_path);
+        let tip = bootstrapped
+            .bootstrap_from_file(&checkpoint_path, &mut bootstrapped_state)
+            .unwrap();
+
+        assert_eq!(tip, 0);
+        assert_eq!(bootstrapped.tip_height(), blockchain.tip_height());
+        assert_eq!(
+            bootstrapped.genesis.as_ref().unwrap().hash,
+            blockchain.genesis.as_ref().unwrap().hash
+        );
+        assert_eq!(
+            bootstrapped.child.as_ref().unwrap().hash,
+            blockchain.child.as_ref().unwrap().hash
+        );
+        assert_eq!(
+            bootstrapped_state.db_to_ledger().to_string(),
+            network_state.db_to_ledger().to_string()
+        );
+        assert_eq!(
+            bootstrapped_state.get_reward_state(),
+            network_state.get_reward_state()
+        );
+        assert!(bootstrapped
+            .bootstrap_from_file(&checkpoint_path, &mut bootstrapped_state)
+            .is_err());
+
+        let _ = fs::remove_file(checkpoint_path);
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+        let _ = fs::remove_file(bootstrap_chain_path);
+        let _ = fs::remove_file(bootstrap_state_path);
+    }
+
+    #[test]
+    fn checkpoint_with_an_invalid_block_is_refused() {
+        // Block 1 extends the genesis block with the right reward, but it isn't
+        // a valid block.
+        let (blockchain, network_state) = checkpoint_chain("vrrb_test_checkpoint_invalid");
+        let checkpoint_path = temp_path("vrrb_test_invalid_checkpoint.json");
+        let chain_path = temp_path("vrrb_test_invalid_bootstrap_chain.db");
+        let state_path = temp_path("vrrb_test_invalid_bootstrap_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+        blockchain
+            .export_checkpoint(&checkpoint_path, 1, &network_state)
+            .unwrap();
+        let checkpoint = Checkpoint::from_bytes(&fs::read(&checkpoint_path).unwrap()).unwrap();
+        assert!(checkpoint
+            .verify(NETWORK_ID, &ProtocolParams::default())
+            .is_ok());
+
+        let mut fresh = Blockchain::new(&chain_path);
+        let mut fresh_state = NetworkState::restore(&state_path);
+        assert!(fresh
+            .bootstrap_from_file(&checkpoint_path, &mut fresh_state)
+            .is_err());
+        assert_eq!(fresh.tip_height(), Some(0));
+
+        let _ = fs::remove_file(checkpoint_path);
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn tampered_checkpoint_is_rejected() {
+        let (blockchain, network_state) = checkpoint_chain("vrrb_test_checkpoint_tampered");
+        let checkpoint_path = temp_path("vrrb_test_tampered_checkpoint.json");
+        let chain_path = temp_path("vrrb_test_tampered_bootstrap_chain.db");
+        let state_path = temp_path("vrrb_test_tampered_bootstrap_state.db");
+        blockchain
+            .export_checkpoint(&checkpoint_path, 1, &network_state)
+            .unwrap();
+
+        let mut checkpoint = Checkpoint::from_bytes(&fs::read(&checkpoint_path).unwrap()).unwrap();
+        checkpoint.blocks[1].header.block_reward.amount += 1;
+        fs::write(&checkpoint_path, checkpoint.to_string()).unwrap();
+
+        let mut fresh = Blockchain::new(&chain_path);
+        let mut fresh_state = NetworkState::restore(&state_path);
+        assert!(fresh
+            .bootstrap_from_file(&checkpoint_path, &mut fresh_state)
+            .is_err());
+        assert!(fresh.genesis.is_none());
+
+        // Re-hashing the tampered blocks still fails the reward check.
+        let rehashed = Checkpoint::new(NETWORK_ID, checkpoint.blocks.clone(), None);
+        assert!(rehashed
+            .verify(NETWORK_ID, &ProtocolParams::default())
+            .is_err());
+
+        checkpoint.blocks[1].header.block_reward.amount -= 1;
+        let other_network = Checkpoint::new("other-net", checkpoint.blocks, None);
+        assert!(other_network
+            .verify("other-net", &ProtocolParams::default())
+            .is_ok());
+        assert!(other_network
+            .verify(NETWORK_ID, &ProtocolParams::default())
+            .is_err());
+
+        let _ = fs::remove_file(checkpoint_path);
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn confirmations_increase_as_blocks_are_added() {
+        let mut blockchain = indexed_chain(1);
+        assert_eq!(blockchain.confirmations("block_0"), Some(0));
+        blockchain.index_height("block_1", 1);
+        assert_eq!(blockchain.confirmations("block_0"), Some(1));
+        blockchain.index_height("block_2", 2);
+        assert_eq!(blockchain.confirmations("block_0"), Some(2));
+        assert_eq!(blockchain.confirmations("block_2"), Some(0));
+        assert_eq!(blockchain.confirmations("unknown"), None);
+    }
+
+    #[test]
+    fn fork_cannot_unwind_past_finality() {
+        let blockchain = indexed_chain(FINALITY_DEPTH + 10);
+        assert!(blockchain.is_final("block_9"));
+        assert!(!blockchain.is_final("block_10"));
+        assert!(!blockchain.can_unwind_to(8));
+        assert!(blockchain.can_unwind_to(9));
+        assert!(blockchain.can_unwind_to(FINALITY_DEPTH));
+    }
+
+    #[test]
+    fn synced_chain_cannot_unwind_past_finality() {
+        let tip = FINALITY_DEPTH + 10;
+        let mut ours = Blockchain::new(&temp_path("test_switch_ours.db"));
+        let mut theirs = Blockchain::new(&temp_path("test_switch_theirs.db"));
+        (0..=tip).for_each(|height| {
+            let block = test_block(height, &format!("block_{}", height));
+            ours.chain.push_back(block.header.clone());
+            ours.index_height(&block.hash, height);
+            theirs.chain.push_back(block.header);
+        });
+        let forked_above = |fork_height: u128| {
+            let mut forked = theirs.clone();
+            forked
+                .chain
+                .iter_mut()
+                .filter(|header| header.block_height > fork_height)
+                .for_each(|header| header.timestamp += 1);
+            forked
+        };
+
+        assert_eq!(ours.fork_height(&theirs), Some(tip));
+        assert!(ours.can_switch_to(&theirs));
+        assert_eq!(ours.fork_height(&forked_above(tip - 3)), Some(tip - 3));
+        assert!(ours.can_switch_to(&forked_above(tip - 3)));
+        assert_eq!(ours.fork_height(&forked_above(5)), Some(5));
+        assert!(!ours.can_switch_to(&forked_above(5)));
+    }
+
+    #[test]
+    fn height_and_txn_indexes_are_bounded() {
+        let mut blockchain = Blockchain::new(&temp_path("test_bounded_indexes.db"));
+        (0..MAX_INDEXED_BLOCKS as u128 + 10).for_each(|height| {
+            let mut block = test_block(height, &format!("block_{}", height));
+            let txn = Txn::unsigned_transfer(1, height);
+            block.txns.insert(format!("txn_{}", height), txn);
+            blockchain.index_block(&block);
+        });
+
+        assert_eq!(blockchain.block_heights.len(), MAX_INDEXED_BLOCKS);
+        assert_eq!(blockchain.txn_blocks.len(), MAX_INDEXED_BLOCKS);
+        assert_eq!(blockchain.block_txns.len(), MAX_INDEXED_BLOCKS);
+        assert!(blockchain.find_txn("txn_9").is_none());
+        assert!(blockchain.find_txn("txn_10").is_some());
+    }
+
+    #[test]
+    fn blocks_are_finalized_exactly_once() {
+        let mut blockchain = indexed_chain(FINALITY_DEPTH);
+        assert!(blockchain.newly_finalized().is_empty());
+
+        blockchain.index_height(&format!("block_{}", FINALITY_DEPTH), FINALITY_DEPTH);
+        assert_eq!(
+            blockchain.newly_finalized(),
+            vec![("block_0".to_string(), 0)]
+        );
+        assert!(blockchain.newly_finalized().is_empty());
+
+        blockchain.index_height("block_101", FINALITY_DEPTH + 1);
+        blockchain.index_height("block_102", FINALITY_DEPTH + 2);
+        assert_eq!(
+            blockchain.newly_finalized(),
+            vec![("block_1".to_string(), 1), ("block_2".to_string(), 2)]
+        );
+        assert!(blockchain.newly_finalized().is_empty());
+    }
+
+    #[test]
+    fn reopened_chain_picks_up_at_its_tip() {
+        let chain_path = temp_path("vrrb_test_reopened_chain.db");
+        let _ = fs::remove_file(&chain_path);
+        let tip = FINALITY_DEPTH + 5;
+        let linked_block = |height: u128| {
+            let mut block = test_block(height, &format!("block_{}", height));
+            block.header.last_hash = format!("block_{}", height.saturating_sub(1));
+            block
+        };
+        let mut blockchain = Blockchain::new(&chain_path);
+        (0..=tip).for_each(|height| {
+            let mut block = linked_block(height);
+            if height == tip {
+                block
+                    .txns
+                    .insert("txn".to_string(), Txn::unsigned_transfer(1, 0));
+            }
+            blockchain.append_block(&block);
+        });
+        // Blocks stored off the chain: one below the tip and one as high.
+        let mut stale = linked_block(tip - 2);
+        stale.hash = "stale".to_string();
+        blockchain.dump(&stale).unwrap();
+        let mut competing = linked_block(tip);
+        competing.hash = "competing".to_string();
+        blockchain.dump(&competing).unwrap();
+        assert_eq!(
+            blockchain.newly_finalized().len() as u128,
+            tip - FINALITY_DEPTH + 1
+        );
+
+        let mut reopened = Blockchain::open(&chain_path);
+        assert_eq!(reopened.genesis.as_ref().unwrap().hash, "block_0");
+        assert_eq!(reopened.tip().unwrap().hash, format!("block_{}", tip));
+        assert_eq!(
+            reopened.parent.as_ref().unwrap().hash,
+            format!("block_{}", tip - 1)
+        );
+        assert_eq!(reopened.canonical_hashes(), blockchain.canonical_hashes());
+        assert_eq!(reopened.block_heights, blockchain.block_heights);
+        assert_eq!(
+            reopened.find_txn("txn"),
+            Some((format!("block_{}", tip), 0))
+        );
+        assert_eq!(reopened.last_finalized, blockchain.last_finalized);
+        assert!(reopened.newly_finalized().is_empty());
+
+        // The headers below the pruned height are kept for the next restart.
+        reopened.prune_archive(tip).unwrap();
+        let reopened = Blockchain::open(&chain_path);
+        assert_eq!(reopened.pruned_below, Some(tip - FINALITY_DEPTH + 1));
+        assert_eq!(reopened.genesis.as_ref().unwrap().hash, "block_0");
+        assert_eq!(reopened.chain.len() as u128, tip + 1);
+        assert_eq!(reopened.canonical_hashes(), blockchain.canonical_hashes());
+        assert!(reopened.verify_header_chain().is_ok());
+
+        let _ = fs::remove_file(chain_path);
+    }
+
+    #[test]
+    fn restarted_node_keeps_its_tip_and_balances() {
+        let chain_path = temp_path("vrrb_test_restart_chain.db");
+        let state_path = temp_path("vrrb_test_restart_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+        let tip = 10;
+        let miners = ["miner_0", "miner_1", "miner_2"];
+        let mut blocks = vec![];
+        {
+            let mut blockchain = Blockchain::new(&chain_path);
+            let mut network_state = NetworkState::restore(&state_path);
+            (0..=tip).for_each(|height| {
+                let mut block = test_block(height, &format!("block_{}", height));
+                block.header.last_hash = format!("block_{}", height.saturating_sub(1));
+                block.header.block_reward.miner = Some(miners[height as usize % 3].to_string());
+                blockchain.append_block(&block);
+                network_state.dump(&block);
+                blocks.push(block);
+            });
+            network_state.flush().unwrap();
+        }
+        let balances = |network_state: &NetworkState| -> Vec<u128> {
+            miners
+                .iter()
+                .map(|miner| network_state.get_balance(miner))
+                .collect()
+        };
+
+        let blockchain = Blockchain::open(&chain_path);
+        let mut network_state = NetworkState::restore(&state_path);
+        let expected = vec![4, 4, 3];
+        assert_eq!(blockchain.tip().unwrap().hash, format!("block_{}", tip));
+        assert_eq!(balances(&network_state), expected);
+        assert_eq!(
+            network_state.last_applied(),
+            Some((tip, format!("block_{}", tip)))
+        );
+
+        // Blocks the node applied before it stopped aren't applied again.
+        blocks.iter().for_each(|block| network_state.dump(block));
+        assert_eq!(balances(&network_state), expected);
+        let mut next = test_block(tip + 1, &format!("block_{}", tip + 1));
+        next.header.last_hash = format!("block_{}", tip);
+        next.header.block_reward.miner = Some(miners[0].to_string());
+        network_state.dump(&next);
+        assert_eq!(balances(&network_state), vec![5, 4, 3]);
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn txns_of_finalized_blocks_are_reported() {
+        let mut blockchain = indexed_chain(FINALITY_DEPTH);
+        let mut index = |block_hash: &str, txn_ids: &[&str]| {
+            txn_ids.iter().for_each(|txn_id| {
+                blockchain
+                    .txn_blocks
+                    .insert(txn_id.to_string(), block_hash.to_string());
+            });
+            blockchain.block_txns.insert(
+                block_hash.to_string(),
+                txn_ids.iter().map(|txn_id| txn_id.to_string()).collect(),
+            );
+        };
+        index("block_0", &["txn_0", "txn_1", "txn_3"]);
+        index("block_1", &["txn_2"]);
+        // Included again in a later block.
+        index("block_2", &["txn_3"]);
+
+        blockchain.index_height(&format!("block_{}", FINALITY_DEPTH), FINALITY_DEPTH);
+        let finalized = blockchain.newly_finalized();
+        assert_eq!(
+            blockchain.finalized_txns(&finalized),
+            vec!["txn_0".to_string(), "txn_1".to_string()]
+        );
+        assert!(blockchain.finalized_txns(&[]).is_empty());
+    }
+
+    #[test]
+    fn competing_blocks_with_the_same_parent_are_kept() {
+        let chain_path = temp_path("vrrb_test_competing_chain.db");
+        let _ = fs::remove_file(&chain_path);
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut parent = test_block(0, "parent");
+        parent.header.last_hash = "genesis_parent".to_string();
+        blockchain.append_block(&parent);
+
+        let mut canonical = test_block(1, "canonical");
+        let mut competing = test_block(1, "competing");
+        canonical.header.last_hash = parent.hash.clone();
+        competing.header.last_hash = parent.hash.clone();
+        blockchain.append_block(&canonical);
+        blockchain.dump(&competing).unwrap();
+
+        assert_eq!(blockchain.get_blocks_at(1).len(), 2);
+        assert!(blockchain.get_block_by_hash("competing").is_some());
+        assert_eq!(
+            blockchain.get_block_by_hash("canonical").unwrap().hash,
+            "canonical"
+        );
+        assert_eq!(
+            blockchain.get_canonical_block_at(1).unwrap().hash,
+            "canonical"
+        );
+        assert_eq!(blockchain.get_canonical_block_at(0).unwrap().hash, "parent");
+        assert_eq!(
+            blockchain.get_canonical_child("parent").unwrap().hash,
+            "canonical"
+        );
+        assert!(blockchain.get_canonical_block_at(2).is_none());
+
+        let _ = fs::remove_file(chain_path);
+    }
+
+    #[test]
+    fn same_claim_mining_twice_at_a_height_is_rejected_and_slashed_by_evidence() {
+        let (mut blockchain, mut network_state) = checkpoint_chain("vrrb_test_self_conflict");
+        let wallet = WalletAccount::new();
+        let mut first = test_block(2, "block_2");
+        first.header.last_hash = "block_1".to_string();
+        first.header.block_reward.miner = Some("address".to_string());
+        sign_block(&mut first, &wallet);
+        blockchain.append_block(&first);
+        network_state.dump(&first);
+        let mut second = test_block(2, "block_2_twin");
+        second.header.last_hash = first.header.last_hash.clone();
+        second.header.timestamp = 1;
+        sign_block(&mut second, &wallet);
+
+        // An unsigned copy can't be held against the claim.
+        let mut unsigned = second.clone();
+        unsigned.hash = "block_2_unsigned".to_string();
+        unsigned.header.signature = String::new();
+        let err = blockchain
+            .process_block(&network_state, &RewardState::start(), &unsigned)
+            .unwrap_err();
+        assert!(!matches!(
+            err.details,
+            InvalidBlockErrorReason::SelfConflict
+        ));
+        // It's remembered as invalid in itself instead.
+        assert!(blockchain.is_known(&unsigned));
+
+        let err = blockchain
+            .process_block(&network_state, &RewardState::start(), &second)
+            .unwrap_err();
+        assert!(matches!(err.details, InvalidBlockErrorReason::SelfConflict));
+        assert_eq!(blockchain.tip().unwrap().hash, first.hash);
+        assert!(!blockchain.is_known(&second));
+
+        // A block from another claim at the height is a fork, not a conflict.
+        let mut other = second.clone();
+        other.header.claim = Claim::new("other".to_string(), "address".to_string(), 1);
+        assert!(blockchain.self_conflicting(&other).is_none());
+        assert!(blockchain.self_conflicting(&first).is_none());
+
+        // The claim is only slashed by a block carrying the evidence.
+        let evidence = blockchain.slashing_evidence(&second).unwrap();
+        assert_eq!(evidence.offender(), wallet.pubkey);
+        assert!(evidence.verify().is_ok());
+        assert_eq!(network_state.is_claim_eligible(&wallet.pubkey), Some(true));
+        let txn = Txn::slashing_evidence(&evidence);
+        assert!(txn.valid_slashing_evidence(&network_state).is_ok());
+        let mut forged = txn.clone();
+        forged.sender_public_key = "pubkey".to_string();
+        assert!(forged.valid_slashing_evidence(&network_state).is_err());
+        let same =
+            SlashingEvidence::conflicting_headers(first.header.clone(), first.header.clone());
+        assert!(same.verify().is_err());
+
+        let mut block = test_block(3, "block_3");
+        block.header.last_hash = first.hash.clone();
+        block.header.block_reward.miner = Some("address".to_string());
+        block.txns.insert(txn.txn_id.clone(), txn.clone());
+        network_state.dump(&block);
+        let history = network_state.get_offense_history(&wallet.pubkey).unwrap();
+        assert_eq!(history.offenses, vec![3]);
+        assert_eq!(network_state.is_claim_eligible(&wallet.pubkey), Some(false));
+        assert!(txn.valid_slashing_evidence(&network_state).is_err());
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn old_chain_db_is_migrated_to_block_hash_keys() {
+        let chain_path = temp_path("vrrb_test_migrated_chain.db");
+        let _ = fs::remove_file(&chain_path);
+        let blocks: Vec<Block> = (0..3)
+            .map(|height| test_block(height, &format!("block_{}", height)))
+            .collect();
+        let mut db = PickleDb::new(
+            chain_path.clone(),
+            PickleDbDumpPolicy::DumpUponRequest,
+            SerializationMethod::Bin,
+        );
+        blocks.iter().for_each(|block| {
+            db.set(&block.header.last_hash, block).unwrap();
+        });
+        db.dump().unwrap();
+        drop(db);
+
+        let blockchain = Blockchain::new(&chain_path);
+        blocks.iter().for_each(|block| {
+            let migrated = blockchain.get_block_by_hash(&block.hash).unwrap();
+            assert_eq!(migrated.header.last_hash, block.header.last_hash);
+            assert_eq!(blockchain.get_blocks_at(block.header.block_height).len(), 1);
+        });
+
+        let db = blockchain.get_chain_db();
+        assert_eq!(db.get::<u32>(CHAIN_DB_VERSION_KEY), Some(CHAIN_DB_VERSION));
+        assert_eq!(Blockchain::block_keys(&db).len(), blocks.len());
+        assert!(db.get::<Block>("block_0_parent").is_none());
+
+        let _ = fs::remove_file(chain_path);
+    }
+
+    #[test]
+    fn blocks_misstating_or_exceeding_cost_are_rejected() {
+        let mut block = test_block(1, "costly");
+        let txn = Txn::unsigned_transfer(1, 0);
+        block.txns.insert(txn.txn_id.clone(), txn);
+        block.header.block_cost = Block::block_cost(&block.txns);
+        assert!(block.valid_block_cost(&ProtocolParams::default()));
+
+        block.header.block_cost += 1;
+        assert!(!block.valid_block_cost(&ProtocolParams::default()));
+
+        (1..=BLOCK_COST_LIMIT / BASE_TXN_COST).for_each(|n| {
+            let txn = Txn::unsigned_transfer(1, n as u128);
+            block.txns.insert(txn.txn_id.clone(), txn);
+        });
+        block.header.block_cost = Block::block_cost(&block.txns);
+        assert!(block.header.block_cost > BLOCK_COST_LIMIT);
+        assert!(!block.valid_block_cost(&ProtocolParams::default()));
+
+        // Testnet blocks mined before headers carried their cost aren't held
+        // to it.
+        let testnet = Activations::testnet().block_cost;
+        assert!(!Activations::active(testnet, testnet - 1));
+        assert!(Activations::active(testnet, testnet));
+        assert!(Activations::active(Activations::local().block_cost, 0));
+    }
+
+    #[test]
+    fn pruned_archive_keeps_headers_and_rebuilds_from_snapshot() {
+        let chain_path = temp_path("vrrb_test_pruned_chain.db");
+        let state_path = temp_path("vrrb_test_pruned_state.db");
+        let rebuilt_path = temp_path("vrrb_test_pruned_rebuilt.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+        let _ = fs::remove_file(&rebuilt_path);
+
+        // Blocks from keep_from up are within the finality depth of the tip.
+        let keep_from = 50;
+        let tip = keep_from + FINALITY_DEPTH - 1;
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut network_state = NetworkState::restore(&state_path);
+        let mut snapshot = None;
+        (0..=tip).for_each(|height| {
+            let mut block = test_block(height, &format!("block_{}", height));
+            block.header.last_hash = format!("block_{}", height.saturating_sub(1));
+            block.header.block_reward.miner = Some(format!("miner_{}", height % 3));
+            blockchain.append_block(&block);
+            network_state.dump(&block);
+            if height == keep_from - 1 {
+                snapshot = Some(StateSnapshot::new(
+                    network_state.db_to_ledger(),
+                    network_state.get_reward_state(),
+                    network_state.state_hash.clone(),
+                ));
+            }
+        });
+
+        assert_eq!(blockchain.prune_archive(tip).unwrap(), keep_from as usize);
+        assert_eq!(blockchain.pruned_below, Some(keep_from));
+        assert_eq!(blockchain.chain.len() as u128, tip + 1);
+        assert!(blockchain.get_block_by_hash("block_0").is_none());
+        assert!(blockchain.get_canonical_block_at(keep_from - 1).is_none());
+        assert!(blockchain.get_blocks_at(keep_from - 1).is_empty());
+        assert!(blockchain.get_canonical_block_at(keep_from).is_some());
+        assert_eq!(
+            blockchain.get_block_range(0, tip)[0].header.block_height,
+            keep_from
+        );
+
+        // The pruned height outlives a restart, and a pruned node neither
+        // exports a checkpoint nor sends its archive.
+        assert_eq!(Blockchain::open(&chain_path).pruned_below, Some(keep_from));
+        assert!(blockchain
+            .export_checkpoint(
+                &temp_path("vrrb_test_pruned.checkpoint"),
+                tip,
+                &network_state
+            )
+            .is_err());
+        assert_eq!(
+            blockchain
+                .requested_components(
+                    &NodeAuth::Full,
+                    &network_state,
+                    &StateComponent::Archive,
+                    MAX_STATE_RESPONSE_SIZE
+                )
+                .unwrap_err(),
+            DeclineReason::Pruned
+        );
+
+        let mut rebuilt = NetworkState::apply_snapshot(&snapshot.unwrap(), &rebuilt_path).unwrap();
+        (keep_from..=tip).for_each(|height| {
+            rebuilt.dump(&blockchain.get_canonical_block_at(height).unwrap());
+        });
+        assert_eq!(
+            rebuilt.db_to_ledger().to_string(),
+            network_state.db_to_ledger().to_string()
+        );
+        assert_eq!(rebuilt.get_reward_state(), network_state.get_reward_state());
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+        let _ = fs::remove_file(rebuilt_path);
+    }
+
+    #[test]
+    fn broken_header_link_is_reported_at_its_index() {
+        let chain_path = temp_path("vrrb_test_header_links_chain.db");
+        let _ = fs::remove_file(&chain_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        (0..6).for_each(|height| {
+            let mut block = test_block(height, &format!("block_{}", height));
+            block.header.last_hash = format!("block_{}", height.saturating_sub(1));
+            blockchain.append_block(&block);
+        });
+        assert_eq!(blockchain.verify_header_chain(), Ok(()));
+
+        // A header inserted out of place links to a block that isn't the one
+        // before it.
+        let mut headers: Vec<BlockHeader> = blockchain.chain.iter().cloned().collect();
+        headers[4].last_hash = "block_2".to_string();
+        blockchain.chain = headers.into_iter().collect();
+        assert_eq!(blockchain.verify_header_chain(), Err(4));
+
+        let _ = fs::remove_file(chain_path);
+    }
+
+    // Blocks 0 to tip built on each other, paying miners 0 to 2 in turn, and
+    // the ledger they produce.
+    fn audited_chain(name: &str, tip: u128) -> (Blockchain, NetworkState) {
+        let chain_path = temp_path(&format!("{}_chain.db", name));
+        let state_path = temp_path(&format!("{}_state.db", name));
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut network_state = NetworkState::restore(&state_path);
+        (0..=tip).for_each(|height| {
+            let mut block = test_block(height, &format!("block_{}", height));
+            block.header.last_hash = format!("block_{}", height.saturating_sub(1));
+            block.header.block_reward.miner = Some(format!("miner_{}", height % 3));
+            blockchain.append_block(&block);
+            network_state.dump(&block);
+        });
+
+        (blockchain, network_state)
+    }
+
+    #[test]
+    fn audit_of_a_clean_chain_is_ok() {
+        let (blockchain, network_state) = audited_chain("vrrb_test_audit_clean", 5);
+        let ledger = network_state.db_to_ledger().to_string();
+
+        let report = blockchain.audit(&network_state);
+        assert_eq!(report.finding, AuditFinding::Ok);
+        assert_eq!(report.blocks_checked, 6);
+        assert!(report.suggestion().is_none());
+        // The audit replays into a scratch ledger, the live one is untouched.
+        assert_eq!(network_state.db_to_ledger().to_string(), ledger);
+        assert!(!std::path::Path::new(&format!("{}.audit", network_state.path)).exists());
+
+        let report = blockchain.audit_with(&network_state, || true);
+        assert_eq!(report.finding, AuditFinding::Cancelled(0));
+        assert_eq!(report.blocks_checked, 0);
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn corrupted_ledger_balance_is_reported_with_its_key_and_height() {
+        let (blockchain, mut network_state) = audited_chain("vrrb_test_audit_ledger", 5);
+        let mut ledger = network_state.db_to_ledger();
+        *ledger.credits.get_mut("miner_1").unwrap() += 1;
+        network_state.update_ledger(ledger, network_state.get_reward_state());
+
+        // miner_1 was last paid for block 4.
+        let report = blockchain.audit(&network_state);
+        assert_eq!(
+            report.finding,
+            AuditFinding::LedgerDivergence {
+                height: 4,
+                keys: vec!["credits:miner_1".to_string()],
+            }
+        );
+        let suggestion = report.suggestion().unwrap();
+        assert!(suggestion.contains("replaying the archive"));
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn corrupted_block_hash_is_reported_as_a_broken_link() {
+        let (blockchain, network_state) = audited_chain("vrrb_test_audit_link", 5);
+        let mut db = blockchain.get_chain_db();
+        let mut block: Block = db.get("block_3").unwrap();
+        block.hash = "forged".to_string();
+        db.set("block_3", &block).unwrap();
+        db.dump().unwrap();
+
+        let report = blockchain.audit(&network_state);
+        assert_eq!(report.finding, AuditFinding::BrokenLink(3));
+        assert_eq!(report.blocks_checked, 3);
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_range_returns_exactly_the_requested_blocks() {
+        let (blockchain, network_state) = audited_chain("vrrb_test_block_range", 5);
+
+        let heights: Vec<u128> = blockchain
+            .get_block_range(2, 4)
+            .iter()
+            .map(|block| block.header.block_height)
+            .collect();
+        assert_eq!(heights, vec![2, 3, 4]);
+        assert_eq!(blockchain.get_block_range(4, 9).len(), 2);
+        assert!(blockchain.get_block_range(6, 8).is_empty());
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_range_is_capped_at_the_max_range() {
+        let tip = MAX_BLOCK_RANGE + 10;
+        let (blockchain, network_state) = audited_chain("vrrb_test_block_range_cap", tip);
+
+        let blocks = blockchain.get_block_range(0, tip);
+        assert_eq!(blocks.len() as u128, MAX_BLOCK_RANGE);
+        assert_eq!(
+            blocks.last().unwrap().header.block_height,
+            MAX_BLOCK_RANGE - 1
+        );
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn genesis_reward_above_height_0_is_rejected() {
+        let state_path = temp_path("vrrb_test_late_genesis_reward_state.db");
+        let _ = fs::remove_file(&state_path);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let genesis = Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap();
+        let mut block = test_block(1, "block_1");
+        block.header.last_hash = genesis.hash.clone();
+        block.header.block_reward =
+            Reward::genesis(Some("address".to_string()), &ProtocolParams::default());
+        let network_state = NetworkState::restore(&state_path);
+
+        let result = block.valid_block(&genesis, &network_state, &RewardState::start());
+        assert!(matches!(
+            result,
+            Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidBlockReward
+            })
+        ));
+
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn second_genesis_is_rejected() {
+        let chain_path = temp_path("vrrb_test_second_genesis_chain.db");
+        let state_path = temp_path("vrrb_test_second_genesis_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let genesis_with = |wallet: &WalletAccount| {
+            let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+            Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap()
+        };
+        let genesis = genesis_with(&WalletAccount::new());
+        let second = genesis_with(&WalletAccount::new());
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        blockchain
+            .process_block(&network_state, &RewardState::start(), &genesis)
+            .unwrap();
+
+        let result = blockchain.process_block(&network_state, &RewardState::start(), &second);
+        assert!(matches!(
+            result,
+            Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::InvalidBlockReward
+            })
+        ));
+        assert_eq!(
+            blockchain.genesis.as_ref().unwrap().header.claim,
+            genesis.header.claim
+        );
+        assert_eq!(blockchain.chain.len(), 1);
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn block_before_genesis_is_stashed_and_genesis_requested() {
+        let chain_path = temp_path("vrrb_test_before_genesis_chain.db");
+        let state_path = temp_path("vrrb_test_before_genesis_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        assert_eq!(blockchain.sync_start_height(), None);
+
+        let block = test_block(1, "block_1");
+        let result = blockchain.process_block(&network_state, &RewardState::start(), &block);
+        assert!(matches!(
+            result,
+            Err(InvalidBlockError {
+                details: InvalidBlockErrorReason::BlockOutOfSequence
+            })
+        ));
+        assert!(blockchain
+            .future_blocks
+            .contains_key(&block.header.last_hash));
+        assert!(blockchain.chain.is_empty());
+        assert_eq!(blockchain.sync_start_height(), Some(0));
+
+        // Once there's a genesis block the sync starts from the stashed block.
+        blockchain.append_block(&test_block(0, "block_0"));
+        assert_eq!(blockchain.sync_start_height(), Some(1));
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn full_node_sends_the_whole_state() {
+        let chain_path = temp_path("vrrb_test_state_response_chain.db");
+        let state_path = temp_path("vrrb_test_state_response_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        blockchain.append_block(&test_block(0, "block_0"));
+        let components = blockchain
+            .requested_components(
+                &NodeAuth::Full,
+                &network_state,
+                &StateComponent::All,
+                MAX_STATE_RESPONSE_SIZE,
+            )
+            .unwrap()
+            .unwrap();
+        assert!(components.genesis.is_some());
+        assert!(components.ledger.is_some());
+        assert!(components.network_state.is_some());
+
+        // Single components are sent elsewhere.
+        assert!(matches!(
+            blockchain.requested_components(
+                &NodeAuth::Light,
+                &network_state,
+                &StateComponent::Ledger,
+                MAX_STATE_RESPONSE_SIZE,
+            ),
+            Ok(None)
+        ));
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn light_or_updating_node_declines_state_request() {
+        let chain_path = temp_path("vrrb_test_state_decline_chain.db");
+        let state_path = temp_path("vrrb_test_state_decline_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        blockchain.append_block(&test_block(0, "block_0"));
+        for component in [StateComponent::All, StateComponent::Archive].iter() {
+            assert_eq!(
+                blockchain
+                    .requested_components(
+                        &NodeAuth::Light,
+                        &network_state,
+                        component,
+                        MAX_STATE_RESPONSE_SIZE
+                    )
+                    .unwrap_err(),
+                DeclineReason::NotFullNode
+            );
+        }
+
+        blockchain.updating_state = true;
+        assert_eq!(
+            blockchain
+                .requested_components(
+                    &NodeAuth::Full,
+                    &network_state,
+                    &StateComponent::All,
+                    MAX_STATE_RESPONSE_SIZE
+                )
+                .unwrap_err(),
+            DeclineReason::UpdatingState
+        );
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn oversized_state_response_is_declined() {
+        let chain_path = temp_path("vrrb_test_state_too_large_chain.db");
+        let state_path = temp_path("vrrb_test_state_too_large_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        let network_state = NetworkState::restore(&state_path);
+        blockchain.append_block(&test_block(0, "block_0"));
+        // The size is the ledger db's on disk, the state isn't serialized.
+        fs::write(&state_path, vec![0; 2048]).unwrap();
+        assert_eq!(
+            blockchain
+                .requested_components(&NodeAuth::Full, &network_state, &StateComponent::All, 1024)
+                .unwrap_err(),
+            DeclineReason::TooLarge
+        );
+
+        let (max_state_response, remaining) = Blockchain::max_state_response_from_args(vec![
+            "vrrb".to_string(),
+            MAX_STATE_RESPONSE_FLAG.to_string(),
+            "1024".to_string(),
+        ]);
+        assert_eq!(max_state_response, 1024);
+        assert_eq!(remaining, vec!["vrrb".to_string()]);
+
+        let _ = fs::remove_file(chain_path);
+        let _ = fs::remove_file(state_path);
+    }
+
+    #[test]
+    fn too_large_state_is_synced_by_block_ranges() {
+        let chain_path = temp_path("vrrb_test_range_sync_chain.db");
+        let _ = fs::remove_file(&chain_path);
+
+        let mut blockchain = Blockchain::new(&chain_path);
+        blockchain.append_block(&test_block(0, "block_0"));
+        assert!(blockchain
+            .range_sync_request("peer".to_string(), "node".to_string())
+            .is_none());
+
+        blockchain.stash_future_blocks(&test_block(250, "block_250"));
+        match blockchain.range_sync_request("peer".to_string(), "node".to_string()) {
+            Some(MessageType::GetBlockRangeMessage {
+                from_height,
+                to_height,
+                requestor,
+                requested_from,
+            }) => {
+                assert_eq!((from_height, to_height), (1, MAX_BLOCK_RANGE));
+                assert_eq!(requestor, "node");
+                assert_eq!(requested_from, "peer");
+            }
+            message => panic!("expected a block range request, got {:?}", message),
+        }
+
+        let _ = fs::remove_file(chain_path);
+    }
+
+    #[test]
+    fn known_block_is_not_validated_again() {
+        let (mut blockchain, network_state) = checkpoint_chain("vrrb_test_known_block");
+        let tip = blockchain.tip().unwrap().clone();
+        assert_eq!(
+            blockchain
+                .process_block(&network_state, &RewardState::start(), &tip)
+                .unwrap(),
+            BlockOutcome::AlreadyKnown
+        );
+        assert_eq!(blockchain.validations, 0);
+
+        // Unsigned, so it's remembered as invalid rather than stashed.
+        let mut invalid = test_block(2, "block_2");
+        invalid.header.last_hash = tip.hash.clone();
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &invalid)
+            .is_err());
+        assert_eq!(blockchain.validations, 1);
+        assert!(blockchain
+            .invalid
+            .contains_key(&Blockchain::block_digest(&invalid)));
+        assert!(blockchain.future_blocks.is_empty());
+        assert_eq!(
+            blockchain
+                .process_block(&network_state, &RewardState::start(), &invalid)
+                .unwrap(),
+            BlockOutcome::AlreadyKnown
+        );
+        assert_eq!(blockchain.validations, 1);
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_with_another_hash_at_a_known_height_is_validated() {
+        let (mut blockchain, network_state) = checkpoint_chain("vrrb_test_unknown_block");
+        let tip = blockchain.tip().unwrap().clone();
+        let mut fork = test_block(1, "block_1_fork");
+        fork.header.last_hash = tip.header.last_hash.clone();
+        fork.header.claim = Claim::new("other".to_string(), "address".to_string(), 1);
+
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &fork)
+            .is_err());
+        assert_eq!(blockchain.validations, 1);
+        assert_eq!(blockchain.tip().unwrap().hash, tip.hash);
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn invalid_block_does_not_hide_a_block_sent_with_its_hash() {
+        let (mut blockchain, network_state) = checkpoint_chain("vrrb_test_invalid_digest");
+        let tip = blockchain.tip().unwrap().clone();
+        let mut invalid = test_block(2, "block_2");
+        invalid.header.last_hash = tip.hash.clone();
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &invalid)
+            .is_err());
+
+        // Another block claiming the hash is still validated.
+        let mut other = invalid.clone();
+        other.header.timestamp = 1;
+        assert!(blockchain.is_known(&invalid));
+        assert!(!blockchain.is_known(&other));
+        assert!(blockchain
+            .process_block(&network_state, &RewardState::start(), &other)
+            .is_err());
+        assert_eq!(blockchain.validations, 2);
+
+        let _ = fs::remove_file(&blockchain.chain_db);
+        let _ = fs::remove_file(&network_state.path);
+    }
+
+    #[test]
+    fn block_caches_are_bounded() {
+        let mut blockchain = Blockchain::new(&temp_path("vrrb_test_block_caches.db"));
+        (0..=INVALID_CACHE_SIZE).for_each(|n| {
+            let block = test_block(n as u128, &format!("block_{}", n));
+            blockchain.cache_block(&block);
+            blockchain.remember_invalid(&block);
+        });
+
+        assert_eq!(blockchain.block_cache.len(), BLOCK_CACHE_SIZE);
+        assert_eq!(blockchain.invalid.len(), INVALID_CACHE_SIZE);
+        assert!(!blockchain.is_known(&test_block(0, "block_0")));
+        let newest = format!("block_{}", INVALID_CACHE_SIZE);
+        assert!(blockchain.block_cache.contains_key(&newest));
+        assert!(blockchain
+            .invalid
+            .contains_key(&Blockchain::block_digest(&test_block(
+                INVALID_CACHE_SIZE as u128,
+                &newest
+            ))));
+        assert!(!blockchain
+            .block_cache
+            .contains_key(&format!("block_{}", INVALID_CACHE_SIZE - BLOCK_CACHE_SIZE)));
+    }
+
+    #[test]
+    fn tip_built_on_wins_a_fork() {
+        let chain_path = temp_path("vrrb_test_fork_events_chain.db");
+        let _ = fs::remove_file(&chain_path);
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut parent = test_block(0, "parent");
+        parent.header.last_hash = "genesis_parent".to_string();
+        blockchain.append_block(&parent);
+
+        let mut canonical = test_block(1, "canonical");
+        let mut competing = test_block(1, "competing");
+        canonical.header.last_hash = parent.hash.clone();
+        canonical.header.timestamp = 10_000_000;
+        competing.header.last_hash = parent.hash.clone();
+        competing.header.timestamp = 20_000_000;
+        blockchain.append_block(&canonical);
+
+        // A competing block that isn't signed by its claim isn't recorded.
+        blockchain.record_competing_tip(&competing);
+        assert!(blockchain.fork_events().is_empty());
+
+        sign_block(&mut competing, &WalletAccount::new());
+        blockchain.record_competing_tip(&competing);
+        blockchain.record_competing_tip(&competing);
+
+        let events = blockchain.fork_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].block_height, 1);
+        assert_eq!(
+            events[0].tips,
+            vec!["canonical".to_string(), "competing".to_string()]
+        );
+        assert!(events[0].winner.is_none() && events[0].losers.is_empty());
+
+        let mut next = test_block(2, "next");
+        next.header.last_hash = canonical.hash.clone();
+        next.header.timestamp = 50_000_000;
+        blockchain.append_block(&next);
+
+        let events = blockchain.fork_events();
+        assert_eq!(events[0].winner, Some("canonical".to_string()));
+        assert_eq!(events[0].losers, vec!["competing".to_string()]);
+        assert_eq!(events[0].resolution_delay, Some(40_000_000));
+        assert_eq!(
+            events[0].to_string(),
+            "height 1: tips canonical, competing, winner canonical after 40ms, losers competing"
+        );
+
+        let _ = fs::remove_file(chain_path);
+    }
+
+    #[test]
+    fn competing_tips_are_bounded() {
+        let chain_path = temp_path("vrrb_test_bounded_forks_chain.db");
+        let _ = fs::remove_file(&chain_path);
+        let mut blockchain = Blockchain::new(&chain_path);
+        let wallet = WalletAccount::new();
+        blockchain.append_block(&test_block(0, "canonical_0"));
+        (1..=MAX_COMPETING_TIPS as u128 + 10).for_each(|block_height| {
+            let last_hash = format!("canonical_{}", block_height - 1);
+            let mut canonical = test_block(block_height, &format!("canonical_{}", block_height));
+            let mut competing = test_block(block_height, &format!("competing_{}", block_height));
+            canonical.header.last_hash = last_hash.clone();
+            competing.header.last_hash = last_hash;
+            sign_block(&mut competing, &wallet);
+            blockchain.append_block(&canonical);
+            blockchain.record_competing_tip(&competing);
+        });
+
+        let events = blockchain.fork_events();
+        assert_eq!(events.len(), MAX_COMPETING_TIPS);
+        assert_eq!(events[0].block_height, 11);
+        assert!(blockchain.fork_resolutions.len() <= MAX_COMPETING_TIPS);
+
+        let _ = fs::remove_file(chain_path);
+    }
 }
@@ -0,0 +1,741 @@
+use crate::block::Block;
+use crate::blockchain::{
+    format_block_height, BlockOutcome, Blockchain, DeclineReason, InvalidBlockErrorReason,
+    StateComponent, MAX_STATE_RESPONSE_SIZE,
+};
+use crate::clock::{Clock, SharedClock};
+use crate::handler::{send_or_dead_letter, BoundedSender};
+use crate::logging::BLOCKCHAIN_TARGET;
+use crate::metrics::BlockMetrics;
+use crate::miner::{MiningCancel, SECOND};
+use crate::network::command_utils::Command;
+use crate::network::message_types::MessageType;
+use crate::network::node::NodeAuth;
+use crate::network::peer_score::PeerScores;
+use crate::network::protocol::{EventLog, VrrbNetworkEvent};
+use crate::params::ProtocolParams;
+use crate::reward::RewardState;
+use crate::state::{Components, Ledger, NetworkState, SharedNetworkState};
+use crate::txn::Txn;
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc::UnboundedSender;
+
+// How long, in seconds, a state sync waits on its peer before giving up on it.
+pub const STATE_SYNC_TIMEOUT: u128 = 60;
+// How long, in seconds, a peer waits for another balance proof after one.
+pub const BALANCE_PROOF_INTERVAL: u128 = 1;
+
+/// Where the blockchain handler sends what it asks of the node's other
+/// threads, and the messages for its peers.
+#[derive(Debug, Clone)]
+pub struct BlockchainSenders {
+    pub miner: UnboundedSender<Command>,
+    pub swarm: UnboundedSender<Command>,
+    pub blockchain: UnboundedSender<Command>,
+    pub state: BoundedSender<Command>,
+    pub wallet: UnboundedSender<Command>,
+}
+
+/// The blockchain thread's handling of the commands that change the chain
+/// and the ledger, sync them from peers or answer peers from them. The node's
+/// thread and `vrrb replay` both feed their commands to it, so a replay runs
+/// the code the recorded node did. Commands that only reach the terminal are
+/// left to the node.
+pub struct BlockchainHandler {
+    pub blockchain: Blockchain,
+    pub network_state: SharedNetworkState,
+    pub reward_state: RewardState,
+    // The params the node is configured with, a state on others is refused.
+    pub params: ProtocolParams,
+    pub node_id: String,
+    // Signs what the node tells peers on its own behalf, node_id is its peer id.
+    node_key: Keypair,
+    pub node_type: NodeAuth,
+    pub max_state_response: usize,
+    pub peer_scores: PeerScores,
+    // Propagation and validation times of the blocks received from peers.
+    pub block_metrics: BlockMetrics,
+    pub mining_cancel: MiningCancel,
+    pub clock: SharedClock,
+    // The node's event log, the blocks finalized are logged to it.
+    pub events: Option<EventLog>,
+    senders: BlockchainSenders,
+    sync_peer: Option<String>,
+    sync_requested_at: u128,
+    // Peers that declined a state request as too large, they're asked for
+    // block ranges instead.
+    range_sync_peers: HashSet<String>,
+    // When each peer was last sent a balance proof, within the last
+    // BALANCE_PROOF_INTERVAL.
+    proofs_sent: HashMap<String, u128>,
+}
+
+/// What the handler did with a command.
+#[derive(Debug)]
+pub enum Handled {
+    // The block was confirmed and applied to the ledger.
+    Applied(Block),
+    Done,
+    // Not one of the handler's commands, it's handed back to the caller.
+    Unhandled(Command),
+}
+
+impl BlockchainHandler {
+    /// A handler for a node with the system clock that sends whole states up
+    /// to MAX_STATE_RESPONSE_SIZE, the node sets its own on the pub fields.
+    pub fn new(
+        blockchain: Blockchain,
+        network_state: SharedNetworkState,
+        reward_state: RewardState,
+        node_key: Keypair,
+        node_type: NodeAuth,
+        senders: BlockchainSenders,
+    ) -> BlockchainHandler {
+        BlockchainHandler {
+            blockchain,
+            network_state,
+            reward_state,
+            params: ProtocolParams::default(),
+            node_id: PeerId::from(node_key.public()).to_string(),
+            node_key,
+            node_type,
+            max_state_response: MAX_STATE_RESPONSE_SIZE,
+            peer_scores: PeerScores::new(),
+            block_metrics: BlockMetrics::default(),
+            mining_cancel: MiningCancel::new(),
+            clock: SharedClock::default(),
+            events: None,
+            senders,
+            sync_peer: None,
+            sync_requested_at: 0,
+            range_sync_peers: HashSet::new(),
+            proofs_sent: HashMap::new(),
+        }
+    }
+
+    /// Gives up on a state sync whose peer hasn't answered in
+    /// STATE_SYNC_TIMEOUT, so the next future block can start another.
+    pub fn check_sync_timeout(&mut self) {
+        if !self.blockchain.updating_state {
+            return;
+        }
+        if let Some(peer) = self.sync_peer.clone() {
+            if self.clock.now().saturating_sub(self.sync_requested_at) / SECOND > STATE_SYNC_TIMEOUT
+            {
+                warn!(target: BLOCKCHAIN_TARGET, "state sync timed out peer={}", peer);
+                self.peer_scores.record_timeout(&peer);
+                self.sync_peer = None;
+                self.blockchain.updating_state = false;
+            }
+        }
+    }
+
+    pub fn handle(&mut self, command: Command) -> Handled {
+        match command {
+            Command::PendingBlock(block, sender_id) => {
+                if self.pending_block(&block, sender_id) {
+                    return Handled::Applied(block);
+                }
+            }
+            Command::GetStateComponents(requestor, component) => {
+                self.get_state_components(requestor, component)
+            }
+            // A peer rejected a block of this node's as stale, its chain
+            // is ahead, so the blocks up to its tip are requested.
+            Command::BlockHeightRejected(tip_height, peer) => {
+                if let Some(message) =
+                    self.blockchain
+                        .catch_up_request(tip_height, peer, self.node_id.clone())
+                {
+                    self.send_to_peers(message, "block range request");
+                }
+            }
+            Command::StateRequestDeclined(peer, reason) => {
+                self.state_request_declined(peer, reason)
+            }
+            Command::StateUpdateComponents(components) => self.update_components(components),
+            Command::ProcessBacklog => self.process_backlog(),
+            Command::InvalidStateComponents => {
+                if let Some(peer) = self.sync_peer.take() {
+                    warn!(
+                        target: BLOCKCHAIN_TARGET,
+                        "state sync failed peer={} reason=corrupt components",
+                        peer
+                    );
+                    self.peer_scores.record_bad_response(&peer);
+                }
+                self.blockchain.updating_state = false;
+            }
+            Command::StateUpdateCompleted(network_state) => {
+                self.network_state.replace(network_state);
+                self.state_changed();
+            }
+            Command::ClaimAbandoned(_, claim) => {
+                info!(
+                    target: BLOCKCHAIN_TARGET,
+                    "claim abandoned hash={} pubkey={}",
+                    claim.hash,
+                    claim.pubkey
+                );
+                self.network_state.write().abandoned_claim(claim.hash);
+                self.state_changed();
+            }
+            Command::SendMissingBlock(block_last_hash, requestor) => {
+                if let Some(block) = self.blockchain.get_canonical_child(&block_last_hash) {
+                    let message = MessageType::MissingBlock {
+                        block,
+                        requestor,
+                        sender_id: self.node_id.clone(),
+                    };
+                    self.send_to_peers(message, "MissingBlock message");
+                }
+            }
+            // Sent as one message, envelope::encode chunks it if the
+            // blocks are too large to publish at once.
+            Command::SendBlockRange(from_height, to_height, requestor) => {
+                let message = MessageType::BlockRangeMessage {
+                    blocks: self.blockchain.get_block_range(from_height, to_height),
+                    requestor,
+                    sender_id: self.node_id.clone(),
+                };
+                self.send_to_peers(message, "BlockRange message");
+            }
+            Command::SendBalanceProof(address, requestor) => {
+                let now = self.clock.now();
+                self.proofs_sent.retain(|_, sent_at| {
+                    now.saturating_sub(*sent_at) / SECOND < BALANCE_PROOF_INTERVAL
+                });
+                let light_client = matches!(self.node_type, NodeAuth::Light | NodeAuth::UltraLight);
+                let proof = if light_client || self.proofs_sent.contains_key(&requestor) {
+                    None
+                } else {
+                    self.network_state.read().balance_proof(&address)
+                };
+                if let Some(proof) = proof {
+                    self.proofs_sent.insert(requestor.clone(), now);
+                    let message = MessageType::BalanceProofResponse {
+                        proof,
+                        requestor,
+                        sender_id: self.node_id.clone(),
+                    };
+                    self.send_to_peers(message, "BalanceProof message");
+                }
+            }
+            command => return Handled::Unhandled(command),
+        }
+
+        Handled::Done
+    }
+
+    // Validates a block received from `sender_id` and applies it if it's
+    // confirmed, returning whether it was.
+    fn pending_block(&mut self, block: &Block, sender_id: String) -> bool {
+        let received_at = self.clock.now();
+        if sender_id != self.node_id {
+            self.peer_scores.add_peer(&sender_id);
+        }
+        if self.blockchain.updating_state {
+            self.blockchain.stash_future_blocks(block);
+            return false;
+        }
+        // Bound first so the read lock is released before the block is dumped.
+        let processed =
+            self.blockchain
+                .process_block(&self.network_state.read(), &self.reward_state, block);
+        if sender_id != self.node_id {
+            self.block_metrics
+                .record(block, received_at, self.clock.now());
+        }
+        match processed {
+            // Another copy of a block confirmed or rejected before.
+            Ok(BlockOutcome::AlreadyKnown) => false,
+            Err(e) => {
+                match e.details {
+                    InvalidBlockErrorReason::BlockOutOfSequence => {
+                        // The block is stashed in future_blocks and the state
+                        // requested once, updating_state keeps the future blocks
+                        // that follow, which are just as invalid, from asking again.
+                        // A node without a genesis block syncs from the start.
+                        if !self.blockchain.updating_state {
+                            if let Some(lowest_block) = self.blockchain.sync_start_height() {
+                                self.start_state_sync(&sender_id, lowest_block);
+                            }
+                        }
+                    }
+                    InvalidBlockErrorReason::SelfConflict => {
+                        // The claim's owner mined the height twice. The
+                        // claim is slashed once a block carries the evidence.
+                        if let Some(evidence) = self.blockchain.slashing_evidence(block) {
+                            let txn = Txn::slashing_evidence(&evidence);
+                            if let Err(e) =
+                                self.senders.miner.send(Command::ProcessTxn(txn.clone()))
+                            {
+                                println!("Error sending slashing evidence to miner: {:?}", e);
+                            }
+                            let message = MessageType::TxnMessage {
+                                txn,
+                                sender_id: self.node_id.clone(),
+                            };
+                            self.send_to_peers(message, "slashing evidence");
+                        }
+                    }
+                    InvalidBlockErrorReason::InvalidBlockHeight => {
+                        self.blockchain
+                            .handle_invalid_height(block, sender_id.clone(), self.node_id.clone())
+                            .into_iter()
+                            .for_each(|message| {
+                                if let Err(e) = self
+                                    .senders
+                                    .swarm
+                                    .send(Command::SendMessage(message.as_bytes()))
+                                {
+                                    println!(
+                                        "Error sending block height response to swarm sender: {:?}",
+                                        e
+                                    );
+                                }
+                            });
+                    }
+                    _ => {
+                        if !self.blockchain.updating_state {
+                            // A node without a tip syncs from the start.
+                            let lowest_block = self.blockchain.tip_height().unwrap_or(0);
+                            if block.header.block_height > lowest_block + 1 {
+                                self.start_state_sync(&sender_id, lowest_block);
+                            } else {
+                                // Miner is out of consensus tell them to update their state.
+                                let message = MessageType::InvalidBlockMessage {
+                                    block_height: block.header.block_height,
+                                    reason: e.details,
+                                    miner_id: sender_id,
+                                    sender_id: self.node_id.clone(),
+                                    tip_height: None,
+                                };
+                                self.send_to_peers(message, "state update request");
+
+                                self.blockchain.remember_invalid(block);
+                            }
+                        }
+                    }
+                }
+
+                if let Err(_) = self
+                    .senders
+                    .miner
+                    .send(Command::InvalidBlock(block.clone()))
+                {
+                    println!("Error sending command to receiver");
+                };
+                false
+            }
+            Ok(BlockOutcome::Confirmed) => {
+                self.network_state.dump(block);
+                if let Some(confirmations) = self.blockchain.confirmations(&block.hash) {
+                    info!(
+                        target: BLOCKCHAIN_TARGET,
+                        "Confirmed block {}",
+                        format_block_height(block.header.block_height, Some(confirmations))
+                    );
+                }
+                self.send_finalized();
+                // Apply a stashed block that builds on this one, or keep
+                // filling the gap below the stashed blocks by hash.
+                if let Some(next) = self.blockchain.future_blocks.remove(&block.hash) {
+                    if let Err(e) = send_or_dead_letter(
+                        &self.senders.blockchain,
+                        Command::PendingBlock(next, sender_id),
+                    ) {
+                        println!("Error sending stashed block to blockchain: {:?}", e);
+                    }
+                } else if self.blockchain.has_gap_above_tip() && sender_id != self.node_id {
+                    let message = MessageType::NeedBlockMessage {
+                        block_last_hash: block.hash.clone(),
+                        sender_id: self.node_id.clone(),
+                        requested_from: sender_id,
+                    };
+                    self.send_to_peers(message, "NeedBlock message");
+                }
+                self.mining_cancel
+                    .block_confirmed(block.header.block_height);
+                if let Err(_) =
+                    send_or_dead_letter(&self.senders.miner, Command::ConfirmedBlock(block.clone()))
+                {
+                    println!("Error sending command to receiver");
+                }
+                self.state_changed();
+                true
+            }
+        }
+    }
+
+    fn get_state_components(&mut self, requestor: String, component: StateComponent) {
+        let requested = self.blockchain.requested_components(
+            &self.node_type,
+            &self.network_state.read(),
+            &component,
+            self.max_state_response,
+        );
+        match requested {
+            Ok(Some(components)) => {
+                if let Err(e) = self
+                    .senders
+                    .state
+                    .send(Command::RequestedComponents(requestor, components))
+                {
+                    println!(
+                        "Error sending requested components to state receiver: {:?}",
+                        e
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(reason) => {
+                info!(
+                    target: BLOCKCHAIN_TARGET,
+                    "declined state request peer={} reason={}",
+                    requestor,
+                    reason
+                );
+                match MessageType::state_request_declined(reason, requestor, &self.node_key) {
+                    Some(message) => self.send_to_peers(message, "state request decline"),
+                    None => println!("Error signing state request decline"),
+                }
+            }
+        }
+    }
+
+    fn state_request_declined(&mut self, peer: String, reason: DeclineReason) {
+        if self.sync_peer.as_deref() != Some(peer.as_str()) {
+            return;
+        }
+        warn!(
+            target: BLOCKCHAIN_TARGET,
+            "state sync declined peer={} reason={}",
+            peer,
+            reason
+        );
+        self.peer_scores.record_decline(&peer);
+        self.sync_peer = None;
+        self.blockchain.updating_state = false;
+        // The peer has the blocks its state was built from, they're applied
+        // on the tip like any others.
+        if reason == DeclineReason::TooLarge {
+            self.range_sync_peers.insert(peer.clone());
+            self.request_blocks(peer);
+            return;
+        }
+        // Ask the next peer that hasn't declined yet rather than waiting
+        // for another future block to restart the sync.
+        let lowest_block = self
+            .blockchain
+            .sync_start_height()
+            .unwrap_or_else(|| self.blockchain.tip_height().unwrap_or(0));
+        if let Some(next) = self
+            .peer_scores
+            .best_peer()
+            .filter(|next| self.peer_scores.declines(next) == 0)
+        {
+            self.request_state(next, lowest_block);
+        }
+    }
+
+    fn update_components(&mut self, components: Components) {
+        let new_network_state = components
+            .network_state
+            .map(|bytes| NetworkState::from_bytes(&bytes));
+        // A peer on other params is on another network, neither its state nor
+        // the peer is taken.
+        if let Some(new_network_state) = &new_network_state {
+            if new_network_state.reward_state.params != self.params {
+                warn!(
+                    target: BLOCKCHAIN_TARGET,
+                    "synced state refused, it's on other protocol params peer={:?}",
+                    self.sync_peer
+                );
+                if let Some(peer) = self.sync_peer.take() {
+                    self.peer_scores.drop_peer(&peer);
+                    self.range_sync_peers.remove(&peer);
+                }
+                self.blockchain.updating_state = false;
+                return;
+            }
+        }
+        let new_blockchain = components
+            .blockchain
+            .map(|bytes| Blockchain::from_bytes(&bytes));
+        // Syncing a peer's chain is the only way a node's chain
+        // is unwound, it never unwinds a final block.
+        if let Some(new_blockchain) = &new_blockchain {
+            if !self.blockchain.can_switch_to(new_blockchain) {
+                warn!(
+                    target: BLOCKCHAIN_TARGET,
+                    "synced chain refused, it unwinds final blocks fork_height={:?}",
+                    self.blockchain.fork_height(new_blockchain)
+                );
+                if let Some(peer) = self.sync_peer.take() {
+                    self.peer_scores.record_bad_response(&peer);
+                }
+                self.blockchain.updating_state = false;
+                return;
+            }
+        }
+        if let Some(peer) = self.sync_peer.take() {
+            self.peer_scores.record_success(&peer);
+        }
+        if let Some(bytes) = components.genesis {
+            self.blockchain.genesis = Some(Block::from_bytes(&bytes))
+        }
+
+        if let Some(bytes) = components.child {
+            self.blockchain.child = Some(Block::from_bytes(&bytes))
+        }
+        if let Some(bytes) = components.parent {
+            self.blockchain.parent = Some(Block::from_bytes(&bytes))
+        }
+        if let Some(mut new_blockchain) = new_blockchain {
+            new_blockchain.future_blocks = self.blockchain.clone().future_blocks;
+            new_blockchain.chain_db = self.blockchain.clone().chain_db;
+            // The bodies left in the chain db are this node's.
+            new_blockchain.pruned_below = self.blockchain.pruned_below;
+            self.blockchain = new_blockchain;
+        }
+        if let Some(mut new_network_state) = new_network_state {
+            new_network_state.path = self.network_state.read().path.clone();
+            self.reward_state = new_network_state.reward_state;
+            self.network_state.replace(new_network_state);
+        }
+
+        if let Some(bytes) = components.ledger {
+            let new_ledger = Ledger::from_bytes(&bytes);
+            self.network_state
+                .write()
+                .update_ledger(new_ledger, self.reward_state);
+        }
+
+        if let Some(bytes) = components.archive {
+            let mut new_db = self.blockchain.chain_db_from_bytes(&bytes);
+            if let Err(e) = new_db.dump() {
+                println!("Error dumping db update: {:?}", e);
+            }
+        }
+
+        if let Err(e) = send_or_dead_letter(&self.senders.blockchain, Command::ProcessBacklog) {
+            println!(
+                "Error sending process backlog command to blockchain receiver: {:?}",
+                e
+            );
+        }
+    }
+
+    fn process_backlog(&mut self) {
+        while let Some((_, block)) = self.blockchain.future_blocks.pop_front() {
+            let processed = self.blockchain.process_block(
+                &self.network_state.read(),
+                &self.reward_state,
+                &block,
+            );
+            match processed {
+                // Synced with the state or gossiped while stashed.
+                Ok(BlockOutcome::AlreadyKnown) => {}
+                Err(e) => {
+                    println!("Error trying to process backlogged future blocks: {:?}", e);
+                }
+                Ok(BlockOutcome::Confirmed) => {
+                    self.network_state.dump(&block);
+                    self.send_finalized();
+                    self.mining_cancel
+                        .block_confirmed(block.header.block_height);
+                    if let Err(e) = send_or_dead_letter(
+                        &self.senders.miner,
+                        Command::ConfirmedBlock(block.clone()),
+                    ) {
+                        println!("Error sending confirmed backlog block to miner: {:?}", e);
+                    }
+                }
+            }
+        }
+        info!(
+            target: BLOCKCHAIN_TARGET,
+            "backlog processed height={}",
+            self.blockchain.tip_height().unwrap_or(0)
+        );
+        if let Some(height) = self.blockchain.tip_height() {
+            self.mining_cancel.block_confirmed(height);
+        }
+        // A chain taken from a peer is only as good as its links, the
+        // node stops rather than run on a broken one and won't start
+        // on it again.
+        if let Err(index) = self.blockchain.verify_header_chain() {
+            error!(
+                target: BLOCKCHAIN_TARGET,
+                "header chain broken after state sync index={} height={}, stopping",
+                index,
+                self.blockchain
+                    .chain
+                    .iter()
+                    .nth(index)
+                    .map_or(0, |header| header.block_height)
+            );
+            if let Err(e) = send_or_dead_letter(&self.senders.wallet, Command::Quit) {
+                println!("Error sending Quit to the wallet: {:?}", e);
+            }
+            self.blockchain.updating_state = false;
+            return;
+        }
+        self.state_changed();
+        self.blockchain.updating_state = false;
+    }
+
+    // Asks a peer picked over the one whose block set it off for the state
+    // from `lowest_block` up.
+    fn start_state_sync(&mut self, triggered_by: &str, lowest_block: u128) {
+        if let Some(peer) = self.peer_scores.select_sync_peer(triggered_by) {
+            self.request_state(peer, lowest_block);
+        }
+    }
+
+    fn request_state(&mut self, peer: String, lowest_block: u128) {
+        if self.range_sync_peers.contains(&peer) {
+            self.request_blocks(peer);
+            return;
+        }
+        let message = MessageType::GetNetworkStateMessage {
+            sender_id: self.node_id.clone(),
+            requested_from: peer.clone(),
+            requestor_node_type: self.node_type.clone(),
+            lowest_block,
+            component: StateComponent::All,
+        };
+        self.send_to_peers(message, "state update request");
+        info!(
+            target: BLOCKCHAIN_TARGET,
+            "state sync started peer={} lowest_block={}",
+            peer,
+            lowest_block
+        );
+        self.sync_peer = Some(peer);
+        self.sync_requested_at = self.clock.now();
+        self.blockchain.updating_state = true;
+    }
+
+    // Asks a peer for the blocks missing between the tip and the stashed
+    // blocks, the node isn't syncing state meanwhile.
+    fn request_blocks(&mut self, peer: String) {
+        if let Some(message) = self
+            .blockchain
+            .range_sync_request(peer.clone(), self.node_id.clone())
+        {
+            info!(
+                target: BLOCKCHAIN_TARGET,
+                "block range sync started peer={}",
+                peer
+            );
+            self.send_to_peers(message, "block range request");
+        }
+    }
+
+    // Logs the blocks finalized by the last confirmed block and tells the
+    // miner which txns they carried.
+    fn send_finalized(&mut self) {
+        let finalized = self.blockchain.newly_finalized();
+        for (hash, height) in finalized.iter() {
+            info!(
+                target: BLOCKCHAIN_TARGET,
+                "Block finalized at height {}: {}",
+                height,
+                hash
+            );
+            if let Some(events) = &self.events {
+                events.log(VrrbNetworkEvent::BlockFinalized {
+                    block_hash: hash.clone(),
+                    block_height: *height,
+                });
+            }
+        }
+        let txn_ids = self.blockchain.finalized_txns(&finalized);
+        if !txn_ids.is_empty() {
+            if let Err(e) = self.senders.miner.send(Command::TxnsFinalized(txn_ids)) {
+                println!("Error sending finalized txns to miner: {:?}", e);
+            }
+        }
+    }
+
+    fn state_changed(&self) {
+        if let Err(e) = send_or_dead_letter(&self.senders.miner, Command::StateChanged) {
+            println!("Error sending updated network state to miner: {:?}", e);
+        }
+    }
+
+    fn send_to_peers(&self, message: MessageType, what: &str) {
+        if let Err(e) = self
+            .senders
+            .swarm
+            .send(Command::SendMessage(message.as_bytes()))
+        {
+            println!("Error sending {} to swarm sender: {:?}", what, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::FINALITY_DEPTH;
+    use crate::network::protocol::read_from_json;
+    use crate::replay::ReplayNode;
+    use crate::test_utils::{temp_path, test_block};
+    use std::fs;
+
+    // Makes the block at `block_height` the tip, as far as finality goes.
+    fn confirm(handler: &mut BlockchainHandler, block_height: u128) {
+        let hash = format!("block_{}", block_height);
+        handler
+            .blockchain
+            .block_heights
+            .insert(hash.clone(), block_height);
+        handler.blockchain.child = Some(test_block(block_height, &hash));
+    }
+
+    #[test]
+    fn finalized_blocks_are_logged_exactly_once() {
+        let dir = std::env::temp_dir().join("vrrb_test_finalized_events");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let events_path = temp_path("vrrb_test_finalized_events.json");
+        let _ = fs::remove_file(&events_path);
+        let mut handler = ReplayNode::new(&dir).handler;
+        handler.events = Some(EventLog::open(&events_path));
+
+        (0..FINALITY_DEPTH).for_each(|height| confirm(&mut handler, height));
+        handler.send_finalized();
+        confirm(&mut handler, FINALITY_DEPTH);
+        handler.send_finalized();
+        handler.send_finalized();
+        confirm(&mut handler, FINALITY_DEPTH + 1);
+        handler.send_finalized();
+        handler.send_finalized();
+
+        handler.events.as_ref().unwrap().flush();
+        let finalized: Vec<(String, u128)> = read_from_json(&events_path)
+            .unwrap()
+            .into_iter()
+            .filter_map(|event| match event {
+                VrrbNetworkEvent::BlockFinalized {
+                    block_hash,
+                    block_height,
+                } => Some((block_hash, block_height)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            finalized,
+            vec![("block_0".to_string(), 0), ("block_1".to_string(), 1)]
+        );
+
+        let _ = fs::remove_file(events_path);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
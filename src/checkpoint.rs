@@ -0,0 +1,176 @@
+use crate::block::Block;
+use crate::params::ProtocolParams;
+use crate::reward::Reward;
+use crate::state::StateSnapshot;
+use crate::utils::take_flag;
+use serde::{Deserialize, Serialize};
+use sha256::digest_bytes;
+use std::error::Error;
+use std::fmt;
+
+pub const BOOTSTRAP_FLAG: &str = "--bootstrap";
+
+/// A published genesis block, optionally followed by the first blocks of the
+/// chain and a snapshot of the ledger at the last of them, used to start new
+/// nodes on a network without syncing from a peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub network_id: String,
+    pub blocks: Vec<Block>,
+    pub snapshot: Option<StateSnapshot>,
+    pub hash: String,
+}
+
+#[derive(Debug)]
+pub struct CheckpointError(pub String);
+
+impl Checkpoint {
+    pub fn new(
+        network_id: &str,
+        blocks: Vec<Block>,
+        snapshot: Option<StateSnapshot>,
+    ) -> Checkpoint {
+        let hash = Checkpoint::integrity_hash(network_id, &blocks, &snapshot);
+        Checkpoint {
+            network_id: network_id.to_string(),
+            blocks,
+            snapshot,
+            hash,
+        }
+    }
+
+    /// Hashes the network id, the blocks and the snapshot's own hash, which
+    /// covers the canonical hash of its ledger. Txn
+    /// validator votes are left out, they are kept in a HashMap and don't
+    /// serialize in a stable order.
+    pub fn integrity_hash(
+        network_id: &str,
+        blocks: &[Block],
+        snapshot: &Option<StateSnapshot>,
+    ) -> String {
+        let blocks: Vec<String> = blocks
+            .iter()
+            .map(|block| {
+                let mut block = block.clone();
+                block.txns.iter_mut().for_each(|(_, txn)| {
+                    txn.validators.clear();
+                });
+                block.to_string()
+            })
+            .collect();
+
+        digest_bytes(
+            format!(
+                "{},{},{:?}",
+                network_id,
+                blocks.join(","),
+                snapshot.as_ref().map(|snapshot| snapshot.hash.clone())
+            )
+            .as_bytes(),
+        )
+    }
+
+    /// Checks the checkpoint's integrity, that it belongs to `network_id`, that
+    /// it starts with a genesis block paying the genesis reward of `params` and
+    /// that every block after it extends the previous one with the reward it
+    /// set. Its snapshot has to be on `params` too.
+    pub fn verify(&self, network_id: &str, params: &ProtocolParams) -> Result<(), CheckpointError> {
+        if Checkpoint::integrity_hash(&self.network_id, &self.blocks, &self.snapshot) != self.hash {
+            return Err(CheckpointError(
+                "checkpoint failed its integrity check".to_string(),
+            ));
+        }
+
+        if self.network_id != network_id {
+            return Err(CheckpointError(format!(
+                "checkpoint is for network {}, not {}",
+                self.network_id, network_id
+            )));
+        }
+
+        let genesis = if let Some(genesis) = self.blocks.first() {
+            genesis
+        } else {
+            return Err(CheckpointError(
+                "checkpoint has no genesis block".to_string(),
+            ));
+        };
+
+        if genesis.header.block_height != 0
+            || genesis.hash != Block::genesis_hash(&genesis.header.last_hash)
+        {
+            return Err(CheckpointError("invalid genesis block".to_string()));
+        }
+
+        let genesis_reward = Reward::genesis(None, params);
+        if genesis.header.block_reward.category != genesis_reward.category
+            || genesis.header.block_reward.amount != genesis_reward.amount
+        {
+            return Err(CheckpointError("invalid genesis reward".to_string()));
+        }
+
+        for pair in self.blocks.windows(2) {
+            let (last_block, block) = (&pair[0], &pair[1]);
+            if block.header.block_height != last_block.header.block_height + 1
+                || block.header.last_hash != last_block.hash
+            {
+                return Err(CheckpointError(format!(
+                    "block {} doesn't extend block {}",
+                    block.header.block_height, last_block.header.block_height
+                )));
+            }
+
+            let reward = &last_block.header.next_block_reward;
+            if block.header.block_reward.category != reward.category
+                || block.header.block_reward.amount != reward.amount
+            {
+                return Err(CheckpointError(format!(
+                    "invalid block reward in block {}",
+                    block.header.block_height
+                )));
+            }
+        }
+
+        if let Some(snapshot) = &self.snapshot {
+            if !snapshot.verify() {
+                return Err(CheckpointError(
+                    "ledger snapshot failed its integrity check".to_string(),
+                ));
+            }
+            if snapshot.reward_state.params != *params {
+                return Err(CheckpointError(
+                    "ledger snapshot is on other protocol params".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the bootstrap flag from the command line arguments and returns
+    /// the checkpoint path along with the remaining arguments.
+    pub fn path_from_args(mut args: Vec<String>) -> (Option<String>, Vec<String>) {
+        let path = take_flag(&mut args, BOOTSTRAP_FLAG);
+        (path, args)
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Checkpoint, serde_json::Error> {
+        serde_json::from_slice::<Checkpoint>(data)
+    }
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for CheckpointError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
@@ -1,8 +1,34 @@
+use crate::block::SECOND;
+use crate::utils::{to_u128, to_u32};
 use crate::verifiable::Verifiable;
+use crate::wallet::WalletAccount;
+use secp256k1::{PublicKey, Signature};
 use serde::{Deserialize, Serialize};
 use sha256::digest_bytes;
+use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// The longest a claim's owner can announce it'll be offline for, longer
+// announcements are cut short so a claim can't be held without mining.
+pub const MAX_DOWNTIME: u128 = 6 * 60 * 60 * SECOND;
+// Downtime counts against a claim's MAX_DOWNTIME until this long after it
+// ends, so announcing again right away doesn't keep the claim away.
+pub const DOWNTIME_PERIOD: u128 = 4 * MAX_DOWNTIME;
+
+/// The lowest of the (claim hash, pointer) pairs, the claim entitled to mine
+/// the block. Claims that tie on the pointer are broken by the
+/// lexicographically smallest claim hash, so every node picks the same winner
+/// whatever order its claim map is in.
+pub fn lowest_pointer<I>(pointers: I) -> Option<(String, u128)>
+where
+    I: IntoIterator<Item = (String, u128)>,
+{
+    pointers
+        .into_iter()
+        .min_by(|(a_hash, a), (b_hash, b)| a.cmp(b).then_with(|| a_hash.cmp(b_hash)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Claim {
     pub pubkey: String,
     pub address: String,
@@ -11,6 +37,226 @@ pub struct Claim {
     pub eligible: bool,
 }
 
+/// One side of a claim transfer: the address paying or being paid, and its
+/// signature over the transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimSigner {
+    pub address: String,
+    pub pubkey: String,
+    pub signature: String,
+}
+
+/// A sale of the claim with `claim_pubkey` for `price`, signed by both its
+/// owner and the buyer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimTransfer {
+    pub claim_pubkey: String,
+    pub from: ClaimSigner,
+    pub to: ClaimSigner,
+    pub price: u128,
+}
+
+/// An announcement made at `announced_at` by the owner of the claim with
+/// `claim_pubkey` that its node is going offline until `until_timestamp`,
+/// signed with the claim's key. It's carried in a block, and from the block's
+/// timestamp until then the claim sits out the pointer election and isn't
+/// abandoned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedDowntime {
+    pub claim_pubkey: String,
+    pub announced_at: u128,
+    pub until_timestamp: u128,
+    pub signature: String,
+}
+
+/// The downtime a block granted a claim, from the block's timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DowntimeWindow {
+    pub from: u128,
+    pub until: u128,
+}
+
+#[derive(Debug, Error)]
+pub enum ClaimError {
+    #[error("no claim with pubkey {0}")]
+    UnknownClaim(String),
+    #[error("{0} doesn't own the claim")]
+    NotOwner(String),
+    #[error("the claim is slashed and can't be transferred")]
+    Ineligible,
+    #[error("{0} already holds a claim")]
+    PubkeyTaken(String),
+    #[error("invalid {0} signature")]
+    InvalidSignature(&'static str),
+    #[error("the announced downtime ended at {0}")]
+    DowntimeOver(u128),
+    #[error("the claim was away for MAX_DOWNTIME in the last DOWNTIME_PERIOD")]
+    DowntimeExhausted,
+}
+
+impl ClaimSigner {
+    /// Signs the transfer `payload` with the wallet, for `address`.
+    pub fn sign(wallet: &WalletAccount, address: &str, payload: &str) -> ClaimSigner {
+        ClaimSigner {
+            address: address.to_string(),
+            pubkey: wallet.get_pubkey(),
+            signature: wallet
+                .sign(payload)
+                .map(|sig| sig.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn signed(&self, payload: &str) -> bool {
+        match (
+            Signature::from_str(&self.signature),
+            PublicKey::from_str(&self.pubkey),
+        ) {
+            (Ok(signature), Ok(pubkey)) => {
+                WalletAccount::verify(payload.to_string(), signature, pubkey).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl ClaimTransfer {
+    /// The claim rekeyed to the buyer, at the next nonce so a transfer signed
+    /// before can't be replayed once the claim comes back to the seller.
+    pub fn transferred(&self, known: &Claim) -> Claim {
+        Claim::new(
+            self.to.pubkey.clone(),
+            self.to.address.clone(),
+            known.nonce + 1,
+        )
+    }
+
+    /// Checks the sale against the claims `get_claim` looks up by pubkey and
+    /// returns the claim as it is once sold. The claim is owned by the holder
+    /// of its key, so the seller must sign with the claim's pubkey from the
+    /// address the claim pays, and the buyer's pubkey can't hold a claim.
+    pub fn validate<F>(&self, get_claim: F) -> Result<Claim, ClaimError>
+    where
+        F: Fn(&str) -> Option<Claim>,
+    {
+        let known = get_claim(&self.claim_pubkey)
+            .ok_or_else(|| ClaimError::UnknownClaim(self.claim_pubkey.clone()))?;
+        if self.from.pubkey != known.pubkey || self.from.address != known.address {
+            return Err(ClaimError::NotOwner(self.from.address.clone()));
+        }
+
+        if !known.eligible {
+            return Err(ClaimError::Ineligible);
+        }
+
+        if self.to.pubkey == known.pubkey || get_claim(&self.to.pubkey).is_some() {
+            return Err(ClaimError::PubkeyTaken(self.to.pubkey.clone()));
+        }
+
+        let payload = known.transfer_payload(&self.from.address, &self.to.address, self.price);
+        if !self.from.signed(&payload) {
+            return Err(ClaimError::InvalidSignature("seller"));
+        }
+
+        if !self.to.signed(&payload) {
+            return Err(ClaimError::InvalidSignature("buyer"));
+        }
+
+        Ok(self.transferred(&known))
+    }
+}
+
+impl PlannedDowntime {
+    /// Announces at `announced_at` that the wallet's claim is offline until
+    /// `until_timestamp`.
+    pub fn sign(
+        wallet: &WalletAccount,
+        announced_at: u128,
+        until_timestamp: u128,
+    ) -> PlannedDowntime {
+        let claim_pubkey = wallet.get_pubkey();
+        let signature = wallet
+            .sign(&PlannedDowntime::payload(
+                &claim_pubkey,
+                announced_at,
+                until_timestamp,
+            ))
+            .map(|sig| sig.to_string())
+            .unwrap_or_default();
+
+        PlannedDowntime {
+            claim_pubkey,
+            announced_at,
+            until_timestamp,
+            signature,
+        }
+    }
+
+    fn payload(claim_pubkey: &str, announced_at: u128, until_timestamp: u128) -> String {
+        format!(
+            "downtime,{},{},{}",
+            claim_pubkey, announced_at, until_timestamp
+        )
+    }
+
+    /// Grants the announcement in a block at `timestamp`, given the claim's
+    /// earlier `windows`, and returns when the claim is back. It replaces a
+    /// window still open at `timestamp`, and is cut short so the claim is
+    /// away for at most MAX_DOWNTIME in total over the windows that ended
+    /// less than DOWNTIME_PERIOD ago.
+    pub fn grant(
+        &self,
+        windows: &mut Vec<DowntimeWindow>,
+        timestamp: u128,
+    ) -> Result<u128, ClaimError> {
+        if self.until_timestamp <= timestamp {
+            return Err(ClaimError::DowntimeOver(self.until_timestamp));
+        }
+
+        let mut granted = windows.clone();
+        granted.retain(|window| window.until.saturating_add(DOWNTIME_PERIOD) > timestamp);
+        granted.iter_mut().for_each(|window| {
+            window.until = std::cmp::min(window.until, timestamp);
+        });
+        let used = granted.iter().fold(0u128, |used, window| {
+            used.saturating_add(window.until.saturating_sub(window.from))
+        });
+        let remaining = MAX_DOWNTIME.saturating_sub(used);
+        if remaining == 0 {
+            return Err(ClaimError::DowntimeExhausted);
+        }
+
+        let until = std::cmp::min(self.until_timestamp, timestamp.saturating_add(remaining));
+        granted.push(DowntimeWindow {
+            from: timestamp,
+            until,
+        });
+        *windows = granted;
+
+        Ok(until)
+    }
+
+    /// Whether the announcement is signed with the claim's own key.
+    pub fn verify(&self) -> bool {
+        match (
+            Signature::from_str(&self.signature),
+            PublicKey::from_str(&self.claim_pubkey),
+        ) {
+            (Ok(signature), Ok(pubkey)) => WalletAccount::verify(
+                PlannedDowntime::payload(
+                    &self.claim_pubkey,
+                    self.announced_at,
+                    self.until_timestamp,
+                ),
+                signature,
+                pubkey,
+            )
+            .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
 impl Claim {
     pub fn new(pubkey: String, address: String, claim_nonce: u128) -> Claim {
         let iters = if let Some(n) = claim_nonce.checked_mul(10) {
@@ -55,23 +301,39 @@ impl Claim {
         let mut pointers = vec![];
         nonce_hex.chars().enumerate().for_each(|(idx, c)| {
             let res = self.hash.find(c);
-            if let Some(n) = res {
-                let n = n as u128;
-                let n = n.checked_pow(idx as u32);
-                if let Some(n) = n {
-                    pointers.push(n as u128);
+            if let (Some(n), Ok(idx)) = (res, to_u32(idx)) {
+                if let Some(n) = to_u128(n).ok().and_then(|n| n.checked_pow(idx)) {
+                    pointers.push(n);
                 }
             }
         });
 
         if pointers.len() == nonce_string_len {
-            let pointer: u128 = pointers.iter().sum();
-            Some(pointer)
+            // A pointer that would overflow can't be the lowest.
+            pointers
+                .iter()
+                .try_fold(0u128, |pointer, n| pointer.checked_add(*n))
         } else {
             None
         }
     }
 
+    /// Whether this claim takes the pubkey of a `known` claim without being
+    /// the same claim: it has another address, or another hash at the same
+    /// nonce. A known claim at a different nonce has a different hash.
+    pub fn conflicts_with(&self, known: &Claim) -> bool {
+        self.pubkey == known.pubkey
+            && (self.address != known.address
+                || (self.nonce == known.nonce && self.hash != known.hash))
+    }
+
+    /// What both sides of a transfer of this claim from `from` to `to` for
+    /// `price` sign. The nonce keeps a signed transfer from being replayed
+    /// after the claim comes back to the seller.
+    pub fn transfer_payload(&self, from: &str, to: &str, price: u128) -> String {
+        format!("{},{},{},{},{}", self.pubkey, self.nonce, from, to, price)
+    }
+
     pub fn from_string(claim_string: String) -> Claim {
         serde_json::from_str::<Claim>(&claim_string).unwrap()
     }
@@ -92,3 +354,148 @@ impl Verifiable for Claim {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ritelinked::LinkedHashMap;
+
+    // The seller's claim, paying "seller", and the claims it's looked up in.
+    fn transfer_claims(seller: &WalletAccount) -> (LinkedHashMap<String, Claim>, Claim) {
+        let claim = Claim::new(seller.get_pubkey(), "seller".to_string(), 1);
+        let mut claims = LinkedHashMap::new();
+        claims.insert(claim.pubkey.clone(), claim.clone());
+
+        (claims, claim)
+    }
+
+    fn transfer(claim: &Claim, from: ClaimSigner, to: ClaimSigner, price: u128) -> ClaimTransfer {
+        ClaimTransfer {
+            claim_pubkey: claim.pubkey.clone(),
+            from,
+            to,
+            price,
+        }
+    }
+
+    fn signers(
+        claim: &Claim,
+        seller: &WalletAccount,
+        buyer: &WalletAccount,
+        price: u128,
+    ) -> (ClaimSigner, ClaimSigner) {
+        let payload = claim.transfer_payload("seller", "buyer", price);
+        (
+            ClaimSigner::sign(seller, "seller", &payload),
+            ClaimSigner::sign(buyer, "buyer", &payload),
+        )
+    }
+
+    #[test]
+    fn transfer_rekeys_the_claim_to_the_buyer_at_the_next_nonce() {
+        let (seller, buyer) = (WalletAccount::new(), WalletAccount::new());
+        let (mut claims, claim) = transfer_claims(&seller);
+        let (from, to) = signers(&claim, &seller, &buyer, 40);
+        let sale = transfer(&claim, from, to, 40);
+
+        let transferred = sale.validate(|pubkey| claims.get(pubkey).cloned()).unwrap();
+        assert_eq!(transferred.pubkey, buyer.get_pubkey());
+        assert_eq!(transferred.address, "buyer");
+        assert_eq!(transferred.nonce, claim.nonce + 1);
+
+        // Once the buyer holds a claim the same sale can't go through again.
+        claims.insert(transferred.pubkey.clone(), transferred);
+        assert!(matches!(
+            sale.validate(|pubkey| claims.get(pubkey).cloned()),
+            Err(ClaimError::PubkeyTaken(_))
+        ));
+
+        // Nor can it be replayed once the claim comes back to the seller.
+        let back = Claim::new(seller.get_pubkey(), "seller".to_string(), claim.nonce + 2);
+        let mut claims = LinkedHashMap::new();
+        claims.insert(back.pubkey.clone(), back);
+        assert!(matches!(
+            sale.validate(|pubkey| claims.get(pubkey).cloned()),
+            Err(ClaimError::InvalidSignature("seller"))
+        ));
+    }
+
+    #[test]
+    fn slashed_claim_cant_be_transferred() {
+        let (seller, buyer) = (WalletAccount::new(), WalletAccount::new());
+        let (mut claims, claim) = transfer_claims(&seller);
+        let (from, to) = signers(&claim, &seller, &buyer, 40);
+        claims.get_mut(&claim.pubkey).unwrap().eligible = false;
+
+        assert!(matches!(
+            transfer(&claim, from, to, 40).validate(|pubkey| claims.get(pubkey).cloned()),
+            Err(ClaimError::Ineligible)
+        ));
+    }
+
+    #[test]
+    fn non_owning_seller_is_rejected() {
+        let (seller, buyer, impostor) = (
+            WalletAccount::new(),
+            WalletAccount::new(),
+            WalletAccount::new(),
+        );
+        let (claims, claim) = transfer_claims(&seller);
+        let get_claim = |pubkey: &str| claims.get(pubkey).cloned();
+        let payload = claim.transfer_payload("seller", "buyer", 40);
+        let to = ClaimSigner::sign(&buyer, "buyer", &payload);
+
+        // Signed from the claim's address with a key other than the claim's.
+        let from = ClaimSigner::sign(&impostor, "seller", &payload);
+        assert!(matches!(
+            transfer(&claim, from, to.clone(), 40).validate(get_claim),
+            Err(ClaimError::NotOwner(_))
+        ));
+
+        // Signed with the claim's key from an address the claim doesn't pay.
+        let elsewhere = claim.transfer_payload("elsewhere", "buyer", 40);
+        let from = ClaimSigner::sign(&seller, "elsewhere", &elsewhere);
+        assert!(matches!(
+            transfer(&claim, from, to.clone(), 40).validate(get_claim),
+            Err(ClaimError::NotOwner(_))
+        ));
+
+        // The claim's pubkey with a signature it didn't make.
+        let mut forged = ClaimSigner::sign(&seller, "seller", &payload);
+        forged.signature = ClaimSigner::sign(&impostor, "seller", &payload).signature;
+        assert!(matches!(
+            transfer(&claim, forged, to, 40).validate(get_claim),
+            Err(ClaimError::InvalidSignature("seller"))
+        ));
+    }
+
+    #[test]
+    fn downtime_is_capped_in_total_over_the_downtime_period() {
+        let owner = WalletAccount::new();
+        let mut windows = vec![];
+        let downtime = PlannedDowntime::sign(&owner, 0, 3 * MAX_DOWNTIME);
+        assert_eq!(downtime.grant(&mut windows, 10).unwrap(), 10 + MAX_DOWNTIME);
+
+        // Announcing again while away doesn't move the end of the downtime.
+        let again = PlannedDowntime::sign(&owner, 20, 3 * MAX_DOWNTIME);
+        assert_eq!(again.grant(&mut windows, 20).unwrap(), 10 + MAX_DOWNTIME);
+
+        // Nor does announcing once it's over, until DOWNTIME_PERIOD passes.
+        let back = 10 + MAX_DOWNTIME;
+        let later = PlannedDowntime::sign(&owner, back, back + DOWNTIME_PERIOD + 2 * MAX_DOWNTIME);
+        assert!(matches!(
+            later.grant(&mut windows, back + 1),
+            Err(ClaimError::DowntimeExhausted)
+        ));
+        assert_eq!(
+            later.grant(&mut windows, back + DOWNTIME_PERIOD).unwrap(),
+            back + DOWNTIME_PERIOD + MAX_DOWNTIME
+        );
+        assert_eq!(windows.len(), 1);
+
+        assert!(matches!(
+            later.grant(&mut windows, back + DOWNTIME_PERIOD + 2 * MAX_DOWNTIME),
+            Err(ClaimError::DowntimeOver(_))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::network::command_utils::{Command, HELP};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+/// The terminal the node is driven from when it's built with the `cli`
+/// feature. Each line read from stdin is parsed into a command, the ones run
+/// against the node's wallet or files go to the loop that owns them and the
+/// rest go to the command handler.
+pub struct Terminal {
+    to_wallet_sender: UnboundedSender<Command>,
+    command_sender: UnboundedSender<Command>,
+}
+
+impl Terminal {
+    pub fn new(
+        to_wallet_sender: UnboundedSender<Command>,
+        command_sender: UnboundedSender<Command>,
+    ) -> Terminal {
+        Terminal {
+            to_wallet_sender,
+            command_sender,
+        }
+    }
+
+    /// Reads commands from stdin until the user quits or stdin is closed.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::task::spawn(async move {
+            let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+            while let Ok(Some(line)) = stdin.next_line().await {
+                if !self.dispatch(&line) {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Hands on the command entered as `line`, false once the user has quit.
+    pub fn dispatch(&self, line: &str) -> bool {
+        let command = match Command::from_str(line) {
+            Some(Command::Help) => {
+                print!("{}", Command::help_text());
+                return true;
+            }
+            Some(command) => command,
+            None => {
+                println!("Enter {} for the list of commands", HELP);
+                return true;
+            }
+        };
+        let quit = matches!(command, Command::Quit);
+        let sender = if runs_in_wallet_loop(&command) {
+            &self.to_wallet_sender
+        } else {
+            &self.command_sender
+        };
+        if let Err(_) = sender.send(command) {
+            println!("Error sending command to command receiver");
+        }
+
+        !quit
+    }
+}
+
+// The commands that need the wallet, the log levels or the node's data dir,
+// which only the wallet loop in the node's main task has.
+fn runs_in_wallet_loop(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::SendTxn(..)
+            | Command::RequestReinstatement(..)
+            | Command::SetLogLevel(..)
+            | Command::RebroadcastTxn(..)
+            | Command::AddSchedule(..)
+            | Command::ListSchedules
+            | Command::CancelSchedule(..)
+            | Command::ResumeSchedule(..)
+            | Command::ExportSnapshot(..)
+            | Command::SetContact(..)
+            | Command::ExportSync(..)
+            | Command::ImportSync(..)
+            | Command::GetTxnStatus(..)
+            | Command::AnnounceDowntime(..)
+            | Command::NewAddress
+            | Command::Quit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn wallet_commands_go_to_the_wallet_loop() {
+        let (to_wallet_sender, mut to_wallet_receiver) = unbounded_channel();
+        let (command_sender, mut command_receiver) = unbounded_channel();
+        let terminal = Terminal::new(to_wallet_sender, command_sender);
+
+        assert!(terminal.dispatch("SCHEDULE LIST"));
+        assert!(terminal.dispatch("MINEBLK"));
+        assert!(terminal.dispatch("not a command"));
+        assert!(terminal.dispatch("HELP"));
+        assert!(!terminal.dispatch("QUIT"));
+
+        assert!(matches!(
+            to_wallet_receiver.try_recv(),
+            Ok(Command::ListSchedules)
+        ));
+        assert!(matches!(to_wallet_receiver.try_recv(), Ok(Command::Quit)));
+        assert!(matches!(
+            command_receiver.try_recv(),
+            Ok(Command::MineBlock)
+        ));
+        assert!(command_receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of the current time in nanoseconds since the unix epoch. The
+/// miner and txns read the time through one, so tests can control it.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> u128;
+}
+
+/// The system's clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+/// A clock that only moves when it's told to. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct MockClock {
+    now: Arc<Mutex<u128>>,
+}
+
+/// A clock handed to the parts of the node that read the time, the system
+/// clock unless one is injected.
+#[derive(Debug, Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    }
+}
+
+impl MockClock {
+    pub fn new(now: u128) -> MockClock {
+        MockClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: u128) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, nanos: u128) {
+        let mut now = self.now.lock().unwrap();
+        *now = now.saturating_add(nanos);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u128 {
+        *self.now.lock().unwrap()
+    }
+}
+
+impl SharedClock {
+    pub fn new<C: Clock + 'static>(clock: C) -> SharedClock {
+        SharedClock(Arc::new(clock))
+    }
+}
+
+impl Clock for SharedClock {
+    fn now(&self) -> u128 {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> SharedClock {
+        SharedClock::new(SystemClock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_moves_only_when_told() {
+        let clock = MockClock::new(100);
+        let shared = SharedClock::new(clock.clone());
+        assert_eq!(shared.now(), 100);
+        clock.advance(50);
+        assert_eq!(shared.now(), 150);
+        clock.set(10);
+        assert_eq!(shared.now(), 10);
+    }
+}
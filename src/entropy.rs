@@ -0,0 +1,48 @@
+use rand::rngs::StdRng;
+use rand::{thread_rng, RngCore, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    // Set while a run is recorded or replayed, the thread's draws then follow
+    // from the seed instead of the thread's RNG.
+    static SEEDED: RefCell<Option<StdRng>> = RefCell::new(None);
+}
+
+/// Seeds the RNG the calling thread draws block nonces from, so a run
+/// recorded with the seed draws the same values when it's replayed.
+pub fn seed(seed: u64) {
+    SEEDED.with(|seeded| *seeded.borrow_mut() = Some(StdRng::seed_from_u64(seed)));
+}
+
+/// Goes back to drawing from the thread's RNG.
+pub fn unseed() {
+    SEEDED.with(|seeded| *seeded.borrow_mut() = None);
+}
+
+/// Runs `f` with the calling thread's seeded RNG, or the thread's RNG if it
+/// isn't seeded.
+pub fn with_rng<T, F: FnOnce(&mut dyn RngCore) -> T>(f: F) -> T {
+    SEEDED.with(|seeded| match seeded.borrow_mut().as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut thread_rng()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_draws_repeat() {
+        let draw = || {
+            (0..8)
+                .map(|_| with_rng(|rng| rng.next_u64()))
+                .collect::<Vec<u64>>()
+        };
+        seed(7);
+        let first = draw();
+        seed(7);
+        assert_eq!(draw(), first);
+        unseed();
+    }
+}
@@ -1,6 +1,28 @@
+use crate::logging::{CHANNEL_TARGET, DEAD_LETTER_TARGET};
 use crate::network::command_utils::Command;
-use log::info;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use crate::utils::take_flag;
+use log::{error, info, warn};
+use ritelinked::LinkedHashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::mpsc::error::{SendError, TryRecvError, TrySendError};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+// How much of a dead-lettered message is logged, blocks can be large.
+const DEAD_LETTER_MAX_LEN: usize = 256;
+pub const CHANNEL_CAPACITY_FLAG: &str = "--channel-capacity";
+pub const BACKPRESSURE_WAIT_FLAG: &str = "--backpressure-wait";
+// The messages a bounded channel holds, and how long in milliseconds a send
+// to a full one waits for room before the message is dropped.
+pub const CHANNEL_CAPACITY: usize = 1024;
+pub const BACKPRESSURE_WAIT: u64 = 50;
+// How long in milliseconds a thread with timed work blocks on its channel
+// before it wakes up to check on that work.
+pub const THREAD_WAKEUP: u64 = 10;
 
 pub trait Handler<T, V> {
     fn send(&self, message: T) -> Option<T>;
@@ -10,19 +32,272 @@ pub trait Handler<T, V> {
 pub struct MessageHandler<T, V> {
     pub sender: UnboundedSender<T>,
     pub receiver: UnboundedReceiver<V>,
+    // number of inbound messages received per gossipsub topic
+    pub topic_counts: LinkedHashMap<String, u128>,
 }
 
 pub struct CommandHandler {
     pub to_mining_sender: UnboundedSender<Command>,
     pub to_blockchain_sender: UnboundedSender<Command>,
     pub to_swarm_sender: UnboundedSender<Command>,
-    pub to_state_sender: UnboundedSender<Command>,
+    pub to_state_sender: BoundedSender<Command>,
     pub receiver: UnboundedReceiver<Command>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    pub capacity: usize,
+    pub wait_ms: u64,
+}
+
+/// The sending half of a bounded channel to a thread. A send to a full
+/// channel waits up to the configured time for the thread to catch up and
+/// drops the message if it doesn't, so a slow thread holds up its senders
+/// briefly rather than letting its queue grow without limit.
+#[derive(Debug, Clone)]
+pub struct BoundedSender<T> {
+    name: &'static str,
+    sender: Sender<T>,
+    capacity: usize,
+    wait: Duration,
+    room: Arc<Room>,
+}
+
+/// The receiving half of a bounded channel. Every message taken off it wakes
+/// the senders waiting for room.
+#[derive(Debug)]
+pub struct BoundedReceiver<T> {
+    receiver: Receiver<T>,
+    room: Arc<Room>,
+    runtime: Option<Runtime>,
+}
+
+/// The receiving half of a thread's unbounded channel. The thread blocks on
+/// it until a message arrives or its wait runs out rather than spinning on
+/// try_recv, and the messages sent meanwhile queue in the channel in order.
+#[derive(Debug)]
+pub struct Inbox<T> {
+    receiver: UnboundedReceiver<T>,
+    runtime: Option<Runtime>,
+}
+
+// The number of messages taken off a bounded channel, so a sender waiting
+// for room wakes up as soon as there is some.
+#[derive(Debug, Default)]
+struct Room {
+    taken: Mutex<u64>,
+    freed: Condvar,
+}
+
+/// A bounded channel named `name` in logs, sized and timed by `config`.
+pub fn bounded_channel<T>(
+    name: &'static str,
+    config: ChannelConfig,
+) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    let capacity = config.capacity.max(1);
+    let (sender, receiver) = mpsc::channel(capacity);
+    let room = Arc::new(Room::default());
+    let sender = BoundedSender {
+        name,
+        sender,
+        capacity,
+        wait: Duration::from_millis(config.wait_ms),
+        room: room.clone(),
+    };
+    let receiver = BoundedReceiver {
+        receiver,
+        room,
+        runtime: None,
+    };
+
+    (sender, receiver)
+}
+
+/// Sends `message` to another thread. A send on an unbounded channel only
+/// fails once the receiving thread is gone, so it isn't retried, the message
+/// is logged to the dead-letter target and handed back in the error.
+pub fn send_or_dead_letter<T: Debug>(
+    sender: &UnboundedSender<T>,
+    message: T,
+) -> Result<(), SendError<T>> {
+    sender.send(message).map_err(|SendError(message)| {
+        error!(
+            target: DEAD_LETTER_TARGET,
+            "message dropped, receiver closed message={}",
+            describe(&message)
+        );
+        SendError(message)
+    })
+}
+
+// Blocks on `recv` for up to `wait`. The threads receiving commands aren't
+// async, so each blocks on a runtime of its own, built on its first receive.
+fn recv_timeout<T>(
+    runtime: &mut Option<Runtime>,
+    wait: Duration,
+    recv: impl Future<Output = Option<T>>,
+) -> Result<T, RecvTimeoutError> {
+    let runtime = runtime.get_or_insert_with(|| {
+        Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("Error building a thread's receive runtime")
+    });
+    match runtime.block_on(async { tokio::time::timeout(wait, recv).await }) {
+        Ok(Some(message)) => Ok(message),
+        Ok(None) => Err(RecvTimeoutError::Disconnected),
+        Err(_) => Err(RecvTimeoutError::Timeout),
+    }
+}
+
+fn describe<T: Debug>(message: &T) -> String {
+    format!("{:?}", message)
+        .chars()
+        .take(DEAD_LETTER_MAX_LEN)
+        .collect()
+}
+
+impl ChannelConfig {
+    /// Removes the channel flags from the command line arguments, falling
+    /// back to CHANNEL_CAPACITY and BACKPRESSURE_WAIT for missing or invalid
+    /// values.
+    pub fn from_args(mut args: Vec<String>) -> (ChannelConfig, Vec<String>) {
+        let mut config = ChannelConfig::default();
+        if let Some(capacity) =
+            take_flag(&mut args, CHANNEL_CAPACITY_FLAG).and_then(|c| c.parse::<usize>().ok())
+        {
+            config.capacity = capacity;
+        }
+        if let Some(wait_ms) =
+            take_flag(&mut args, BACKPRESSURE_WAIT_FLAG).and_then(|w| w.parse::<u64>().ok())
+        {
+            config.wait_ms = wait_ms;
+        }
+
+        (config, args)
+    }
+}
+
+impl Default for ChannelConfig {
+    fn default() -> ChannelConfig {
+        ChannelConfig {
+            capacity: CHANNEL_CAPACITY,
+            wait_ms: BACKPRESSURE_WAIT,
+        }
+    }
+}
+
+impl<T: Debug> BoundedSender<T> {
+    /// Sends `message`, waiting for room if the channel is full. A message
+    /// still without room once the wait is over is dropped, logged to the
+    /// dead-letter target and handed back in the error.
+    pub fn send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let started = Instant::now();
+        let mut message = message;
+        let mut engaged = false;
+        loop {
+            let taken = self.room.taken();
+            match self.sender.try_send(message) {
+                Ok(()) => {
+                    if engaged {
+                        info!(
+                            target: CHANNEL_TARGET,
+                            "backpressure released channel={} waited_ms={}",
+                            self.name,
+                            started.elapsed().as_millis()
+                        );
+                    }
+                    return Ok(());
+                }
+                Err(TrySendError::Full(returned)) => {
+                    if !engaged {
+                        warn!(
+                            target: CHANNEL_TARGET,
+                            "backpressure engaged channel={} capacity={}",
+                            self.name,
+                            self.capacity
+                        );
+                        engaged = true;
+                    }
+                    let waited = started.elapsed();
+                    if waited >= self.wait {
+                        error!(
+                            target: DEAD_LETTER_TARGET,
+                            "message dropped, channel full channel={} message={}",
+                            self.name,
+                            describe(&returned)
+                        );
+                        return Err(TrySendError::Full(returned));
+                    }
+                    message = returned;
+                    self.room.wait_for_room(taken, self.wait - waited);
+                }
+                Err(closed) => return Err(closed),
+            }
+        }
+    }
+}
+
+impl<T> BoundedReceiver<T> {
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let message = self.receiver.try_recv()?;
+        self.room.take();
+        Ok(message)
+    }
+
+    /// Waits up to `wait` for the next message.
+    pub fn recv_timeout(&mut self, wait: Duration) -> Result<T, RecvTimeoutError> {
+        let message = recv_timeout(&mut self.runtime, wait, self.receiver.recv())?;
+        self.room.take();
+        Ok(message)
+    }
+}
+
+impl<T> Inbox<T> {
+    pub fn new(receiver: UnboundedReceiver<T>) -> Inbox<T> {
+        Inbox {
+            receiver,
+            runtime: None,
+        }
+    }
+
+    /// Waits up to `wait` for the next message.
+    pub fn recv_timeout(&mut self, wait: Duration) -> Result<T, RecvTimeoutError> {
+        recv_timeout(&mut self.runtime, wait, self.receiver.recv())
+    }
+}
+
+impl Room {
+    fn taken(&self) -> u64 {
+        *self.taken.lock().unwrap()
+    }
+
+    fn take(&self) {
+        *self.taken.lock().unwrap() += 1;
+        self.freed.notify_all();
+    }
+
+    // Waits up to `wait` for a message to be taken once `taken` have been.
+    fn wait_for_room(&self, taken: u64, wait: Duration) {
+        let guard = self.taken.lock().unwrap();
+        let _ = self
+            .freed
+            .wait_timeout_while(guard, wait, |now| *now == taken)
+            .unwrap();
+    }
+}
+
 impl<T: Clone, V: Clone> MessageHandler<T, V> {
     pub fn new(sender: UnboundedSender<T>, receiver: UnboundedReceiver<V>) -> MessageHandler<T, V> {
-        MessageHandler { sender, receiver }
+        MessageHandler {
+            sender,
+            receiver,
+            topic_counts: LinkedHashMap::new(),
+        }
+    }
+
+    pub fn tag_topic(&mut self, topic: &str) {
+        *self.topic_counts.entry(topic.to_string()).or_insert(0) += 1;
     }
 }
 
@@ -31,7 +306,7 @@ impl CommandHandler {
         to_mining_sender: UnboundedSender<Command>,
         to_blockchain_sender: UnboundedSender<Command>,
         to_swarm_sender: UnboundedSender<Command>,
-        to_state_sender: UnboundedSender<Command>,
+        to_state_sender: BoundedSender<Command>,
         receiver: UnboundedReceiver<Command>,
     ) -> CommandHandler {
         CommandHandler {
@@ -50,6 +325,16 @@ impl CommandHandler {
                     println!("Error sending to mining sender: {:?}", e);
                 }
             }
+            Command::PauseMining => {
+                if let Err(e) = self.to_mining_sender.send(Command::PauseMining) {
+                    println!("Error sending PauseMining command to miner: {:?}", e);
+                }
+            }
+            Command::ResumeMining => {
+                if let Err(e) = self.to_mining_sender.send(Command::ResumeMining) {
+                    println!("Error sending ResumeMining command to miner: {:?}", e);
+                }
+            }
             Command::GetState => {
                 //TODO: request the state from the most recent confirmed block miner's node.
             }
@@ -61,6 +346,28 @@ impl CommandHandler {
                     );
                 }
             }
+            // Miners treat a forwarded txn like any other.
+            Command::ProcessForwardedTxn(txn, _) => {
+                if let Err(e) = self.to_mining_sender.send(Command::ProcessTxn(txn)) {
+                    println!(
+                        "Error sending transaction to mining sender for processing: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::TxnAck(txn_validator, sender_id) => {
+                if let Err(e) = self
+                    .to_mining_sender
+                    .send(Command::TxnAck(txn_validator, sender_id))
+                {
+                    println!("Error sending txn ack to miner: {:?}", e);
+                }
+            }
+            Command::RebroadcastTxn(txn_id) => {
+                if let Err(e) = self.to_mining_sender.send(Command::RebroadcastTxn(txn_id)) {
+                    println!("Error sending RebroadcastTxn command to miner: {:?}", e);
+                }
+            }
             Command::ProcessTxnValidator(validator) => {
                 if let Err(e) = self
                     .to_mining_sender
@@ -82,11 +389,11 @@ impl CommandHandler {
             }
             Command::StateUpdateCompleted(network_state) => {
                 if let Err(e) = self
-                    .to_mining_sender
+                    .to_blockchain_sender
                     .send(Command::StateUpdateCompleted(network_state))
                 {
                     println!(
-                        "Error sending updated network state to mining receiver: {:?}",
+                        "Error sending updated network state to blockchain receiver: {:?}",
                         e
                     );
                 }
@@ -126,6 +433,17 @@ impl CommandHandler {
                     );
                 }
             }
+            Command::StateRequestDeclined(peer, reason) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::StateRequestDeclined(peer, reason))
+                {
+                    println!(
+                        "Error sending declined state request to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
             Command::ConfirmedBlock(_block) => {}
             Command::PendingBlock(block, sender_id) => {
                 if let Err(e) = self
@@ -135,12 +453,147 @@ impl CommandHandler {
                     println!("Error sending pending block to miner: {:?}", e);
                 }
             }
+            Command::ProcessCompactBlock(block, sender_id, source) => {
+                if let Err(e) = self
+                    .to_mining_sender
+                    .send(Command::ProcessCompactBlock(block, sender_id, source))
+                {
+                    println!("Error sending compact block to miner: {:?}", e);
+                }
+            }
+            Command::SendTxns(txn_ids, requestor) => {
+                if let Err(e) = self
+                    .to_mining_sender
+                    .send(Command::SendTxns(txn_ids, requestor))
+                {
+                    println!("Error sending SendTxns command to miner: {:?}", e);
+                }
+            }
+            Command::ProcessFetchedTxns(txns) => {
+                if let Err(e) = self
+                    .to_mining_sender
+                    .send(Command::ProcessFetchedTxns(txns))
+                {
+                    println!("Error sending fetched txns to miner: {:?}", e);
+                }
+            }
+            Command::SyncPool(peer_id) => {
+                if let Err(e) = self.to_mining_sender.send(Command::SyncPool(peer_id)) {
+                    println!("Error sending SyncPool command to miner: {:?}", e);
+                }
+            }
+            Command::ProcessPoolTxnIds(txn_ids, sender_id) => {
+                if let Err(e) = self
+                    .to_mining_sender
+                    .send(Command::ProcessPoolTxnIds(txn_ids, sender_id))
+                {
+                    println!("Error sending pool txn ids to miner: {:?}", e);
+                }
+            }
+            Command::SendPeers(requestor) => {
+                if let Err(e) = self.to_swarm_sender.send(Command::SendPeers(requestor)) {
+                    println!("Error sending SendPeers command to swarm: {:?}", e);
+                }
+            }
+            Command::DialPeers(peers, sender_id) => {
+                if let Err(e) = self
+                    .to_swarm_sender
+                    .send(Command::DialPeers(peers, sender_id))
+                {
+                    println!("Error sending DialPeers command to swarm: {:?}", e);
+                }
+            }
+            Command::SendMissingBlock(block_last_hash, requestor) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::SendMissingBlock(block_last_hash, requestor))
+                {
+                    println!(
+                        "Error sending SendMissingBlock command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::SendBlockRange(from_height, to_height, requestor) => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::SendBlockRange(
+                    from_height,
+                    to_height,
+                    requestor,
+                )) {
+                    println!(
+                        "Error sending SendBlockRange command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::SendBalanceProof(address, requestor) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::SendBalanceProof(address, requestor))
+                {
+                    println!(
+                        "Error sending SendBalanceProof command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::BalanceProof(proof, sender_id) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::BalanceProof(proof, sender_id))
+                {
+                    println!("Error sending balance proof to blockchain thread: {:?}", e);
+                }
+            }
+            // Blocks in a range are processed in height order like any other
+            // pending block.
+            Command::ProcessBlockRange(blocks, sender_id) => {
+                for block in blocks {
+                    if let Err(e) = self
+                        .to_blockchain_sender
+                        .send(Command::PendingBlock(block, sender_id.clone()))
+                    {
+                        println!("Error sending pending block to blockchain thread: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            Command::BlockHeightRejected(tip_height, peer_id) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::BlockHeightRejected(tip_height, peer_id))
+                {
+                    println!(
+                        "Error sending BlockHeightRejected command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
             Command::InvalidBlock(_block) => {}
             Command::GetBalance(address) => {
                 if let Err(e) = self.to_mining_sender.send(Command::GetBalance(address)) {
                     println!("Error sending GetBalance command to mining thread: {:?}", e);
                 }
             }
+            Command::EstimateFee(target_blocks) => {
+                if let Err(e) = self
+                    .to_mining_sender
+                    .send(Command::EstimateFee(target_blocks))
+                {
+                    println!(
+                        "Error sending EstimateFee command to mining thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::GetRewardInfo => {
+                if let Err(e) = self.to_mining_sender.send(Command::GetRewardInfo) {
+                    println!(
+                        "Error sending GetRewardInfo command to mining thread: {:?}",
+                        e
+                    );
+                }
+            }
             Command::SendGenesis(sender_id) => {
                 if let Err(e) = self
                     .to_blockchain_sender
@@ -161,6 +614,67 @@ impl CommandHandler {
                     );
                 }
             }
+            Command::GetPeers => {
+                if let Err(e) = self.to_swarm_sender.send(Command::GetPeers) {
+                    println!("Error sending GetPeers command to swarm: {:?}", e);
+                }
+            }
+            Command::ShowKnownPeers => {
+                if let Err(e) = self.to_swarm_sender.send(Command::ShowKnownPeers) {
+                    println!("Error sending ShowKnownPeers command to swarm: {:?}", e);
+                }
+            }
+            Command::GetClaim(key) => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::GetClaim(key)) {
+                    println!(
+                        "Error sending GetClaim command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::ShowSlashed => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::ShowSlashed) {
+                    println!(
+                        "Error sending ShowSlashed command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::RewardState => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::RewardState) {
+                    println!(
+                        "Error sending RewardState command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::SupplyAudit => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::SupplyAudit) {
+                    println!(
+                        "Error sending SupplyAudit command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::GetMetrics => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::GetMetrics) {
+                    println!(
+                        "Error sending GetMetrics command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+                if let Err(e) = self.to_mining_sender.send(Command::GetMetrics) {
+                    println!("Error sending GetMetrics command to miner: {:?}", e);
+                }
+            }
+            Command::ShowForks => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::ShowForks) {
+                    println!(
+                        "Error sending ShowForks command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
             Command::MineBlock => {
                 info!("Received mine block command, starting the miner");
                 if let Err(e) = self.to_mining_sender.send(Command::StartMiner) {
@@ -175,6 +689,60 @@ impl CommandHandler {
                     println!("Error sending claim abandoned command to miner: {:?}", e)
                 }
             }
+            Command::ExportState(path) => {
+                if let Err(e) = self.to_blockchain_sender.send(Command::ExportState(path)) {
+                    println!(
+                        "Error sending ExportState command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::ExportCheckpoint(path, up_to_height) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::ExportCheckpoint(path, up_to_height))
+                {
+                    println!(
+                        "Error sending ExportCheckpoint command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::PruneArchive(keep_from_height) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::PruneArchive(keep_from_height))
+                {
+                    println!(
+                        "Error sending PruneArchive command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::Verify | Command::CancelVerify => {
+                if let Err(e) = self.to_blockchain_sender.send(command) {
+                    println!("Error sending Verify command to blockchain thread: {:?}", e);
+                }
+            }
+            Command::ImportState(..) => {
+                if let Err(e) = self.to_blockchain_sender.send(command) {
+                    println!(
+                        "Error sending ImportState command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
+            Command::ExportAddressActivity(address, format, path) => {
+                if let Err(e) = self
+                    .to_blockchain_sender
+                    .send(Command::ExportAddressActivity(address, format, path))
+                {
+                    println!(
+                        "Error sending ExportAddressActivity command to blockchain thread: {:?}",
+                        e
+                    );
+                }
+            }
             _ => {}
         }
     }
@@ -197,3 +765,79 @@ impl<T: Clone, V: Clone> Handler<T, V> for MessageHandler<T, V> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn full_channel_applies_backpressure_instead_of_growing() {
+        let config = ChannelConfig {
+            capacity: 2,
+            wait_ms: 0,
+        };
+        let (sender, mut receiver) = bounded_channel::<u32>("test", config);
+        assert!(sender.send(1).is_ok());
+        assert!(sender.send(2).is_ok());
+        assert!(matches!(sender.send(3), Err(TrySendError::Full(3))));
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(1)), Ok(2));
+        assert!(receiver.try_recv().is_err());
+
+        // A send waits for the receiver to make room.
+        let config = ChannelConfig {
+            capacity: 1,
+            wait_ms: 5000,
+        };
+        let (sender, mut receiver) = bounded_channel::<u32>("test", config);
+        assert!(sender.send(1).is_ok());
+        let consumer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let first = receiver.try_recv();
+            (first, receiver.recv_timeout(Duration::from_secs(5)))
+        });
+        let started = Instant::now();
+        assert!(sender.send(2).is_ok());
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(10));
+        // Woken by the receive rather than at the end of the wait.
+        assert!(waited < Duration::from_millis(5000));
+        assert_eq!(consumer.join().unwrap(), (Ok(1), Ok(2)));
+    }
+
+    #[test]
+    fn inbox_blocks_until_a_message_or_the_wait_runs_out() {
+        let (sender, receiver) = unbounded_channel::<u32>();
+        let mut inbox = Inbox::new(receiver);
+        let started = Instant::now();
+        assert_eq!(
+            inbox.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        // Messages sent while the thread is busy queue in order.
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(inbox.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert_eq!(inbox.recv_timeout(Duration::from_secs(5)), Ok(2));
+        drop(sender);
+        assert_eq!(
+            inbox.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn send_to_a_closed_channel_is_handed_back() {
+        let (sender, receiver) = unbounded_channel::<Command>();
+        assert!(send_or_dead_letter(&sender, Command::StopMine).is_ok());
+        drop(receiver);
+        assert!(matches!(
+            send_or_dead_letter(&sender, Command::StopMine),
+            Err(SendError(Command::StopMine))
+        ));
+    }
+}
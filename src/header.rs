@@ -1,8 +1,12 @@
+use crate::balance_proof;
 use crate::block::Block;
 use crate::claim::Claim;
+use crate::entropy;
+use crate::params::ProtocolParams;
 use crate::reward::{Reward, RewardState};
 use bytebuffer::ByteBuffer;
 use rand::Rng;
+use ritelinked::LinkedHashMap;
 use secp256k1::Error;
 use secp256k1::{
     key::{PublicKey, SecretKey},
@@ -29,11 +33,23 @@ pub struct BlockHeader {
     pub block_height: u128,
     pub timestamp: u128,
     pub txn_hash: String,
+    // The summed txn_cost of the block's txns, so validators can check it
+    // against the block cost limit without redoing txn selection.
+    #[serde(default)]
+    pub block_cost: u64,
     pub claim: Claim,
     pub claim_map_hash: Option<String>,
     pub block_reward: Reward,
     pub next_block_reward: Reward,
     pub neighbor_hash: Option<String>,
+    // The ledger's nonce epoch when the block was mined, validators on
+    // another epoch compute other pointers for the claims.
+    #[serde(default)]
+    pub nonce_epoch: u128,
+    // The ledger root of the balances the block was mined on, what light
+    // clients check balance proofs against.
+    #[serde(default)]
+    pub ledger_root: Option<String>,
     pub signature: String,
 }
 
@@ -44,32 +60,50 @@ impl BlockHeader {
         claim: Claim,
         secret_key: String,
     ) -> BlockHeader {
-        let mut rng = rand::thread_rng();
-        let last_hash = digest_bytes("Genesis_Last_Hash".as_bytes());
+        let last_hash = BlockHeader::genesis_last_hash(&reward_state.params);
         let block_nonce = nonce;
-        let next_block_nonce: u64 = rng.gen_range(u32MAX as u64, u64MAX);
+        // The genesis claim is the only one yet, a nonce it has no pointer for
+        // would move the ledger to the next nonce epoch with the genesis block.
+        let next_block_nonce: u64 = loop {
+            let nonce = entropy::with_rng(|rng| rng.gen_range(u32MAX as u64, u64MAX));
+            if claim.get_pointer(u128::from(nonce)).is_some() {
+                break nonce;
+            }
+        };
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_nanos();
         let txn_hash = digest_bytes("Genesis_Txn_Hash".as_bytes());
-        let block_reward = Reward::genesis(Some(claim.address.clone()));
-        let next_block_reward = Reward::new(None, reward_state);
+        let block_reward = Reward::genesis(Some(claim.address.clone()), &reward_state.params);
+        let next_block_reward = Reward::new(
+            None,
+            reward_state,
+            &BlockHeader::reward_seed(&last_hash, block_nonce, 0),
+        );
         let claim_map_hash: Option<String> = None;
         let neighbor_hash: Option<String> = None;
+        // Nothing has been credited or debited before the genesis block.
+        let ledger_root = Some(balance_proof::ledger_root(
+            &LinkedHashMap::new(),
+            &LinkedHashMap::new(),
+        ));
         let payload = format!(
-            "{},{},{},{},{},{},{:?},{:?},{:?},{:?},{:?}",
+            "{},{},{},{},{},{},{},{:?},{:?},{:?},{:?},{:?},{},{:?}",
             last_hash,
             block_nonce,
             next_block_nonce,
             0,
             timestamp,
             txn_hash,
+            0,
             claim,
             claim_map_hash,
             block_reward,
             next_block_reward,
             neighbor_hash,
+            0,
+            ledger_root,
         );
 
         let signature = BlockHeader::sign(&payload, secret_key).unwrap().to_string();
@@ -81,11 +115,14 @@ impl BlockHeader {
             block_height: 0,
             timestamp,
             txn_hash,
+            block_cost: 0,
             claim,
             claim_map_hash: None,
             block_reward,
             next_block_reward,
             neighbor_hash: None,
+            nonce_epoch: 0,
+            ledger_root,
             signature,
         }
     }
@@ -95,35 +132,41 @@ impl BlockHeader {
         reward_state: &RewardState,
         claim: Claim,
         txn_hash: String,
+        block_cost: u64,
         claim_map_hash: Option<String>,
         neighbor_hash: Option<String>,
+        nonce_epoch: u128,
+        ledger_root: Option<String>,
         secret_key: String,
+        timestamp: u128,
     ) -> BlockHeader {
-        let mut rng = rand::thread_rng();
         let last_hash = last_block.hash;
         let block_nonce = last_block.header.next_block_nonce.clone();
-        let next_block_nonce: u64 = rng.gen_range(0, u64MAX);
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
+        let next_block_nonce: u64 = entropy::with_rng(|rng| rng.gen_range(0, u64MAX));
         let mut block_reward = last_block.header.next_block_reward;
         block_reward.miner = Some(claim.clone().address);
-        let next_block_reward = Reward::new(None, reward_state);
         let block_height = last_block.header.block_height + 1;
+        let next_block_reward = Reward::new(
+            None,
+            reward_state,
+            &BlockHeader::reward_seed(&last_hash, block_nonce, block_height),
+        );
         let payload = format!(
-            "{},{},{},{},{},{},{:?},{:?},{:?},{:?},{:?}",
+            "{},{},{},{},{},{},{},{:?},{:?},{:?},{:?},{:?},{},{:?}",
             last_hash,
             block_nonce,
             next_block_nonce,
             block_height,
             timestamp,
             txn_hash,
+            block_cost,
             claim,
             claim_map_hash,
             block_reward,
             next_block_reward,
             neighbor_hash,
+            nonce_epoch,
+            ledger_root,
         );
 
         let signature = BlockHeader::sign(&payload, secret_key).unwrap().to_string();
@@ -135,15 +178,37 @@ impl BlockHeader {
             block_height: last_block.header.block_height + 1,
             timestamp,
             txn_hash,
+            block_cost,
             claim,
             claim_map_hash,
             block_reward,
             next_block_reward,
             neighbor_hash: None,
+            nonce_epoch,
+            ledger_root,
             signature,
         }
     }
 
+    /// The last hash of a genesis block. Chains started on the testnet params
+    /// keep the last hash their genesis was mined with, on any other params it
+    /// commits to them, so nodes on different params don't share a chain.
+    pub fn genesis_last_hash(params: &ProtocolParams) -> String {
+        if *params == ProtocolParams::testnet() {
+            return digest_bytes("Genesis_Last_Hash".as_bytes());
+        }
+
+        digest_bytes(format!("Genesis_Last_Hash,{}", params.hash()).as_bytes())
+    }
+
+    /// What the amount of the next block's reward is derived from. The last
+    /// hash and the block nonce are fixed by the previous block, so a miner
+    /// can't search for a larger amount, and every validator recomputes the
+    /// same one.
+    pub fn reward_seed(last_hash: &str, block_nonce: u64, block_height: u128) -> Vec<u8> {
+        format!("{},{},{}", last_hash, block_nonce, block_height).into_bytes()
+    }
+
     pub fn sign(message: &str, secret_key: String) -> Result<Signature, Error> {
         let message_bytes = message.as_bytes().to_owned();
         let mut buffer = ByteBuffer::new();
@@ -198,21 +263,30 @@ impl BlockHeader {
 
     pub fn get_payload(&self) -> String {
         format!(
-            "{},{},{},{},{},{},{:?},{:?},{:?},{:?},{:?}",
+            "{},{},{},{},{},{},{},{:?},{:?},{:?},{:?},{:?},{},{:?}",
             self.last_hash,
             self.block_nonce,
             self.next_block_nonce,
             self.block_height,
             self.timestamp,
             self.txn_hash,
+            self.block_cost,
             self.claim,
             self.claim_map_hash,
             self.block_reward,
             self.next_block_reward,
             self.neighbor_hash,
+            self.nonce_epoch,
+            self.ledger_root,
         )
     }
 
+    /// A hash of the signed contents. Every genesis block has the same block
+    /// hash, a network tells its own genesis block apart by this instead.
+    pub fn hash(&self) -> String {
+        digest_bytes(self.get_payload().as_bytes())
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         self.to_string().as_bytes().to_vec()
     }
@@ -1,19 +1,18 @@
 use crate::block;
+use crate::blockchain::format_block_height;
 use crate::claim::Claim;
 use crate::header::BlockHeader;
-use crate::network::protocol::VrrbNetworkEvent;
+use crate::miner::QuorumConfig;
+use crate::network::protocol::read_from_json;
 use crate::pool::Pool;
 use crate::reward::RewardState;
 use crate::state::Ledger;
 use crate::state::NetworkState;
 use crate::txn::Txn;
-use crate::wallet::WalletAccount;
+use crate::wallet::{PendingTxn, WalletAccount};
 use libp2p::Multiaddr;
 use ritelinked::LinkedHashMap;
-use std::collections::LinkedList;
-use std::fs;
-use std::io;
-use thiserror::Error;
+use std::collections::{HashSet, LinkedList};
 use tui::{
     layout::{Alignment, Constraint},
     style::{Color, Modifier, Style},
@@ -21,14 +20,6 @@ use tui::{
     widgets::{Block, BorderType, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table},
 };
 
-#[derive(Error, Debug)]
-pub enum JsonError {
-    #[error("Error reading the json file: {0}")]
-    ReadJsonError(#[from] io::Error),
-    #[error("Error parsing the json file: {0}")]
-    ParseJsonError(#[from] serde_json::Error),
-}
-
 pub fn render_home<'a>(addr: &Multiaddr, wallet: &WalletAccount) -> Paragraph<'a> {
     let home = Paragraph::new(vec![
         Spans::from(vec![Span::raw("")]),
@@ -109,9 +100,9 @@ pub fn render_wallet<'a>(
 
     let items: Vec<_> = wallet_addresses
         .iter()
-        .map(|(_, address)| {
+        .map(|(address_number, address)| {
             ListItem::new(Spans::from(vec![Span::styled(
-                address.clone(),
+                format!("{}: {}", address_number, address),
                 Style::default(),
             )]))
         })
@@ -188,6 +179,43 @@ pub fn render_wallet<'a>(
     (list, wallet_detail)
 }
 
+pub fn render_outbox<'a>(outbox: &LinkedHashMap<String, PendingTxn>) -> Table<'a> {
+    let header_style = Style::default().add_modifier(Modifier::BOLD);
+    let rows: Vec<Row> = outbox
+        .iter()
+        .rev()
+        .map(|(txn_id, pending)| {
+            Row::new(vec![
+                Cell::from(Span::raw(txn_id.clone())),
+                Cell::from(Span::raw(pending.txn.receiver_address.clone())),
+                Cell::from(Span::raw(pending.txn.txn_amount.to_string())),
+                Cell::from(Span::raw(pending.status.to_string())),
+            ])
+        })
+        .collect();
+
+    Table::new(rows)
+        .header(Row::new(vec![
+            Cell::from(Span::styled("Txn ID", header_style)),
+            Cell::from(Span::styled("Receiver", header_style)),
+            Cell::from(Span::styled("Amount", header_style)),
+            Cell::from(Span::styled("Status", header_style)),
+        ]))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title("Outbox")
+                .border_type(BorderType::Plain),
+        )
+        .widths(&[
+            Constraint::Percentage(30),
+            Constraint::Percentage(30),
+            Constraint::Percentage(10),
+            Constraint::Percentage(30),
+        ])
+}
+
 pub fn render_mining_data<'a>() -> List<'a> {
     let data = Block::default()
         .borders(Borders::ALL)
@@ -333,7 +361,13 @@ pub fn render_header_chain<'a>(
                 ]),
                 Row::new(vec![
                     Cell::from(Span::raw("Block Height")),
-                    Cell::from(Span::raw(selected_block_header.block_height.to_string())),
+                    Cell::from(Span::raw(format_block_height(
+                        selected_block_header.block_height,
+                        chain.back().map(|tip| {
+                            tip.block_height
+                                .saturating_sub(selected_block_header.block_height)
+                        }),
+                    ))),
                 ]),
                 Row::new(vec![
                     Cell::from(Span::raw("Timestamp")),
@@ -416,7 +450,7 @@ pub fn render_header_chain<'a>(
     (list, table)
 }
 
-pub fn render_block_table<'a>(block: &block::Block) -> Table<'a> {
+pub fn render_block_table<'a>(block: &block::Block, confirmations: Option<u128>) -> Table<'a> {
     let header_style = Style::default()
         .add_modifier(Modifier::BOLD)
         .add_modifier(Modifier::UNDERLINED);
@@ -440,7 +474,10 @@ pub fn render_block_table<'a>(block: &block::Block) -> Table<'a> {
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Block Height")),
-            Cell::from(Span::raw(block.header.block_height.to_string())),
+            Cell::from(Span::raw(format_block_height(
+                block.header.block_height,
+                confirmations,
+            ))),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Timestamp")),
@@ -575,7 +612,7 @@ pub fn render_invalid_blocks<'a>(
 
     let table = {
         if let Some(block) = selected_block {
-            render_block_table(&block)
+            render_block_table(&block, None)
         } else {
             render_empty_table()
         }
@@ -693,17 +730,21 @@ pub fn render_claim_data<'a>(claim: &Claim) -> Table<'a> {
     table
 }
 
-pub fn render_txn_data<'a>(txn: &Txn) -> Table<'a> {
+/// The txn's fields and where it stands against the confirmations the quorum
+/// needs from the `eligible` validators, as counted by `counting`.
+pub fn render_txn_data<'a>(
+    txn: &Txn,
+    quorum: &QuorumConfig,
+    eligible: &HashSet<String>,
+    counting: &str,
+) -> Table<'a> {
     let header_style = Style::default()
         .fg(Color::White)
         .add_modifier(Modifier::BOLD)
         .add_modifier(Modifier::UNDERLINED);
 
-    let mut confirmations = txn.validators.clone();
-    let mut rejections = txn.validators.clone();
-
-    confirmations.retain(|_, v| v.clone());
-    rejections.retain(|_, v| !v.clone());
+    let tally = QuorumConfig::tally(txn, eligible);
+    let active_validators = QuorumConfig::active_validators(eligible, counting);
 
     let table = Table::new(vec![
         Row::new(vec![
@@ -744,11 +785,21 @@ pub fn render_txn_data<'a>(txn: &Txn) -> Table<'a> {
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Confirmations")),
-            Cell::from(Span::raw(confirmations.len().to_string())),
+            Cell::from(Span::raw(tally.yes().to_string())),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Rejections")),
-            Cell::from(Span::raw(rejections.len().to_string())),
+            Cell::from(Span::raw(tally.no().to_string())),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Required Confirmations")),
+            Cell::from(Span::raw(quorum.required(active_validators).to_string())),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Status")),
+            Cell::from(Span::raw(
+                quorum.status(txn, eligible, counting).to_string(),
+            )),
         ]),
         Row::new(vec![
             Cell::from(Span::raw("Sender Nonce")),
@@ -869,6 +920,9 @@ pub fn render_txn_pool<'a>(
     txn_pool_status_list_state: &ListState,
     txn_pool_list_state: &ListState,
     txn_pool: &Pool<String, Txn>,
+    quorum: &QuorumConfig,
+    eligible: &HashSet<String>,
+    counting: &str,
 ) -> (List<'a>, List<'a>, Table<'a>) {
     let status_block = Block::default()
         .borders(Borders::ALL)
@@ -923,7 +977,7 @@ pub fn render_txn_pool<'a>(
 
                         if let Some(txn_id) = selected_txn_id {
                             if let Some(txn) = txn_pool.pending.get(&txn_id) {
-                                render_txn_data(txn)
+                                render_txn_data(txn, quorum, eligible, counting)
                             } else {
                                 render_empty_table()
                             }
@@ -963,7 +1017,7 @@ pub fn render_txn_pool<'a>(
 
                         if let Some(txn_id) = selected_txn_id {
                             if let Some(txn) = txn_pool.confirmed.get(&txn_id) {
-                                render_txn_data(txn)
+                                render_txn_data(txn, quorum, eligible, counting)
                             } else {
                                 render_empty_table()
                             }
@@ -1160,6 +1214,21 @@ pub fn render_reward_state<'a>(reward_state: &RewardState) -> Table<'a> {
                 reward_state.n_motherlodes_current_epoch.to_string(),
             )),
         ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Next Block Category Odds")),
+            Cell::from(Span::raw(
+                reward_state
+                    .category_distribution()
+                    .iter()
+                    .map(|(category, p)| format!("{:?}: {:.4}%", category, p * 100f64))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            )),
+        ]),
+        Row::new(vec![
+            Cell::from(Span::raw("Expected Block Reward")),
+            Cell::from(Span::raw(format!("{:.2}", reward_state.expected_reward()))),
+        ]),
     ])
     .header(Row::new(vec![
         Cell::from(Span::styled("Field", header_style)),
@@ -1226,9 +1295,3 @@ pub fn get_credits(ledger: &Ledger) -> LinkedHashMap<String, u128> {
 pub fn get_debits(ledger: &Ledger) -> LinkedHashMap<String, u128> {
     ledger.debits.clone()
 }
-
-pub fn read_from_json(path: &String) -> Result<Vec<VrrbNetworkEvent>, JsonError> {
-    let content = fs::read_to_string(path)?;
-    let parsed_json: Vec<VrrbNetworkEvent> = serde_json::from_str(&content)?;
-    Ok(parsed_json)
-}
@@ -1,21 +1,43 @@
 pub mod account;
+pub mod activity;
+pub mod audit;
+pub mod balance_proof;
 pub mod block;
 pub mod blockchain;
+pub mod blockchain_handler;
+pub mod checkpoint;
 pub mod claim;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod clock;
+pub mod entropy;
 pub mod fields;
 pub mod handler;
 pub mod header;
+#[cfg(feature = "cli")]
 pub mod helpers;
+pub mod logging;
+pub mod metrics;
 pub mod miner;
 pub mod network;
+pub mod params;
 pub mod pool;
+pub mod replay;
 pub mod reward;
+pub mod schedule;
+pub mod slashing;
+pub mod snapshot;
 pub mod state;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod token;
 pub mod txn;
 pub mod utils;
 pub mod validator;
 pub mod verifiable;
+pub mod wal;
 pub mod wallet;
+pub mod wallet_sync;
 
 // #[cfg(test)]
 // mod tests {
@@ -2550,18 +2572,95 @@ pub mod wallet;
 //     #[test]
 //     fn test_claim_update_after_homestead() {}
 
-//     #[test]
-//     fn test_mature_claim_valid_signature_mines_block() {}
-
-//     #[test]
-//     fn test_immature_claim_valid_signature_doesnt_mine_block() {}
-
-//     #[test]
-//     fn test_mature_claim_invalid_signature_doesnt_mine_block() {}
-
 //     #[test]
 //     fn test_claim_for_sale() {}
 
 //     #[test]
 //     fn test_claim_sold() {}
 //}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+    use crate::clock::{Clock, MockClock, SharedClock};
+    use crate::miner::{Miner, MiningCancel, SECOND};
+    use crate::reward::RewardState;
+    use crate::state::{NetworkState, SharedNetworkState};
+    use crate::test_utils::temp_path;
+    use crate::wallet::WalletAccount;
+
+    // A miner on a mock clock for `wallet`'s claim signing with `secret_key`,
+    // whose genesis block was mined at the clock's time.
+    fn genesis_miner(
+        state_path: &str,
+        wallet: &WalletAccount,
+        secret_key: String,
+    ) -> (Miner, MockClock) {
+        let _ = std::fs::remove_file(state_path);
+        let clock = MockClock::new(1_000 * SECOND);
+        let mut miner = Miner::start(
+            secret_key,
+            wallet.get_pubkey(),
+            "address".to_string(),
+            RewardState::start(),
+            SharedNetworkState::new(NetworkState::restore(state_path)),
+            1,
+        );
+        miner.time = SharedClock::new(clock.clone());
+        let mut genesis = Block::genesis(
+            &miner.reward_state,
+            miner.claim.clone(),
+            wallet.get_secretkey(),
+        )
+        .unwrap();
+        genesis.header.timestamp = clock.now();
+        miner.last_block = Some(genesis);
+        (miner, clock)
+    }
+
+    #[test]
+    fn test_mature_claim_valid_signature_mines_block() {
+        let state_path = temp_path("vrrb_test_mature_claim_state.db");
+        let wallet = WalletAccount::new();
+        let (mut miner, clock) = genesis_miner(&state_path, &wallet, wallet.get_secretkey());
+
+        clock.advance(SECOND);
+        let block = miner.mine(&MiningCancel::new()).unwrap();
+        assert_eq!(block.header.block_height, 1);
+        assert_eq!(block.header.timestamp, clock.now());
+        assert!(block.valid_block_signature());
+        assert!(!block.invalid_in_itself());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_immature_claim_valid_signature_doesnt_mine_block() {
+        let state_path = temp_path("vrrb_test_immature_claim_state.db");
+        let wallet = WalletAccount::new();
+        let (mut miner, clock) = genesis_miner(&state_path, &wallet, wallet.get_secretkey());
+
+        // The claim can't mine until a second has passed since the last block.
+        assert!(miner.mine(&MiningCancel::new()).is_none());
+        clock.advance(SECOND - 1);
+        assert!(miner.mine(&MiningCancel::new()).is_none());
+        clock.advance(1);
+        assert!(miner.mine(&MiningCancel::new()).is_some());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_mature_claim_invalid_signature_doesnt_mine_block() {
+        let state_path = temp_path("vrrb_test_mature_claim_invalid_signature_state.db");
+        let wallet = WalletAccount::new();
+        let other = WalletAccount::new();
+        let (mut miner, clock) = genesis_miner(&state_path, &wallet, other.get_secretkey());
+
+        // Signed with a key other than the claim's, the block is refused by
+        // every node however long the claim waited.
+        clock.advance(SECOND);
+        let block = miner.mine(&MiningCancel::new()).unwrap();
+        assert!(!block.valid_block_signature());
+        assert!(block.invalid_in_itself());
+        let _ = std::fs::remove_file(&state_path);
+    }
+}
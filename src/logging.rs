@@ -0,0 +1,304 @@
+use crate::utils::take_flag;
+use chrono::Utc;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use simplelog::{Config, WriteLogger};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+pub const LOG_LEVEL_FLAG: &str = "--log-level";
+pub const LOG_FORMAT_FLAG: &str = "--log-format";
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+// Log targets for the node's threads, so each can be turned up on its own.
+pub const BLOCKCHAIN_TARGET: &str = "vrrb::blockchain";
+pub const MINER_TARGET: &str = "vrrb::miner";
+pub const STATE_TARGET: &str = "vrrb::state";
+pub const SWARM_TARGET: &str = "vrrb::swarm";
+// Commands dropped after every attempt to hand them to a thread failed.
+pub const DEAD_LETTER_TARGET: &str = "vrrb::dead_letter";
+// Backpressure on the bounded channels between threads.
+pub const CHANNEL_TARGET: &str = "vrrb::channel";
+// Setting the level of this target sets the level of every target that
+// doesn't have one of its own.
+pub const DEFAULT_TARGET: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Plain,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogOptions {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+}
+
+/// Writes each log record to a file as a single line of JSON so logs can be
+/// ingested by a log pipeline.
+pub struct JsonLogger {
+    level: LevelFilter,
+    file: Mutex<File>,
+}
+
+/// The log level of each target, adjustable while the node is running. A
+/// target's level also applies to the targets nested under it, so setting
+/// "vrrb" covers "vrrb::miner" unless that has a level of its own.
+#[derive(Debug)]
+pub struct LogLevels {
+    default: AtomicUsize,
+    targets: RwLock<HashMap<String, AtomicUsize>>,
+}
+
+/// Passes on the records that LogLevels enables to the logger that writes them.
+pub struct FilteredLogger {
+    levels: Arc<LogLevels>,
+    inner: Box<dyn Log>,
+}
+
+fn level_from_usize(level: usize) -> LevelFilter {
+    match level {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Parses a log level such as "debug" or "WARN". Unknown levels fall back to
+/// Info rather than failing startup.
+pub fn parse_log_level(level: &str) -> LevelFilter {
+    match LevelFilter::from_str(level) {
+        Ok(level) => level,
+        Err(_) => {
+            println!(
+                "Invalid log level {:?}, defaulting to {}",
+                level, DEFAULT_LOG_LEVEL
+            );
+            DEFAULT_LOG_LEVEL
+        }
+    }
+}
+
+impl LogFormat {
+    pub fn from_str(format: &str) -> LogFormat {
+        match format.to_lowercase().as_str() {
+            "json" => LogFormat::Json,
+            _ => LogFormat::Plain,
+        }
+    }
+}
+
+impl LogOptions {
+    /// Removes the logging flags from the command line arguments and returns the
+    /// options along with the remaining positional arguments.
+    pub fn from_args(mut args: Vec<String>) -> (LogOptions, Vec<String>) {
+        let options = LogOptions {
+            level: take_flag(&mut args, LOG_LEVEL_FLAG)
+                .map_or(DEFAULT_LOG_LEVEL, |level| parse_log_level(&level)),
+            format: take_flag(&mut args, LOG_FORMAT_FLAG)
+                .map_or(LogFormat::Plain, |format| LogFormat::from_str(&format)),
+        };
+
+        (options, args)
+    }
+
+    /// Installs the logger, returning the levels that control it at runtime.
+    pub fn init(&self, file: File) -> Result<Arc<LogLevels>, SetLoggerError> {
+        let inner: Box<dyn Log> = match self.format {
+            LogFormat::Plain => WriteLogger::new(LevelFilter::Trace, Config::default(), file),
+            LogFormat::Json => Box::new(JsonLogger {
+                level: LevelFilter::Trace,
+                file: Mutex::new(file),
+            }),
+        };
+        let levels = Arc::new(LogLevels::new(self.level));
+        log::set_boxed_logger(Box::new(FilteredLogger::new(levels.clone(), inner)))?;
+        log::set_max_level(levels.max_level());
+
+        Ok(levels)
+    }
+}
+
+impl LogLevels {
+    pub fn new(default: LevelFilter) -> LogLevels {
+        LogLevels {
+            default: AtomicUsize::new(default as usize),
+            targets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the level of a target, or the default level for DEFAULT_TARGET,
+    /// and raises or lowers the global max level to match.
+    pub fn set(&self, target: &str, level: LevelFilter) {
+        if target == DEFAULT_TARGET {
+            self.default.store(level as usize, Ordering::Relaxed);
+        } else if let Ok(mut targets) = self.targets.write() {
+            targets
+                .entry(target.to_string())
+                .or_insert_with(|| AtomicUsize::new(0))
+                .store(level as usize, Ordering::Relaxed);
+        }
+
+        log::set_max_level(self.max_level());
+    }
+
+    /// The level of the most specific target set for `target`, or the
+    /// default level if none is.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let default = level_from_usize(self.default.load(Ordering::Relaxed));
+        let targets = match self.targets.read() {
+            Ok(targets) => targets,
+            Err(_) => return default,
+        };
+
+        targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target == prefix.as_str()
+                    || (target.starts_with(prefix.as_str())
+                        && target[prefix.len()..].starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| level_from_usize(level.load(Ordering::Relaxed)))
+            .unwrap_or(default)
+    }
+
+    /// The most verbose level of any target, records above it are dropped
+    /// by the log macros before they reach the logger.
+    pub fn max_level(&self) -> LevelFilter {
+        let default = level_from_usize(self.default.load(Ordering::Relaxed));
+        match self.targets.read() {
+            Ok(targets) => targets
+                .values()
+                .map(|level| level_from_usize(level.load(Ordering::Relaxed)))
+                .fold(default, std::cmp::max),
+            Err(_) => default,
+        }
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+}
+
+impl FilteredLogger {
+    pub fn new(levels: Arc<LogLevels>, inner: Box<dyn Log>) -> FilteredLogger {
+        FilteredLogger { levels, inner }
+    }
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.levels.enabled(metadata) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+impl JsonLogger {
+    pub fn format_record(record: &Record) -> String {
+        serde_json::json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+        .to_string()
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            if let Ok(mut file) = self.file.lock() {
+                let _ = writeln!(file, "{}", JsonLogger::format_record(record));
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn invalid_log_level_falls_back_to_info() {
+        assert_eq!(parse_log_level("verbose"), LevelFilter::Info);
+        assert_eq!(parse_log_level(""), LevelFilter::Info);
+        assert_eq!(parse_log_level("debug"), LevelFilter::Debug);
+        assert_eq!(parse_log_level("WARN"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn log_flags_are_removed_from_args() {
+        let args = vec![
+            "vrrb",
+            "--log-level",
+            "trace",
+            "peer",
+            "--log-format",
+            "json",
+        ]
+        .into_iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let (options, positional) = LogOptions::from_args(args);
+        assert_eq!(options.level, LevelFilter::Trace);
+        assert_eq!(options.format, LogFormat::Json);
+        assert_eq!(positional, vec!["vrrb".to_string(), "peer".to_string()]);
+    }
+
+    #[test]
+    fn levels_are_filtered_per_target() {
+        let levels = LogLevels::new(LevelFilter::Info);
+        let enabled = |level, target| {
+            levels.enabled(&Metadata::builder().level(level).target(target).build())
+        };
+        assert!(enabled(Level::Info, MINER_TARGET));
+        assert!(!enabled(Level::Debug, MINER_TARGET));
+
+        levels.set(MINER_TARGET, LevelFilter::Debug);
+        levels.set(SWARM_TARGET, LevelFilter::Error);
+        assert!(enabled(Level::Debug, MINER_TARGET));
+        assert!(enabled(Level::Debug, "vrrb::miner::pool"));
+        assert!(!enabled(Level::Debug, "vrrb::minerals"));
+        assert!(!enabled(Level::Debug, BLOCKCHAIN_TARGET));
+        assert!(!enabled(Level::Warn, SWARM_TARGET));
+        assert_eq!(levels.max_level(), LevelFilter::Debug);
+
+        levels.set("vrrb", LevelFilter::Trace);
+        assert!(enabled(Level::Trace, BLOCKCHAIN_TARGET));
+        assert!(!enabled(Level::Trace, MINER_TARGET));
+
+        levels.set(DEFAULT_TARGET, LevelFilter::Warn);
+        assert!(!enabled(Level::Info, "libp2p_gossipsub"));
+        assert!(enabled(Level::Warn, "libp2p_gossipsub"));
+        assert!(enabled(Level::Error, SWARM_TARGET));
+    }
+}
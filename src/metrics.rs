@@ -0,0 +1,219 @@
+use crate::block::Block;
+use crate::miner::MILLI;
+use std::collections::VecDeque;
+use std::fmt;
+
+// The number of blocks whose timings are kept, the oldest are dropped first.
+pub const BLOCK_METRICS_CAPACITY: usize = 256;
+
+/// How long a block took to reach this node and to be validated by it, in
+/// nanoseconds. Propagation is estimated from the header timestamp, so it
+/// includes any skew between the miner's clock and this node's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTiming {
+    pub block_height: u128,
+    pub propagation: u128,
+    pub validation: u128,
+}
+
+/// The timings of the most recent blocks received from peers, kept in a ring
+/// buffer so the node's memory use doesn't grow with the chain.
+#[derive(Debug, Clone)]
+pub struct BlockMetrics {
+    capacity: usize,
+    timings: VecDeque<BlockTiming>,
+}
+
+/// The p50 and p95 of the block timings held, in nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSummary {
+    pub blocks: usize,
+    pub propagation_p50: u128,
+    pub propagation_p95: u128,
+    pub validation_p50: u128,
+    pub validation_p95: u128,
+}
+
+/// How much the miner's pools hold, to see that garbage collection keeps them
+/// from growing. `txn_votes` counts the votes held on pool txns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolMetrics {
+    pub pending_txns: usize,
+    pub confirmed_txns: usize,
+    pub txn_votes: usize,
+    pub pending_claims: usize,
+    pub confirmed_claims: usize,
+    pub abandonment_votes: usize,
+}
+
+impl BlockMetrics {
+    pub fn new(capacity: usize) -> BlockMetrics {
+        BlockMetrics {
+            capacity,
+            timings: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records a block received at `received_at` whose validation finished at
+    /// `validated_at`. A header timestamp after the block arrived counts as no
+    /// propagation delay at all.
+    pub fn record(&mut self, block: &Block, received_at: u128, validated_at: u128) -> BlockTiming {
+        let timing = BlockTiming {
+            block_height: block.header.block_height,
+            propagation: received_at.saturating_sub(block.header.timestamp),
+            validation: validated_at.saturating_sub(received_at),
+        };
+        if self.capacity == 0 {
+            return timing;
+        }
+        while self.timings.len() >= self.capacity {
+            self.timings.pop_front();
+        }
+        self.timings.push_back(timing);
+
+        timing
+    }
+
+    pub fn timings(&self) -> &VecDeque<BlockTiming> {
+        &self.timings
+    }
+
+    /// None until a block has been recorded.
+    pub fn summary(&self) -> Option<MetricsSummary> {
+        let propagation: Vec<u128> = self.timings.iter().map(|t| t.propagation).collect();
+        let validation: Vec<u128> = self.timings.iter().map(|t| t.validation).collect();
+
+        Some(MetricsSummary {
+            blocks: self.timings.len(),
+            propagation_p50: percentile(&propagation, 50)?,
+            propagation_p95: percentile(&propagation, 95)?,
+            validation_p50: percentile(&validation, 50)?,
+            validation_p95: percentile(&validation, 95)?,
+        })
+    }
+}
+
+impl Default for BlockMetrics {
+    fn default() -> BlockMetrics {
+        BlockMetrics::new(BLOCK_METRICS_CAPACITY)
+    }
+}
+
+/// The nearest-rank percentile `p`, from 1 to 100, of the samples: the
+/// smallest sample at least p% of them are less than or equal to. None
+/// without samples.
+pub fn percentile(samples: &[u128], p: usize) -> Option<u128> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let rank = std::cmp::max((p.min(100) * sorted.len() + 99) / 100, 1);
+
+    Some(sorted[rank - 1])
+}
+
+impl fmt::Display for MetricsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "last {} blocks: propagation p50={}ms p95={}ms, validation p50={}ms p95={}ms",
+            self.blocks,
+            self.propagation_p50 / MILLI,
+            self.propagation_p95 / MILLI,
+            self.validation_p50 / MILLI,
+            self.validation_p95 / MILLI
+        )
+    }
+}
+
+impl fmt::Display for PoolMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "txns pending={} confirmed={} votes={}, claims pending={} confirmed={}, \
+            abandonment votes={}",
+            self.pending_txns,
+            self.confirmed_txns,
+            self.txn_votes,
+            self.pending_claims,
+            self.confirmed_claims,
+            self.abandonment_votes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claim::Claim;
+    use crate::reward::RewardState;
+    use crate::wallet::WalletAccount;
+
+    fn block_at(height: u128, timestamp: u128) -> Block {
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.get_pubkey(), "address".to_string(), 1);
+        let mut block =
+            Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap();
+        block.header.block_height = height;
+        block.header.timestamp = timestamp;
+        block
+    }
+
+    #[test]
+    fn propagation_is_measured_from_the_header_timestamp() {
+        let mut metrics = BlockMetrics::default();
+        let mined_at = 1_000 * MILLI;
+
+        let timing = metrics.record(
+            &block_at(1, mined_at),
+            mined_at + 250 * MILLI,
+            mined_at + 270 * MILLI,
+        );
+        assert_eq!(
+            timing,
+            BlockTiming {
+                block_height: 1,
+                propagation: 250 * MILLI,
+                validation: 20 * MILLI,
+            }
+        );
+
+        // A miner's clock running ahead doesn't make propagation negative.
+        let timing = metrics.record(&block_at(2, mined_at), mined_at - MILLI, mined_at);
+        assert_eq!(timing.propagation, 0);
+        assert_eq!(timing.validation, MILLI);
+        assert_eq!(metrics.timings().len(), 2);
+    }
+
+    #[test]
+    fn percentiles_follow_the_nearest_rank() {
+        let samples = vec![35, 20, 50, 15, 40];
+        assert_eq!(percentile(&samples, 50), Some(35));
+        assert_eq!(percentile(&samples, 95), Some(50));
+        assert_eq!(percentile(&samples, 1), Some(15));
+        assert_eq!(percentile(&samples, 100), Some(50));
+
+        let samples: Vec<u128> = (1..=20).collect();
+        assert_eq!(percentile(&samples, 50), Some(10));
+        assert_eq!(percentile(&samples, 95), Some(19));
+        assert_eq!(percentile(&[], 50), None);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_only_the_latest_blocks() {
+        let mut metrics = BlockMetrics::new(3);
+        assert!(metrics.summary().is_none());
+        (1..=5).for_each(|height| {
+            metrics.record(&block_at(height, 0), height * MILLI, height * MILLI);
+        });
+
+        let heights: Vec<u128> = metrics.timings().iter().map(|t| t.block_height).collect();
+        assert_eq!(heights, vec![3, 4, 5]);
+        let summary = metrics.summary().unwrap();
+        assert_eq!(summary.blocks, 3);
+        assert_eq!(summary.propagation_p50, 4 * MILLI);
+        assert_eq!(summary.propagation_p95, 5 * MILLI);
+        assert_eq!(summary.validation_p95, 0);
+    }
+}
@@ -1,25 +1,63 @@
 use crate::block::Block;
-use crate::claim::Claim;
+use crate::claim::{lowest_pointer, Claim};
+use crate::clock::{Clock, SharedClock};
 use crate::header::BlockHeader;
+use crate::logging::MINER_TARGET;
+use crate::metrics::PoolMetrics;
+use crate::network::clock::ClockSkew;
+use crate::network::forwarding::SeenTxns;
+use crate::network::message_types::MessageType;
+use crate::network::voting::{VoteOutcome, VoteTally};
+use crate::params::ProtocolParams;
 use crate::pool::{Pool, PoolKind};
 use crate::reward::RewardState;
-use crate::state::NetworkState;
-use crate::txn::Txn;
+use crate::slashing::SlashingEvidence;
+use crate::state::SharedNetworkState;
+use crate::txn::{txn_cost, Txn, MIN_TXN_FEE};
+use crate::utils::take_flag;
 use crate::validator::TxnValidator;
 use crate::verifiable::Verifiable;
+use crate::wallet::TxnStatus;
+use log::{info, warn};
 use ritelinked::LinkedHashMap;
 use serde::{Deserialize, Serialize};
-use sha256::digest_bytes;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, RwLock};
 
-pub const VALIDATOR_THRESHOLD: f64 = 0.60;
+pub const VALIDATOR_THRESHOLD_FLAG: &str = "--validator-threshold";
+pub const MIN_RELAY_FEE_FLAG: &str = "--min-relay-fee";
+pub const ASSEMBLY_WINDOW_FLAG: &str = "--assembly-window";
+pub const ASSEMBLY_MIN_TXNS_FLAG: &str = "--assembly-min-txns";
+pub const ELECTION_RETRY_FLAG: &str = "--election-retry";
+// How long, in milliseconds, a miner that wins with too few confirmed txns
+// waits for more before assembling its block.
+pub const ASSEMBLY_WINDOW: u128 = 500;
+// A miner with at least this many confirmed txns assembles its block at once.
+pub const ASSEMBLY_MIN_TXNS: usize = 1;
+// How long, in milliseconds, a miner that lost the pointer election waits
+// before checking it again, unless the block it lost to is confirmed first.
+pub const ELECTION_RETRY_INTERVAL: u128 = 1000;
+pub const ABANDONMENT_ROUNDS_FLAG: &str = "--abandonment-rounds";
+// A claim is voted abandoned once this many abandonment timeouts pass without
+// the block it should have mined.
+pub const ABANDONMENT_ROUNDS: u128 = 1;
 pub const NANO: u128 = 1;
 pub const MICRO: u128 = NANO * 1000;
 pub const MILLI: u128 = MICRO * 1000;
 pub const SECOND: u128 = MILLI * 1000;
+// The cache is cleared rather than growing past this many pointers.
+pub const MAX_CACHED_POINTERS: usize = 100_000;
+// The pools are garbage collected every this many confirmed blocks, or sooner
+// once they hold this many txns.
+pub const POOL_GC_INTERVAL: u128 = 10;
+pub const POOL_GC_THRESHOLD: usize = 10_000;
+// The most finalized txn ids remembered to keep them out of the pools.
+pub const FINALIZED_TXNS_CAPACITY: usize = 100_000;
+// The most blocks from other miners kept for the election pacing, the oldest
+// is dropped to make room.
+pub const MAX_SEEN_BLOCKS: usize = 64;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MinerStatus {
@@ -31,6 +69,111 @@ pub enum MinerStatus {
 #[derive(Debug)]
 pub struct NoLowestPointerError(String);
 
+/// When a miner votes the claim due to mine the next block abandoned. The
+/// time is measured from the last confirmed block's header timestamp, so a
+/// node's own clock only counts through the skew estimated from its peers,
+/// and `rounds` of the network's abandonment timeouts have to pass without a
+/// block. A claim is voted on at most once per timeout. The timeout itself is
+/// a protocol parameter, a node can only wait longer than it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AbandonmentConfig {
+    pub rounds: u128,
+}
+
+/// A digest of the miner's claim map that doesn't depend on its order. Every
+/// claim is hashed on its own and kept by its pubkey, sorted, and the digest
+/// is a hash over those claim hashes in order. Inserting, removing or
+/// nonce-ing up a claim only rehashes that claim, the digest over the claim
+/// hashes is cached and only worked out again after a change.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClaimMapHash {
+    entries: BTreeMap<String, [u8; 32]>,
+    // The digest over the entries, None once they changed since.
+    #[serde(skip)]
+    root: Option<[u8; 32]>,
+    #[serde(skip)]
+    recomputes: usize,
+}
+
+/// The share of active validators that must vote a txn valid to confirm it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QuorumConfig {
+    pub threshold: f64,
+}
+
+/// Where a txn stands against the confirmations it needs from the active
+/// validators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfirmationStatus {
+    Confirmed,
+    Unconfirmed,
+    // enough validators voted against it that it can't be confirmed
+    Rejected,
+}
+
+/// How long a miner that wins the pointer election with fewer than
+/// `min_txns` confirmed txns waits for validators to confirm more before
+/// assembling its block, so quiet networks don't mine empty blocks while txns
+/// are a vote away from being confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AssemblyWindow {
+    pub window_ms: u128,
+    pub min_txns: usize,
+}
+
+// The wait for txns at the height being mined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AssemblyWait {
+    height: u128,
+    opened_at: u128,
+    done: bool,
+}
+
+/// Paces a miner around the pointer election. A miner that lost the election
+/// for a height sits out until the block at that height is confirmed or
+/// `retry_ms` passes, instead of asking itself to mine again at once. Valid
+/// blocks seen from other miners are kept until their height is confirmed, so
+/// a winner doesn't broadcast a duplicate for a height a valid block covers.
+#[derive(Debug, Clone)]
+pub struct ElectionPacing {
+    pub retry_ms: u128,
+    lost: Option<LostElection>,
+    seen: LinkedHashMap<String, Block>,
+}
+
+// The height the miner lost the election for and when it last checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LostElection {
+    height: u128,
+    lost_at: u128,
+}
+
+/// Claim pointers keyed by claim hash and nonce, so that repeated mining
+/// attempts for the same nonce don't recompute a pointer for every claim.
+/// Nonce-ing up changes every claim's hash, so the cache is cleared then.
+#[derive(Debug, Clone, Default)]
+pub struct PointerCache {
+    pointers: HashMap<(String, u128), Option<u128>>,
+    computes: usize,
+}
+
+/// The height of the last confirmed block, shared between the blockchain
+/// thread and the miner. Blocks are confirmed while the miner is assembling
+/// its own, an attempt at or below the confirmed height is abandoned between
+/// phases instead of broadcasting a stale block.
+#[derive(Debug, Clone, Default)]
+pub struct MiningCancel {
+    confirmed_height: Arc<RwLock<Option<u128>>>,
+}
+
+/// The pools a miner writes out on shutdown, they're otherwise only held in
+/// memory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedPools {
+    txn_pool: Pool<String, Txn>,
+    claim_pool: Pool<String, Claim>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Miner {
     pub claim: Claim,
@@ -40,27 +183,419 @@ pub struct Miner {
     pub claim_pool: Pool<String, Claim>,
     pub last_block: Option<Block>,
     pub reward_state: RewardState,
-    pub network_state: NetworkState,
+    pub network_state: SharedNetworkState,
     pub neighbors: Option<Vec<BlockHeader>>,
     pub current_nonce_timer: u128,
     pub n_miners: u128,
     pub init: bool,
     pub abandoned_claim_counter: LinkedHashMap<String, Claim>,
     pub abandoned_claim: Option<Claim>,
+    pub claim_map_digest: ClaimMapHash,
+    // The nonce epoch the claim and claim map are on, the ledger's is the
+    // one that counts.
+    #[serde(default)]
+    pub nonce_epoch: u128,
+    #[serde(skip)]
+    pub pointer_cache: PointerCache,
+    #[serde(default)]
+    pub quorum: QuorumConfig,
+    #[serde(skip)]
+    pub clock: ClockSkew,
+    // Where the miner reads the time from, the system clock outside of tests.
+    #[serde(skip)]
+    pub time: SharedClock,
+    // Txns paying a lower fee aren't admitted to the pool.
+    #[serde(default = "default_min_relay_fee")]
+    pub min_relay_fee: u128,
+    #[serde(skip)]
+    pub assembly: AssemblyWindow,
+    #[serde(skip)]
+    assembly_wait: Option<AssemblyWait>,
+    // A paused miner keeps its pools and state but mines nothing until it's
+    // resumed, a restarted node isn't paused.
+    #[serde(skip)]
+    pub paused: bool,
+    #[serde(skip)]
+    pub pacing: ElectionPacing,
+    #[serde(default)]
+    pub abandonment: AbandonmentConfig,
+    // The network time this miner last voted each claim hash abandoned.
+    #[serde(skip)]
+    abandonment_votes: LinkedHashMap<String, u128>,
+    // Txns in blocks past the finality depth, dropped from the pools by the
+    // next garbage collection and not let back in.
+    #[serde(skip, default = "default_finalized_txns")]
+    pub finalized_txns: SeenTxns,
+    #[serde(skip)]
+    blocks_since_gc: u128,
     secret_key: String,
 }
 
+fn default_min_relay_fee() -> u128 {
+    MIN_TXN_FEE
+}
+
+fn default_finalized_txns() -> SeenTxns {
+    SeenTxns::new(FINALIZED_TXNS_CAPACITY)
+}
+
+impl ClaimMapHash {
+    pub fn from_claim_map(claim_map: &LinkedHashMap<String, Claim>) -> ClaimMapHash {
+        let mut claim_map_hash = ClaimMapHash::default();
+        claim_map.iter().for_each(|(pubkey, claim)| {
+            claim_map_hash.insert(pubkey, claim);
+        });
+        claim_map_hash.refresh();
+
+        claim_map_hash
+    }
+
+    pub fn entry_digest(pubkey: &str, claim: &Claim) -> [u8; 32] {
+        let payload = format!(
+            "{},{},{},{},{}",
+            pubkey, claim.address, claim.hash, claim.nonce, claim.eligible
+        );
+        *blake3::hash(payload.as_bytes()).as_bytes()
+    }
+
+    pub fn insert(&mut self, pubkey: &str, claim: &Claim) {
+        let digest = ClaimMapHash::entry_digest(pubkey, claim);
+        if self.entries.insert(pubkey.to_string(), digest) != Some(digest) {
+            self.root = None;
+        }
+    }
+
+    pub fn remove(&mut self, pubkey: &str) {
+        if self.entries.remove(pubkey).is_some() {
+            self.root = None;
+        }
+    }
+
+    /// The cached digest, or the digest over the claim hashes if they changed
+    /// since it was last cached.
+    pub fn digest(&self) -> String {
+        let root = self
+            .root
+            .unwrap_or_else(|| ClaimMapHash::root(self.entries.values()));
+        hex::encode(root)
+    }
+
+    /// Caches the digest after a batch of changes.
+    pub fn refresh(&mut self) {
+        if self.root.is_none() {
+            self.root = Some(ClaimMapHash::root(self.entries.values()));
+        }
+    }
+
+    /// The digest the claim map would have with the `removed` pubkeys taken
+    /// out and `changes` inserted, leaving this one as it is. Only the changed
+    /// claims are hashed.
+    pub fn digest_after(
+        &self,
+        changes: &LinkedHashMap<String, Claim>,
+        removed: &[String],
+    ) -> String {
+        let mut entries: BTreeMap<&str, [u8; 32]> = self
+            .entries
+            .iter()
+            .filter(|(pubkey, _)| !removed.contains(*pubkey))
+            .map(|(pubkey, digest)| (pubkey.as_str(), *digest))
+            .collect();
+        changes.iter().for_each(|(pubkey, claim)| {
+            entries.insert(pubkey.as_str(), ClaimMapHash::entry_digest(pubkey, claim));
+        });
+
+        hex::encode(ClaimMapHash::root(entries.values()))
+    }
+
+    /// Throws away the cached entries and rebuilds the digest from the claim map.
+    /// This is the slow path, the miner should never need it while mining.
+    pub fn recompute(&mut self, claim_map: &LinkedHashMap<String, Claim>) -> String {
+        let recomputes = self.recomputes + 1;
+        *self = ClaimMapHash::from_claim_map(claim_map);
+        self.recomputes = recomputes;
+        self.digest()
+    }
+
+    pub fn n_recomputes(&self) -> usize {
+        self.recomputes
+    }
+
+    // The claim hashes are all 32 bytes and each one covers its pubkey, so
+    // hashing them one after the other in pubkey order can't be ambiguous.
+    fn root<'a>(digests: impl Iterator<Item = &'a [u8; 32]>) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        digests.for_each(|digest| {
+            hasher.update(digest);
+        });
+        *hasher.finalize().as_bytes()
+    }
+}
+
+impl QuorumConfig {
+    /// Removes the validator threshold flag from the command line arguments,
+    /// falling back to the network's threshold if it is missing or not in
+    /// (0, 1].
+    pub fn from_args(mut args: Vec<String>) -> (QuorumConfig, Vec<String>) {
+        let mut quorum = QuorumConfig::default();
+        if let Some(threshold) =
+            take_flag(&mut args, VALIDATOR_THRESHOLD_FLAG).and_then(|t| t.parse::<f64>().ok())
+        {
+            if threshold > 0.0 && threshold <= 1.0 {
+                quorum.threshold = threshold;
+            }
+        }
+
+        (quorum, args)
+    }
+
+    /// The votes needed to confirm a txn, ceil(threshold * active_validators),
+    /// and never less than one.
+    pub fn required(&self, active_validators: usize) -> usize {
+        let required = (self.threshold * active_validators as f64).ceil() as usize;
+        std::cmp::max(required, 1)
+    }
+
+    /// The pubkeys of the eligible claims in `claims`, the validators whose
+    /// votes count.
+    pub fn eligible_validators(claims: &LinkedHashMap<String, Claim>) -> HashSet<String> {
+        claims
+            .iter()
+            .filter(|(_, claim)| claim.eligible)
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect()
+    }
+
+    /// The validators expected to vote on a txn, the `eligible` claim holders
+    /// other than `counting`, the node or block miner doing the count.
+    pub fn active_validators(eligible: &HashSet<String>, counting: &str) -> usize {
+        eligible.iter().filter(|pubkey| *pubkey != counting).count()
+    }
+
+    /// The votes on a txn that count, those cast by `eligible` claim holders.
+    pub fn tally(txn: &Txn, eligible: &HashSet<String>) -> VoteTally {
+        VoteTally::from_votes(
+            txn.validators
+                .iter()
+                .filter(|(pubkey, _)| eligible.contains(*pubkey)),
+        )
+    }
+
+    /// Confirmed once the txn has the required yes votes from eligible claim
+    /// holders, rejected once too few of them, `counting` included, are left
+    /// to give it them. Votes from any other pubkey aren't counted.
+    pub fn status(
+        &self,
+        txn: &Txn,
+        eligible: &HashSet<String>,
+        counting: &str,
+    ) -> ConfirmationStatus {
+        let active_validators = QuorumConfig::active_validators(eligible, counting);
+        QuorumConfig::tally(txn, eligible)
+            .status(self.required(active_validators), active_validators)
+    }
+}
+
+impl Default for QuorumConfig {
+    fn default() -> QuorumConfig {
+        QuorumConfig {
+            threshold: ProtocolParams::default().validator_share(),
+        }
+    }
+}
+
+impl AssemblyWindow {
+    /// Removes the assembly window flags from the command line arguments,
+    /// falling back to ASSEMBLY_WINDOW and ASSEMBLY_MIN_TXNS for missing or
+    /// invalid values.
+    pub fn from_args(mut args: Vec<String>) -> (AssemblyWindow, Vec<String>) {
+        let mut assembly = AssemblyWindow::default();
+        if let Some(window_ms) =
+            take_flag(&mut args, ASSEMBLY_WINDOW_FLAG).and_then(|w| w.parse::<u128>().ok())
+        {
+            assembly.window_ms = window_ms;
+        }
+        if let Some(min_txns) =
+            take_flag(&mut args, ASSEMBLY_MIN_TXNS_FLAG).and_then(|n| n.parse::<usize>().ok())
+        {
+            assembly.min_txns = min_txns;
+        }
+
+        (assembly, args)
+    }
+
+    /// The window in nanoseconds.
+    pub fn window(&self) -> u128 {
+        self.window_ms.saturating_mul(MILLI)
+    }
+}
+
+impl Default for AssemblyWindow {
+    fn default() -> AssemblyWindow {
+        AssemblyWindow {
+            window_ms: ASSEMBLY_WINDOW,
+            min_txns: ASSEMBLY_MIN_TXNS,
+        }
+    }
+}
+
+impl ElectionPacing {
+    /// Removes the election retry flag from the command line arguments,
+    /// falling back to ELECTION_RETRY_INTERVAL for a missing or invalid value.
+    pub fn from_args(mut args: Vec<String>) -> (ElectionPacing, Vec<String>) {
+        let mut pacing = ElectionPacing::default();
+        if let Some(retry_ms) =
+            take_flag(&mut args, ELECTION_RETRY_FLAG).and_then(|r| r.parse::<u128>().ok())
+        {
+            pacing.retry_ms = retry_ms;
+        }
+
+        (pacing, args)
+    }
+
+    /// The retry interval in nanoseconds.
+    pub fn retry_interval(&self) -> u128 {
+        self.retry_ms.saturating_mul(MILLI)
+    }
+
+    /// Records that the miner lost the election for `height` at `now`.
+    pub fn lost(&mut self, height: u128, now: u128) {
+        self.lost = Some(LostElection {
+            height,
+            lost_at: now,
+        });
+    }
+
+    /// Whether the miner lost the election and its retry interval hasn't run
+    /// out at `now`, requests to mine are dropped until then.
+    pub fn sitting_out(&self, now: u128) -> bool {
+        match self.lost {
+            Some(lost) => now.saturating_sub(lost.lost_at) < self.retry_interval(),
+            None => false,
+        }
+    }
+
+    /// True once, when the retry interval of a lost election runs out at
+    /// `now` without the block at its height being confirmed.
+    pub fn retry_due(&mut self, now: u128) -> bool {
+        if self.lost.is_some() && !self.sitting_out(now) {
+            self.lost = None;
+            return true;
+        }
+
+        false
+    }
+
+    /// A block at `height` was confirmed: a miner that lost the election for
+    /// it can try for the next one, and the blocks seen up to it are dropped.
+    pub fn confirmed(&mut self, height: u128) {
+        if let Some(lost) = self.lost {
+            if lost.height <= height {
+                self.lost = None;
+            }
+        }
+        self.seen
+            .retain(|_, block| block.header.block_height > height);
+    }
+
+    /// Keeps a block received from another miner, that was already found
+    /// valid, until its height is confirmed. At most MAX_SEEN_BLOCKS are kept.
+    pub fn saw_block(&mut self, block: &Block) {
+        if !self.seen.contains_key(&block.hash) && self.seen.len() >= MAX_SEEN_BLOCKS {
+            self.seen.pop_front();
+        }
+        self.seen.insert(block.hash.clone(), block.clone());
+    }
+
+    /// Whether to broadcast a block the miner just mined. It's suppressed if a
+    /// valid block from another miner at the same height was seen.
+    pub fn should_broadcast(&self, block: &Block) -> bool {
+        !self.seen.values().any(|seen| {
+            seen.header.block_height == block.header.block_height && seen.hash != block.hash
+        })
+    }
+}
+
+impl Default for ElectionPacing {
+    fn default() -> ElectionPacing {
+        ElectionPacing {
+            retry_ms: ELECTION_RETRY_INTERVAL,
+            lost: None,
+            seen: LinkedHashMap::new(),
+        }
+    }
+}
+
+impl PointerCache {
+    pub fn get_pointer(&mut self, claim: &Claim, nonce: u128) -> Option<u128> {
+        let key = (claim.hash.clone(), nonce);
+        if let Some(pointer) = self.pointers.get(&key) {
+            return *pointer;
+        }
+
+        if self.pointers.len() >= MAX_CACHED_POINTERS {
+            self.pointers.clear();
+        }
+        self.computes += 1;
+        let pointer = claim.get_pointer(nonce);
+        self.pointers.insert(key, pointer);
+        pointer
+    }
+
+    pub fn clear(&mut self) {
+        self.pointers.clear();
+    }
+
+    pub fn n_computes(&self) -> usize {
+        self.computes
+    }
+}
+
+impl MiningCancel {
+    pub fn new() -> MiningCancel {
+        MiningCancel::default()
+    }
+
+    /// Records a block confirmed at `height`, cancelling any attempt to mine
+    /// a block at or below it.
+    pub fn block_confirmed(&self, height: u128) {
+        if let Ok(mut confirmed_height) = self.confirmed_height.write() {
+            if confirmed_height.map_or(true, |confirmed| height > confirmed) {
+                *confirmed_height = Some(height);
+            }
+        }
+    }
+
+    /// Whether an attempt to mine a block at `height` has been cancelled.
+    pub fn cancels(&self, height: u128) -> bool {
+        self.confirmed_height
+            .read()
+            .map(|confirmed_height| confirmed_height.map_or(false, |confirmed| confirmed >= height))
+            .unwrap_or(false)
+    }
+}
+
 impl Miner {
     pub fn start(
         secret_key: String,
         pubkey: String,
         address: String,
         reward_state: RewardState,
-        network_state: NetworkState,
+        network_state: SharedNetworkState,
         n_miners: u128,
     ) -> Miner {
-        let miner = Miner {
-            claim: Claim::new(pubkey.clone(), address, 1),
+        // A miner restarted part way through a nonce up picks up the claims
+        // and epoch the ledger committed, whatever it had in memory.
+        let (nonce_epoch, claim_map) = {
+            let network_state = network_state.read();
+            (network_state.nonce_epoch(), network_state.get_claims())
+        };
+        let claim = match claim_map.get(&pubkey) {
+            Some(claim) => claim.clone(),
+            None => Claim::new(pubkey.clone(), address, 1 + nonce_epoch),
+        };
+        let mut miner = Miner {
+            claim,
             mining: false,
             claim_map: LinkedHashMap::new(),
             txn_pool: Pool::new(PoolKind::Txn),
@@ -74,39 +609,61 @@ impl Miner {
             init: false,
             abandoned_claim_counter: LinkedHashMap::new(),
             abandoned_claim: None,
+            claim_map_digest: ClaimMapHash::default(),
+            nonce_epoch,
+            pointer_cache: PointerCache::default(),
+            quorum: QuorumConfig::default(),
+            clock: ClockSkew::new(),
+            time: SharedClock::default(),
+            min_relay_fee: MIN_TXN_FEE,
+            assembly: AssemblyWindow::default(),
+            assembly_wait: None,
+            paused: false,
+            pacing: ElectionPacing::default(),
+            abandonment: AbandonmentConfig::default(),
+            abandonment_votes: LinkedHashMap::new(),
+            finalized_txns: default_finalized_txns(),
+            blocks_since_gc: 0,
             secret_key,
         };
+        miner.set_claim_map(claim_map);
 
         miner
     }
 
+    /// Claims whose owners are offline as of the last block sit out the
+    /// election, as they do when the block is validated.
     pub fn get_lowest_pointer(&mut self, nonce: u128) -> Option<(String, u128)> {
-        let claim_map = self.claim_map.clone();
-        let mut pointers = claim_map
-            .iter()
-            .map(|(_, claim)| return (claim.clone().hash, claim.clone().get_pointer(nonce)))
-            .collect::<Vec<_>>();
-
-        pointers.retain(|(_, v)| !v.is_none());
-
-        let mut raw_pointers = pointers
-            .iter()
-            .map(|(k, v)| {
-                return (k.clone(), v.unwrap());
-            })
-            .collect::<Vec<_>>();
+        let away = self.last_block.as_ref().map_or(vec![], |block| {
+            self.network_state
+                .read()
+                .claims_away(block.header.timestamp)
+        });
+        let pointer_cache = &mut self.pointer_cache;
+        lowest_pointer(
+            self.claim_map
+                .values()
+                .filter(|claim| !away.contains(&claim.pubkey))
+                .filter_map(|claim| {
+                    pointer_cache
+                        .get_pointer(claim, nonce)
+                        .map(|pointer| (claim.hash.clone(), pointer))
+                }),
+        )
+    }
 
-        if let Some(min) = raw_pointers.clone().iter().min_by_key(|(_, v)| v) {
-            raw_pointers.retain(|(_, v)| *v == min.1);
-            Some(raw_pointers[0].clone())
-        } else {
-            None
-        }
+    /// Whether the claim of `pubkey` is exempt from being abandoned at `now`,
+    /// its owner having announced downtime covering it.
+    pub fn abandonment_suppressed(&self, pubkey: &str, now: u128) -> bool {
+        self.network_state
+            .read()
+            .downtime_until(pubkey, now)
+            .is_some()
     }
 
     pub fn check_my_claim(&mut self, nonce: u128) -> Result<bool, Box<dyn Error>> {
-        if let Some((hash, _)) = self.clone().get_lowest_pointer(nonce) {
-            return Ok(hash == self.clone().claim.hash);
+        if let Some((hash, _)) = self.get_lowest_pointer(nonce) {
+            return Ok(hash == self.claim.hash);
         } else {
             Err(
                 Box::new(
@@ -117,8 +674,7 @@ impl Miner {
     }
 
     pub fn genesis(&mut self) -> Option<Block> {
-        self.claim_map
-            .insert(self.claim.pubkey.clone(), self.claim.clone());
+        self.insert_claim(self.claim.pubkey.clone(), self.claim.clone());
         Block::genesis(
             &self.reward_state.clone(),
             self.claim.clone(),
@@ -126,176 +682,782 @@ impl Miner {
         )
     }
 
-    pub fn mine(&mut self) -> Option<Block> {
-        let claim_map_hash =
-            digest_bytes(serde_json::to_string(&self.claim_map).unwrap().as_bytes());
+    /// Mines a block on top of the last block, None if the miner is paused,
+    /// it's too soon after the last block or a block at the same height is
+    /// confirmed before it's done.
+    pub fn mine(&mut self, cancel: &MiningCancel) -> Option<Block> {
+        if self.paused {
+            return None;
+        }
         if let Some(last_block) = self.last_block.clone() {
-            return Block::mine(
-                self.clone().claim,
-                last_block.clone(),
-                self.clone().txn_pool.confirmed.clone(),
-                self.clone().claim_pool.confirmed.clone(),
-                Some(claim_map_hash),
-                &self.clone().reward_state.clone(),
-                &self.clone().network_state.clone(),
-                self.clone().neighbors.clone(),
-                self.abandoned_claim.clone(),
-                self.secret_key.clone(),
-            );
+            let height = last_block.header.block_height + 1;
+            let block = if cancel.cancels(height) {
+                None
+            } else {
+                let txns = self.select_txns();
+                let claim_map_hash = Block::claim_map_hash_after(
+                    &self.network_state.read(),
+                    &self.claim,
+                    &self.claim_pool.confirmed,
+                    &txns,
+                    height,
+                );
+                Block::mine(
+                    self.clone().claim,
+                    last_block.clone(),
+                    txns,
+                    self.clone().claim_pool.confirmed.clone(),
+                    Some(claim_map_hash),
+                    &self.network_state.read(),
+                    self.clone().neighbors.clone(),
+                    self.abandoned_claim.clone(),
+                    self.secret_key.clone(),
+                    self.get_timestamp(),
+                    || cancel.cancels(height),
+                )
+            };
+            if block.is_none() && cancel.cancels(height) {
+                info!(
+                    target: MINER_TARGET,
+                    "block assembly cancelled height={}", height
+                );
+            }
+            return block;
         }
 
         None
     }
 
-    pub fn nonce_up(&mut self) {
-        self.claim.nonce_up();
-        let mut new_claim_map = LinkedHashMap::new();
-        self.claim_map.clone().iter().for_each(|(pk, claim)| {
-            let mut new_claim = claim.clone();
-            new_claim.nonce_up();
-            new_claim_map.insert(pk.clone(), new_claim.clone());
-        });
-        self.claim_map = new_claim_map;
+    /// Whether the miner should be asked to mine the next block.
+    pub fn should_mine(&self) -> bool {
+        self.mining && !self.paused
     }
 
-    pub fn process_txn(&mut self, mut txn: Txn) -> TxnValidator {
-        if let Some(_txn) = self.txn_pool.confirmed.get(&txn.txn_id) {
-            // Nothing really to do here
-        } else if let Some(txn) = self.txn_pool.pending.get(&txn.txn_id) {
-            // add validator if you have not validated already
-            if let None = txn.validators.clone().get(&self.claim.pubkey) {
-                let mut txn = txn.clone();
-                txn.validators.insert(
-                    self.claim.pubkey.clone(),
-                    txn.valid_txn(&self.network_state, &self.txn_pool),
-                );
-                self.txn_pool
-                    .pending
-                    .insert(txn.txn_id.clone(), txn.clone());
-            }
-        } else {
-            // add validator
-            txn.validators.insert(
-                self.claim.pubkey.clone(),
-                txn.valid_txn(&self.network_state, &self.txn_pool),
-            );
-            self.txn_pool
-                .pending
-                .insert(txn.txn_id.clone(), txn.clone());
+    /// Stops mining without dropping the pools or the claim map. A wait for
+    /// txns that was under way starts over once the miner is resumed.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.assembly_wait = None;
+    }
+
+    /// Resumes a paused miner and returns the claim message to announce it
+    /// with, None if it wasn't paused.
+    pub fn resume(&mut self, sender_id: String) -> Option<MessageType> {
+        if !self.paused {
+            return None;
         }
+        self.paused = false;
 
-        return TxnValidator::new(
-            self.claim.pubkey.clone(),
-            txn.clone(),
-            &self.network_state,
-            &self.txn_pool,
-        );
+        Some(MessageType::ClaimMessage {
+            claim: self.claim.clone(),
+            sender_id,
+        })
     }
 
-    pub fn process_txn_validator(&mut self, txn_validator: TxnValidator) {
-        if let Some(_txn) = self.txn_pool.confirmed.get(&txn_validator.txn.txn_id) {
-        } else if let Some(txn) = self.txn_pool.pending.get_mut(&txn_validator.txn.txn_id) {
-            txn.validators
-                .entry(txn_validator.pubkey)
-                .or_insert(txn_validator.vote);
-        } else {
-            let mut txn = txn_validator.txn.clone();
-            txn.validators
-                .insert(txn_validator.pubkey, txn_validator.vote);
-            self.txn_pool.pending.insert(txn.txn_id.clone(), txn);
+    /// Whether the block at `height` can be assembled at `now`. With fewer than
+    /// the window's min_txns confirmed txns the miner waits, for at most the
+    /// window from the first time it asked at this height.
+    pub fn ready_to_assemble(&mut self, height: u128, now: u128) -> bool {
+        let wait = match self.assembly_wait {
+            Some(wait) if wait.height == height => wait,
+            _ => AssemblyWait {
+                height,
+                opened_at: now,
+                done: false,
+            },
+        };
+        let ready = wait.done
+            || self.txn_pool.confirmed.len() >= self.assembly.min_txns
+            || now.saturating_sub(wait.opened_at) >= self.assembly.window();
+        self.assembly_wait = Some(AssemblyWait {
+            done: ready,
+            ..wait
+        });
+
+        ready
+    }
+
+    /// True once, when a miner waiting to assemble a block has enough
+    /// confirmed txns or its window has run out, so it can go back to mining.
+    /// Checked as txns are confirmed and on every pass of the mining loop.
+    pub fn assembly_wait_over(&mut self, now: u128) -> bool {
+        match self.assembly_wait {
+            Some(wait) if !wait.done => self.ready_to_assemble(wait.height, now),
+            _ => false,
         }
     }
 
-    pub fn check_confirmed(&mut self, txn_id: String) {
-        let mut validators = {
-            if let Some(txn) = self.txn_pool.pending.get(&txn_id) {
-                txn.validators.clone()
-            } else {
-                HashMap::new()
-            }
-        };
+    /// Picks the confirmed txns to include in the next block, highest fee per
+    /// cost unit first, stopping at the first txn that would take the block
+    /// over the block cost limit.
+    pub fn select_txns(&self) -> LinkedHashMap<String, Txn> {
+        let mut txns: Vec<&Txn> = self.txn_pool.confirmed.values().collect();
+        // Compares fee / cost across the two txns without dividing.
+        txns.sort_by(|a, b| {
+            let a_fee = a.txn_fee.saturating_mul(txn_cost(b) as u128);
+            let b_fee = b.txn_fee.saturating_mul(txn_cost(a) as u128);
+            b_fee.cmp(&a_fee)
+        });
 
-        validators.retain(|_, v| *v);
-        if validators.len() as f64 / (self.claim_map.len() - 1) as f64 > VALIDATOR_THRESHOLD {
-            if let Some((k, v)) = self.txn_pool.pending.remove_entry(&txn_id) {
-                self.txn_pool.confirmed.insert(k, v);
+        let mut selected = LinkedHashMap::new();
+        let mut block_cost: u64 = 0;
+        for txn in txns {
+            let cost = txn_cost(txn);
+            if block_cost.saturating_add(cost) > self.reward_state.params.block_cost_limit {
+                break;
             }
+            block_cost += cost;
+            selected.insert(txn.txn_id.clone(), txn.clone());
         }
+
+        selected
     }
 
-    pub fn check_rejected(&self, txn_id: String) -> Option<Vec<String>> {
-        let mut validators = {
-            if let Some(txn) = self.txn_pool.pending.get(&txn_id) {
-                txn.validators.clone()
-            } else {
-                HashMap::new()
-            }
+    /// Catches the miner up with the nonce epoch the ledger moved to when it
+    /// applied a block, taking its claim and claim map from the ledger. The
+    /// miner is only ever behind the ledger, so syncing twice is a no-op.
+    pub fn sync_nonce_epoch(&mut self) -> bool {
+        let (epoch, claims) = {
+            let network_state = self.network_state.read();
+            (network_state.nonce_epoch(), network_state.get_claims())
         };
+        if epoch <= self.nonce_epoch {
+            return false;
+        }
 
-        let mut rejected = validators.clone();
-        rejected.retain(|_, v| !*v);
-        validators.retain(|_, v| *v);
-
-        if rejected.len() as f64 / self.claim_map.len() as f64 > 1.0 - VALIDATOR_THRESHOLD {
-            let slash_claims = validators
-                .iter()
-                .map(|(k, _)| return k.to_string())
-                .collect::<Vec<_>>();
-            return Some(slash_claims);
-        } else {
-            return None;
+        match claims.get(&self.claim.pubkey) {
+            Some(claim) => self.claim = claim.clone(),
+            None => (self.nonce_epoch..epoch).for_each(|_| self.claim.nonce_up()),
         }
+        self.pointer_cache.clear();
+        self.set_claim_map(claims);
+        self.nonce_epoch = epoch;
+
+        true
     }
 
-    pub fn slash_claim(&mut self, pubkey: String) {
-        if let Some(claim) = self.claim_map.get_mut(&pubkey) {
-            claim.eligible = false;
-        }
+    pub fn claim_map_hash(&self) -> String {
+        self.claim_map_digest.digest()
     }
 
-    pub fn check_time_elapsed(&self) -> u128 {
-        let timestamp = self.get_timestamp();
-        if let Some(time) = timestamp.checked_sub(self.current_nonce_timer) {
-            time / SECOND
-        } else {
-            0u128
-        }
+    pub fn recompute_claim_map_hash(&mut self) -> String {
+        self.claim_map_digest.recompute(&self.claim_map)
     }
 
-    pub fn get_timestamp(&self) -> u128 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
+    /// Keeps a block received from another miner for the election pacing if
+    /// it's valid on top of the last confirmed block. Invalid blocks are
+    /// dropped, they can't hold back a block of the miner's own.
+    pub fn saw_block(&mut self, block: &Block) {
+        let valid = self.last_block.as_ref().map_or(false, |last_block| {
+            block
+                .valid_block(last_block, &self.network_state.read(), &self.reward_state)
+                .is_ok()
+        });
+        if valid {
+            self.pacing.saw_block(block);
+        }
     }
 
-    pub fn abandoned_claim(&mut self, hash: String) {
-        self.claim_map.retain(|_, v| v.hash != hash);
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        self.current_nonce_timer = timestamp;
+    /// Moves the miner on to a block the blockchain confirmed. The block's txns
+    /// leave the pool, its claims join the claim map and the claim map is
+    /// reconciled with it.
+    pub fn confirm_block(&mut self, block: &Block) {
+        self.pacing.confirmed(block.header.block_height);
+        self.current_nonce_timer = block.header.timestamp;
+        self.last_block = Some(block.clone());
+        self.blocks_since_gc += 1;
+        block.txns.keys().for_each(|txn_id| {
+            self.txn_pool.confirmed.remove(txn_id);
+        });
+        block.claims.iter().for_each(|(pubkey, claim)| {
+            self.claim_pool.confirmed.remove(pubkey);
+            self.insert_claim(pubkey.clone(), claim.clone());
+        });
+
+        // Check if the miner's claim nonce changed,
+        // if it did change, make sure that it HAD to change.
+        // If it did have to change (nonce up) and your local claim map is different
+        // nonce up the local claim map until it is in consensus.
+        self.insert_claim(
+            block.header.claim.pubkey.clone(),
+            block.header.claim.clone(),
+        );
+        // Claims sold in the block are rekeyed to their buyers.
+        block
+            .txns
+            .values()
+            .filter_map(|txn| txn.claim_transfer_data())
+            .for_each(|transfer| {
+                if let Some(known) = self.claim_map.get(&transfer.claim_pubkey).cloned() {
+                    self.retain_claims(|pubkey, _| *pubkey != transfer.claim_pubkey);
+                    self.insert_claim(transfer.to.pubkey.clone(), transfer.transferred(&known));
+                }
+            });
+        // The ledger slashes the claims the block carries evidence against.
+        block
+            .txns
+            .values()
+            .filter_map(|txn| txn.evidence())
+            .for_each(|evidence| self.slash_claim(evidence.offender().to_string()));
+        self.reconcile_claim_map(block);
+        // Applying the block may have moved the ledger to the next nonce epoch.
+        self.sync_nonce_epoch();
     }
 
-    pub fn to_string(&self) -> String {
-        serde_json::to_string(&self).unwrap()
+    /// Remembers the txns the blockchain reports finalized, the next garbage
+    /// collection drops them from the pools.
+    pub fn finalize_txns(&mut self, txn_ids: &[String]) {
+        txn_ids.iter().for_each(|txn_id| {
+            self.finalized_txns.insert(txn_id);
+        });
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        self.to_string().as_bytes().to_vec()
+    /// Whether POOL_GC_INTERVAL blocks were confirmed since the last garbage
+    /// collection, or the pools have grown past POOL_GC_THRESHOLD txns.
+    pub fn gc_due(&self) -> bool {
+        self.blocks_since_gc >= POOL_GC_INTERVAL
+            || self.txn_pool.pending.len() + self.txn_pool.confirmed.len() >= POOL_GC_THRESHOLD
     }
 
-    pub fn from_bytes(data: &[u8]) -> Miner {
-        serde_json::from_slice(data).unwrap()
+    /// Drops finalized txns, vote maps and all, from the pools, the ledger is
+    /// their record from then on. Votes to abandon a claim are dropped once
+    /// the claim map no longer holds the claim they were on, it was abandoned
+    /// already or has been nonced up since.
+    pub fn collect_garbage(&mut self) {
+        let (pending, confirmed) = (self.txn_pool.pending.len(), self.txn_pool.confirmed.len());
+        let finalized_txns = &self.finalized_txns;
+        self.txn_pool
+            .pending
+            .retain(|txn_id, _| !finalized_txns.contains(txn_id));
+        self.txn_pool
+            .confirmed
+            .retain(|txn_id, _| !finalized_txns.contains(txn_id));
+
+        let claim_map = &self.claim_map;
+        self.abandoned_claim_counter.retain(|_, claim| {
+            claim_map
+                .get(&claim.pubkey)
+                .map_or(false, |known| known.hash == claim.hash)
+        });
+        self.blocks_since_gc = 0;
+
+        info!(
+            target: MINER_TARGET,
+            "pools garbage collected pending_dropped={} confirmed_dropped={}",
+            pending - self.txn_pool.pending.len(),
+            confirmed - self.txn_pool.confirmed.len()
+        );
     }
 
-    pub fn from_string(data: &str) -> Miner {
-        serde_json::from_str(data).unwrap()
+    /// The sizes of the pools, see metrics::PoolMetrics.
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        let pool_txns = self
+            .txn_pool
+            .pending
+            .values()
+            .chain(self.txn_pool.confirmed.values());
+        PoolMetrics {
+            pending_txns: self.txn_pool.pending.len(),
+            confirmed_txns: self.txn_pool.confirmed.len(),
+            txn_votes: pool_txns.map(|txn| txn.validators.len()).sum(),
+            pending_claims: self.claim_pool.pending.len(),
+            confirmed_claims: self.claim_pool.confirmed.len(),
+            abandonment_votes: self.abandoned_claim_counter.len(),
+        }
     }
 
-    pub fn get_field_names(&self) -> Vec<String> {
-        vec![
+    /// Replaces the claim map with the ledger's claims if, with the confirmed
+    /// block's claims in, it doesn't hash to the block's claim map hash, and
+    /// returns whether it did. The block was checked against the ledger before
+    /// it was confirmed, so a mismatch means this miner's claim map is behind.
+    pub fn reconcile_claim_map(&mut self, block: &Block) -> bool {
+        self.claim_map_digest.refresh();
+        let claim_map_hash = self.claim_map_hash();
+        match &block.header.claim_map_hash {
+            Some(expected) if *expected != claim_map_hash => {}
+            // Genesis blocks don't carry a claim map hash.
+            _ => return false,
+        }
+        warn!(
+            target: MINER_TARGET,
+            "claim map out of sync, reloading it from the ledger height={} local_hash={}",
+            block.header.block_height,
+            claim_map_hash
+        );
+        let claims = self.network_state.get_claims();
+        self.pointer_cache.clear();
+        self.set_claim_map(claims);
+
+        true
+    }
+
+    pub fn insert_claim(&mut self, pubkey: String, claim: Claim) {
+        self.claim_map_digest.insert(&pubkey, &claim);
+        self.claim_map.insert(pubkey, claim);
+    }
+
+    pub fn retain_claims<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&String, &Claim) -> bool,
+    {
+        let claim_map_digest = &mut self.claim_map_digest;
+        self.claim_map.retain(|pubkey, claim| {
+            let keep = f(pubkey, claim);
+            if !keep {
+                claim_map_digest.remove(pubkey);
+            }
+            keep
+        });
+    }
+
+    pub fn set_claim_map(&mut self, claim_map: LinkedHashMap<String, Claim>) {
+        self.claim_map_digest = ClaimMapHash::from_claim_map(&claim_map);
+        self.claim_map = claim_map;
+    }
+
+    /// Removes the min relay fee flag from the command line arguments, falling
+    /// back to MIN_TXN_FEE if it is missing or invalid.
+    pub fn min_relay_fee_from_args(mut args: Vec<String>) -> (u128, Vec<String>) {
+        let min_relay_fee = take_flag(&mut args, MIN_RELAY_FEE_FLAG)
+            .and_then(|fee| fee.parse().ok())
+            .unwrap_or(MIN_TXN_FEE);
+
+        (min_relay_fee, args)
+    }
+
+    /// Whether a txn pays at least the min relay fee and may enter the pool.
+    /// Txns already in the pool are let through so votes on them still count.
+    pub fn admits_txn(&self, txn: &Txn) -> bool {
+        if self.finalized_txns.contains(&txn.txn_id) {
+            return false;
+        }
+        // Evidence and downtime pay no fee, they're validated like any other txn.
+        if txn.txn_fee >= self.min_relay_fee
+            || txn.is_slashing_evidence()
+            || txn.is_planned_downtime()
+            || self.txn_pool.pending.contains_key(&txn.txn_id)
+            || self.txn_pool.confirmed.contains_key(&txn.txn_id)
+        {
+            return true;
+        }
+
+        info!(
+            target: MINER_TARGET,
+            "txn rejected txn_id={} fee={} min_relay_fee={}",
+            txn.txn_id,
+            txn.txn_fee,
+            self.min_relay_fee
+        );
+        false
+    }
+
+    /// Whether a claim may enter the claim pool. A claim whose pubkey is
+    /// already held by a different claim is rejected, the known one is kept.
+    pub fn admits_claim(&self, claim: &Claim) -> bool {
+        let known = self
+            .claim_map
+            .get(&claim.pubkey)
+            .or_else(|| self.claim_pool.confirmed.get(&claim.pubkey));
+        match known {
+            Some(known) if claim.conflicts_with(known) => {
+                warn!(
+                    target: MINER_TARGET,
+                    "conflicting claim rejected pubkey={} hash={} known_hash={} address={} known_address={}",
+                    claim.pubkey,
+                    claim.hash,
+                    known.hash,
+                    claim.address,
+                    known.address
+                );
+                false
+            }
+            // Blocks announcing a slashed claim again are rejected.
+            Some(known) if !known.eligible => {
+                warn!(
+                    target: MINER_TARGET,
+                    "ineligible claim rejected pubkey={} hash={}",
+                    claim.pubkey,
+                    claim.hash
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub fn process_txn(&mut self, mut txn: Txn) -> TxnValidator {
+        if let Some(_txn) = self.txn_pool.confirmed.get(&txn.txn_id) {
+            // Nothing really to do here
+        } else if let Some(txn) = self.txn_pool.pending.get(&txn.txn_id) {
+            // add validator if you have not validated already
+            if let None = txn.validators.clone().get(&self.claim.pubkey) {
+                let mut txn = txn.clone();
+                let vote = self.vote(&txn);
+                self.add_vote(&mut txn, vote);
+                self.txn_pool
+                    .pending
+                    .insert(txn.txn_id.clone(), txn.clone());
+            }
+        } else {
+            // add validator
+            self.strip_votes(&mut txn);
+            let vote = self.vote(&txn);
+            self.add_vote(&mut txn, vote);
+            self.txn_pool
+                .pending
+                .insert(txn.txn_id.clone(), txn.clone());
+        }
+
+        return TxnValidator::new(
+            self.claim.pubkey.clone(),
+            txn.clone(),
+            &self.network_state.read(),
+            &self.txn_pool,
+        )
+        .signed(&self.secret_key);
+    }
+
+    /// The txns among `txn_ids` this node can serve a peer, from the last
+    /// block or the pool.
+    pub fn txns_for(&self, txn_ids: &[String]) -> Vec<Txn> {
+        let last_block_txns = self
+            .last_block
+            .as_ref()
+            .map(|block| block.txns.clone())
+            .unwrap_or_default();
+        txn_ids
+            .iter()
+            .filter_map(|txn_id| {
+                last_block_txns
+                    .get(txn_id)
+                    .or_else(|| self.txn_pool.get(txn_id))
+                    .cloned()
+            })
+            .collect()
+    }
+
+    /// Takes txns fetched to sync the pool like txns gossiped to the node,
+    /// returning this node's votes on the ones admitted.
+    pub fn process_synced_txns(&mut self, txns: Vec<Txn>) -> Vec<TxnValidator> {
+        txns.into_iter()
+            .filter(|txn| self.admits_txn(txn))
+            .map(|txn| {
+                let txn_validator = self.process_txn(txn.clone());
+                self.check_confirmed(txn.txn_id.clone());
+                txn_validator
+            })
+            .collect()
+    }
+
+    // Records this node's signed vote on the txn, the signature is carried
+    // into the block so peers can check the txn reached quorum.
+    fn add_vote(&self, txn: &mut Txn, vote: bool) {
+        txn.validators.insert(self.claim.pubkey.clone(), vote);
+        txn.validator_signatures.insert(
+            self.claim.pubkey.clone(),
+            TxnValidator::sign_vote(txn, vote, &self.secret_key),
+        );
+    }
+
+    /// Validates a txn against the miner's ledger and pending txns.
+    fn vote(&self, txn: &Txn) -> bool {
+        match txn.valid_txn(&self.network_state.read(), Some(&self.txn_pool)) {
+            Ok(()) => true,
+            Err(e) => {
+                info!(target: MINER_TARGET, "Txn {} is invalid: {}", txn.txn_id, e);
+                false
+            }
+        }
+    }
+
+    /// Records a peer's vote on a txn, unless its signature doesn't verify or
+    /// the peer doesn't hold an eligible claim.
+    pub fn process_txn_validator(&mut self, txn_validator: TxnValidator) {
+        if !txn_validator.valid_signature() {
+            warn!(
+                target: MINER_TARGET,
+                "Dropping unsigned vote on txn {} from {}",
+                txn_validator.txn.txn_id,
+                txn_validator.pubkey
+            );
+            return;
+        }
+        if !self.eligible_validators().contains(&txn_validator.pubkey) {
+            warn!(
+                target: MINER_TARGET,
+                "Dropping vote on txn {} from {} without an eligible claim",
+                txn_validator.txn.txn_id,
+                txn_validator.pubkey
+            );
+            return;
+        }
+        if let Some(_txn) = self.txn_pool.confirmed.get(&txn_validator.txn.txn_id) {
+        } else if let Some(txn) = self.txn_pool.pending.get_mut(&txn_validator.txn.txn_id) {
+            // A validator's first vote stands, one contradicting it isn't counted.
+            match VoteTally::from_votes(&txn.validators)
+                .record(&txn_validator.pubkey, txn_validator.vote)
+            {
+                VoteOutcome::Counted => {
+                    txn.validators
+                        .insert(txn_validator.pubkey.clone(), txn_validator.vote);
+                    txn.validator_signatures
+                        .insert(txn_validator.pubkey, txn_validator.signature);
+                }
+                VoteOutcome::Duplicate => {}
+                VoteOutcome::Equivocation => warn!(
+                    target: MINER_TARGET,
+                    "Validator {} equivocated on txn {}", txn_validator.pubkey, txn.txn_id
+                ),
+            }
+        } else if self.admits_txn(&txn_validator.txn) {
+            let mut txn = txn_validator.txn.clone();
+            self.strip_votes(&mut txn);
+            txn.validators
+                .insert(txn_validator.pubkey.clone(), txn_validator.vote);
+            txn.validator_signatures
+                .insert(txn_validator.pubkey, txn_validator.signature);
+            self.txn_pool.pending.insert(txn.txn_id.clone(), txn);
+        }
+    }
+
+    pub fn check_confirmed(&mut self, txn_id: String) {
+        let confirmed = self.txn_pool.pending.get(&txn_id).map_or(false, |txn| {
+            self.confirmation_status(txn) == ConfirmationStatus::Confirmed
+        });
+        if confirmed {
+            if let Some((k, v)) = self.txn_pool.pending.remove_entry(&txn_id) {
+                self.txn_pool.confirmed.insert(k, v);
+            }
+        }
+    }
+
+    /// The yes votes a txn needs to be confirmed with the validators active
+    /// now.
+    pub fn required_confirmations(&self) -> usize {
+        self.quorum.required(self.active_validators())
+    }
+
+    pub fn confirmation_status(&self, txn: &Txn) -> ConfirmationStatus {
+        self.quorum
+            .status(txn, &self.eligible_validators(), &self.claim.pubkey)
+    }
+
+    /// The pubkeys of the eligible claims in the claim map, the validators
+    /// whose votes count.
+    pub fn eligible_validators(&self) -> HashSet<String> {
+        QuorumConfig::eligible_validators(&self.claim_map)
+    }
+
+    // Drops the votes a txn arrived with that wouldn't count, the ones from
+    // pubkeys without an eligible claim or without a valid signature.
+    fn strip_votes(&self, txn: &mut Txn) {
+        let eligible = self.eligible_validators();
+        let counted: HashSet<String> = txn
+            .validators
+            .keys()
+            .filter(|pubkey| eligible.contains(*pubkey) && txn.valid_vote_signature(pubkey))
+            .cloned()
+            .collect();
+        txn.validators.retain(|pubkey, _| counted.contains(pubkey));
+        txn.validator_signatures
+            .retain(|pubkey, _| counted.contains(pubkey));
+    }
+
+    /// The eligible claims in the claim map other than this node's own, the
+    /// validators expected to vote on a txn.
+    pub fn active_validators(&self) -> usize {
+        QuorumConfig::active_validators(&self.eligible_validators(), &self.claim.pubkey)
+    }
+
+    /// Evidence against the validators that voted for a txn that has been
+    /// rejected, None while the txn can still be confirmed. A txn can be
+    /// rejected for want of yes votes, the yes voters are only reported once
+    /// a quorum of the eligible claims has voted it down.
+    pub fn check_rejected(&self, txn_id: String) -> Option<Vec<SlashingEvidence>> {
+        let txn = self.txn_pool.pending.get(&txn_id)?;
+        if self.confirmation_status(txn) != ConfirmationStatus::Rejected {
+            return None;
+        }
+
+        let eligible = self.eligible_validators();
+        let tally = QuorumConfig::tally(txn, &eligible);
+        Some(
+            tally
+                .voters(true)
+                .iter()
+                .filter(|voter| {
+                    let active = QuorumConfig::active_validators(&eligible, voter);
+                    tally.no() >= self.quorum.required(active)
+                })
+                .filter_map(|voter| SlashingEvidence::rejected_txn_vote(txn, voter))
+                .collect(),
+        )
+    }
+
+    /// The votes a pending txn has collected so far, reported to the wallet
+    /// that sent it.
+    pub fn txn_status(&self, txn_id: &str) -> Option<TxnStatus> {
+        let txn = self.txn_pool.pending.get(txn_id)?;
+        let tally = QuorumConfig::tally(txn, &self.eligible_validators());
+        Some(TxnStatus::Validating {
+            confirmations: tally.yes(),
+            rejections: tally.no(),
+        })
+    }
+
+    /// A TxnMessage announcing a txn that is still waiting on validators
+    /// again, for when its first broadcast wasn't picked up. Txns that are
+    /// already confirmed, or that this node hasn't seen, aren't rebroadcast.
+    pub fn rebroadcast_txn(&self, txn_id: &str, sender_id: &str) -> Option<MessageType> {
+        let in_last_block = self
+            .last_block
+            .as_ref()
+            .map_or(false, |block| block.txns.contains_key(txn_id));
+        if self.txn_pool.confirmed.contains_key(txn_id) || in_last_block {
+            return None;
+        }
+
+        // Peers that haven't seen the txn record their own votes on it.
+        let mut txn = self.txn_pool.pending.get(txn_id)?.clone();
+        txn.validators.clear();
+        Some(MessageType::TxnMessage {
+            txn,
+            sender_id: sender_id.to_string(),
+        })
+    }
+
+    /// Why a txn was rejected, this node's own validation error if it voted
+    /// against it.
+    pub fn rejection_reason(&self, txn_id: &str) -> String {
+        if let Some(txn) = self.txn_pool.pending.get(txn_id) {
+            if let Err(e) = txn.valid_txn(&self.network_state.read(), Some(&self.txn_pool)) {
+                return e.to_string();
+            }
+        }
+
+        "rejected by validators".to_string()
+    }
+
+    pub fn slash_claim(&mut self, pubkey: String) {
+        self.claim_pool.confirmed.remove(&pubkey);
+        if let Some(claim) = self.claim_map.get_mut(&pubkey) {
+            claim.eligible = false;
+            self.claim_map_digest.insert(&pubkey, claim);
+        }
+    }
+
+    /// Seconds since the nonce timer was last reset. The timer is set from
+    /// block timestamps, so it's measured against the network's time.
+    pub fn check_time_elapsed(&self) -> u128 {
+        let timestamp = self.network_timestamp();
+        if let Some(time) = timestamp.checked_sub(self.current_nonce_timer) {
+            time / SECOND
+        } else {
+            0u128
+        }
+    }
+
+    /// The network's abandonment timeout in seconds.
+    pub fn abandonment_timeout(&self) -> u128 {
+        self.reward_state.params.abandonment_timeout
+    }
+
+    /// Whether the claim that should have mined the next block has gone
+    /// longer than the abandonment rounds' worth of timeout without one.
+    pub fn claim_overdue(&self) -> bool {
+        self.check_time_elapsed()
+            > self
+                .abandonment_timeout()
+                .saturating_mul(self.abandonment.rounds)
+    }
+
+    /// The claim with `hash` if this miner votes it abandoned now, recording
+    /// the vote. None while the claim isn't overdue, its owner announced
+    /// downtime, or this miner voted on it less than a timeout ago.
+    pub fn abandonment_vote(&mut self, hash: &str) -> Option<Claim> {
+        if !self.claim_overdue() {
+            return None;
+        }
+
+        let now = self.network_timestamp();
+        let window = self.abandonment_timeout().saturating_mul(SECOND);
+        self.abandonment_votes
+            .retain(|_, voted_at| now.saturating_sub(*voted_at) < window);
+        if self.abandonment_votes.contains_key(hash) {
+            return None;
+        }
+
+        let claim = self
+            .claim_map
+            .values()
+            .find(|claim| claim.hash == hash && !self.abandonment_suppressed(&claim.pubkey, now))
+            .cloned()?;
+        self.abandonment_votes.insert(hash.to_string(), now);
+        self.abandoned_claim_counter
+            .insert(self.claim.pubkey.clone(), claim.clone());
+        Some(claim)
+    }
+
+    pub fn get_timestamp(&self) -> u128 {
+        self.time.now()
+    }
+
+    /// The local time corrected for the clock skew estimated from peers.
+    pub fn network_timestamp(&self) -> u128 {
+        self.clock.adjusted(self.get_timestamp())
+    }
+
+    pub fn abandoned_claim(&mut self, hash: String) {
+        self.retain_claims(|_, v| v.hash != hash);
+        self.current_nonce_timer = self.network_timestamp();
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string(&self).unwrap()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_string().as_bytes().to_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Miner {
+        serde_json::from_slice(data).unwrap()
+    }
+
+    pub fn from_string(data: &str) -> Miner {
+        serde_json::from_str(data).unwrap()
+    }
+
+    /// Writes the txn and claim pools to `path` so the pending txns and claims
+    /// outlive a restart.
+    pub fn save_pools(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let pools = SavedPools {
+            txn_pool: self.txn_pool.clone(),
+            claim_pool: self.claim_pool.clone(),
+        };
+        std::fs::write(path, serde_json::to_string(&pools)?)?;
+
+        Ok(())
+    }
+
+    /// Replaces the pools with the ones saved at `path`, returns false and
+    /// leaves them as they were if there are none to read.
+    pub fn restore_pools(&mut self, path: &str) -> bool {
+        let pools = std::fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<SavedPools>(&data).ok());
+        match pools {
+            Some(pools) => {
+                self.txn_pool = pools.txn_pool;
+                self.claim_pool = pools.claim_pool;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_field_names(&self) -> Vec<String> {
+        vec![
             "claim".to_string(),
             "mining".to_string(),
             "claim_map".to_string(),
@@ -310,11 +1472,37 @@ impl Miner {
             "init".to_string(),
             "abandoned_claim_counter".to_string(),
             "abandoned_claim".to_string(),
+            "claim_map_digest".to_string(),
             "secret_key".to_string(),
         ]
     }
 }
 
+impl AbandonmentConfig {
+    /// Removes the abandonment rounds flag from the command line arguments,
+    /// falling back to ABANDONMENT_ROUNDS for a missing, zero or invalid value.
+    pub fn from_args(mut args: Vec<String>) -> (AbandonmentConfig, Vec<String>) {
+        let mut abandonment = AbandonmentConfig::default();
+        if let Some(rounds) =
+            take_flag(&mut args, ABANDONMENT_ROUNDS_FLAG).and_then(|r| r.parse::<u128>().ok())
+        {
+            if rounds > 0 {
+                abandonment.rounds = rounds;
+            }
+        }
+
+        (abandonment, args)
+    }
+}
+
+impl Default for AbandonmentConfig {
+    fn default() -> AbandonmentConfig {
+        AbandonmentConfig {
+            rounds: ABANDONMENT_ROUNDS,
+        }
+    }
+}
+
 impl fmt::Display for NoLowestPointerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -326,3 +1514,1381 @@ impl Error for NoLowestPointerError {
         &self.0
     }
 }
+
+impl fmt::Display for ConfirmationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfirmationStatus::Confirmed => write!(f, "confirmed"),
+            ConfirmationStatus::Unconfirmed => write!(f, "unconfirmed"),
+            ConfirmationStatus::Rejected => write!(f, "rejected"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::compute_expected_claim_map_hash;
+    use crate::claim::PlannedDowntime;
+    use crate::clock::MockClock;
+    use crate::params::BLOCK_COST_LIMIT;
+    use crate::reward::Category;
+    use crate::state::{Ledger, NetworkState};
+    use crate::token::TokenLedger;
+    use crate::txn::BASE_TXN_COST;
+    use crate::wallet::WalletAccount;
+
+    fn claim_map(n: u128) -> LinkedHashMap<String, Claim> {
+        let mut claim_map = LinkedHashMap::new();
+        (0..n).for_each(|i| {
+            let claim = Claim::new(format!("pubkey_{}", i), format!("address_{}", i), 1);
+            claim_map.insert(claim.pubkey.clone(), claim);
+        });
+        claim_map
+    }
+
+    // A yes vote on the txn signed by a fresh validator, whose claim the miner
+    // is given.
+    fn signed_vote(miner: &mut Miner, txn: &Txn) -> TxnValidator {
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.get_pubkey(), "validator_address".to_string(), 1);
+        miner.insert_claim(claim.pubkey.clone(), claim);
+        TxnValidator {
+            pubkey: wallet.get_pubkey(),
+            vote: true,
+            txn: txn.clone(),
+            signature: String::new(),
+        }
+        .signed(&wallet.get_secretkey())
+    }
+
+    fn test_miner(state_path: &str) -> Miner {
+        Miner::start(
+            String::new(),
+            "pubkey".to_string(),
+            "address".to_string(),
+            RewardState::start(),
+            SharedNetworkState::new(NetworkState::restore(state_path)),
+            1,
+        )
+    }
+
+    #[test]
+    fn test_incremental_claim_map_hash_matches_recompute() {
+        let mut claims = claim_map(5);
+        let mut claim_map_hash = ClaimMapHash::from_claim_map(&claims);
+
+        let new_claim = Claim::new("pubkey_5".to_string(), "address_5".to_string(), 1);
+        claim_map_hash.insert(&new_claim.pubkey, &new_claim);
+        claims.insert(new_claim.pubkey.clone(), new_claim);
+
+        claim_map_hash.remove("pubkey_2");
+        claims.remove("pubkey_2");
+
+        if let Some(claim) = claims.get_mut("pubkey_3") {
+            claim.nonce_up();
+            claim_map_hash.insert("pubkey_3", claim);
+        }
+
+        let incremental = claim_map_hash.digest();
+        assert_eq!(claim_map_hash.n_recomputes(), 0);
+        assert_eq!(incremental, ClaimMapHash::default().recompute(&claims));
+    }
+
+    #[test]
+    fn test_claim_map_hash_is_cached_until_a_claim_changes() {
+        let claims = claim_map(3);
+        let mut claim_map_hash = ClaimMapHash::from_claim_map(&claims);
+        let digest = claim_map_hash.digest();
+        assert!(claim_map_hash.root.is_some());
+
+        // Inserting a claim as it is doesn't throw the cached digest away.
+        claim_map_hash.insert("pubkey_1", claims.get("pubkey_1").unwrap());
+        assert!(claim_map_hash.root.is_some());
+
+        let mut nonced_up = claims.get("pubkey_1").unwrap().clone();
+        nonced_up.nonce_up();
+        claim_map_hash.insert("pubkey_1", &nonced_up);
+        assert!(claim_map_hash.root.is_none());
+        let changed = claim_map_hash.digest();
+        assert_ne!(changed, digest);
+        claim_map_hash.refresh();
+        assert_eq!(claim_map_hash.digest(), changed);
+
+        // Two maps holding the same claim hashes under other pubkeys differ.
+        let mut swapped = claim_map_hash.clone();
+        let (first, second) = (swapped.entries["pubkey_0"], swapped.entries["pubkey_2"]);
+        swapped.entries.insert("pubkey_0".to_string(), second);
+        swapped.entries.insert("pubkey_2".to_string(), first);
+        swapped.root = None;
+        assert_ne!(swapped.digest(), changed);
+    }
+
+    #[test]
+    fn test_claim_map_hash_after_changes_matches_inserting_them() {
+        let claims = claim_map(4);
+        let claim_map_hash = ClaimMapHash::from_claim_map(&claims);
+        let mut changes = LinkedHashMap::new();
+        let mut nonced_up = claims.get("pubkey_1").unwrap().clone();
+        nonced_up.nonce_up();
+        changes.insert("pubkey_1".to_string(), nonced_up);
+        let new_claim = Claim::new("pubkey_9".to_string(), "address_9".to_string(), 1);
+        changes.insert(new_claim.pubkey.clone(), new_claim);
+
+        let mut applied = claims.clone();
+        changes.iter().for_each(|(pubkey, claim)| {
+            applied.insert(pubkey.clone(), claim.clone());
+        });
+        assert_eq!(
+            claim_map_hash.digest_after(&changes, &[]),
+            ClaimMapHash::from_claim_map(&applied).digest()
+        );
+        assert_eq!(
+            claim_map_hash.digest(),
+            ClaimMapHash::from_claim_map(&claims).digest()
+        );
+
+        // A removed claim is taken out unless it's changed as well.
+        let removed = vec!["pubkey_2".to_string()];
+        applied.remove("pubkey_2");
+        assert_eq!(
+            claim_map_hash.digest_after(&changes, &removed),
+            ClaimMapHash::from_claim_map(&applied).digest()
+        );
+    }
+
+    #[test]
+    fn test_claim_map_hash_is_order_independent() {
+        let claims = claim_map(3);
+        let mut reversed = LinkedHashMap::new();
+        claims.iter().rev().for_each(|(k, v)| {
+            reversed.insert(k.clone(), v.clone());
+        });
+
+        assert_eq!(
+            ClaimMapHash::from_claim_map(&claims).digest(),
+            ClaimMapHash::from_claim_map(&reversed).digest()
+        );
+    }
+
+    #[test]
+    fn test_miner_missing_a_claim_reconciles_instead_of_rejecting_the_block() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_reconcile_claim_map_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let claims = claim_map(3);
+        NetworkState::restore(&state_path).update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims: claims.clone(),
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        let mut miner = test_miner(&state_path);
+        miner.retain_claims(|pubkey, _| pubkey != "pubkey_2");
+
+        let wallet = WalletAccount::new();
+        let claim = claims.get("pubkey_0").unwrap().clone();
+        let mut block =
+            Block::genesis(&RewardState::start(), claim.clone(), wallet.get_secretkey()).unwrap();
+        block.header.claim_map_hash = Some(compute_expected_claim_map_hash(
+            &miner.network_state.read(),
+            &block,
+        ));
+        assert!(block.valid_claim_map_hash(&miner.network_state.read()));
+
+        // The miner applies the confirmed block's claims, which don't bring
+        // back the claim it's missing.
+        miner.insert_claim(claim.pubkey.clone(), claim);
+        assert_ne!(Some(miner.claim_map_hash()), block.header.claim_map_hash);
+        assert!(miner.reconcile_claim_map(&block));
+        assert!(miner.claim_map.contains_key("pubkey_2"));
+        assert_eq!(Some(miner.claim_map_hash()), block.header.claim_map_hash);
+        assert!(!miner.reconcile_claim_map(&block));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_paused_miner_mines_nothing_until_resumed() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_paused_miner_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let wallet = WalletAccount::new();
+        let mut miner = Miner::start(
+            wallet.get_secretkey(),
+            wallet.get_pubkey(),
+            "address".to_string(),
+            RewardState::start(),
+            SharedNetworkState::new(NetworkState::restore(&state_path)),
+            1,
+        );
+        let mut genesis = miner.genesis().unwrap();
+        // Far enough in the past that the next block isn't too soon after it.
+        genesis.header.timestamp = 0;
+        miner.last_block = Some(genesis);
+        miner.mining = true;
+        let txn = Txn::unsigned_transfer(1, 0);
+        miner
+            .txn_pool
+            .pending
+            .insert(txn.txn_id.clone(), txn.clone());
+        let cancel = MiningCancel::default();
+
+        assert!(miner.resume("node".to_string()).is_none());
+        miner.pause();
+        assert!(!miner.should_mine());
+        assert!(miner.mine(&cancel).is_none());
+        assert!(miner.txn_pool.pending.contains_key(&txn.txn_id));
+        assert!(miner.mining);
+
+        match miner.resume("node".to_string()) {
+            Some(MessageType::ClaimMessage { claim, sender_id }) => {
+                assert_eq!(claim, miner.claim);
+                assert_eq!(sender_id, "node");
+            }
+            message => panic!("expected a claim message, got {:?}", message),
+        }
+        assert!(miner.should_mine());
+        assert!(miner.mine(&cancel).is_some());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_repeated_lowest_pointer_calls_reuse_cached_pointers() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_pointer_cache_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        claim_map(50).into_iter().for_each(|(pubkey, claim)| {
+            miner.insert_claim(pubkey, claim);
+        });
+
+        let nonce = 1234;
+        let lowest = miner.get_lowest_pointer(nonce);
+        assert_eq!(miner.pointer_cache.n_computes(), 50);
+        (0..100).for_each(|_| {
+            assert_eq!(miner.get_lowest_pointer(nonce), lowest);
+        });
+        assert_eq!(miner.pointer_cache.n_computes(), 50);
+
+        miner.network_state.write().nonce_up();
+        assert!(miner.sync_nonce_epoch());
+        miner.get_lowest_pointer(nonce);
+        assert_eq!(miner.pointer_cache.n_computes(), 100);
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_restart_after_partial_nonce_up_picks_up_the_ledger_epoch() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_partial_nonce_up_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let mut claims = claim_map(3);
+        let own_claim = Claim::new("pubkey".to_string(), "address".to_string(), 1);
+        claims.insert(own_claim.pubkey.clone(), own_claim);
+        NetworkState::restore(&state_path).update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        let mut miner = test_miner(&state_path);
+        assert_eq!(miner.nonce_epoch, 0);
+        assert_eq!(miner.claim_map.len(), 4);
+
+        // The node dies after the ledger is bumped and before the miner is.
+        assert!(miner.network_state.write().nonce_up_to(1));
+        let restarted = test_miner(&state_path);
+        let ledger_claims = restarted.network_state.get_claims();
+        assert_eq!(restarted.nonce_epoch, 1);
+        assert_eq!(Some(&restarted.claim), ledger_claims.get("pubkey"));
+        assert_eq!(
+            restarted.claim_map_hash(),
+            ClaimMapHash::from_claim_map(&ledger_claims).digest()
+        );
+
+        // Catching the old miner up doesn't bump the ledger a second time, and
+        // syncing again changes nothing.
+        assert!(miner.sync_nonce_epoch());
+        assert!(!miner.sync_nonce_epoch());
+        assert_eq!(miner.network_state.read().nonce_epoch(), 1);
+        assert_eq!(miner.network_state.get_claims(), ledger_claims);
+        assert_eq!(miner.claim, restarted.claim);
+        assert_eq!(miner.claim_map_hash(), restarted.claim_map_hash());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_empty_pool_win_waits_for_txns_confirmed_in_the_window() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_assembly_wait_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        let window = miner.assembly.window();
+        let start = 1_000 * SECOND;
+        assert!(!miner.ready_to_assemble(1, start));
+        assert!(!miner.assembly_wait_over(start + window / 2));
+
+        // With the voter the only other validator its yes vote confirms the txn.
+        let mut txn = Txn::unsigned_transfer(1, 0);
+        txn.txn_fee = MIN_TXN_FEE;
+        let vote = signed_vote(&mut miner, &txn);
+        miner.process_txn_validator(vote);
+        miner.check_confirmed(txn.txn_id.clone());
+        assert!(miner.assembly_wait_over(start + window / 2));
+        assert!(!miner.assembly_wait_over(start + window / 2));
+        assert!(miner.ready_to_assemble(1, start + window / 2));
+        assert!(miner.select_txns().contains_key(&txn.txn_id));
+
+        // With enough txns confirmed the next height doesn't wait at all.
+        assert!(miner.ready_to_assemble(2, start + window));
+        assert!(!miner.assembly_wait_over(start + window));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_assembly_wait_never_exceeds_the_window() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_assembly_window_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        miner.assembly.window_ms = 250;
+        let window = 250 * MILLI;
+        let start = 1_000 * SECOND;
+        assert!(!miner.ready_to_assemble(1, start));
+        // Asking again at the same height doesn't restart the window.
+        assert!(!miner.ready_to_assemble(1, start + window - 1));
+        assert!(!miner.assembly_wait_over(start + window - 1));
+        assert!(miner.assembly_wait_over(start + window));
+        assert!(miner.ready_to_assemble(1, start + window));
+        assert!(miner.select_txns().is_empty());
+
+        let args = vec![
+            ASSEMBLY_WINDOW_FLAG.to_string(),
+            "100".to_string(),
+            "vrrb".to_string(),
+            ASSEMBLY_MIN_TXNS_FLAG.to_string(),
+            "5".to_string(),
+        ];
+        assert_eq!(
+            AssemblyWindow::from_args(args),
+            (
+                AssemblyWindow {
+                    window_ms: 100,
+                    min_txns: 5
+                },
+                vec!["vrrb".to_string()]
+            )
+        );
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    // A miner with a signing key whose last block is its genesis block, far
+    // enough in the past for the next block to be mined right away.
+    fn signing_miner(state_path: &str) -> Miner {
+        let wallet = WalletAccount::new();
+        let mut miner = Miner::start(
+            wallet.get_secretkey(),
+            wallet.get_pubkey(),
+            "address".to_string(),
+            RewardState::start(),
+            SharedNetworkState::new(NetworkState::restore(state_path)),
+            1,
+        );
+        let mut genesis = miner.genesis().unwrap();
+        genesis.header.timestamp -= 2 * SECOND;
+        miner.last_block = Some(genesis);
+        miner
+    }
+
+    #[test]
+    fn test_confirmed_block_cancels_mining_at_its_height() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_mining_cancel_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = signing_miner(&state_path);
+
+        // Without a confirmed block at its height the attempt isn't affected.
+        let mut confirmed = miner.mine(&MiningCancel::new()).unwrap();
+        assert_eq!(confirmed.header.block_height, 1);
+
+        let cancel = MiningCancel::new();
+        cancel.block_confirmed(confirmed.header.block_height);
+        assert!(miner.mine(&cancel).is_none());
+
+        // The next attempt builds on the confirmed block.
+        confirmed.header.timestamp -= 2 * SECOND;
+        miner.last_block = Some(confirmed.clone());
+        let block = miner.mine(&cancel).unwrap();
+        assert_eq!(block.header.block_height, 2);
+        assert_eq!(block.header.last_hash, confirmed.hash);
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_cancelling_between_phases_produces_no_block() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_mining_phases_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let miner = signing_miner(&state_path);
+        let last_block = miner.last_block.clone().unwrap();
+
+        // Cancelled after selecting the txns, after signing and after hashing.
+        (1..=4).for_each(|cancelled_at| {
+            let checks = std::cell::Cell::new(0);
+            let block = Block::mine(
+                miner.claim.clone(),
+                last_block.clone(),
+                miner.select_txns(),
+                LinkedHashMap::new(),
+                None,
+                &miner.network_state.read(),
+                None,
+                None,
+                miner.secret_key.clone(),
+                miner.get_timestamp(),
+                || {
+                    checks.set(checks.get() + 1);
+                    checks.get() >= cancelled_at
+                },
+            );
+            assert_eq!(block.is_some(), cancelled_at == 4);
+        });
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_losing_miner_sends_no_retries_within_the_interval() {
+        let mut pacing = ElectionPacing::default();
+        let start = 10 * SECOND;
+        let interval = pacing.retry_interval();
+        pacing.lost(5, start);
+
+        // The mining loop asks on every pass, none is due before the interval runs out.
+        let retries = (0..100)
+            .map(|tick| start + tick * interval / 100)
+            .filter(|now| pacing.retry_due(*now))
+            .count();
+        assert_eq!(retries, 0);
+        assert!(pacing.sitting_out(start + interval - 1));
+        assert!(pacing.retry_due(start + interval));
+        assert!(!pacing.retry_due(start + interval + 1));
+        assert!(!pacing.sitting_out(start + interval + 1));
+
+        // The block at the lost height being confirmed ends the wait early.
+        pacing.lost(6, start);
+        pacing.confirmed(6);
+        assert!(!pacing.sitting_out(start));
+        assert!(!pacing.retry_due(start + interval));
+    }
+
+    #[test]
+    fn test_winner_suppresses_broadcast_after_a_valid_competing_block() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_suppressed_broadcast_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let mut miner = signing_miner(&state_path);
+        let cancel = MiningCancel::new();
+        let competitor = miner.mine(&cancel).unwrap();
+        let block = miner.mine(&cancel).unwrap();
+        assert_ne!(competitor.hash, block.hash);
+        assert!(miner.pacing.should_broadcast(&block));
+
+        miner.pacing.saw_block(&competitor);
+        assert!(!miner.pacing.should_broadcast(&block));
+
+        // Seen blocks are dropped once their height is confirmed.
+        miner.pacing.confirmed(block.header.block_height);
+        assert!(miner.pacing.should_broadcast(&block));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_winner_broadcasts_over_an_invalid_competing_block() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_invalid_competitor_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let mut miner = signing_miner(&state_path);
+        let cancel = MiningCancel::new();
+        let mut competitor = miner.mine(&cancel).unwrap();
+        competitor.header.signature = String::new();
+        let block = miner.mine(&cancel).unwrap();
+
+        // The invalid competitor is never kept.
+        miner.saw_block(&competitor);
+        assert!(!competitor.valid_block_signature());
+        assert!(miner.pacing.should_broadcast(&block));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_seen_blocks_are_capped() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_seen_blocks_capped_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let mut miner = signing_miner(&state_path);
+        let cancel = MiningCancel::new();
+        let block = miner.mine(&cancel).unwrap();
+        let mut pacing = ElectionPacing::default();
+        let mut competitor = miner.mine(&cancel).unwrap();
+        pacing.saw_block(&competitor);
+        (0..MAX_SEEN_BLOCKS).for_each(|n| {
+            competitor.hash = format!("competitor_{}", n);
+            competitor.header.block_height = block.header.block_height + 1;
+            pacing.saw_block(&competitor);
+        });
+        assert_eq!(pacing.seen.len(), MAX_SEEN_BLOCKS);
+        // The first competitor, the only one at the block's height, was dropped.
+        assert!(pacing.should_broadcast(&block));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_tied_pointers_pick_the_same_winner_in_any_order() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_tied_pointers_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        // For nonce 0xab a hash starting with "ba" has the lowest possible
+        // pointer, 1, so the first three claims tie.
+        let nonce = 0xab;
+        let claims: Vec<Claim> = ["ba1", "ba0", "ba2", "a0b"]
+            .iter()
+            .enumerate()
+            .map(|(i, prefix)| Claim {
+                pubkey: format!("pubkey_{}", i),
+                address: format!("address_{}", i),
+                hash: format!("{}{}", prefix, "f".repeat(61)),
+                nonce: 1,
+                eligible: true,
+            })
+            .collect();
+        let winner = (claims[1].hash.clone(), 1);
+
+        (0..256)
+            .map(|n| [n % 4, n / 4 % 4, n / 16 % 4, n / 64])
+            .filter(|order| (0..4).all(|i| order.contains(&i)))
+            .for_each(|order| {
+                let mut miner = test_miner(&state_path);
+                order.iter().for_each(|&i| {
+                    miner.insert_claim(claims[i].pubkey.clone(), claims[i].clone());
+                });
+                assert_eq!(miner.get_lowest_pointer(nonce), Some(winner.clone()));
+            });
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_txns_below_min_relay_fee_are_rejected() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_min_relay_fee_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        miner.min_relay_fee = 10;
+        let mut below = Txn::unsigned_transfer(1, 0);
+        below.txn_fee = 9;
+        let mut at_minimum = Txn::unsigned_transfer(1, 1);
+        at_minimum.txn_fee = 10;
+
+        assert!(!miner.admits_txn(&below));
+        assert!(miner.admits_txn(&at_minimum));
+        [below.clone(), at_minimum.clone()].iter().for_each(|txn| {
+            let vote = signed_vote(&mut miner, txn);
+            miner.process_txn_validator(vote);
+        });
+        assert!(!miner.txn_pool.pending.contains_key(&below.txn_id));
+        assert!(miner.txn_pool.pending.contains_key(&at_minimum.txn_id));
+
+        let args = vec![
+            "vrrb".to_string(),
+            MIN_RELAY_FEE_FLAG.to_string(),
+            "25".to_string(),
+        ];
+        assert_eq!(
+            Miner::min_relay_fee_from_args(args),
+            (25, vec!["vrrb".to_string()])
+        );
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_claim_taking_a_known_pubkey_is_rejected() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_duplicate_claim_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        let known = Claim::new("pubkey".to_string(), "address".to_string(), 1);
+        miner.insert_claim(known.pubkey.clone(), known.clone());
+
+        let other_address = Claim::new("pubkey".to_string(), "other_address".to_string(), 1);
+        let mut other_hash = known.clone();
+        other_hash.hash = "forged".to_string();
+        assert!(!miner.admits_claim(&other_address));
+        assert!(!miner.admits_claim(&other_hash));
+
+        // The same claim, or the same wallet at a later nonce, is let through.
+        let mut nonced_up = known.clone();
+        nonced_up.nonce_up();
+        assert!(miner.admits_claim(&known));
+        assert!(miner.admits_claim(&nonced_up));
+        assert!(miner.admits_claim(&Claim::new(
+            "new_pubkey".to_string(),
+            "address".to_string(),
+            1
+        )));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_txn_selection_stops_at_block_cost_limit() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_txn_selection_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        let n_txns = (BLOCK_COST_LIMIT / BASE_TXN_COST) as u128;
+        (0..n_txns).for_each(|n| {
+            let mut txn = Txn::unsigned_transfer(1, n);
+            txn.txn_fee = n;
+            miner.txn_pool.confirmed.insert(txn.txn_id.clone(), txn);
+        });
+
+        let selected = miner.select_txns();
+        let block_cost = Block::block_cost(&selected);
+        assert!(selected.len() > 0 && selected.len() < n_txns as usize);
+        assert!(block_cost <= BLOCK_COST_LIMIT);
+
+        let lowest_selected_fee = selected.values().map(|txn| txn.txn_fee).min().unwrap();
+        let skipped: Vec<&Txn> = miner
+            .txn_pool
+            .confirmed
+            .values()
+            .filter(|txn| !selected.contains_key(&txn.txn_id))
+            .collect();
+        assert!(skipped.iter().all(|txn| txn.txn_fee <= lowest_selected_fee));
+        let next = skipped.iter().max_by_key(|txn| txn.txn_fee).unwrap();
+        assert!(block_cost + txn_cost(next) > BLOCK_COST_LIMIT);
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_quorum_scales_with_threshold_and_validators() {
+        [
+            (0.6, 5, 3),
+            (0.6, 4, 3),
+            (0.6, 10, 6),
+            (1.0, 3, 3),
+            (0.34, 3, 2),
+            (0.5, 0, 1),
+        ]
+        .iter()
+        .for_each(|(threshold, active_validators, required)| {
+            let quorum = QuorumConfig {
+                threshold: *threshold,
+            };
+            assert_eq!(quorum.required(*active_validators), *required);
+        });
+
+        let args = vec![
+            "vrrb".to_string(),
+            VALIDATOR_THRESHOLD_FLAG.to_string(),
+            "0.75".to_string(),
+        ];
+        let (quorum, remaining) = QuorumConfig::from_args(args);
+        assert_eq!(quorum.threshold, 0.75);
+        assert_eq!(remaining, vec!["vrrb".to_string()]);
+        let args = vec![VALIDATOR_THRESHOLD_FLAG.to_string(), "1.5".to_string()];
+        assert_eq!(QuorumConfig::from_args(args).0, QuorumConfig::default());
+    }
+
+    #[test]
+    fn test_txn_confirmed_once_quorum_of_active_validators_votes() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_quorum_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        [(0.6, 5), (0.6, 10), (1.0, 4), (0.25, 8)]
+            .iter()
+            .for_each(|(threshold, n_validators)| {
+                let mut miner = test_miner(&state_path);
+                miner.quorum = QuorumConfig {
+                    threshold: *threshold,
+                };
+                miner.insert_claim(miner.claim.pubkey.clone(), miner.claim.clone());
+                claim_map(*n_validators + 1)
+                    .into_iter()
+                    .for_each(|(pubkey, claim)| miner.insert_claim(pubkey, claim));
+                // Slashed claims don't count towards the quorum.
+                miner.slash_claim(format!("pubkey_{}", n_validators));
+                assert_eq!(miner.active_validators(), *n_validators as usize);
+
+                let required = miner.quorum.required(*n_validators as usize);
+                let mut txn = Txn::unsigned_transfer(1, 0);
+                let txn_id = txn.txn_id.clone();
+                (0..required - 1).for_each(|n| {
+                    txn.validators.insert(format!("pubkey_{}", n), true);
+                });
+                txn.validators.insert("rejecting".to_string(), false);
+                miner.txn_pool.pending.insert(txn_id.clone(), txn);
+
+                miner.check_confirmed(txn_id.clone());
+                assert!(!miner.txn_pool.confirmed.contains_key(&txn_id));
+
+                if let Some(txn) = miner.txn_pool.pending.get_mut(&txn_id) {
+                    txn.validators.insert(format!("pubkey_{}", required), true);
+                }
+                miner.check_confirmed(txn_id.clone());
+                assert!(miner.txn_pool.confirmed.contains_key(&txn_id));
+            });
+        let _ = std::fs::remove_file(&state_path);
+    }
+    #[test]
+    fn test_saved_pools_are_restored_after_a_restart() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_saved_pools_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let pools_path = std::env::temp_dir()
+            .join("vrrb_test_saved_pools.json")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let _ = std::fs::remove_file(&pools_path);
+
+        let mut miner = test_miner(&state_path);
+        assert!(!miner.restore_pools(&pools_path));
+        let pending = Txn::unsigned_transfer(1, 0);
+        let confirmed = Txn::unsigned_transfer(2, 0);
+        miner
+            .txn_pool
+            .pending
+            .insert(pending.txn_id.clone(), pending.clone());
+        miner
+            .txn_pool
+            .confirmed
+            .insert(confirmed.txn_id.clone(), confirmed.clone());
+        claim_map(2).into_iter().for_each(|(pubkey, claim)| {
+            miner.claim_pool.pending.insert(pubkey, claim);
+        });
+        miner.save_pools(&pools_path).unwrap();
+
+        let mut restarted = test_miner(&state_path);
+        assert!(restarted.restore_pools(&pools_path));
+        assert!(restarted.txn_pool.pending.contains_key(&pending.txn_id));
+        assert!(restarted.txn_pool.confirmed.contains_key(&confirmed.txn_id));
+        assert_eq!(restarted.claim_pool.pending.len(), 2);
+        assert!(restarted.claim_pool.pending.contains_key("pubkey_1"));
+
+        let _ = std::fs::remove_file(&state_path);
+        let _ = std::fs::remove_file(&pools_path);
+    }
+
+    // Claims keyed by the pubkeys of the wallets that own them.
+    fn owned_claims(wallets: &[WalletAccount]) -> LinkedHashMap<String, Claim> {
+        wallets
+            .iter()
+            .map(|wallet| {
+                let claim = Claim::new(wallet.get_pubkey(), wallet.clone().get_address(1), 1);
+                (claim.pubkey.clone(), claim)
+            })
+            .collect()
+    }
+
+    // Applies a block at `timestamp` carrying the owner's announcement that
+    // its claim is offline until `until`.
+    fn announce_downtime(miner: &Miner, owner: &WalletAccount, timestamp: u128, until: u128) {
+        let mut block = Block::genesis(
+            &RewardState::start(),
+            miner.claim.clone(),
+            owner.get_secretkey(),
+        )
+        .unwrap();
+        block.header.block_reward.category = Category::Flake(Some(0));
+        block.header.timestamp = timestamp;
+        let txn = Txn::planned_downtime(&PlannedDowntime::sign(owner, timestamp, until));
+        block.txns.insert(txn.txn_id.clone(), txn);
+        miner.network_state.dump(&block);
+    }
+
+    #[test]
+    fn test_abandonment_is_suppressed_during_announced_downtime() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_downtime_abandonment_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let wallets = vec![WalletAccount::new(), WalletAccount::new()];
+        let claims = owned_claims(&wallets);
+        NetworkState::restore(&state_path).update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        let miner = test_miner(&state_path);
+        let (away, other) = (wallets[0].get_pubkey(), wallets[1].get_pubkey());
+        let now = miner.network_timestamp();
+        assert!(!miner.abandonment_suppressed(&away, now));
+
+        let until = now + 60 * SECOND;
+        announce_downtime(&miner, &wallets[0], now, until);
+        assert!(miner.abandonment_suppressed(&away, now));
+        assert!(miner.abandonment_suppressed(&away, until - 1));
+        assert!(!miner.abandonment_suppressed(&other, now));
+        // Votes to abandon the claim count again once the downtime is over.
+        assert!(!miner.abandonment_suppressed(&away, until));
+
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_claim_matures_for_abandonment_on_a_mock_clock() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_mock_clock_abandonment_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let wallets = vec![WalletAccount::new()];
+        let claims = owned_claims(&wallets);
+        let hash = claims.values().next().unwrap().hash.clone();
+        NetworkState::restore(&state_path).update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        let clock = MockClock::new(1_000 * SECOND);
+        let mut miner = test_miner(&state_path);
+        miner.time = SharedClock::new(clock.clone());
+        let timeout = miner.reward_state.params.abandonment_timeout;
+        let pubkey = wallets[0].get_pubkey();
+
+        // The last block started the timer, the claim due to mine the next one
+        // has the abandonment timeout to do it.
+        miner.current_nonce_timer = miner.network_timestamp();
+        let until = clock.now() + 2 * timeout * SECOND;
+        announce_downtime(&miner, &wallets[0], clock.now(), until);
+        (1..=timeout).for_each(|elapsed| {
+            clock.advance(SECOND);
+            assert_eq!(miner.check_time_elapsed(), elapsed);
+            assert!(!miner.claim_overdue());
+        });
+        clock.advance(SECOND);
+        assert!(miner.claim_overdue());
+
+        // Its owner announced downtime, so it isn't abandoned until that's over.
+        assert!(miner.abandonment_suppressed(&pubkey, miner.network_timestamp()));
+        clock.set(until);
+        assert!(!miner.abandonment_suppressed(&pubkey, miner.network_timestamp()));
+
+        // Abandoning the claim starts the timer over.
+        assert!(miner.claim_map.contains_key(&pubkey));
+        miner.abandoned_claim(hash);
+        assert!(!miner.claim_map.contains_key(&pubkey));
+        assert_eq!(miner.check_time_elapsed(), 0);
+        assert!(!miner.claim_overdue());
+
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_election_skips_claims_in_downtime() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_downtime_election_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let wallets: Vec<WalletAccount> = (0..3).map(|_| WalletAccount::new()).collect();
+        let claims = owned_claims(&wallets);
+        NetworkState::restore(&state_path).update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims: claims.clone(),
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        let mut miner = test_miner(&state_path);
+        miner.retain_claims(|pubkey, _| claims.contains_key(pubkey));
+        claims
+            .iter()
+            .for_each(|(pubkey, claim)| miner.insert_claim(pubkey.clone(), claim.clone()));
+        let nonce = (1..)
+            .find(|nonce| miner.get_lowest_pointer(*nonce).is_some())
+            .unwrap();
+        let (winner, _) = miner.get_lowest_pointer(nonce).unwrap();
+        let owner = wallets
+            .iter()
+            .find(|wallet| claims.get(&wallet.get_pubkey()).unwrap().hash == winner)
+            .unwrap();
+
+        let mut last_block = Block::genesis(
+            &RewardState::start(),
+            miner.claim.clone(),
+            owner.get_secretkey(),
+        )
+        .unwrap();
+        last_block.header.timestamp = 1_000;
+        miner.last_block = Some(last_block.clone());
+        announce_downtime(&miner, owner, 500, 2_000);
+        assert_ne!(
+            miner.get_lowest_pointer(nonce).map(|(hash, _)| hash),
+            Some(winner.clone())
+        );
+
+        // The claim is back in the election from the first block after its downtime.
+        last_block.header.timestamp = 2_000;
+        miner.last_block = Some(last_block);
+        assert_eq!(
+            miner.get_lowest_pointer(nonce).map(|(hash, _)| hash),
+            Some(winner)
+        );
+
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_only_pending_txns_are_rebroadcast() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_rebroadcast_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        let mut pending = Txn::unsigned_transfer(1, 0);
+        pending.validators.insert("pubkey_0".to_string(), true);
+        let confirmed = Txn::unsigned_transfer(2, 0);
+        miner
+            .txn_pool
+            .pending
+            .insert(pending.txn_id.clone(), pending.clone());
+        miner
+            .txn_pool
+            .confirmed
+            .insert(confirmed.txn_id.clone(), confirmed.clone());
+
+        match miner.rebroadcast_txn(&pending.txn_id, "node") {
+            Some(MessageType::TxnMessage { txn, sender_id }) => {
+                assert_eq!(txn.txn_id, pending.txn_id);
+                assert!(txn.validators.is_empty());
+                assert_eq!(sender_id, "node");
+            }
+            message => panic!("expected a txn message, got {:?}", message),
+        }
+        assert!(miner.rebroadcast_txn(&confirmed.txn_id, "node").is_none());
+        assert!(miner.rebroadcast_txn("unknown", "node").is_none());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_miner_sees_blocks_applied_through_the_shared_state() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_miner_shared_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let miner = signing_miner(&state_path);
+        let genesis = miner.last_block.clone().unwrap();
+        assert_eq!(miner.network_state.get_balance("address"), 0);
+
+        // Applied by another thread's handle, the miner isn't sent the state.
+        let network_state = miner.network_state.clone();
+        network_state.dump(&genesis);
+        assert_eq!(
+            miner.network_state.get_balance("address"),
+            crate::params::GENESIS_REWARD
+        );
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_votes_without_a_valid_signature_are_dropped() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_signed_votes_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        let mut txn = Txn::unsigned_transfer(1, 0);
+        txn.txn_fee = MIN_TXN_FEE;
+
+        let mut forged = signed_vote(&mut miner, &txn);
+        forged.pubkey = WalletAccount::new().get_pubkey();
+        miner.process_txn_validator(forged);
+        assert!(!miner.txn_pool.pending.contains_key(&txn.txn_id));
+
+        let vote = signed_vote(&mut miner, &txn);
+        miner.process_txn_validator(vote.clone());
+        let pending = miner.txn_pool.pending.get(&txn.txn_id).unwrap();
+        assert!(pending.valid_vote_signature(&vote.pubkey));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_votes_only_count_from_eligible_claim_holders() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_eligible_votes_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        let mut txn = Txn::unsigned_transfer(1, 0);
+        txn.txn_fee = MIN_TXN_FEE;
+
+        // A self-signed vote from a pubkey without a claim is dropped.
+        let outsider = WalletAccount::new();
+        let outsider_vote = TxnValidator {
+            pubkey: outsider.get_pubkey(),
+            vote: true,
+            txn: txn.clone(),
+            signature: String::new(),
+        }
+        .signed(&outsider.get_secretkey());
+        miner.process_txn_validator(outsider_vote.clone());
+        assert!(!miner.txn_pool.pending.contains_key(&txn.txn_id));
+
+        // Votes the txn arrives carrying are stripped unless they'd count.
+        let mut carried = txn.clone();
+        carried
+            .validators
+            .insert(outsider_vote.pubkey.clone(), true);
+        carried
+            .validator_signatures
+            .insert(outsider_vote.pubkey.clone(), outsider_vote.signature);
+        carried.validators.insert("unsigned".to_string(), true);
+        let vote = signed_vote(&mut miner, &carried);
+        miner.process_txn_validator(vote.clone());
+        let pending = miner.txn_pool.pending.get(&txn.txn_id).unwrap();
+        assert_eq!(
+            pending.validators.keys().collect::<Vec<_>>(),
+            vec![&vote.pubkey]
+        );
+        assert_eq!(pending.validator_signatures.len(), 1);
+
+        // Only the votes of eligible claim holders are tallied.
+        let mut txn = pending.clone();
+        txn.validators.insert(outsider.get_pubkey(), false);
+        assert_eq!(
+            miner.confirmation_status(&txn),
+            ConfirmationStatus::Confirmed
+        );
+        miner.slash_claim(vote.pubkey.clone());
+        assert_eq!(
+            miner.confirmation_status(&txn),
+            ConfirmationStatus::Unconfirmed
+        );
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_txn_status_follows_required_confirmations() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_required_confirmations_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut miner = test_miner(&state_path);
+        miner.claim_map = claim_map(3);
+        assert_eq!(miner.required_confirmations(), 2);
+
+        let mut txn = Txn::unsigned_transfer(1, 0);
+        txn.validators.insert("pubkey_0".to_string(), true);
+        assert_eq!(
+            miner.confirmation_status(&txn),
+            ConfirmationStatus::Unconfirmed
+        );
+        miner
+            .txn_pool
+            .pending
+            .insert(txn.txn_id.clone(), txn.clone());
+        miner.check_confirmed(txn.txn_id.clone());
+        assert!(miner.txn_pool.pending.contains_key(&txn.txn_id));
+
+        txn.validators.insert("pubkey_1".to_string(), true);
+        assert_eq!(
+            miner.confirmation_status(&txn),
+            ConfirmationStatus::Confirmed
+        );
+        assert_eq!(miner.confirmation_status(&txn).to_string(), "confirmed");
+        miner
+            .txn_pool
+            .pending
+            .insert(txn.txn_id.clone(), txn.clone());
+        miner.check_confirmed(txn.txn_id.clone());
+        assert!(miner.txn_pool.confirmed.contains_key(&txn.txn_id));
+
+        // Three of the four voters against leaves too few to confirm it.
+        let mut rejected = Txn::unsigned_transfer(1, 1);
+        (0..3).for_each(|n| {
+            rejected.validators.insert(format!("pubkey_{}", n), false);
+        });
+        assert_eq!(
+            miner.confirmation_status(&rejected),
+            ConfirmationStatus::Rejected
+        );
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_finalized_txns_are_collected_from_the_pools() {
+        let state_path = std::env::temp_dir()
+            .join("vrrb_test_pool_gc_state.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let mut miner = test_miner(&state_path);
+        miner.min_relay_fee = 0;
+        claim_map(3)
+            .into_iter()
+            .for_each(|(pubkey, claim)| miner.insert_claim(pubkey, claim));
+
+        let txns: Vec<Txn> = (1..=3)
+            .map(|amount| Txn::unsigned_transfer(amount, 0))
+            .collect();
+        txns.iter().for_each(|txn| {
+            let vote = signed_vote(&mut miner, txn);
+            miner.process_txn_validator(vote);
+        });
+        let confirmed = miner.txn_pool.pending.remove(&txns[1].txn_id).unwrap();
+        miner
+            .txn_pool
+            .confirmed
+            .insert(confirmed.txn_id.clone(), confirmed);
+        // A vote on a live claim and one on a claim that has left the claim map.
+        let live = miner.claim_map.get("pubkey_1").unwrap().clone();
+        let stale = Claim::new("pubkey_gone".to_string(), "address_gone".to_string(), 1);
+        miner
+            .abandoned_claim_counter
+            .insert("pubkey_0".to_string(), live);
+        miner
+            .abandoned_claim_counter
+            .insert("pubkey_2".to_string(), stale);
+        let before = miner.pool_metrics();
+        assert_eq!((before.pending_txns, before.confirmed_txns), (2, 1));
+        assert_eq!((before.txn_votes, before.abandonment_votes), (3, 2));
+
+        assert!(!miner.gc_due());
+        miner.blocks_since_gc = POOL_GC_INTERVAL;
+        assert!(miner.gc_due());
+        miner.finalize_txns(&[txns[0].txn_id.clone(), txns[1].txn_id.clone()]);
+        miner.collect_garbage();
+        assert!(!miner.gc_due());
+
+        let after = miner.pool_metrics();
+        assert_eq!((after.pending_txns, after.confirmed_txns), (1, 0));
+        assert_eq!((after.txn_votes, after.abandonment_votes), (1, 1));
+        assert!(miner.txn_pool.pending.contains_key(&txns[2].txn_id));
+        assert!(miner.abandoned_claim_counter.contains_key("pubkey_0"));
+
+        // A late vote doesn't bring a finalized txn back into the pool.
+        let vote = signed_vote(&mut miner, &txns[0]);
+        miner.process_txn_validator(vote);
+        assert!(miner.txn_status(&txns[0].txn_id).is_none());
+        assert_eq!(miner.pool_metrics().pending_txns, 1);
+
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_overlapping_pools_converge_after_a_sync_exchange() {
+        let paths: Vec<String> = ["a", "b"]
+            .iter()
+            .map(|name| {
+                std::env::temp_dir()
+                    .join(format!("vrrb_test_pool_sync_state_{}.db", name))
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        paths.iter().for_each(|path| {
+            let _ = std::fs::remove_file(path);
+        });
+        let mut miner_a = test_miner(&paths[0]);
+        let mut miner_b = test_miner(&paths[1]);
+        miner_a.min_relay_fee = 0;
+        miner_b.min_relay_fee = 0;
+        let txns: Vec<Txn> = (0..6).map(|n| Txn::unsigned_transfer(1, n)).collect();
+        txns[..4].iter().for_each(|txn| {
+            miner_a.process_txn(txn.clone());
+        });
+        txns[2..].iter().for_each(|txn| {
+            miner_b.process_txn(txn.clone());
+        });
+        // Confirmed txns aren't fetched again.
+        let confirmed = Txn::unsigned_transfer(1, 6);
+        miner_a.process_txn(confirmed.clone());
+        miner_b
+            .txn_pool
+            .confirmed
+            .insert(confirmed.txn_id.clone(), confirmed.clone());
+
+        // What the SyncPool, ProcessPoolTxnIds, SendTxns and ProcessFetchedTxns
+        // handlers do between the two.
+        let sync = |from: &Miner, to: &mut Miner| {
+            let txn_ids = to.txn_pool.missing(&from.txn_pool.pending_ids());
+            to.process_synced_txns(from.txns_for(&txn_ids)).len()
+        };
+        assert_eq!(
+            miner_b.txn_pool.missing(&miner_a.txn_pool.pending_ids()),
+            vec![txns[0].txn_id.clone(), txns[1].txn_id.clone()]
+        );
+        assert_eq!(sync(&miner_a, &mut miner_b), 2);
+        assert_eq!(sync(&miner_b, &mut miner_a), 2);
+
+        txns.iter().for_each(|txn| {
+            assert!(miner_a.txn_pool.pending.contains_key(&txn.txn_id));
+            assert!(miner_b.txn_pool.pending.contains_key(&txn.txn_id));
+        });
+        assert!(!miner_b.txn_pool.pending.contains_key(&confirmed.txn_id));
+        assert_eq!(sync(&miner_a, &mut miner_b), 0);
+
+        paths.iter().for_each(|path| {
+            let _ = std::fs::remove_file(path);
+        });
+    }
+
+    // A miner on a mock clock with one claim in its claim map, the last block
+    // confirmed `since` seconds ago by its header timestamp.
+    fn abandonment_miner(name: &str, since: u128) -> (Miner, MockClock, String, String) {
+        let state_path = std::env::temp_dir()
+            .join(name)
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&state_path);
+        let claims = owned_claims(&[WalletAccount::new()]);
+        let hash = claims.values().next().unwrap().hash.clone();
+        NetworkState::restore(&state_path).update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        let clock = MockClock::new(1_000 * SECOND);
+        let mut miner = test_miner(&state_path);
+        miner.time = SharedClock::new(clock.clone());
+        miner.current_nonce_timer = clock.now() - since * SECOND;
+
+        (miner, clock, hash, state_path)
+    }
+
+    #[test]
+    fn test_claim_is_voted_abandoned_once_per_timeout() {
+        let (mut miner, clock, hash, state_path) =
+            abandonment_miner("vrrb_test_abandonment_vote_state.db", 2);
+        let timeout = miner.abandonment_timeout();
+        assert_eq!(timeout, miner.reward_state.params.abandonment_timeout);
+
+        // The time since the last block's header counts, not since this miner
+        // started waiting.
+        clock.advance((timeout - 2) * SECOND);
+        assert!(miner.abandonment_vote(&hash).is_none());
+        clock.advance(SECOND);
+        let claim = miner.abandonment_vote(&hash).unwrap();
+        assert_eq!(claim.hash, hash);
+        assert_eq!(
+            miner.abandoned_claim_counter.get(&miner.claim.pubkey),
+            Some(&claim)
+        );
+
+        // Still overdue, but the vote isn't repeated within the timeout.
+        assert!(miner.abandonment_vote(&hash).is_none());
+        clock.advance((timeout - 1) * SECOND);
+        assert!(miner.claim_overdue());
+        assert!(miner.abandonment_vote(&hash).is_none());
+        clock.advance(SECOND);
+        assert!(miner.abandonment_vote(&hash).is_some());
+        assert!(miner.abandonment_vote("unknown").is_none());
+
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_abandonment_rounds_are_configurable_but_not_the_timeout() {
+        let args = vec![
+            "vrrb".to_string(),
+            ABANDONMENT_ROUNDS_FLAG.to_string(),
+            "2".to_string(),
+        ];
+        let (abandonment, remaining) = AbandonmentConfig::from_args(args);
+        assert_eq!(abandonment, AbandonmentConfig { rounds: 2 });
+        assert_eq!(remaining, vec!["vrrb".to_string()]);
+        let args = vec![ABANDONMENT_ROUNDS_FLAG.to_string(), "0".to_string()];
+        assert_eq!(
+            AbandonmentConfig::from_args(args).0,
+            AbandonmentConfig::default()
+        );
+
+        // A node can wait more timeouts, but each is the network's.
+        let (mut miner, clock, hash, state_path) =
+            abandonment_miner("vrrb_test_abandonment_config_state.db", 0);
+        miner.abandonment = abandonment;
+        let timeout = miner.reward_state.params.abandonment_timeout;
+        assert_eq!(miner.abandonment_timeout(), timeout);
+        clock.advance(2 * timeout * SECOND);
+        assert!(!miner.claim_overdue());
+        assert!(miner.abandonment_vote(&hash).is_none());
+        clock.advance(SECOND);
+        assert!(miner.abandonment_vote(&hash).is_some());
+
+        let _ = std::fs::remove_file(&state_path);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::block::SECOND;
+use ritelinked::LinkedHashMap;
+use std::convert::TryFrom;
+
+// The offsets of at most this many peers are kept, the least recently heard
+// from are dropped first.
+pub const MAX_SKEW_SAMPLES: usize = 64;
+// The skew isn't estimated until this many peers have been heard from.
+pub const MIN_SKEW_SAMPLES: usize = 3;
+// A warning is logged once the local clock is this many seconds off the network.
+pub const SKEW_WARNING_THRESHOLD: u128 = 10;
+// How far ahead of the local clock, in seconds, a peer's timestamp may be
+// before it's rejected, widened by the estimated skew.
+pub const MAX_TIMESTAMP_DRIFT: u128 = 15;
+
+/// Estimates how far the local clock is off from the network's, as the median
+/// of the offsets between peers' timestamps and the local time they arrived
+/// at. Only the latest offset of each peer is kept, so a peer can't outvote
+/// the others by sending more, and the median ignores a few skewed peers.
+/// Peers are told apart by the libp2p peer id gossipsub authenticated their
+/// message with, not by the sender id they claim in it.
+#[derive(Debug, Clone, Default)]
+pub struct ClockSkew {
+    // Peer timestamp minus local time in nanoseconds, keyed by libp2p peer
+    // id, at most MAX_SKEW_SAMPLES of them.
+    offsets: LinkedHashMap<String, i128>,
+    warned: bool,
+}
+
+impl ClockSkew {
+    pub fn new() -> ClockSkew {
+        ClockSkew::default()
+    }
+
+    /// Records the offset of a timestamp a peer sent, received at local time
+    /// `now`. Returns the estimated skew when it first goes over
+    /// SKEW_WARNING_THRESHOLD, so the caller can warn about it once.
+    pub fn record(&mut self, peer_id: &str, peer_timestamp: u128, now: u128) -> Option<i128> {
+        let offset = i128::try_from(peer_timestamp)
+            .unwrap_or(i128::MAX)
+            .saturating_sub(i128::try_from(now).unwrap_or(i128::MAX));
+        self.offsets.insert(peer_id.to_string(), offset);
+        while self.offsets.len() > MAX_SKEW_SAMPLES {
+            self.offsets.pop_front();
+        }
+
+        let exceeds = self.exceeds_threshold();
+        let newly_exceeded = exceeds && !self.warned;
+        self.warned = exceeds;
+        if newly_exceeded {
+            Some(self.estimated_skew())
+        } else {
+            None
+        }
+    }
+
+    /// How many nanoseconds the network's clock is ahead of the local clock,
+    /// negative if it's behind. 0 until MIN_SKEW_SAMPLES peers have been heard
+    /// from.
+    pub fn estimated_skew(&self) -> i128 {
+        if self.offsets.len() < MIN_SKEW_SAMPLES {
+            return 0;
+        }
+
+        let mut offsets: Vec<i128> = self.offsets.values().cloned().collect();
+        offsets.sort_unstable();
+        let mid = offsets.len() / 2;
+        if offsets.len() % 2 == 0 {
+            offsets[mid - 1] / 2 + offsets[mid] / 2
+        } else {
+            offsets[mid]
+        }
+    }
+
+    pub fn exceeds_threshold(&self) -> bool {
+        self.estimated_skew().unsigned_abs() > SKEW_WARNING_THRESHOLD * SECOND
+    }
+
+    /// The local time `now` corrected by the estimated skew.
+    pub fn adjusted(&self, now: u128) -> u128 {
+        let skew = self.estimated_skew();
+        if skew < 0 {
+            now.saturating_sub(skew.unsigned_abs())
+        } else {
+            now.saturating_add(skew.unsigned_abs())
+        }
+    }
+
+    /// How far ahead of the local clock a peer's timestamp may be, in
+    /// nanoseconds.
+    pub fn timestamp_tolerance(&self) -> u128 {
+        (MAX_TIMESTAMP_DRIFT * SECOND).saturating_add(self.estimated_skew().unsigned_abs())
+    }
+
+    /// Whether a peer's timestamp is within the tolerance of local time `now`.
+    pub fn valid_timestamp(&self, timestamp: u128, now: u128) -> bool {
+        timestamp <= now.saturating_add(self.timestamp_tolerance())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u128 = 1_000_000 * SECOND;
+
+    #[test]
+    fn peer_timestamps_recover_the_offset() {
+        let mut clock = ClockSkew::new();
+        let offset = 42 * SECOND;
+        assert_eq!(clock.record("peer_0", NOW + offset, NOW), None);
+        assert_eq!(clock.record("peer_1", NOW + offset + 1, NOW), None);
+        assert_eq!(clock.estimated_skew(), 0);
+
+        assert_eq!(
+            clock.record("peer_2", NOW + offset - 1, NOW),
+            Some(offset as i128)
+        );
+        assert_eq!(clock.adjusted(NOW), NOW + offset);
+        // Only the first crossing of the threshold is reported.
+        assert_eq!(clock.record("peer_3", NOW + offset, NOW), None);
+
+        let mut behind = ClockSkew::new();
+        (0..5).for_each(|n| {
+            behind.record(&format!("peer_{}", n), NOW - 7 * SECOND, NOW);
+        });
+        assert_eq!(behind.estimated_skew(), -7 * SECOND as i128);
+        assert!(!behind.exceeds_threshold());
+    }
+
+    #[test]
+    fn outliers_dont_move_the_median() {
+        let mut clock = ClockSkew::new();
+        (0..7).for_each(|n| {
+            clock.record(&format!("peer_{}", n), NOW + 2 * SECOND, NOW);
+        });
+        clock.record("fast", NOW + 3600 * SECOND, NOW);
+        clock.record("slow", 0, NOW);
+        clock.record("also_fast", u128::MAX, NOW);
+        assert_eq!(clock.estimated_skew(), 2 * SECOND as i128);
+
+        // Repeated samples from one peer replace its offset.
+        (0..100).for_each(|_| {
+            clock.record("fast", NOW + 3600 * SECOND, NOW);
+        });
+        assert_eq!(clock.estimated_skew(), 2 * SECOND as i128);
+    }
+
+    #[test]
+    fn samples_are_capped_dropping_the_least_recent_peers() {
+        let mut clock = ClockSkew::new();
+        (0..MAX_SKEW_SAMPLES).for_each(|n| {
+            clock.record(&format!("slow_{}", n), NOW - 60 * SECOND, NOW);
+        });
+        assert_eq!(clock.estimated_skew(), -60 * SECOND as i128);
+
+        // Hearing from a peer again keeps it, the others are dropped first.
+        (0..MAX_SKEW_SAMPLES).for_each(|n| {
+            clock.record("slow_0", NOW - 60 * SECOND, NOW);
+            clock.record(&format!("peer_{}", n), NOW, NOW);
+        });
+        assert_eq!(clock.offsets.len(), MAX_SKEW_SAMPLES);
+        assert!(clock.offsets.contains_key("slow_0"));
+        assert!(!clock.offsets.contains_key("slow_1"));
+        assert_eq!(clock.estimated_skew(), 0);
+    }
+
+    #[test]
+    fn timestamp_tolerance_widens_by_the_skew() {
+        let mut clock = ClockSkew::new();
+        let ahead = NOW + MAX_TIMESTAMP_DRIFT * SECOND + 30 * SECOND;
+        assert_eq!(clock.timestamp_tolerance(), MAX_TIMESTAMP_DRIFT * SECOND);
+        assert!(clock.valid_timestamp(NOW + MAX_TIMESTAMP_DRIFT * SECOND, NOW));
+        assert!(!clock.valid_timestamp(ahead, NOW));
+
+        (0..3).for_each(|n| {
+            clock.record(&format!("peer_{}", n), NOW + 30 * SECOND, NOW);
+        });
+        assert_eq!(
+            clock.timestamp_tolerance(),
+            (MAX_TIMESTAMP_DRIFT + 30) * SECOND
+        );
+        assert!(clock.valid_timestamp(ahead, NOW));
+    }
+}
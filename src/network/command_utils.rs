@@ -1,10 +1,14 @@
+use crate::activity::ExportFormat;
+use crate::balance_proof::BalanceProof;
 use crate::block::Block;
-use crate::blockchain::StateComponent;
+use crate::blockchain::{DeclineReason, StateComponent};
 use crate::claim::Claim;
+use crate::network::compact_block::CompactBlock;
 use crate::network::message_types::StateBlock;
 use crate::state::{Components, NetworkState};
-use crate::txn::Txn;
+use crate::txn::{Txn, MIN_TXN_FEE};
 use crate::validator::TxnValidator;
+use crate::wallet::{Address, TxnStatus};
 use serde::{Deserialize, Serialize};
 
 pub const NEWTXN: &str = "NEW_TXN";
@@ -13,27 +17,304 @@ pub const GETSTATE: &str = "GET_STE";
 pub const SENDSTATE: &str = "SENDSTE";
 pub const MINEBLOCK: &str = "MINEBLK";
 pub const STOPMINE: &str = "STPMINE";
+pub const PAUSEMINE: &str = "PAUSEMINE";
+pub const RESUMEMINE: &str = "RESUMEMINE";
 pub const ACQUIRECLAIM: &str = "ACQRCLM";
 pub const SELLCLAIM: &str = "SELLCLM";
 pub const SENDADDRESS: &str = "SENDADR";
+pub const NEWADDRESS: &str = "NEWADDR";
 pub const TXNTOPIC: &str = "txn";
 pub const QUIT: &str = "QUIT";
 pub const TEST: &str = "TEST";
 pub const GETBAL: &str = "GETBAL";
 pub const GETHEIGHT: &str = "GETHEIGHT";
+pub const EXPORTSTATE: &str = "EXPORTSTATE";
+pub const IMPORTSTATE: &str = "IMPORTSTATE";
+pub const EXPORTADDR: &str = "EXPORTADDR";
+pub const ESTIMATEFEE: &str = "ESTFEE";
+pub const REINSTATE: &str = "REINSTATE";
+pub const SHOWSLASHED: &str = "SHOWSLASHED";
+pub const FORKS: &str = "FORKS";
+pub const EXPORTCHECKPOINT: &str = "EXPORTCHECKPOINT";
+pub const TXSTATUS: &str = "TXSTATUS";
+pub const GETPEERS: &str = "GETPEERS";
+pub const KNOWNPEERS: &str = "KNOWNPEERS";
+pub const PRUNEARCHIVE: &str = "PRUNEARCHIVE";
+pub const LOGLEVEL: &str = "LOGLEVEL";
+pub const REBROADCAST: &str = "REBROADCAST";
+pub const SCHEDULE: &str = "SCHEDULE";
+pub const SNAPSHOT: &str = "SNAPSHOT";
+pub const GETCLAIM: &str = "GETCLAIM";
+pub const REWARDINFO: &str = "REWARDINFO";
+pub const REWARDSTATE: &str = "REWARDSTATE";
+pub const VERIFY: &str = "VERIFY";
+pub const SUPPLYAUDIT: &str = "SUPPLYAUDIT";
+pub const GETMETRICS: &str = "GETMETRICS";
+pub const DOWNTIME: &str = "DOWNTIME";
+pub const HELP: &str = "HELP";
+pub const CONTACT: &str = "CONTACT";
+pub const EXPORTSYNC: &str = "EXPORTSYNC";
+pub const IMPORTSYNC: &str = "IMPORTSYNC";
+
+/// How a command the terminal accepts is entered, shown by HELP.
+pub struct CommandHelp {
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub example: &'static str,
+}
+
+// Every command Command::from_str parses from the terminal, in the order HELP
+// lists them. A command added to the parser is added here too.
+pub const COMMAND_HELP: &[CommandHelp] = &[
+    CommandHelp {
+        usage: "SENDTXN <address number> <receiver> <amount> [fee]",
+        description: "Send coins from a wallet address, the fee defaults to the minimum relay fee",
+        example: "SENDTXN 1 <receiver> 500 10",
+    },
+    CommandHelp {
+        usage: "REINSTATE <address number> <bond> [fee]",
+        description: "Post a bond to reinstate a slashed claim",
+        example: "REINSTATE 1 1000",
+    },
+    CommandHelp {
+        usage: "EXPORTADDR <address> <csv|json> <path>",
+        description: "Export an address's txn activity",
+        example: "EXPORTADDR <receiver> csv activity.csv",
+    },
+    CommandHelp {
+        usage: "LOGLEVEL <target> <level>",
+        description: "Set the log level of a target, * for every target",
+        example: "LOGLEVEL vrrb::miner debug",
+    },
+    CommandHelp {
+        usage: "EXPORTCHECKPOINT <path> <up to height>",
+        description: "Export a checkpoint new nodes can bootstrap from",
+        example: "EXPORTCHECKPOINT checkpoint.json 100",
+    },
+    CommandHelp {
+        usage: "CONTACT <name> <address>",
+        description: "Add a contact to the address book, or change its address",
+        example: "CONTACT alice <receiver>",
+    },
+    CommandHelp {
+        usage: "EXPORTSYNC <path> <passphrase, spaces allowed>",
+        description: "Export the wallet's addresses, contacts, outbox and schedules, encrypted",
+        example: "EXPORTSYNC wallet.sync correct horse battery staple",
+    },
+    CommandHelp {
+        usage: "IMPORTSYNC <path> <passphrase, spaces allowed>",
+        description: "Merge a file exported by EXPORTSYNC from the same wallet on another device",
+        example: "IMPORTSYNC wallet.sync correct horse battery staple",
+    },
+    CommandHelp {
+        usage: "GETBAL <address number>",
+        description: "Show the balance of one of the wallet's addresses",
+        example: "GETBAL 1",
+    },
+    CommandHelp {
+        usage: "ESTFEE <target blocks>",
+        description: "Estimate the fee a txn needs to confirm within the target",
+        example: "ESTFEE 3",
+    },
+    CommandHelp {
+        usage: "EXPORTSTATE <path>",
+        description: "Export the network state",
+        example: "EXPORTSTATE state.json",
+    },
+    CommandHelp {
+        usage: "IMPORTSTATE <path> <db path>",
+        description: "Import a network state exported by EXPORTSTATE into a new ledger db",
+        example: "IMPORTSTATE state.json imported.db",
+    },
+    CommandHelp {
+        usage: "TXSTATUS <txn id>",
+        description: "Show the status of a txn the wallet sent",
+        example: "TXSTATUS <txn id>",
+    },
+    CommandHelp {
+        usage: "REBROADCAST <txn id>",
+        description: "Send a pending txn to the network again",
+        example: "REBROADCAST <txn id>",
+    },
+    CommandHelp {
+        usage: "GETCLAIM <pubkey|claim hash>",
+        description: "Show a claim and its standing",
+        example: "GETCLAIM <pubkey>",
+    },
+    CommandHelp {
+        usage: "DOWNTIME <seconds>",
+        description: "Announce planned downtime so the node's claim isn't abandoned",
+        example: "DOWNTIME 600",
+    },
+    CommandHelp {
+        usage: "PRUNEARCHIVE <keep from height>",
+        description: "Drop archived blocks below a height",
+        example: "PRUNEARCHIVE 1000",
+    },
+    CommandHelp {
+        usage: "SCHEDULE ADD <address number> <receiver> <amount> <interval blocks> [payments]",
+        description: "Schedule recurring payments, they run until cancelled without [payments]",
+        example: "SCHEDULE ADD 1 <receiver> 50 10 12",
+    },
+    CommandHelp {
+        usage: "SCHEDULE LIST",
+        description: "List the wallet's scheduled payments",
+        example: "SCHEDULE LIST",
+    },
+    CommandHelp {
+        usage: "SCHEDULE CANCEL <id>",
+        description: "Cancel a scheduled payment",
+        example: "SCHEDULE CANCEL 1",
+    },
+    CommandHelp {
+        usage: "SCHEDULE RESUME <id>",
+        description: "Resume a paused scheduled payment",
+        example: "SCHEDULE RESUME 1",
+    },
+    CommandHelp {
+        usage: "SNAPSHOT EXPORT <path>",
+        description: "Export the node's data to an archive",
+        example: "SNAPSHOT EXPORT node.tar",
+    },
+    CommandHelp {
+        usage: "GET_STE",
+        description: "Request the network state from peers",
+        example: "GET_STE",
+    },
+    CommandHelp {
+        usage: "MINEBLK",
+        description: "Start mining",
+        example: "MINEBLK",
+    },
+    CommandHelp {
+        usage: "STPMINE",
+        description: "Stop mining",
+        example: "STPMINE",
+    },
+    CommandHelp {
+        usage: "PAUSEMINE",
+        description: "Pause mining, the claim stays active",
+        example: "PAUSEMINE",
+    },
+    CommandHelp {
+        usage: "RESUMEMINE",
+        description: "Resume paused mining",
+        example: "RESUMEMINE",
+    },
+    CommandHelp {
+        usage: "SENDADR",
+        description: "Announce the wallet's address to peers",
+        example: "SENDADR",
+    },
+    CommandHelp {
+        usage: "NEWADDR",
+        description: "Derive the wallet's next receive address",
+        example: "NEWADDR",
+    },
+    CommandHelp {
+        usage: "GETHEIGHT",
+        description: "Show the height of the local chain",
+        example: "GETHEIGHT",
+    },
+    CommandHelp {
+        usage: "REWARDINFO",
+        description: "Show the reward state",
+        example: "REWARDINFO",
+    },
+    CommandHelp {
+        usage: "REWARDSTATE",
+        description: "Show the epoch and the rewards left to emit",
+        example: "REWARDSTATE",
+    },
+    CommandHelp {
+        usage: "GETPEERS",
+        description: "Ask peers for the peers they know",
+        example: "GETPEERS",
+    },
+    CommandHelp {
+        usage: "KNOWNPEERS",
+        description: "Show the peers this node knows",
+        example: "KNOWNPEERS",
+    },
+    CommandHelp {
+        usage: "SHOWSLASHED",
+        description: "Show the slashed claims",
+        example: "SHOWSLASHED",
+    },
+    CommandHelp {
+        usage: "FORKS",
+        description: "Show the heights competing blocks were seen at and which one won",
+        example: "FORKS",
+    },
+    CommandHelp {
+        usage: "SUPPLYAUDIT",
+        description: "Check the ledger's supply against the rewards issued",
+        example: "SUPPLYAUDIT",
+    },
+    CommandHelp {
+        usage: "GETMETRICS",
+        description: "Show block propagation and validation metrics, and the pool sizes",
+        example: "GETMETRICS",
+    },
+    CommandHelp {
+        usage: "VERIFY",
+        description: "Audit the chain db against the ledger",
+        example: "VERIFY",
+    },
+    CommandHelp {
+        usage: "VERIFY CANCEL",
+        description: "Stop a running audit",
+        example: "VERIFY CANCEL",
+    },
+    CommandHelp {
+        usage: "HELP",
+        description: "Show this list",
+        example: "HELP",
+    },
+    CommandHelp {
+        usage: "QUIT",
+        description: "Flush the node's state and exit",
+        example: "QUIT",
+    },
+];
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum Command {
-    SendTxn(u32, String, u128), // address number, receiver address, amount
+    SendTxn(u32, String, u128, u128), // address number, receiver address, amount, fee
+    RequestReinstatement(u32, u128, u128), // address number, bond, fee
     ProcessTxn(Txn),
+    ProcessForwardedTxn(Txn, u8), // txn, hops
+    TxnAck(TxnValidator, String), // the acking miner's vote, sender id
     ProcessTxnValidator(TxnValidator),
     ConfirmedBlock(Block),
     PendingBlock(Block, String),
+    ProcessCompactBlock(CompactBlock, String, Option<String>), // compact block, sender id, source
+    SendTxns(Vec<String>, String),                             // txn ids, requestor
+    ProcessFetchedTxns(Vec<Txn>),
+    TxnsFinalized(Vec<String>),             // txn ids
+    SyncPool(String),                       // peer id
+    ProcessPoolTxnIds(Vec<String>, String), // txn ids, sender id
+    TxnStatus(String, TxnStatus),           // txn id, status
+    GetTxnStatus(String),                   // txn id
+    RebroadcastTxn(String),                 // txn id
+    SendPeers(String),                      // requestor
+    // peer ids and addresses, sender id
+    DialPeers(Vec<(String, String)>, String),
+    SendMissingBlock(String, String), // block last hash, requestor
+    // from height, to height, requestor
+    SendBlockRange(u128, u128, String),
+    ProcessBlockRange(Vec<Block>, String), // blocks, sender id
+    BlockHeightRejected(u128, String),     // the peer's tip height, peer id
+    SendBalanceProof(String, String),      // address, requestor
+    BalanceProof(BalanceProof, String),    // proof, sender id
     InvalidBlock(Block),
     ProcessClaim(Claim),
+    AnnounceDowntime(u128), // seconds
     CheckStateUpdateStatus((u128, Block, u128)),
     StateUpdateCompleted(NetworkState),
+    // the shared network state changed, for the miner to refresh what it derives from it
+    StateChanged,
     StoreStateDbChunk(StateBlock, Vec<u8>, u32, u32),
     SendState(String, u128),
     SendMessage(Vec<u8>),
@@ -43,10 +324,30 @@ pub enum Command {
     GetStateComponents(String, StateComponent),
     RequestedComponents(String, Components),
     StoreStateComponentChunk(Vec<u8>, u32, u32),
+    StateRequestDeclined(String, DeclineReason), // peer, reason
+    StoreMessageChunk(String, u32, u32, Vec<u8>), // message id, chunk number, total chunks, data
     StateUpdateComponents(Components),
+    InvalidStateComponents,
     UpdateLastBlock(Block),
     ClaimAbandoned(String, Claim),
-    SlashClaims(Vec<String>),
+    ExportState(String),
+    ExportCheckpoint(String, u128), // path, up to height
+    PruneArchive(u128),             // keep from height
+    SetLogLevel(String, String),    // target, level
+    ImportState(String, String),    // snapshot path, db path
+    // pubkey or claim hash
+    GetClaim(String),
+    ExportAddressActivity(String, ExportFormat, String), // address, format, path
+    EstimateFee(u32),                                    // target blocks
+    // address number, receiver, amount, interval blocks, payments
+    AddSchedule(u32, String, u128, u128, Option<u32>),
+    CancelSchedule(u32), // schedule id
+    ResumeSchedule(u32), // schedule id
+    ConfirmedHeight(u128),
+    ExportSnapshot(String),     // archive path
+    SetContact(String, String), // name, address
+    ExportSync(String, String), // path, passphrase
+    ImportSync(String, String), // path, passphrase
     UpdateAppMiner(Vec<u8>),
     UpdateAppBlockchain(Vec<u8>),
     UpdateAppMessageCache(Vec<u8>),
@@ -54,27 +355,79 @@ pub enum Command {
     CheckAbandoned,
     StartMiner,
     GetHeight,
+    GetRewardInfo,
+    RewardState,
+    GetPeers,
+    ShowKnownPeers,
+    ShowSlashed,
+    SupplyAudit,
+    GetMetrics,
+    ListSchedules,
+    Verify,
+    CancelVerify,
+    ShowForks,
     MineBlock,
     MineGenesis,
     StopMine,
+    PauseMining,
+    ResumeMining,
     GetState,
     ProcessBacklog,
     SendAddress,
-    NonceUp,
+    NewAddress,
+    Help,
     Quit,
 }
 
 impl Command {
+    /// The commands the terminal accepts with their arguments and an example
+    /// of each.
+    pub fn help_text() -> String {
+        let mut text = String::from("Commands, [] marks an optional argument:\n");
+        for help in COMMAND_HELP {
+            text.push_str(&format!(
+                "  {}\n      {}\n      e.g. {}\n",
+                help.usage, help.description, help.example
+            ));
+        }
+
+        text
+    }
+
     pub fn from_str(command_string: &str) -> Option<Command> {
         let args: Vec<&str> = command_string.split(' ').collect();
-        if args.len() == 4 {
+        if args[0] == SCHEDULE {
+            return Command::parse_schedule(&args);
+        }
+        if args[0] == SNAPSHOT {
+            return Command::parse_snapshot(&args);
+        }
+        if args[0] == EXPORTSYNC || args[0] == IMPORTSYNC {
+            return Command::parse_sync(command_string);
+        }
+        if args.len() == 5 {
             match args[0] {
-                SENDTXN => {
-                    return Some(Command::SendTxn(
-                        args[1].parse::<u32>().unwrap(),
-                        args[2].to_string(),
-                        args[3].parse::<u128>().unwrap(),
-                    ))
+                SENDTXN => return Command::parse_send_txn(&args),
+                _ => {
+                    println!("Invalid command string!");
+                    return None;
+                }
+            }
+        } else if args.len() == 4 {
+            match args[0] {
+                SENDTXN => return Command::parse_send_txn(&args),
+                REINSTATE => return Command::parse_reinstatement(&args),
+                EXPORTADDR => {
+                    if let Some(format) = ExportFormat::from_str(args[2]) {
+                        return Some(Command::ExportAddressActivity(
+                            args[1].to_string(),
+                            format,
+                            args[3].to_string(),
+                        ));
+                    } else {
+                        println!("Invalid export format, expected csv or json");
+                        return None;
+                    }
                 }
                 _ => {
                     println!("Invalid command string!");
@@ -83,6 +436,33 @@ impl Command {
             }
         } else if args.len() == 3 {
             match args[0] {
+                REINSTATE => return Command::parse_reinstatement(&args),
+                CONTACT => {
+                    return Some(Command::SetContact(
+                        args[1].to_string(),
+                        args[2].to_string(),
+                    ))
+                }
+                LOGLEVEL => {
+                    return Some(Command::SetLogLevel(
+                        args[1].to_string(),
+                        args[2].to_string(),
+                    ))
+                }
+                IMPORTSTATE => {
+                    return Some(Command::ImportState(
+                        args[1].to_string(),
+                        args[2].to_string(),
+                    ))
+                }
+                EXPORTCHECKPOINT => {
+                    if let Ok(up_to_height) = args[2].parse::<u128>() {
+                        return Some(Command::ExportCheckpoint(args[1].to_string(), up_to_height));
+                    } else {
+                        println!("Invalid command string");
+                        None
+                    }
+                }
                 _ => {
                     println!("Invalid command string!");
                     return None;
@@ -98,6 +478,35 @@ impl Command {
                         None
                     }
                 }
+                ESTIMATEFEE => {
+                    if let Ok(target_blocks) = args[1].parse::<u32>() {
+                        return Some(Command::EstimateFee(target_blocks));
+                    } else {
+                        println!("Invalid command string");
+                        None
+                    }
+                }
+                EXPORTSTATE => return Some(Command::ExportState(args[1].to_string())),
+                TXSTATUS => return Some(Command::GetTxnStatus(args[1].to_string())),
+                REBROADCAST => return Some(Command::RebroadcastTxn(args[1].to_string())),
+                GETCLAIM => return Some(Command::GetClaim(args[1].to_string())),
+                VERIFY if args[1] == "CANCEL" => return Some(Command::CancelVerify),
+                DOWNTIME => {
+                    if let Ok(seconds) = args[1].parse::<u128>() {
+                        return Some(Command::AnnounceDowntime(seconds));
+                    } else {
+                        println!("Invalid command string");
+                        None
+                    }
+                }
+                PRUNEARCHIVE => {
+                    if let Ok(keep_from_height) = args[1].parse::<u128>() {
+                        return Some(Command::PruneArchive(keep_from_height));
+                    } else {
+                        println!("Invalid command string");
+                        None
+                    }
+                }
                 _ => {
                     println!("Invalid command string");
                     None
@@ -108,8 +517,21 @@ impl Command {
                 GETSTATE => return Some(Command::GetState),
                 MINEBLOCK => return Some(Command::MineBlock),
                 STOPMINE => return Some(Command::StopMine),
+                PAUSEMINE => return Some(Command::PauseMining),
+                RESUMEMINE => return Some(Command::ResumeMining),
                 SENDADDRESS => return Some(Command::SendAddress),
+                NEWADDRESS => return Some(Command::NewAddress),
                 GETHEIGHT => return Some(Command::GetHeight),
+                REWARDINFO => return Some(Command::GetRewardInfo),
+                REWARDSTATE => return Some(Command::RewardState),
+                GETPEERS => return Some(Command::GetPeers),
+                KNOWNPEERS => return Some(Command::ShowKnownPeers),
+                SHOWSLASHED => return Some(Command::ShowSlashed),
+                SUPPLYAUDIT => return Some(Command::SupplyAudit),
+                GETMETRICS => return Some(Command::GetMetrics),
+                VERIFY => return Some(Command::Verify),
+                HELP => return Some(Command::Help),
+                FORKS => return Some(Command::ShowForks),
                 QUIT => return Some(Command::Quit),
                 _ => {
                     println!("Invalid command string");
@@ -118,4 +540,287 @@ impl Command {
             }
         }
     }
+
+    // SENDTXN <address number> <receiver> <amount> [fee], the fee defaults to MIN_TXN_FEE.
+    fn parse_send_txn(args: &[&str]) -> Option<Command> {
+        let receiver = match Address::parse(args[2]) {
+            Ok(address) => address,
+            Err(e) => {
+                println!("Invalid receiver address: {}", e);
+                return None;
+            }
+        };
+        let fee = match args.get(4).map(|fee| fee.parse::<u128>()) {
+            Some(Ok(fee)) => fee,
+            Some(Err(_)) => {
+                println!("Invalid command string");
+                return None;
+            }
+            None => MIN_TXN_FEE,
+        };
+        match (args[1].parse::<u32>(), args[3].parse::<u128>()) {
+            (Ok(address_number), Ok(amount)) => Some(Command::SendTxn(
+                address_number,
+                receiver.to_string(),
+                amount,
+                fee,
+            )),
+            _ => {
+                println!("Invalid command string");
+                None
+            }
+        }
+    }
+
+    // REINSTATE <address number> <bond> [fee], the fee defaults to MIN_TXN_FEE.
+    fn parse_reinstatement(args: &[&str]) -> Option<Command> {
+        let fee = match args.get(3).map(|fee| fee.parse::<u128>()) {
+            Some(Ok(fee)) => fee,
+            Some(Err(_)) => {
+                println!("Invalid command string");
+                return None;
+            }
+            None => MIN_TXN_FEE,
+        };
+        match (args[1].parse::<u32>(), args[2].parse::<u128>()) {
+            (Ok(address_number), Ok(bond)) => {
+                Some(Command::RequestReinstatement(address_number, bond, fee))
+            }
+            _ => {
+                println!("Invalid command string");
+                None
+            }
+        }
+    }
+
+    // SCHEDULE ADD <address number> <receiver> <amount> <interval blocks> [payments],
+    // SCHEDULE LIST, SCHEDULE CANCEL <id> or SCHEDULE RESUME <id>. A schedule
+    // without a number of payments runs until it's cancelled.
+    fn parse_schedule(args: &[&str]) -> Option<Command> {
+        match (args.get(1).copied(), args.len()) {
+            (Some("ADD"), 6) | (Some("ADD"), 7) => {
+                let receiver = match Address::parse(args[3]) {
+                    Ok(address) => address,
+                    Err(e) => {
+                        println!("Invalid receiver address: {}", e);
+                        return None;
+                    }
+                };
+                let payments = match args.get(6).map(|payments| payments.parse::<u32>()) {
+                    Some(Ok(payments)) => Some(payments),
+                    Some(Err(_)) => {
+                        println!("Invalid command string");
+                        return None;
+                    }
+                    None => None,
+                };
+                match (
+                    args[2].parse::<u32>(),
+                    args[4].parse::<u128>(),
+                    args[5].parse::<u128>(),
+                ) {
+                    (Ok(address_number), Ok(amount), Ok(interval_blocks))
+                        if interval_blocks > 0 =>
+                    {
+                        Some(Command::AddSchedule(
+                            address_number,
+                            receiver.to_string(),
+                            amount,
+                            interval_blocks,
+                            payments,
+                        ))
+                    }
+                    _ => {
+                        println!("Invalid command string");
+                        None
+                    }
+                }
+            }
+            (Some("LIST"), 2) => Some(Command::ListSchedules),
+            (Some("CANCEL"), 3) | (Some("RESUME"), 3) => {
+                if let Ok(id) = args[2].parse::<u32>() {
+                    if args[1] == "CANCEL" {
+                        Some(Command::CancelSchedule(id))
+                    } else {
+                        Some(Command::ResumeSchedule(id))
+                    }
+                } else {
+                    println!("Invalid command string");
+                    None
+                }
+            }
+            _ => {
+                println!("Invalid command string");
+                None
+            }
+        }
+    }
+
+    // EXPORTSYNC|IMPORTSYNC <path> <passphrase>. The passphrase is the rest of
+    // the line, spaces and all.
+    fn parse_sync(command_string: &str) -> Option<Command> {
+        let mut args = command_string.splitn(3, ' ');
+        match (args.next(), args.next(), args.next()) {
+            (Some(command), Some(path), Some(passphrase))
+                if !path.is_empty() && !passphrase.is_empty() =>
+            {
+                if command == EXPORTSYNC {
+                    Some(Command::ExportSync(
+                        path.to_string(),
+                        passphrase.to_string(),
+                    ))
+                } else {
+                    Some(Command::ImportSync(
+                        path.to_string(),
+                        passphrase.to_string(),
+                    ))
+                }
+            }
+            _ => {
+                println!("Invalid command string");
+                None
+            }
+        }
+    }
+
+    // SNAPSHOT EXPORT <path>. A snapshot is only imported into a stopped
+    // node, with --import-snapshot.
+    fn parse_snapshot(args: &[&str]) -> Option<Command> {
+        match (args.get(1).copied(), args.len()) {
+            (Some("EXPORT"), 3) => Some(Command::ExportSnapshot(args[2].to_string())),
+            (Some("IMPORT"), _) => {
+                println!(
+                    "Stop the node and start it with --import-snapshot <path> [--force] to \
+                     import a snapshot"
+                );
+                None
+            }
+            _ => {
+                println!("Invalid command string");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::AddressNetwork;
+
+    #[test]
+    fn help_parses() {
+        assert!(matches!(Command::from_str(HELP), Some(Command::Help)));
+    }
+
+    #[test]
+    fn help_lists_every_command() {
+        let text = Command::help_text();
+        let commands = [
+            SENDTXN,
+            REINSTATE,
+            EXPORTADDR,
+            LOGLEVEL,
+            EXPORTCHECKPOINT,
+            CONTACT,
+            EXPORTSYNC,
+            IMPORTSYNC,
+            GETBAL,
+            ESTIMATEFEE,
+            EXPORTSTATE,
+            IMPORTSTATE,
+            TXSTATUS,
+            REBROADCAST,
+            GETCLAIM,
+            DOWNTIME,
+            PRUNEARCHIVE,
+            SCHEDULE,
+            SNAPSHOT,
+            GETSTATE,
+            MINEBLOCK,
+            STOPMINE,
+            PAUSEMINE,
+            RESUMEMINE,
+            SENDADDRESS,
+            NEWADDRESS,
+            GETHEIGHT,
+            REWARDINFO,
+            REWARDSTATE,
+            GETPEERS,
+            KNOWNPEERS,
+            SHOWSLASHED,
+            FORKS,
+            SUPPLYAUDIT,
+            GETMETRICS,
+            VERIFY,
+            HELP,
+            QUIT,
+        ];
+        for command in commands.iter() {
+            assert!(
+                COMMAND_HELP
+                    .iter()
+                    .any(|help| help.usage.split(' ').next() == Some(*command)),
+                "{} is missing from the help",
+                command
+            );
+            assert!(text.contains(command));
+        }
+
+        // Every example has to parse, so the help can't drift from the parser.
+        let receiver = Address::new(AddressNetwork::default(), vec![7; 32]).to_string();
+        for help in COMMAND_HELP {
+            let example = help
+                .example
+                .replace("<receiver>", &receiver)
+                .replace("<txn id>", "txn_id")
+                .replace("<pubkey>", "pubkey");
+            assert!(
+                Command::from_str(&example).is_some(),
+                "{} doesn't parse",
+                example
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_numbers_are_rejected() {
+        let receiver = Address::new(AddressNetwork::default(), vec![7; 32]).to_string();
+        let send = |args: &str| Command::from_str(&format!("{} 1 {} {}", SENDTXN, receiver, args));
+        assert!(matches!(send("10 2"), Some(Command::SendTxn(1, _, 10, 2))));
+        assert!(matches!(
+            send("10"),
+            Some(Command::SendTxn(1, _, 10, MIN_TXN_FEE))
+        ));
+        assert!(send("10 fee").is_none());
+        assert!(send("ten").is_none());
+        assert!(Command::from_str(&format!("{} x {} 10", SENDTXN, receiver)).is_none());
+
+        assert!(matches!(
+            Command::from_str(&format!("{} 1 100 3", REINSTATE)),
+            Some(Command::RequestReinstatement(1, 100, 3))
+        ));
+        assert!(Command::from_str(&format!("{} 1 100 -3", REINSTATE)).is_none());
+        assert!(Command::from_str(&format!("{} 1 bond", REINSTATE)).is_none());
+    }
+
+    #[test]
+    fn sync_passphrases_keep_their_spaces() {
+        match Command::from_str(&format!(
+            "{} wallet.sync correct  horse battery",
+            EXPORTSYNC
+        )) {
+            Some(Command::ExportSync(path, passphrase)) => {
+                assert_eq!(path, "wallet.sync");
+                assert_eq!(passphrase, "correct  horse battery");
+            }
+            command => panic!("expected an export, got {:?}", command),
+        }
+        assert!(matches!(
+            Command::from_str(&format!("{} wallet.sync one", IMPORTSYNC)),
+            Some(Command::ImportSync(_, passphrase)) if passphrase == "one"
+        ));
+        assert!(Command::from_str(&format!("{} wallet.sync", IMPORTSYNC)).is_none());
+        assert!(Command::from_str(&format!("{} wallet.sync ", EXPORTSYNC)).is_none());
+    }
 }
@@ -0,0 +1,291 @@
+use crate::block::{Block, SECOND};
+use crate::claim::Claim;
+use crate::header::BlockHeader;
+use crate::pool::Pool;
+use crate::txn::Txn;
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+
+// How long to wait for missing txns before requesting the full block, in seconds.
+pub const COMPACT_BLOCK_TIMEOUT: u128 = 5;
+
+/// A block with its txns replaced by their ids. Peers rebuild the block from
+/// their own txn pool and only fetch the txns they haven't seen.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub neighbors: Option<Vec<BlockHeader>>,
+    pub height: u128,
+    pub txn_ids: Vec<String>,
+    pub claims: LinkedHashMap<String, Claim>,
+    pub hash: String,
+    pub abandoned_claim: Option<Claim>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Reconstruction {
+    Complete(Block),
+    Missing(Vec<String>),
+    // The rebuilt txns don't hash to the header's txn_hash, the full block has
+    // to be requested from the sender.
+    Mismatch,
+}
+
+/// A compact block waiting on txns requested from the peer that sent it.
+#[derive(Clone, Debug)]
+pub struct PendingCompactBlock {
+    pub compact: CompactBlock,
+    pub sender_id: String,
+    pub fetched: LinkedHashMap<String, Txn>,
+    pub requested_at: u128,
+}
+
+/// Compact blocks waiting on missing txns, keyed by block hash.
+#[derive(Clone, Debug)]
+pub struct CompactBlockCache {
+    pub pending: LinkedHashMap<String, PendingCompactBlock>,
+}
+
+impl CompactBlock {
+    pub fn from_block(block: &Block) -> CompactBlock {
+        CompactBlock {
+            header: block.header.clone(),
+            neighbors: block.neighbors.clone(),
+            height: block.height,
+            txn_ids: block.txns.keys().cloned().collect(),
+            claims: block.claims.clone(),
+            hash: block.hash.clone(),
+            abandoned_claim: block.abandoned_claim.clone(),
+        }
+    }
+
+    /// Rebuilds the block from the txn pool and any txns already fetched from the
+    /// sender, checking the rebuilt txns against the header's txn_hash.
+    pub fn reconstruct(
+        &self,
+        txn_pool: &Pool<String, Txn>,
+        fetched: &LinkedHashMap<String, Txn>,
+    ) -> Reconstruction {
+        let mut txns = LinkedHashMap::new();
+        let mut missing = vec![];
+        self.txn_ids.iter().for_each(|txn_id| {
+            let txn = fetched
+                .get(txn_id)
+                .or_else(|| txn_pool.confirmed.get(txn_id))
+                .or_else(|| txn_pool.pending.get(txn_id));
+            if let Some(txn) = txn {
+                txns.insert(txn_id.clone(), txn.clone());
+            } else {
+                missing.push(txn_id.clone());
+            }
+        });
+
+        if !missing.is_empty() {
+            return Reconstruction::Missing(missing);
+        }
+
+        if Block::txn_hash(&txns) != self.header.txn_hash {
+            return Reconstruction::Mismatch;
+        }
+
+        Reconstruction::Complete(Block {
+            header: self.header.clone(),
+            neighbors: self.neighbors.clone(),
+            height: self.height,
+            txns,
+            claims: self.claims.clone(),
+            hash: self.hash.clone(),
+            received_at: None,
+            received_from: None,
+            abandoned_claim: self.abandoned_claim.clone(),
+        })
+    }
+}
+
+impl CompactBlockCache {
+    pub fn new() -> CompactBlockCache {
+        CompactBlockCache {
+            pending: LinkedHashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, compact: CompactBlock, sender_id: String, requested_at: u128) {
+        self.pending.insert(
+            compact.hash.clone(),
+            PendingCompactBlock {
+                compact,
+                sender_id,
+                fetched: LinkedHashMap::new(),
+                requested_at,
+            },
+        );
+    }
+
+    /// Whether a block in the cache is waiting on the txn.
+    pub fn wants(&self, txn_id: &str) -> bool {
+        self.pending
+            .values()
+            .any(|pending| pending.compact.txn_ids.iter().any(|id| id == txn_id))
+    }
+
+    /// Adds fetched txns to the blocks waiting on them and retries their
+    /// reconstruction. Blocks that are complete or mismatched are removed from
+    /// the cache and returned, blocks still missing txns keep waiting.
+    pub fn add_txns(
+        &mut self,
+        txns: &[Txn],
+        txn_pool: &Pool<String, Txn>,
+    ) -> Vec<(PendingCompactBlock, Reconstruction)> {
+        let mut resolved = vec![];
+        self.pending.iter_mut().for_each(|(hash, pending)| {
+            txns.iter()
+                .filter(|txn| pending.compact.txn_ids.contains(&txn.txn_id))
+                .for_each(|txn| {
+                    pending.fetched.insert(txn.txn_id.clone(), txn.clone());
+                });
+
+            match pending.compact.reconstruct(txn_pool, &pending.fetched) {
+                Reconstruction::Missing(_) => {}
+                reconstruction => resolved.push((hash.clone(), reconstruction)),
+            }
+        });
+
+        resolved
+            .into_iter()
+            .filter_map(|(hash, reconstruction)| {
+                self.pending
+                    .remove(&hash)
+                    .map(|pending| (pending, reconstruction))
+            })
+            .collect()
+    }
+
+    /// Removes and returns the blocks that have waited longer than `timeout`
+    /// seconds for their missing txns.
+    pub fn expired(&mut self, now: u128, timeout: u128) -> Vec<PendingCompactBlock> {
+        let expired: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| now.saturating_sub(pending.requested_at) / SECOND > timeout)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+
+        expired
+            .iter()
+            .filter_map(|hash| self.pending.remove(hash))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::PoolKind;
+    use crate::test_utils::test_block;
+
+    fn block_with(txns: &[Txn]) -> Block {
+        let mut block = test_block(1, "hash");
+        block.txns = txns
+            .iter()
+            .map(|txn| (txn.txn_id.clone(), txn.clone()))
+            .collect();
+        block.header.txn_hash = Block::txn_hash(&block.txns);
+        block
+    }
+
+    fn test_txns(n: u128) -> Vec<Txn> {
+        (0..n)
+            .map(|nonce| Txn::unsigned_transfer(10, nonce))
+            .collect()
+    }
+
+    fn pool_with(txns: &[Txn]) -> Pool<String, Txn> {
+        let mut pool = Pool::new(PoolKind::Txn);
+        txns.iter().for_each(|txn| {
+            pool.confirmed.insert(txn.txn_id.clone(), txn.clone());
+        });
+        pool
+    }
+
+    #[test]
+    fn reconstructs_block_when_all_txns_are_known() {
+        let txns = test_txns(4);
+        let block = block_with(&txns);
+        let compact = CompactBlock::from_block(&block);
+        match compact.reconstruct(&pool_with(&txns), &LinkedHashMap::new()) {
+            Reconstruction::Complete(rebuilt) => {
+                assert_eq!(rebuilt.header.txn_hash, block.header.txn_hash);
+                assert_eq!(
+                    rebuilt.txns.keys().collect::<Vec<_>>(),
+                    block.txns.keys().collect::<Vec<_>>()
+                );
+            }
+            reconstruction => panic!("expected a complete block, got {:?}", reconstruction),
+        }
+    }
+
+    #[test]
+    fn fetches_only_missing_txns() {
+        let txns = test_txns(5);
+        let block = block_with(&txns);
+        let pool = pool_with(&txns[..3]);
+        let mut cache = CompactBlockCache::new();
+        let compact = CompactBlock::from_block(&block);
+
+        match compact.reconstruct(&pool, &LinkedHashMap::new()) {
+            Reconstruction::Missing(missing) => {
+                assert_eq!(
+                    missing,
+                    vec![txns[3].txn_id.clone(), txns[4].txn_id.clone()]
+                );
+            }
+            reconstruction => panic!("expected missing txns, got {:?}", reconstruction),
+        }
+
+        cache.insert(compact, "sender".to_string(), 0);
+        assert!(cache.add_txns(&txns[3..4], &pool).is_empty());
+        let resolved = cache.add_txns(&txns[4..], &pool);
+        assert_eq!(resolved.len(), 1);
+        assert!(cache.pending.is_empty());
+        match &resolved[0].1 {
+            Reconstruction::Complete(rebuilt) => assert_eq!(rebuilt.txns.len(), 5),
+            reconstruction => panic!("expected a complete block, got {:?}", reconstruction),
+        }
+    }
+
+    #[test]
+    fn txns_with_other_votes_still_reconstruct() {
+        let txns = test_txns(3);
+        let block = block_with(&txns);
+        // The local copy of a txn gathered other votes than the miner's did.
+        let mut local = txns.clone();
+        local[1].validators.insert("validator".to_string(), true);
+        let compact = CompactBlock::from_block(&block);
+        match compact.reconstruct(&pool_with(&local), &LinkedHashMap::new()) {
+            Reconstruction::Complete(rebuilt) => {
+                assert_eq!(Block::txn_hash(&rebuilt.txns), block.header.txn_hash)
+            }
+            reconstruction => panic!("expected a complete block, got {:?}", reconstruction),
+        }
+    }
+
+    #[test]
+    fn mismatched_txns_fall_back_to_full_block() {
+        let txns = test_txns(3);
+        let block = block_with(&txns);
+        // The local copy of a txn differs from the one the miner included.
+        let mut local = txns.clone();
+        local[1].txn_payload = format!("{}altered", local[1].txn_payload);
+        let compact = CompactBlock::from_block(&block);
+        match compact.reconstruct(&pool_with(&local), &LinkedHashMap::new()) {
+            Reconstruction::Mismatch => {}
+            reconstruction => panic!("expected a mismatch, got {:?}", reconstruction),
+        }
+
+        let mut cache = CompactBlockCache::new();
+        cache.insert(compact, "sender".to_string(), 0);
+        let expired = cache.expired(10 * SECOND, COMPACT_BLOCK_TIMEOUT);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].sender_id, "sender");
+    }
+}
@@ -1,7 +1,12 @@
 #[allow(unused_imports)]
 use crate::account::AccountState;
 use crate::network::command_utils::Command;
-use crate::network::protocol::{build_transport, VrrbNetworkBehavior};
+use crate::network::node::NodeAuth;
+use crate::network::peer_table::{PeerExchange, PeerTable};
+use crate::network::protocol::{self, build_transport, EventLog, VrrbNetworkBehavior};
+use crate::network::topics::{TopicNames, NETWORK_ID};
+use crate::params::MAX_GOSSIP_SIZE;
+use crate::utils::{take_flags, take_switch};
 use core::num::NonZeroU32;
 use libp2p::gossipsub::MessageId;
 use libp2p::gossipsub::{
@@ -10,27 +15,236 @@ use libp2p::gossipsub::{
 };
 use libp2p::identify::{Identify, IdentifyConfig};
 use libp2p::kad::{record::store::MemoryStore, Kademlia};
+use libp2p::multiaddr::Protocol;
 use libp2p::ping::{Ping, PingConfig};
-use libp2p::swarm::Swarm;
-use libp2p::{identity::Keypair, PeerId};
+use libp2p::swarm::{AddressScore, Swarm};
+use libp2p::{identity::Keypair, Multiaddr, PeerId};
+use rand::Rng;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
 use std::time::Duration;
+use thiserror::Error;
 use tokio::sync::mpsc;
 
-pub const MAX_TRANSMIT_SIZE: usize = 2000000;
+pub const LISTEN_FLAG: &str = "--listen";
+pub const EXTERNAL_ADDR_FLAG: &str = "--external-addr";
+pub const TOPIC_NAMESPACE_FLAG: &str = "--topic-namespace";
+// Subscribes and publishes to the legacy topic too, for networks that still
+// have nodes on it. Off by default, a node on it processes every message once.
+pub const LEGACY_TOPIC_FLAG: &str = "--legacy-topic";
+// A free port is picked from this range unless the node is given one.
+pub const DEFAULT_PORT_RANGE: (u16, u16) = (9292, 19292);
 
+/// The port to listen on, or an inclusive range to pick a free one from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortSpec {
+    Fixed(u16),
+    Range(u16, u16),
+}
+
+/// An interface to listen on, given as `<ip>`, `<ip>:<port>` or
+/// `<ip>:<from>-<to>`. An IPv6 address followed by a port goes in brackets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenSpec {
+    pub ip: IpAddr,
+    pub port: PortSpec,
+}
+
+/// Where the swarm listens, the addresses it announces and the namespace its
+/// topics are in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenConfig {
+    pub listen: Vec<ListenSpec>,
+    // Addresses a node behind NAT is reachable on, announced to its peers.
+    pub external_addrs: Vec<Multiaddr>,
+    pub topic_namespace: Option<String>,
+    // Whether the legacy topic is used as well, see topics::TopicNames.
+    pub legacy_topic: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum ListenError {
+    #[error("invalid listen address {0}, expected <ip>, <ip>:<port> or <ip>:<from>-<to>")]
+    InvalidListenSpec(String),
+    #[error("invalid external address {0}, expected a multiaddr")]
+    InvalidExternalAddr(String),
+    #[error("invalid topic namespace {0:?}, expected letters, digits, '-', '_' or '.'")]
+    InvalidNamespace(String),
+    #[error("{0} expects a value")]
+    MissingValue(&'static str),
+    #[error("no free port on {0} between {1} and {2}")]
+    NoFreePort(IpAddr, u16, u16),
+    #[error("unable to listen on {0}: {1}")]
+    Listen(Multiaddr, String),
+}
+
+impl Default for PortSpec {
+    fn default() -> PortSpec {
+        PortSpec::Range(DEFAULT_PORT_RANGE.0, DEFAULT_PORT_RANGE.1)
+    }
+}
+
+impl PortSpec {
+    fn parse(port: &str) -> Option<PortSpec> {
+        if let Some(split) = port.find('-') {
+            let from = port[..split].parse().ok()?;
+            let to = port[split + 1..].parse().ok()?;
+            if from > to {
+                return None;
+            }
+            Some(PortSpec::Range(from, to))
+        } else {
+            port.parse().ok().map(PortSpec::Fixed)
+        }
+    }
+}
+
+impl ListenSpec {
+    /// Listens on the fixed port or on the first port of the range that
+    /// `listen` binds, returning the address. The range is tried from a random
+    /// offset so that nodes started together on one host don't all try the
+    /// same ports. The port is bound once, by `listen`, which keeps the
+    /// listener, so nothing can take it between checking and listening.
+    pub fn listen<F>(&self, mut listen: F) -> Result<Multiaddr, ListenError>
+    where
+        F: FnMut(Multiaddr) -> Result<(), String>,
+    {
+        match self.port {
+            PortSpec::Fixed(port) => {
+                let addr = self.addr(port);
+                listen(addr.clone()).map_err(|e| ListenError::Listen(addr.clone(), e))?;
+                Ok(addr)
+            }
+            PortSpec::Range(from, to) => {
+                let n_ports = (to - from) as u32 + 1;
+                let offset = rand::thread_rng().gen_range(0, n_ports);
+                (0..n_ports)
+                    .map(|n| self.addr(from + ((offset + n) % n_ports) as u16))
+                    .find(|addr| listen(addr.clone()).is_ok())
+                    .ok_or(ListenError::NoFreePort(self.ip, from, to))
+            }
+        }
+    }
+
+    fn addr(&self, port: u16) -> Multiaddr {
+        Multiaddr::from(self.ip).with(Protocol::Tcp(port))
+    }
+}
+
+impl FromStr for ListenSpec {
+    type Err = ListenError;
+
+    fn from_str(spec: &str) -> Result<ListenSpec, ListenError> {
+        // Checked first, a bare IPv6 address has colons of its own.
+        if let Ok(ip) = spec.trim_start_matches('[').trim_end_matches(']').parse() {
+            return Ok(ListenSpec {
+                ip,
+                port: PortSpec::default(),
+            });
+        }
+
+        let invalid = || ListenError::InvalidListenSpec(spec.to_string());
+        let split = spec.rfind(':').ok_or_else(invalid)?;
+        let ip = spec[..split]
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| invalid())?;
+        let port = PortSpec::parse(&spec[split + 1..]).ok_or_else(invalid)?;
+        Ok(ListenSpec { ip, port })
+    }
+}
+
+impl ListenConfig {
+    /// Splits `--listen <spec>`, `--external-addr <multiaddr>`, both of which
+    /// may be repeated, `--topic-namespace <name>` and `--legacy-topic` out of
+    /// the arguments.
+    /// The node listens on DEFAULT_PORT_RANGE on every interface if it isn't
+    /// given a `--listen`.
+    pub fn from_args(mut args: Vec<String>) -> Result<(ListenConfig, Vec<String>), ListenError> {
+        let mut config = ListenConfig {
+            listen: vec![],
+            external_addrs: vec![],
+            topic_namespace: None,
+            legacy_topic: take_switch(&mut args, LEGACY_TOPIC_FLAG),
+        };
+        for spec in take_flags(&mut args, LISTEN_FLAG) {
+            let spec = spec.ok_or(ListenError::MissingValue(LISTEN_FLAG))?;
+            config.listen.push(spec.parse()?);
+        }
+        for addr in take_flags(&mut args, EXTERNAL_ADDR_FLAG) {
+            let addr = addr.ok_or(ListenError::MissingValue(EXTERNAL_ADDR_FLAG))?;
+            let parsed = addr
+                .parse()
+                .map_err(|_| ListenError::InvalidExternalAddr(addr))?;
+            config.external_addrs.push(parsed);
+        }
+        if let Some(namespace) = take_flags(&mut args, TOPIC_NAMESPACE_FLAG).pop() {
+            let namespace = namespace.ok_or(ListenError::MissingValue(TOPIC_NAMESPACE_FLAG))?;
+            let valid = !namespace.is_empty()
+                && namespace
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+            if !valid {
+                return Err(ListenError::InvalidNamespace(namespace));
+            }
+            config.topic_namespace = Some(namespace);
+        }
+
+        if config.listen.is_empty() {
+            config.listen = ListenConfig::default().listen;
+        }
+
+        Ok((config, args))
+    }
+
+    /// The topics of the network `network_id` in this node's namespace.
+    pub fn topics(&self, network_id: &str) -> TopicNames {
+        TopicNames::new(
+            self.topic_namespace.as_deref(),
+            network_id,
+            self.legacy_topic,
+        )
+    }
+}
+
+impl Default for ListenConfig {
+    fn default() -> ListenConfig {
+        ListenConfig {
+            listen: vec![ListenSpec {
+                ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                port: PortSpec::default(),
+            }],
+            external_addrs: vec![],
+            topic_namespace: None,
+            legacy_topic: false,
+        }
+    }
+}
+
+/// Builds the swarm and starts it listening as `listen_config` says, failing
+/// if one of the requested ports isn't free. The listen and external
+/// addresses are added to kademlia, the external ones are announced to peers.
 pub async fn configure_swarm(
     message_sender: mpsc::UnboundedSender<GossipsubMessage>,
     command_sender: mpsc::UnboundedSender<Command>,
     local_peer_id: PeerId,
     local_key: Keypair,
+    node_type: NodeAuth,
     pubkey: String,
     address: String,
-    event_path: String,
-) -> Swarm<VrrbNetworkBehavior> {
+    events: EventLog,
+    peers_path: String,
+    listen_config: &ListenConfig,
+) -> Result<Swarm<VrrbNetworkBehavior>, ListenError> {
+    // The topic is part of the id so that the same message can be published on both
+    // its own topic and the legacy topic without being rejected as a duplicate.
     let message_id_fn = |message: &GossipsubMessage| {
         let mut s = DefaultHasher::new();
+        message.topic.hash(&mut s);
         message.data.hash(&mut s);
         MessageId::from(s.finish().to_string())
     };
@@ -51,7 +265,7 @@ pub async fn configure_swarm(
         .validation_mode(ValidationMode::Strict)
         .message_id_fn(message_id_fn)
         .flood_publish(true)
-        .max_transmit_size(MAX_TRANSMIT_SIZE)
+        .max_transmit_size(MAX_GOSSIP_SIZE)
         .build()
         .expect("Valid config");
 
@@ -61,14 +275,20 @@ pub async fn configure_swarm(
     )
     .expect("Correct configuration");
 
-    let testnet_topic = Topic::new("test-net");
-    gossipsub.subscribe(&testnet_topic).unwrap();
+    let topics = listen_config.topics(NETWORK_ID);
+    topics.subscriptions(&node_type).iter().for_each(|topic| {
+        gossipsub.subscribe(&Topic::new(topic)).unwrap();
+    });
+    // Answers to this node's requests are published on its own topic.
+    gossipsub
+        .subscribe(&Topic::new(topics.peer_topic(&local_peer_id.to_string())))
+        .unwrap();
 
     let store = MemoryStore::new(local_peer_id);
     let kademlia = Kademlia::new(local_peer_id, store);
 
     let identify_config =
-        IdentifyConfig::new("vrrb/test-net/0.1.0".to_string(), local_key.public());
+        IdentifyConfig::new(protocol::protocol_id(NETWORK_ID), local_key.public());
     let identify = Identify::new(identify_config);
 
     let ping_config = PingConfig::new();
@@ -88,10 +308,183 @@ pub async fn configure_swarm(
         message_sender: message_sender.clone(),
         pubkey,
         address,
-        path: event_path.clone(),
+        node_id: local_peer_id.to_string(),
+        events,
+        peer_table: PeerTable::restore(&peers_path),
+        peers_path,
+        awaiting_peers: HashSet::new(),
+        peer_exchange: PeerExchange::new(),
+        rejected_peers: HashSet::new(),
+        topics,
     };
 
     let transport = build_transport(local_key).await.unwrap();
 
-    Swarm::new(transport, behaviour, local_peer_id)
+    let mut swarm = Swarm::new(transport, behaviour, local_peer_id);
+    for spec in listen_config.listen.iter() {
+        let addr =
+            spec.listen(|addr| swarm.listen_on(addr).map(|_| ()).map_err(|e| e.to_string()))?;
+        println!("{:?}", &addr);
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&local_peer_id, addr);
+    }
+    for addr in listen_config.external_addrs.iter() {
+        swarm.add_external_address(addr.clone(), AddressScore::Infinite);
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&local_peer_id, addr.clone());
+    }
+
+    Ok(swarm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv6Addr, TcpListener};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn listen_specs_parse_an_ip_a_port_or_a_port_range() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            "127.0.0.1".parse::<ListenSpec>().unwrap(),
+            ListenSpec {
+                ip: localhost,
+                port: PortSpec::default(),
+            }
+        );
+        assert_eq!(
+            "127.0.0.1:9292".parse::<ListenSpec>().unwrap().port,
+            PortSpec::Fixed(9292)
+        );
+        assert_eq!(
+            "127.0.0.1:9000-9100".parse::<ListenSpec>().unwrap().port,
+            PortSpec::Range(9000, 9100)
+        );
+        let ipv6 = "[::1]:9292".parse::<ListenSpec>().unwrap();
+        assert_eq!(ipv6.ip, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(ipv6.port, PortSpec::Fixed(9292));
+        assert_eq!(
+            "::1".parse::<ListenSpec>().unwrap().port,
+            PortSpec::default()
+        );
+
+        [
+            "localhost:9292",
+            "127.0.0.1:",
+            "127.0.0.1:70000",
+            "127.0.0.1:9100-9000",
+        ]
+        .iter()
+        .for_each(|spec| assert!(spec.parse::<ListenSpec>().is_err(), "{}", spec));
+    }
+
+    #[test]
+    fn listen_flags_are_split_out_of_the_arguments() {
+        let (config, remaining) = ListenConfig::from_args(args(&[
+            "vrrb",
+            "--listen",
+            "127.0.0.1:9292",
+            "--listen",
+            "10.0.0.1:9000-9100",
+            "--external-addr",
+            "/ip4/203.0.113.7/tcp/9292",
+            "--topic-namespace",
+            "alpha",
+            "peer",
+        ]))
+        .unwrap();
+        assert_eq!(remaining, args(&["vrrb", "peer"]));
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.listen[1].port, PortSpec::Range(9000, 9100));
+        assert_eq!(
+            config.external_addrs,
+            vec!["/ip4/203.0.113.7/tcp/9292".parse::<Multiaddr>().unwrap()]
+        );
+        assert_eq!(
+            config.topics(NETWORK_ID),
+            TopicNames::new(Some("alpha"), NETWORK_ID, false)
+        );
+
+        let (config, _) = ListenConfig::from_args(args(&["vrrb"])).unwrap();
+        assert_eq!(config, ListenConfig::default());
+        assert_eq!(config.topics(NETWORK_ID), TopicNames::default());
+
+        let (config, _) = ListenConfig::from_args(args(&["vrrb", "--legacy-topic"])).unwrap();
+        assert!(config.topics(NETWORK_ID).legacy);
+
+        assert!(ListenConfig::from_args(args(&["vrrb", "--listen"])).is_err());
+        assert!(ListenConfig::from_args(args(&["vrrb", "--external-addr", "nat"])).is_err());
+        assert!(ListenConfig::from_args(args(&["vrrb", "--topic-namespace", "a/b"])).is_err());
+    }
+
+    // Binds the address and keeps the listener, like the swarm does.
+    fn bind(listeners: &mut Vec<TcpListener>, addr: Multiaddr) -> Result<(), String> {
+        let port = addr
+            .iter()
+            .find_map(|protocol| match protocol {
+                Protocol::Tcp(port) => Some(port),
+                _ => None,
+            })
+            .unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map_err(|e| e.to_string())?;
+        listeners.push(listener);
+        Ok(())
+    }
+
+    #[test]
+    fn occupied_port_is_reported() {
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let spec = ListenSpec {
+            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: PortSpec::Fixed(port),
+        };
+        let mut listeners = vec![];
+        assert!(matches!(
+            spec.listen(|addr| bind(&mut listeners, addr)),
+            Err(ListenError::Listen(addr, _)) if addr == spec.addr(port)
+        ));
+
+        // A range holding only the occupied port has nothing free either.
+        let spec = ListenSpec {
+            port: PortSpec::Range(port, port),
+            ..spec
+        };
+        assert!(matches!(
+            spec.listen(|addr| bind(&mut listeners, addr)),
+            Err(ListenError::NoFreePort(..))
+        ));
+        assert!(listeners.is_empty());
+        drop(socket);
+    }
+
+    #[test]
+    fn listening_keeps_the_port_it_bound() {
+        let spec = ListenSpec {
+            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: PortSpec::Fixed(0),
+        };
+        let mut listeners = vec![];
+        spec.listen(|addr| bind(&mut listeners, addr)).unwrap();
+        let port = listeners[0].local_addr().unwrap().port();
+
+        // The port stays taken by the listener that checked it.
+        let spec = ListenSpec {
+            port: PortSpec::Range(port, port),
+            ..spec
+        };
+        assert!(matches!(
+            spec.listen(|addr| bind(&mut listeners, addr)),
+            Err(ListenError::NoFreePort(..))
+        ));
+        assert_eq!(listeners.len(), 1);
+    }
 }
@@ -0,0 +1,314 @@
+use crate::block::SECOND;
+use crate::network::chunkable::Chunkable;
+use crate::network::message_types::MessageType;
+use crate::network::topics::GossipTopic;
+use crate::params::{MAX_GOSSIP_SIZE, MAX_TRANSMIT_SIZE};
+use ritelinked::LinkedHashMap;
+use sha256::digest_bytes;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+// Partially received messages are dropped after this many seconds.
+pub const MESSAGE_CHUNK_TIMEOUT: u128 = 60;
+// The most partially received messages kept, the least recently added to
+// is dropped first.
+pub const MAX_PENDING_MESSAGES: usize = 32;
+// The most bytes of chunks kept across partially received messages. A message
+// with more chunks than fit in this is refused outright.
+pub const MAX_PENDING_BYTES: usize = 16 * MAX_GOSSIP_SIZE;
+
+/// A message over MAX_GOSSIP_SIZE that can't be split into chunks.
+#[derive(Debug)]
+pub struct MessageTooLarge {
+    pub message_type: String,
+    pub size: usize,
+}
+
+/// Messages on the blocks topic carry blocks and are chunked when a block is
+/// too large. State is already sent in chunks of its own and everything else
+/// is expected to fit in a single message.
+impl Chunkable for MessageType {
+    fn chunk(&self) -> Option<Vec<Vec<u8>>> {
+        match self {
+            MessageType::MessageChunk { .. } => None,
+            message if GossipTopic::for_message(message) == GossipTopic::Blocks => Some(
+                self.clone()
+                    .as_bytes()
+                    .chunks(MAX_TRANSMIT_SIZE)
+                    .map(|chunk| chunk.to_vec())
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Hex encodes a message for publishing. A message over MAX_GOSSIP_SIZE is
+/// split into MessageChunks identified by the hash of the message, or
+/// rejected if it can't be chunked.
+pub fn encode(message: MessageType, sender_id: &str) -> Result<Vec<String>, MessageTooLarge> {
+    let bytes = message.clone().as_bytes();
+    let encoded = hex::encode(&bytes);
+    if encoded.len() <= MAX_GOSSIP_SIZE {
+        return Ok(vec![encoded]);
+    }
+
+    let too_large = |size| MessageTooLarge {
+        message_type: message.name().to_string(),
+        size,
+    };
+    let chunks = if let Some(chunks) = message.chunk() {
+        chunks
+    } else {
+        return Err(too_large(encoded.len()));
+    };
+
+    let message_id = digest_bytes(&bytes);
+    let total_chunks = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(idx, data)| {
+            let chunk = MessageType::MessageChunk {
+                message_id: message_id.clone(),
+                chunk_number: idx as u32 + 1,
+                total_chunks,
+                data,
+                sender_id: sender_id.to_string(),
+            };
+            let encoded = hex::encode(chunk.as_bytes());
+            if encoded.len() > MAX_GOSSIP_SIZE {
+                return Err(too_large(encoded.len()));
+            }
+            Ok(encoded)
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    total_chunks: u32,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    started_at: u128,
+    size: usize,
+}
+
+/// The chunks of messages still being received, keyed by message id and
+/// ordered from least to most recently added to.
+#[derive(Debug, Default)]
+pub struct MessageChunks {
+    pending: LinkedHashMap<String, PartialMessage>,
+    // The bytes of every chunk held in pending.
+    size: usize,
+}
+
+impl MessageChunks {
+    pub fn new() -> MessageChunks {
+        MessageChunks {
+            pending: LinkedHashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Stores a chunk, returning the bytes of the message it belongs to once
+    /// every chunk of it has arrived and they hash to its id. Partial messages
+    /// older than MESSAGE_CHUNK_TIMEOUT are dropped, as are the least recently
+    /// added to past MAX_PENDING_MESSAGES or MAX_PENDING_BYTES.
+    pub fn insert(
+        &mut self,
+        message_id: &str,
+        chunk_number: u32,
+        total_chunks: u32,
+        data: Vec<u8>,
+        now: u128,
+    ) -> Option<Vec<u8>> {
+        let size = &mut self.size;
+        self.pending.retain(|_, partial| {
+            let expired = now.saturating_sub(partial.started_at) / SECOND > MESSAGE_CHUNK_TIMEOUT;
+            if expired {
+                *size -= partial.size;
+            }
+            !expired
+        });
+
+        if chunk_number == 0
+            || chunk_number > total_chunks
+            || data.len() > MAX_TRANSMIT_SIZE
+            || total_chunks as usize > MAX_PENDING_BYTES / MAX_TRANSMIT_SIZE
+        {
+            return None;
+        }
+
+        if !self.pending.contains_key(message_id) {
+            self.pending.insert(
+                message_id.to_string(),
+                PartialMessage {
+                    total_chunks,
+                    chunks: BTreeMap::new(),
+                    started_at: now,
+                    size: 0,
+                },
+            );
+        }
+        let partial = self.pending.to_back(message_id)?;
+        if partial.total_chunks != total_chunks {
+            return None;
+        }
+
+        self.size += data.len();
+        partial.size += data.len();
+        if let Some(replaced) = partial.chunks.insert(chunk_number, data) {
+            self.size -= replaced.len();
+            partial.size -= replaced.len();
+        }
+        let complete = partial.chunks.len() == total_chunks as usize;
+
+        while self.pending.len() > MAX_PENDING_MESSAGES || self.size > MAX_PENDING_BYTES {
+            if let Some((_, dropped)) = self.pending.pop_front() {
+                self.size -= dropped.size;
+            }
+        }
+        if !complete {
+            return None;
+        }
+
+        let partial = self.pending.remove(message_id)?;
+        self.size -= partial.size;
+        let bytes: Vec<u8> = partial
+            .chunks
+            .into_iter()
+            .flat_map(|(_, data)| data)
+            .collect();
+        if digest_bytes(&bytes) != message_id {
+            return None;
+        }
+
+        Some(bytes)
+    }
+}
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes exceeds the max message size of {} bytes and can't be chunked",
+            self.message_type, self.size, MAX_GOSSIP_SIZE
+        )
+    }
+}
+
+impl Error for MessageTooLarge {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::claim::Claim;
+    use crate::test_utils::test_block;
+
+    fn signed_block(signature: String) -> Block {
+        let mut block = test_block(0, "hash");
+        block.header.signature = signature;
+        block
+    }
+
+    #[test]
+    fn oversize_block_is_chunked_and_reassembled() {
+        let message = MessageType::BlockMessage {
+            block: signed_block("s".repeat(MAX_GOSSIP_SIZE)),
+            sender_id: "sender".to_string(),
+        };
+        let payloads = encode(message.clone(), "sender").unwrap();
+        assert!(payloads.len() > 1);
+        assert!(payloads
+            .iter()
+            .all(|encoded| encoded.len() <= MAX_GOSSIP_SIZE));
+
+        let mut message_chunks = MessageChunks::new();
+        let mut reassembled = None;
+        for encoded in payloads.iter().rev() {
+            assert!(reassembled.is_none());
+            if let Some(MessageType::MessageChunk {
+                message_id,
+                chunk_number,
+                total_chunks,
+                data,
+                ..
+            }) = MessageType::from_bytes(&hex::decode(encoded).unwrap())
+            {
+                reassembled =
+                    message_chunks.insert(&message_id, chunk_number, total_chunks, data, 0);
+            } else {
+                panic!("expected a message chunk");
+            }
+        }
+
+        assert_eq!(reassembled.unwrap(), message.as_bytes());
+    }
+
+    #[test]
+    fn pending_messages_are_bounded() {
+        let mut message_chunks = MessageChunks::new();
+        (0..MAX_PENDING_MESSAGES + 1).for_each(|n| {
+            let message_id = n.to_string();
+            assert!(message_chunks
+                .insert(&message_id, 1, 2, vec![0; 8], 0)
+                .is_none());
+        });
+        assert_eq!(message_chunks.pending.len(), MAX_PENDING_MESSAGES);
+        assert!(!message_chunks.pending.contains_key("0"));
+        assert_eq!(message_chunks.size, MAX_PENDING_MESSAGES * 8);
+
+        // Chunks of oversize messages aren't kept.
+        let too_many = (MAX_PENDING_BYTES / MAX_TRANSMIT_SIZE + 1) as u32;
+        assert!(message_chunks
+            .insert("large", 1, too_many, vec![0; 8], 0)
+            .is_none());
+        assert!(message_chunks
+            .insert("large", 1, 2, vec![0; MAX_TRANSMIT_SIZE + 1], 0)
+            .is_none());
+        assert!(!message_chunks.pending.contains_key("large"));
+
+        // Full chunks are dropped, least recently added to first, to stay
+        // within the byte budget.
+        let per_message = 2;
+        (0..MAX_PENDING_BYTES / (per_message * MAX_TRANSMIT_SIZE) + 1).for_each(|n| {
+            let message_id = format!("full_{}", n);
+            (1..=per_message as u32).for_each(|chunk_number| {
+                message_chunks.insert(
+                    &message_id,
+                    chunk_number,
+                    per_message as u32 + 1,
+                    vec![0; MAX_TRANSMIT_SIZE],
+                    0,
+                );
+            });
+        });
+        assert!(message_chunks.size <= MAX_PENDING_BYTES);
+        assert!(!message_chunks.pending.contains_key("full_0"));
+
+        // Partial messages expire.
+        let later = (MESSAGE_CHUNK_TIMEOUT + 1) * SECOND;
+        message_chunks.insert("late", 1, 2, vec![0; 8], later);
+        assert_eq!(message_chunks.pending.len(), 1);
+        assert_eq!(message_chunks.size, 8);
+    }
+
+    #[test]
+    fn oversize_unchunkable_message_is_rejected() {
+        let message = MessageType::ClaimMessage {
+            claim: Claim::new("p".repeat(MAX_GOSSIP_SIZE), "address".to_string(), 0),
+            sender_id: "sender".to_string(),
+        };
+        let err = encode(message, "sender").unwrap_err();
+        assert_eq!(err.message_type, "ClaimMessage");
+        assert!(err.size > MAX_GOSSIP_SIZE);
+
+        let message = MessageType::BlockMessage {
+            block: signed_block(String::new()),
+            sender_id: "sender".to_string(),
+        };
+        assert_eq!(encode(message, "sender").unwrap().len(), 1);
+    }
+}
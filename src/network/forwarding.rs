@@ -0,0 +1,223 @@
+use crate::network::message_types::MessageType;
+use crate::network::node::NodeAuth;
+use crate::txn::Txn;
+use ritelinked::LinkedHashSet;
+
+// A txn is forwarded at most this many times between nodes that don't
+// validate txns, enough to cross a few light nodes to reach a miner.
+pub const MAX_TXN_HOPS: u8 = 3;
+// The most txn ids a node remembers, the oldest are forgotten first.
+pub const SEEN_TXNS_CAPACITY: usize = 10000;
+
+/// The ids of txns a node has already handled, so that a txn gossiped back
+/// to it isn't handled again.
+#[derive(Clone, Debug)]
+pub struct SeenTxns {
+    ids: LinkedHashSet<String>,
+    capacity: usize,
+}
+
+/// What a node does with a txn received from the network.
+#[derive(Clone, Debug)]
+pub enum TxnRoute {
+    // Validated like any txn, wherever it came from.
+    Process(Txn),
+    // Published again on the txns topic.
+    Forward(MessageType),
+    Drop,
+}
+
+/// Forwards the txns received by nodes that don't validate txns, so that
+/// txns sent from a wallet behind a light node whose mesh holds no miners
+/// still reach one.
+#[derive(Clone, Debug)]
+pub struct TxnForwarder {
+    pub seen: SeenTxns,
+}
+
+impl SeenTxns {
+    pub fn new(capacity: usize) -> SeenTxns {
+        SeenTxns {
+            ids: LinkedHashSet::new(),
+            capacity,
+        }
+    }
+
+    /// Records the txn id, returning false if it had been seen already.
+    pub fn insert(&mut self, txn_id: &str) -> bool {
+        if self.ids.contains(txn_id) {
+            return false;
+        }
+
+        self.ids.insert(txn_id.to_string());
+        while self.ids.len() > self.capacity {
+            self.ids.pop_front();
+        }
+        true
+    }
+
+    pub fn contains(&self, txn_id: &str) -> bool {
+        self.ids.contains(txn_id)
+    }
+}
+
+impl TxnForwarder {
+    pub fn new() -> TxnForwarder {
+        TxnForwarder {
+            seen: SeenTxns::new(SEEN_TXNS_CAPACITY),
+        }
+    }
+
+    /// Routes a txn that has been forwarded `hops` times, 0 if it came from
+    /// the node it was sent from. Nodes that validate txns process it, the
+    /// others forward it once unless it has used up MAX_TXN_HOPS.
+    pub fn route(&mut self, node_type: &NodeAuth, txn: Txn, hops: u8, node_id: &str) -> TxnRoute {
+        if node_type.validates_txns() {
+            return TxnRoute::Process(txn);
+        }
+
+        if hops >= MAX_TXN_HOPS || !self.seen.insert(&txn.txn_id) {
+            return TxnRoute::Drop;
+        }
+
+        TxnRoute::Forward(MessageType::ForwardedTxnMessage {
+            txn,
+            hops: hops + 1,
+            sender_id: node_id.to_string(),
+        })
+    }
+}
+
+impl Default for TxnForwarder {
+    fn default() -> TxnForwarder {
+        TxnForwarder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // A node in a simulated mesh, receiving what its neighbors publish.
+    struct TestNode {
+        node_type: NodeAuth,
+        neighbors: Vec<usize>,
+        forwarder: TxnForwarder,
+        processed: Vec<(String, u8)>,
+        forwarded: usize,
+    }
+
+    fn mesh(node_types: Vec<NodeAuth>, links: &[(usize, usize)]) -> Vec<TestNode> {
+        let mut nodes: Vec<TestNode> = node_types
+            .into_iter()
+            .map(|node_type| TestNode {
+                node_type,
+                neighbors: vec![],
+                forwarder: TxnForwarder::new(),
+                processed: vec![],
+                forwarded: 0,
+            })
+            .collect();
+        links.iter().for_each(|(a, b)| {
+            nodes[*a].neighbors.push(*b);
+            nodes[*b].neighbors.push(*a);
+        });
+        nodes
+    }
+
+    // Publishes the txn from `origin` and delivers messages until none are left.
+    fn gossip(nodes: &mut Vec<TestNode>, origin: usize, txn: Txn) {
+        let mut in_flight: VecDeque<(usize, MessageType)> = VecDeque::new();
+        let message = MessageType::TxnMessage {
+            txn,
+            sender_id: origin.to_string(),
+        };
+        nodes[origin]
+            .neighbors
+            .iter()
+            .for_each(|neighbor| in_flight.push_back((*neighbor, message.clone())));
+
+        while let Some((idx, message)) = in_flight.pop_front() {
+            let (txn, hops) = match message {
+                MessageType::TxnMessage { txn, .. } => (txn, 0),
+                MessageType::ForwardedTxnMessage { txn, hops, .. } => (txn, hops),
+                message => panic!("unexpected message {:?}", message),
+            };
+            let node = &mut nodes[idx];
+            match node
+                .forwarder
+                .route(&node.node_type, txn, hops, &idx.to_string())
+            {
+                TxnRoute::Process(txn) => node.processed.push((txn.txn_id, hops)),
+                TxnRoute::Forward(message) => {
+                    node.forwarded += 1;
+                    node.neighbors
+                        .iter()
+                        .for_each(|neighbor| in_flight.push_back((*neighbor, message.clone())));
+                }
+                TxnRoute::Drop => {}
+            }
+        }
+    }
+
+    fn txn() -> Txn {
+        Txn::unsigned_transfer(1, 0)
+    }
+
+    #[test]
+    fn txn_sent_behind_light_nodes_reaches_a_miner() {
+        // wallet node - light - light - full
+        let mut nodes = mesh(
+            vec![
+                NodeAuth::Light,
+                NodeAuth::Light,
+                NodeAuth::Light,
+                NodeAuth::Full,
+            ],
+            &[(0, 1), (1, 2), (2, 3)],
+        );
+        let txn = txn();
+        gossip(&mut nodes, 0, txn.clone());
+
+        assert_eq!(nodes[3].processed, vec![(txn.txn_id, 2)]);
+        assert!(nodes[3].processed[0].1 <= MAX_TXN_HOPS);
+    }
+
+    #[test]
+    fn txn_is_not_forwarded_past_the_hop_limit() {
+        let n_light = MAX_TXN_HOPS as usize + 2;
+        let mut node_types = vec![NodeAuth::Light; n_light];
+        node_types.push(NodeAuth::Full);
+        let links: Vec<(usize, usize)> = (0..n_light).map(|idx| (idx, idx + 1)).collect();
+        let mut nodes = mesh(node_types, &links);
+        gossip(&mut nodes, 0, txn());
+
+        assert!(nodes[n_light].processed.is_empty());
+    }
+
+    #[test]
+    fn seen_txns_stop_forwarding_loops() {
+        // Light nodes in a ring, every one of them forwards the txn once, the
+        // sender too when it comes back around.
+        let mut nodes = mesh(vec![NodeAuth::Light; 4], &[(0, 1), (1, 2), (2, 3), (3, 0)]);
+        let txn = txn();
+        gossip(&mut nodes, 0, txn.clone());
+
+        nodes.iter().for_each(|node| assert_eq!(node.forwarded, 1));
+        nodes
+            .iter()
+            .for_each(|node| assert!(node.forwarder.seen.contains(&txn.txn_id)));
+    }
+
+    #[test]
+    fn seen_txns_forget_the_oldest_first() {
+        let mut seen = SeenTxns::new(2);
+        assert!(seen.insert("a"));
+        assert!(!seen.insert("a"));
+        assert!(seen.insert("b"));
+        assert!(seen.insert("c"));
+        assert!(!seen.contains("a"));
+        assert!(seen.contains("b") && seen.contains("c"));
+    }
+}
@@ -1,18 +1,45 @@
-use crate::blockchain::StateComponent;
+use crate::blockchain::{InvalidBlockErrorReason, StateComponent};
 use crate::network::command_utils::Command;
 use crate::network::message_types::{MessageType, StateBlock};
+use crate::network::protocol::{EventLog, VrrbNetworkEvent};
+use crate::network::topics::TopicNames;
+use crate::pool::MAX_POOL_TXN_IDS;
 use libp2p::gossipsub::GossipsubMessage;
 
 pub const PROPOSAL_EXPIRATION_KEY: &str = "expires";
 pub const PROPOSAL_YES_VOTE_KEY: &str = "yes";
 pub const PROPOSAL_NO_VOTE_KEY: &str = "no";
 
-pub fn process_message(message: GossipsubMessage, node_id: String) -> Option<Command> {
+pub fn process_message(
+    message: GossipsubMessage,
+    node_id: String,
+    events: &EventLog,
+    topics: &TopicNames,
+) -> Option<Command> {
+    let topic = message.topic.clone();
+    // The publisher gossipsub verified the message's signature against.
+    let source = message.source.map(|peer_id| peer_id.to_string());
     if let Some(message) = MessageType::from_bytes(
         &hex::decode(&String::from_utf8_lossy(&message.data).into_owned()).unwrap(),
     ) {
+        if !topics.accepts(topic.as_str(), &message) {
+            println!("Ignoring message received on the wrong topic: {}", topic);
+            return None;
+        }
+
+        if let Some(event) = VrrbNetworkEvent::from_message(&message) {
+            events.log(event);
+        }
+
         match message.clone() {
             MessageType::TxnMessage { txn, .. } => Some(Command::ProcessTxn(txn)),
+            MessageType::ForwardedTxnMessage { txn, hops, .. } => {
+                Some(Command::ProcessForwardedTxn(txn, hops))
+            }
+            MessageType::TxnAckMessage {
+                txn_validator,
+                sender_id,
+            } => Some(Command::TxnAck(txn_validator, sender_id)),
             MessageType::BlockMessage {
                 block, sender_id, ..
             } => Some(Command::PendingBlock(block, sender_id)),
@@ -38,7 +65,7 @@ pub fn process_message(message: GossipsubMessage, node_id: String) -> Option<Com
                         StateComponent::Ledger => {
                             Some(Command::SendStateComponents(sender_id, component))
                         }
-                        StateComponent::All => {
+                        StateComponent::All | StateComponent::Archive => {
                             Some(Command::SendStateComponents(sender_id, component))
                         }
                         _ => Some(Command::SendState(sender_id, lowest_block)),
@@ -86,15 +113,298 @@ pub fn process_message(message: GossipsubMessage, node_id: String) -> Option<Com
                 }
                 None
             }
+            MessageType::StateRequestDeclined {
+                reason,
+                requestor,
+                sender_id,
+                public_key,
+                signature,
+            } => {
+                if requestor == node_id
+                    && MessageType::valid_decline(
+                        &reason,
+                        &requestor,
+                        &sender_id,
+                        &public_key,
+                        &signature,
+                    )
+                {
+                    return Some(Command::StateRequestDeclined(sender_id, reason));
+                }
+                None
+            }
+            MessageType::CompactBlockMessage { block, sender_id } => {
+                Some(Command::ProcessCompactBlock(block, sender_id, source))
+            }
+            MessageType::GetTxnsMessage {
+                txn_ids,
+                sender_id,
+                requested_from,
+            } => {
+                if requested_from == node_id {
+                    return Some(Command::SendTxns(txn_ids, sender_id));
+                }
+                None
+            }
+            MessageType::TxnsResponseMessage {
+                txns, requestor, ..
+            } => {
+                if requestor == node_id {
+                    return Some(Command::ProcessFetchedTxns(txns));
+                }
+                None
+            }
+            MessageType::PoolTxnIdsMessage {
+                txn_ids,
+                sender_id,
+                requested_from,
+            } => {
+                // A peer announcing more ids than any node sends is ignored.
+                if requested_from == node_id && txn_ids.len() <= MAX_POOL_TXN_IDS {
+                    return Some(Command::ProcessPoolTxnIds(txn_ids, sender_id));
+                }
+                None
+            }
+            MessageType::GetPeersMessage {
+                sender_id,
+                requested_from,
+            } => {
+                if requested_from == node_id {
+                    return Some(Command::SendPeers(sender_id));
+                }
+                None
+            }
+            MessageType::PeersResponseMessage {
+                peers,
+                requestor,
+                sender_id,
+            } => {
+                if requestor == node_id {
+                    return Some(Command::DialPeers(peers, sender_id));
+                }
+                None
+            }
+            MessageType::NeedBlockMessage {
+                block_last_hash,
+                sender_id,
+                requested_from,
+            } => {
+                if requested_from == node_id {
+                    return Some(Command::SendMissingBlock(block_last_hash, sender_id));
+                }
+                None
+            }
+            MessageType::GetBlockRangeMessage {
+                from_height,
+                to_height,
+                requestor,
+                requested_from,
+            } => {
+                if requested_from == node_id {
+                    return Some(Command::SendBlockRange(from_height, to_height, requestor));
+                }
+                None
+            }
+            MessageType::BlockRangeMessage {
+                blocks,
+                requestor,
+                sender_id,
+            } => {
+                if requestor == node_id {
+                    return Some(Command::ProcessBlockRange(blocks, sender_id));
+                }
+                None
+            }
+            MessageType::InvalidBlockMessage {
+                reason: InvalidBlockErrorReason::InvalidBlockHeight,
+                miner_id,
+                sender_id,
+                tip_height: Some(tip_height),
+                ..
+            } => {
+                if miner_id == node_id {
+                    return Some(Command::BlockHeightRejected(tip_height, sender_id));
+                }
+                None
+            }
+            MessageType::MissingBlock {
+                block,
+                requestor,
+                sender_id,
+            } => {
+                if requestor == node_id {
+                    return Some(Command::PendingBlock(block, sender_id));
+                }
+                None
+            }
             MessageType::ClaimAbandonedMessage {
                 claim,
                 sender_id,
             } => {
                 return Some(Command::ClaimAbandoned(sender_id, claim))
             }
+            MessageType::MessageChunk {
+                message_id,
+                chunk_number,
+                total_chunks,
+                data,
+                ..
+            } => Some(Command::StoreMessageChunk(
+                message_id,
+                chunk_number,
+                total_chunks,
+                data,
+            )),
+            // Requests are rate limited by their sender, which has to be the
+            // peer that published them.
+            MessageType::BalanceProofRequest { address, sender_id } => {
+                if source.as_deref() == Some(sender_id.as_str()) {
+                    return Some(Command::SendBalanceProof(address, sender_id));
+                }
+                None
+            }
+            MessageType::BalanceProofResponse {
+                proof,
+                requestor,
+                sender_id,
+            } => {
+                if requestor == node_id {
+                    return Some(Command::BalanceProof(proof, sender_id));
+                }
+                None
+            }
             _ => None,
         }
     } else {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::DeclineReason;
+    use crate::network::protocol::{read_from_json, MAX_NETWORK_EVENTS};
+    use crate::network::topics::{GossipTopic, NETWORK_ID};
+    use crate::test_utils::{temp_path, test_block};
+    use libp2p::gossipsub::TopicHash;
+    use libp2p::identity::Keypair;
+    use libp2p::PeerId;
+    use std::fs;
+
+    fn gossip(message: MessageType) -> GossipsubMessage {
+        let topic = GossipTopic::for_message(&message).name(NETWORK_ID);
+        GossipsubMessage {
+            source: None,
+            data: hex::encode(message.as_bytes()).into_bytes(),
+            sequence_number: None,
+            topic: TopicHash::from_raw(topic),
+        }
+    }
+
+    #[test]
+    fn processing_a_block_logs_a_network_event() {
+        let path = temp_path("vrrb_message_block_event.json");
+        let _ = fs::remove_file(&path);
+        let message = gossip(MessageType::BlockMessage {
+            block: test_block(7, "block_hash"),
+            sender_id: "sender".to_string(),
+        });
+
+        let events = EventLog::open(&path);
+        let command = process_message(message, "node".to_string(), &events, &TopicNames::default());
+        assert!(matches!(command, Some(Command::PendingBlock(..))));
+        assert!(read_from_json(&path).is_err());
+
+        events.flush();
+        let events = read_from_json(&path).unwrap();
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            VrrbNetworkEvent::BlockReceived {
+                block_hash,
+                block_height,
+                sender_id,
+            } => {
+                assert_eq!(block_hash, "block_hash");
+                assert_eq!(*block_height, 7);
+                assert_eq!(sender_id, "sender");
+            }
+            event => panic!("expected a block received event, got {:?}", event),
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn only_a_decline_signed_by_its_sender_is_taken() {
+        let path = temp_path("vrrb_message_declines.json");
+        let events = EventLog::open(&path);
+        let key = Keypair::generate_ed25519();
+        let declined =
+            MessageType::state_request_declined(DeclineReason::TooLarge, "node".to_string(), &key)
+                .unwrap();
+        let command = process_message(
+            gossip(declined.clone()),
+            "node".to_string(),
+            &events,
+            &TopicNames::default(),
+        );
+        assert!(matches!(
+            command,
+            Some(Command::StateRequestDeclined(sender_id, DeclineReason::TooLarge))
+                if sender_id == PeerId::from(key.public()).to_string()
+        ));
+
+        // Signed by another node than the one it names.
+        let forged = match declined {
+            MessageType::StateRequestDeclined {
+                reason,
+                requestor,
+                public_key,
+                signature,
+                ..
+            } => MessageType::StateRequestDeclined {
+                reason,
+                requestor,
+                sender_id: "sync_peer".to_string(),
+                public_key,
+                signature,
+            },
+            message => panic!("expected a decline, got {:?}", message),
+        };
+        let command = process_message(
+            gossip(forged),
+            "node".to_string(),
+            &events,
+            &TopicNames::default(),
+        );
+        assert!(command.is_none());
+        drop(events);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn event_log_keeps_only_the_latest_events() {
+        let path = temp_path("vrrb_message_capped_events.json");
+        let _ = fs::remove_file(&path);
+        let events = EventLog::open(&path);
+        (0..MAX_NETWORK_EVENTS + 5).for_each(|n| {
+            events.log(VrrbNetworkEvent::PeerConnected {
+                peer_id: n.to_string(),
+            });
+        });
+        // The events are written out once, when the last handle is dropped.
+        let handle = events.clone();
+        drop(events);
+        assert!(read_from_json(&path).is_err());
+        drop(handle);
+
+        let logged = read_from_json(&path).unwrap();
+        assert_eq!(logged.len(), MAX_NETWORK_EVENTS);
+        match &logged[0] {
+            VrrbNetworkEvent::PeerConnected { peer_id } => assert_eq!(peer_id, "5"),
+            event => panic!("expected a peer connected event, got {:?}", event),
+        }
+        assert_eq!(EventLog::open(&path).path(), path);
+        let _ = fs::remove_file(&path);
+    }
+}
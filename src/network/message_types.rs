@@ -1,15 +1,21 @@
+use crate::balance_proof::BalanceProof;
 use crate::block::Block;
-use crate::blockchain::StateComponent;
+use crate::blockchain::{DeclineReason, StateComponent};
 use crate::claim::Claim;
+use crate::network::compact_block::CompactBlock;
 use crate::network::node::NodeAuth;
 use crate::txn::Txn;
 use crate::validator::TxnValidator;
 use crate::blockchain::InvalidBlockErrorReason;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StateBlock(pub u128);
 
+// Peers on other versions read these as serde_json, a change to the format
+// has to update the golden vectors checked in network::wire.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum MessageType {
     NetworkStateDataBaseMessage {
@@ -25,6 +31,20 @@ pub enum MessageType {
         txn: Txn,
         sender_id: String,
     },
+    // A txn forwarded by a node that doesn't validate txns, `hops` counts the
+    // times it's been forwarded, see network::forwarding.
+    ForwardedTxnMessage {
+        txn: Txn,
+        hops: u8,
+        sender_id: String,
+    },
+    // Sent by the first miner to accept a txn into its pool, so the wallet
+    // that sent it knows it reached one. It carries the miner's signed vote,
+    // the wallet only counts it from an eligible validator.
+    TxnAckMessage {
+        txn_validator: TxnValidator,
+        sender_id: String,
+    },
     TxnValidatorMessage {
         txn_validator: TxnValidator,
         sender_id: String,
@@ -71,6 +91,10 @@ pub enum MessageType {
         reason: InvalidBlockErrorReason,
         miner_id: String,
         sender_id: String,
+        // The sender's tip height when it rejected the block as stale, the
+        // miner's chain is behind it up to there. None for other rejections.
+        #[serde(default)]
+        tip_height: Option<u128>,
     },
     DisconnectMessage {
         sender_id: String,
@@ -92,10 +116,82 @@ pub enum MessageType {
         requestor: String,
         sender_id: String,
     },
+    // Sent instead of the state a peer requested, see blockchain::DeclineReason.
+    // Signed with the declining node's identity key, see valid_decline.
+    StateRequestDeclined {
+        reason: DeclineReason,
+        requestor: String,
+        sender_id: String,
+        #[serde(default)]
+        public_key: Vec<u8>,
+        #[serde(default)]
+        signature: Vec<u8>,
+    },
     ClaimAbandonedMessage {
         claim: Claim,
         sender_id: String,
-    }
+    },
+    CompactBlockMessage {
+        block: CompactBlock,
+        sender_id: String,
+    },
+    GetTxnsMessage {
+        txn_ids: Vec<String>,
+        sender_id: String,
+        requested_from: String,
+    },
+    TxnsResponseMessage {
+        txns: Vec<Txn>,
+        requestor: String,
+        sender_id: String,
+    },
+    GetPeersMessage {
+        sender_id: String,
+        requested_from: String,
+    },
+    // The ids of the sender's pending txns, announced to a newly connected
+    // peer which fetches the ones it's missing with a GetTxnsMessage.
+    PoolTxnIdsMessage {
+        txn_ids: Vec<String>,
+        sender_id: String,
+        requested_from: String,
+    },
+    // Heights are inclusive, the peer serves at most MAX_BLOCK_RANGE blocks.
+    GetBlockRangeMessage {
+        from_height: u128,
+        to_height: u128,
+        requestor: String,
+        requested_from: String,
+    },
+    BlockRangeMessage {
+        blocks: Vec<Block>,
+        requestor: String,
+        sender_id: String,
+    },
+    // Peer ids and multiaddrs as strings, PeerId doesn't implement Serialize.
+    PeersResponseMessage {
+        peers: Vec<(String, String)>,
+        requestor: String,
+        sender_id: String,
+    },
+    // A piece of a message too large to publish in one, see network::envelope.
+    MessageChunk {
+        message_id: String,
+        chunk_number: u32,
+        total_chunks: u32,
+        data: Vec<u8>,
+        sender_id: String,
+    },
+    // A light client asking full nodes to prove an address's balance.
+    BalanceProofRequest {
+        address: String,
+        sender_id: String,
+    },
+    BalanceProofResponse {
+        proof: BalanceProof,
+        requestor: String,
+        sender_id: String,
+    },
 
 }
 
@@ -111,4 +207,83 @@ impl MessageType {
             None
         }
     }
+
+    /// A decline of `requestor`'s state request, signed with the identity key
+    /// of the declining node, whose peer id is its sender id.
+    pub fn state_request_declined(
+        reason: DeclineReason,
+        requestor: String,
+        key: &Keypair,
+    ) -> Option<MessageType> {
+        let sender_id = PeerId::from(key.public()).to_string();
+        let signature = key
+            .sign(&decline_payload(&reason, &requestor, &sender_id))
+            .ok()?;
+
+        Some(MessageType::StateRequestDeclined {
+            reason,
+            requestor,
+            sender_id,
+            public_key: key.public().into_protobuf_encoding(),
+            signature,
+        })
+    }
+
+    /// Whether a decline was signed by the node its sender id names, so a
+    /// peer can't decline on another's behalf and move the requester's sync
+    /// elsewhere.
+    pub fn valid_decline(
+        reason: &DeclineReason,
+        requestor: &str,
+        sender_id: &str,
+        public_key: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        match PublicKey::from_protobuf_encoding(public_key) {
+            Ok(public_key) => {
+                PeerId::from(public_key.clone()).to_string() == sender_id
+                    && public_key.verify(&decline_payload(reason, requestor, sender_id), signature)
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::NetworkStateDataBaseMessage { .. } => "NetworkStateDataBaseMessage",
+            MessageType::TxnMessage { .. } => "TxnMessage",
+            MessageType::ForwardedTxnMessage { .. } => "ForwardedTxnMessage",
+            MessageType::TxnAckMessage { .. } => "TxnAckMessage",
+            MessageType::TxnValidatorMessage { .. } => "TxnValidatorMessage",
+            MessageType::BlockMessage { .. } => "BlockMessage",
+            MessageType::BlockChunkMessage { .. } => "BlockChunkMessage",
+            MessageType::ClaimMessage { .. } => "ClaimMessage",
+            MessageType::NeedBlocksMessage { .. } => "NeedBlocksMessage",
+            MessageType::NeedBlockMessage { .. } => "NeedBlockMessage",
+            MessageType::MissingBlock { .. } => "MissingBlock",
+            MessageType::GetNetworkStateMessage { .. } => "GetNetworkStateMessage",
+            MessageType::InvalidBlockMessage { .. } => "InvalidBlockMessage",
+            MessageType::DisconnectMessage { .. } => "DisconnectMessage",
+            MessageType::NeedGenesisBlock { .. } => "NeedGenesisBlock",
+            MessageType::MissingGenesis { .. } => "MissingGenesis",
+            MessageType::StateComponentChunkMessage { .. } => "StateComponentChunkMessage",
+            MessageType::StateRequestDeclined { .. } => "StateRequestDeclined",
+            MessageType::ClaimAbandonedMessage { .. } => "ClaimAbandonedMessage",
+            MessageType::CompactBlockMessage { .. } => "CompactBlockMessage",
+            MessageType::GetTxnsMessage { .. } => "GetTxnsMessage",
+            MessageType::TxnsResponseMessage { .. } => "TxnsResponseMessage",
+            MessageType::GetPeersMessage { .. } => "GetPeersMessage",
+            MessageType::PoolTxnIdsMessage { .. } => "PoolTxnIdsMessage",
+            MessageType::PeersResponseMessage { .. } => "PeersResponseMessage",
+            MessageType::GetBlockRangeMessage { .. } => "GetBlockRangeMessage",
+            MessageType::BlockRangeMessage { .. } => "BlockRangeMessage",
+            MessageType::MessageChunk { .. } => "MessageChunk",
+            MessageType::BalanceProofRequest { .. } => "BalanceProofRequest",
+            MessageType::BalanceProofResponse { .. } => "BalanceProofResponse",
+        }
+    }
+}
+
+fn decline_payload(reason: &DeclineReason, requestor: &str, sender_id: &str) -> Vec<u8> {
+    format!("declined,{:?},{},{}", reason, requestor, sender_id).into_bytes()
 }
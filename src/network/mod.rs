@@ -1,9 +1,19 @@
 pub mod chunkable;
+pub mod clock;
 pub mod command_utils;
+pub mod compact_block;
 pub mod config_utils;
+pub mod envelope;
+pub mod forwarding;
 pub mod message;
 pub mod message_types;
 pub mod node;
+pub mod peer_score;
+pub mod peer_table;
 pub mod protocol;
 pub mod sendable;
+pub mod supervisor;
+pub mod topics;
 pub mod voting;
+#[cfg(test)]
+mod wire;
@@ -2,14 +2,19 @@
 use crate::account::AccountState;
 use crate::handler::{CommandHandler, MessageHandler};
 use crate::network::command_utils::Command;
+use crate::network::envelope::MessageChunks;
+use crate::network::forwarding::{TxnForwarder, TxnRoute};
 use crate::network::message;
 use crate::network::message_types::MessageType;
+use crate::network::protocol::EventLog;
+use crate::network::topics::{SeenMessages, TopicNames};
+use crate::txn::Txn;
 use libp2p::gossipsub::GossipsubMessage;
 use libp2p::{identity, PeerId};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-
-pub const MAX_TRANSMIT_SIZE: usize = 65000;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -27,6 +32,14 @@ pub enum NodeAuth {
     Bootstrap,
 }
 
+impl NodeAuth {
+    /// Whether the node validates txns, the others forward the txns they
+    /// receive toward the nodes that do, see network::forwarding.
+    pub fn validates_txns(&self) -> bool {
+        matches!(self, NodeAuth::Archive | NodeAuth::Full)
+    }
+}
+
 #[allow(dead_code)]
 pub struct Node {
     pub key: identity::Keypair,
@@ -34,6 +47,13 @@ pub struct Node {
     pub node_type: NodeAuth,
     pub command_handler: CommandHandler,
     pub message_handler: MessageHandler<MessageType, GossipsubMessage>,
+    // The event log read by the network data view.
+    pub events: EventLog,
+    pub message_chunks: MessageChunks,
+    pub txn_forwarder: TxnForwarder,
+    // Which topics messages are accepted on, see topics::TopicNames.
+    pub topics: TopicNames,
+    pub seen_messages: SeenMessages,
 }
 
 impl Node {
@@ -49,8 +69,9 @@ impl Node {
         node_type: NodeAuth,
         command_handler: CommandHandler,
         message_handler: MessageHandler<MessageType, GossipsubMessage>,
+        events: EventLog,
+        local_key: identity::Keypair,
     ) -> Node {
-        let local_key = identity::Keypair::generate_ed25519();
         let local_peer_id = PeerId::from(local_key.public());
 
         Node {
@@ -59,6 +80,49 @@ impl Node {
             node_type,
             command_handler,
             message_handler,
+            events,
+            message_chunks: MessageChunks::new(),
+            txn_forwarder: TxnForwarder::new(),
+            topics: TopicNames::default(),
+            seen_messages: SeenMessages::new(),
+        }
+    }
+
+    /// The node's identity keypair kept at `path`, generated and written there
+    /// the first time, so the node keeps its peer id across restarts and a
+    /// snapshot of its data dir carries it along.
+    pub fn load_key(path: &str) -> Result<identity::Keypair, Box<dyn Error>> {
+        if let Ok(mut encoded) = fs::read(path) {
+            let keypair = identity::ed25519::Keypair::decode(&mut encoded)?;
+            return Ok(identity::Keypair::Ed25519(keypair));
+        }
+        let keypair = identity::ed25519::Keypair::generate();
+        fs::write(path, &keypair.encode()[..])?;
+
+        Ok(identity::Keypair::Ed25519(keypair))
+    }
+
+    // Hands a txn received from the network to the miner, or forwards it if
+    // this node doesn't validate txns.
+    fn route_txn(&mut self, txn: Txn, hops: u8) {
+        let node_id = self.id.to_string();
+        match self
+            .txn_forwarder
+            .route(&self.node_type, txn, hops, &node_id)
+        {
+            TxnRoute::Process(txn) => self
+                .command_handler
+                .handle_command(Command::ProcessTxn(txn)),
+            TxnRoute::Forward(message) => {
+                if let Err(e) = self
+                    .command_handler
+                    .to_swarm_sender
+                    .send(Command::SendMessage(message.as_bytes()))
+                {
+                    println!("Error forwarding txn: {:?}", e);
+                }
+            }
+            TxnRoute::Drop => {}
         }
     }
 
@@ -74,8 +138,27 @@ impl Node {
                         }
                     }
                     from_message = self.message_handler.receiver.recv() => {
-                        if let Some(message) = from_message {
-                           message::process_message(message, self.id.clone().to_string())
+                        let seen_messages = &mut self.seen_messages;
+                        if let Some(message) = from_message.filter(|message| seen_messages.accept(message.topic.as_str(), &message.data)) {
+                           self.message_handler.tag_topic(message.topic.as_str());
+                           let (source, topic) = (message.source, message.topic.clone());
+                           match message::process_message(message, self.id.clone().to_string(), &self.events, &self.topics) {
+                               Some(Command::StoreMessageChunk(message_id, chunk_number, total_chunks, data)) => {
+                                   let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+                                   self.message_chunks
+                                       .insert(&message_id, chunk_number, total_chunks, data, now)
+                                       .and_then(|data| {
+                                           let message = GossipsubMessage {
+                                               source,
+                                               data: hex::encode(data).into_bytes(),
+                                               sequence_number: None,
+                                               topic,
+                                           };
+                                           message::process_message(message, self.id.clone().to_string(), &self.events, &self.topics)
+                                       })
+                               }
+                               command => command,
+                           }
                         } else {
                             None
                         }
@@ -107,6 +190,8 @@ impl Node {
 
                         break;
                     }
+                    Command::ProcessTxn(txn) => self.route_txn(txn, 0),
+                    Command::ProcessForwardedTxn(txn, hops) => self.route_txn(txn, hops),
                     Command::SendAddress => {
                         if let Err(e) = self
                             .command_handler
@@ -156,3 +241,63 @@ impl Node {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::{bounded_channel, ChannelConfig};
+    use crate::test_utils::temp_path;
+    use futures::executor::block_on;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn headless_node_runs_commands_until_quit() {
+        // Started the way the headless node is, with nothing from the cli
+        // feature and no terminal to read commands from.
+        let (to_miner_sender, mut to_miner_receiver) = unbounded_channel();
+        let (to_blockchain_sender, _to_blockchain_receiver) = unbounded_channel();
+        let (to_swarm_sender, _to_swarm_receiver) = unbounded_channel();
+        let (to_state_sender, _to_state_receiver) =
+            bounded_channel("state", ChannelConfig::default());
+        let (command_sender, command_receiver) = unbounded_channel();
+        let (to_message_sender, to_message_receiver) = unbounded_channel();
+        let command_handler = CommandHandler::new(
+            to_miner_sender,
+            to_blockchain_sender,
+            to_swarm_sender,
+            to_state_sender,
+            command_receiver,
+        );
+        let message_handler = MessageHandler::new(to_message_sender, to_message_receiver);
+        let mut node = Node::new(
+            NodeAuth::Full,
+            command_handler,
+            message_handler,
+            EventLog::open(&temp_path("vrrb_test_headless_events.db")),
+            identity::Keypair::generate_ed25519(),
+        );
+
+        command_sender.send(Command::MineBlock).unwrap();
+        command_sender.send(Command::Quit).unwrap();
+        assert!(block_on(node.start()).is_ok());
+        assert!(matches!(
+            to_miner_receiver.try_recv(),
+            Ok(Command::StartMiner)
+        ));
+    }
+
+    #[test]
+    fn node_key_is_kept_across_restarts() {
+        let path = std::env::temp_dir().join("vrrb_test_node.key");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let key = Node::load_key(path).unwrap();
+        let reloaded = Node::load_key(path).unwrap();
+        assert_eq!(PeerId::from(key.public()), PeerId::from(reloaded.public()));
+
+        fs::write(path, b"not a key").unwrap();
+        assert!(Node::load_key(path).is_err());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,151 @@
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+pub const GOOD_RESPONSE_SCORE: i128 = 1;
+pub const TIMEOUT_SCORE: i128 = -1;
+pub const BAD_RESPONSE_SCORE: i128 = -2;
+pub const MIN_PEER_SCORE: i128 = -6;
+
+/// Tracks how reliably peers answer state requests so the node can choose who
+/// to sync from. Peers that fall to MIN_PEER_SCORE are dropped from candidacy.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerScores {
+    pub scores: LinkedHashMap<String, i128>,
+    pub dropped: HashSet<String>,
+    // State requests each peer declined since it last served one. Declining
+    // isn't misbehaviour, so it doesn't cost score, but peers that declined
+    // fewer are asked first.
+    #[serde(default)]
+    pub declines: LinkedHashMap<String, u32>,
+}
+
+impl PeerScores {
+    pub fn new() -> PeerScores {
+        PeerScores {
+            scores: LinkedHashMap::new(),
+            dropped: HashSet::new(),
+            declines: LinkedHashMap::new(),
+        }
+    }
+
+    pub fn add_peer(&mut self, peer_id: &str) {
+        if !self.dropped.contains(peer_id) {
+            self.scores.entry(peer_id.to_string()).or_insert(0);
+        }
+    }
+
+    pub fn record_success(&mut self, peer_id: &str) {
+        self.declines.remove(peer_id);
+        self.adjust(peer_id, GOOD_RESPONSE_SCORE);
+    }
+
+    pub fn record_decline(&mut self, peer_id: &str) {
+        *self.declines.entry(peer_id.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn declines(&self, peer_id: &str) -> u32 {
+        self.declines.get(peer_id).copied().unwrap_or(0)
+    }
+
+    pub fn record_timeout(&mut self, peer_id: &str) {
+        self.adjust(peer_id, TIMEOUT_SCORE);
+    }
+
+    pub fn record_bad_response(&mut self, peer_id: &str) {
+        self.adjust(peer_id, BAD_RESPONSE_SCORE);
+    }
+
+    /// Drops a peer from candidacy whatever its score, for a peer that can't
+    /// be synced from at all.
+    pub fn drop_peer(&mut self, peer_id: &str) {
+        self.scores.remove(peer_id);
+        self.declines.remove(peer_id);
+        self.dropped.insert(peer_id.to_string());
+    }
+
+    pub fn score(&self, peer_id: &str) -> Option<i128> {
+        self.scores.get(peer_id).copied()
+    }
+
+    pub fn is_dropped(&self, peer_id: &str) -> bool {
+        self.dropped.contains(peer_id)
+    }
+
+    /// Returns the highest scored of the candidates that declined the fewest
+    /// state requests, on a tie the most recently added peer wins.
+    pub fn best_peer(&self) -> Option<String> {
+        self.scores
+            .iter()
+            .max_by_key(|(peer_id, score)| (Reverse(self.declines(peer_id)), **score))
+            .map(|(peer_id, _)| peer_id.clone())
+    }
+
+    /// Adds the peer that triggered the sync as a candidate and returns the best
+    /// peer to request state from.
+    pub fn select_sync_peer(&mut self, triggered_by: &str) -> Option<String> {
+        self.add_peer(triggered_by);
+        self.best_peer()
+    }
+
+    fn adjust(&mut self, peer_id: &str, delta: i128) {
+        if self.dropped.contains(peer_id) {
+            return;
+        }
+
+        let score = self.scores.entry(peer_id.to_string()).or_insert(0);
+        *score += delta;
+
+        if *score <= MIN_PEER_SCORE {
+            self.scores.remove(peer_id);
+            self.dropped.insert(peer_id.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peer_with_corrupt_components_is_deprioritized() {
+        let mut peer_scores = PeerScores::new();
+        peer_scores.add_peer("good_peer");
+        peer_scores.add_peer("bad_peer");
+
+        peer_scores.record_bad_response("bad_peer");
+
+        assert_eq!(
+            peer_scores.select_sync_peer("bad_peer"),
+            Some("good_peer".to_string())
+        );
+    }
+
+    #[test]
+    fn test_peer_dropped_after_repeated_bad_responses() {
+        let mut peer_scores = PeerScores::new();
+        (0..3).for_each(|_| peer_scores.record_bad_response("bad_peer"));
+
+        assert!(peer_scores.is_dropped("bad_peer"));
+        assert_eq!(peer_scores.select_sync_peer("bad_peer"), None);
+    }
+
+    #[test]
+    fn test_sync_rotates_to_the_next_peer_after_a_decline() {
+        let mut peer_scores = PeerScores::new();
+        peer_scores.add_peer("first_peer");
+        peer_scores.add_peer("second_peer");
+        peer_scores.record_success("first_peer");
+        assert_eq!(peer_scores.best_peer(), Some("first_peer".to_string()));
+
+        peer_scores.record_decline("first_peer");
+        assert_eq!(peer_scores.best_peer(), Some("second_peer".to_string()));
+        assert_eq!(peer_scores.score("first_peer"), Some(GOOD_RESPONSE_SCORE));
+
+        // Once every peer has declined, the one that declined least is asked.
+        peer_scores.record_decline("second_peer");
+        peer_scores.record_decline("second_peer");
+        assert_eq!(peer_scores.best_peer(), Some("first_peer".to_string()));
+    }
+}
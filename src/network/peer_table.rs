@@ -0,0 +1,310 @@
+use crate::block::SECOND;
+use crate::logging::SWARM_TARGET;
+use crate::network::protocol::VrrbNetworkBehavior;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::Swarm;
+use libp2p::{Multiaddr, PeerId};
+use log::info;
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+
+// Peers not seen for longer than this many seconds aren't handed out.
+pub const PEER_TTL: u128 = 60 * 60;
+// The most peers sent in a single peer exchange response.
+pub const MAX_PEERS_RESPONSE: usize = 16;
+// How many of the received peers to dial, the rest are added to kademlia.
+pub const PEERS_TO_DIAL: usize = 3;
+// The most peers kept in the table, the least recently seen are dropped first.
+pub const MAX_KNOWN_PEERS: usize = 1000;
+// The most peers learned from peer exchange that are added to kademlia.
+pub const MAX_EXCHANGED_PEERS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub addr: String,
+    pub last_seen: u128,
+}
+
+/// The peers this node has recently been connected to, persisted to the data
+/// dir so that a restarted node has peers to dial other than the one passed on
+/// the command line. Peer ids and addresses are kept as strings, the same form
+/// they are exchanged in.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerTable {
+    // Ordered from least to most recently seen.
+    pub peers: LinkedHashMap<String, PeerRecord>,
+}
+
+/// The peer exchange requests this node has sent. Only a peer that was asked
+/// for its peers is listened to, once, and the peers taken from responses are
+/// bounded so a peer can't flood kademlia with addresses.
+#[derive(Debug, Clone, Default)]
+pub struct PeerExchange {
+    // The peers asked for their peers that haven't responded yet.
+    requests: HashSet<String>,
+    // The peers taken from responses so far.
+    added: HashSet<String>,
+}
+
+impl PeerTable {
+    pub fn new() -> PeerTable {
+        PeerTable {
+            peers: LinkedHashMap::new(),
+        }
+    }
+
+    /// Records that `peer_id` was reachable at `addr` at `now`, moving it to the
+    /// back of the table. Past MAX_KNOWN_PEERS the least recently seen peer is
+    /// dropped.
+    pub fn seen(&mut self, peer_id: &str, addr: &str, now: u128) {
+        self.peers.remove(peer_id);
+        self.peers.insert(
+            peer_id.to_string(),
+            PeerRecord {
+                addr: addr.to_string(),
+                last_seen: now,
+            },
+        );
+        while self.peers.len() > MAX_KNOWN_PEERS {
+            self.peers.pop_front();
+        }
+    }
+
+    /// Returns up to MAX_PEERS_RESPONSE peers seen within PEER_TTL, most
+    /// recently seen first, leaving out `exclude`.
+    pub fn reachable(&self, now: u128, exclude: &str) -> Vec<(String, String)> {
+        self.peers
+            .iter()
+            .rev()
+            .filter(|(peer_id, _)| peer_id.as_str() != exclude)
+            .filter(|(_, record)| now.saturating_sub(record.last_seen) / SECOND <= PEER_TTL)
+            .take(MAX_PEERS_RESPONSE)
+            .map(|(peer_id, record)| (peer_id.clone(), record.addr.clone()))
+            .collect()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Loads the table saved at `path`, starting empty if there isn't one.
+    pub fn restore(path: &str) -> PeerTable {
+        if let Ok(data) = fs::read(path) {
+            if let Ok(table) = serde_json::from_slice::<PeerTable>(&data) {
+                return table;
+            }
+        }
+
+        PeerTable::new()
+    }
+}
+
+impl PeerExchange {
+    pub fn new() -> PeerExchange {
+        PeerExchange::default()
+    }
+
+    /// Records that `peer_id` was asked for its peers.
+    pub fn requested(&mut self, peer_id: &str) {
+        self.requests.insert(peer_id.to_string());
+    }
+
+    /// The peers to take from a response sent by `sender_id`, none unless it
+    /// was asked for them. At most MAX_PEERS_RESPONSE are taken from a
+    /// response, and no new ones once MAX_EXCHANGED_PEERS have been.
+    pub fn accept(&mut self, sender_id: &str, peers: &[(String, String)]) -> Vec<(String, String)> {
+        if !self.requests.remove(sender_id) {
+            info!(target: SWARM_TARGET, "Unrequested peers from {} dropped", sender_id);
+            return vec![];
+        }
+
+        let added = &mut self.added;
+        peers
+            .iter()
+            .take(MAX_PEERS_RESPONSE)
+            .filter(|(peer_id, _)| {
+                if added.contains(peer_id) {
+                    return true;
+                }
+                if added.len() >= MAX_EXCHANGED_PEERS {
+                    return false;
+                }
+                added.insert(peer_id.clone());
+                true
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Parses exchanged peers, skipping any with an invalid id or address.
+pub fn parse_peers(peers: &[(String, String)]) -> Vec<(PeerId, Multiaddr)> {
+    peers
+        .iter()
+        .filter_map(|(peer_id, addr)| match (peer_id.parse(), addr.parse()) {
+            (Ok(peer_id), Ok(addr)) => Some((peer_id, addr)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Picks the address to share for a peer from the addresses it listens on,
+/// preferring one that isn't a loopback address.
+pub fn shareable_addr(listen_addrs: &[Multiaddr]) -> Option<&Multiaddr> {
+    listen_addrs
+        .iter()
+        .find(|addr| {
+            !addr.iter().any(|protocol| match protocol {
+                Protocol::Ip4(ip) => ip.is_loopback(),
+                Protocol::Ip6(ip) => ip.is_loopback(),
+                _ => false,
+            })
+        })
+        .or_else(|| listen_addrs.first())
+}
+
+/// Adds exchanged peers to kademlia and dials the first PEERS_TO_DIAL of them
+/// the node isn't already connected to.
+pub fn add_peers(swarm: &mut Swarm<VrrbNetworkBehavior>, peers: &[(String, String)]) {
+    let local_peer_id = *swarm.local_peer_id();
+    let mut dialed = 0;
+    for (peer_id, addr) in parse_peers(peers) {
+        if peer_id == local_peer_id {
+            continue;
+        }
+
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&peer_id, addr.clone());
+        if dialed < PEERS_TO_DIAL && !swarm.is_connected(&peer_id) {
+            match swarm.dial_addr(addr.clone()) {
+                Ok(_) => dialed += 1,
+                Err(e) => info!(target: SWARM_TARGET, "Dial {:?} failed: {:?}", addr, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    #[test]
+    fn stale_peers_are_left_out() {
+        let now = 2 * PEER_TTL * SECOND;
+        let mut table = PeerTable::new();
+        table.seen(
+            "stale",
+            "/ip4/127.0.0.1/tcp/9292",
+            now - (PEER_TTL + 1) * SECOND,
+        );
+        table.seen("fresh", "/ip4/127.0.0.1/tcp/9293", now - SECOND);
+        table.seen("requestor", "/ip4/127.0.0.1/tcp/9294", now);
+
+        assert_eq!(
+            table.reachable(now, "requestor"),
+            vec![("fresh".to_string(), "/ip4/127.0.0.1/tcp/9293".to_string())]
+        );
+    }
+
+    #[test]
+    fn response_is_capped() {
+        let mut table = PeerTable::new();
+        (0..MAX_PEERS_RESPONSE + 5).for_each(|n| {
+            table.seen(&n.to_string(), "/ip4/127.0.0.1/tcp/9292", n as u128);
+        });
+
+        let peers = table.reachable(MAX_PEERS_RESPONSE as u128, "");
+        assert_eq!(peers.len(), MAX_PEERS_RESPONSE);
+        assert_eq!(peers[0].0, (MAX_PEERS_RESPONSE + 4).to_string());
+    }
+
+    #[test]
+    fn table_is_capped() {
+        let mut table = PeerTable::new();
+        (0..MAX_KNOWN_PEERS + 5).for_each(|n| {
+            table.seen(&n.to_string(), "/ip4/127.0.0.1/tcp/9292", n as u128);
+        });
+
+        assert_eq!(table.peers.len(), MAX_KNOWN_PEERS);
+        assert!(!table.peers.contains_key("4"));
+        assert!(table.peers.contains_key("5"));
+    }
+
+    #[test]
+    fn only_requested_responses_are_taken_and_bounded() {
+        let peers = |from: usize, n: usize| -> Vec<(String, String)> {
+            (from..from + n)
+                .map(|n| (n.to_string(), "/ip4/127.0.0.1/tcp/9292".to_string()))
+                .collect()
+        };
+        let mut exchange = PeerExchange::new();
+        assert!(exchange.accept("stranger", &peers(0, 1)).is_empty());
+
+        exchange.requested("peer");
+        let taken = exchange.accept("peer", &peers(0, MAX_PEERS_RESPONSE + 5));
+        assert_eq!(taken, peers(0, MAX_PEERS_RESPONSE));
+        // Only one response per request.
+        assert!(exchange.accept("peer", &peers(0, 1)).is_empty());
+
+        let mut n_added = MAX_PEERS_RESPONSE;
+        while n_added < MAX_EXCHANGED_PEERS {
+            exchange.requested("peer");
+            n_added += exchange
+                .accept("peer", &peers(n_added, MAX_PEERS_RESPONSE))
+                .len();
+        }
+        exchange.requested("peer");
+        assert!(exchange.accept("peer", &peers(n_added, 1)).is_empty());
+        // Peers already known are still taken.
+        exchange.requested("peer");
+        assert_eq!(exchange.accept("peer", &peers(0, 1)), peers(0, 1));
+    }
+
+    #[test]
+    fn table_persists_and_reloads() {
+        let path = temp_path("vrrb_peer_table.json");
+        let _ = fs::remove_file(&path);
+        assert!(PeerTable::restore(&path).peers.is_empty());
+
+        let mut table = PeerTable::new();
+        table.seen("first", "/ip4/127.0.0.1/tcp/9292", 1);
+        table.seen("second", "/ip4/127.0.0.1/tcp/9293", 2);
+        table.save(&path).unwrap();
+
+        let restored = PeerTable::restore(&path);
+        assert_eq!(restored.peers, table.peers);
+        assert_eq!(restored.reachable(2, ""), table.reachable(2, ""));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn saved_addresses_redial_after_reload() {
+        let path = temp_path("vrrb_peer_table_addrs.json");
+        let peer_ids = vec![PeerId::random(), PeerId::random()];
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip4/10.0.0.1/tcp/19292".parse().unwrap(),
+            "/ip6/::1/tcp/19293".parse().unwrap(),
+        ];
+        let mut table = PeerTable::new();
+        peer_ids
+            .iter()
+            .zip(addrs.iter())
+            .for_each(|(peer_id, addr)| {
+                table.seen(&peer_id.to_string(), &addr.to_string(), 1);
+            });
+        table.save(&path).unwrap();
+
+        let restored = parse_peers(&PeerTable::restore(&path).reachable(1, ""));
+        let expected: Vec<(PeerId, Multiaddr)> =
+            peer_ids.into_iter().zip(addrs.into_iter()).rev().collect();
+        assert_eq!(restored, expected);
+        let _ = fs::remove_file(&path);
+    }
+}
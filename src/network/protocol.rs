@@ -1,4 +1,8 @@
+use crate::logging::SWARM_TARGET;
 use crate::network::command_utils::Command;
+use crate::network::message_types::MessageType;
+use crate::network::peer_table::{self, PeerExchange, PeerTable};
+use crate::network::topics::{GossipTopic, TopicNames, NETWORK_ID};
 use libp2p::{
     core::{
         muxing::StreamMuxerBox, transport::upgrade::Version, transport::Boxed,
@@ -20,22 +24,167 @@ use libp2p::{
     yamux::YamuxConfig,
     NetworkBehaviour, PeerId, Transport,
 };
-use log::info;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
 use std::fmt::Debug;
 use std::fs;
 use std::io::Error;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 
+// The most events kept in the event log, older events are dropped first.
+pub const MAX_NETWORK_EVENTS: usize = 100;
+// How often the swarm thread writes new events to the event log, in seconds.
+pub const EVENT_FLUSH_INTERVAL: u64 = 1;
+// The version of the message protocol this node speaks. Peers are compatible
+// if they share the major version, or the minor version before 1.0.
+pub const PROTOCOL_VERSION: &str = "0.1.0";
+
+/// The event log read by the network data view. Events are kept in memory
+/// and written out in batches by `flush`, which the swarm thread calls every
+/// EVENT_FLUSH_INTERVAL, and once the last handle to the log is dropped.
+/// Clones share the same events.
+#[derive(Clone, Debug)]
+pub struct EventLog {
+    inner: Arc<Mutex<EventBuffer>>,
+}
+
+#[derive(Debug)]
+struct EventBuffer {
+    path: String,
+    events: VecDeque<VrrbNetworkEvent>,
+    // Whether events were added since the log was last written.
+    dirty: bool,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum VrrbNetworkEvent {
     VrrbStarted,
     VrrbProtocolEvent {
-        event: String
+        event: String,
+    },
+    PeerConnected {
+        peer_id: String,
+    },
+    PeerDisconnected {
+        peer_id: String,
+    },
+    BlockReceived {
+        block_hash: String,
+        block_height: u128,
+        sender_id: String,
+    },
+    TxnReceived {
+        txn_id: String,
+        sender_id: String,
+    },
+    PeerRejected {
+        peer_id: String,
+        protocol_version: String,
+        reason: String,
+    },
+    // A block on the chain crossed the finality depth, logged once per block.
+    BlockFinalized {
+        block_hash: String,
+        block_height: u128,
+    },
+}
+
+impl VrrbNetworkEvent {
+    /// The event logged when a gossiped message is received, if it is one the
+    /// network data view shows.
+    pub fn from_message(message: &MessageType) -> Option<VrrbNetworkEvent> {
+        match message {
+            MessageType::BlockMessage { block, sender_id } => {
+                Some(VrrbNetworkEvent::BlockReceived {
+                    block_hash: block.hash.clone(),
+                    block_height: block.header.block_height,
+                    sender_id: sender_id.clone(),
+                })
+            }
+            MessageType::CompactBlockMessage { block, sender_id } => {
+                Some(VrrbNetworkEvent::BlockReceived {
+                    block_hash: block.hash.clone(),
+                    block_height: block.header.block_height,
+                    sender_id: sender_id.clone(),
+                })
+            }
+            MessageType::TxnMessage { txn, sender_id }
+            | MessageType::ForwardedTxnMessage { txn, sender_id, .. } => {
+                Some(VrrbNetworkEvent::TxnReceived {
+                    txn_id: txn.txn_id.clone(),
+                    sender_id: sender_id.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The event logged when a peer's protocol version, exchanged over identify
+    /// when it connects, isn't compatible with this node's.
+    pub fn handshake(peer_id: &str, protocol_version: &str) -> Option<VrrbNetworkEvent> {
+        if let Err(reason) = check_protocol_version(protocol_version, NETWORK_ID) {
+            return Some(VrrbNetworkEvent::PeerRejected {
+                peer_id: peer_id.to_string(),
+                protocol_version: protocol_version.to_string(),
+                reason,
+            });
+        }
+
+        None
     }
 }
 
+/// The protocol version advertised to peers over identify.
+pub fn protocol_id(network_id: &str) -> String {
+    format!("vrrb/{}/{}", network_id, PROTOCOL_VERSION)
+}
+
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let parts: Vec<u32> = version
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if parts.len() != 3 {
+        return None;
+    }
+
+    Some((parts[0], parts[1], parts[2]))
+}
+
+/// Checks that a protocol id advertised by a peer is for `network_id` and a
+/// version compatible with PROTOCOL_VERSION, returning the reason it isn't.
+pub fn check_protocol_version(protocol_id: &str, network_id: &str) -> Result<(), String> {
+    let parts: Vec<&str> = protocol_id.split('/').collect();
+    if parts.len() != 3 || parts[0] != "vrrb" {
+        return Err(format!("unknown protocol {}", protocol_id));
+    }
+
+    if parts[1] != network_id {
+        return Err(format!(
+            "peer is on network {}, not {}",
+            parts[1], network_id
+        ));
+    }
+
+    let (major, minor, _) = if let Some(version) = parse_version(parts[2]) {
+        version
+    } else {
+        return Err(format!("invalid protocol version {}", parts[2]));
+    };
+    let (local_major, local_minor, _) = parse_version(PROTOCOL_VERSION).unwrap();
+    if major != local_major || (major == 0 && minor != local_minor) {
+        return Err(format!(
+            "protocol version {} is incompatible with {}",
+            parts[2], PROTOCOL_VERSION
+        ));
+    }
+
+    Ok(())
+}
+
 #[derive(NetworkBehaviour)]
 pub struct VrrbNetworkBehavior {
     pub gossipsub: Gossipsub,
@@ -51,20 +200,58 @@ pub struct VrrbNetworkBehavior {
     #[behaviour(ignore)]
     pub address: String,
     #[behaviour(ignore)]
-    pub path: String,
+    pub node_id: String,
+    #[behaviour(ignore)]
+    pub events: EventLog,
+    #[behaviour(ignore)]
+    pub peer_table: PeerTable,
+    #[behaviour(ignore)]
+    pub peers_path: String,
+    // Dialed peers to request more peers from once they subscribe to the
+    // blocks topic.
+    #[behaviour(ignore)]
+    pub awaiting_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    pub peer_exchange: PeerExchange,
+    // Peers that failed the version handshake, banned by the swarm thread.
+    #[behaviour(ignore)]
+    pub rejected_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    pub topics: TopicNames,
 }
 
 impl NetworkBehaviourEventProcess<IdentifyEvent> for VrrbNetworkBehavior {
     // called when 'identify'
     fn inject_event(&mut self, event: IdentifyEvent) {
-        if let Err(_) = write_to_json(self.path.clone(), &event) {
-            info!("Error writing to json in identify event");
+        if let Err(_) = write_to_json(&self.events, &event) {
+            error!(target: SWARM_TARGET, "Error writing to json in identify event");
         };
         match event {
             IdentifyEvent::Received { peer_id, info } => {
+                if let Some(event) =
+                    VrrbNetworkEvent::handshake(&peer_id.to_string(), &info.protocol_version)
+                {
+                    info!(target: SWARM_TARGET, "Rejecting peer {}: {:?}", peer_id, event);
+                    self.events.log(event);
+                    self.kademlia.remove_peer(&peer_id);
+                    self.awaiting_peers.remove(&peer_id);
+                    self.rejected_peers.insert(peer_id);
+                    return;
+                }
                 for addr in &info.listen_addrs {
                     self.kademlia.add_address(&peer_id, addr.clone());
                 }
+                if let Some(addr) = peer_table::shareable_addr(&info.listen_addrs) {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_nanos();
+                    self.peer_table
+                        .seen(&peer_id.to_string(), &addr.to_string(), now);
+                    if let Err(e) = self.peer_table.save(&self.peers_path) {
+                        error!(target: SWARM_TARGET, "Error saving peer table: {:?}", e);
+                    }
+                }
                 self.kademlia.bootstrap().unwrap();
             }
             _ => {}
@@ -74,8 +261,8 @@ impl NetworkBehaviourEventProcess<IdentifyEvent> for VrrbNetworkBehavior {
 
 impl NetworkBehaviourEventProcess<GossipsubEvent> for VrrbNetworkBehavior {
     fn inject_event(&mut self, event: GossipsubEvent) {
-        if let Err(_) = write_to_json(self.path.clone(), &event) {
-            info!("Error writing to json in GossipsubEvent");
+        if let Err(_) = write_to_json(&self.events, &event) {
+            error!(target: SWARM_TARGET, "Error writing to json in GossipsubEvent");
         };
         match event {
             GossipsubEvent::Message {
@@ -87,6 +274,36 @@ impl NetworkBehaviourEventProcess<GossipsubEvent> for VrrbNetworkBehavior {
                     println!("Error sending message to message handling thread");
                 };
             }
+            // Swap pending txn ids with peers as they join, a new peer would
+            // otherwise only learn of txns gossiped after it. The ids go to the
+            // peer's own topic, so it's synced once it subscribes to that.
+            GossipsubEvent::Subscribed { peer_id, topic }
+                if topic.as_str() == self.topics.peer_topic(&peer_id.to_string()) =>
+            {
+                if let Err(e) = self
+                    .command_sender
+                    .send(Command::SyncPool(peer_id.to_string()))
+                {
+                    println!("Error sending pool sync to command receiver: {:?}", e);
+                }
+            }
+            GossipsubEvent::Subscribed { peer_id, topic } => {
+                if topic.as_str() == self.topics.name(GossipTopic::Blocks)
+                    && self.awaiting_peers.remove(&peer_id)
+                {
+                    self.peer_exchange.requested(&peer_id.to_string());
+                    let message = MessageType::GetPeersMessage {
+                        sender_id: self.node_id.clone(),
+                        requested_from: peer_id.to_string(),
+                    };
+                    if let Err(e) = self
+                        .command_sender
+                        .send(Command::SendMessage(message.as_bytes()))
+                    {
+                        println!("Error sending peers request to command receiver: {:?}", e);
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -94,8 +311,8 @@ impl NetworkBehaviourEventProcess<GossipsubEvent> for VrrbNetworkBehavior {
 
 impl NetworkBehaviourEventProcess<PingEvent> for VrrbNetworkBehavior {
     fn inject_event(&mut self, event: PingEvent) {
-        if let Err(_) = write_to_json(self.path.clone(), &event) {
-            info!("Error writing to json in PingEvent");
+        if let Err(_) = write_to_json(&self.events, &event) {
+            error!(target: SWARM_TARGET, "Error writing to json in PingEvent");
         }
         match event {
             PingEvent { result, peer } => {
@@ -126,8 +343,8 @@ impl NetworkBehaviourEventProcess<PingEvent> for VrrbNetworkBehavior {
 
 impl NetworkBehaviourEventProcess<KademliaEvent> for VrrbNetworkBehavior {
     fn inject_event(&mut self, event: KademliaEvent) {
-        if let Err(_) = write_to_json(self.path.clone(), &event) {
-            info!("Error writing to json in Kademlia Event");
+        if let Err(_) = write_to_json(&self.events, &event) {
+            error!(target: SWARM_TARGET, "Error writing to json in Kademlia Event");
         }
         match event {
             KademliaEvent::QueryResult { result, .. } => match result {
@@ -234,38 +451,116 @@ pub async fn build_transport(
         .boxed())
 }
 
-pub fn write_to_json<T: Debug>(path: String, event: &T) -> Result<(), serde_json::Error> {
-    let content = fs::read_to_string(path.clone());
-    if let Ok(string) = content {
-        let result: Result<Vec<VrrbNetworkEvent>, serde_json::Error> =
-            serde_json::from_str(&string);
-        if let Ok(mut events) = result {
-            let new_event = get_event(event);
-            events.push(new_event);
-            if events.len() > 100 {
-                events.remove(0);
-            }
-            let json_vec = serde_json::to_vec(&events);
-            if let Ok(json) = json_vec {
-                if let Err(e) = fs::write(path.clone(), json) {
-                    info!("Error writing event to events.json: {:?}", e);
-                }
-            }
-        } else {
-            let new_event = get_event(event);
-            let events = vec![new_event];
-            let json_vec = serde_json::to_vec(&events);
-            if let Ok(json) = json_vec {
-                if let Err(e) = fs::write(path.clone(), json) {
-                    info!("Error writing event to events.json: {:?}", e);
-                }
+pub fn write_to_json<T: Debug>(events: &EventLog, event: &T) -> Result<(), serde_json::Error> {
+    events.log(get_event(event));
+    Ok(())
+}
+
+impl EventLog {
+    /// Opens the event log at `path`, keeping the events already in it.
+    pub fn open(path: &str) -> EventLog {
+        let events = read_from_json(&path.to_string()).unwrap_or_default();
+        let skip = events.len().saturating_sub(MAX_NETWORK_EVENTS);
+        EventLog {
+            inner: Arc::new(Mutex::new(EventBuffer {
+                path: path.to_string(),
+                events: events.into_iter().skip(skip).collect(),
+                dirty: false,
+            })),
+        }
+    }
+
+    pub fn path(&self) -> String {
+        self.inner.lock().unwrap().path.clone()
+    }
+
+    /// Adds an event to the log, it is written out by the next flush. Only the
+    /// last MAX_NETWORK_EVENTS are kept.
+    pub fn log(&self, event: VrrbNetworkEvent) {
+        let mut buffer = self.inner.lock().unwrap();
+        buffer.events.push_back(event);
+        if buffer.events.len() > MAX_NETWORK_EVENTS {
+            buffer.events.pop_front();
+        }
+        buffer.dirty = true;
+    }
+
+    /// Writes the log out if events were added since it last was.
+    pub fn flush(&self) {
+        self.inner.lock().unwrap().flush()
+    }
+}
+
+impl EventBuffer {
+    fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Ok(json) = serde_json::to_vec(&self.events) {
+            match fs::write(&self.path, json) {
+                Ok(()) => self.dirty = false,
+                Err(e) => error!(
+                    target: SWARM_TARGET,
+                    "Error writing events to {}: {:?}", self.path, e
+                ),
             }
         }
     }
-    Ok(())
+}
+
+impl Drop for EventBuffer {
+    fn drop(&mut self) {
+        self.flush()
+    }
 }
 
 pub fn get_event<T: Debug>(event: &T) -> VrrbNetworkEvent {
     let event_string = format!("{:?}", event);
     VrrbNetworkEvent::VrrbProtocolEvent { event: event_string }
-}
\ No newline at end of file
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum JsonError {
+    #[error("Error reading the json file: {0}")]
+    ReadJsonError(#[from] std::io::Error),
+    #[error("Error parsing the json file: {0}")]
+    ParseJsonError(#[from] serde_json::Error),
+}
+
+pub fn read_from_json(path: &String) -> Result<Vec<VrrbNetworkEvent>, JsonError> {
+    let content = fs::read_to_string(path)?;
+    let parsed_json: Vec<VrrbNetworkEvent> = serde_json::from_str(&content)?;
+    Ok(parsed_json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_with_unsupported_version_is_rejected() {
+        assert!(VrrbNetworkEvent::handshake("peer", &protocol_id(NETWORK_ID)).is_none());
+        assert!(VrrbNetworkEvent::handshake("peer", "vrrb/test-net/0.1.7").is_none());
+
+        [
+            "vrrb/test-net/0.2.0",
+            "vrrb/test-net/1.0.0",
+            "vrrb/main-net/0.1.0",
+            "ipfs/0.1.0",
+        ]
+        .iter()
+        .for_each(|protocol_version| {
+            match VrrbNetworkEvent::handshake("peer", protocol_version) {
+                Some(VrrbNetworkEvent::PeerRejected {
+                    peer_id,
+                    protocol_version: rejected_version,
+                    ..
+                }) => {
+                    assert_eq!(peer_id, "peer");
+                    assert_eq!(&rejected_version, protocol_version);
+                }
+                event => panic!("{} wasn't rejected: {:?}", protocol_version, event),
+            }
+        });
+    }
+}
@@ -0,0 +1,201 @@
+use crate::block::SECOND;
+use crate::utils::take_flag;
+use rand::Rng;
+use std::collections::HashSet;
+
+pub const MIN_PEERS_FLAG: &str = "--min-peers";
+// Known peers are re-dialed while the node has fewer connections than this.
+pub const MIN_PEERS: usize = 3;
+// The delay before the first retry of an address, doubled on every failure.
+pub const BASE_DIAL_BACKOFF: u128 = 1;
+pub const MAX_DIAL_BACKOFF: u128 = 5 * 60;
+// Addresses are dropped from the rotation after this many failed dials in a row.
+pub const MAX_DIAL_FAILURES: u32 = 5;
+
+/// The delay in seconds before retrying an address that has failed
+/// `failures` dials in a row, doubling from BASE_DIAL_BACKOFF up to
+/// MAX_DIAL_BACKOFF.
+pub fn backoff(failures: u32) -> u128 {
+    let factor = 2u128.checked_pow(failures).unwrap_or(u128::MAX);
+    std::cmp::min(BASE_DIAL_BACKOFF.saturating_mul(factor), MAX_DIAL_BACKOFF)
+}
+
+/// Adds up to half of `delay` again, so nodes that lost the same peer don't
+/// all re-dial it at once.
+pub fn with_jitter(delay: u128) -> u128 {
+    if delay < 2 {
+        return delay;
+    }
+
+    delay + rand::thread_rng().gen_range(0, delay / 2)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialTarget {
+    pub addr: String,
+    pub failures: u32,
+    // When the address may next be dialed, in nanoseconds.
+    pub next_attempt: u128,
+}
+
+/// Keeps the node connected to at least `min_peers` peers, re-dialing known
+/// addresses in rotation with exponential backoff when connections drop.
+#[derive(Debug, Clone)]
+pub struct ConnectionSupervisor {
+    pub min_peers: usize,
+    pub connected: HashSet<String>,
+    pub targets: Vec<DialTarget>,
+    next: usize,
+}
+
+impl ConnectionSupervisor {
+    pub fn new(min_peers: usize) -> ConnectionSupervisor {
+        ConnectionSupervisor {
+            min_peers,
+            connected: HashSet::new(),
+            targets: vec![],
+            next: 0,
+        }
+    }
+
+    /// Removes the min peers flag from the command line arguments, falling
+    /// back to MIN_PEERS if it is missing or invalid.
+    pub fn min_peers_from_args(mut args: Vec<String>) -> (usize, Vec<String>) {
+        let min_peers = take_flag(&mut args, MIN_PEERS_FLAG)
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(MIN_PEERS);
+
+        (min_peers, args)
+    }
+
+    /// Adds an address to the rotation, if it isn't already in it.
+    pub fn add_target(&mut self, addr: &str) {
+        if !self.targets.iter().any(|target| target.addr == addr) {
+            self.targets.push(DialTarget {
+                addr: addr.to_string(),
+                failures: 0,
+                next_attempt: 0,
+            });
+        }
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.connected.len()
+    }
+
+    pub fn connected(&mut self, peer_id: &str) {
+        self.connected.insert(peer_id.to_string());
+    }
+
+    pub fn disconnected(&mut self, peer_id: &str) {
+        self.connected.remove(peer_id);
+    }
+
+    pub fn dial_succeeded(&mut self, addr: &str) {
+        if let Some(target) = self.targets.iter_mut().find(|target| target.addr == addr) {
+            target.failures = 0;
+            target.next_attempt = 0;
+        }
+    }
+
+    /// Backs off from an address that couldn't be reached, dropping it from
+    /// the rotation after MAX_DIAL_FAILURES failures in a row.
+    pub fn dial_failed(&mut self, addr: &str, now: u128) {
+        if let Some(idx) = self.targets.iter().position(|target| target.addr == addr) {
+            let target = &mut self.targets[idx];
+            target.failures += 1;
+            if target.failures >= MAX_DIAL_FAILURES {
+                self.targets.remove(idx);
+                if self.next > idx {
+                    self.next -= 1;
+                }
+                return;
+            }
+            target.next_attempt = now + with_jitter(backoff(target.failures)) * SECOND;
+        }
+    }
+
+    /// The next address to dial, if the node is below its minimum peer count
+    /// and an address in the rotation is due. The address isn't handed out
+    /// again until its backoff has passed.
+    pub fn next_dial(&mut self, now: u128) -> Option<String> {
+        if self.peer_count() >= self.min_peers || self.targets.is_empty() {
+            return None;
+        }
+
+        let n_targets = self.targets.len();
+        for offset in 0..n_targets {
+            let idx = (self.next + offset) % n_targets;
+            let target = &mut self.targets[idx];
+            if target.next_attempt <= now {
+                target.next_attempt = now + backoff(target.failures) * SECOND;
+                self.next = (idx + 1) % n_targets;
+                return Some(target.addr.clone());
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff(0), BASE_DIAL_BACKOFF);
+        assert_eq!(backoff(1), 2 * BASE_DIAL_BACKOFF);
+        assert_eq!(backoff(3), 8 * BASE_DIAL_BACKOFF);
+        assert_eq!(backoff(20), MAX_DIAL_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_DIAL_BACKOFF);
+        (0..10).for_each(|failures| {
+            let delay = with_jitter(backoff(failures));
+            assert!(delay >= backoff(failures) && delay <= backoff(failures) * 3 / 2);
+        });
+    }
+
+    #[test]
+    fn address_is_rotated_out_after_repeated_failures() {
+        let mut supervisor = ConnectionSupervisor::new(1);
+        supervisor.add_target("/ip4/127.0.0.1/tcp/9292");
+        supervisor.add_target("/ip4/127.0.0.1/tcp/9293");
+
+        let mut now = 0;
+        assert_eq!(
+            supervisor.next_dial(now),
+            Some("/ip4/127.0.0.1/tcp/9292".to_string())
+        );
+        assert_eq!(
+            supervisor.next_dial(now),
+            Some("/ip4/127.0.0.1/tcp/9293".to_string())
+        );
+        // Both addresses are backing off.
+        assert_eq!(supervisor.next_dial(now), None);
+
+        (0..MAX_DIAL_FAILURES).for_each(|_| {
+            supervisor.dial_failed("/ip4/127.0.0.1/tcp/9292", now);
+        });
+        now += MAX_DIAL_BACKOFF * 2 * SECOND;
+        assert_eq!(supervisor.targets.len(), 1);
+        assert_eq!(
+            supervisor.next_dial(now),
+            Some("/ip4/127.0.0.1/tcp/9293".to_string())
+        );
+    }
+
+    #[test]
+    fn redialing_stops_once_minimum_is_met() {
+        let mut supervisor = ConnectionSupervisor::new(2);
+        supervisor.add_target("/ip4/127.0.0.1/tcp/9292");
+        supervisor.connected("peer_1");
+        assert!(supervisor.next_dial(0).is_some());
+
+        supervisor.connected("peer_2");
+        let later = MAX_DIAL_BACKOFF * 2 * SECOND;
+        assert_eq!(supervisor.next_dial(later), None);
+
+        supervisor.disconnected("peer_1");
+        assert!(supervisor.next_dial(later).is_some());
+    }
+}
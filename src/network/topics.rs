@@ -0,0 +1,527 @@
+use crate::network::message_types::MessageType;
+use crate::network::node::NodeAuth;
+use sha256::digest_bytes;
+use std::collections::{HashMap, VecDeque};
+
+pub const NETWORK_ID: &str = "test-net";
+// Every message used to be published to a single topic named after the network.
+pub const LEGACY_TOPIC: &str = "test-net";
+// How many of the messages received last are remembered to drop the copies
+// of them that arrive on another topic.
+pub const MAX_SEEN_MESSAGES: usize = 10_000;
+
+/// The gossipsub topics messages are split across, so that nodes only
+/// download the classes of messages they need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GossipTopic {
+    Blocks,
+    Txns,
+    State,
+    Claims,
+}
+
+impl GossipTopic {
+    pub fn all() -> Vec<GossipTopic> {
+        vec![
+            GossipTopic::Blocks,
+            GossipTopic::Txns,
+            GossipTopic::State,
+            GossipTopic::Claims,
+        ]
+    }
+
+    pub fn suffix(&self) -> &str {
+        match self {
+            Self::Blocks => "blocks",
+            Self::Txns => "txns",
+            Self::State => "state",
+            Self::Claims => "claims",
+        }
+    }
+
+    pub fn name(&self, network_id: &str) -> String {
+        format!("{}-{}", network_id, self.suffix())
+    }
+
+    /// Returns the topic a message must be published on.
+    pub fn for_message(message: &MessageType) -> GossipTopic {
+        match message {
+            MessageType::TxnMessage { .. }
+            | MessageType::ForwardedTxnMessage { .. }
+            | MessageType::TxnAckMessage { .. }
+            | MessageType::TxnValidatorMessage { .. }
+            | MessageType::PoolTxnIdsMessage { .. } => GossipTopic::Txns,
+            MessageType::BlockMessage { .. }
+            | MessageType::CompactBlockMessage { .. }
+            | MessageType::GetTxnsMessage { .. }
+            | MessageType::TxnsResponseMessage { .. }
+            | MessageType::GetPeersMessage { .. }
+            | MessageType::PeersResponseMessage { .. }
+            | MessageType::GetBlockRangeMessage { .. }
+            | MessageType::BlockRangeMessage { .. }
+            | MessageType::MessageChunk { .. }
+            | MessageType::BalanceProofRequest { .. }
+            | MessageType::BalanceProofResponse { .. }
+            | MessageType::NeedBlocksMessage { .. }
+            | MessageType::NeedBlockMessage { .. }
+            | MessageType::MissingBlock { .. }
+            | MessageType::InvalidBlockMessage { .. }
+            | MessageType::NeedGenesisBlock { .. }
+            | MessageType::MissingGenesis { .. }
+            | MessageType::DisconnectMessage { .. } => GossipTopic::Blocks,
+            MessageType::NetworkStateDataBaseMessage { .. }
+            | MessageType::BlockChunkMessage { .. }
+            | MessageType::GetNetworkStateMessage { .. }
+            | MessageType::StateComponentChunkMessage { .. }
+            | MessageType::StateRequestDeclined { .. } => GossipTopic::State,
+            MessageType::ClaimMessage { .. } | MessageType::ClaimAbandonedMessage { .. } => {
+                GossipTopic::Claims
+            }
+        }
+    }
+
+    /// The peer a message answering its request is addressed to. It's
+    /// published on that peer's own topic instead of being gossiped to every
+    /// node.
+    pub fn recipient(message: &MessageType) -> Option<&str> {
+        match message {
+            MessageType::BalanceProofResponse { requestor, .. } => Some(requestor),
+            MessageType::PoolTxnIdsMessage { requested_from, .. } => Some(requested_from),
+            _ => None,
+        }
+    }
+
+    /// The topic only the peer `peer_id` subscribes to.
+    pub fn peer_topic(network_id: &str, peer_id: &str) -> String {
+        format!("{}-peer-{}", network_id, peer_id)
+    }
+
+    /// The topics a node of the given type subscribes to. Light nodes don't
+    /// serve or sync full state, so they skip the state topic.
+    pub fn subscriptions(node_type: &NodeAuth, network_id: &str, legacy: bool) -> Vec<String> {
+        let mut topics: Vec<String> = GossipTopic::all()
+            .iter()
+            .filter(|topic| match node_type {
+                NodeAuth::Light | NodeAuth::UltraLight => **topic != GossipTopic::State,
+                _ => true,
+            })
+            .map(|topic| topic.name(network_id))
+            .collect();
+
+        if legacy {
+            topics.push(LEGACY_TOPIC.to_string());
+        }
+
+        topics
+    }
+}
+
+/// The names of the topics a node publishes and subscribes to. Nodes started
+/// with a topic namespace only share topics with nodes in the same namespace,
+/// so separate networks can run side by side on one host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicNames {
+    // The network id, behind the namespace if there is one.
+    pub network_id: String,
+    // Whether the legacy topic is subscribed and published to as well, while
+    // older nodes are still on the network. It isn't namespaced, so it's left
+    // out under a namespace.
+    pub legacy: bool,
+}
+
+/// The digests of the messages received lately and the topic each arrived on
+/// first. A node on the legacy topic gets every message from the nodes that
+/// publish to both topics twice, the copy on the other topic is dropped.
+#[derive(Debug, Default)]
+pub struct SeenMessages {
+    topics: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+impl TopicNames {
+    pub fn new(namespace: Option<&str>, network_id: &str, legacy: bool) -> TopicNames {
+        match namespace {
+            Some(namespace) => TopicNames {
+                network_id: format!("{}/{}", namespace, network_id),
+                legacy: false,
+            },
+            None => TopicNames {
+                network_id: network_id.to_string(),
+                legacy,
+            },
+        }
+    }
+
+    pub fn name(&self, topic: GossipTopic) -> String {
+        topic.name(&self.network_id)
+    }
+
+    /// The name of the topic a message must be published on.
+    pub fn for_message(&self, message: &MessageType) -> String {
+        match GossipTopic::recipient(message) {
+            Some(peer_id) => self.peer_topic(peer_id),
+            None => self.name(GossipTopic::for_message(message)),
+        }
+    }
+
+    pub fn peer_topic(&self, peer_id: &str) -> String {
+        GossipTopic::peer_topic(&self.network_id, peer_id)
+    }
+
+    pub fn subscriptions(&self, node_type: &NodeAuth) -> Vec<String> {
+        GossipTopic::subscriptions(node_type, &self.network_id, self.legacy)
+    }
+
+    pub fn accepts(&self, topic: &str, message: &MessageType) -> bool {
+        accepts(topic, message, &self.network_id, self.legacy)
+    }
+}
+
+/// The topics of this node's network, without a namespace or the legacy topic.
+impl Default for TopicNames {
+    fn default() -> TopicNames {
+        TopicNames::new(None, NETWORK_ID, false)
+    }
+}
+
+impl SeenMessages {
+    pub fn new() -> SeenMessages {
+        SeenMessages::default()
+    }
+
+    /// Records a message received on `topic`, returning false if the same
+    /// message was already received on another topic. A message resent on
+    /// the topic it first arrived on is let through.
+    pub fn accept(&mut self, topic: &str, data: &[u8]) -> bool {
+        let digest = digest_bytes(data);
+        if let Some(first_topic) = self.topics.get(&digest) {
+            return first_topic == topic;
+        }
+
+        self.topics.insert(digest.clone(), topic.to_string());
+        self.order.push_back(digest);
+        if self.order.len() > MAX_SEEN_MESSAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.topics.remove(&oldest);
+            }
+        }
+
+        true
+    }
+}
+
+/// Whether a message received on `topic` arrived on the topic it is routed to.
+/// Messages published on the wrong topic are ignored, a message addressed to a
+/// peer is only taken on that peer's topic.
+pub fn accepts(topic: &str, message: &MessageType, network_id: &str, legacy: bool) -> bool {
+    if let Some(peer_id) = GossipTopic::recipient(message) {
+        return topic == GossipTopic::peer_topic(network_id, peer_id);
+    }
+    if legacy && topic == LEGACY_TOPIC {
+        return true;
+    }
+
+    GossipTopic::for_message(message).name(network_id) == topic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance_proof::CommittedLedger;
+    use crate::blockchain::{DeclineReason, InvalidBlockErrorReason, StateComponent};
+    use crate::claim::Claim;
+    use crate::network::compact_block::CompactBlock;
+    use crate::network::message_types::StateBlock;
+    use crate::test_utils::test_block;
+    use crate::txn::Txn;
+    use crate::validator::TxnValidator;
+    use ritelinked::LinkedHashMap;
+
+    fn every_message() -> Vec<MessageType> {
+        let sender_id = "sender".to_string();
+        let txn = Txn::unsigned_transfer(1, 0);
+        let claim = Claim::new("pubkey".to_string(), "address".to_string(), 1);
+        vec![
+            MessageType::NetworkStateDataBaseMessage {
+                object: StateBlock(0),
+                data: vec![],
+                chunk_number: 1,
+                total_chunks: 1,
+                last_block: 0,
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::TxnMessage {
+                txn: txn.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::ForwardedTxnMessage {
+                txn: txn.clone(),
+                hops: 1,
+                sender_id: sender_id.clone(),
+            },
+            MessageType::TxnAckMessage {
+                txn_validator: TxnValidator {
+                    pubkey: "pubkey".to_string(),
+                    vote: true,
+                    txn: txn.clone(),
+                    signature: String::new(),
+                },
+                sender_id: sender_id.clone(),
+            },
+            MessageType::TxnValidatorMessage {
+                txn_validator: TxnValidator {
+                    pubkey: "pubkey".to_string(),
+                    vote: true,
+                    txn: txn.clone(),
+                    signature: String::new(),
+                },
+                sender_id: sender_id.clone(),
+            },
+            MessageType::BlockMessage {
+                block: test_block(0, "hash"),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::BlockChunkMessage {
+                sender_id: sender_id.clone(),
+                requestor: sender_id.clone(),
+                block_height: 0,
+                chunk_number: 1,
+                total_chunks: 1,
+                data: vec![],
+            },
+            MessageType::ClaimMessage {
+                claim: claim.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::NeedBlocksMessage {
+                blocks_needed: vec![0],
+                sender_id: sender_id.clone(),
+            },
+            MessageType::NeedBlockMessage {
+                block_last_hash: String::new(),
+                sender_id: sender_id.clone(),
+                requested_from: sender_id.clone(),
+            },
+            MessageType::MissingBlock {
+                block: test_block(0, "hash"),
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::GetNetworkStateMessage {
+                sender_id: sender_id.clone(),
+                requested_from: sender_id.clone(),
+                requestor_node_type: NodeAuth::Full,
+                lowest_block: 0,
+                component: StateComponent::All,
+            },
+            MessageType::InvalidBlockMessage {
+                block_height: 0,
+                reason: InvalidBlockErrorReason::General,
+                miner_id: sender_id.clone(),
+                sender_id: sender_id.clone(),
+                tip_height: None,
+            },
+            MessageType::DisconnectMessage {
+                sender_id: sender_id.clone(),
+                pubkey: "pubkey".to_string(),
+            },
+            MessageType::NeedGenesisBlock {
+                sender_id: sender_id.clone(),
+                requested_from: sender_id.clone(),
+            },
+            MessageType::MissingGenesis {
+                block: test_block(0, "hash"),
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::StateComponentChunkMessage {
+                data: vec![],
+                chunk_number: 1,
+                total_chunks: 1,
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::StateRequestDeclined {
+                reason: DeclineReason::NotFullNode,
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+                public_key: vec![],
+                signature: vec![],
+            },
+            MessageType::CompactBlockMessage {
+                block: CompactBlock::from_block(&test_block(0, "hash")),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::GetTxnsMessage {
+                txn_ids: vec![txn.txn_id.clone()],
+                sender_id: sender_id.clone(),
+                requested_from: sender_id.clone(),
+            },
+            MessageType::TxnsResponseMessage {
+                txns: vec![txn],
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::GetPeersMessage {
+                sender_id: sender_id.clone(),
+                requested_from: sender_id.clone(),
+            },
+            MessageType::PoolTxnIdsMessage {
+                txn_ids: vec![],
+                sender_id: sender_id.clone(),
+                requested_from: sender_id.clone(),
+            },
+            MessageType::PeersResponseMessage {
+                peers: vec![],
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::GetBlockRangeMessage {
+                from_height: 0,
+                to_height: 2,
+                requestor: sender_id.clone(),
+                requested_from: sender_id.clone(),
+            },
+            MessageType::BlockRangeMessage {
+                blocks: vec![test_block(0, "hash")],
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::MessageChunk {
+                message_id: String::new(),
+                chunk_number: 1,
+                total_chunks: 1,
+                data: vec![],
+                sender_id: sender_id.clone(),
+            },
+            MessageType::BalanceProofRequest {
+                address: claim.address.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::BalanceProofResponse {
+                proof: CommittedLedger {
+                    block_height: 0,
+                    credits: LinkedHashMap::new(),
+                    debits: LinkedHashMap::new(),
+                }
+                .prove(&claim.address),
+                requestor: sender_id.clone(),
+                sender_id: sender_id.clone(),
+            },
+            MessageType::ClaimAbandonedMessage { claim, sender_id },
+        ]
+    }
+
+    #[test]
+    fn every_message_routes_to_exactly_one_topic() {
+        every_message().iter().for_each(|message| {
+            let n_topics = GossipTopic::all()
+                .iter()
+                .filter(|topic| accepts(&topic.name(NETWORK_ID), message, NETWORK_ID, false))
+                .count();
+            let expected = match GossipTopic::recipient(message) {
+                Some(_) => 0,
+                None => 1,
+            };
+            assert_eq!(n_topics, expected, "{:?}", message);
+        });
+    }
+
+    #[test]
+    fn addressed_messages_go_to_the_recipients_topic() {
+        let message = MessageType::BalanceProofResponse {
+            proof: CommittedLedger {
+                block_height: 1,
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+            }
+            .prove("address"),
+            requestor: "requestor".to_string(),
+            sender_id: "sender".to_string(),
+        };
+        let topics = TopicNames::new(None, NETWORK_ID, true);
+        let topic = topics.for_message(&message);
+        assert_eq!(topic, topics.peer_topic("requestor"));
+        assert!(topics.accepts(&topic, &message));
+        assert!(!topics.accepts(&topics.peer_topic("other"), &message));
+        assert!(!topics.accepts(&topics.name(GossipTopic::Blocks), &message));
+        assert!(!topics.accepts(LEGACY_TOPIC, &message));
+    }
+
+    #[test]
+    fn light_nodes_skip_state_topic() {
+        let state_topic = GossipTopic::State.name(NETWORK_ID);
+        let light = GossipTopic::subscriptions(&NodeAuth::Light, NETWORK_ID, false);
+        let full = GossipTopic::subscriptions(&NodeAuth::Full, NETWORK_ID, false);
+        assert!(!light.contains(&state_topic));
+        assert!(light.contains(&GossipTopic::Txns.name(NETWORK_ID)));
+        assert!(full.contains(&state_topic));
+        assert!(!full.contains(&LEGACY_TOPIC.to_string()));
+    }
+
+    #[test]
+    fn messages_on_wrong_topic_are_ignored() {
+        let message = MessageType::TxnMessage {
+            txn: Txn::unsigned_transfer(1, 0),
+            sender_id: "sender".to_string(),
+        };
+        let blocks_topic = GossipTopic::Blocks.name(NETWORK_ID);
+        assert!(!accepts(&blocks_topic, &message, NETWORK_ID, false));
+        assert!(accepts(
+            &GossipTopic::Txns.name(NETWORK_ID),
+            &message,
+            NETWORK_ID,
+            false
+        ));
+        assert!(!accepts(LEGACY_TOPIC, &message, NETWORK_ID, false));
+        assert!(accepts(LEGACY_TOPIC, &message, NETWORK_ID, true));
+    }
+
+    #[test]
+    fn namespaced_topics_are_named_after_the_namespace_and_network() {
+        let message = MessageType::TxnMessage {
+            txn: Txn::unsigned_transfer(1, 0),
+            sender_id: "sender".to_string(),
+        };
+        let default = TopicNames::default();
+        assert_eq!(
+            default.for_message(&message),
+            format!("{}-txns", NETWORK_ID)
+        );
+        assert!(!default.legacy);
+        assert!(!default
+            .subscriptions(&NodeAuth::Full)
+            .contains(&LEGACY_TOPIC.to_string()));
+
+        let alpha = TopicNames::new(Some("alpha"), NETWORK_ID, true);
+        let beta = TopicNames::new(Some("beta"), NETWORK_ID, true);
+        assert_eq!(
+            alpha.name(GossipTopic::Blocks),
+            format!("alpha/{}-blocks", NETWORK_ID)
+        );
+        assert!(!alpha
+            .subscriptions(&NodeAuth::Full)
+            .contains(&LEGACY_TOPIC.to_string()));
+        assert!(alpha.accepts(&alpha.for_message(&message), &message));
+        assert!(!alpha.accepts(&beta.for_message(&message), &message));
+        assert!(!alpha.accepts(&default.for_message(&message), &message));
+        assert!(!alpha.accepts(LEGACY_TOPIC, &message));
+    }
+
+    #[test]
+    fn copies_of_a_message_on_another_topic_are_dropped() {
+        let mut seen = SeenMessages::new();
+        let txns_topic = GossipTopic::Txns.name(NETWORK_ID);
+        assert!(seen.accept(&txns_topic, b"message"));
+        assert!(!seen.accept(LEGACY_TOPIC, b"message"));
+        assert!(seen.accept(&txns_topic, b"message"));
+        assert!(seen.accept(LEGACY_TOPIC, b"other message"));
+
+        (0..MAX_SEEN_MESSAGES).for_each(|n| {
+            seen.accept(&txns_topic, n.to_string().as_bytes());
+        });
+        assert!(seen.accept(LEGACY_TOPIC, b"message"));
+        assert!(seen.order.len() <= MAX_SEEN_MESSAGES);
+    }
+}
@@ -10,7 +10,9 @@
 // if it's 60% of casted votes, approve the block and confirm the state at the
 // current block height. If not, require the proposing node to update their
 // local state before reproposing the block.
+use crate::miner::ConfirmationStatus;
 use ritelinked::LinkedHashMap;
+
 #[derive(Debug, Clone)]
 pub struct BallotBox {
     pub proposals: LinkedHashMap<String, LinkedHashMap<String, u128>>,
@@ -58,3 +60,167 @@ impl BallotBox {
         self.proposal_results.insert(proposal_id, result);
     }
 }
+
+/// The yes and no votes validators cast on a txn or a block, by pubkey. Each
+/// validator gets one vote, a second one contradicting the first is
+/// equivocation: it isn't counted and the validator is recorded instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VoteTally {
+    votes: LinkedHashMap<String, bool>,
+    equivocators: Vec<String>,
+}
+
+/// What recording a vote did to the tally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteOutcome {
+    Counted,
+    // the validator already cast the same vote
+    Duplicate,
+    // the validator already cast the opposite vote
+    Equivocation,
+}
+
+impl VoteTally {
+    pub fn new() -> VoteTally {
+        VoteTally::default()
+    }
+
+    /// A tally of votes already collected, such as a txn's validators.
+    pub fn from_votes<'a, I>(votes: I) -> VoteTally
+    where
+        I: IntoIterator<Item = (&'a String, &'a bool)>,
+    {
+        let mut tally = VoteTally::new();
+        votes.into_iter().for_each(|(pubkey, vote)| {
+            tally.record(pubkey, *vote);
+        });
+
+        tally
+    }
+
+    pub fn record(&mut self, pubkey: &str, vote: bool) -> VoteOutcome {
+        match self.votes.get(pubkey) {
+            None => {
+                self.votes.insert(pubkey.to_string(), vote);
+                VoteOutcome::Counted
+            }
+            Some(cast) if *cast == vote => VoteOutcome::Duplicate,
+            Some(_) => {
+                if !self.equivocators.iter().any(|known| known == pubkey) {
+                    self.equivocators.push(pubkey.to_string());
+                }
+                VoteOutcome::Equivocation
+            }
+        }
+    }
+
+    pub fn yes(&self) -> usize {
+        self.votes.values().filter(|vote| **vote).count()
+    }
+
+    pub fn no(&self) -> usize {
+        self.votes.len() - self.yes()
+    }
+
+    /// The pubkeys that cast `vote`.
+    pub fn voters(&self, vote: bool) -> Vec<String> {
+        self.votes
+            .iter()
+            .filter(|(_, cast)| **cast == vote)
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect()
+    }
+
+    /// The validators that cast contradicting votes.
+    pub fn equivocators(&self) -> &[String] {
+        &self.equivocators
+    }
+
+    /// The share of the active validators that voted yes, 0 with none active.
+    pub fn ratio(&self, active_validators: usize) -> f64 {
+        if active_validators == 0 {
+            return 0f64;
+        }
+
+        self.yes() as f64 / active_validators as f64
+    }
+
+    /// Final once there are `required` yes votes, rejected once too few of
+    /// the validators, the one counting included, are left to give them.
+    pub fn status(&self, required: usize, active_validators: usize) -> ConfirmationStatus {
+        if self.yes() >= required {
+            ConfirmationStatus::Confirmed
+        } else if self.no() > (active_validators + 1).saturating_sub(required) {
+            ConfirmationStatus::Rejected
+        } else {
+            ConfirmationStatus::Unconfirmed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tally_counts_one_vote_per_validator() {
+        let mut tally = VoteTally::new();
+        assert_eq!(tally.record("a", true), VoteOutcome::Counted);
+        assert_eq!(tally.record("b", true), VoteOutcome::Counted);
+        assert_eq!(tally.record("c", false), VoteOutcome::Counted);
+        assert_eq!(tally.record("a", true), VoteOutcome::Duplicate);
+        assert_eq!((tally.yes(), tally.no()), (2, 1));
+        assert_eq!(tally.voters(true), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(tally.voters(false), vec!["c".to_string()]);
+
+        let mut votes = LinkedHashMap::new();
+        votes.insert("a".to_string(), true);
+        votes.insert("b".to_string(), true);
+        votes.insert("c".to_string(), false);
+        assert_eq!(VoteTally::from_votes(&votes), tally);
+    }
+
+    #[test]
+    fn ratio_is_taken_against_the_active_set() {
+        let mut tally = VoteTally::new();
+        tally.record("a", true);
+        tally.record("b", true);
+        tally.record("c", false);
+        assert!((tally.ratio(5) - 0.4).abs() < 1e-9);
+        assert!((tally.ratio(2) - 1.0).abs() < 1e-9);
+        assert_eq!(tally.ratio(0), 0f64);
+
+        // 3 of 5 needed: two yes votes aren't final, a third is.
+        assert_eq!(tally.status(3, 5), ConfirmationStatus::Unconfirmed);
+        tally.record("d", true);
+        assert_eq!(tally.status(3, 5), ConfirmationStatus::Confirmed);
+    }
+
+    #[test]
+    fn tally_is_rejected_once_quorum_is_out_of_reach() {
+        let mut tally = VoteTally::new();
+        tally.record("a", false);
+        tally.record("b", false);
+        tally.record("c", false);
+        assert_eq!(tally.status(3, 5), ConfirmationStatus::Unconfirmed);
+        tally.record("d", false);
+        assert_eq!(tally.status(3, 5), ConfirmationStatus::Rejected);
+    }
+
+    #[test]
+    fn contradicting_votes_are_equivocation() {
+        let mut tally = VoteTally::new();
+        tally.record("a", true);
+        tally.record("b", false);
+        assert_eq!(tally.record("a", false), VoteOutcome::Equivocation);
+        assert_eq!(tally.record("a", false), VoteOutcome::Equivocation);
+        assert_eq!(tally.record("b", true), VoteOutcome::Equivocation);
+
+        // The first vote stands and each validator is recorded once.
+        assert_eq!((tally.yes(), tally.no()), (1, 1));
+        assert_eq!(tally.equivocators(), &["a".to_string(), "b".to_string()]);
+        assert!(VoteTally::from_votes(&LinkedHashMap::new())
+            .equivocators()
+            .is_empty());
+    }
+}
@@ -0,0 +1,457 @@
+use crate::balance_proof::{BalanceProof, EntryKind, EntryProof, LedgerLeaf};
+use crate::block::Block;
+use crate::blockchain::{DeclineReason, InvalidBlockErrorReason, StateComponent};
+use crate::claim::{Claim, ClaimSigner, ClaimTransfer};
+use crate::header::BlockHeader;
+use crate::network::compact_block::CompactBlock;
+use crate::network::message_types::{MessageType, StateBlock};
+use crate::network::node::NodeAuth;
+use crate::reward::{Category, Reward};
+use crate::state::{Components, Ledger};
+use crate::token::TokenLedger;
+use crate::txn::{Txn, TxnKind};
+use crate::validator::TxnValidator;
+use ritelinked::LinkedHashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// The wire format is whatever serde_json makes of MessageType and the chain
+// data it carries, so renaming a field or changing its type breaks peers on
+// another version without any code failing to compile. Every golden vector in
+// data/wire has to deserialize into the current types and serialize back to
+// the same bytes.
+//
+// A deliberate format change is made by running the tests with
+// VRRB_REGENERATE_WIRE_VECTORS set, which rewrites the vectors from the
+// samples below, so the change shows up in the vectors under review.
+//
+// None of the wire types deny unknown fields. A node has to keep reading
+// what a newer node sends after it adds a field, and fields are added with
+// #[serde(default)] so the newer node can read what an older one sends.
+const REGENERATE_VAR: &str = "VRRB_REGENERATE_WIRE_VECTORS";
+const UNKNOWN_FIELD: &str = "added_by_a_newer_node";
+
+fn vectors_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("data")
+        .join("wire")
+}
+
+fn vector_path(name: &str) -> PathBuf {
+    vectors_dir().join(format!("{}.json", name))
+}
+
+// Rewrites the vector from the sample when regenerating, otherwise checks the
+// vector round trips through T and returns it.
+fn check_vector<T: Serialize + DeserializeOwned>(name: &str, sample: &T) -> Value {
+    let path = vector_path(name);
+    if std::env::var(REGENERATE_VAR).is_ok() {
+        let json = serde_json::to_string(sample).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("{}\n", json)).unwrap();
+    }
+
+    let golden =
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("missing wire vector {}: {}", name, e));
+    let golden = golden.trim_end();
+    let decoded: T = serde_json::from_str(golden)
+        .unwrap_or_else(|e| panic!("wire vector {} no longer deserializes: {}", name, e));
+    assert_eq!(
+        serde_json::to_string(&decoded).unwrap(),
+        golden,
+        "wire vector {} serializes differently",
+        name
+    );
+
+    serde_json::from_str(golden).unwrap()
+}
+
+// Adds a field the current types don't know to `object` and checks that the
+// vector still deserializes into T.
+fn check_unknown_field<T: DeserializeOwned>(name: &str, mut vector: Value, object: &[&str]) {
+    let mut fields = &mut vector;
+    for key in object {
+        fields = fields.get_mut(*key).unwrap();
+    }
+    fields
+        .as_object_mut()
+        .unwrap()
+        .insert(UNKNOWN_FIELD.to_string(), Value::from(1));
+    if let Err(e) = serde_json::from_value::<T>(vector) {
+        panic!("wire vector {} with an unknown field: {}", name, e);
+    }
+}
+
+fn claim() -> Claim {
+    Claim {
+        pubkey: "pubkey".to_string(),
+        address: "address".to_string(),
+        hash: "claim_hash".to_string(),
+        nonce: 1,
+        eligible: true,
+    }
+}
+
+fn reward(miner: Option<String>) -> Reward {
+    Reward {
+        miner,
+        category: Category::Flake(Some(1)),
+        amount: 1,
+    }
+}
+
+fn header() -> BlockHeader {
+    BlockHeader {
+        last_hash: "last_hash".to_string(),
+        block_nonce: 1,
+        next_block_nonce: 2,
+        block_height: 3,
+        timestamp: 4,
+        txn_hash: "txn_hash".to_string(),
+        block_cost: 1000,
+        claim: claim(),
+        claim_map_hash: Some("claim_map_hash".to_string()),
+        block_reward: reward(Some("address".to_string())),
+        next_block_reward: reward(None),
+        neighbor_hash: None,
+        nonce_epoch: 0,
+        ledger_root: Some("ledger_root".to_string()),
+        signature: "header_signature".to_string(),
+    }
+}
+
+fn balance_proof() -> BalanceProof {
+    let leaf = |kind, index| LedgerLeaf {
+        kind,
+        address: "address".to_string(),
+        amount: 10,
+        index,
+        branch: vec!["sibling_hash".to_string()],
+    };
+    BalanceProof {
+        address: "address".to_string(),
+        block_height: 3,
+        leaf_count: 2,
+        root: "tree_root".to_string(),
+        credit: EntryProof::Present(leaf(EntryKind::Credit, 0)),
+        debit: EntryProof::Absent {
+            before: Some(leaf(EntryKind::Credit, 0)),
+            after: None,
+        },
+    }
+}
+
+fn txn() -> Txn {
+    let mut validators = HashMap::new();
+    validators.insert("validator".to_string(), true);
+    let mut validator_signatures = HashMap::new();
+    validator_signatures.insert("validator".to_string(), "vote_signature".to_string());
+    Txn {
+        txn_id: "txn_id".to_string(),
+        kind: TxnKind::Transfer,
+        txn_timestamp: 5,
+        sender_address: "sender".to_string(),
+        sender_public_key: "sender_pubkey".to_string(),
+        receiver_address: "receiver".to_string(),
+        txn_token: None,
+        txn_amount: 10,
+        txn_fee: 1,
+        txn_data: None,
+        txn_payload: "txn_payload".to_string(),
+        txn_signature: "txn_signature".to_string(),
+        chain_id: "chain_id".to_string(),
+        validators,
+        validator_signatures,
+        nonce: 1,
+    }
+}
+
+fn block() -> Block {
+    let mut txns = LinkedHashMap::new();
+    txns.insert("txn_id".to_string(), txn());
+    let mut claims = LinkedHashMap::new();
+    claims.insert("pubkey".to_string(), claim());
+    Block {
+        header: header(),
+        neighbors: None,
+        height: 3,
+        txns,
+        claims,
+        hash: "block_hash".to_string(),
+        received_at: None,
+        received_from: None,
+        abandoned_claim: None,
+    }
+}
+
+fn txn_validator() -> TxnValidator {
+    TxnValidator {
+        pubkey: "validator".to_string(),
+        vote: true,
+        txn: txn(),
+        signature: "vote_signature".to_string(),
+    }
+}
+
+fn claim_transfer() -> ClaimTransfer {
+    ClaimTransfer {
+        claim_pubkey: "pubkey".to_string(),
+        from: ClaimSigner {
+            address: "address".to_string(),
+            pubkey: "pubkey".to_string(),
+            signature: "from_signature".to_string(),
+        },
+        to: ClaimSigner {
+            address: "buyer".to_string(),
+            pubkey: "buyer_pubkey".to_string(),
+            signature: "to_signature".to_string(),
+        },
+        price: 100,
+    }
+}
+
+fn components() -> Components {
+    Components {
+        genesis: Some(vec![1, 2, 3]),
+        child: None,
+        parent: None,
+        blockchain: None,
+        ledger: Some(vec![4, 5]),
+        network_state: None,
+        archive: None,
+    }
+}
+
+fn ledger() -> Ledger {
+    let mut credits = LinkedHashMap::new();
+    credits.insert("address".to_string(), 10);
+    let mut debits = LinkedHashMap::new();
+    debits.insert("sender".to_string(), 5);
+    let mut claims = LinkedHashMap::new();
+    claims.insert("pubkey".to_string(), claim());
+    Ledger {
+        credits,
+        debits,
+        claims,
+        tokens: TokenLedger::default(),
+    }
+}
+
+// One of every MessageType variant.
+fn messages() -> Vec<MessageType> {
+    let sender_id = "sender".to_string();
+    let requestor = "requestor".to_string();
+    let requested_from = "peer".to_string();
+    vec![
+        MessageType::NetworkStateDataBaseMessage {
+            object: StateBlock(3),
+            data: vec![1, 2],
+            chunk_number: 1,
+            total_chunks: 2,
+            last_block: 3,
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::TxnMessage {
+            txn: txn(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::ForwardedTxnMessage {
+            txn: txn(),
+            hops: 2,
+            sender_id: sender_id.clone(),
+        },
+        MessageType::TxnAckMessage {
+            txn_validator: txn_validator(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::TxnValidatorMessage {
+            txn_validator: txn_validator(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::BlockMessage {
+            block: block(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::BlockChunkMessage {
+            sender_id: sender_id.clone(),
+            requestor: requestor.clone(),
+            block_height: 3,
+            chunk_number: 1,
+            total_chunks: 2,
+            data: vec![1, 2],
+        },
+        MessageType::ClaimMessage {
+            claim: claim(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::NeedBlocksMessage {
+            blocks_needed: vec![3, 4],
+            sender_id: sender_id.clone(),
+        },
+        MessageType::NeedBlockMessage {
+            block_last_hash: "last_hash".to_string(),
+            sender_id: sender_id.clone(),
+            requested_from: requested_from.clone(),
+        },
+        MessageType::MissingBlock {
+            block: block(),
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::GetNetworkStateMessage {
+            sender_id: sender_id.clone(),
+            requested_from: requested_from.clone(),
+            requestor_node_type: NodeAuth::Full,
+            lowest_block: 3,
+            component: StateComponent::All,
+        },
+        MessageType::InvalidBlockMessage {
+            block_height: 3,
+            reason: InvalidBlockErrorReason::InvalidClaimMapHash,
+            miner_id: "miner".to_string(),
+            sender_id: sender_id.clone(),
+            tip_height: Some(4),
+        },
+        MessageType::DisconnectMessage {
+            sender_id: sender_id.clone(),
+            pubkey: "pubkey".to_string(),
+        },
+        MessageType::NeedGenesisBlock {
+            sender_id: sender_id.clone(),
+            requested_from: requested_from.clone(),
+        },
+        MessageType::MissingGenesis {
+            block: block(),
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::StateComponentChunkMessage {
+            data: vec![1, 2],
+            chunk_number: 1,
+            total_chunks: 2,
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::StateRequestDeclined {
+            reason: DeclineReason::TooLarge,
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+            public_key: vec![],
+            signature: vec![],
+        },
+        MessageType::ClaimAbandonedMessage {
+            claim: claim(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::CompactBlockMessage {
+            block: CompactBlock::from_block(&block()),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::GetTxnsMessage {
+            txn_ids: vec!["txn_id".to_string()],
+            sender_id: sender_id.clone(),
+            requested_from: requested_from.clone(),
+        },
+        MessageType::TxnsResponseMessage {
+            txns: vec![txn()],
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::GetPeersMessage {
+            sender_id: sender_id.clone(),
+            requested_from: requested_from.clone(),
+        },
+        MessageType::PoolTxnIdsMessage {
+            txn_ids: vec!["txn_id".to_string()],
+            sender_id: sender_id.clone(),
+            requested_from: requested_from.clone(),
+        },
+        MessageType::GetBlockRangeMessage {
+            from_height: 3,
+            to_height: 5,
+            requestor: requestor.clone(),
+            requested_from,
+        },
+        MessageType::BlockRangeMessage {
+            blocks: vec![block()],
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::PeersResponseMessage {
+            peers: vec![(
+                "peer_id".to_string(),
+                "/ip4/127.0.0.1/tcp/19292".to_string(),
+            )],
+            requestor: requestor.clone(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::BalanceProofRequest {
+            address: "address".to_string(),
+            sender_id: sender_id.clone(),
+        },
+        MessageType::BalanceProofResponse {
+            proof: balance_proof(),
+            requestor,
+            sender_id: sender_id.clone(),
+        },
+        MessageType::MessageChunk {
+            message_id: "message_id".to_string(),
+            chunk_number: 1,
+            total_chunks: 2,
+            data: vec![1, 2],
+            sender_id,
+        },
+    ]
+}
+
+#[test]
+fn every_message_type_matches_its_golden_vector() {
+    messages().iter().for_each(|message| {
+        let name = format!("messages/{}", message.name());
+        let vector = check_vector(&name, message);
+        check_unknown_field::<MessageType>(&name, vector, &[message.name()]);
+    });
+}
+
+// A vector left behind by a removed variant, or a variant sampled twice,
+// fails here.
+#[test]
+fn message_vectors_are_one_per_sampled_variant() {
+    let mut names: Vec<String> = messages()
+        .iter()
+        .map(|message| message.name().to_string())
+        .collect();
+    names.sort_unstable();
+    let mut vectors: Vec<String> = fs::read_dir(vectors_dir().join("messages"))
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            Some(path.file_stem()?.to_str()?.to_string())
+        })
+        .collect();
+    vectors.sort_unstable();
+    assert_eq!(names, vectors);
+}
+
+#[test]
+fn chain_data_matches_its_golden_vectors() {
+    fn check<T: Serialize + DeserializeOwned>(name: &str, sample: T) {
+        let vector = check_vector(name, &sample);
+        check_unknown_field::<T>(name, vector, &[]);
+    }
+
+    check("Block", block());
+    check("BlockHeader", header());
+    check("Claim", claim());
+    check("ClaimTransfer", claim_transfer());
+    check("CompactBlock", CompactBlock::from_block(&block()));
+    check("Components", components());
+    check("Ledger", ledger());
+    check("Txn", txn());
+    check("TxnValidator", txn_validator());
+}
@@ -0,0 +1,215 @@
+use crate::network::topics::NETWORK_ID;
+use serde::{Deserialize, Serialize};
+use sha256::digest_bytes;
+
+pub const MAINNET_ID: &str = "main-net";
+pub const TESTNET_ID: &str = "test-net";
+pub const LOCAL_NET_ID: &str = "local-net";
+
+// The mainnet values, the other presets override what they need to.
+pub const TOTAL_NUGGETS: u128 = 80000000;
+pub const TOTAL_VEINS: u128 = 1400000;
+pub const TOTAL_MOTHERLODES: u128 = 20000;
+pub const N_BLOCKS_PER_EPOCH: u128 = 16000000;
+pub const NUGGET_FINAL_EPOCH: u128 = 300;
+pub const VEIN_FINAL_EPOCH: u128 = 200;
+pub const MOTHERLODE_FINAL_EPOCH: u128 = 100;
+pub const FLAKE_REWARD_RANGE: (u128, u128) = (1, 8);
+pub const GRAIN_REWARD_RANGE: (u128, u128) = (8, 64);
+pub const NUGGET_REWARD_RANGE: (u128, u128) = (64, 512);
+pub const VEIN_REWARD_RANGE: (u128, u128) = (512, 4096);
+pub const MOTHERLODE_REWARD_RANGE: (u128, u128) = (4096, 32769);
+pub const GENESIS_REWARD: u128 = 200_000_000;
+pub const VALIDATOR_THRESHOLD: u128 = 60;
+pub const ABANDONMENT_TIMEOUT: u128 = 30;
+pub const BLOCK_COST_LIMIT: u64 = 1_000_000;
+// The largest message gossipsub will publish or accept.
+pub const MAX_GOSSIP_SIZE: usize = 2000000;
+// The largest chunk of data sent in a single message. Chunks serialize as JSON
+// arrays of up to 4 characters per byte and messages are hex encoded on top of
+// that, the rest is left for the fields around the chunk. Nodes drop larger
+// chunks, so every node on a network has to chunk the same way.
+pub const MAX_TRANSMIT_SIZE: usize = MAX_GOSSIP_SIZE / 8 - 1024;
+
+// The testnet heights rules added since it launched take effect at, see
+// Activations. Mainnet and local networks start with every rule active.
+pub const TESTNET_BLOCK_COST_HEIGHT: u128 = 400_000;
+pub const TESTNET_EMISSION_SCHEDULE_HEIGHT: u128 = 450_000;
+pub const TESTNET_NEXT_BLOCK_REWARD_HEIGHT: u128 = 500_000;
+pub const TESTNET_LEDGER_ROOT_HEIGHT: u128 = 550_000;
+
+/// The values every node on a network has to agree on to validate blocks the
+/// same way. A node's params travel with its reward state, which every block
+/// is validated against, a state synced from a peer on other params is
+/// refused, and the genesis block commits to params other than the testnet's,
+/// see BlockHeader::genesis_last_hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProtocolParams {
+    pub total_nuggets: u128,
+    pub total_veins: u128,
+    pub total_motherlodes: u128,
+    pub blocks_per_epoch: u128,
+    pub nugget_final_epoch: u128,
+    pub vein_final_epoch: u128,
+    pub motherlode_final_epoch: u128,
+    pub flake_reward_range: (u128, u128),
+    pub grain_reward_range: (u128, u128),
+    pub nugget_reward_range: (u128, u128),
+    pub vein_reward_range: (u128, u128),
+    pub motherlode_reward_range: (u128, u128),
+    pub genesis_reward: u128,
+    // Percent of the active validators whose votes confirm a txn or abandon a
+    // claim. Kept whole so the params hash the same on every node.
+    pub validator_threshold: u128,
+    // Seconds without a block before miners vote the claim that should have
+    // mined it abandoned.
+    pub abandonment_timeout: u128,
+    pub block_cost_limit: u64,
+}
+
+impl ProtocolParams {
+    pub fn mainnet() -> ProtocolParams {
+        ProtocolParams {
+            total_nuggets: TOTAL_NUGGETS,
+            total_veins: TOTAL_VEINS,
+            total_motherlodes: TOTAL_MOTHERLODES,
+            blocks_per_epoch: N_BLOCKS_PER_EPOCH,
+            nugget_final_epoch: NUGGET_FINAL_EPOCH,
+            vein_final_epoch: VEIN_FINAL_EPOCH,
+            motherlode_final_epoch: MOTHERLODE_FINAL_EPOCH,
+            flake_reward_range: FLAKE_REWARD_RANGE,
+            grain_reward_range: GRAIN_REWARD_RANGE,
+            nugget_reward_range: NUGGET_REWARD_RANGE,
+            vein_reward_range: VEIN_REWARD_RANGE,
+            motherlode_reward_range: MOTHERLODE_REWARD_RANGE,
+            genesis_reward: GENESIS_REWARD,
+            validator_threshold: VALIDATOR_THRESHOLD,
+            abandonment_timeout: ABANDONMENT_TIMEOUT,
+            block_cost_limit: BLOCK_COST_LIMIT,
+        }
+    }
+
+    /// The public testnet rehearses mainnet, it runs the same schedule.
+    pub fn testnet() -> ProtocolParams {
+        ProtocolParams::mainnet()
+    }
+
+    /// Epochs of 100 blocks and a supply to match, so that a local network
+    /// goes through several epochs, and claims are abandoned sooner.
+    pub fn local() -> ProtocolParams {
+        ProtocolParams {
+            total_nuggets: 500,
+            total_veins: 50,
+            total_motherlodes: 20,
+            blocks_per_epoch: 100,
+            nugget_final_epoch: 30,
+            vein_final_epoch: 20,
+            motherlode_final_epoch: 10,
+            abandonment_timeout: 10,
+            ..ProtocolParams::mainnet()
+        }
+    }
+
+    /// The preset of the network `network_id`, None for an unknown network.
+    pub fn for_network(network_id: &str) -> Option<ProtocolParams> {
+        match network_id {
+            MAINNET_ID => Some(ProtocolParams::mainnet()),
+            TESTNET_ID => Some(ProtocolParams::testnet()),
+            LOCAL_NET_ID => Some(ProtocolParams::local()),
+            _ => None,
+        }
+    }
+
+    /// The validator threshold as a share of the active validators.
+    pub fn validator_share(&self) -> f64 {
+        self.validator_threshold as f64 / 100f64
+    }
+
+    pub fn hash(&self) -> String {
+        digest_bytes(serde_json::to_string(self).unwrap().as_bytes())
+    }
+}
+
+/// The params of the network this node is built for.
+impl Default for ProtocolParams {
+    fn default() -> ProtocolParams {
+        ProtocolParams::for_network(NETWORK_ID).unwrap_or_else(ProtocolParams::testnet)
+    }
+}
+
+/// The heights at which validation rules added after a network launched take
+/// effect. Blocks below a rule's height were mined without it and are still
+/// validated without it, so the chain replays from genesis. These aren't part
+/// of ProtocolParams, adding a rule mustn't change the params hash committed
+/// in the genesis block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activations {
+    // Headers commit to the block cost, which is held to the block cost limit.
+    pub block_cost: u128,
+    // Epoch emissions are worked out by `reward::epoch_emissions`, which
+    // decays every category at its own rate and fills the epoch. Before it
+    // every category past the first epoch decayed at the nuggets' rate and
+    // rounding left blocks of the epoch unrewarded. Only consulted at genesis
+    // and when an epoch turns over.
+    pub emission_schedule: u128,
+    // The reward a block sets for the next one is checked against the
+    // category and amount derived from the ledger's reward state and the
+    // reward seed. Before it miners drew the category themselves.
+    pub next_block_reward: u128,
+    // Headers commit to the root of the ledger the block is applied to, which
+    // light clients check balance proofs against.
+    pub ledger_root: u128,
+}
+
+impl Activations {
+    /// Every rule active from genesis.
+    pub fn all() -> Activations {
+        Activations {
+            block_cost: 0,
+            emission_schedule: 0,
+            next_block_reward: 0,
+            ledger_root: 0,
+        }
+    }
+
+    pub fn mainnet() -> Activations {
+        Activations::all()
+    }
+
+    pub fn testnet() -> Activations {
+        Activations {
+            block_cost: TESTNET_BLOCK_COST_HEIGHT,
+            emission_schedule: TESTNET_EMISSION_SCHEDULE_HEIGHT,
+            next_block_reward: TESTNET_NEXT_BLOCK_REWARD_HEIGHT,
+            ledger_root: TESTNET_LEDGER_ROOT_HEIGHT,
+        }
+    }
+
+    pub fn local() -> Activations {
+        Activations::all()
+    }
+
+    /// The activations of the network `network_id`, None for an unknown
+    /// network.
+    pub fn for_network(network_id: &str) -> Option<Activations> {
+        match network_id {
+            MAINNET_ID => Some(Activations::mainnet()),
+            TESTNET_ID => Some(Activations::testnet()),
+            LOCAL_NET_ID => Some(Activations::local()),
+            _ => None,
+        }
+    }
+
+    /// Whether a rule activated at `activation` applies to a block at
+    /// `height`.
+    pub fn active(activation: u128, height: u128) -> bool {
+        height >= activation
+    }
+}
+
+/// The activations of the network this node is built for.
+impl Default for Activations {
+    fn default() -> Activations {
+        Activations::for_network(NETWORK_ID).unwrap_or_else(Activations::testnet)
+    }
+}
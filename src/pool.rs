@@ -1,9 +1,14 @@
+use crate::txn::{txn_cost, Txn, BASE_TXN_COST, MIN_TXN_FEE};
 use crate::verifiable::Verifiable;
 use ritelinked::LinkedHashMap;
 use serde::{Deserialize, Serialize};
 use std::cmp::Eq;
 use std::hash::Hash;
 
+// The most pending txn ids announced to a peer in one pool sync, a pool holding
+// more only announces its oldest.
+pub const MAX_POOL_TXN_IDS: usize = 1000;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Pool<K: Serialize + Eq + Hash, V: Verifiable> {
     pub kind: PoolKind,
@@ -32,4 +37,159 @@ impl<K: Serialize + Eq + Hash, V: Verifiable> Pool<K, V> {
             },
         }
     }
+
+    /// Looks up an entry whether it's pending or confirmed.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.confirmed.get(key).or_else(|| self.pending.get(key))
+    }
+
+    /// The keys of the first MAX_POOL_TXN_IDS pending entries, announced to
+    /// newly connected peers so they can fetch the ones they're missing.
+    pub fn pending_ids(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.pending
+            .keys()
+            .take(MAX_POOL_TXN_IDS)
+            .cloned()
+            .collect()
+    }
+
+    /// The keys among `ids` that are neither pending nor confirmed here.
+    pub fn missing(&self, ids: &[K]) -> Vec<K>
+    where
+        K: Clone,
+    {
+        ids.iter()
+            .filter(|id| !self.pending.contains_key(id) && !self.confirmed.contains_key(id))
+            .cloned()
+            .collect()
+    }
+}
+
+impl<K: Serialize + Eq + Hash> Pool<K, Txn> {
+    /// Recommends a fee for a txn to be included within `target_blocks` blocks,
+    /// taken from a percentile of the fees of pending and recently confirmed txns.
+    /// The sooner the target, the higher the percentile. Never returns less than
+    /// MIN_TXN_FEE.
+    pub fn estimate_fee(&self, target_blocks: u32) -> u128 {
+        let fees: Vec<u128> = self
+            .pending
+            .values()
+            .chain(self.confirmed.values())
+            .map(|txn| txn.txn_fee)
+            .collect();
+
+        Self::percentile_fee(fees, target_blocks)
+    }
+
+    /// Recommends a fee for a txn costing `cost` units, pricing the fees of
+    /// pending and recently confirmed txns per cost unit rather than per txn.
+    /// Rates are kept per BASE_TXN_COST units so small fees don't round to 0.
+    pub fn estimate_fee_for_cost(&self, target_blocks: u32, cost: u64) -> u128 {
+        let rates: Vec<u128> = self
+            .pending
+            .values()
+            .chain(self.confirmed.values())
+            .map(|txn| txn.txn_fee.saturating_mul(BASE_TXN_COST as u128) / txn_cost(txn) as u128)
+            .collect();
+
+        let rate = Self::percentile_fee(rates, target_blocks);
+        let base_cost = BASE_TXN_COST as u128;
+        let fee = (rate.saturating_mul(cost as u128) + base_cost - 1) / base_cost;
+        std::cmp::max(fee, MIN_TXN_FEE)
+    }
+
+    fn percentile_fee(mut fees: Vec<u128>, target_blocks: u32) -> u128 {
+        if fees.is_empty() {
+            return MIN_TXN_FEE;
+        }
+
+        fees.sort_unstable();
+        let percentile = Self::fee_percentile(target_blocks);
+        // nearest rank
+        let rank = (percentile * fees.len() as u128 + 99) / 100;
+        let index = rank.saturating_sub(1) as usize;
+
+        std::cmp::max(fees[index], MIN_TXN_FEE)
+    }
+
+    pub fn fee_percentile(target_blocks: u32) -> u128 {
+        match target_blocks {
+            0 | 1 => 90,
+            2 | 3 => 75,
+            4..=6 => 50,
+            _ => 25,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seeded_pool(fees: Vec<u128>) -> Pool<String, Txn> {
+        let mut pool = Pool::new(PoolKind::Txn);
+        fees.into_iter().enumerate().for_each(|(idx, fee)| {
+            let mut txn = Txn::unsigned_transfer(1, idx as u128);
+            txn.txn_fee = fee;
+            if idx % 2 == 0 {
+                pool.pending.insert(txn.txn_id.clone(), txn);
+            } else {
+                pool.confirmed.insert(txn.txn_id.clone(), txn);
+            }
+        });
+        pool
+    }
+
+    #[test]
+    fn empty_pool_returns_minimum_fee() {
+        let pool: Pool<String, Txn> = Pool::new(PoolKind::Txn);
+        assert_eq!(pool.estimate_fee(1), MIN_TXN_FEE);
+        assert_eq!(pool.estimate_fee(10), MIN_TXN_FEE);
+    }
+
+    #[test]
+    fn estimate_sits_at_target_percentile() {
+        // fees 10, 20, ..., 200 inserted out of order
+        let fees = (1..=20).rev().map(|n| n * 10).collect();
+        let pool = seeded_pool(fees);
+        assert_eq!(pool.estimate_fee(1), 180);
+        assert_eq!(pool.estimate_fee(3), 150);
+        assert_eq!(pool.estimate_fee(5), 100);
+        assert_eq!(pool.estimate_fee(20), 50);
+    }
+
+    #[test]
+    fn estimate_never_below_minimum() {
+        let pool = seeded_pool(vec![0, 0, 0, 0]);
+        assert_eq!(pool.estimate_fee(1), MIN_TXN_FEE);
+    }
+
+    #[test]
+    fn fee_for_cost_scales_with_cost() {
+        let fees = (1..=20).map(|n| n * 1000).collect();
+        let pool = seeded_pool(fees);
+        let fee = pool.estimate_fee_for_cost(1, BASE_TXN_COST);
+        let double = pool.estimate_fee_for_cost(1, 2 * BASE_TXN_COST);
+        assert!(fee > MIN_TXN_FEE);
+        assert!(double >= 2 * fee - 1 && double <= 2 * fee);
+
+        let empty: Pool<String, Txn> = Pool::new(PoolKind::Txn);
+        assert_eq!(empty.estimate_fee_for_cost(1, BASE_TXN_COST), MIN_TXN_FEE);
+    }
+
+    #[test]
+    fn pending_ids_are_capped() {
+        let mut pool: Pool<String, Txn> = Pool::new(PoolKind::Txn);
+        (0..MAX_POOL_TXN_IDS as u128 + 1).for_each(|n| {
+            let txn = Txn::unsigned_transfer(1, n);
+            pool.pending.insert(txn.txn_id.clone(), txn);
+        });
+        let ids = pool.pending_ids();
+        assert_eq!(ids.len(), MAX_POOL_TXN_IDS);
+        assert_eq!(ids[0], *pool.pending.keys().next().unwrap());
+        assert!(pool.missing(&ids).is_empty());
+    }
 }
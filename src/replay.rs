@@ -0,0 +1,733 @@
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::blockchain_handler::{BlockchainHandler, BlockchainSenders, Handled};
+use crate::clock::{MockClock, SharedClock};
+use crate::entropy;
+use crate::handler::{bounded_channel, BoundedReceiver, ChannelConfig};
+use crate::miner::Miner;
+use crate::network::command_utils::Command;
+use crate::network::node::NodeAuth;
+use crate::reward::RewardState;
+use crate::state::{NetworkState, SharedNetworkState};
+use crate::utils::take_flag;
+use libp2p::identity::Keypair;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+// --record <log> records the commands the node's handlers receive to the log.
+pub const RECORD_FLAG: &str = "--record";
+// `vrrb replay <log>` replays a recorded log instead of starting a node.
+pub const REPLAY_COMMAND: &str = "replay";
+
+/// The thread a recorded command was received by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Handler {
+    Blockchain,
+    Miner,
+    State,
+}
+
+/// What the ledger was left at by a block the blockchain handler applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub block_height: u128,
+    pub block_hash: String,
+    pub state_hash: Option<String>,
+    pub ledger_hash: Option<String>,
+    // sha256 of the claims in the ledger, in the order the ledger holds them.
+    pub claims_hash: String,
+}
+
+/// What the miner was left at by a block the blockchain handler confirmed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MinerOutcome {
+    pub block_height: u128,
+    pub block_hash: String,
+    // The miner's claim map hash with the block's claims in.
+    pub claim_map_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum LogEntry {
+    // The first entry, what the recording threads' RNGs were seeded with.
+    Seed(u64),
+    Command {
+        step: u64,
+        handler: Handler,
+        timestamp: u128,
+        command: Command,
+    },
+    // Written once the block received at `step` has been applied and dumped.
+    Applied {
+        step: u64,
+        outcome: Outcome,
+    },
+    // Written once the miner has moved on to the block confirmed at `step`.
+    Confirmed {
+        step: u64,
+        outcome: MinerOutcome,
+    },
+}
+
+/// Records the commands a node's handlers receive, one JSON object per line
+/// like the block WAL, so the run can be replayed with `vrrb replay`. The
+/// threads sharing a recorder number their commands from one counter, in the
+/// order they received them.
+#[derive(Debug, Clone)]
+pub struct CommandRecorder {
+    pub path: String,
+    seed: u64,
+    // The last step recorded, held while an entry is written so the lines of
+    // different threads don't interleave.
+    step: Arc<Mutex<u64>>,
+}
+
+/// The first step a replay didn't reproduce, with what was recorded for it
+/// and what the replay got. None on either side means no block was applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: u64,
+    pub recorded: Option<Outcome>,
+    pub replayed: Option<Outcome>,
+}
+
+/// The first confirmed block the replayed miner didn't move on to like the
+/// recorded one did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinerDivergence {
+    pub step: u64,
+    pub recorded: Option<MinerOutcome>,
+    pub replayed: Option<MinerOutcome>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    // The log couldn't be read, or doesn't start with the run's seed.
+    Log(String),
+    Diverged(Divergence),
+    MinerDiverged(MinerDivergence),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub commands: usize,
+    // Commands that only reach the terminal, or that the miner handler
+    // rebuilt for the replay doesn't apply.
+    pub skipped: usize,
+    pub blocks: usize,
+    pub confirmations: usize,
+    pub state_hash: Option<String>,
+}
+
+/// What a recorded command did when it was fed to its handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Replayed {
+    Applied(Outcome),
+    // The miner moved on to a confirmed block.
+    Confirmed(MinerOutcome),
+    Handled,
+    Skipped,
+}
+
+/// The blockchain and miner handlers rebuilt on fresh dbs, fed the commands
+/// of a recorded run. The blockchain commands go through the node's own
+/// BlockchainHandler, on a clock set to the time each was recorded at. Blocks
+/// are replayed from the pending blocks the blockchain handler received, the
+/// miner isn't asked to mine them again since a block's header timestamp
+/// comes from the clock it was mined at.
+pub struct ReplayNode {
+    pub handler: BlockchainHandler,
+    miner: Miner,
+    clock: MockClock,
+    outboxes: Outboxes,
+}
+
+// The receiving ends of the channels the blockchain handler sends on. What it
+// sends is in the log as the commands the other threads received, so it's
+// dropped once each command is handled.
+struct Outboxes {
+    miner: UnboundedReceiver<Command>,
+    swarm: UnboundedReceiver<Command>,
+    blockchain: UnboundedReceiver<Command>,
+    state: BoundedReceiver<Command>,
+    wallet: UnboundedReceiver<Command>,
+}
+
+impl Outcome {
+    pub fn of(block: &Block, network_state: &NetworkState) -> Outcome {
+        Outcome {
+            block_height: block.header.block_height,
+            block_hash: block.hash.clone(),
+            state_hash: network_state.state_hash.clone(),
+            ledger_hash: network_state.ledger_hash(),
+            claims_hash: network_state.claims_hash.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl MinerOutcome {
+    pub fn of(block: &Block, miner: &Miner) -> MinerOutcome {
+        MinerOutcome {
+            block_height: block.header.block_height,
+            block_hash: block.hash.clone(),
+            claim_map_hash: miner.claim_map_hash(),
+        }
+    }
+}
+
+impl CommandRecorder {
+    /// Splits `--record <log>` out of the arguments.
+    pub fn path_from_args(mut args: Vec<String>) -> (Option<String>, Vec<String>) {
+        let path = take_flag(&mut args, RECORD_FLAG);
+        (path, args)
+    }
+
+    /// Starts a log at `path` for a run seeded with `seed`, replacing any log
+    /// already there.
+    pub fn create(path: &str, seed: u64) -> Result<CommandRecorder, Box<dyn Error>> {
+        File::create(path)?;
+        let recorder = CommandRecorder {
+            path: path.to_string(),
+            seed,
+            step: Arc::new(Mutex::new(0)),
+        };
+        recorder.append(&LogEntry::Seed(seed))?;
+        Ok(recorder)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Records a command received by `handler` and returns its step.
+    pub fn record(&self, handler: Handler, command: &Command) -> u64 {
+        let mut step = self.step.lock().unwrap();
+        *step += 1;
+        let entry = LogEntry::Command {
+            step: *step,
+            handler,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+            command: command.clone(),
+        };
+        if let Err(e) = self.append(&entry) {
+            println!("Error recording command to {}: {:?}", self.path, e);
+        }
+        *step
+    }
+
+    /// Records what applying the block received at `step` left the ledger at.
+    pub fn applied(&self, step: u64, outcome: &Outcome) {
+        let _step = self.step.lock().unwrap();
+        let entry = LogEntry::Applied {
+            step,
+            outcome: outcome.clone(),
+        };
+        if let Err(e) = self.append(&entry) {
+            println!("Error recording applied block to {}: {:?}", self.path, e);
+        }
+    }
+
+    /// Records what the block confirmed at `step` left the miner at.
+    pub fn confirmed(&self, step: u64, outcome: &MinerOutcome) {
+        let _step = self.step.lock().unwrap();
+        let entry = LogEntry::Confirmed {
+            step,
+            outcome: outcome.clone(),
+        };
+        if let Err(e) = self.append(&entry) {
+            println!("Error recording confirmed block to {}: {:?}", self.path, e);
+        }
+    }
+
+    fn append(&self, entry: &LogEntry) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+impl ReplayNode {
+    /// Starts the handlers on empty chain and ledger dbs in `dir`.
+    pub fn new(dir: &Path) -> ReplayNode {
+        let chain_path = dir.join("replay_chain.db");
+        let state_path = dir.join("replay_state.db");
+        let _ = fs::remove_file(&chain_path);
+        let _ = fs::remove_file(&state_path);
+        let network_state = SharedNetworkState::new(NetworkState::restore(
+            state_path.to_str().unwrap_or_default(),
+        ));
+        let miner = Miner::start(
+            String::new(),
+            "replay".to_string(),
+            "replay".to_string(),
+            RewardState::start(),
+            network_state.clone(),
+            0,
+        );
+        let (miner_sender, miner_outbox) = mpsc::unbounded_channel();
+        let (swarm_sender, swarm_outbox) = mpsc::unbounded_channel();
+        let (blockchain_sender, blockchain_outbox) = mpsc::unbounded_channel();
+        let (state_sender, state_outbox) =
+            bounded_channel("replay_state", ChannelConfig::default());
+        let (wallet_sender, wallet_outbox) = mpsc::unbounded_channel();
+        let senders = BlockchainSenders {
+            miner: miner_sender,
+            swarm: swarm_sender,
+            blockchain: blockchain_sender,
+            state: state_sender,
+            wallet: wallet_sender,
+        };
+        let mut handler = BlockchainHandler::new(
+            Blockchain::new(chain_path.to_str().unwrap_or_default()),
+            network_state,
+            RewardState::start(),
+            Keypair::generate_ed25519(),
+            NodeAuth::Full,
+            senders,
+        );
+        let clock = MockClock::default();
+        handler.clock = SharedClock::new(clock.clone());
+        ReplayNode {
+            handler,
+            miner,
+            clock,
+            outboxes: Outboxes {
+                miner: miner_outbox,
+                swarm: swarm_outbox,
+                blockchain: blockchain_outbox,
+                state: state_outbox,
+                wallet: wallet_outbox,
+            },
+        }
+    }
+
+    /// Feeds a command recorded at `timestamp` to the handler that received
+    /// it. The messages a handler sends on to the others aren't, they're in
+    /// the log as the commands the others received.
+    pub fn apply(&mut self, handler: Handler, timestamp: u128, command: Command) -> Replayed {
+        self.clock.set(timestamp);
+        let replayed = match handler {
+            Handler::Blockchain => self.apply_to_blockchain(command),
+            Handler::Miner => self.apply_to_miner(command),
+            // The state thread only answers peers' requests for state.
+            Handler::State => Replayed::Skipped,
+        };
+        self.outboxes.clear();
+
+        replayed
+    }
+
+    fn apply_to_blockchain(&mut self, command: Command) -> Replayed {
+        // The node checks on its sync between commands, a sync that timed out
+        // by the time this one was received had timed out for the node too.
+        self.handler.check_sync_timeout();
+        match self.handler.handle(command) {
+            Handled::Applied(block) => {
+                Replayed::Applied(Outcome::of(&block, &self.handler.network_state.read()))
+            }
+            Handled::Done => Replayed::Handled,
+            // Commands that print to the terminal, export or audit.
+            Handled::Unhandled(_) => Replayed::Skipped,
+        }
+    }
+
+    fn apply_to_miner(&mut self, command: Command) -> Replayed {
+        match command {
+            Command::ConfirmedBlock(block) => {
+                self.miner.confirm_block(&block);
+                if self.miner.gc_due() {
+                    self.miner.collect_garbage();
+                }
+                return Replayed::Confirmed(MinerOutcome::of(&block, &self.miner));
+            }
+            Command::TxnsFinalized(txn_ids) => self.miner.finalize_txns(&txn_ids),
+            Command::ProcessTxn(txn) if !self.miner.admits_txn(&txn) => {}
+            Command::ProcessTxn(txn) => {
+                self.miner.process_txn(txn.clone());
+                self.miner.check_confirmed(txn.txn_id);
+            }
+            Command::ProcessClaim(claim) if !self.miner.admits_claim(&claim) => {}
+            Command::ProcessClaim(claim) => {
+                self.miner
+                    .claim_pool
+                    .confirmed
+                    .insert(claim.pubkey.clone(), claim);
+            }
+            Command::ProcessTxnValidator(validator) => {
+                let txn_id = validator.txn.txn_id.clone();
+                self.miner.process_txn_validator(validator);
+                // Evidence against the yes voters was recorded as the txns it
+                // was submitted in.
+                if self.miner.check_rejected(txn_id.clone()).is_none() {
+                    self.miner.check_confirmed(txn_id);
+                }
+            }
+            Command::StateChanged => {
+                let claim_map = self.handler.network_state.get_claims();
+                self.miner.set_claim_map(claim_map);
+            }
+            // Mining is replayed from the blocks the blockchain handler received.
+            _ => return Replayed::Skipped,
+        }
+        Replayed::Handled
+    }
+}
+
+impl Outboxes {
+    fn clear(&mut self) {
+        while self.miner.try_recv().is_ok() {}
+        while self.swarm.try_recv().is_ok() {}
+        while self.blockchain.try_recv().is_ok() {}
+        while self.state.try_recv().is_ok() {}
+        while self.wallet.try_recv().is_ok() {}
+    }
+}
+
+/// Replays the run recorded at `path` on fresh dbs in `dir`, checking every
+/// block the replay applies, and every block the miner confirms, against what
+/// the run recorded for it. The calling
+/// thread is seeded with the run's seed while the log is replayed.
+pub fn replay(path: &str, dir: &Path) -> Result<ReplayReport, ReplayError> {
+    let entries = read_log(path)?;
+    let seed = match entries.first() {
+        Some(LogEntry::Seed(seed)) => *seed,
+        _ => return Err(ReplayError::Log(format!("{} has no seed", path))),
+    };
+    let mut recorded: HashMap<u64, Outcome> = HashMap::new();
+    let mut recorded_confirmations: HashMap<u64, MinerOutcome> = HashMap::new();
+    entries.iter().for_each(|entry| match entry {
+        LogEntry::Applied { step, outcome } => {
+            recorded.insert(*step, outcome.clone());
+        }
+        LogEntry::Confirmed { step, outcome } => {
+            recorded_confirmations.insert(*step, outcome.clone());
+        }
+        _ => {}
+    });
+
+    entropy::seed(seed);
+    let mut node = ReplayNode::new(dir);
+    let mut report = ReplayReport::default();
+    for entry in entries {
+        if let LogEntry::Command {
+            step,
+            handler,
+            timestamp,
+            command,
+        } = entry
+        {
+            report.commands += 1;
+            let (applied, confirmed) = match node.apply(handler, timestamp, command) {
+                Replayed::Applied(outcome) => {
+                    report.blocks += 1;
+                    (Some(outcome), None)
+                }
+                Replayed::Confirmed(outcome) => {
+                    report.confirmations += 1;
+                    (None, Some(outcome))
+                }
+                Replayed::Handled => (None, None),
+                Replayed::Skipped => {
+                    report.skipped += 1;
+                    (None, None)
+                }
+            };
+            let recorded = recorded.remove(&step);
+            if recorded != applied {
+                entropy::unseed();
+                return Err(ReplayError::Diverged(Divergence {
+                    step,
+                    recorded,
+                    replayed: applied,
+                }));
+            }
+            let recorded = recorded_confirmations.remove(&step);
+            if recorded != confirmed {
+                entropy::unseed();
+                return Err(ReplayError::MinerDiverged(MinerDivergence {
+                    step,
+                    recorded,
+                    replayed: confirmed,
+                }));
+            }
+        }
+    }
+    entropy::unseed();
+    report.state_hash = node.handler.network_state.read().state_hash.clone();
+
+    Ok(report)
+}
+
+// Unlike the WAL's, a line that can't be parsed fails the replay, a command
+// left out would make every step after it suspect.
+fn read_log(path: &str) -> Result<Vec<LogEntry>, ReplayError> {
+    let file = File::open(path).map_err(|e| ReplayError::Log(format!("{}: {}", path, e)))?;
+    let mut entries = vec![];
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| ReplayError::Log(format!("{}: {}", path, e)))?;
+        let entry = serde_json::from_str::<LogEntry>(&line)
+            .map_err(|e| ReplayError::Log(format!("{} line {}: {}", path, index + 1, e)))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |outcome: &Option<Outcome>| match outcome {
+            Some(outcome) => format!(
+                "block {} at height {} state_hash={:?} ledger_hash={:?} claims_hash={}",
+                outcome.block_hash,
+                outcome.block_height,
+                outcome.state_hash,
+                outcome.ledger_hash,
+                outcome.claims_hash
+            ),
+            None => "no block applied".to_string(),
+        };
+        write!(
+            f,
+            "replay diverged at step {}: recorded {}, replayed {}",
+            self.step,
+            describe(&self.recorded),
+            describe(&self.replayed)
+        )
+    }
+}
+
+impl fmt::Display for MinerDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |outcome: &Option<MinerOutcome>| match outcome {
+            Some(outcome) => format!(
+                "block {} at height {} claim_map_hash={}",
+                outcome.block_hash, outcome.block_height, outcome.claim_map_hash
+            ),
+            None => "no block confirmed".to_string(),
+        };
+        write!(
+            f,
+            "replay diverged at step {}: miner recorded {}, replayed {}",
+            self.step,
+            describe(&self.recorded),
+            describe(&self.replayed)
+        )
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Log(details) => write!(f, "unreadable command log: {}", details),
+            ReplayError::Diverged(divergence) => write!(f, "{}", divergence),
+            ReplayError::MinerDiverged(divergence) => write!(f, "{}", divergence),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replayed {} commands ({} skipped), {} blocks, {} confirmations matched, state={:?}",
+            self.commands, self.skipped, self.blocks, self.confirmations, self.state_hash
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::SECOND;
+    use crate::miner::MiningCancel;
+    use crate::wallet::WalletAccount;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Feeds a command to the node like the node's thread would, recording it
+    // first and the block it applied or confirmed after.
+    fn run(node: &mut ReplayNode, recorder: &CommandRecorder, handler: Handler, command: Command) {
+        let step = recorder.record(handler, &command);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        match node.apply(handler, now, command) {
+            Replayed::Applied(outcome) => recorder.applied(step, &outcome),
+            Replayed::Confirmed(outcome) => recorder.confirmed(step, &outcome),
+            _ => {}
+        }
+    }
+
+    // Records a node applying a genesis block and the block mined on it.
+    fn record_run(dir: &Path, log_path: &str) -> ReplayNode {
+        let recorder = CommandRecorder::create(log_path, 42).unwrap();
+        entropy::seed(recorder.seed());
+        let mut node = ReplayNode::new(dir);
+        let wallet = WalletAccount::new();
+        let mut miner = Miner::start(
+            wallet.get_secretkey(),
+            wallet.get_pubkey(),
+            "address".to_string(),
+            RewardState::start(),
+            node.handler.network_state.clone(),
+            1,
+        );
+
+        let mut genesis = miner.genesis().unwrap();
+        genesis.header.timestamp -= 2 * SECOND;
+        let pending = Command::PendingBlock(genesis.clone(), "miner".to_string());
+        run(&mut node, &recorder, Handler::Blockchain, pending);
+        run(
+            &mut node,
+            &recorder,
+            Handler::Miner,
+            Command::ConfirmedBlock(genesis.clone()),
+        );
+        run(&mut node, &recorder, Handler::Miner, Command::StateChanged);
+
+        miner.confirm_block(&genesis);
+        miner.set_claim_map(node.handler.network_state.get_claims());
+        if let Some(block) = miner.mine(&MiningCancel::new()) {
+            let pending = Command::PendingBlock(block.clone(), "miner".to_string());
+            run(&mut node, &recorder, Handler::Blockchain, pending);
+            run(
+                &mut node,
+                &recorder,
+                Handler::Miner,
+                Command::ConfirmedBlock(block),
+            );
+        }
+        run(&mut node, &recorder, Handler::Miner, Command::MineBlock);
+        entropy::unseed();
+        node
+    }
+
+    #[test]
+    fn replay_reproduces_a_recorded_run() {
+        let record_dir = temp_dir("vrrb_test_replay_record");
+        let replay_dir = temp_dir("vrrb_test_replay_replay");
+        let log_path = record_dir.join("commands.log");
+        let log_path = log_path.to_str().unwrap();
+
+        let node = record_run(&record_dir, log_path);
+        let recorded_hash = node.handler.network_state.read().state_hash.clone();
+        assert!(recorded_hash.is_some());
+
+        let report = replay(log_path, &replay_dir).unwrap();
+        assert_eq!(report.state_hash, recorded_hash);
+        assert!(report.blocks >= 1);
+        assert_eq!(report.confirmations, report.blocks);
+        // The request to mine is left to the recorded pending blocks.
+        assert_eq!(report.skipped, 1);
+
+        let _ = fs::remove_dir_all(&record_dir);
+        let _ = fs::remove_dir_all(&replay_dir);
+    }
+
+    #[test]
+    fn mutated_command_is_reported_at_its_step() {
+        let record_dir = temp_dir("vrrb_test_replay_mutated_record");
+        let replay_dir = temp_dir("vrrb_test_replay_mutated_replay");
+        let log_path = record_dir.join("commands.log");
+        let log_path = log_path.to_str().unwrap();
+        record_run(&record_dir, log_path);
+
+        // Pay the genesis reward to another address than its claim's.
+        let mut mutated_step = None;
+        let mut lines = vec![];
+        for line in fs::read_to_string(log_path).unwrap().lines() {
+            let entry = serde_json::from_str::<LogEntry>(line).unwrap();
+            match entry {
+                LogEntry::Command {
+                    step,
+                    handler,
+                    timestamp,
+                    command: Command::PendingBlock(mut block, sender_id),
+                } if mutated_step.is_none() => {
+                    mutated_step = Some(step);
+                    block.header.claim.address = "mutated".to_string();
+                    let command = Command::PendingBlock(block, sender_id);
+                    let entry = LogEntry::Command {
+                        step,
+                        handler,
+                        timestamp,
+                        command,
+                    };
+                    lines.push(serde_json::to_string(&entry).unwrap());
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+        fs::write(log_path, lines.join("\n")).unwrap();
+
+        match replay(log_path, &replay_dir) {
+            Err(ReplayError::Diverged(divergence)) => {
+                assert_eq!(Some(divergence.step), mutated_step);
+                assert!(divergence.recorded.is_some());
+                assert!(divergence.replayed.is_none());
+            }
+            result => panic!("expected a divergence, got {:?}", result),
+        }
+
+        let _ = fs::remove_dir_all(&record_dir);
+        let _ = fs::remove_dir_all(&replay_dir);
+    }
+
+    #[test]
+    fn miner_confirmation_is_checked_against_the_recording() {
+        let record_dir = temp_dir("vrrb_test_replay_miner_record");
+        let replay_dir = temp_dir("vrrb_test_replay_miner_replay");
+        let log_path = record_dir.join("commands.log");
+        let log_path = log_path.to_str().unwrap();
+        record_run(&record_dir, log_path);
+
+        // Record a claim map the miner never got to.
+        let mut mutated_step = None;
+        let mut lines = vec![];
+        for line in fs::read_to_string(log_path).unwrap().lines() {
+            match serde_json::from_str::<LogEntry>(line).unwrap() {
+                LogEntry::Confirmed { step, mut outcome } if mutated_step.is_none() => {
+                    mutated_step = Some(step);
+                    outcome.claim_map_hash = "mutated".to_string();
+                    let entry = LogEntry::Confirmed { step, outcome };
+                    lines.push(serde_json::to_string(&entry).unwrap());
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+        fs::write(log_path, lines.join("\n")).unwrap();
+
+        match replay(log_path, &replay_dir) {
+            Err(ReplayError::MinerDiverged(divergence)) => {
+                assert_eq!(Some(divergence.step), mutated_step);
+                assert_eq!(divergence.recorded.unwrap().claim_map_hash, "mutated");
+                assert!(divergence.replayed.is_some());
+            }
+            result => panic!("expected the miner to diverge, got {:?}", result),
+        }
+
+        let _ = fs::remove_dir_all(&record_dir);
+        let _ = fs::remove_dir_all(&replay_dir);
+    }
+}
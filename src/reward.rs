@@ -1,8 +1,5 @@
+use crate::params::{Activations, ProtocolParams};
 use crate::utils::decay_calculator;
-use rand::{
-    distributions::{Distribution, WeightedIndex},
-    thread_rng, Rng,
-};
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
@@ -15,21 +12,6 @@ pub const SITARI: u128 = 1000 * RIMA;
 pub const PSIGMA: u128 = 1000 * SITARI;
 pub const VRRB: u128 = 1000 * PSIGMA;
 
-// Generate a random variable reward to include in new blocks
-pub const TOTAL_NUGGETS: u128 = 80000000;
-pub const TOTAL_VEINS: u128 = 1400000;
-pub const TOTAL_MOTHERLODES: u128 = 20000;
-pub const N_BLOCKS_PER_EPOCH: u128 = 16000000;
-pub const NUGGET_FINAL_EPOCH: u128 = 300;
-pub const VEIN_FINAL_EPOCH: u128 = 200;
-pub const MOTHERLODE_FINAL_EPOCH: u128 = 100;
-pub const FLAKE_REWARD_RANGE: (u128, u128) = (1, 8);
-pub const GRAIN_REWARD_RANGE: (u128, u128) = (8, 64);
-pub const NUGGET_REWARD_RANGE: (u128, u128) = (64, 512);
-pub const VEIN_REWARD_RANGE: (u128, u128) = (512, 4096);
-pub const MOTHERLODE_REWARD_RANGE: (u128, u128) = (4096, 32769);
-pub const GENESIS_REWARD: u128 = 200_000_000;
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
 pub enum Category {
     Flake(Option<u128>),
@@ -53,6 +35,90 @@ pub struct RewardState {
     pub n_motherlodes_current_epoch: u128,
     pub n_flakes_current_epoch: u128,
     pub n_grains_current_epoch: u128,
+    #[serde(default)]
+    pub params: ProtocolParams,
+}
+
+/// The number of blocks that reward a category, at its decay rate, out of the
+/// `remaining` supply of it. Rounding can't push the count over `remaining`.
+fn emission(remaining: u128, total: u128, final_epoch: u128) -> u128 {
+    let n = (decay_calculator(total, final_epoch) * remaining as f64) as u128;
+    std::cmp::min(n, remaining)
+}
+
+/// The number of nugget, vein, motherlode, flake and grain blocks in an epoch,
+/// given the nuggets, veins and motherlodes remaining at its start. Applies
+/// from `Activations::emission_schedule`, see `legacy_epoch_emissions` for
+/// the epochs that start below it.
+///
+/// The rare categories are clamped, in that order, so that together they
+/// never take more than the epoch's blocks. The rest of the epoch is split
+/// 60/40 between flakes and grains with grains taking the rounding, so the
+/// five counts always add up to exactly the blocks in an epoch.
+pub fn epoch_emissions(
+    n_nuggets_remaining: u128,
+    n_veins_remaining: u128,
+    n_motherlodes_remaining: u128,
+    params: &ProtocolParams,
+) -> (u128, u128, u128, u128, u128) {
+    let mut remaining_blocks = params.blocks_per_epoch;
+    let mut clamp = |n: u128| {
+        let n = std::cmp::min(n, remaining_blocks);
+        remaining_blocks -= n;
+        n
+    };
+    let n_nuggets = clamp(emission(
+        n_nuggets_remaining,
+        params.total_nuggets,
+        params.nugget_final_epoch,
+    ));
+    let n_veins = clamp(emission(
+        n_veins_remaining,
+        params.total_veins,
+        params.vein_final_epoch,
+    ));
+    let n_motherlodes = clamp(emission(
+        n_motherlodes_remaining,
+        params.total_motherlodes,
+        params.motherlode_final_epoch,
+    ));
+    let n_flakes = (remaining_blocks as f64 * 0.6f64) as u128;
+    let n_grains = remaining_blocks - n_flakes;
+
+    (n_nuggets, n_veins, n_motherlodes, n_flakes, n_grains)
+}
+
+/// The counts epochs starting below `Activations::emission_schedule` were
+/// mined with, kept so those blocks replay to the reward state they were
+/// validated against. Past the first epoch every rare category decayed at
+/// the nuggets' rate, and flakes and grains were both rounded down, leaving
+/// a block of the epoch unrewarded now and then.
+pub fn legacy_epoch_emissions(
+    n_nuggets_remaining: u128,
+    n_veins_remaining: u128,
+    n_motherlodes_remaining: u128,
+    params: &ProtocolParams,
+    first_epoch: bool,
+) -> (u128, u128, u128, u128, u128) {
+    let nugget_rate = decay_calculator(params.total_nuggets, params.nugget_final_epoch);
+    let (vein_rate, motherlode_rate) = if first_epoch {
+        (
+            decay_calculator(params.total_veins, params.vein_final_epoch),
+            decay_calculator(params.total_motherlodes, params.motherlode_final_epoch),
+        )
+    } else {
+        (nugget_rate, nugget_rate)
+    };
+    let n_nuggets = (nugget_rate * n_nuggets_remaining as f64) as u128;
+    let n_veins = (vein_rate * n_veins_remaining as f64) as u128;
+    let n_motherlodes = (motherlode_rate * n_motherlodes_remaining as f64) as u128;
+    let remaining_blocks = params
+        .blocks_per_epoch
+        .saturating_sub(n_nuggets + n_veins + n_motherlodes);
+    let n_flakes = (remaining_blocks as f64 * 0.6f64) as u128;
+    let n_grains = (remaining_blocks as f64 * 0.4f64) as u128;
+
+    (n_nuggets, n_veins, n_motherlodes, n_flakes, n_grains)
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -63,102 +129,238 @@ pub struct Reward {
 }
 
 impl RewardState {
+    /// The reward state at genesis on the network this node is built for.
     pub fn start() -> RewardState {
-        let n_nuggets_ce: u128 =
-            (decay_calculator(TOTAL_NUGGETS, NUGGET_FINAL_EPOCH) * TOTAL_NUGGETS as f64) as u128;
-        let n_veins_ce: u128 =
-            (decay_calculator(TOTAL_VEINS, VEIN_FINAL_EPOCH) * TOTAL_VEINS as f64) as u128;
-        let n_motherlodes_ce: u128 = (decay_calculator(TOTAL_MOTHERLODES, MOTHERLODE_FINAL_EPOCH)
-            * TOTAL_MOTHERLODES as f64) as u128;
-        let remaining_blocks = N_BLOCKS_PER_EPOCH - (n_nuggets_ce + n_veins_ce + n_motherlodes_ce);
-        let n_flakes_ce: u128 = (remaining_blocks as f64 * 0.6f64) as u128;
-        let n_grains_ce: u128 = (remaining_blocks as f64 * 0.4f64) as u128;
+        RewardState::start_with(ProtocolParams::default())
+    }
+
+    pub fn start_with(params: ProtocolParams) -> RewardState {
+        RewardState::start_with_activations(params, &Activations::default())
+    }
+
+    /// The reward state at genesis, its first epoch counted with the emission
+    /// schedule `activations` has active at height 0.
+    pub fn start_with_activations(
+        params: ProtocolParams,
+        activations: &Activations,
+    ) -> RewardState {
+        let (n_nuggets_ce, n_veins_ce, n_motherlodes_ce, n_flakes_ce, n_grains_ce) =
+            RewardState::emissions(
+                (
+                    params.total_nuggets,
+                    params.total_veins,
+                    params.total_motherlodes,
+                ),
+                &params,
+                activations,
+                0,
+            );
 
         RewardState {
             current_block: 0,
             epoch: 1,
-            next_epoch_block: 16000000,
-            n_nuggets_remaining: TOTAL_NUGGETS,
-            n_veins_remaining: TOTAL_VEINS,
-            n_motherlodes_remaining: TOTAL_MOTHERLODES,
+            next_epoch_block: params.blocks_per_epoch,
+            n_nuggets_remaining: params.total_nuggets,
+            n_veins_remaining: params.total_veins,
+            n_motherlodes_remaining: params.total_motherlodes,
             n_nuggets_current_epoch: n_nuggets_ce,
             n_veins_current_epoch: n_veins_ce,
             n_motherlodes_current_epoch: n_motherlodes_ce,
             n_flakes_current_epoch: n_flakes_ce,
             n_grains_current_epoch: n_grains_ce,
+            params,
         }
     }
 
-    pub fn update(&mut self, last_reward: Category) {
-        let mut n_nuggets_ce: u128 = self.n_nuggets_current_epoch;
-        let mut n_veins_ce: u128 = self.n_veins_current_epoch;
-        let mut n_motherlodes_ce: u128 = self.n_motherlodes_current_epoch;
-        let mut n_flakes_ce: u128 = self.n_flakes_current_epoch;
-        let mut n_grains_ce: u128 = self.n_grains_current_epoch;
-        let remaining_blocks_in_ce: u128 = self.next_epoch_block - (self.current_block + 1);
-
-        if remaining_blocks_in_ce != 0 {
-            n_nuggets_ce = match last_reward {
-                Category::Nugget(Some(_)) => n_nuggets_ce - 1,
-                _ => n_nuggets_ce,
-            };
-            n_veins_ce = match last_reward {
-                Category::Vein(Some(_)) => n_veins_ce - 1,
-                _ => n_veins_ce,
-            };
-            n_motherlodes_ce = match last_reward {
-                Category::Motherlode(Some(_)) => n_motherlodes_ce - 1,
-                _ => n_motherlodes_ce,
-            };
-            n_flakes_ce = match last_reward {
-                Category::Flake(Some(_)) => n_flakes_ce - 1,
-                _ => n_flakes_ce,
-            };
-            n_grains_ce = match last_reward {
-                Category::Grain(Some(_)) => n_grains_ce - 1,
-                _ => n_grains_ce,
-            };
+    /// The counts of an epoch starting at `height`, given the nuggets, veins
+    /// and motherlodes remaining, with the emission schedule active there.
+    fn emissions(
+        remaining: (u128, u128, u128),
+        params: &ProtocolParams,
+        activations: &Activations,
+        height: u128,
+    ) -> (u128, u128, u128, u128, u128) {
+        let (n_nuggets, n_veins, n_motherlodes) = remaining;
+        if Activations::active(activations.emission_schedule, height) {
+            epoch_emissions(n_nuggets, n_veins, n_motherlodes, params)
         } else {
-            n_nuggets_ce = (decay_calculator(TOTAL_NUGGETS, NUGGET_FINAL_EPOCH)
-                * self.n_nuggets_remaining as f64) as u128;
-            n_veins_ce = (decay_calculator(TOTAL_NUGGETS, NUGGET_FINAL_EPOCH)
-                * self.n_veins_remaining as f64) as u128;
-            n_motherlodes_ce = (decay_calculator(TOTAL_NUGGETS, NUGGET_FINAL_EPOCH)
-                * self.n_motherlodes_remaining as f64) as u128;
-            let remaining_blocks =
-                N_BLOCKS_PER_EPOCH - (n_nuggets_ce + n_veins_ce + n_motherlodes_ce);
-            n_flakes_ce = (remaining_blocks as f64 * 0.6f64) as u128;
-            n_grains_ce = (remaining_blocks as f64 * 0.4f64) as u128;
+            legacy_epoch_emissions(n_nuggets, n_veins, n_motherlodes, params, height == 0)
         }
+    }
 
-        self.current_block = self.current_block + 1;
-        self.epoch = if self.current_block + 1 != self.next_epoch_block {
-            self.epoch
-        } else {
-            self.epoch + 1
-        };
-        self.next_epoch_block = if self.current_block + 1 != self.next_epoch_block {
-            self.next_epoch_block
-        } else {
-            self.next_epoch_block + N_BLOCKS_PER_EPOCH
-        };
-        self.n_nuggets_remaining = match last_reward {
-            Category::Nugget(Some(_)) => self.n_nuggets_remaining - 1,
-            _ => self.n_nuggets_remaining,
-        };
-        self.n_veins_remaining = match last_reward {
-            Category::Vein(Some(_)) => self.n_veins_remaining - 1,
-            _ => self.n_veins_remaining,
-        };
-        self.n_motherlodes_remaining = match last_reward {
-            Category::Motherlode(Some(_)) => self.n_motherlodes_remaining - 1,
-            _ => self.n_motherlodes_remaining,
-        };
-        self.n_nuggets_current_epoch = n_nuggets_ce;
-        self.n_veins_current_epoch = n_veins_ce;
-        self.n_motherlodes_current_epoch = n_motherlodes_ce;
-        self.n_flakes_current_epoch = n_flakes_ce;
-        self.n_grains_current_epoch = n_grains_ce;
+    /// Counts the reward of the block just confirmed. The update that brings
+    /// current_block to the block before next_epoch_block starts the next
+    /// epoch, whose counts are drawn from what is left of the totals.
+    pub fn update(&mut self, last_reward: Category) {
+        self.update_with_activations(last_reward, &Activations::default())
+    }
+
+    /// Like `update`, turning an epoch over with the emission schedule
+    /// `activations` has active at the block just confirmed.
+    pub fn update_with_activations(&mut self, last_reward: Category, activations: &Activations) {
+        match last_reward {
+            Category::Nugget(Some(_)) => {
+                self.n_nuggets_current_epoch -= 1;
+                self.n_nuggets_remaining -= 1;
+            }
+            Category::Vein(Some(_)) => {
+                self.n_veins_current_epoch -= 1;
+                self.n_veins_remaining -= 1;
+            }
+            Category::Motherlode(Some(_)) => {
+                self.n_motherlodes_current_epoch -= 1;
+                self.n_motherlodes_remaining -= 1;
+            }
+            Category::Flake(Some(_)) => self.n_flakes_current_epoch -= 1,
+            Category::Grain(Some(_)) => self.n_grains_current_epoch -= 1,
+            _ => {}
+        }
+
+        self.current_block += 1;
+        if self.current_block + 1 == self.next_epoch_block {
+            self.epoch += 1;
+            self.next_epoch_block += self.params.blocks_per_epoch;
+            let emissions = RewardState::emissions(
+                (
+                    self.n_nuggets_remaining,
+                    self.n_veins_remaining,
+                    self.n_motherlodes_remaining,
+                ),
+                &self.params,
+                activations,
+                self.current_block,
+            );
+            self.n_nuggets_current_epoch = emissions.0;
+            self.n_veins_current_epoch = emissions.1;
+            self.n_motherlodes_current_epoch = emissions.2;
+            self.n_flakes_current_epoch = emissions.3;
+            self.n_grains_current_epoch = emissions.4;
+        }
+    }
+
+    /// The chance of each category being drawn for the next block: its count
+    /// left in the current epoch over the count of all of them. Every chance
+    /// is 0 once the epoch has nothing left to reward.
+    pub fn category_distribution(&self) -> Vec<(Category, f64)> {
+        let weights = self.category_weights();
+        let total: u128 = weights.iter().map(|(_, weight)| weight).sum();
+        weights
+            .into_iter()
+            .map(|(category, weight)| {
+                if total == 0 {
+                    (category, 0f64)
+                } else {
+                    (category, weight as f64 / total as f64)
+                }
+            })
+            .collect()
+    }
+
+    /// The expected reward of the next block, each category's chance times
+    /// the mean of the amounts it's drawn from.
+    pub fn expected_reward(&self) -> f64 {
+        self.category_distribution()
+            .into_iter()
+            .map(|(category, p)| match category.reward_range(&self.params) {
+                // Amounts are spread over [low, high).
+                Some((low, high)) => p * (low + high - 1) as f64 / 2f64,
+                None => 0f64,
+            })
+            .sum()
+    }
+
+    /// The least and the most the reward schedule can have emitted by the
+    /// block at `height`, the last block this state was updated with: the
+    /// genesis reward plus the range of every block after it. Nuggets, veins
+    /// and motherlodes are counted from what is left of their totals, every
+    /// other block rewarded a flake or a grain.
+    pub fn emission_bounds(&self, height: u128) -> (u128, u128) {
+        let params = &self.params;
+        let rare = vec![
+            (
+                params
+                    .total_nuggets
+                    .saturating_sub(self.n_nuggets_remaining),
+                params.nugget_reward_range,
+            ),
+            (
+                params.total_veins.saturating_sub(self.n_veins_remaining),
+                params.vein_reward_range,
+            ),
+            (
+                params
+                    .total_motherlodes
+                    .saturating_sub(self.n_motherlodes_remaining),
+                params.motherlode_reward_range,
+            ),
+        ];
+        let n_rare: u128 = rare.iter().map(|(n, _)| n).sum();
+        let common = (
+            height.saturating_sub(n_rare),
+            (params.flake_reward_range.0, params.grain_reward_range.1),
+        );
+
+        // Amounts are spread over [low, high).
+        rare.into_iter().chain(std::iter::once(common)).fold(
+            (params.genesis_reward, params.genesis_reward),
+            |(least, most), (n, (low, high))| (least + n * low, most + n * (high - 1)),
+        )
+    }
+
+    /// The emission the reward schedule expects by the block at `height`, the
+    /// middle of its bounds. Amounts vary from block to block, so a ledger
+    /// that follows the schedule lands within the bounds rather than on this.
+    pub fn expected_cumulative_emission(&self, height: u128) -> u128 {
+        let (least, most) = self.emission_bounds(height);
+        least + (most - least) / 2
+    }
+
+    /// The blocks left until the next epoch starts. The epoch turns over with
+    /// the update that brings current_block to the block before
+    /// next_epoch_block.
+    pub fn blocks_to_next_epoch(&self) -> u128 {
+        self.next_epoch_block
+            .saturating_sub(1)
+            .saturating_sub(self.current_block)
+    }
+
+    /// Where in the emission schedule the chain is, one line per fact.
+    pub fn emission_progress(&self) -> String {
+        let rare = [
+            (
+                "Nuggets",
+                self.n_nuggets_remaining,
+                self.n_nuggets_current_epoch,
+            ),
+            ("Veins", self.n_veins_remaining, self.n_veins_current_epoch),
+            (
+                "Motherlodes",
+                self.n_motherlodes_remaining,
+                self.n_motherlodes_current_epoch,
+            ),
+        ];
+        let mut lines = vec![
+            format!("Epoch: {} (block {})", self.epoch, self.current_block),
+            format!("Blocks to next epoch: {}", self.blocks_to_next_epoch()),
+        ];
+        lines.extend(rare.iter().map(|(name, remaining, current_epoch)| {
+            format!(
+                "{} remaining: {} ({} this epoch)",
+                name, remaining, current_epoch
+            )
+        }));
+        lines.join("\n")
+    }
+
+    // The weights the next block's category is drawn with.
+    fn category_weights(&self) -> Vec<(Category, u128)> {
+        vec![
+            (Category::Flake(None), self.n_flakes_current_epoch),
+            (Category::Grain(None), self.n_grains_current_epoch),
+            (Category::Nugget(None), self.n_nuggets_current_epoch),
+            (Category::Vein(None), self.n_veins_current_epoch),
+            (Category::Motherlode(None), self.n_motherlodes_current_epoch),
+        ]
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -180,10 +382,11 @@ impl RewardState {
     }
 
     pub fn valid_reward(&self, category: Category) -> Option<bool> {
+        let params = &self.params;
         match category {
             Category::Flake(amount) => match amount {
                 Some(amt) => {
-                    if amt < FLAKE_REWARD_RANGE.0 || amt > FLAKE_REWARD_RANGE.1 {
+                    if amt < params.flake_reward_range.0 || amt > params.flake_reward_range.1 {
                         return Some(false);
                     }
                     if self.n_flakes_current_epoch == 0 {
@@ -194,7 +397,7 @@ impl RewardState {
             },
             Category::Grain(amount) => match amount {
                 Some(amt) => {
-                    if amt < GRAIN_REWARD_RANGE.0 || amt > GRAIN_REWARD_RANGE.1 {
+                    if amt < params.grain_reward_range.0 || amt > params.grain_reward_range.1 {
                         return Some(false);
                     }
 
@@ -206,7 +409,7 @@ impl RewardState {
             },
             Category::Nugget(amount) => match amount {
                 Some(amt) => {
-                    if amt < NUGGET_REWARD_RANGE.0 || amt > NUGGET_REWARD_RANGE.1 {
+                    if amt < params.nugget_reward_range.0 || amt > params.nugget_reward_range.1 {
                         return Some(false);
                     }
 
@@ -218,12 +421,11 @@ impl RewardState {
                         return Some(false);
                     }
 
-                    if self.epoch > NUGGET_FINAL_EPOCH {
+                    if self.epoch > params.nugget_final_epoch {
                         return Some(false);
                     }
 
-                    if self.epoch == NUGGET_FINAL_EPOCH && self.n_nuggets_remaining > 1
-                    {
+                    if self.epoch == params.nugget_final_epoch && self.n_nuggets_remaining > 1 {
                         return Some(false);
                     }
                 }
@@ -231,7 +433,7 @@ impl RewardState {
             },
             Category::Vein(amount) => match amount {
                 Some(amt) => {
-                    if amt < VEIN_REWARD_RANGE.0 || amt > VEIN_REWARD_RANGE.1 {
+                    if amt < params.vein_reward_range.0 || amt > params.vein_reward_range.1 {
                         return Some(false);
                     }
                     if self.n_veins_current_epoch == 0 {
@@ -242,11 +444,11 @@ impl RewardState {
                         return Some(false);
                     }
 
-                    if self.epoch > VEIN_FINAL_EPOCH {
+                    if self.epoch > params.vein_final_epoch {
                         return Some(false);
                     }
 
-                    if self.epoch == VEIN_FINAL_EPOCH && self.n_veins_remaining > 1 {
+                    if self.epoch == params.vein_final_epoch && self.n_veins_remaining > 1 {
                         return Some(false);
                     }
                 }
@@ -254,7 +456,9 @@ impl RewardState {
             },
             Category::Motherlode(amount) => match amount {
                 Some(amt) => {
-                    if amt < MOTHERLODE_REWARD_RANGE.0 || amt > MOTHERLODE_REWARD_RANGE.1 {
+                    if amt < params.motherlode_reward_range.0
+                        || amt > params.motherlode_reward_range.1
+                    {
                         return Some(false);
                     }
                     if self.n_motherlodes_current_epoch == 0 {
@@ -265,11 +469,11 @@ impl RewardState {
                         return Some(false);
                     }
 
-                    if self.epoch > MOTHERLODE_FINAL_EPOCH {
+                    if self.epoch > params.motherlode_final_epoch {
                         return Some(false);
                     }
 
-                    if self.epoch == MOTHERLODE_FINAL_EPOCH
+                    if self.epoch == params.motherlode_final_epoch
                         && self.n_motherlodes_remaining > 1
                     {
                         return Some(false);
@@ -279,7 +483,7 @@ impl RewardState {
             },
             Category::Genesis(amount) => match amount {
                 Some(amt) => {
-                    if amt != GENESIS_REWARD {
+                    if amt != params.genesis_reward {
                         return Some(false);
                     }
                 }
@@ -291,8 +495,24 @@ impl RewardState {
 }
 
 impl Reward {
-    pub fn new(miner: Option<String>, reward_state: &RewardState) -> Reward {
-        let category: Category = Category::new(&reward_state);
+    /// A reward of a category drawn from the reward state, its amount derived
+    /// from `seed`, see Category::with_amount.
+    pub fn new(miner: Option<String>, reward_state: &RewardState, seed: &[u8]) -> Reward {
+        Reward::from_category(
+            miner,
+            Category::generate_category(reward_state, seed),
+            seed,
+            &reward_state.params,
+        )
+    }
+
+    pub fn from_category(
+        miner: Option<String>,
+        category: Category,
+        seed: &[u8],
+        params: &ProtocolParams,
+    ) -> Reward {
+        let category = category.with_amount(seed, params);
         Reward {
             miner,
             category,
@@ -306,8 +526,8 @@ impl Reward {
             },
         }
     }
-    pub fn genesis(miner: Option<String>) -> Reward {
-        let category = Category::Genesis(Some(GENESIS_REWARD as u128));
+    pub fn genesis(miner: Option<String>, params: &ProtocolParams) -> Reward {
+        let category = Category::Genesis(Some(params.genesis_reward));
         Reward {
             miner,
             category,
@@ -335,71 +555,75 @@ impl Reward {
 }
 
 impl Category {
-    pub fn new(reward_state: &RewardState) -> Category {
-        Category::generate_category(reward_state).amount()
-    }
-
-    pub fn generate_category(reward_state: &RewardState) -> Category {
-        let n_flakes_current_epoch = reward_state
-            .clone()
-            .n_flakes_current_epoch
-            .clone();
-        let n_grains_current_epoch = reward_state
-            .clone()
-            .n_grains_current_epoch
-            .clone();
-        let n_nuggets_current_epoch = reward_state
-            .clone()
-            .n_grains_current_epoch
-            .clone();
-        let n_veins_current_epoch = reward_state
-            .clone()
-            .n_veins_current_epoch
-            .clone();
-        let n_motherlodes_current_epoch = reward_state
-            .clone()
-            .n_motherlodes_current_epoch
-            .clone();
-
-        let items = vec![
-            (Category::Flake(None), n_flakes_current_epoch),
-            (Category::Grain(None), n_grains_current_epoch),
-            (Category::Nugget(None), n_nuggets_current_epoch),
-            (Category::Vein(None), n_veins_current_epoch),
-            (Category::Motherlode(None), n_motherlodes_current_epoch),
-        ];
-        let dist = WeightedIndex::new(items.iter().map(|item| item.1)).unwrap();
-        let mut rng = rand::thread_rng();
-        items[dist.sample(&mut rng)].0
+    pub fn new(reward_state: &RewardState, seed: &[u8]) -> Category {
+        Category::generate_category(reward_state, seed).with_amount(seed, &reward_state.params)
+    }
+
+    /// The category `seed` hashes to, weighted by the categories left in the
+    /// current epoch. Like the amount it's derived rather than drawn, so a
+    /// validator recomputes the category from its own reward state.
+    pub fn generate_category(reward_state: &RewardState, seed: &[u8]) -> Category {
+        let weights = reward_state.category_weights();
+        let total: u128 = weights.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return weights[0].0;
+        }
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&blake3::hash(seed).as_bytes()[16..]);
+        let mut point = u128::from_le_bytes(bytes) % total;
+        for (category, weight) in weights.iter() {
+            if point < *weight {
+                return *category;
+            }
+            point -= weight;
+        }
+        weights[0].0
+    }
+
+    /// The range a category's amount is drawn from, None for the genesis
+    /// reward.
+    pub fn reward_range(&self, params: &ProtocolParams) -> Option<(u128, u128)> {
+        match self {
+            Self::Flake(_) => Some(params.flake_reward_range),
+            Self::Grain(_) => Some(params.grain_reward_range),
+            Self::Nugget(_) => Some(params.nugget_reward_range),
+            Self::Vein(_) => Some(params.vein_reward_range),
+            Self::Motherlode(_) => Some(params.motherlode_reward_range),
+            Self::Genesis(_) => None,
+        }
+    }
+
+    /// The amount the category carries.
+    pub fn amount(&self) -> Option<u128> {
+        match self {
+            Self::Flake(amount)
+            | Self::Grain(amount)
+            | Self::Nugget(amount)
+            | Self::Vein(amount)
+            | Self::Motherlode(amount)
+            | Self::Genesis(amount) => *amount,
+        }
     }
 
-    pub fn amount(&self) -> Category {
-        let mut rng = thread_rng();
+    /// The category with the amount in its range that `seed` hashes to. The
+    /// amount is derived rather than drawn so that validators can recompute
+    /// it, the genesis reward keeps its amount.
+    pub fn with_amount(&self, seed: &[u8], params: &ProtocolParams) -> Category {
+        let amount = |(low, high): (u128, u128)| {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&blake3::hash(seed).as_bytes()[..16]);
+            Some(low + u128::from_le_bytes(bytes) % (high - low))
+        };
         match self {
-            Self::Genesis(None) => Category::Genesis(None),
-            Self::Flake(None) => Category::Flake(Some(
-                rng.gen_range(FLAKE_REWARD_RANGE.0, FLAKE_REWARD_RANGE.1),
-            )),
-            Self::Grain(None) => Category::Grain(Some(
-                rng.gen_range(GRAIN_REWARD_RANGE.0, GRAIN_REWARD_RANGE.1),
-            )),
-            Self::Nugget(None) => Category::Nugget(Some(
-                rng.gen_range(NUGGET_REWARD_RANGE.0, NUGGET_REWARD_RANGE.1),
-            )),
-            Self::Vein(None) => Category::Vein(Some(
-                rng.gen_range(VEIN_REWARD_RANGE.0, VEIN_REWARD_RANGE.1),
-            )),
-            Self::Motherlode(None) => Category::Motherlode(Some(
-                rng.gen_range(MOTHERLODE_REWARD_RANGE.0, MOTHERLODE_REWARD_RANGE.1),
-            )),
-            Self::Genesis(Some(amount)) => Self::Genesis(Some(*amount)),
-            Self::Flake(Some(amount)) => Self::Flake(Some(*amount)),
-            Self::Grain(Some(amount)) => Self::Grain(Some(*amount)),
-            Self::Nugget(Some(amount)) => Self::Nugget(Some(*amount)),
-            Self::Vein(Some(amount)) => Self::Vein(Some(*amount)),
-            Self::Motherlode(Some(amount)) => Self::Motherlode(Some(*amount)),
+            Self::Flake(_) => Self::Flake(amount(params.flake_reward_range)),
+            Self::Grain(_) => Self::Grain(amount(params.grain_reward_range)),
+            Self::Nugget(_) => Self::Nugget(amount(params.nugget_reward_range)),
+            Self::Vein(_) => Self::Vein(amount(params.vein_reward_range)),
+            Self::Motherlode(_) => Self::Motherlode(amount(params.motherlode_reward_range)),
+            Self::Genesis(amount) => Self::Genesis(*amount),
         }
     }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let as_string = serde_json::to_string(self).unwrap();
 
@@ -421,9 +645,109 @@ impl Category {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::header::BlockHeader;
+    use crate::params::{
+        GENESIS_REWARD, NUGGET_FINAL_EPOCH, N_BLOCKS_PER_EPOCH, TOTAL_MOTHERLODES, TOTAL_NUGGETS,
+        TOTAL_VEINS,
+    };
+    use strum::IntoEnumIterator;
+
     #[test]
     fn test_reward_state_starting_point() {}
 
+    #[test]
+    fn test_nodes_derive_the_same_reward_amount_for_a_header() {
+        let params = ProtocolParams::mainnet();
+        let seed = BlockHeader::reward_seed("last_hash", 7, 1);
+        Category::iter()
+            .filter(|category| category.reward_range(&params).is_some())
+            .for_each(|category| {
+                let ours = Reward::from_category(None, category, &seed, &params);
+                let theirs = Reward::from_category(None, category, &seed.clone(), &params);
+                assert_eq!(ours, theirs);
+                let (low, high) = category.reward_range(&params).unwrap();
+                assert!(ours.amount >= low && ours.amount < high);
+                assert_eq!(ours.category, ours.category.with_amount(&seed, &params));
+            });
+
+        // Other headers spread the amounts over the range.
+        let amounts = (0..64)
+            .map(|nonce| {
+                let seed = BlockHeader::reward_seed("last_hash", nonce, 1);
+                Reward::from_category(None, Category::Motherlode(None), &seed, &params).amount
+            })
+            .collect::<std::collections::HashSet<u128>>();
+        assert!(amounts.len() > 1);
+    }
+
+    #[test]
+    fn test_epoch_emissions_fill_the_epoch() {
+        let params = ProtocolParams::mainnet();
+        assert_eq!(
+            epoch_emissions(TOTAL_NUGGETS, TOTAL_VEINS, TOTAL_MOTHERLODES, &params),
+            (2107490, 43022, 860, 8309176, 5539452)
+        );
+
+        let cases = [
+            (TOTAL_NUGGETS, TOTAL_VEINS, TOTAL_MOTHERLODES),
+            (0, 0, 0),
+            (1, 1, 1),
+            (u128::MAX, 0, 0),
+            (u128::MAX, u128::MAX, u128::MAX),
+        ];
+        cases.iter().for_each(|&(nuggets, veins, motherlodes)| {
+            let (n_nuggets, n_veins, n_motherlodes, n_flakes, n_grains) =
+                epoch_emissions(nuggets, veins, motherlodes, &params);
+            assert!(n_nuggets <= nuggets && n_veins <= veins && n_motherlodes <= motherlodes);
+            assert_eq!(
+                n_nuggets + n_veins + n_motherlodes + n_flakes + n_grains,
+                N_BLOCKS_PER_EPOCH
+            );
+        });
+        assert_eq!(
+            epoch_emissions(u128::MAX, u128::MAX, u128::MAX, &params),
+            (N_BLOCKS_PER_EPOCH, 0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_emission_schedule_applies_from_its_activation_height() {
+        let params = ProtocolParams::mainnet();
+        let activations = Activations::testnet();
+        let legacy = RewardState::start_with_activations(params, &activations);
+        let current = RewardState::start_with_activations(params, &Activations::all());
+        // Grains were rounded down at genesis, leaving a block unrewarded.
+        assert_eq!(legacy.n_grains_current_epoch, 5539451);
+        assert_eq!(current.n_grains_current_epoch, 5539452);
+
+        let turn_over = |height: u128| {
+            let mut reward_state = legacy;
+            reward_state.current_block = height - 1;
+            reward_state.next_epoch_block = height + 1;
+            reward_state.update_with_activations(Category::Genesis(None), &activations);
+            reward_state
+        };
+        // Below the height veins decayed at the nuggets' rate.
+        let below = turn_over(activations.emission_schedule - 1);
+        let nugget_rate = decay_calculator(TOTAL_NUGGETS, NUGGET_FINAL_EPOCH);
+        assert_eq!(
+            below.n_veins_current_epoch,
+            (nugget_rate * TOTAL_VEINS as f64) as u128
+        );
+        let at = turn_over(activations.emission_schedule);
+        assert_eq!(
+            (
+                at.n_nuggets_current_epoch,
+                at.n_veins_current_epoch,
+                at.n_motherlodes_current_epoch,
+                at.n_flakes_current_epoch,
+                at.n_grains_current_epoch
+            ),
+            epoch_emissions(TOTAL_NUGGETS, TOTAL_VEINS, TOTAL_MOTHERLODES, &params)
+        );
+    }
+
     #[test]
     fn test_reward_state_updates_after_mined_block() {}
 
@@ -435,4 +759,182 @@ mod tests {
 
     #[test]
     fn test_reward_category_invalid_amount() {}
+
+    fn reward_state_with(
+        flakes: u128,
+        grains: u128,
+        nuggets: u128,
+        veins: u128,
+        motherlodes: u128,
+    ) -> RewardState {
+        let mut reward_state = RewardState::start();
+        reward_state.n_flakes_current_epoch = flakes;
+        reward_state.n_grains_current_epoch = grains;
+        reward_state.n_nuggets_current_epoch = nuggets;
+        reward_state.n_veins_current_epoch = veins;
+        reward_state.n_motherlodes_current_epoch = motherlodes;
+        reward_state
+    }
+
+    #[test]
+    fn test_category_distribution_sums_to_one() {
+        let distribution = RewardState::start().category_distribution();
+        let total: f64 = distribution.iter().map(|(_, p)| p).sum();
+        assert!((total - 1f64).abs() < 1e-9);
+
+        let distribution = reward_state_with(5, 3, 2, 0, 0).category_distribution();
+        assert_eq!(
+            distribution,
+            vec![
+                (Category::Flake(None), 0.5),
+                (Category::Grain(None), 0.3),
+                (Category::Nugget(None), 0.2),
+                (Category::Vein(None), 0.0),
+                (Category::Motherlode(None), 0.0),
+            ]
+        );
+        assert!(reward_state_with(0, 0, 0, 0, 0)
+            .category_distribution()
+            .iter()
+            .all(|(_, p)| *p == 0f64));
+    }
+
+    #[test]
+    fn test_category_weights_use_their_own_counts() {
+        let only_motherlodes = reward_state_with(0, 0, 0, 0, 7);
+        assert_eq!(
+            only_motherlodes.category_distribution()[4],
+            (Category::Motherlode(None), 1.0)
+        );
+        assert_eq!(
+            Category::generate_category(&only_motherlodes, b"seed"),
+            Category::Motherlode(None)
+        );
+
+        // Nuggets are weighted by the nugget count, not the grain count.
+        let only_nuggets = reward_state_with(0, 0, 3, 0, 0);
+        assert_eq!(
+            Category::generate_category(&only_nuggets, b"seed"),
+            Category::Nugget(None)
+        );
+        let no_nuggets = reward_state_with(0, 3, 0, 0, 0);
+        assert_eq!(
+            Category::generate_category(&no_nuggets, b"seed"),
+            Category::Grain(None)
+        );
+    }
+
+    #[test]
+    fn test_expected_reward_follows_epoch_depletion() {
+        // 0.5 * 4 + 0.3 * 35.5 + 0.2 * 287.5
+        let expected = reward_state_with(5, 3, 2, 0, 0).expected_reward();
+        assert!((expected - 70.15).abs() < 1e-9);
+
+        let start = RewardState::start();
+        let mut depleted = start;
+        depleted.n_motherlodes_current_epoch = 0;
+        depleted.n_veins_current_epoch = 0;
+        assert!(depleted.expected_reward() < start.expected_reward());
+        assert_eq!(reward_state_with(0, 0, 0, 0, 0).expected_reward(), 0f64);
+        assert_eq!(reward_state_with(0, 0, 0, 0, 1).expected_reward(), 18432f64);
+    }
+
+    #[test]
+    fn test_emission_progress_shows_the_reward_state() {
+        let mut reward_state = reward_state_with(10, 5, 3, 2, 1);
+        reward_state.epoch = 3;
+        reward_state.current_block = 2 * N_BLOCKS_PER_EPOCH + 10;
+        reward_state.next_epoch_block = 3 * N_BLOCKS_PER_EPOCH;
+        reward_state.n_nuggets_remaining = 700;
+        reward_state.n_veins_remaining = 40;
+        reward_state.n_motherlodes_remaining = 6;
+
+        assert_eq!(
+            reward_state.emission_progress(),
+            vec![
+                "Epoch: 3 (block 32000010)",
+                "Blocks to next epoch: 15999989",
+                "Nuggets remaining: 700 (3 this epoch)",
+                "Veins remaining: 40 (2 this epoch)",
+                "Motherlodes remaining: 6 (1 this epoch)",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            RewardState::start().blocks_to_next_epoch(),
+            N_BLOCKS_PER_EPOCH - 1
+        );
+    }
+
+    #[test]
+    fn test_reward_validation_respects_overridden_ranges() {
+        let params = ProtocolParams {
+            flake_reward_range: (100, 200),
+            genesis_reward: 5,
+            ..ProtocolParams::mainnet()
+        };
+        let overridden = RewardState::start_with(params);
+        let mainnet = RewardState::start_with(ProtocolParams::mainnet());
+
+        assert_eq!(
+            overridden.valid_reward(Category::Flake(Some(150))),
+            Some(true)
+        );
+        assert_eq!(
+            overridden.valid_reward(Category::Flake(Some(5))),
+            Some(false)
+        );
+        assert_eq!(
+            mainnet.valid_reward(Category::Flake(Some(150))),
+            Some(false)
+        );
+        assert_eq!(mainnet.valid_reward(Category::Flake(Some(5))), Some(true));
+        assert_eq!(
+            overridden.valid_reward(Category::Genesis(Some(5))),
+            Some(true)
+        );
+        assert_eq!(
+            overridden.valid_reward(Category::Genesis(Some(GENESIS_REWARD))),
+            Some(false)
+        );
+
+        let seed = BlockHeader::reward_seed("last_hash", 7, 1);
+        let flake = Reward::from_category(None, Category::Flake(None), &seed, &params);
+        assert!(flake.amount >= 100 && flake.amount < 200);
+        assert_eq!(overridden.valid_reward(flake.category), Some(true));
+    }
+
+    #[test]
+    fn test_local_epochs_roll_over_in_a_simulated_run() {
+        let params = ProtocolParams::local();
+        let activations = Activations::local();
+        let mut reward_state = RewardState::start_with_activations(params, &activations);
+        let n_epochs = 3;
+
+        (1..=n_epochs * params.blocks_per_epoch).for_each(|height| {
+            let seed = BlockHeader::reward_seed("last_hash", height as u64, height);
+            let category = Category::new(&reward_state, &seed);
+            assert_eq!(reward_state.valid_reward(category), Some(true));
+            reward_state.update_with_activations(category, &activations);
+
+            assert_eq!(reward_state.current_block, height);
+            assert_eq!(
+                reward_state.epoch,
+                (height + 1) / params.blocks_per_epoch + 1
+            );
+            if (height + 1) % params.blocks_per_epoch == 0 {
+                // A new epoch starts with a full epoch's worth of rewards.
+                let weights = reward_state.category_weights();
+                let total: u128 = weights.iter().map(|(_, weight)| weight).sum();
+                assert_eq!(total, params.blocks_per_epoch);
+            }
+        });
+
+        assert_eq!(reward_state.epoch, n_epochs + 1);
+        assert_eq!(
+            reward_state.blocks_to_next_epoch(),
+            params.blocks_per_epoch - 1
+        );
+        assert!(reward_state.n_nuggets_remaining < params.total_nuggets);
+    }
 }
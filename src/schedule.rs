@@ -0,0 +1,427 @@
+use crate::wallet::{PendingTxn, WalletAccount, WalletError};
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScheduleStatus {
+    Active,
+    // A payment couldn't be made, no more are made until it's resumed.
+    Paused(String),
+    // All of the schedule's payments have been made.
+    Finished,
+}
+
+/// A payment the wallet makes every `interval_blocks` blocks, from the first
+/// confirmed block at or above `next_height`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u32,
+    pub address_number: u32,
+    pub receiver: String,
+    pub amount: u128,
+    pub fee: u128,
+    pub interval_blocks: u128,
+    pub next_height: u128,
+    // The payments left to make, None if it runs until it's cancelled.
+    pub payments_left: Option<u32>,
+    pub status: ScheduleStatus,
+    // Each device numbers its own schedules, a schedule synced from another
+    // device is matched by when it was created instead. Zero for schedules
+    // saved before they were synced, those are matched by id.
+    #[serde(default)]
+    pub created_at: u128,
+    #[serde(default)]
+    pub modified: u128,
+}
+
+/// What happened to a schedule that came due at a height.
+#[derive(Debug, Clone)]
+pub enum ScheduleEvent {
+    Sent(u32, PendingTxn),
+    Paused(u32, String),
+}
+
+/// The wallet's recurring payments, run on every confirmed block and saved
+/// next to the wallet's other data so they survive a restart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Schedules {
+    next_id: u32,
+    schedules: LinkedHashMap<u32, Schedule>,
+    // When each cancelled schedule was created, so syncing with a device that
+    // still has it doesn't bring it back.
+    #[serde(default)]
+    cancelled: HashSet<u128>,
+}
+
+impl Schedules {
+    pub fn new() -> Schedules {
+        Schedules::default()
+    }
+
+    /// Adds a schedule whose first payment is due `interval_blocks` after
+    /// `height`, the last height the wallet saw. Returns its id.
+    pub fn add(
+        &mut self,
+        address_number: u32,
+        receiver: String,
+        amount: u128,
+        fee: u128,
+        interval_blocks: u128,
+        payments: Option<u32>,
+        height: u128,
+    ) -> u32 {
+        self.next_id += 1;
+        let interval_blocks = interval_blocks.max(1);
+        let created_at = now();
+        let schedule = Schedule {
+            id: self.next_id,
+            address_number,
+            receiver,
+            amount,
+            fee,
+            interval_blocks,
+            next_height: height.saturating_add(interval_blocks),
+            payments_left: payments,
+            status: ScheduleStatus::Active,
+            created_at,
+            modified: created_at,
+        };
+        self.schedules.insert(schedule.id, schedule);
+
+        self.next_id
+    }
+
+    pub fn cancel(&mut self, id: u32) -> Option<Schedule> {
+        let schedule = self.schedules.remove(&id)?;
+        if schedule.created_at != 0 {
+            self.cancelled.insert(schedule.created_at);
+        }
+        Some(schedule)
+    }
+
+    /// Resumes a paused schedule, its next payment is made at the next
+    /// confirmed block if it's overdue.
+    pub fn resume(&mut self, id: u32) -> bool {
+        match self.schedules.get_mut(&id) {
+            Some(schedule) if matches!(schedule.status, ScheduleStatus::Paused(_)) => {
+                schedule.status = ScheduleStatus::Active;
+                schedule.modified = now();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Schedule> {
+        self.schedules.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Schedule> {
+        self.schedules.values()
+    }
+
+    /// Makes the payments of the active schedules due at `height` from the
+    /// wallet, given the balance of each of its addresses. A schedule whose
+    /// address can't cover a payment is paused rather than dropped. Missed
+    /// payments aren't made up, the next one is due `interval_blocks` after
+    /// the one that was made.
+    pub fn run<F>(
+        &mut self,
+        height: u128,
+        wallet: &mut WalletAccount,
+        balance: F,
+    ) -> Vec<ScheduleEvent>
+    where
+        F: Fn(&str) -> u128,
+    {
+        let mut events = vec![];
+        // What the payments made at this height spend from each address.
+        let mut spent: HashMap<String, u128> = HashMap::new();
+        let now = now();
+        for schedule in self.schedules.values_mut() {
+            if schedule.status != ScheduleStatus::Active || schedule.next_height > height {
+                continue;
+            }
+            schedule.modified = now;
+
+            let address = match wallet.addresses.get(&schedule.address_number) {
+                Some(address) => address.clone(),
+                None => {
+                    let reason = WalletError::UnknownAddress(schedule.address_number).to_string();
+                    schedule.status = ScheduleStatus::Paused(reason.clone());
+                    events.push(ScheduleEvent::Paused(schedule.id, reason));
+                    continue;
+                }
+            };
+            let cost = schedule.amount.saturating_add(schedule.fee);
+            let available = balance(&address).saturating_sub(*spent.get(&address).unwrap_or(&0));
+            if available < cost {
+                let reason = InsufficientBalance(cost, available).to_string();
+                schedule.status = ScheduleStatus::Paused(reason.clone());
+                events.push(ScheduleEvent::Paused(schedule.id, reason));
+                continue;
+            }
+
+            match wallet.send_txn(
+                schedule.address_number,
+                schedule.receiver.clone(),
+                schedule.amount,
+                schedule.fee,
+            ) {
+                Ok(pending) => {
+                    *spent.entry(address).or_insert(0) += cost;
+                    schedule.next_height = height.saturating_add(schedule.interval_blocks);
+                    if let Some(payments_left) = schedule.payments_left.as_mut() {
+                        *payments_left = payments_left.saturating_sub(1);
+                        if *payments_left == 0 {
+                            schedule.status = ScheduleStatus::Finished;
+                        }
+                    }
+                    events.push(ScheduleEvent::Sent(schedule.id, pending));
+                }
+                Err(e) => {
+                    schedule.status = ScheduleStatus::Paused(e.to_string());
+                    events.push(ScheduleEvent::Paused(schedule.id, e.to_string()));
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Merges the schedules of the same wallet on another device. Of a
+    /// schedule both devices have, the more recently modified copy is kept,
+    /// one cancelled on either device stays cancelled and the rest are added
+    /// under new ids.
+    pub fn merge(&mut self, other: Schedules) {
+        self.cancelled.extend(other.cancelled);
+        let cancelled = &self.cancelled;
+        self.schedules
+            .retain(|_, schedule| !cancelled.contains(&schedule.created_at));
+        for (_, theirs) in other.schedules {
+            if self.cancelled.contains(&theirs.created_at) {
+                continue;
+            }
+            match self
+                .schedules
+                .values_mut()
+                .find(|ours| ours.is_same(&theirs))
+            {
+                Some(ours) => {
+                    if theirs.modified > ours.modified {
+                        *ours = Schedule {
+                            id: ours.id,
+                            ..theirs
+                        };
+                    }
+                }
+                None => {
+                    self.next_id += 1;
+                    let schedule = Schedule {
+                        id: self.next_id,
+                        ..theirs
+                    };
+                    self.schedules.insert(schedule.id, schedule);
+                }
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Loads the schedules saved at `path`, starting with none if there aren't
+    /// any.
+    pub fn restore(path: &str) -> Schedules {
+        if let Ok(data) = fs::read(path) {
+            if let Ok(schedules) = serde_json::from_slice::<Schedules>(&data) {
+                return schedules;
+            }
+        }
+
+        Schedules::new()
+    }
+}
+
+impl Schedule {
+    // Whether the schedules are copies of one schedule on two devices.
+    fn is_same(&self, other: &Schedule) -> bool {
+        if self.created_at == 0 || other.created_at == 0 {
+            return self.id == other.id && self.created_at == other.created_at;
+        }
+        self.created_at == other.created_at
+    }
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+}
+
+/// A scheduled payment's cost and the balance that couldn't cover it.
+#[derive(Debug)]
+pub struct InsufficientBalance(u128, u128);
+
+impl fmt::Display for ScheduleStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleStatus::Active => write!(f, "active"),
+            ScheduleStatus::Paused(reason) => write!(f, "paused ({})", reason),
+            ScheduleStatus::Finished => write!(f, "finished"),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} to {} every {} blocks from address {}, next at height {}",
+            self.id,
+            self.amount,
+            self.receiver,
+            self.interval_blocks,
+            self.address_number,
+            self.next_height
+        )?;
+        if let Some(payments_left) = self.payments_left {
+            write!(f, ", {} payments left", payments_left)?;
+        }
+        write!(f, " [{}]", self.status)
+    }
+}
+
+impl fmt::Display for InsufficientBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "insufficient balance: {} needed, {} available",
+            self.0, self.1
+        )
+    }
+}
+
+impl Error for InsufficientBalance {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn receiver() -> String {
+        WalletAccount::new().get_address(1)
+    }
+
+    #[test]
+    fn payments_are_sent_at_the_scheduled_heights() {
+        let mut wallet = WalletAccount::new();
+        let mut schedules = Schedules::new();
+        let id = schedules.add(1, receiver(), 10, 1, 3, None, 0);
+
+        let mut sent_at = vec![];
+        for height in 1..=10 {
+            for event in schedules.run(height, &mut wallet, |_| 1000) {
+                match event {
+                    ScheduleEvent::Sent(sent_id, pending) => {
+                        assert_eq!(sent_id, id);
+                        assert_eq!(pending.txn.txn_amount, 10);
+                        sent_at.push(height);
+                    }
+                    ScheduleEvent::Paused(..) => panic!("schedule shouldn't pause"),
+                }
+            }
+        }
+        assert_eq!(sent_at, vec![3, 6, 9]);
+        assert_eq!(wallet.outbox.len(), 3);
+        assert_eq!(schedules.get(id).unwrap().next_height, 12);
+
+        let limited = schedules.add(1, receiver(), 10, 1, 1, Some(2), 10);
+        (11..=14).for_each(|height| {
+            schedules.run(height, &mut wallet, |_| 1000);
+        });
+        assert_eq!(
+            schedules.get(limited).unwrap().status,
+            ScheduleStatus::Finished
+        );
+        assert_eq!(wallet.outbox.len(), 3 + 1 + 2);
+    }
+
+    #[test]
+    fn insufficient_balance_pauses_the_schedule() {
+        let mut wallet = WalletAccount::new();
+        let mut schedules = Schedules::new();
+        let id = schedules.add(1, receiver(), 10, 1, 2, None, 0);
+
+        let events = schedules.run(2, &mut wallet, |_| 5);
+        assert!(matches!(events[..], [ScheduleEvent::Paused(paused_id, _)] if paused_id == id));
+        assert!(matches!(
+            schedules.get(id).unwrap().status,
+            ScheduleStatus::Paused(_)
+        ));
+        assert!(wallet.outbox.is_empty());
+
+        // It stays paused once the balance covers it, until it's resumed.
+        assert!(schedules.run(4, &mut wallet, |_| 1000).is_empty());
+        assert!(schedules.resume(id));
+        assert!(matches!(
+            schedules.run(5, &mut wallet, |_| 1000)[..],
+            [ScheduleEvent::Sent(..)]
+        ));
+        assert_eq!(schedules.get(id).unwrap().next_height, 7);
+
+        // Schedules due at the same height don't spend the same balance twice.
+        let second = schedules.add(1, receiver(), 10, 1, 2, None, 5);
+        let events = schedules.run(7, &mut wallet, |_| 15);
+        assert!(matches!(events[0], ScheduleEvent::Sent(sent_id, _) if sent_id == id));
+        assert!(matches!(events[1], ScheduleEvent::Paused(paused_id, _) if paused_id == second));
+    }
+
+    #[test]
+    fn cancelled_schedules_stop_paying() {
+        let mut wallet = WalletAccount::new();
+        let mut schedules = Schedules::new();
+        let id = schedules.add(1, receiver(), 10, 1, 1, None, 0);
+        assert_eq!(schedules.run(1, &mut wallet, |_| 1000).len(), 1);
+
+        assert!(schedules.cancel(id).is_some());
+        assert!(schedules.cancel(id).is_none());
+        (2..5).for_each(|height| {
+            assert!(schedules.run(height, &mut wallet, |_| 1000).is_empty());
+        });
+        assert_eq!(wallet.outbox.len(), 1);
+    }
+
+    #[test]
+    fn schedules_survive_a_reload() {
+        let path = temp_dir().join("vrrb_test_schedules.json");
+        let path = path.to_str().unwrap();
+        let mut wallet = WalletAccount::new();
+        wallet.get_address(2);
+        let mut schedules = Schedules::new();
+        let first = schedules.add(1, receiver(), 10, 1, 5, Some(4), 0);
+        schedules.add(2, receiver(), 20, 1, 5, None, 0);
+        schedules.run(5, &mut wallet, |_| 1000);
+        schedules.save(path).unwrap();
+
+        let mut restored = Schedules::restore(path);
+        assert_eq!(
+            restored.iter().cloned().collect::<Vec<Schedule>>(),
+            schedules.iter().cloned().collect::<Vec<Schedule>>()
+        );
+        assert_eq!(restored.get(first).unwrap().payments_left, Some(3));
+        // Ids aren't reused after a reload.
+        assert_eq!(restored.add(1, receiver(), 1, 1, 1, None, 5), 3);
+
+        let _ = fs::remove_file(path);
+        assert!(Schedules::restore(path).iter().next().is_none());
+    }
+}
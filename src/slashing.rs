@@ -0,0 +1,324 @@
+use crate::header::BlockHeader;
+use crate::txn::Txn;
+use crate::wallet::WalletAccount;
+use secp256k1::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Blocks a slashed claim waits, bond locked, before it is eligible again.
+pub const REINSTATEMENT_DELAY: u128 = 100;
+// Offenses within this many blocks of the previous one are repeat offenses.
+pub const OFFENSE_WINDOW: u128 = 1000;
+pub const MIN_REINSTATEMENT_BOND: u128 = 1000;
+
+/// A bond locked by the owner of a slashed claim to have it reinstated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reinstatement {
+    pub txn_id: String,
+    pub owner: String,
+    pub bond: u128,
+    pub release_height: u128,
+}
+
+/// The slashing history of a single claim, stored in the ledger db.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffenseHistory {
+    // Heights of the blocks the claim was slashed at.
+    pub offenses: Vec<u128>,
+    // Delay applied to the next reinstatement request, doubled on repeat offenses.
+    pub delay: u128,
+    pub slashed: bool,
+    pub pending: Option<Reinstatement>,
+}
+
+#[derive(Debug)]
+pub struct ReinstatementError(String);
+
+/// A validator's vote on a txn and its signature over the txn's vote payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedVote {
+    pub pubkey: String,
+    pub vote: bool,
+    pub signature: String,
+}
+
+/// Proof that a claim broke the rules, carried in a block by an evidence txn
+/// so every node slashes the claim when it applies the same block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SlashingEvidence {
+    // Two headers the claim signed at the same height, ordered by hash so
+    // every node that saw both reports the same evidence.
+    ConflictingHeaders(BlockHeader, BlockHeader),
+    // A yes vote the claim signed for a txn that validators rejected, with
+    // the signed no votes, ordered by pubkey. It only slashes the claim if
+    // the no votes are from a quorum of the claims eligible to validate.
+    RejectedTxnVote {
+        txn_id: String,
+        txn_payload: String,
+        txn_timestamp: u128,
+        vote: SignedVote,
+        rejections: Vec<SignedVote>,
+    },
+}
+
+#[derive(Debug)]
+pub struct InvalidEvidenceError(String);
+
+impl OffenseHistory {
+    pub fn new() -> OffenseHistory {
+        OffenseHistory {
+            offenses: vec![],
+            delay: REINSTATEMENT_DELAY,
+            slashed: false,
+            pending: None,
+        }
+    }
+
+    /// Records a slash at `block_height`. A repeat offense within OFFENSE_WINDOW
+    /// doubles the delay of the next reinstatement, and a pending reinstatement
+    /// is cancelled, returning the bond it forfeits.
+    pub fn record_offense(&mut self, block_height: u128) -> Option<Reinstatement> {
+        if let Some(last) = self.offenses.last() {
+            if block_height.saturating_sub(*last) <= OFFENSE_WINDOW {
+                self.delay = self.delay.saturating_mul(2);
+            }
+        }
+        self.offenses.push(block_height);
+        self.slashed = true;
+        self.pending.take()
+    }
+
+    /// Starts a reinstatement, locking `bond` until the current delay has passed.
+    /// Returns the height at which the claim will be reinstated.
+    pub fn request(
+        &mut self,
+        txn_id: &str,
+        owner: &str,
+        bond: u128,
+        block_height: u128,
+    ) -> Result<u128, ReinstatementError> {
+        if !self.slashed {
+            return Err(ReinstatementError("claim is not slashed".to_string()));
+        }
+
+        if self.pending.is_some() {
+            return Err(ReinstatementError(
+                "a reinstatement is already pending".to_string(),
+            ));
+        }
+
+        if bond < MIN_REINSTATEMENT_BOND {
+            return Err(ReinstatementError(format!(
+                "bond of {} is below the minimum of {}",
+                bond, MIN_REINSTATEMENT_BOND
+            )));
+        }
+
+        let release_height = block_height.saturating_add(self.delay);
+        self.pending = Some(Reinstatement {
+            txn_id: txn_id.to_string(),
+            owner: owner.to_string(),
+            bond,
+            release_height,
+        });
+
+        Ok(release_height)
+    }
+
+    /// Completes a pending reinstatement once `block_height` reaches its release
+    /// height, returning it so the bond can be released to its owner.
+    pub fn release(&mut self, block_height: u128) -> Option<Reinstatement> {
+        if let Some(pending) = &self.pending {
+            if block_height >= pending.release_height {
+                self.slashed = false;
+                return self.pending.take();
+            }
+        }
+
+        None
+    }
+}
+
+impl SignedVote {
+    /// Whether the signature is the validator's over its vote on the txn.
+    pub fn verify(&self, txn_id: &str, txn_payload: &str) -> bool {
+        if let (Ok(signature), Ok(pubkey)) = (
+            Signature::from_str(&self.signature),
+            PublicKey::from_str(&self.pubkey),
+        ) {
+            let payload = Txn::vote_payload_for(txn_id, txn_payload, self.vote);
+            WalletAccount::verify(payload, signature, pubkey).unwrap_or(false)
+        } else {
+            false
+        }
+    }
+}
+
+impl SlashingEvidence {
+    pub fn conflicting_headers(first: BlockHeader, second: BlockHeader) -> SlashingEvidence {
+        if first.hash() <= second.hash() {
+            SlashingEvidence::ConflictingHeaders(first, second)
+        } else {
+            SlashingEvidence::ConflictingHeaders(second, first)
+        }
+    }
+
+    /// Evidence against a yes vote for the rejected `txn`, with the no votes
+    /// it was rejected by. Votes are taken from the txn, signatures and all,
+    /// so every node that saw them reports the same evidence.
+    pub fn rejected_txn_vote(txn: &Txn, voter: &str) -> Option<SlashingEvidence> {
+        let signed_vote = |pubkey: &String, vote: bool| SignedVote {
+            pubkey: pubkey.clone(),
+            vote,
+            signature: txn
+                .validator_signatures
+                .get(pubkey)
+                .cloned()
+                .unwrap_or_default(),
+        };
+        let vote = match txn.validators.get(voter) {
+            Some(true) => signed_vote(&voter.to_string(), true),
+            _ => return None,
+        };
+        let mut rejections: Vec<SignedVote> = txn
+            .validators
+            .iter()
+            .filter(|(_, vote)| !**vote)
+            .map(|(pubkey, _)| signed_vote(pubkey, false))
+            .collect();
+        rejections.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+
+        Some(SlashingEvidence::RejectedTxnVote {
+            txn_id: txn.txn_id.clone(),
+            txn_payload: txn.txn_payload.clone(),
+            txn_timestamp: txn.txn_timestamp,
+            vote,
+            rejections,
+        })
+    }
+
+    /// The pubkey of the claim the evidence is against.
+    pub fn offender(&self) -> &str {
+        match self {
+            SlashingEvidence::ConflictingHeaders(first, _) => &first.claim.pubkey,
+            SlashingEvidence::RejectedTxnVote { vote, .. } => &vote.pubkey,
+        }
+    }
+
+    /// The time of the offense, the later of the two headers' timestamps, or
+    /// the timestamp of the txn voted for.
+    pub fn timestamp(&self) -> u128 {
+        match self {
+            SlashingEvidence::ConflictingHeaders(first, second) => {
+                first.timestamp.max(second.timestamp)
+            }
+            SlashingEvidence::RejectedTxnVote { txn_timestamp, .. } => *txn_timestamp,
+        }
+    }
+
+    /// The pubkeys that voted the txn down, for evidence against a yes vote.
+    /// Whether they make a quorum depends on the claims eligible when the
+    /// evidence is checked, so it isn't part of `verify`.
+    pub fn rejected_by(&self) -> HashSet<String> {
+        match self {
+            SlashingEvidence::RejectedTxnVote { rejections, .. } => rejections
+                .iter()
+                .map(|rejection| rejection.pubkey.clone())
+                .collect(),
+            _ => HashSet::new(),
+        }
+    }
+
+    /// Checks the evidence proves the offense: two different headers at the
+    /// same height, both signed by the same claim, or a signed yes vote for a
+    /// txn along with signed no votes from other validators.
+    pub fn verify(&self) -> Result<(), InvalidEvidenceError> {
+        match self {
+            SlashingEvidence::ConflictingHeaders(first, second) => {
+                if first.claim.pubkey != second.claim.pubkey {
+                    return Err(InvalidEvidenceError(
+                        "headers are signed by different claims".to_string(),
+                    ));
+                }
+                if first.block_height != second.block_height {
+                    return Err(InvalidEvidenceError(
+                        "headers are at different heights".to_string(),
+                    ));
+                }
+                if first.hash() == second.hash() {
+                    return Err(InvalidEvidenceError("headers are the same".to_string()));
+                }
+                if first.verify().is_err() || second.verify().is_err() {
+                    return Err(InvalidEvidenceError(
+                        "headers aren't signed by the claim".to_string(),
+                    ));
+                }
+
+                Ok(())
+            }
+            SlashingEvidence::RejectedTxnVote {
+                txn_id,
+                txn_payload,
+                vote,
+                rejections,
+                ..
+            } => {
+                if !vote.vote || rejections.iter().any(|rejection| rejection.vote) {
+                    return Err(InvalidEvidenceError(
+                        "votes aren't a yes against no votes".to_string(),
+                    ));
+                }
+                if rejections.is_empty()
+                    || rejections
+                        .windows(2)
+                        .any(|pair| pair[0].pubkey >= pair[1].pubkey)
+                    || rejections
+                        .iter()
+                        .any(|rejection| rejection.pubkey == vote.pubkey)
+                {
+                    return Err(InvalidEvidenceError(
+                        "no votes aren't from distinct other validators".to_string(),
+                    ));
+                }
+                if !vote.verify(txn_id, txn_payload)
+                    || rejections
+                        .iter()
+                        .any(|rejection| !rejection.verify(txn_id, txn_payload))
+                {
+                    return Err(InvalidEvidenceError(
+                        "votes aren't signed by their validators".to_string(),
+                    ));
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ReinstatementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ReinstatementError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InvalidEvidenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for InvalidEvidenceError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
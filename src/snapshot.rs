@@ -0,0 +1,526 @@
+use crate::blockchain::CHAIN_DB_VERSION;
+use crate::network::topics::NETWORK_ID;
+use crate::utils::{take_flag, take_switch};
+use serde::{Deserialize, Serialize};
+use sha256::digest_bytes;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const SNAPSHOT_MAGIC: &[u8] = b"VRRBSNAP";
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+pub const IMPORT_SNAPSHOT_FLAG: &str = "--import-snapshot";
+pub const FORCE_FLAG: &str = "--force";
+// Held in the data dir while a node runs on it, see DataDirLock.
+pub const LOCK_FILE: &str = "node.lock";
+// Files in the data dir that are never archived: logs, the running node's
+// lock, and the staging and temporary files written while a db or another
+// file is replaced.
+const SKIPPED_EXTENSIONS: [&str; 5] = ["log", "lock", "staging", "tmp", "import"];
+// Nodes used to open their dbs under a random test_<n>.db name on every
+// start. The ones left behind are stale and aren't archived.
+const STALE_DB_PREFIX: &str = "test_";
+
+/// A file archived from the data dir, with the checksum of its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMember {
+    pub name: String,
+    pub len: u64,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub format_version: u32,
+    pub node_version: String,
+    pub chain_db_version: u32,
+    pub network_id: String,
+    pub members: Vec<SnapshotMember>,
+}
+
+/// Everything a node keeps in its data dir, the chain db, ledger db and its
+/// write-ahead log, the node's identity key, the peer table and the wallet's
+/// schedules, packed into one archive to move the node to another machine.
+///
+/// The archive is SNAPSHOT_MAGIC, the length of the manifest as a big endian
+/// u64, the manifest as JSON and then the contents of each member in the
+/// order the manifest lists them.
+#[derive(Debug, Clone)]
+pub struct NodeSnapshot {
+    pub manifest: SnapshotManifest,
+    files: Vec<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct NodeSnapshotError(pub String);
+
+/// Marks a data dir as in use by a running node, so a snapshot isn't
+/// imported over the dbs it has open. The lock file holds the node's process
+/// id and is removed when the lock is dropped, a lock left behind by a node
+/// that crashed is taken over once its process is gone.
+#[derive(Debug)]
+pub struct DataDirLock {
+    path: PathBuf,
+}
+
+impl NodeSnapshot {
+    /// Reads the files at the top of `data_dir` into a snapshot.
+    pub fn from_dir(data_dir: &str) -> Result<NodeSnapshot, Box<dyn Error>> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(data_dir)? {
+            let path = entry?.path();
+            let skipped_extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| SKIPPED_EXTENSIONS.contains(&extension))
+                .unwrap_or(false);
+            let stale = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(STALE_DB_PREFIX) && name.ends_with(".db"))
+                .unwrap_or(false);
+            let skipped = skipped_extension || stale;
+            if path.is_file() && !skipped {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut members = vec![];
+        let mut files = vec![];
+        for path in paths {
+            let name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let data = fs::read(&path)?;
+            members.push(SnapshotMember {
+                name,
+                len: data.len() as u64,
+                checksum: digest_bytes(&data),
+            });
+            files.push(data);
+        }
+
+        Ok(NodeSnapshot {
+            manifest: SnapshotManifest {
+                format_version: SNAPSHOT_FORMAT_VERSION,
+                node_version: env!("CARGO_PKG_VERSION").to_string(),
+                chain_db_version: CHAIN_DB_VERSION,
+                network_id: NETWORK_ID.to_string(),
+                members,
+            },
+            files,
+        })
+    }
+
+    /// Archives `data_dir` to `out_path`, returning the manifest written. The
+    /// archive is written next to `out_path` and renamed over it so a failed
+    /// export never leaves a partial archive behind.
+    pub fn export(data_dir: &str, out_path: &str) -> Result<SnapshotManifest, Box<dyn Error>> {
+        let mut snapshot = NodeSnapshot::from_dir(data_dir)?;
+        // An earlier export to the same path isn't archived in the new one.
+        let out_dir = Path::new(out_path)
+            .parent()
+            .and_then(|dir| fs::canonicalize(dir).ok());
+        if out_dir.is_some() && out_dir == fs::canonicalize(data_dir).ok() {
+            if let Some(out_name) = Path::new(out_path).file_name() {
+                snapshot.remove(&out_name.to_string_lossy());
+            }
+        }
+
+        let tmp_path = format!("{}.tmp", out_path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&snapshot.to_bytes()?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, out_path)?;
+
+        Ok(snapshot.manifest)
+    }
+
+    /// Restores the archive at `archive_path` into `data_dir`. Every member's
+    /// checksum and the network id are checked before anything is written, a
+    /// data dir that isn't empty is only written over with `force` and one a
+    /// node is running on never is. The members are written to a staging dir
+    /// next to `data_dir` and moved into it once all of them are on disk.
+    pub fn import(
+        archive_path: &str,
+        data_dir: &str,
+        force: bool,
+    ) -> Result<SnapshotManifest, Box<dyn Error>> {
+        if DataDirLock::held(data_dir) {
+            return Err(Box::new(NodeSnapshotError(format!(
+                "a node is running on {}, stop it before importing a snapshot",
+                data_dir
+            ))));
+        }
+        let snapshot = NodeSnapshot::from_bytes(&fs::read(archive_path)?)?;
+        snapshot.verify(NETWORK_ID)?;
+        let not_empty = fs::read_dir(data_dir)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false);
+        if not_empty && !force {
+            return Err(Box::new(NodeSnapshotError(format!(
+                "{} isn't empty, import with --force to write over it",
+                data_dir
+            ))));
+        }
+
+        let staging_dir = format!("{}.import", data_dir.trim_end_matches('/'));
+        if let Err(e) = snapshot.write_to(&staging_dir) {
+            let _ = fs::remove_dir_all(&staging_dir);
+            return Err(e);
+        }
+        fs::create_dir_all(data_dir)?;
+        for member in snapshot.manifest.members.iter() {
+            fs::rename(
+                Path::new(&staging_dir).join(&member.name),
+                Path::new(data_dir).join(&member.name),
+            )?;
+        }
+        let _ = fs::remove_dir_all(&staging_dir);
+
+        Ok(snapshot.manifest)
+    }
+
+    /// Removes `--import-snapshot <path>` and `--force` from the arguments,
+    /// returning the archive to import before the node starts and whether to
+    /// write over a data dir that isn't empty.
+    pub fn import_from_args(mut args: Vec<String>) -> (Option<(String, bool)>, Vec<String>) {
+        let path = take_flag(&mut args, IMPORT_SNAPSHOT_FLAG);
+        let force = take_switch(&mut args, FORCE_FLAG);
+        (path.map(|path| (path, force)), args)
+    }
+
+    /// Checks the snapshot was taken on `network_id` in a format this node
+    /// reads.
+    pub fn verify(&self, network_id: &str) -> Result<(), NodeSnapshotError> {
+        if self.manifest.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(NodeSnapshotError(format!(
+                "unsupported snapshot format version {}",
+                self.manifest.format_version
+            )));
+        }
+
+        if self.manifest.network_id != network_id {
+            return Err(NodeSnapshotError(format!(
+                "snapshot is for network {}, not {}",
+                self.manifest.network_id, network_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, serde_json::Error> {
+        let manifest = serde_json::to_vec(&self.manifest)?;
+        let mut data = SNAPSHOT_MAGIC.to_vec();
+        data.extend((manifest.len() as u64).to_be_bytes().iter());
+        data.extend(manifest);
+        self.files.iter().for_each(|file| data.extend(file));
+        Ok(data)
+    }
+
+    /// Parses an archive, checking each member's length and checksum and that
+    /// its name can only be written inside the data dir.
+    pub fn from_bytes(data: &[u8]) -> Result<NodeSnapshot, NodeSnapshotError> {
+        let truncated = || NodeSnapshotError("snapshot is truncated".to_string());
+        if !data.starts_with(SNAPSHOT_MAGIC) {
+            return Err(NodeSnapshotError("not a node snapshot".to_string()));
+        }
+
+        let mut offset = SNAPSHOT_MAGIC.len();
+        let mut len_bytes = [0u8; 8];
+        len_bytes.copy_from_slice(data.get(offset..offset + 8).ok_or_else(truncated)?);
+        offset += 8;
+        let manifest_len =
+            usize::try_from(u64::from_be_bytes(len_bytes)).map_err(|_| truncated())?;
+        let manifest_end = offset.checked_add(manifest_len).ok_or_else(truncated)?;
+        let manifest: SnapshotManifest =
+            serde_json::from_slice(data.get(offset..manifest_end).ok_or_else(truncated)?)
+                .map_err(|e| NodeSnapshotError(format!("invalid snapshot manifest: {}", e)))?;
+        offset = manifest_end;
+
+        let mut names = HashSet::new();
+        let mut files = vec![];
+        for member in manifest.members.iter() {
+            if !valid_member_name(&member.name) || !names.insert(member.name.clone()) {
+                return Err(NodeSnapshotError(format!(
+                    "invalid snapshot member name {:?}",
+                    member.name
+                )));
+            }
+
+            let end = usize::try_from(member.len)
+                .ok()
+                .and_then(|len| offset.checked_add(len))
+                .ok_or_else(truncated)?;
+            let file = data.get(offset..end).ok_or_else(truncated)?;
+            if digest_bytes(file) != member.checksum {
+                return Err(NodeSnapshotError(format!(
+                    "snapshot member {} failed its checksum",
+                    member.name
+                )));
+            }
+            files.push(file.to_vec());
+            offset = end;
+        }
+
+        if offset != data.len() {
+            return Err(NodeSnapshotError("snapshot has trailing data".to_string()));
+        }
+
+        Ok(NodeSnapshot { manifest, files })
+    }
+
+    fn remove(&mut self, name: &str) {
+        if let Some(index) = self
+            .manifest
+            .members
+            .iter()
+            .position(|member| member.name == name)
+        {
+            self.manifest.members.remove(index);
+            self.files.remove(index);
+        }
+    }
+
+    fn write_to(&self, dir: &str) -> Result<(), Box<dyn Error>> {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir)?;
+        for (member, data) in self.manifest.members.iter().zip(self.files.iter()) {
+            let mut file = File::create(Path::new(dir).join(&member.name))?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl DataDirLock {
+    /// Locks `data_dir` for this process, failing if a running node holds it.
+    pub fn acquire(data_dir: &str) -> Result<DataDirLock, NodeSnapshotError> {
+        if DataDirLock::held(data_dir) {
+            return Err(NodeSnapshotError(format!(
+                "a node is already running on {}",
+                data_dir
+            )));
+        }
+        let path = Path::new(data_dir).join(LOCK_FILE);
+        fs::write(&path, std::process::id().to_string())
+            .map_err(|e| NodeSnapshotError(format!("can't lock {}: {}", data_dir, e)))?;
+
+        Ok(DataDirLock { path })
+    }
+
+    /// Whether a running process holds the lock on `data_dir`. Where there's
+    /// no /proc to tell whether the process is still running, any lock file
+    /// is taken to be held.
+    pub fn held(data_dir: &str) -> bool {
+        let pid = match fs::read_to_string(Path::new(data_dir).join(LOCK_FILE)) {
+            Ok(pid) => pid,
+            Err(_) => return false,
+        };
+        let proc_dir = Path::new("/proc");
+        if !proc_dir.is_dir() {
+            return true;
+        }
+
+        proc_dir.join(pid.trim()).exists()
+    }
+}
+
+impl Drop for DataDirLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Member names are plain file names, so an archive can't write outside the
+// data dir.
+fn valid_member_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\\')
+}
+
+impl fmt::Display for NodeSnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for NodeSnapshotError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::blockchain::Blockchain;
+    use crate::claim::Claim;
+    use crate::reward::RewardState;
+    use crate::state::NetworkState;
+    use crate::test_utils::temp_path;
+    use crate::wallet::WalletAccount;
+
+    // A data dir holding a chain with a genesis block, the ledger it
+    // produces and a peer table.
+    fn node_data_dir(name: &str) -> (String, String, String) {
+        let data_dir = temp_path(name);
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(&data_dir).unwrap();
+        let chain_path = format!("{}/chain.db", data_dir);
+        let state_path = format!("{}/state.db", data_dir);
+
+        let wallet = WalletAccount::new();
+        let claim = Claim::new(wallet.pubkey.clone(), "address".to_string(), 1);
+        let genesis = Block::genesis(&RewardState::start(), claim, wallet.get_secretkey()).unwrap();
+        let mut blockchain = Blockchain::new(&chain_path);
+        let mut network_state = NetworkState::restore(&state_path);
+        blockchain
+            .process_block(&network_state, &RewardState::start(), &genesis)
+            .unwrap();
+        network_state.dump(&genesis);
+        fs::write(format!("{}/peers.json", data_dir), b"{}").unwrap();
+        fs::write(format!("{}/node.key", data_dir), b"key").unwrap();
+        fs::write(format!("{}/vrrb_log_file_1.log", data_dir), b"log").unwrap();
+        fs::write(format!("{}/test_1.db", data_dir), b"stale").unwrap();
+
+        (data_dir, chain_path, state_path)
+    }
+
+    #[test]
+    fn imported_snapshot_matches_the_exported_node() {
+        let (data_dir, chain_path, state_path) = node_data_dir("vrrb_test_snapshot_export");
+        let archive_path = temp_path("vrrb_test_node_snapshot.snap");
+        let import_dir = temp_path("vrrb_test_snapshot_import");
+        let _ = fs::remove_dir_all(&import_dir);
+
+        let manifest = NodeSnapshot::export(&data_dir, &archive_path).unwrap();
+        let names: Vec<&str> = manifest
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["chain.db", "node.key", "peers.json", "state.db"]
+        );
+        assert_eq!(manifest.network_id, NETWORK_ID);
+        assert_eq!(
+            NodeSnapshot::import(&archive_path, &import_dir, false).unwrap(),
+            manifest
+        );
+
+        let blockchain = Blockchain::new(&chain_path);
+        let imported = Blockchain::new(&format!("{}/chain.db", import_dir));
+        assert_eq!(imported.get_blocks_at(0).len(), 1);
+        assert_eq!(
+            imported.get_blocks_at(0)[0].hash,
+            blockchain.get_blocks_at(0)[0].hash
+        );
+        assert!(imported.get_blocks_at(1).is_empty());
+        let network_state = NetworkState::restore(&state_path);
+        let imported_state = NetworkState::restore(&format!("{}/state.db", import_dir));
+        assert_eq!(imported_state.state_hash, network_state.state_hash);
+        assert_eq!(
+            imported_state.db_to_ledger().to_string(),
+            network_state.db_to_ledger().to_string()
+        );
+        assert_eq!(imported_state.get_claims().len(), 1);
+
+        // The data dir isn't empty now, so importing again needs force.
+        assert!(NodeSnapshot::import(&archive_path, &import_dir, false).is_err());
+        assert!(NodeSnapshot::import(&archive_path, &import_dir, true).is_ok());
+
+        let _ = fs::remove_file(&archive_path);
+        let _ = fs::remove_dir_all(&data_dir);
+        let _ = fs::remove_dir_all(&import_dir);
+    }
+
+    #[test]
+    fn corrupted_member_fails_import_without_writing() {
+        let (data_dir, ..) = node_data_dir("vrrb_test_snapshot_corrupt");
+        let archive_path = temp_path("vrrb_test_corrupt_snapshot.snap");
+        let import_dir = temp_path("vrrb_test_snapshot_corrupt_import");
+        let _ = fs::remove_dir_all(&import_dir);
+        NodeSnapshot::export(&data_dir, &archive_path).unwrap();
+
+        // Flip a byte in the last member, after the others would be written.
+        let mut data = fs::read(&archive_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&archive_path, &data).unwrap();
+
+        let e = NodeSnapshot::import(&archive_path, &import_dir, false).unwrap_err();
+        assert!(e.to_string().contains("failed its checksum"));
+        assert!(!Path::new(&import_dir).exists());
+        assert!(!Path::new(&format!("{}.import", import_dir)).exists());
+
+        let mut snapshot = NodeSnapshot::from_dir(&data_dir).unwrap();
+        snapshot.manifest.network_id = "other-net".to_string();
+        assert!(snapshot.verify(NETWORK_ID).is_err());
+
+        let _ = fs::remove_file(&archive_path);
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn import_is_refused_while_a_node_runs_on_the_data_dir() {
+        let (data_dir, ..) = node_data_dir("vrrb_test_snapshot_locked");
+        let archive_path = temp_path("vrrb_test_locked_snapshot.snap");
+        let import_dir = temp_path("vrrb_test_snapshot_locked_import");
+        let _ = fs::remove_dir_all(&import_dir);
+        fs::create_dir_all(&import_dir).unwrap();
+        NodeSnapshot::export(&data_dir, &archive_path).unwrap();
+
+        let lock = DataDirLock::acquire(&import_dir).unwrap();
+        assert!(DataDirLock::acquire(&import_dir).is_err());
+        let e = NodeSnapshot::import(&archive_path, &import_dir, true).unwrap_err();
+        assert!(e.to_string().contains("a node is running"));
+        assert!(!Path::new(&import_dir).join("chain.db").exists());
+
+        drop(lock);
+        assert!(!DataDirLock::held(&import_dir));
+        // A lock left by a process that is gone doesn't hold the dir.
+        if Path::new("/proc").is_dir() {
+            fs::write(Path::new(&import_dir).join(LOCK_FILE), u32::MAX.to_string()).unwrap();
+            assert!(!DataDirLock::held(&import_dir));
+        }
+        assert!(NodeSnapshot::import(&archive_path, &import_dir, true).is_ok());
+
+        let _ = fs::remove_file(&archive_path);
+        let _ = fs::remove_dir_all(&data_dir);
+        let _ = fs::remove_dir_all(&import_dir);
+    }
+
+    #[test]
+    fn import_flags_are_taken_from_the_args() {
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+        assert_eq!(
+            NodeSnapshot::import_from_args(args(&["vrrb", "--import-snapshot", "node.snap"])),
+            (Some(("node.snap".to_string(), false)), args(&["vrrb"]))
+        );
+        assert_eq!(
+            NodeSnapshot::import_from_args(args(&[
+                "vrrb",
+                "--force",
+                "--import-snapshot",
+                "node.snap"
+            ])),
+            (Some(("node.snap".to_string(), true)), args(&["vrrb"]))
+        );
+        assert_eq!(
+            NodeSnapshot::import_from_args(args(&["vrrb", "peer"])),
+            (None, args(&["vrrb", "peer"]))
+        );
+    }
+}
@@ -1,19 +1,39 @@
+use crate::activity::{record_event, ActivityReport, AddressEvent, Direction, ExportFormat};
+use crate::balance_proof;
+use crate::logging::STATE_TARGET;
+use crate::miner::{ClaimMapHash, QuorumConfig};
 use crate::network::chunkable::Chunkable;
-use crate::network::node::MAX_TRANSMIT_SIZE;
+use crate::params::{ProtocolParams, MAX_TRANSMIT_SIZE};
 use crate::pool::Pool;
+use crate::slashing::{OffenseHistory, Reinstatement, ReinstatementError};
+use crate::token::{Token, TokenLedger};
 use crate::txn::Txn;
-use crate::{block::Block, claim::Claim, reward::RewardState};
+use crate::wal::BlockWal;
+use crate::wallet::{Address, AddressNetwork};
+use crate::{
+    block::Block,
+    claim::{lowest_pointer, Claim, DowntimeWindow},
+    reward::{Category, RewardState},
+};
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
 use ritelinked::LinkedHashMap;
 use serde::{Deserialize, Serialize};
 use sha256::digest_bytes;
-use log::info;
+use log::{error, info, warn};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Ledger {
     pub credits: LinkedHashMap<String, u128>,
     pub debits: LinkedHashMap<String, u128>,
     pub claims: LinkedHashMap<String, Claim>,
+    // Ledgers sent before tokens were issued have none.
+    #[serde(default)]
+    pub tokens: TokenLedger,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -27,23 +47,276 @@ pub struct Components {
     pub archive: Option<Vec<u8>>,
 }
 
+// The ledger key of the eligible validator sets, kept for the blocks of the
+// last VALIDATOR_HISTORY heights.
+const ELIGIBLE_VALIDATORS_KEY: &str = "eligiblevalidators";
+const VALIDATOR_HISTORY: u128 = 256;
+// The most nonce epochs a single block can move the ledger on by, should no
+// claim get a pointer in any of them.
+const MAX_NONCE_EPOCHS_PER_BLOCK: u128 = 16;
+
+/// The version of the snapshots this node exports. Version 1 snapshots hash
+/// the ledger's JSON, from version 2 they hash its canonical hash.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// A standalone copy of the ledger and reward state used for backups. The
+/// integrity hash is checked whenever a snapshot is imported.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StateSnapshot {
+    // Snapshots exported before the version was recorded are version 1.
+    #[serde(default = "StateSnapshot::first_version")]
+    pub version: u32,
+    pub ledger: Ledger,
+    pub reward_state: RewardState,
+    pub state_hash: Option<String>,
+    pub hash: String,
+}
+
+/// The ledger keys changed since they were last written to the db. The
+/// claims map is by far the largest of them and most blocks don't change it,
+/// so balances and claims are only rewritten when a block changes them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirtyKeys {
+    // "credits" and "debits"
+    pub balances: bool,
+    // "claims" and its indexes
+    pub claims: bool,
+    // "tokens", "token_credits" and "token_debits"
+    pub tokens: bool,
+}
+
+/// Reverse indexes over the claims map, written to the ledger db alongside it
+/// so a claim can be looked up by its owner or hash without scanning the map.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimIndexes {
+    // Canonical owner address -> pubkeys of the claims it owns, sorted.
+    pub by_address: LinkedHashMap<String, Vec<String>>,
+    // Claim hash -> pubkey.
+    pub by_hash: LinkedHashMap<String, String>,
+    // Pubkey -> whether the claim is eligible to mine.
+    pub eligibility: LinkedHashMap<String, bool>,
+}
+
+/// The ledger's claims, their ClaimMapHash and their indexes kept in memory,
+/// so the claim map hash a block commits to is worked out from the claims it
+/// changes instead of rehashing every claim, and lookups by owner or hash
+/// don't read the ledger db. Updated wherever the claims are written to the
+/// ledger db, a state that wasn't loaded from the db has none.
+#[derive(Clone, Default)]
+pub struct ClaimCache {
+    loaded: bool,
+    claims: LinkedHashMap<String, Claim>,
+    hash: ClaimMapHash,
+    indexes: ClaimIndexes,
+}
+
+/// The claim changes a block makes, see `NetworkState::apply_block_claims`.
+#[derive(Debug, Default)]
+pub struct BlockClaimChanges {
+    pub changed: bool,
+    // The pubkeys of the claims slashed by evidence in the block.
+    pub slashed: Vec<String>,
+    // The claims sold in the block, by their pubkey and their buyer's.
+    pub transferred: Vec<(String, String)>,
+    // The claim changes left out, and why.
+    pub rejected: Vec<String>,
+    pub released: Vec<(String, Reinstatement)>,
+}
+
+/// The coins in the ledger against what the reward schedule can have
+/// emitted by the last block applied to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SupplyAudit {
+    pub height: u128,
+    pub total_supply: u128,
+    pub expected: u128,
+    // The least and the most the schedule can have emitted.
+    pub bounds: (u128, u128),
+}
+
+/// The hashes a NetworkState chains over the blocks it applies, which the
+/// ledger db doesn't otherwise hold. Flushed on shutdown along with the hash
+/// of the last block applied, they're only restored on top of that block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FlushedHashes {
+    last_applied: String,
+    credits: Option<String>,
+    debits: Option<String>,
+    state_hash: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SnapshotError(String);
+
+#[derive(Debug)]
+pub struct ComponentsError(String);
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NetworkState {
     // Path to database
     pub path: String,
-    // hash of the state of credits in the network
+    // hash of the state of credits in the network, chained from block to block
+    // by folding each block's credits into the last hash, see credit_hash.
     pub credits: Option<String>,
-    // hash of the state of debits in the network
+    // hash of the state of debits in the network, chained like the credits.
     pub debits: Option<String>,
     //reward state of the network
     pub reward_state: RewardState,
     // the last state hash -> sha256 hash of credits, debits & reward state.
     pub state_hash: Option<String>,
+    // root of the Merkle tree over the balances, the next block's header
+    // commits to it so light clients can check proofs of balances. It's also
+    // the balances' part of the ledger hash, see ledger_hash.
+    #[serde(default)]
+    pub ledger_root: Option<String>,
+    // hash of the claims, see Ledger::claims_hash, kept so the ledger hash
+    // doesn't rehash the claims on blocks that don't change them.
+    #[serde(default)]
+    pub claims_hash: Option<String>,
+    // canonical hash of the token ledger, None until a token is issued.
+    #[serde(default)]
+    pub tokens_hash: Option<String>,
+    // keys to write on the next dump, on top of those the block changes.
+    #[serde(skip)]
+    pub dirty: DirtyKeys,
+    #[serde(skip)]
+    claim_cache: Arc<ClaimCache>,
+}
+
+/// The one NetworkState the threads of a node share, instead of a clone each
+/// that drifts from the others. Reads take the read lock and mutations the
+/// write lock, so a reader sees the ledger either before or after a block is
+/// applied and never part way through, and the holder of the write lock is
+/// the ledger db's only writer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "NetworkState", into = "NetworkState")]
+pub struct SharedNetworkState {
+    inner: Arc<RwLock<NetworkState>>,
+}
+
+impl DirtyKeys {
+    pub fn all() -> DirtyKeys {
+        DirtyKeys {
+            balances: true,
+            claims: true,
+            tokens: true,
+        }
+    }
+}
+
+impl ClaimCache {
+    fn new(claims: LinkedHashMap<String, Claim>) -> ClaimCache {
+        ClaimCache {
+            loaded: true,
+            hash: ClaimMapHash::from_claim_map(&claims),
+            indexes: ClaimIndexes::from_claims(&claims),
+            claims,
+        }
+    }
+
+    /// Moves the cache on to `claims`, hashing and reindexing only the claims
+    /// that changed.
+    fn update(&mut self, claims: &LinkedHashMap<String, Claim>) {
+        let removed: Vec<String> = self
+            .claims
+            .keys()
+            .filter(|pubkey| !claims.contains_key(*pubkey))
+            .cloned()
+            .collect();
+        removed.iter().for_each(|pubkey| {
+            self.hash.remove(pubkey);
+            if let Some(claim) = self.claims.get(pubkey) {
+                self.indexes.remove(pubkey, claim);
+            }
+        });
+        claims.iter().for_each(|(pubkey, claim)| {
+            let cached = self.claims.get(pubkey);
+            if cached != Some(claim) {
+                self.hash.insert(pubkey, claim);
+                if let Some(cached) = cached {
+                    self.indexes.remove(pubkey, cached);
+                }
+                self.indexes.insert(pubkey, claim);
+            }
+        });
+        self.hash.refresh();
+        self.claims = claims.clone();
+        self.loaded = true;
+    }
+}
+
+impl fmt::Debug for ClaimCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClaimCache")
+            .field("loaded", &self.loaded)
+            .field("claims", &self.claims.len())
+            .field("hash", &self.hash.digest())
+            .field("indexes", &self.indexes.eligibility.len())
+            .finish()
+    }
+}
+
+impl ClaimIndexes {
+    pub fn from_claims(claims: &LinkedHashMap<String, Claim>) -> ClaimIndexes {
+        let mut indexes = ClaimIndexes::default();
+        let mut sorted: Vec<(&String, &Claim)> = claims.iter().collect();
+        sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+        sorted.into_iter().for_each(|(pubkey, claim)| {
+            indexes
+                .by_address
+                .entry(Address::canonicalize(&claim.address))
+                .or_insert_with(Vec::new)
+                .push(pubkey.clone());
+            indexes.by_hash.insert(claim.hash.clone(), pubkey.clone());
+            indexes.eligibility.insert(pubkey.clone(), claim.eligible);
+        });
+        indexes
+    }
+
+    /// Indexes the claim of `pubkey`, keeping its owner's pubkeys sorted.
+    fn insert(&mut self, pubkey: &str, claim: &Claim) {
+        let owned = self
+            .by_address
+            .entry(Address::canonicalize(&claim.address))
+            .or_insert_with(Vec::new);
+        if let Err(at) = owned.binary_search_by(|owned| owned.as_str().cmp(pubkey)) {
+            owned.insert(at, pubkey.to_string());
+        }
+        self.by_hash.insert(claim.hash.clone(), pubkey.to_string());
+        self.eligibility.insert(pubkey.to_string(), claim.eligible);
+    }
+
+    /// Drops the claim of `pubkey`, as it was indexed, from the indexes.
+    fn remove(&mut self, pubkey: &str, claim: &Claim) {
+        let address = Address::canonicalize(&claim.address);
+        if let Some(owned) = self.by_address.get_mut(&address) {
+            owned.retain(|owned| owned != pubkey);
+            if owned.is_empty() {
+                self.by_address.remove(&address);
+            }
+        }
+        if self.by_hash.get(&claim.hash).map(String::as_str) == Some(pubkey) {
+            self.by_hash.remove(&claim.hash);
+        }
+        self.eligibility.remove(pubkey);
+    }
 }
 
 impl NetworkState {
+    /// Loads the network state from the ledger db at `path`, first re-applying
+    /// any blocks whose dump was interrupted so that the db and the state
+    /// loaded from it agree.
     pub fn restore(path: &str) -> NetworkState {
-        let db = match PickleDb::load_bin(path, PickleDbDumpPolicy::DumpUponRequest) {
+        let mut network_state = NetworkState::load(path);
+        if network_state.recover() > 0 {
+            network_state = NetworkState::load(path);
+        }
+
+        network_state
+    }
+
+    fn load(path: &str) -> NetworkState {
+        let mut db = match PickleDb::load_bin(path, PickleDbDumpPolicy::DumpUponRequest) {
             Ok(nst) => nst,
             Err(_) => PickleDb::new(
                 path,
@@ -52,13 +325,52 @@ impl NetworkState {
             ),
         };
 
-        let (credits_map, debits_map, reward_state, _claims) =
+        let (mut credits_map, mut debits_map, reward_state, claims) =
             NetworkState::restore_state_objects(&db);
+        // Ledger dbs written before balances were keyed by canonical address.
+        if !db.exists("addressesmigrated") && !(credits_map.is_empty() && debits_map.is_empty()) {
+            credits_map = NetworkState::migrate_legacy_balances(&credits_map);
+            debits_map = NetworkState::migrate_legacy_balances(&debits_map);
+            if let Err(_) = db.set("credits", &credits_map) {
+                println!("Error setting migrated credits to state");
+            }
+            if let Err(_) = db.set("debits", &debits_map) {
+                println!("Error setting migrated debits to state");
+            }
+            // The hashes flushed before the migration chain over balances
+            // keyed by legacy addresses, the node goes on with the hashes of
+            // the migrated balances instead.
+            if let Err(_) = db.rem("flushedhashes") {
+                println!("Error removing flushed hashes from state");
+            }
+            if let Err(_) = db.set("addressesmigrated", &true) {
+                println!("Error setting address migration to state");
+            }
+            if let Err(e) = db.dump() {
+                error!(target: STATE_TARGET, "Error dumping state to file: {:?}", e)
+            }
+        }
+        // Ledger dbs written before the claims were indexed.
+        if !claims.is_empty() && !db.exists("claims_by_hash") {
+            if let Err(_) = NetworkState::set_claims(&mut db, &claims) {
+                println!("Error setting claims to state");
+            }
+            if let Err(e) = db.dump() {
+                error!(target: STATE_TARGET, "Error dumping state to file: {:?}", e)
+            }
+        }
 
-        let credits = digest_bytes(format!("{:?}", &credits_map).as_bytes());
-        let debits = digest_bytes(format!("{:?}", &credits_map).as_bytes());
+        let credits = digest_bytes(NetworkState::canonical_map_string(&credits_map).as_bytes());
+        let debits = digest_bytes(NetworkState::canonical_map_string(&debits_map).as_bytes());
+        let ledger_root = balance_proof::ledger_root(&credits_map, &debits_map);
+        let tokens_hash = TokenLedger {
+            tokens: NetworkState::restore_tokens(&db),
+            credits: NetworkState::restore_token_balances(&db, "token_credits"),
+            debits: NetworkState::restore_token_balances(&db, "token_debits"),
+        }
+        .hash();
 
-        NetworkState {
+        let mut network_state = NetworkState {
             path: path.to_string(),
             credits: {
                 if credits_map.is_empty() {
@@ -76,7 +388,43 @@ impl NetworkState {
             },
             reward_state,
             state_hash: None,
+            ledger_root: Some(ledger_root),
+            claims_hash: Some(Ledger::claims_hash(&claims)),
+            tokens_hash,
+            dirty: DirtyKeys::default(),
+            claim_cache: Arc::new(ClaimCache::new(claims)),
+        };
+        // Hashes flushed before a block was applied after them are stale.
+        let last_applied: Option<(u128, String)> = db.get("lastapplied");
+        let flushed: Option<FlushedHashes> = db.get("flushedhashes");
+        if let (Some((_, hash)), Some(flushed)) = (last_applied, flushed) {
+            if flushed.last_applied == hash {
+                network_state.credits = flushed.credits;
+                network_state.debits = flushed.debits;
+                network_state.state_hash = flushed.state_hash;
+            }
+        }
+
+        network_state
+    }
+
+    /// Writes the hashes chained over the blocks applied so far to the ledger
+    /// db, so a node shut down cleanly picks up where it left off. The ledger
+    /// itself is written with every block.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut db = self.get_staged_ledger_db();
+        let last_applied: Option<(u128, String)> = db.get("lastapplied");
+        if let Some((_, hash)) = last_applied {
+            let flushed = FlushedHashes {
+                last_applied: hash,
+                credits: self.credits.clone(),
+                debits: self.debits.clone(),
+                state_hash: self.state_hash.clone(),
+            };
+            db.set("flushedhashes", &flushed)?;
         }
+
+        self.persist(&mut db)
     }
 
     pub fn get_balance(&self, address: &str) -> u128 {
@@ -90,63 +438,302 @@ impl NetworkState {
         }
     }
 
+    /// The balance of `token_id` held by the address, separate from its
+    /// native balance.
+    pub fn get_token_balance(&self, address: &str, token_id: &str) -> u128 {
+        let db = self.get_ledger_db();
+        let address = Address::canonicalize(address);
+        let amount = |key: &str| {
+            NetworkState::restore_token_balances(&db, key)
+                .get(token_id)
+                .and_then(|balances| balances.get(&address).copied())
+                .unwrap_or(0)
+        };
+
+        amount("token_credits").saturating_sub(amount("token_debits"))
+    }
+
+    pub fn get_token(&self, token_id: &str) -> Option<Token> {
+        NetworkState::restore_tokens(&self.get_ledger_db()).remove(token_id)
+    }
+
+    pub fn restore_tokens(db: &PickleDb) -> LinkedHashMap<String, Token> {
+        if let Some(map) = db.get("tokens") {
+            map
+        } else {
+            LinkedHashMap::new()
+        }
+    }
+
+    /// The token registry and every token balance.
+    pub fn get_token_ledger(&self) -> TokenLedger {
+        let db = self.get_ledger_db();
+        TokenLedger {
+            tokens: NetworkState::restore_tokens(&db),
+            credits: NetworkState::restore_token_balances(&db, "token_credits"),
+            debits: NetworkState::restore_token_balances(&db, "token_debits"),
+        }
+    }
+
+    /// Token credits or debits, keyed by token id and then by address.
+    pub fn restore_token_balances(
+        db: &PickleDb,
+        key: &str,
+    ) -> LinkedHashMap<String, LinkedHashMap<String, u128>> {
+        if let Some(map) = db.get(key) {
+            map
+        } else {
+            LinkedHashMap::new()
+        }
+    }
+
+    pub fn restore_activity(db: &PickleDb) -> LinkedHashMap<String, Vec<AddressEvent>> {
+        if let Some(map) = db.get("activity") {
+            map
+        } else {
+            LinkedHashMap::new()
+        }
+    }
+
+    pub fn restore_offenses(db: &PickleDb) -> LinkedHashMap<String, OffenseHistory> {
+        if let Some(map) = db.get("offenses") {
+            map
+        } else {
+            LinkedHashMap::new()
+        }
+    }
+
+    /// The downtime blocks granted each claim whose owner announced it, by
+    /// pubkey.
+    pub fn restore_downtime(db: &PickleDb) -> LinkedHashMap<String, Vec<DowntimeWindow>> {
+        if let Some(map) = db.get("downtime") {
+            map
+        } else {
+            LinkedHashMap::new()
+        }
+    }
+
+    pub fn get_offense_history(&self, pubkey: &str) -> Option<OffenseHistory> {
+        let db = self.get_ledger_db();
+        NetworkState::restore_offenses(&db).get(pubkey).cloned()
+    }
+
+    /// Returns the balance changes recorded for the address, oldest first. Past
+    /// MAX_ADDRESS_EVENTS the oldest are carried over as a single event.
+    pub fn get_address_activity(&self, address: &str) -> Vec<AddressEvent> {
+        let db = self.get_ledger_db();
+        let activity = NetworkState::restore_activity(&db);
+        if let Some(events) = activity.get(&Address::canonicalize(address)) {
+            events.clone()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Writes the address' activity with a running balance to `path` and returns
+    /// the number of rows written. The export is refused if the running balance
+    /// doesn't reconcile with `get_balance`, e.g. after a snapshot import which
+    /// carries balances but no history.
+    pub fn export_address_activity(
+        &self,
+        address: &str,
+        format: ExportFormat,
+        path: &str,
+    ) -> Result<usize, Box<dyn Error>> {
+        let events = self.get_address_activity(address);
+        let report = ActivityReport::new(
+            &Address::canonicalize(address),
+            &events,
+            self.get_balance(address),
+        )?;
+        report.write(format, path)
+    }
+
     pub fn credit_hash(self, block: &Block) -> String {
         let mut credits = LinkedHashMap::new();
 
-        block.txns.iter().for_each(|(_txn_id, txn)| {
-            if let Some(entry) = credits.get_mut(&txn.receiver_address) {
-                *entry += txn.clone().txn_amount
-            } else {
-                credits.insert(txn.clone().receiver_address, txn.clone().txn_amount);
-            }
-        });
+        block
+            .txns
+            .iter()
+            .filter(|(_txn_id, txn)| {
+                !txn.is_claim_reinstatement()
+                    && !txn.is_coinbase()
+                    && !txn.is_token_txn()
+                    && !txn.is_slashing_evidence()
+                    && !txn.is_planned_downtime()
+            })
+            .for_each(|(_txn_id, txn)| {
+                let receiver = Address::canonicalize(&txn.receiver_address);
+                if let Some(entry) = credits.get_mut(&receiver) {
+                    *entry += txn.clone().txn_amount
+                } else {
+                    credits.insert(receiver, txn.clone().txn_amount);
+                }
+            });
 
-        if let Some(entry) = credits.get_mut(&block.header.block_reward.miner.clone().unwrap()) {
+        let miner = Address::canonicalize(&block.header.block_reward.miner.clone().unwrap());
+        let fees: u128 = block
+            .txns
+            .values()
+            .filter(|txn| !txn.is_coinbase())
+            .map(|txn| txn.txn_fee)
+            .sum();
+        if fees > 0 {
+            *credits.entry(miner.clone()).or_insert(0) += fees;
+        }
+        if let Some(entry) = credits.get_mut(&miner) {
             *entry += block.header.block_reward.amount
         } else {
-            credits.insert(
-                block.header.block_reward.miner.clone().unwrap(),
-                block.header.block_reward.amount,
-            );
+            credits.insert(miner, block.header.block_reward.amount);
         }
 
+        let credits = NetworkState::canonical_map_string(&credits);
         if let Some(chs) = self.credits {
-            return digest_bytes(format!("{},{:?}", chs, credits).as_bytes());
+            return digest_bytes(format!("{},{}", chs, credits).as_bytes());
         } else {
-            return digest_bytes(format!("{:?},{:?}", self.credits, credits).as_bytes());
+            return digest_bytes(format!("{:?},{}", self.credits, credits).as_bytes());
         }
     }
 
     pub fn debit_hash(self, block: &Block) -> String {
         let mut debits = LinkedHashMap::new();
+        // Replays the reinstatement requests, a rejected one locks no bond.
+        let mut offenses = if block.txns.values().any(|txn| txn.is_claim_reinstatement()) {
+            NetworkState::restore_offenses(&self.get_ledger_db())
+        } else {
+            LinkedHashMap::new()
+        };
 
-        block.txns.iter().for_each(|(_txn_id, txn)| {
-            if let Some(entry) = debits.get_mut(&txn.sender_address) {
-                *entry += txn.clone().txn_amount
-            } else {
-                debits.insert(txn.clone().sender_address, txn.clone().txn_amount);
-            }
-        });
+        block
+            .txns
+            .iter()
+            .filter(|(_txn_id, txn)| !txn.is_coinbase())
+            .for_each(|(txn_id, txn)| {
+                let sender = Address::canonicalize(&txn.sender_address);
+                let rejected = txn.is_claim_reinstatement()
+                    && NetworkState::request_reinstatement(
+                        &mut offenses,
+                        txn_id,
+                        txn,
+                        block.header.block_height,
+                    )
+                    .is_err();
+                // A token txn only takes the fee in the native coin.
+                let amount = if rejected {
+                    txn.txn_fee
+                } else {
+                    txn.native_cost()
+                };
+                if amount == 0 {
+                    return;
+                }
+                if let Some(entry) = debits.get_mut(&sender) {
+                    *entry += amount
+                } else {
+                    debits.insert(sender, amount);
+                }
+            });
 
+        let debits = NetworkState::canonical_map_string(&debits);
         if let Some(dhs) = self.debits {
-            return digest_bytes(format!("{},{:?}", dhs, debits).as_bytes());
+            return digest_bytes(format!("{},{}", dhs, debits).as_bytes());
         } else {
-            return digest_bytes(format!("{:?},{:?}", self.debits, debits).as_bytes());
+            return digest_bytes(format!("{:?},{}", self.debits, debits).as_bytes());
         }
     }
 
+    /// Starts the reinstatement a txn requests for its claim, returning the
+    /// height the claim will be reinstated at. A rejected request locks no bond.
+    fn request_reinstatement(
+        offenses: &mut LinkedHashMap<String, OffenseHistory>,
+        txn_id: &str,
+        txn: &Txn,
+        block_height: u128,
+    ) -> Result<u128, ReinstatementError> {
+        let owner = Address::canonicalize(&txn.sender_address);
+        offenses
+            .entry(txn.sender_public_key.clone())
+            .or_insert_with(OffenseHistory::new)
+            .request(txn_id, &owner, txn.txn_amount, block_height)
+    }
+
+    /// Formats a map with its entries sorted by key, so that nodes which inserted
+    /// the same entries in a different order still compute the same hash.
+    pub fn canonical_map_string<V: fmt::Debug>(map: &LinkedHashMap<String, V>) -> String {
+        let sorted: BTreeMap<&String, &V> = map.iter().collect();
+        format!("{:?}", sorted)
+    }
+
+    /// The canonical hash of the balances: every (address, amount) pair is
+    /// hashed and the hashes, sorted by kind and address, are combined in a
+    /// Merkle tree, see balance_proof::ledger_root. Unlike the chained credit
+    /// and debit hashes it can be recomputed from the balances alone.
+    pub fn balances_hash(
+        credits: &LinkedHashMap<String, u128>,
+        debits: &LinkedHashMap<String, u128>,
+    ) -> String {
+        balance_proof::ledger_root(credits, debits)
+    }
+
     pub fn hash(&mut self, block: Block) -> String {
         let credit_hash = self.clone().credit_hash(&block);
         let debit_hash = self.clone().debit_hash(&block);
         let reward_state_hash = digest_bytes(format!("{:?}", self.reward_state).as_bytes());
-        let payload = format!(
+        let mut payload = format!(
             "{:?},{:?},{:?},{:?}",
             self.state_hash, credit_hash, debit_hash, reward_state_hash
         );
+        if let Some(token_hash) = NetworkState::token_txns_hash(&block) {
+            payload = format!("{},{}", payload, token_hash);
+        }
         let new_state_hash = digest_bytes(payload.as_bytes());
         new_state_hash
     }
 
+    /// The hash of the token each token txn in the block moves, between whom
+    /// and how much, sorted by txn id. The native credit and debit hashes leave
+    /// token amounts out, so the state hash commits to them through this one.
+    /// None for a block without token txns, whose state hash is computed as it
+    /// was before tokens.
+    pub fn token_txns_hash(block: &Block) -> Option<String> {
+        let token_txns: BTreeMap<&String, String> = block
+            .txns
+            .iter()
+            .filter(|(_, txn)| txn.is_token_txn())
+            .map(|(txn_id, txn)| {
+                let token_txn = format!(
+                    "{:?},{:?},{},{},{}",
+                    txn.kind,
+                    txn.txn_token,
+                    Address::canonicalize(&txn.sender_address),
+                    Address::canonicalize(&txn.receiver_address),
+                    txn.txn_amount
+                );
+                (txn_id, token_txn)
+            })
+            .collect();
+        if token_txns.is_empty() {
+            return None;
+        }
+
+        Some(digest_bytes(format!("{:?}", token_txns).as_bytes()))
+    }
+
+    /// Re-keys balances written under legacy addresses by their canonical
+    /// address, adding up an account's balances written under both forms.
+    fn migrate_legacy_balances(
+        balances: &LinkedHashMap<String, u128>,
+    ) -> LinkedHashMap<String, u128> {
+        let mut migrated = LinkedHashMap::new();
+        balances.iter().for_each(|(address, amount)| {
+            let canonical = Address::parse_with(address, AddressNetwork::default(), true)
+                .map_or_else(|_| address.clone(), |address| address.to_string());
+            *migrated.entry(canonical).or_insert(0) += amount;
+        });
+
+        migrated
+    }
+
     pub fn restore_state_objects(
         db: &PickleDb,
     ) -> (
@@ -182,92 +769,488 @@ impl NetworkState {
         (credits, debits, reward_state, claims)
     }
 
+    /// Applies a block to the ledger db. The block is logged to the db's
+    /// write-ahead log first and marked committed once the db is dumped, so a
+    /// crash in between is recovered from by `restore`. A block at or below
+    /// the height of the last block applied is refused, a restarted node
+    /// replaying its chain doesn't apply a block twice.
     pub fn dump(&mut self, block: &Block) {
-        let mut db = self.get_ledger_db();
+        if let (Category::Genesis(_), Some(genesis_miner)) =
+            (block.header.block_reward.category, self.genesis_miner())
+        {
+            warn!(
+                target: STATE_TARGET,
+                "second genesis reward refused hash={} genesis_miner={}",
+                block.hash,
+                genesis_miner
+            );
+            return;
+        }
+        if let Some((height, hash)) = self.last_applied() {
+            if block.header.block_height <= height {
+                warn!(
+                    target: STATE_TARGET,
+                    "stale block refused height={} hash={} last_applied={}",
+                    block.header.block_height,
+                    block.hash,
+                    hash
+                );
+                return;
+            }
+        }
+        let wal = BlockWal::for_db(&self.path);
+        if let Err(e) = wal.begin(block) {
+            error!(target: STATE_TARGET, "Error logging block to the write-ahead log: {:?}", e);
+        }
+        let (mut db, claims) = self.write_block(block);
+        match self.persist(&mut db) {
+            Ok(_) => {
+                if let Some(claims) = claims {
+                    self.cache_claims(&claims);
+                }
+                if let Err(e) = wal.commit(&block.hash) {
+                    error!(
+                        target: STATE_TARGET,
+                        "Error committing block to the write-ahead log: {:?}",
+                        e
+                    );
+                }
+            }
+            Err(e) => error!(target: STATE_TARGET, "Error dumping state to file: {:?}", e),
+        }
+    }
+
+    /// Re-applies the blocks left uncommitted in the write-ahead log and
+    /// returns how many were. A block the db was dumped with before the crash
+    /// is only marked committed.
+    fn recover(&mut self) -> usize {
+        let wal = BlockWal::for_db(&self.path);
+        let mut reapplied = 0;
+        for block in wal.uncommitted() {
+            // A block at or below the last applied height was either applied
+            // or would be refused by dump.
+            let persisted = match self.last_applied() {
+                Some((height, _)) => block.header.block_height <= height,
+                None => false,
+            };
+            if persisted {
+                if let Err(e) = wal.commit(&block.hash) {
+                    error!(
+                        target: STATE_TARGET,
+                        "Error committing block to the write-ahead log: {:?}",
+                        e
+                    );
+                }
+            } else {
+                warn!(
+                    target: STATE_TARGET,
+                    "re-applying interrupted block height={} hash={}",
+                    block.header.block_height,
+                    block.hash
+                );
+                self.dump(&block);
+                reapplied += 1;
+            }
+        }
+
+        reapplied
+    }
+
+    /// Applies a block to a staged copy of the ledger db without dumping it,
+    /// returning the db and the claims if the block changed them.
+    fn write_block(&mut self, block: &Block) -> (PickleDb, Option<LinkedHashMap<String, Claim>>) {
+        let mut db = self.get_staged_ledger_db();
         let (mut credits, mut debits, mut reward_state, mut claims) =
             NetworkState::restore_state_objects(&db);
+        let mut offenses = NetworkState::restore_offenses(&db);
+        let mut downtime = NetworkState::restore_downtime(&db);
+        let mut downtime_granted = false;
+        let mut activity = NetworkState::restore_activity(&db);
+        let mut tokens = NetworkState::restore_tokens(&db);
+        let mut token_credits = NetworkState::restore_token_balances(&db, "token_credits");
+        let mut token_debits = NetworkState::restore_token_balances(&db, "token_debits");
+        let block_height = block.header.block_height;
+        let mut dirty = self.dirty;
+
+        let fee_receiver = block
+            .header
+            .block_reward
+            .miner
+            .as_ref()
+            .map(|miner| Address::canonicalize(miner));
+        block.txns.iter().for_each(|(txn_id, txn)| {
+            // The coinbase records the block reward, credited from the header.
+            if txn.is_coinbase() {
+                return;
+            }
+            // Evidence moves no coins, the claim it slashes is applied with
+            // the block's other claim changes.
+            if txn.is_slashing_evidence() {
+                return;
+            }
+            // Downtime is granted from the block's timestamp, so every node
+            // applying the block keeps the claim away for as long.
+            if let Some(announced) = txn.downtime() {
+                let windows = downtime
+                    .entry(announced.claim_pubkey.clone())
+                    .or_insert_with(Vec::new);
+                match announced.grant(windows, block.header.timestamp) {
+                    Ok(until) => {
+                        info!(
+                            target: STATE_TARGET,
+                            "claim announced downtime pubkey={} until={}",
+                            announced.claim_pubkey,
+                            until
+                        );
+                        downtime_granted = true;
+                    }
+                    Err(e) => warn!(
+                        target: STATE_TARGET,
+                        "planned downtime not granted pubkey={} reason={}",
+                        announced.claim_pubkey,
+                        e
+                    ),
+                }
+                return;
+            }
+            // Every other txn pays its fee to the miner, whatever it does.
+            if let Some(miner) = fee_receiver.as_ref().filter(|_| txn.txn_fee > 0) {
+                let sender = Address::canonicalize(&txn.sender_address);
+                *debits.entry(sender).or_insert(0) += txn.txn_fee;
+                *credits.entry(miner.clone()).or_insert(0) += txn.txn_fee;
+                dirty.balances = true;
+            }
+            // The bond is only locked if the request is accepted, it is credited
+            // back to the owner when the claim is reinstated.
+            if txn.is_claim_reinstatement() {
+                let owner = Address::canonicalize(&txn.sender_address);
+                let request =
+                    NetworkState::request_reinstatement(&mut offenses, txn_id, txn, block_height);
+                match request {
+                    Ok(release_height) => {
+                        info!(
+                            target: STATE_TARGET,
+                            "Claim {} will be reinstated at height {}",
+                            txn.sender_public_key, release_height
+                        );
+                        *debits.entry(owner.clone()).or_insert(0) += txn.txn_amount;
+                        dirty.balances = true;
+                        record_event(
+                            activity.entry(owner).or_insert_with(Vec::new),
+                            AddressEvent {
+                                timestamp: txn.txn_timestamp,
+                                block_height,
+                                txn_id: txn_id.clone(),
+                                direction: Direction::Out,
+                                counterparty: String::new(),
+                                amount: txn.txn_amount,
+                            },
+                        );
+                    }
+                    Err(e) => println!("Rejected claim reinstatement {}: {}", txn_id, e),
+                }
+                return;
+            }
+
+            // A claim transfer pays its price like any transfer, the claim is
+            // rekeyed to the buyer with the block's other claim changes.
+
+            // Token txns move only the token, they don't touch native balances.
+            if let Some(token_id) = &txn.txn_token {
+                let receiver = Address::canonicalize(&txn.receiver_address);
+                if let Some(token) = Token::from_issuance(txn) {
+                    if tokens.contains_key(token_id) {
+                        return;
+                    }
+                    tokens.insert(token_id.clone(), token);
+                } else if !tokens.contains_key(token_id) {
+                    return;
+                } else {
+                    let sender = Address::canonicalize(&txn.sender_address);
+                    *token_debits
+                        .entry(token_id.clone())
+                        .or_insert_with(LinkedHashMap::new)
+                        .entry(sender)
+                        .or_insert(0) += txn.txn_amount;
+                }
+                *token_credits
+                    .entry(token_id.clone())
+                    .or_insert_with(LinkedHashMap::new)
+                    .entry(receiver)
+                    .or_insert(0) += txn.txn_amount;
+                dirty.tokens = true;
+                return;
+            }
 
-        block.txns.iter().for_each(|(_txn_id, txn)| {
-            if let Some(entry) = credits.get_mut(&txn.receiver_address) {
+            dirty.balances = true;
+            let receiver = Address::canonicalize(&txn.receiver_address);
+            if let Some(entry) = credits.get_mut(&receiver) {
                 *entry += txn.clone().txn_amount
             } else {
-                credits.insert(txn.clone().receiver_address, txn.clone().txn_amount);
+                credits.insert(receiver, txn.clone().txn_amount);
             }
 
-            if let Some(entry) = debits.get_mut(&txn.clone().sender_address) {
+            let sender = Address::canonicalize(&txn.sender_address);
+            if let Some(entry) = debits.get_mut(&sender) {
                 *entry += txn.txn_amount
             } else {
-                debits.insert(txn.clone().sender_address, txn.clone().txn_amount);
+                debits.insert(sender, txn.clone().txn_amount);
             }
         });
 
-        block.claims.iter().for_each(|(k, v)| {
-            claims.insert(k.clone(), v.clone());
-        });
-
-        claims.insert(
-            block.header.claim.clone().pubkey,
-            block.header.claim.clone(),
+        let changes = NetworkState::apply_block_claims(
+            &mut claims,
+            &mut offenses,
+            &block.header.claim,
+            &block.claims,
+            &block.txns,
+            block_height,
         );
+        dirty.claims |= changes.changed;
+        changes.slashed.iter().for_each(|pubkey| {
+            warn!(
+                target: STATE_TARGET,
+                "claim slashed pubkey={} height={}",
+                pubkey,
+                block_height
+            );
+        });
+        changes.transferred.iter().for_each(|(pubkey, to)| {
+            info!(
+                target: STATE_TARGET,
+                "claim transferred pubkey={} to={}",
+                pubkey,
+                to
+            );
+        });
+        changes.rejected.iter().for_each(|reason| {
+            warn!(target: STATE_TARGET, "claim change rejected {}", reason);
+        });
+        // Claims that waited out their reinstatement delay get their bond back.
+        changes.released.iter().for_each(|(pubkey, reinstatement)| {
+            info!(
+                target: STATE_TARGET,
+                "Claim {} reinstated at height {}",
+                pubkey,
+                block_height
+            );
+            *credits.entry(reinstatement.owner.clone()).or_insert(0) += reinstatement.bond;
+            dirty.balances = true;
+            record_event(
+                activity
+                    .entry(reinstatement.owner.clone())
+                    .or_insert_with(Vec::new),
+                AddressEvent {
+                    timestamp: block.header.timestamp,
+                    block_height,
+                    txn_id: reinstatement.txn_id.clone(),
+                    direction: Direction::In,
+                    counterparty: String::new(),
+                    amount: reinstatement.bond,
+                },
+            );
+        });
 
-        if let Some(entry) = credits.get_mut(&block.header.block_reward.miner.clone().unwrap()) {
+        let miner = Address::canonicalize(&block.header.block_reward.miner.clone().unwrap());
+        if block.header.block_reward.amount > 0 || !credits.contains_key(&miner) {
+            dirty.balances = true;
+        }
+        if let Category::Genesis(_) = block.header.block_reward.category {
+            if let Err(_) = db.set("genesisminer", &miner) {
+                println!("Error setting genesis miner to state")
+            };
+        }
+        if let Some(entry) = credits.get_mut(&miner) {
             *entry += block.header.block_reward.amount.clone()
         } else {
-            credits.insert(
-                block.header.block_reward.miner.clone().unwrap().clone(),
-                block.header.block_reward.amount.clone(),
-            );
+            credits.insert(miner, block.header.block_reward.amount.clone());
         }
 
+        AddressEvent::from_block(block)
+            .into_iter()
+            .for_each(|(address, event)| {
+                record_event(activity.entry(address).or_insert_with(Vec::new), event);
+            });
+
         reward_state.update(block.header.block_reward.category.clone());
         self.update_state_hash(&block);
         self.update_reward_state(&block);
         self.update_credits_and_debits(&block);
+        self.ledger_root = Some(NetworkState::balances_hash(&credits, &debits));
+        if dirty.tokens {
+            self.tokens_hash = TokenLedger {
+                tokens: tokens.clone(),
+                credits: token_credits.clone(),
+                debits: token_debits.clone(),
+            }
+            .hash();
+        }
 
-        if let Err(_) = db.set("credits", &credits) {
-            println!("Error setting credits to state")
-        };
-        if let Err(_) = db.set("debits", &debits) {
-            println!("Error setting debits to state")
-        };
+        if dirty.balances {
+            if let Err(_) = db.set("credits", &credits) {
+                println!("Error setting credits to state")
+            };
+            if let Err(_) = db.set("debits", &debits) {
+                println!("Error setting debits to state")
+            };
+        }
         if let Err(_) = db.set("rewardstate", &reward_state) {
             println!("Error setting reward state to state")
         };
-        if let Err(_) = db.set("claims", &claims) {
-            println!("Error setting claims to state");
+        // The block that leaves no claim a pointer for the next block's nonce
+        // moves the ledger to the next nonce epoch, so every node applying
+        // it bumps the claims at the same height.
+        let away = NetworkState::away_at(&downtime, block.header.timestamp);
+        let epochs = NetworkState::nonce_up_until_pointed(
+            &mut claims,
+            &away,
+            u128::from(block.header.next_block_nonce),
+        );
+        if epochs > 0 {
+            let epoch = db.get::<u128>("nonceepoch").unwrap_or(0) + epochs;
+            info!(
+                target: STATE_TARGET,
+                "claims nonced up height={} epoch={}",
+                block_height,
+                epoch
+            );
+            if let Err(_) = db.set("nonceepoch", &epoch) {
+                println!("Error setting nonce epoch to state");
+            };
+            dirty.claims = true;
+        }
+        if dirty.claims {
+            if let Err(_) = NetworkState::set_claims(&mut db, &claims) {
+                println!("Error setting claims to state");
+            };
+        }
+        if dirty.claims || !db.exists(ELIGIBLE_VALIDATORS_KEY) {
+            NetworkState::record_eligible_validators(&mut db, &claims, block_height + 1);
+        }
+        if dirty.tokens {
+            if let Err(_) = db.set("tokens", &tokens) {
+                println!("Error setting token registry to state");
+            };
+            if let Err(_) = db.set("token_credits", &token_credits) {
+                println!("Error setting token credits to state");
+            };
+            if let Err(_) = db.set("token_debits", &token_debits) {
+                println!("Error setting token debits to state");
+            };
+        }
+        self.dirty = DirtyKeys::default();
+        if let Err(_) = db.set("activity", &activity) {
+            println!("Error setting address activity to state");
+        };
+        if let Err(_) = db.set("offenses", &offenses) {
+            println!("Error setting claim offenses to state");
+        };
+        if downtime_granted {
+            if let Err(_) = db.set("downtime", &downtime) {
+                println!("Error setting claim downtime to state");
+            };
+        }
+        if let Err(_) = db.set("lastapplied", &(block_height, block.hash.clone())) {
+            println!("Error setting last applied block to state");
+        };
+        if let Err(_) = db.set("lastappliedat", &block.header.timestamp) {
+            println!("Error setting last applied block timestamp to state");
         };
-        if let Err(e) = db.dump() {
-            info!("Error dumping state to file: {:?}", e)
+
+        if dirty.claims {
+            (db, Some(claims))
+        } else {
+            (db, None)
+        }
+    }
+
+    /// The number of times every claim's nonce has been bumped since genesis,
+    /// 0 for ledger dbs written before it was recorded.
+    pub fn nonce_epoch(&self) -> u128 {
+        self.get_ledger_db().get("nonceepoch").unwrap_or(0)
+    }
+
+    // Bumps every claim's nonce until one of the claims that aren't `away`
+    // has a pointer for `nonce`, at most MAX_NONCE_EPOCHS_PER_BLOCK times,
+    // and returns how many times they were bumped.
+    fn nonce_up_until_pointed(
+        claims: &mut LinkedHashMap<String, Claim>,
+        away: &[String],
+        nonce: u128,
+    ) -> u128 {
+        let mut epochs = 0;
+        loop {
+            let mut present = claims
+                .values()
+                .filter(|claim| !away.contains(&claim.pubkey))
+                .peekable();
+            if present.peek().is_none()
+                || epochs >= MAX_NONCE_EPOCHS_PER_BLOCK
+                || present.any(|claim| claim.get_pointer(nonce).is_some())
+            {
+                return epochs;
+            }
+            claims.values_mut().for_each(|claim| claim.nonce_up());
+            epochs += 1;
         }
     }
 
-    pub fn nonce_up(&mut self) {
+    /// Bumps every claim's nonce to the next nonce epoch and returns it.
+    pub fn nonce_up(&mut self) -> u128 {
+        let epoch = self.nonce_epoch() + 1;
+        self.nonce_up_to(epoch);
+        epoch
+    }
+
+    /// Bumps every claim's nonce once for each epoch the ledger is behind
+    /// `epoch` and records `epoch`, in a single write to the ledger db so the
+    /// claims and the epoch never disagree. Returns false, having written
+    /// nothing, if the ledger is already at `epoch`, so applying the same
+    /// bump twice is a no-op.
+    pub fn nonce_up_to(&mut self, epoch: u128) -> bool {
+        let current = self.nonce_epoch();
+        if epoch <= current {
+            return false;
+        }
+
         let mut new_claim_map = LinkedHashMap::new();
-        self.get_claims().clone().iter().for_each(|(pk, claim)| {
+        self.get_claims().iter().for_each(|(pk, claim)| {
             let mut new_claim = claim.clone();
-            new_claim.nonce_up();
-            new_claim_map.insert(pk.clone(), new_claim.clone());
+            (current..epoch).for_each(|_| new_claim.nonce_up());
+            new_claim_map.insert(pk.clone(), new_claim);
         });
         let mut db = self.get_ledger_db();
-        if let Err(e) = db.set("claims", &new_claim_map) {
+        if let Err(e) = NetworkState::set_claims(&mut db, &new_claim_map) {
             println!("Error setting nonced up claims to database: {:?}", e);
         }
+        if let Err(e) = db.set("nonceepoch", &epoch) {
+            println!("Error setting nonce epoch to database: {:?}", e);
+        }
+        match db.dump() {
+            Ok(_) => self.cache_claims(&new_claim_map),
+            Err(e) => error!(target: STATE_TARGET, "Error dumping state to file: {:?}", e),
+        }
+
+        true
     }
 
     pub fn abandoned_claim(&mut self, hash: String) {
         let mut db = self.get_ledger_db();
+        let pubkey = if let Some(pubkey) = self.get_claim_by_hash(&hash) {
+            pubkey
+        } else {
+            return;
+        };
         let (_, _, _, mut claims) = NetworkState::restore_state_objects(&db);
+        claims.remove(&pubkey);
 
-        claims.retain(|_, v| {
-            v.hash != hash
-        });
-
-        if let Err(_) = db.set("claims", &claims) {
+        if let Err(_) = NetworkState::set_claims(&mut db, &claims) {
             println!("Error setting claims to state")
         };
 
-        if let Err(e) = db.dump() {
-            info!("Error dumping state to file: {:?}", e)
+        match db.dump() {
+            Ok(_) => self.cache_claims(&claims),
+            Err(e) => error!(target: STATE_TARGET, "Error dumping state to file: {:?}", e),
         }
     }
 
@@ -284,13 +1267,99 @@ impl NetworkState {
         };
     }
 
-    pub fn update_credits_and_debits(&mut self, block: &Block) {
-        let chs = self.clone().credit_hash(block);
-        let dhs = self.clone().debit_hash(block);
-        self.credits = Some(chs);
+    fn staging_path(&self) -> String {
+        format!("{}.staging", self.path)
+    }
+
+    /// The ledger db loaded from a staging file next to it. Dumping it writes
+    /// the staging file, the ledger db is left as it was until `persist`
+    /// renames the staging file over it.
+    fn get_staged_ledger_db(&self) -> PickleDb {
+        let staging_path = self.staging_path();
+        let _ = fs::remove_file(&staging_path);
+        // The staging file only needs the db's contents to load from, a hard
+        // link avoids copying them. The dump replaces the link with a new file.
+        if fs::hard_link(&self.path, &staging_path).is_err() {
+            let _ = fs::copy(&self.path, &staging_path);
+        }
+
+        match PickleDb::load_bin(&staging_path, PickleDbDumpPolicy::DumpUponRequest) {
+            Ok(db) => db,
+            Err(_) => PickleDb::new(
+                &staging_path,
+                PickleDbDumpPolicy::DumpUponRequest,
+                SerializationMethod::Bin,
+            ),
+        }
+    }
+
+    /// Dumps a staged db and syncs it to disk.
+    fn write_staged(&self, db: &mut PickleDb) -> Result<(), Box<dyn Error>> {
+        db.dump()?;
+        fs::File::open(self.staging_path())?.sync_all()?;
+        Ok(())
+    }
+
+    /// Writes a staged db and atomically renames it over the ledger db, so an
+    /// interrupted write never leaves a partially written ledger db behind.
+    fn persist(&self, db: &mut PickleDb) -> Result<(), Box<dyn Error>> {
+        self.write_staged(db)?;
+        fs::rename(self.staging_path(), &self.path)?;
+        Ok(())
+    }
+
+    pub fn update_credits_and_debits(&mut self, block: &Block) {
+        let chs = self.clone().credit_hash(block);
+        let dhs = self.clone().debit_hash(block);
+        self.credits = Some(chs);
         self.debits = Some(dhs);
     }
 
+    /// The pubkeys of the claims whose votes count towards the quorum of the
+    /// txns in the block at `height`, those eligible once the block below it
+    /// was applied. A block is checked against the validators of its own
+    /// height, whatever happened to the claims since. Heights older than the
+    /// history kept, or before it was, get the claims eligible now.
+    pub fn eligible_validators(&self, height: u128) -> HashSet<String> {
+        let history: BTreeMap<u128, Vec<String>> = self
+            .get_ledger_db()
+            .get(ELIGIBLE_VALIDATORS_KEY)
+            .unwrap_or_default();
+        match history.range(..=height).next_back() {
+            Some((_, eligible)) => eligible.iter().cloned().collect(),
+            None => QuorumConfig::eligible_validators(&self.get_claims()),
+        }
+    }
+
+    // Records the eligible validators from `height` on, dropping the sets
+    // replaced before the last VALIDATOR_HISTORY heights.
+    fn record_eligible_validators(
+        db: &mut PickleDb,
+        claims: &LinkedHashMap<String, Claim>,
+        height: u128,
+    ) {
+        let mut history: BTreeMap<u128, Vec<String>> =
+            db.get(ELIGIBLE_VALIDATORS_KEY).unwrap_or_default();
+        let mut eligible: Vec<String> = QuorumConfig::eligible_validators(claims)
+            .into_iter()
+            .collect();
+        eligible.sort();
+        history.insert(height, eligible);
+        let horizon = height.saturating_sub(VALIDATOR_HISTORY);
+        if let Some(oldest) = history.range(..=horizon).next_back().map(|(h, _)| *h) {
+            history = history.split_off(&oldest);
+        }
+        if let Err(_) = db.set(ELIGIBLE_VALIDATORS_KEY, &history) {
+            println!("Error setting eligible validators to state");
+        }
+    }
+
+    /// The address the genesis reward was credited to, None until the genesis
+    /// block is applied. A block paying the genesis reward again isn't.
+    pub fn genesis_miner(&self) -> Option<String> {
+        self.get_ledger_db().get("genesisminer")
+    }
+
     pub fn update_reward_state(&mut self, block: &Block) {
         self.reward_state.update(block.header.block_reward.category);
     }
@@ -322,6 +1391,9 @@ impl NetworkState {
     }
 
     pub fn get_claims(&self) -> LinkedHashMap<String, Claim> {
+        if self.claim_cache.loaded {
+            return self.claim_cache.claims.clone();
+        }
         let db = self.get_ledger_db();
         let claims: LinkedHashMap<String, Claim> = if let Some(map) = db.get("claims") {
             map
@@ -332,6 +1404,126 @@ impl NetworkState {
         claims
     }
 
+    /// The hash of the claim map with the `removed` pubkeys taken out of the
+    /// ledger's claims and `changes` applied over them, hashing only the
+    /// changed claims when they are cached.
+    pub fn claim_map_hash_with(
+        &self,
+        changes: &LinkedHashMap<String, Claim>,
+        removed: &[String],
+    ) -> String {
+        if self.claim_cache.loaded {
+            return self.claim_cache.hash.digest_after(changes, removed);
+        }
+        let mut claims = self.get_claims();
+        removed.iter().for_each(|pubkey| {
+            claims.remove(pubkey);
+        });
+        changes.iter().for_each(|(pubkey, claim)| {
+            claims.insert(pubkey.clone(), claim.clone());
+        });
+
+        ClaimMapHash::from_claim_map(&claims).digest()
+    }
+
+    // Every write of the claims goes through here once the db is dumped, so
+    // the claims hash stays that of the claims in the db.
+    fn cache_claims(&mut self, claims: &LinkedHashMap<String, Claim>) {
+        Arc::make_mut(&mut self.claim_cache).update(claims);
+        self.claims_hash = Some(Ledger::claims_hash(claims));
+    }
+
+    /// Writes the claims map and its indexes to the db.
+    fn set_claims(
+        db: &mut PickleDb,
+        claims: &LinkedHashMap<String, Claim>,
+    ) -> Result<(), pickledb::error::Error> {
+        let indexes = ClaimIndexes::from_claims(claims);
+        db.set("claims", claims)?;
+        db.set("claims_by_address", &indexes.by_address)?;
+        db.set("claims_by_hash", &indexes.by_hash)?;
+        db.set("claim_eligibility", &indexes.eligibility)
+    }
+
+    fn get_claim_index<V: serde::de::DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> LinkedHashMap<String, V> {
+        if let Some(index) = self.get_ledger_db().get(key) {
+            index
+        } else {
+            LinkedHashMap::new()
+        }
+    }
+
+    /// The pubkeys of the claims owned by `address`.
+    pub fn get_claims_by_address(&self, address: &str) -> Vec<String> {
+        let address = Address::canonicalize(address);
+        if self.claim_cache.loaded {
+            return self
+                .claim_cache
+                .indexes
+                .by_address
+                .get(&address)
+                .cloned()
+                .unwrap_or_default();
+        }
+
+        self.get_claim_index::<Vec<String>>("claims_by_address")
+            .remove(&address)
+            .unwrap_or_default()
+    }
+
+    /// The pubkey of the claim with `hash`.
+    pub fn get_claim_by_hash(&self, hash: &str) -> Option<String> {
+        if self.claim_cache.loaded {
+            return self.claim_cache.indexes.by_hash.get(hash).cloned();
+        }
+
+        self.get_claim_index::<String>("claims_by_hash")
+            .remove(hash)
+    }
+
+    /// The claim with `pubkey`, without reading the ledger db when the
+    /// claims are cached.
+    pub fn get_claim_by_pubkey(&self, pubkey: &str) -> Option<Claim> {
+        if self.claim_cache.loaded {
+            return self.claim_cache.claims.get(pubkey).cloned();
+        }
+
+        self.get_claims().remove(pubkey)
+    }
+
+    /// The claim with `key` as its pubkey or, failing that, its hash.
+    pub fn get_claim(&self, key: &str) -> Option<Claim> {
+        if let Some(claim) = self.get_claim_by_pubkey(key) {
+            return Some(claim);
+        }
+
+        self.get_claim_by_hash(key)
+            .and_then(|pubkey| self.get_claim_by_pubkey(&pubkey))
+    }
+
+    /// Whether the claim of `pubkey` is eligible to mine, None if there's no
+    /// such claim.
+    pub fn is_claim_eligible(&self, pubkey: &str) -> Option<bool> {
+        if self.claim_cache.loaded {
+            return self.claim_cache.indexes.eligibility.get(pubkey).cloned();
+        }
+
+        self.get_claim_index::<bool>("claim_eligibility")
+            .remove(pubkey)
+    }
+
+    /// Returns the claims that have been slashed and not yet reinstated.
+    pub fn get_ineligible_claims(&self) -> Vec<Claim> {
+        self.get_claims()
+            .values()
+            .filter(|claim| !claim.eligible)
+            .cloned()
+            .collect()
+    }
+
     pub fn get_reward_state(&self) -> RewardState {
         let db = self.get_ledger_db();
         if let Some(reward_state) = db.get("rewardstate") {
@@ -341,6 +1533,36 @@ impl NetworkState {
         }
     }
 
+    /// The coins in existence: everything credited less every debit that
+    /// wasn't credited to someone else. Transfers cancel out, a bond locked
+    /// for a pending reinstatement still exists and a forfeited one is burned.
+    pub fn total_supply(&self) -> u128 {
+        let credited: u128 = self.get_credits().values().sum();
+        let debited: u128 = self.get_debits().values().sum();
+        let locked: u128 = NetworkState::restore_offenses(&self.get_ledger_db())
+            .values()
+            .filter_map(|history| history.pending.as_ref())
+            .map(|pending| pending.bond)
+            .sum();
+
+        (credited + locked).saturating_sub(debited)
+    }
+
+    /// Compares the total supply with the emission the reward schedule
+    /// expects by the last block applied. None until the genesis block is.
+    pub fn audit_supply(&self) -> Option<SupplyAudit> {
+        let reward_state = self.get_reward_state();
+        // The genesis block takes the reward state from block 0 to 1.
+        let height = reward_state.current_block.checked_sub(1)?;
+
+        Some(SupplyAudit {
+            height,
+            total_supply: self.total_supply(),
+            expected: reward_state.expected_cumulative_emission(height),
+            bounds: reward_state.emission_bounds(height),
+        })
+    }
+
     pub fn get_last_block(&self) -> Option<Block> {
         let db = self.get_ledger_db();
         if let Some(last_block) = db.get("lastblock") {
@@ -361,7 +1583,7 @@ impl NetworkState {
 
     pub fn get_account_credits(&self, address: &str) -> u128 {
         let credits = self.get_credits();
-        if let Some(amount) = credits.get(address) {
+        if let Some(amount) = credits.get(&Address::canonicalize(address)) {
             return *amount;
         } else {
             return 0u128;
@@ -370,7 +1592,7 @@ impl NetworkState {
 
     pub fn get_account_debits(&self, address: &str) -> u128 {
         let debits = self.get_debits();
-        if let Some(amount) = debits.get(address) {
+        if let Some(amount) = debits.get(&Address::canonicalize(address)) {
             return *amount;
         } else {
             return 0u128;
@@ -387,63 +1609,313 @@ impl NetworkState {
         if let Err(_) = db.set("rewardstate", &reward_state) {
             println!("Error setting reward state to ledger");
         }
-        if let Err(_) = db.set("claims", &ledger.claims) {
+        if let Err(_) = NetworkState::set_claims(&mut db, &ledger.claims) {
             println!("Error setting claims to ledger");
         }
-        if let Err(_) = db.dump() {
-            info!("Error dumping ledger to db");
+        if let Err(_) = db.set("tokens", &ledger.tokens.tokens) {
+            println!("Error setting token registry to ledger");
+        }
+        if let Err(_) = db.set("token_credits", &ledger.tokens.credits) {
+            println!("Error setting token credits to ledger");
+        }
+        if let Err(_) = db.set("token_debits", &ledger.tokens.debits) {
+            println!("Error setting token debits to ledger");
+        }
+        // The validators eligible at the heights before the ledger aren't known.
+        if let Err(_) = db.rem(ELIGIBLE_VALIDATORS_KEY) {
+            println!("Error removing eligible validators from ledger");
+        }
+        // The blocks applied before were applied to another ledger, the next
+        // block isn't held to their height.
+        if let Err(_) = db.rem("lastapplied") {
+            println!("Error removing last applied block from ledger");
+        }
+        match db.dump() {
+            Ok(_) => self.cache_claims(&ledger.claims),
+            Err(_) => error!(target: STATE_TARGET, "Error dumping ledger to db"),
         }
+        self.ledger_root = Some(ledger.balances_hash());
+        self.tokens_hash = ledger.tokens.hash();
     }
 
+    /// The claims whose owners are offline as of the last block applied sit
+    /// out the election, so the next block isn't held up waiting for them.
     pub fn get_lowest_pointer(&self, nonce: u128) -> Option<(String, u128)> {
-        let claim_map = self.get_claims();
-        let mut pointers = claim_map
-            .iter()
-            .map(|(_, claim)| return (claim.clone().hash, claim.clone().get_pointer(nonce)))
-            .collect::<Vec<_>>();
+        let away = self
+            .last_applied_timestamp()
+            .map_or(vec![], |at| self.claims_away(at));
+        lowest_pointer(
+            self.get_claims()
+                .values()
+                .filter(|claim| !away.contains(&claim.pubkey))
+                .filter_map(|claim| {
+                    claim
+                        .get_pointer(nonce)
+                        .map(|pointer| (claim.hash.clone(), pointer))
+                }),
+        )
+    }
+
+    /// The timestamp of the last block applied, None before genesis and for
+    /// ledger dbs written before it was recorded.
+    pub fn last_applied_timestamp(&self) -> Option<u128> {
+        self.get_ledger_db().get("lastappliedat")
+    }
+
+    /// The height and hash of the last block applied to the ledger, None for
+    /// a ledger no block was applied to since it was set.
+    pub fn last_applied(&self) -> Option<(u128, String)> {
+        self.get_ledger_db().get("lastapplied")
+    }
+
+    /// When the claim of `pubkey` is back, None if no block granted it
+    /// downtime covering `at`.
+    pub fn downtime_until(&self, pubkey: &str, at: u128) -> Option<u128> {
+        NetworkState::restore_downtime(&self.get_ledger_db())
+            .get(pubkey)
+            .and_then(|windows| {
+                windows
+                    .iter()
+                    .find(|window| window.from <= at && at < window.until)
+                    .map(|window| window.until)
+            })
+    }
 
-        pointers.retain(|(_, v)| !v.is_none());
+    /// The pubkeys of the claims granted downtime covering `at`.
+    pub fn claims_away(&self, at: u128) -> Vec<String> {
+        NetworkState::away_at(&NetworkState::restore_downtime(&self.get_ledger_db()), at)
+    }
 
-        let mut raw_pointers = pointers
+    fn away_at(downtime: &LinkedHashMap<String, Vec<DowntimeWindow>>, at: u128) -> Vec<String> {
+        downtime
             .iter()
-            .map(|(k, v)| {
-                return (k.clone(), v.unwrap());
+            .filter(|(_, windows)| {
+                windows
+                    .iter()
+                    .any(|window| window.from <= at && at < window.until)
             })
-            .collect::<Vec<_>>();
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect()
+    }
 
-        if let Some(min) = raw_pointers.clone().iter().min_by_key(|(_, v)| v) {
-            raw_pointers.retain(|(_, v)| *v == min.1);
-            Some(raw_pointers[0].clone())
-        } else {
-            None
+    /// Applies a block's changes to the claims, the same way when the block is
+    /// applied by `dump` as when its claim map hash is computed. In txn order,
+    /// evidence slashes the eligible claim it's against and a claim transfer
+    /// rekeys the claim to its buyer. The block's claims are then added in
+    /// pubkey order, skipping any that would take over another claim's pubkey
+    /// or announce an ineligible claim again, and the header's claim last.
+    /// Slashed claims stay ineligible until their reinstatement is released at
+    /// `block_height`.
+    pub fn apply_block_claims(
+        claims: &mut LinkedHashMap<String, Claim>,
+        offenses: &mut LinkedHashMap<String, OffenseHistory>,
+        header_claim: &Claim,
+        block_claims: &LinkedHashMap<String, Claim>,
+        txns: &LinkedHashMap<String, Txn>,
+        block_height: u128,
+    ) -> BlockClaimChanges {
+        let mut changes = BlockClaimChanges::default();
+        txns.iter().for_each(|(txn_id, txn)| {
+            if txn.is_slashing_evidence() {
+                let evidence = txn.evidence().filter(|evidence| evidence.verify().is_ok());
+                if let Some(evidence) = evidence {
+                    let offender = evidence.offender().to_string();
+                    if claims.get(&offender).map_or(false, |claim| claim.eligible) {
+                        NetworkState::slash_claim(claims, offenses, &offender, block_height);
+                        changes.slashed.push(offender);
+                        changes.changed = true;
+                    }
+                }
+            } else if let Some(transfer) = txn.claim_transfer_data() {
+                match transfer.validate(|pubkey| claims.get(pubkey).cloned()) {
+                    Ok(transferred) => {
+                        claims.remove(&transfer.claim_pubkey);
+                        changes
+                            .transferred
+                            .push((transfer.claim_pubkey.clone(), transferred.pubkey.clone()));
+                        claims.insert(transferred.pubkey.clone(), transferred);
+                        changes.changed = true;
+                    }
+                    Err(e) => changes
+                        .rejected
+                        .push(format!("transfer txn_id={} reason={}", txn_id, e)),
+                }
+            }
+        });
+
+        let mut sorted: Vec<(&String, &Claim)> = block_claims.iter().collect();
+        sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+        sorted.into_iter().for_each(|(k, v)| {
+            // A claim can't take over the pubkey of another claim.
+            if let Some(known) = claims.get(k).filter(|known| v.conflicts_with(known)) {
+                changes.rejected.push(format!(
+                    "conflicting pubkey={} hash={} known_hash={}",
+                    k, v.hash, known.hash
+                ));
+                return;
+            }
+            // Nor can a slashed claim be announced again to make it eligible,
+            // blocks doing so don't validate.
+            if claims.get(k).map_or(false, |known| !known.eligible) {
+                changes
+                    .rejected
+                    .push(format!("ineligible pubkey={} hash={}", k, v.hash));
+                return;
+            }
+            claims.insert(k.clone(), v.clone());
+            changes.changed = true;
+        });
+
+        if claims.get(&header_claim.pubkey) != Some(header_claim) {
+            changes.changed = true;
         }
+        claims.insert(header_claim.pubkey.clone(), header_claim.clone());
+
+        // A slashed claim stays ineligible until it is reinstated, even if it is
+        // announced again.
+        claims.iter_mut().for_each(|(pubkey, claim)| {
+            if let Some(history) = offenses.get(pubkey) {
+                if history.slashed && claim.eligible {
+                    claim.eligible = false;
+                    changes.changed = true;
+                }
+            }
+        });
+
+        // Claims that waited out their reinstatement delay without further
+        // offenses are eligible again.
+        offenses.iter_mut().for_each(|(pubkey, history)| {
+            if let Some(reinstatement) = history.release(block_height) {
+                if let Some(claim) = claims.get_mut(pubkey) {
+                    claim.eligible = true;
+                    changes.changed = true;
+                }
+                changes.released.push((pubkey.clone(), reinstatement));
+            }
+        });
+
+        changes
+    }
+
+    /// The claim map hash the ledger's claims would have once a block mined
+    /// with `header_claim`, `block_claims` and `txns` at `block_height` is
+    /// applied, before any nonce epoch it moves the ledger to. Only the claims
+    /// the block changes are hashed when the claims are cached.
+    pub fn claim_map_hash_after_block(
+        &self,
+        header_claim: &Claim,
+        block_claims: &LinkedHashMap<String, Claim>,
+        txns: &LinkedHashMap<String, Txn>,
+        block_height: u128,
+    ) -> String {
+        let before = self.get_claims();
+        let mut claims = before.clone();
+        let mut offenses = NetworkState::restore_offenses(&self.get_ledger_db());
+        NetworkState::apply_block_claims(
+            &mut claims,
+            &mut offenses,
+            header_claim,
+            block_claims,
+            txns,
+            block_height,
+        );
+        let changes: LinkedHashMap<String, Claim> = claims
+            .iter()
+            .filter(|(pubkey, claim)| before.get(*pubkey) != Some(*claim))
+            .map(|(pubkey, claim)| (pubkey.clone(), claim.clone()))
+            .collect();
+        let removed: Vec<String> = before
+            .keys()
+            .filter(|pubkey| !claims.contains_key(*pubkey))
+            .cloned()
+            .collect();
+
+        self.claim_map_hash_with(&changes, &removed)
     }
 
-    pub fn slash_claims(&mut self, bad_validators: Vec<String>) {
+    /// Marks the claims ineligible and records the offense at `block_height`.
+    /// A claim slashed while its reinstatement is pending forfeits the bond.
+    pub fn slash_claims(&mut self, bad_validators: Vec<String>, block_height: u128) {
         let mut db = self.get_ledger_db();
         let (_, _, _, mut claims) = NetworkState::restore_state_objects(&db);
+        let mut offenses = NetworkState::restore_offenses(&db);
 
         bad_validators.iter().for_each(|k| {
-            if let Some(claim) = claims.get_mut(&k.to_string()) {
-                claim.eligible = false;
-            }
+            NetworkState::slash_claim(&mut claims, &mut offenses, k, block_height);
         });
 
-        if let Err(_) = db.set("claims", &claims) {
+        if let Err(_) = NetworkState::set_claims(&mut db, &claims) {
             println!("Error setting claims to state")
         };
 
-        if let Err(e) = db.dump() {
-            info!("Error dumping state to file: {:?}", e)
+        if let Err(_) = db.set("offenses", &offenses) {
+            println!("Error setting claim offenses to state")
+        };
+
+        match db.dump() {
+            Ok(_) => self.cache_claims(&claims),
+            Err(e) => error!(target: STATE_TARGET, "Error dumping state to file: {:?}", e),
+        }
+    }
+
+    /// Marks the claim ineligible and records the offense at `block_height`,
+    /// if the claim is known.
+    fn slash_claim(
+        claims: &mut LinkedHashMap<String, Claim>,
+        offenses: &mut LinkedHashMap<String, OffenseHistory>,
+        pubkey: &str,
+        block_height: u128,
+    ) {
+        if let Some(claim) = claims.get_mut(pubkey) {
+            claim.eligible = false;
+            let history = offenses
+                .entry(pubkey.to_string())
+                .or_insert_with(OffenseHistory::new);
+            if let Some(forfeited) = history.record_offense(block_height) {
+                info!(
+                    target: STATE_TARGET,
+                    "Claim {} slashed during reinstatement, bond of {} forfeited",
+                    pubkey, forfeited.bond
+                );
+            }
         }
     }
 
+    /// The credits and debits to an address from the txns pending in the pool.
     pub fn pending_balance(
         &self,
-        _address: String,
-        _txn_pool: &Pool<String, Txn>,
+        address: String,
+        txn_pool: &Pool<String, Txn>,
     ) -> Option<(u128, u128)> {
-        None
+        let address = Address::canonicalize(&address);
+        let mut credits = 0u128;
+        let mut debits = 0u128;
+        txn_pool.pending.values().for_each(|txn| {
+            if !txn.is_token_txn() && Address::canonicalize(&txn.receiver_address) == address {
+                credits = credits.saturating_add(txn.txn_amount);
+            }
+            if !txn.is_coinbase() && Address::canonicalize(&txn.sender_address) == address {
+                debits = debits.saturating_add(txn.native_cost());
+            }
+        });
+
+        Some((credits, debits))
+    }
+
+    /// The amount of `token_id` the address is sending in txns still in the pool.
+    pub fn pending_token_debits(
+        &self,
+        address: &str,
+        token_id: &str,
+        txn_pool: &Pool<String, Txn>,
+    ) -> u128 {
+        let address = Address::canonicalize(address);
+        txn_pool
+            .pending
+            .values()
+            .filter(|txn| txn.txn_token.as_deref() == Some(token_id) && !txn.is_token_issuance())
+            .filter(|txn| Address::canonicalize(&txn.sender_address) == address)
+            .fold(0u128, |debits, txn| debits.saturating_add(txn.txn_amount))
     }
 
     pub fn credits_as_bytes(credits: &LinkedHashMap<String, u128>) -> Vec<u8> {
@@ -502,19 +1974,180 @@ impl NetworkState {
         serde_json::from_str::<NetworkState>(&string).unwrap()
     }
 
+    /// Writes the ledger and reward state to a standalone snapshot file and
+    /// returns the snapshot's integrity hash.
+    pub fn export_snapshot(&self, path: &str) -> Result<String, Box<dyn Error>> {
+        let snapshot = StateSnapshot::new(
+            self.db_to_ledger(),
+            self.get_reward_state(),
+            self.state_hash.clone(),
+        );
+        fs::write(path, snapshot.to_string())?;
+
+        Ok(snapshot.hash)
+    }
+
+    /// Loads a snapshot file into a fresh ledger database at `path`, refusing
+    /// one that already exists. The snapshot is verified before it is written
+    /// and the database is re-read afterwards to make sure the imported state
+    /// hashes to the same value as the export.
+    pub fn import_snapshot(snapshot_path: &str, path: &str) -> Result<NetworkState, Box<dyn Error>> {
+        if std::path::Path::new(path).exists() {
+            return Err(Box::new(SnapshotError(format!(
+                "{} already exists, a snapshot is only imported into a fresh db",
+                path
+            ))));
+        }
+        let snapshot = StateSnapshot::from_bytes(&fs::read(snapshot_path)?)?;
+        if !snapshot.verify() {
+            return Err(Box::new(SnapshotError(format!(
+                "snapshot {} failed its integrity check",
+                snapshot_path
+            ))));
+        }
+        if snapshot.reward_state.params != ProtocolParams::default() {
+            return Err(Box::new(SnapshotError(format!(
+                "snapshot {} is on other protocol params",
+                snapshot_path
+            ))));
+        }
+
+        NetworkState::apply_snapshot(&snapshot, path)
+    }
+
+    /// Writes a verified snapshot into a copy of the ledger database at
+    /// `path`, checks that the written state hashes to the snapshot's hash and
+    /// only then renames the copy over the database, so a snapshot that fails
+    /// to import leaves the live state as it was.
+    pub fn apply_snapshot(
+        snapshot: &StateSnapshot,
+        path: &str,
+    ) -> Result<NetworkState, Box<dyn Error>> {
+        let import_path = format!("{}.import", path);
+        let _ = fs::remove_file(&import_path);
+        if std::path::Path::new(path).exists() {
+            fs::copy(path, &import_path)?;
+        }
+
+        let mut network_state = NetworkState::restore(&import_path);
+        network_state.update_ledger(snapshot.ledger.clone(), snapshot.reward_state);
+
+        let network_state = NetworkState::restore(&import_path);
+        let imported = StateSnapshot::new(
+            network_state.db_to_ledger(),
+            network_state.get_reward_state(),
+            snapshot.state_hash.clone(),
+        );
+
+        if imported.hash != snapshot.hash {
+            let _ = fs::remove_file(&import_path);
+            return Err(Box::new(SnapshotError(format!(
+                "imported state hash {} doesn't match exported hash {}",
+                imported.hash, snapshot.hash
+            ))));
+        }
+
+        fs::File::open(&import_path)?.sync_all()?;
+        fs::rename(&import_path, path)?;
+        let mut network_state = NetworkState::restore(path);
+        network_state.state_hash = snapshot.state_hash.clone();
+
+        Ok(network_state)
+    }
+
+    /// The canonical hash of the whole ledger, combined from the hashes the
+    /// state keeps of its parts, see Ledger::canonical_hash. The credit and
+    /// debit hashes chain over every block so a ledger can't be checked
+    /// against them on its own, it can be against this one.
+    pub fn ledger_hash(&self) -> Option<String> {
+        Some(Ledger::combine_hashes(
+            self.ledger_root.as_ref()?,
+            self.claims_hash.as_ref()?,
+            &self.tokens_hash,
+        ))
+    }
+
+    /// Whether the ledger in the db still hashes to the ledger hash the state
+    /// holds, false once the db was changed outside of applying blocks and
+    /// updating the ledger.
+    pub fn verify_ledger_hash(&self) -> bool {
+        let ledger = self.db_to_ledger();
+
+        self.ledger_root == Some(ledger.balances_hash())
+            && self.ledger_hash() == Some(ledger.canonical_hash())
+    }
+
     pub fn db_to_ledger(&self) -> Ledger {
         let credits = self.get_credits();
         let debits = self.get_debits();
         let claims = self.get_claims();
+        let tokens = self.get_token_ledger();
 
         Ledger {
             credits,
             debits,
             claims,
+            tokens,
         }
     }
 }
 
+impl SharedNetworkState {
+    pub fn new(network_state: NetworkState) -> SharedNetworkState {
+        SharedNetworkState {
+            inner: Arc::new(RwLock::new(network_state)),
+        }
+    }
+
+    /// Holds the read lock for reads that have to agree with each other. A
+    /// thread holding it mustn't mutate the state until it's dropped.
+    pub fn read(&self) -> RwLockReadGuard<NetworkState> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<NetworkState> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A copy of the state as it is now, for sending to a peer.
+    pub fn snapshot(&self) -> NetworkState {
+        self.read().clone()
+    }
+
+    /// Swaps in a state synced from a peer or imported from a snapshot.
+    pub fn replace(&self, network_state: NetworkState) {
+        *self.write() = network_state;
+    }
+
+    pub fn get_balance(&self, address: &str) -> u128 {
+        self.read().get_balance(address)
+    }
+
+    pub fn get_claims(&self) -> LinkedHashMap<String, Claim> {
+        self.read().get_claims()
+    }
+
+    pub fn dump(&self, block: &Block) {
+        self.write().dump(block)
+    }
+
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        self.read().flush()
+    }
+}
+
+impl From<NetworkState> for SharedNetworkState {
+    fn from(network_state: NetworkState) -> SharedNetworkState {
+        SharedNetworkState::new(network_state)
+    }
+}
+
+impl From<SharedNetworkState> for NetworkState {
+    fn from(shared: SharedNetworkState) -> NetworkState {
+        shared.snapshot()
+    }
+}
+
 impl Ledger {
     pub fn as_bytes(&self) -> Vec<u8> {
         self.to_string().as_bytes().to_vec()
@@ -531,9 +2164,180 @@ impl Ledger {
     pub fn from_string(string: &String) -> Ledger {
         serde_json::from_str::<Ledger>(&string).unwrap()
     }
+
+    pub fn balances_hash(&self) -> String {
+        NetworkState::balances_hash(&self.credits, &self.debits)
+    }
+
+    /// A hash rolled over every claim in order of its pubkey.
+    pub fn claims_hash(claims: &LinkedHashMap<String, Claim>) -> String {
+        let mut claims: Vec<(&String, &Claim)> = claims.iter().collect();
+        claims.sort_by(|(a, _), (b, _)| a.cmp(b));
+        claims.iter().fold(String::new(), |acc, (pubkey, claim)| {
+            let claim = serde_json::to_string(claim).unwrap();
+            digest_bytes(format!("{},{},{}", acc, pubkey, claim).as_bytes())
+        })
+    }
+
+    /// The hash of the whole ledger: the balances' canonical hash, the claims
+    /// hash and, once a token has been issued, the token ledger's canonical
+    /// hash, hashed together. It only depends on the entries, not on the
+    /// order they were inserted in.
+    pub fn canonical_hash(&self) -> String {
+        Ledger::combine_hashes(
+            &self.balances_hash(),
+            &Ledger::claims_hash(&self.claims),
+            &self.tokens.hash(),
+        )
+    }
+
+    pub fn combine_hashes(
+        balances_hash: &str,
+        claims_hash: &str,
+        tokens_hash: &Option<String>,
+    ) -> String {
+        let payload = format!("ledger,{},{}", balances_hash, claims_hash);
+        match tokens_hash {
+            Some(tokens_hash) => digest_bytes(format!("{},{}", payload, tokens_hash).as_bytes()),
+            None => digest_bytes(payload.as_bytes()),
+        }
+    }
+}
+
+impl SupplyAudit {
+    /// Whether the supply strays outside what the schedule can have emitted,
+    /// i.e. coins were minted or burned outside of block rewards.
+    pub fn diverged(&self) -> bool {
+        self.total_supply < self.bounds.0 || self.total_supply > self.bounds.1
+    }
+}
+
+impl StateSnapshot {
+    pub fn new(ledger: Ledger, reward_state: RewardState, state_hash: Option<String>) -> StateSnapshot {
+        let hash =
+            StateSnapshot::integrity_hash(SNAPSHOT_VERSION, &ledger, &reward_state, &state_hash);
+        StateSnapshot {
+            version: SNAPSHOT_VERSION,
+            ledger,
+            reward_state,
+            state_hash,
+            hash,
+        }
+    }
+
+    fn first_version() -> u32 {
+        1
+    }
+
+    /// Hashes the ledger's canonical hash, so a snapshot re-read from a db
+    /// that holds the same entries in another order still verifies, along
+    /// with the reward state and the state hash. Version 1 snapshots hashed
+    /// the ledger's JSON instead.
+    pub fn integrity_hash(
+        version: u32,
+        ledger: &Ledger,
+        reward_state: &RewardState,
+        state_hash: &Option<String>,
+    ) -> String {
+        let ledger_hash = if version < 2 {
+            ledger.to_string()
+        } else {
+            ledger.canonical_hash()
+        };
+        digest_bytes(
+            format!(
+                "{},{},{:?}",
+                ledger_hash,
+                reward_state.to_string(),
+                state_hash
+            )
+            .as_bytes(),
+        )
+    }
+
+    pub fn verify(&self) -> bool {
+        self.version <= SNAPSHOT_VERSION
+            && StateSnapshot::integrity_hash(
+                self.version,
+                &self.ledger,
+                &self.reward_state,
+                &self.state_hash,
+            ) == self.hash
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<StateSnapshot, serde_json::Error> {
+        serde_json::from_slice::<StateSnapshot>(data)
+    }
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SnapshotError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ComponentsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ComponentsError {
+    fn description(&self) -> &str {
+        &self.0
+    }
 }
 
 impl Components {
+    /// Checks that the parts of a state update agree with each other before
+    /// any of them are applied. The ledger and network state have to be sent
+    /// together, the ledger has to hash to the network state's ledger hash
+    /// and the network state has to be at the child block.
+    pub fn verify(&self) -> Result<(), ComponentsError> {
+        let (ledger, network_state) = match (&self.ledger, &self.network_state) {
+            (Some(ledger), Some(network_state)) => (ledger, network_state),
+            (None, None) => return Ok(()),
+            _ => {
+                return Err(ComponentsError(
+                    "ledger and network state must be sent together".to_string(),
+                ))
+            }
+        };
+
+        let ledger = serde_json::from_slice::<Ledger>(ledger)
+            .map_err(|e| ComponentsError(format!("invalid ledger: {}", e)))?;
+        let network_state = serde_json::from_slice::<NetworkState>(network_state)
+            .map_err(|e| ComponentsError(format!("invalid network state: {}", e)))?;
+
+        if network_state.ledger_hash() != Some(ledger.canonical_hash()) {
+            return Err(ComponentsError(
+                "ledger doesn't match the network state".to_string(),
+            ));
+        }
+
+        if let (Some(state_hash), Some(child)) = (&network_state.state_hash, &self.child) {
+            let child = serde_json::from_slice::<Block>(child)
+                .map_err(|e| ComponentsError(format!("invalid child block: {}", e)))?;
+            if *state_hash != child.hash {
+                return Err(ComponentsError(
+                    "network state isn't at the child block".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         self.to_string().as_bytes().to_vec()
     }
@@ -542,6 +2346,10 @@ impl Components {
         serde_json::from_slice::<Components>(data).unwrap()
     }
 
+    pub fn try_from_bytes(data: &[u8]) -> Result<Components, serde_json::Error> {
+        serde_json::from_slice::<Components>(data)
+    }
+
     pub fn to_string(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
@@ -588,6 +2396,1722 @@ impl Clone for NetworkState {
             debits: self.debits.clone(),
             reward_state: self.reward_state.clone(),
             state_hash: self.state_hash.clone(),
+            ledger_root: self.ledger_root.clone(),
+            claims_hash: self.claims_hash.clone(),
+            tokens_hash: self.tokens_hash.clone(),
+            dirty: self.dirty,
+            claim_cache: self.claim_cache.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::activity::{ActivityReport, Direction, CSV_HEADER, MAX_ADDRESS_EVENTS};
+    use crate::block::compute_expected_claim_map_hash;
+    use crate::claim::{ClaimSigner, ClaimTransfer, PlannedDowntime, MAX_DOWNTIME};
+    use crate::params::GENESIS_REWARD;
+    use crate::reward::{Category, Reward};
+    use crate::slashing::REINSTATEMENT_DELAY;
+    use crate::test_utils::{self, temp_path};
+    use crate::token::TokenMetadata;
+    use crate::txn::{InvalidTxnErrorReason, TxnKind};
+    use crate::verifiable::Verifiable;
+    use crate::wallet::{AddressNetwork, WalletAccount};
+    use rand::Rng;
+
+    #[test]
+    fn test_snapshot_export_import_round_trip() {
+        let db_path = temp_path("vrrb_test_snapshot_export.db");
+        let snapshot_path = temp_path("vrrb_test_snapshot.json");
+        let import_path = temp_path("vrrb_test_snapshot_import.db");
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(&import_path);
+
+        let mut credits = LinkedHashMap::new();
+        credits.insert("0x192sender".to_string(), 1000u128);
+        credits.insert("0x192receiver".to_string(), 250u128);
+        let mut debits = LinkedHashMap::new();
+        debits.insert("0x192sender".to_string(), 250u128);
+        let claim = Claim::new("pubkey".to_string(), "0x192sender".to_string(), 1);
+        let mut claims = LinkedHashMap::new();
+        claims.insert(claim.pubkey.clone(), claim.clone());
+
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.update_ledger(
+            Ledger {
+                credits,
+                debits,
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+
+        let exported_hash = network_state.export_snapshot(&snapshot_path).unwrap();
+        let imported = NetworkState::import_snapshot(&snapshot_path, &import_path).unwrap();
+
+        assert_eq!(imported.get_balance("0x192sender"), 750);
+        assert_eq!(imported.get_balance("0x192receiver"), 250);
+        assert_eq!(
+            imported.get_claims().get("pubkey").unwrap().hash,
+            claim.hash
+        );
+        assert_eq!(
+            StateSnapshot::new(
+                imported.db_to_ledger(),
+                imported.get_reward_state(),
+                imported.state_hash.clone()
+            )
+            .hash,
+            exported_hash
+        );
+
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(snapshot_path);
+        let _ = fs::remove_file(import_path);
+    }
+
+    #[test]
+    fn test_legacy_balances_are_migrated_once_on_open() {
+        let db_path = temp_path("vrrb_test_legacy_balances.db");
+        let _ = fs::remove_file(&db_path);
+        let mut address_bytes = "pubkey".as_bytes().to_vec();
+        address_bytes.push(1u8);
+        let legacy = format!(
+            "{}{}",
+            crate::wallet::TESTNET_ADDRESS_PREFIX,
+            digest_bytes(digest_bytes(&address_bytes).as_bytes())
+        );
+        let canonical = test_address("pubkey");
+
+        let mut credits = LinkedHashMap::new();
+        credits.insert(legacy.clone(), 500u128);
+        credits.insert(canonical.clone(), 250u128);
+        let mut debits = LinkedHashMap::new();
+        debits.insert(legacy.clone(), 100u128);
+        let mut db = PickleDb::new(
+            &db_path,
+            PickleDbDumpPolicy::DumpUponRequest,
+            SerializationMethod::Bin,
+        );
+        db.set("credits", &credits).unwrap();
+        db.set("debits", &debits).unwrap();
+        db.dump().unwrap();
+
+        let network_state = NetworkState::restore(&db_path);
+        assert_eq!(network_state.get_account_credits(&canonical), 750);
+        assert_eq!(network_state.get_account_debits(&canonical), 100);
+        assert!(!network_state.get_credits().contains_key(&legacy));
+        let db = PickleDb::load_bin(&db_path, PickleDbDumpPolicy::DumpUponRequest).unwrap();
+        assert_eq!(db.get::<bool>("addressesmigrated"), Some(true));
+
+        let migrated = fs::read(&db_path).unwrap();
+        NetworkState::restore(&db_path);
+        assert_eq!(fs::read(&db_path).unwrap(), migrated);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_legacy_balance_migration_recomputes_the_stored_hashes() {
+        let db_path = temp_path("vrrb_test_legacy_balance_hashes.db");
+        let _ = fs::remove_file(&db_path);
+        let mut address_bytes = "pubkey".as_bytes().to_vec();
+        address_bytes.push(1u8);
+        let legacy = format!(
+            "{}{}",
+            crate::wallet::TESTNET_ADDRESS_PREFIX,
+            digest_bytes(digest_bytes(&address_bytes).as_bytes())
+        );
+        let canonical = test_address("pubkey");
+
+        let mut credits = LinkedHashMap::new();
+        credits.insert(legacy.clone(), 500u128);
+        let mut debits = LinkedHashMap::new();
+        debits.insert(legacy.clone(), 100u128);
+        let mut db = PickleDb::new(
+            &db_path,
+            PickleDbDumpPolicy::DumpUponRequest,
+            SerializationMethod::Bin,
+        );
+        db.set("credits", &credits).unwrap();
+        db.set("debits", &debits).unwrap();
+        db.set("lastapplied", &(1u128, "block_1".to_string()))
+            .unwrap();
+        let legacy_hashes = FlushedHashes {
+            last_applied: "block_1".to_string(),
+            credits: Some("legacy_credits".to_string()),
+            debits: Some("legacy_debits".to_string()),
+            state_hash: Some("legacy_state".to_string()),
+        };
+        db.set("flushedhashes", &legacy_hashes).unwrap();
+        db.dump().unwrap();
+
+        let network_state = NetworkState::restore(&db_path);
+        let mut migrated_credits = LinkedHashMap::new();
+        migrated_credits.insert(canonical.clone(), 500u128);
+        let mut migrated_debits = LinkedHashMap::new();
+        migrated_debits.insert(canonical, 100u128);
+        assert_eq!(
+            network_state.credits,
+            Some(digest_bytes(
+                NetworkState::canonical_map_string(&migrated_credits).as_bytes()
+            ))
+        );
+        assert_eq!(
+            network_state.debits,
+            Some(digest_bytes(
+                NetworkState::canonical_map_string(&migrated_debits).as_bytes()
+            ))
+        );
+        assert_eq!(network_state.state_hash, None);
+        assert_eq!(
+            network_state.ledger_root,
+            Some(NetworkState::balances_hash(
+                &migrated_credits,
+                &migrated_debits
+            ))
+        );
+        let db = PickleDb::load_bin(&db_path, PickleDbDumpPolicy::DumpUponRequest).unwrap();
+        assert!(!db.exists("flushedhashes"));
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_failed_snapshot_import_leaves_the_live_db_intact() {
+        let db_path = temp_path("vrrb_test_snapshot_live.db");
+        let snapshot_path = temp_path("vrrb_test_snapshot_tampered.json");
+        let import_path = temp_path("vrrb_test_snapshot_tampered_import.db");
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(&import_path);
+        let miner = test_address("miner");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &miner, 1000, vec![]));
+        let live_db = fs::read(&db_path).unwrap();
+
+        let mut snapshot = StateSnapshot::new(
+            network_state.db_to_ledger(),
+            network_state.get_reward_state(),
+            network_state.state_hash.clone(),
+        );
+        snapshot.ledger.credits.insert(miner.clone(), 1_000_000);
+        fs::write(&snapshot_path, snapshot.to_string()).unwrap();
+        assert!(NetworkState::import_snapshot(&snapshot_path, &import_path).is_err());
+        assert!(!std::path::Path::new(&import_path).exists());
+        assert!(!std::path::Path::new(&format!("{}.import", import_path)).exists());
+
+        // Even a snapshot that checks out isn't imported over an existing db.
+        network_state.export_snapshot(&snapshot_path).unwrap();
+        assert!(NetworkState::import_snapshot(&snapshot_path, &db_path).is_err());
+        assert_eq!(fs::read(&db_path).unwrap(), live_db);
+        assert_eq!(NetworkState::restore(&db_path).get_balance(&miner), 1000);
+
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(snapshot_path);
+    }
+
+    fn test_address(seed: &str) -> String {
+        Address::from_pubkey(AddressNetwork::default(), seed, 1).to_string()
+    }
+
+    fn test_transfer(sender: &str, receiver: &str, amount: u128) -> Txn {
+        let mut txn = Txn::unsigned_transfer(amount, 0);
+        txn.sender_address = sender.to_string();
+        txn.receiver_address = receiver.to_string();
+        txn
+    }
+
+    fn invalid_reason(txn: &Txn, network_state: &NetworkState) -> InvalidTxnErrorReason {
+        txn.valid_txn(network_state, None).unwrap_err().details
+    }
+
+    fn test_block(height: u128, miner: &str, reward: u128, txns: Vec<Txn>) -> Block {
+        let mut block = test_utils::test_block(height, &format!("block_{}", height + 1));
+        block.header.last_hash = format!("block_{}", height);
+        block.header.timestamp = height;
+        block.header.claim = Claim::new("pubkey".to_string(), miner.to_string(), 1);
+        block.header.block_reward = Reward {
+            miner: Some(miner.to_string()),
+            category: Category::Flake(Some(reward)),
+            amount: reward,
+        };
+        block.header.next_block_reward = Reward {
+            miner: None,
+            category: Category::Flake(Some(reward)),
+            amount: reward,
+        };
+        block.txns = txns
+            .into_iter()
+            .map(|txn| (txn.txn_id.clone(), txn))
+            .collect();
+        block
+    }
+
+    #[test]
+    fn test_readers_never_see_a_torn_dump() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let db_path = temp_path("vrrb_test_shared_state.db");
+        let _ = fs::remove_file(&db_path);
+        let network_state = SharedNetworkState::new(NetworkState::restore(&db_path));
+        let miner = test_address("miner");
+        let done = Arc::new(AtomicBool::new(false));
+
+        // Under one read lock the balance always matches the last block applied.
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let network_state = network_state.clone();
+                let miner = miner.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        let state = network_state.read();
+                        let applied = state.state_hash.as_ref().map_or(0, |hash| {
+                            hash.trim_start_matches("block_").parse::<u128>().unwrap()
+                        });
+                        assert_eq!(state.get_balance(&miner), 10 * applied);
+                    }
+                })
+            })
+            .collect();
+
+        (0..5).for_each(|height| network_state.dump(&test_block(height, &miner, 10, vec![])));
+        done.store(true, Ordering::SeqCst);
+        readers
+            .into_iter()
+            .for_each(|reader| reader.join().unwrap());
+        assert_eq!(network_state.get_balance(&miner), 50);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_genesis_reward_applies_exactly_once() {
+        let db_path = temp_path("vrrb_test_genesis_once.db");
+        let _ = fs::remove_file(&db_path);
+        let mut network_state = NetworkState::restore(&db_path);
+        let miner = test_address("genesis");
+        let mut genesis = test_block(0, &miner, 0, vec![]);
+        genesis.header.block_reward =
+            Reward::genesis(Some(miner.clone()), &ProtocolParams::default());
+        network_state.dump(&genesis);
+        assert_eq!(network_state.get_balance(&miner), GENESIS_REWARD);
+        assert_eq!(
+            network_state.genesis_miner(),
+            Some(Address::canonicalize(&miner))
+        );
+
+        // Neither another genesis nor the same one applied again is paid.
+        let other = test_address("other");
+        let mut second = test_block(5, &other, 0, vec![]);
+        second.header.block_reward =
+            Reward::genesis(Some(other.clone()), &ProtocolParams::default());
+        network_state.dump(&second);
+        network_state.dump(&genesis);
+        assert_eq!(network_state.get_balance(&miner), GENESIS_REWARD);
+        assert_eq!(network_state.get_balance(&other), 0);
+        assert_eq!(network_state.state_hash, Some(genesis.hash.clone()));
+
+        // The genesis miner is in the db, so it's refused after a restart too.
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&second);
+        assert_eq!(network_state.get_balance(&miner), GENESIS_REWARD);
+        assert_eq!(network_state.get_balance(&other), 0);
+        assert_eq!(network_state.state_hash, Some(genesis.hash.clone()));
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_eligible_validators_are_those_of_the_blocks_height() {
+        let db_path = temp_path("vrrb_test_eligible_validators.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let mut network_state = NetworkState::restore(&db_path);
+        let mut genesis = test_block(0, &miner, 100, vec![]);
+        let first = Claim::new("first".to_string(), miner.clone(), 1);
+        genesis.claims.insert(first.pubkey.clone(), first);
+        network_state.dump(&genesis);
+        network_state.dump(&test_block(1, &miner, 0, vec![]));
+        let mut joined = test_block(2, &miner, 0, vec![]);
+        let second = Claim::new("second".to_string(), miner.clone(), 1);
+        joined.claims.insert(second.pubkey.clone(), second);
+        network_state.dump(&joined);
+
+        let validators = |height: u128| {
+            let mut validators: Vec<String> = network_state
+                .eligible_validators(height)
+                .into_iter()
+                .collect();
+            validators.sort();
+            validators
+        };
+        assert_eq!(validators(1), vec!["first", "pubkey"]);
+        assert_eq!(validators(2), vec!["first", "pubkey"]);
+        assert_eq!(validators(3), vec!["first", "pubkey", "second"]);
+
+        // A claim slashed since doesn't change the validators of past heights.
+        network_state.slash_claims(vec!["first".to_string()], 2);
+        assert_eq!(validators(2), vec!["first", "pubkey"]);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_address_activity_is_capped_and_still_reconciles() {
+        let n_events = MAX_ADDRESS_EVENTS as u128 + 10;
+        let mut events = vec![];
+        (0..n_events).for_each(|height| {
+            let (direction, amount) = if height % 2 == 0 {
+                (Direction::In, 3)
+            } else {
+                (Direction::Out, 1)
+            };
+            let event = AddressEvent {
+                timestamp: height,
+                block_height: height,
+                txn_id: format!("txn_{}", height),
+                direction,
+                counterparty: String::new(),
+                amount,
+            };
+            record_event(&mut events, event);
+        });
+
+        assert_eq!(events.len(), MAX_ADDRESS_EVENTS);
+        assert_eq!(events[0].direction, Direction::Carried);
+        assert_eq!(events.last().unwrap().block_height, n_events - 1);
+        let report = ActivityReport::new("address", &events, n_events).unwrap();
+        assert!(report.reconciled);
+        assert_eq!(report.rows[0].direction.to_str(), "carried");
+    }
+
+    #[test]
+    fn test_export_address_activity_reconciles() {
+        let db_path = temp_path("vrrb_test_activity.db");
+        let csv_path = temp_path("vrrb_test_activity.csv");
+        let json_path = temp_path("vrrb_test_activity.json");
+        let _ = fs::remove_file(&db_path);
+
+        let miner = test_address("miner");
+        let other = test_address("other");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &miner, 1000, vec![]));
+        network_state.dump(&test_block(
+            1,
+            &other,
+            50,
+            vec![test_transfer(&miner, &other, 300)],
+        ));
+        network_state.dump(&test_block(
+            2,
+            &other,
+            50,
+            vec![test_transfer(&other, &miner, 100)],
+        ));
+
+        let n_rows = network_state
+            .export_address_activity(&miner, ExportFormat::Csv, &csv_path)
+            .unwrap();
+        assert_eq!(n_rows, 3);
+
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        let rows: Vec<Vec<String>> = lines
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split(',').map(|v| v.to_string()).collect())
+            .collect();
+        let directions: Vec<&str> = rows.iter().map(|row| row[3].as_str()).collect();
+        assert_eq!(directions, vec!["reward", "out", "in"]);
+
+        let mut balance = 0u128;
+        for row in rows.iter() {
+            let amount = row[5].parse::<u128>().unwrap();
+            balance = if row[3] == "out" {
+                balance - amount
+            } else {
+                balance + amount
+            };
+            assert_eq!(row[6].parse::<u128>().unwrap(), balance);
+        }
+        assert_eq!(balance, 800);
+        assert_eq!(balance, network_state.get_balance(&miner));
+        assert!(csv.lines().last().unwrap().starts_with("# reconciled=true"));
+
+        network_state
+            .export_address_activity(&other, ExportFormat::Json, &json_path)
+            .unwrap();
+        let report: ActivityReport =
+            serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+        assert!(report.reconciled);
+        assert_eq!(report.rows[0].direction, Direction::In);
+        assert_eq!(report.final_balance, network_state.get_balance(&other));
+
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(csv_path);
+        let _ = fs::remove_file(json_path);
+    }
+
+    #[test]
+    fn test_fees_are_paid_by_the_sender_to_the_miner() {
+        let db_path = temp_path("vrrb_test_fees.db");
+        let _ = fs::remove_file(&db_path);
+        let sender = test_address("sender");
+        let receiver = test_address("receiver");
+        let miner = test_address("miner");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &sender, 1000, vec![]));
+
+        let mut txn = test_transfer(&sender, &receiver, 300);
+        txn.txn_fee = 5;
+        network_state.dump(&test_block(1, &miner, 50, vec![txn]));
+        assert_eq!(network_state.get_balance(&sender), 695);
+        assert_eq!(network_state.get_balance(&receiver), 300);
+        assert_eq!(network_state.get_balance(&miner), 55);
+
+        for address in [&sender, &miner].iter() {
+            let events = network_state.get_address_activity(address);
+            let report = ActivityReport::new(address, &events, network_state.get_balance(address));
+            assert!(report.unwrap().reconciled);
+        }
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_state_hash_is_independent_of_insertion_order() {
+        let first_path = temp_path("vrrb_test_order_first.db");
+        let second_path = temp_path("vrrb_test_order_second.db");
+        let _ = fs::remove_file(&first_path);
+        let _ = fs::remove_file(&second_path);
+
+        let miner = test_address("miner");
+        let txns = vec![
+            test_transfer(&miner, &test_address("a"), 10),
+            test_transfer(&miner, &test_address("b"), 20),
+            test_transfer(&test_address("c"), &miner, 30),
+        ];
+        let claims = vec![
+            Claim::new("pubkey_a".to_string(), test_address("a"), 1),
+            Claim::new("pubkey_b".to_string(), test_address("b"), 1),
+            Claim::new("pubkey_c".to_string(), test_address("c"), 1),
+        ];
+
+        let mut first_block = test_block(0, &miner, 100, txns.clone());
+        let mut second_block = test_block(0, &miner, 100, txns.into_iter().rev().collect());
+        claims.iter().for_each(|claim| {
+            first_block
+                .claims
+                .insert(claim.pubkey.clone(), claim.clone());
+        });
+        claims.iter().rev().for_each(|claim| {
+            second_block
+                .claims
+                .insert(claim.pubkey.clone(), claim.clone());
+        });
+
+        let mut first = NetworkState::restore(&first_path);
+        let mut second = NetworkState::restore(&second_path);
+        assert_eq!(
+            first.hash(first_block.clone()),
+            second.hash(second_block.clone())
+        );
+
+        first.dump(&first_block);
+        second.dump(&second_block);
+        assert_eq!(first.credits, second.credits);
+        assert_eq!(first.debits, second.debits);
+        assert_eq!(
+            first.get_claims().keys().collect::<Vec<&String>>(),
+            second.get_claims().keys().collect::<Vec<&String>>()
+        );
+        assert_eq!(
+            NetworkState::restore(&first_path).credits,
+            NetworkState::restore(&second_path).credits
+        );
+
+        let _ = fs::remove_file(first_path);
+        let _ = fs::remove_file(second_path);
+    }
+
+    fn test_ledger(n_entries: usize) -> Ledger {
+        let mut ledger = Ledger {
+            credits: LinkedHashMap::new(),
+            debits: LinkedHashMap::new(),
+            claims: LinkedHashMap::new(),
+            tokens: TokenLedger::default(),
+        };
+        (0..n_entries).for_each(|n| {
+            let address = format!("address_{}", n);
+            ledger.credits.insert(address.clone(), 100 + n as u128);
+            ledger.debits.insert(address.clone(), n as u128);
+            let claim = Claim::new(format!("pubkey_{}", n), address, 1);
+            ledger.claims.insert(claim.pubkey.clone(), claim);
+        });
+        ledger
+    }
+
+    #[test]
+    fn test_canonical_ledger_hash_is_independent_of_insertion_order() {
+        let ledger = test_ledger(10);
+        let reversed = Ledger {
+            credits: ledger.credits.clone().into_iter().rev().collect(),
+            debits: ledger.debits.clone().into_iter().rev().collect(),
+            claims: ledger.claims.clone().into_iter().rev().collect(),
+            tokens: ledger.tokens.clone(),
+        };
+        assert_eq!(ledger.canonical_hash(), reversed.canonical_hash());
+        assert_eq!(ledger.balances_hash(), reversed.balances_hash());
+
+        // Any entry changing changes the hash.
+        let hash = ledger.canonical_hash();
+        let mut credited = ledger.clone();
+        *credited.credits.get_mut("address_3").unwrap() += 1;
+        let mut debited = ledger.clone();
+        debited.debits.insert("address_10".to_string(), 1);
+        let mut slashed = ledger.clone();
+        slashed.claims.get_mut("pubkey_5").unwrap().eligible = false;
+        let mut removed = ledger.clone();
+        removed.claims.remove("pubkey_0");
+        vec![credited, debited, slashed, removed]
+            .iter()
+            .for_each(|changed| assert_ne!(changed.canonical_hash(), hash));
+    }
+
+    #[test]
+    fn test_verify_ledger_hash_catches_a_single_mutation() {
+        let db_path = temp_path("vrrb_test_verify_ledger_hash.db");
+        let _ = fs::remove_file(&db_path);
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.update_ledger(test_ledger(10_000), RewardState::start());
+        assert!(network_state.verify_ledger_hash());
+        assert!(NetworkState::restore(&db_path).verify_ledger_hash());
+
+        let mut db = network_state.get_ledger_db();
+        let mut credits: LinkedHashMap<String, u128> = db.get("credits").unwrap();
+        *credits.get_mut("address_5000").unwrap() += 1;
+        db.set("credits", &credits).unwrap();
+        db.dump().unwrap();
+        assert!(!network_state.verify_ledger_hash());
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_version_one_snapshots_still_verify() {
+        let ledger = test_ledger(3);
+        let reward_state = RewardState::start();
+        let mut snapshot = StateSnapshot::new(ledger.clone(), reward_state.clone(), None);
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert!(snapshot.verify());
+
+        // Exported before the version was recorded, hashing the ledger's JSON.
+        let mut legacy: serde_json::Value = serde_json::from_str(&snapshot.to_string()).unwrap();
+        legacy.as_object_mut().unwrap().remove("version");
+        legacy["hash"] = StateSnapshot::integrity_hash(1, &ledger, &reward_state, &None).into();
+        let legacy = StateSnapshot::from_bytes(legacy.to_string().as_bytes()).unwrap();
+        assert_eq!(legacy.version, 1);
+        assert!(legacy.verify());
+
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(!snapshot.verify());
+    }
+
+    #[test]
+    fn test_slashed_claims_are_listed_as_ineligible() {
+        let db_path = temp_path("vrrb_test_ineligible.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let mut block = test_block(0, &miner, 100, vec![]);
+        ["pubkey_a", "pubkey_b"].iter().for_each(|pubkey| {
+            let claim = Claim::new(pubkey.to_string(), test_address(pubkey), 1);
+            block.claims.insert(claim.pubkey.clone(), claim);
+        });
+
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+        assert!(network_state.get_ineligible_claims().is_empty());
+
+        network_state.slash_claims(vec!["pubkey_a".to_string()], 0);
+        let ineligible: Vec<String> = network_state
+            .get_ineligible_claims()
+            .into_iter()
+            .map(|claim| claim.pubkey)
+            .collect();
+        assert_eq!(ineligible, vec!["pubkey_a".to_string()]);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    fn test_reinstatement(owner: &str, pubkey: &str, bond: u128) -> Txn {
+        let mut txn = test_transfer(owner, owner, bond);
+        txn.kind = TxnKind::ClaimReinstatement;
+        txn.sender_public_key = pubkey.to_string();
+        txn
+    }
+
+    // A ledger where the owner of claim "pubkey" holds 5000 coins and the claim
+    // was slashed at height 0.
+    fn slashed_state(name: &str) -> (NetworkState, String, String) {
+        let db_path = temp_path(name);
+        let _ = fs::remove_file(&db_path);
+        let owner = test_address("owner");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &owner, 5000, vec![]));
+        network_state.slash_claims(vec!["pubkey".to_string()], 0);
+        assert!(!network_state.get_claims().get("pubkey").unwrap().eligible);
+        (network_state, owner, db_path)
+    }
+
+    #[test]
+    fn test_reinstatement_after_delay_restores_eligibility_and_bond() {
+        let (mut network_state, owner, db_path) = slashed_state("vrrb_test_reinstate.db");
+        network_state.dump(&test_block(
+            1,
+            &owner,
+            0,
+            vec![test_reinstatement(&owner, "pubkey", 2000)],
+        ));
+        assert_eq!(network_state.get_balance(&owner), 3000);
+
+        network_state.dump(&test_block(REINSTATEMENT_DELAY, &owner, 0, vec![]));
+        assert!(!network_state.get_claims().get("pubkey").unwrap().eligible);
+
+        network_state.dump(&test_block(REINSTATEMENT_DELAY + 1, &owner, 0, vec![]));
+        assert!(network_state.get_claims().get("pubkey").unwrap().eligible);
+        assert_eq!(network_state.get_balance(&owner), 5000);
+        let history = network_state.get_offense_history("pubkey").unwrap();
+        assert!(!history.slashed);
+        assert!(history.pending.is_none());
+        let json_path = temp_path("vrrb_test_reinstate.json");
+        assert!(network_state
+            .export_address_activity(&owner, ExportFormat::Json, &json_path)
+            .is_ok());
+
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(json_path);
+    }
+
+    #[test]
+    fn test_rejected_reinstatement_is_not_debited() {
+        let db_path = temp_path("vrrb_test_rejected_reinstatement.db");
+        let _ = fs::remove_file(&db_path);
+        let owner = test_address("owner");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &owner, 5000, vec![]));
+
+        // The claim was never slashed, so there is nothing to reinstate.
+        let block = test_block(
+            1,
+            &owner,
+            0,
+            vec![test_reinstatement(&owner, "pubkey", 2000)],
+        );
+        assert_eq!(
+            network_state.clone().debit_hash(&block),
+            network_state
+                .clone()
+                .debit_hash(&test_block(1, &owner, 0, vec![]))
+        );
+        network_state.dump(&block);
+        assert_eq!(network_state.get_balance(&owner), 5000);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_slash_during_reinstatement_forfeits_bond() {
+        let (mut network_state, owner, db_path) = slashed_state("vrrb_test_forfeit.db");
+        network_state.dump(&test_block(
+            1,
+            &owner,
+            0,
+            vec![test_reinstatement(&owner, "pubkey", 2000)],
+        ));
+        network_state.slash_claims(vec!["pubkey".to_string()], 5);
+
+        network_state.dump(&test_block(REINSTATEMENT_DELAY * 4, &owner, 0, vec![]));
+        assert!(!network_state.get_claims().get("pubkey").unwrap().eligible);
+        assert_eq!(network_state.get_balance(&owner), 3000);
+        let history = network_state.get_offense_history("pubkey").unwrap();
+        assert_eq!(history.offenses, vec![0, 5]);
+        assert!(history.pending.is_none());
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_repeat_offense_doubles_next_delay() {
+        let (mut network_state, owner, db_path) = slashed_state("vrrb_test_double_delay.db");
+        network_state.dump(&test_block(
+            1,
+            &owner,
+            0,
+            vec![test_reinstatement(&owner, "pubkey", 2000)],
+        ));
+        network_state.slash_claims(vec!["pubkey".to_string()], 5);
+
+        network_state.dump(&test_block(
+            10,
+            &owner,
+            0,
+            vec![test_reinstatement(&owner, "pubkey", 1000)],
+        ));
+        let history = network_state.get_offense_history("pubkey").unwrap();
+        assert_eq!(history.delay, REINSTATEMENT_DELAY * 2);
+        assert_eq!(
+            history.pending.unwrap().release_height,
+            10 + REINSTATEMENT_DELAY * 2
+        );
+
+        network_state.dump(&test_block(10 + REINSTATEMENT_DELAY, &owner, 0, vec![]));
+        assert!(!network_state.get_claims().get("pubkey").unwrap().eligible);
+
+        network_state.dump(&test_block(10 + REINSTATEMENT_DELAY * 2, &owner, 0, vec![]));
+        assert!(network_state.get_claims().get("pubkey").unwrap().eligible);
+        assert_eq!(network_state.get_balance(&owner), 3000);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_components_with_mismatched_ledger_are_rejected() {
+        let db_path = temp_path("vrrb_test_components.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let sender = test_address("sender");
+        let block = test_block(1, &miner, 100, vec![test_transfer(&miner, &sender, 40)]);
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+
+        let components = Components {
+            genesis: None,
+            child: Some(block.as_bytes()),
+            parent: None,
+            blockchain: None,
+            ledger: Some(network_state.db_to_ledger().as_bytes()),
+            network_state: Some(network_state.as_bytes()),
+            archive: None,
+        };
+        assert!(components.verify().is_ok());
+
+        let mut ledger = network_state.db_to_ledger();
+        ledger.credits.insert(sender.clone(), 1000);
+        let tampered = Components {
+            ledger: Some(ledger.as_bytes()),
+            ..components.clone()
+        };
+        assert!(tampered.verify().is_err());
+
+        // The ledger hash covers the claims, not only the balances.
+        let mut ledger = network_state.db_to_ledger();
+        let claim = Claim::new("other_pubkey".to_string(), sender.clone(), 1);
+        ledger.claims.insert(claim.pubkey.clone(), claim);
+        let extra_claim = Components {
+            ledger: Some(ledger.as_bytes()),
+            ..components.clone()
+        };
+        assert!(extra_claim.verify().is_err());
+
+        let other_block = test_block(2, &miner, 100, vec![]);
+        let wrong_child = Components {
+            child: Some(other_block.as_bytes()),
+            ..components.clone()
+        };
+        assert!(wrong_child.verify().is_err());
+
+        let partial = Components {
+            ledger: None,
+            ..components
+        };
+        assert!(partial.verify().is_err());
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    fn db_bytes<V: Serialize + serde::de::DeserializeOwned>(path: &str, key: &str) -> Vec<u8> {
+        let db = PickleDb::load_bin(path, PickleDbDumpPolicy::DumpUponRequest).unwrap();
+        serde_json::to_vec(&db.get::<V>(key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_claims_only_block_leaves_balances_untouched() {
+        let db_path = temp_path("vrrb_test_claims_only.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(
+            0,
+            &miner,
+            100,
+            vec![test_transfer(&miner, &test_address("a"), 10)],
+        ));
+        let credits = db_bytes::<LinkedHashMap<String, u128>>(&db_path, "credits");
+        let debits = db_bytes::<LinkedHashMap<String, u128>>(&db_path, "debits");
+
+        let mut block = test_block(1, &miner, 0, vec![]);
+        let claim = Claim::new("pubkey_a".to_string(), test_address("a"), 1);
+        block.claims.insert(claim.pubkey.clone(), claim);
+        network_state.dump(&block);
+
+        assert!(network_state.get_claims().contains_key("pubkey_a"));
+        assert_eq!(
+            db_bytes::<LinkedHashMap<String, u128>>(&db_path, "credits"),
+            credits
+        );
+        assert_eq!(
+            db_bytes::<LinkedHashMap<String, u128>>(&db_path, "debits"),
+            debits
+        );
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_txn_only_block_leaves_claims_untouched() {
+        let db_path = temp_path("vrrb_test_txn_only.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &miner, 100, vec![]));
+        let claims = db_bytes::<LinkedHashMap<String, Claim>>(&db_path, "claims");
+
+        network_state.dump(&test_block(
+            1,
+            &miner,
+            0,
+            vec![test_transfer(&miner, &test_address("a"), 10)],
+        ));
+
+        assert_eq!(network_state.get_balance(&test_address("a")), 10);
+        assert_eq!(
+            db_bytes::<LinkedHashMap<String, Claim>>(&db_path, "claims"),
+            claims
+        );
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_partial_writes_match_full_writes() {
+        let db_path = temp_path("vrrb_test_partial_writes.db");
+        let control_path = temp_path("vrrb_test_full_writes.db");
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(&control_path);
+        let miner = test_address("miner");
+        let mut claims_block = test_block(1, &miner, 0, vec![]);
+        let claim = Claim::new("pubkey_a".to_string(), test_address("a"), 1);
+        claims_block.claims.insert(claim.pubkey.clone(), claim);
+        let blocks = vec![
+            test_block(0, &miner, 100, vec![]),
+            claims_block,
+            test_block(
+                2,
+                &miner,
+                0,
+                vec![test_transfer(&miner, &test_address("a"), 10)],
+            ),
+            test_block(3, &miner, 0, vec![]),
+        ];
+
+        let mut network_state = NetworkState::restore(&db_path);
+        let mut control = NetworkState::restore(&control_path);
+        blocks.iter().for_each(|block| {
+            network_state.dump(block);
+            control.dirty = DirtyKeys::all();
+            control.dump(block);
+        });
+
+        assert_eq!(network_state.state_hash, control.state_hash);
+        assert_eq!(network_state.credits, control.credits);
+        assert_eq!(network_state.debits, control.debits);
+        assert_eq!(network_state.ledger_hash(), control.ledger_hash());
+        assert_eq!(network_state.get_credits(), control.get_credits());
+        assert_eq!(network_state.get_debits(), control.get_debits());
+        assert_eq!(network_state.get_claims(), control.get_claims());
+
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(control_path);
+    }
+
+    #[test]
+    fn test_token_issuance_and_transfer() {
+        let db_path = temp_path("vrrb_test_tokens.db");
+        let _ = fs::remove_file(&db_path);
+        let mut wallet = WalletAccount::new();
+        let issuer = wallet.get_address(1);
+        let receiver = test_address("receiver");
+        let miner = test_address("miner");
+        let metadata = TokenMetadata {
+            name: "Token".to_string(),
+            decimals: 2,
+        };
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &issuer, 100, vec![]));
+
+        let issuance = wallet.issue_token(1, "TKN", &metadata, 1000, 1);
+        assert!(issuance.valid_txn(&network_state, None).is_ok());
+        network_state.dump(&test_block(1, &miner, 0, vec![issuance.clone()]));
+        assert_eq!(network_state.get_token("TKN").unwrap().issuer, issuer);
+        assert_eq!(network_state.get_token_balance(&issuer, "TKN"), 1000);
+        assert_eq!(
+            invalid_reason(&issuance, &network_state),
+            InvalidTxnErrorReason::InvalidTokenIssuance
+        );
+
+        let transfer = wallet
+            .send_token(1, receiver.clone(), "TKN", 300)
+            .unwrap()
+            .txn;
+        assert!(transfer.valid_txn(&network_state, None).is_ok());
+        let too_much = wallet
+            .send_token(1, receiver.clone(), "TKN", 1001)
+            .unwrap()
+            .txn;
+        assert_eq!(
+            invalid_reason(&too_much, &network_state),
+            InvalidTxnErrorReason::InsufficientBalance
+        );
+        let unknown = wallet
+            .send_token(1, receiver.clone(), "NONE", 1)
+            .unwrap()
+            .txn;
+        assert_eq!(
+            invalid_reason(&unknown, &network_state),
+            InvalidTxnErrorReason::UnknownToken
+        );
+
+        network_state.dump(&test_block(2, &miner, 0, vec![transfer]));
+        assert_eq!(network_state.get_token_balance(&issuer, "TKN"), 700);
+        assert_eq!(network_state.get_token_balance(&receiver, "TKN"), 300);
+        // Both txns paid their fee to the miner in the native coin.
+        assert_eq!(network_state.get_balance(&issuer), 98);
+        assert_eq!(network_state.get_balance(&miner), 2);
+        assert_eq!(network_state.get_balance(&receiver), 0);
+        assert!(network_state.get_address_activity(&receiver).is_empty());
+        assert_eq!(network_state.get_token_balance(&miner, "TKN"), 0);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_token_state_is_in_the_ledger_and_its_hashes() {
+        let db_path = temp_path("vrrb_test_token_ledger.db");
+        let import_path = temp_path("vrrb_test_token_ledger_import.db");
+        let snapshot_path = temp_path("vrrb_test_token_ledger.snapshot");
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(&import_path);
+        let mut wallet = WalletAccount::new();
+        let issuer = wallet.get_address(1);
+        let receiver = test_address("receiver");
+        let miner = test_address("miner");
+        let metadata = TokenMetadata {
+            name: "Token".to_string(),
+            decimals: 2,
+        };
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &issuer, 100, vec![]));
+        let untokened = network_state.db_to_ledger();
+        assert!(untokened.tokens.is_empty());
+
+        let issuance = wallet.issue_token(1, "TKN", &metadata, 1000, 1);
+        network_state.dump(&test_block(1, &miner, 0, vec![issuance]));
+        let issued = network_state.db_to_ledger();
+        assert!(issued.tokens.tokens.contains_key("TKN"));
+        assert_ne!(issued.canonical_hash(), untokened.canonical_hash());
+
+        // Moving a token leaves the native sums as they'd be for the fee
+        // alone, the state hash still commits to the amount moved.
+        let small = wallet
+            .send_token(1, receiver.clone(), "TKN", 300)
+            .unwrap()
+            .txn;
+        let large = wallet
+            .send_token(1, receiver.clone(), "TKN", 400)
+            .unwrap()
+            .txn;
+        let small = test_block(2, &miner, 0, vec![small]);
+        let large = test_block(2, &miner, 0, vec![large]);
+        assert_eq!(
+            network_state.clone().credit_hash(&small),
+            network_state.clone().credit_hash(&large)
+        );
+        assert_eq!(
+            network_state.clone().debit_hash(&small),
+            network_state.clone().debit_hash(&large)
+        );
+        assert_ne!(
+            NetworkState::token_txns_hash(&small),
+            NetworkState::token_txns_hash(&large)
+        );
+        assert!(NetworkState::token_txns_hash(&test_block(2, &miner, 0, vec![])).is_none());
+
+        network_state.dump(&small);
+        let transferred = network_state.db_to_ledger();
+        assert_ne!(transferred.canonical_hash(), issued.canonical_hash());
+
+        // A snapshot carries the token registry and balances across.
+        network_state.export_snapshot(&snapshot_path).unwrap();
+        let imported = NetworkState::import_snapshot(&snapshot_path, &import_path).unwrap();
+        assert_eq!(imported.db_to_ledger().tokens, transferred.tokens);
+        assert_eq!(imported.get_token("TKN"), network_state.get_token("TKN"));
+        assert_eq!(imported.get_token_balance(&issuer, "TKN"), 700);
+        assert_eq!(imported.get_token_balance(&receiver, "TKN"), 300);
+
+        let _ = fs::remove_file(db_path);
+        let _ = fs::remove_file(import_path);
+        let _ = fs::remove_file(snapshot_path);
+    }
+
+    #[test]
+    fn test_interrupted_dump_is_reapplied_once_on_restore() {
+        let db_path = temp_path("vrrb_test_wal.db");
+        let wal = BlockWal::for_db(&db_path);
+        let _ = fs::remove_file(&db_path);
+        let _ = fs::remove_file(&wal.path);
+        let miner = test_address("miner");
+        let other = test_address("other");
+
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &miner, 1000, vec![]));
+        // Committed entries are truncated, leaving no log behind.
+        assert!(wal.uncommitted().is_empty());
+        assert!(!std::path::Path::new(&wal.path).exists());
+
+        // Crash after the block is logged and applied, before the db is dumped.
+        let block = test_block(1, &other, 50, vec![test_transfer(&miner, &other, 300)]);
+        wal.begin(&block).unwrap();
+        let _ = network_state.write_block(&block);
+        assert_eq!(network_state.get_balance(&other), 0);
+
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(restored.get_balance(&miner), 700);
+        assert_eq!(restored.get_balance(&other), 350);
+        assert!(wal.uncommitted().is_empty());
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(restored.get_balance(&other), 350);
+
+        // Crash after the db is dumped, before the block is committed.
+        let block = test_block(2, &other, 50, vec![]);
+        wal.begin(&block).unwrap();
+        let (mut db, _) = network_state.write_block(&block);
+        network_state.persist(&mut db).unwrap();
+        assert_eq!(wal.uncommitted().len(), 1);
+
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(restored.get_balance(&other), 400);
+        assert!(wal.uncommitted().is_empty());
+
+        let _ = fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_interrupted_dump_leaves_the_db_intact() {
+        let db_path = temp_path("vrrb_test_staged_dump.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let other = test_address("other");
+
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&test_block(0, &miner, 1000, vec![]));
+        let good_db = fs::read(&db_path).unwrap();
+        assert!(!std::path::Path::new(&network_state.staging_path()).exists());
+
+        // Fail after the staged db is written, before it's renamed over the db.
+        let block = test_block(1, &other, 50, vec![test_transfer(&miner, &other, 300)]);
+        let (mut db, _) = network_state.write_block(&block);
+        network_state.write_staged(&mut db).unwrap();
+
+        assert_eq!(fs::read(&db_path).unwrap(), good_db);
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(restored.get_balance(&miner), 1000);
+        assert_eq!(restored.get_balance(&other), 0);
+
+        // The next dump starts over from the intact db.
+        network_state.dump(&block);
+        assert_eq!(network_state.get_balance(&miner), 700);
+        assert_eq!(network_state.get_balance(&other), 350);
+
+        let _ = fs::remove_file(&db_path);
+    }
+
+    fn assert_claim_indexes_match_scan(network_state: &NetworkState) {
+        let claims = network_state.get_claims();
+        claims.iter().for_each(|(pubkey, claim)| {
+            let address = Address::canonicalize(&claim.address);
+            let mut owned: Vec<String> = claims
+                .iter()
+                .filter(|(_, other)| Address::canonicalize(&other.address) == address)
+                .map(|(pubkey, _)| pubkey.clone())
+                .collect();
+            owned.sort();
+            assert_eq!(network_state.get_claims_by_address(&claim.address), owned);
+            assert_eq!(
+                network_state.get_claim_by_hash(&claim.hash),
+                Some(pubkey.clone())
+            );
+            assert_eq!(
+                network_state.is_claim_eligible(pubkey),
+                Some(claim.eligible)
+            );
+        });
+        assert_eq!(
+            network_state.get_claims_by_address(&test_address("no_claims")),
+            Vec::<String>::new()
+        );
+        assert_eq!(network_state.get_claim_by_hash("no_such_hash"), None);
+        assert_eq!(network_state.is_claim_eligible("no_such_pubkey"), None);
+    }
+
+    #[test]
+    fn test_get_claim_by_pubkey_or_hash() {
+        let db_path = temp_path("vrrb_test_get_claim.db");
+        let _ = fs::remove_file(&db_path);
+        let owner = test_address("owner");
+        let mut block = test_block(0, &owner, 100, vec![]);
+        let claim = Claim::new("pubkey_0".to_string(), owner.clone(), 3);
+        block.claims.insert(claim.pubkey.clone(), claim.clone());
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+
+        let by_pubkey = network_state.get_claim("pubkey_0").unwrap();
+        assert_eq!(by_pubkey.address, owner);
+        assert_eq!(by_pubkey.hash, claim.hash);
+        assert_eq!(by_pubkey.nonce, claim.nonce);
+        assert!(by_pubkey.eligible);
+        let by_hash = network_state.get_claim(&claim.hash).unwrap();
+        assert_eq!(by_hash.pubkey, "pubkey_0");
+        assert_eq!(by_hash.get_pointer(42), claim.get_pointer(42));
+
+        assert!(network_state.get_claim("unknown").is_none());
+        let _ = fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_claim_indexes_stay_consistent() {
+        let db_path = temp_path("vrrb_test_claim_indexes.db");
+        let _ = fs::remove_file(&db_path);
+        let mut rng = rand::thread_rng();
+        let owners: Vec<String> = (0..4).map(|n| test_address(&n.to_string())).collect();
+        let mut block = test_block(0, &owners[0], 100, vec![]);
+        (0..30).for_each(|n| {
+            let claim = Claim::new(
+                format!("pubkey_{}_{}", n, rng.gen::<u32>()),
+                owners[rng.gen_range(0, owners.len())].clone(),
+                rng.gen_range(1, 5),
+            );
+            block.claims.insert(claim.pubkey.clone(), claim);
+        });
+
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+        assert_claim_indexes_match_scan(&network_state);
+
+        let old_hashes: Vec<String> = network_state
+            .get_claims()
+            .values()
+            .map(|claim| claim.hash.clone())
+            .collect();
+        network_state.nonce_up();
+        assert_claim_indexes_match_scan(&network_state);
+        assert!(old_hashes
+            .iter()
+            .all(|hash| network_state.get_claim_by_hash(hash).is_none()));
+
+        let slashed: Vec<String> = block.claims.keys().take(5).cloned().collect();
+        network_state.slash_claims(slashed.clone(), 1);
+        assert_claim_indexes_match_scan(&network_state);
+        assert_eq!(network_state.is_claim_eligible(&slashed[0]), Some(false));
+
+        let abandoned = network_state.get_claims().get(&slashed[1]).unwrap().clone();
+        network_state.abandoned_claim(abandoned.hash.clone());
+        assert_claim_indexes_match_scan(&network_state);
+        assert_eq!(network_state.get_claim_by_hash(&abandoned.hash), None);
+        assert!(!network_state
+            .get_claims_by_address(&abandoned.address)
+            .contains(&abandoned.pubkey));
+
+        // The indexes kept in memory match those written to the db, and
+        // lookups are answered from memory.
+        let claims = network_state.get_claims();
+        let restored = NetworkState::restore(&db_path);
+        assert_claim_indexes_match_scan(&restored);
+        let _ = fs::remove_file(&db_path);
+        assert_claim_indexes_match_scan(&network_state);
+        let (pubkey, claim) = claims.iter().next().unwrap();
+        assert_eq!(network_state.get_claim(&claim.hash), Some(claim.clone()));
+        assert!(network_state
+            .get_claims_by_address(&claim.address)
+            .contains(pubkey));
+    }
+
+    #[test]
+    fn test_cached_claim_map_hash_matches_hashing_the_ledger() {
+        let db_path = temp_path("vrrb_test_cached_claim_map_hash.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let from_scratch = |network_state: &NetworkState| {
+            let claims = NetworkState::restore(&network_state.path).get_claims();
+            ClaimMapHash::from_claim_map(&claims).digest()
+        };
+
+        let mut network_state = NetworkState::restore(&db_path);
+        let mut block = test_block(0, &miner, 100, vec![]);
+        (0..10).for_each(|n| {
+            let claim = Claim::new(format!("pubkey_{}", n), test_address(&n.to_string()), 1);
+            block.claims.insert(claim.pubkey.clone(), claim);
+        });
+        network_state.dump(&block);
+        let no_changes = LinkedHashMap::new();
+        assert_eq!(
+            network_state.claim_map_hash_with(&no_changes, &[]),
+            from_scratch(&network_state)
+        );
+
+        network_state.nonce_up();
+        network_state.slash_claims(vec!["pubkey_3".to_string()], 1);
+        let abandoned = network_state.get_claim_by_pubkey("pubkey_4").unwrap();
+        network_state.abandoned_claim(abandoned.hash);
+        assert_eq!(
+            network_state.claim_map_hash_with(&no_changes, &[]),
+            from_scratch(&network_state)
+        );
+
+        let mut changes = LinkedHashMap::new();
+        let claim = Claim::new("pubkey_new".to_string(), miner.clone(), 1);
+        changes.insert(claim.pubkey.clone(), claim.clone());
+        let mut block = test_block(1, &miner, 0, vec![]);
+        block.claims.insert(claim.pubkey.clone(), claim);
+        let expected = network_state.claim_map_hash_with(&changes, &[]);
+        network_state.dump(&block);
+        assert_eq!(from_scratch(&network_state), expected);
+
+        let _ = fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_block_from_the_loser_of_a_tie_is_rejected() {
+        let db_path = temp_path("vrrb_test_tied_pointers.db");
+        let _ = fs::remove_file(&db_path);
+        // For nonce 0xab both hashes have the lowest possible pointer, 1.
+        let tied: Vec<Claim> = ["ba1", "ba0"]
+            .iter()
+            .enumerate()
+            .map(|(i, prefix)| Claim {
+                pubkey: format!("pubkey_{}", i),
+                address: test_address(&i.to_string()),
+                hash: format!("{}{}", prefix, "f".repeat(61)),
+                nonce: 1,
+                eligible: true,
+            })
+            .collect();
+        let miner = test_address("miner");
+        let mut block = test_block(0, &miner, 100, vec![]);
+        tied.iter().for_each(|claim| {
+            block.claims.insert(claim.pubkey.clone(), claim.clone());
+        });
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+        network_state.abandoned_claim(block.header.claim.hash.clone());
+        assert_eq!(
+            network_state.get_lowest_pointer(0xab),
+            Some((tied[1].hash.clone(), 1))
+        );
+
+        let mut mined = test_block(1, &miner, 100, vec![]);
+        mined.header.block_nonce = 0xab;
+        mined.header.claim = tied[0].clone();
+        assert!(!mined.valid_claim_pointer(&network_state));
+        mined.header.claim = tied[1].clone();
+        assert!(mined.valid_claim_pointer(&network_state));
+
+        let _ = fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_applying_a_nonce_epoch_twice_is_a_no_op() {
+        let db_path = temp_path("vrrb_test_nonce_epoch.db");
+        let _ = std::fs::remove_file(&db_path);
+        let mut claims = LinkedHashMap::new();
+        let claim = Claim::new("pubkey".to_string(), "address".to_string(), 1);
+        claims.insert(claim.pubkey.clone(), claim);
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        assert_eq!(network_state.nonce_epoch(), 0);
+
+        assert!(network_state.nonce_up_to(1));
+        let nonced_up = network_state.get_claim("pubkey").unwrap();
+        assert_eq!(nonced_up.nonce, 2);
+        assert!(!network_state.nonce_up_to(1));
+        assert_eq!(network_state.get_claim("pubkey"), Some(nonced_up));
+
+        // A ledger two epochs behind is bumped twice in one write.
+        assert!(network_state.nonce_up_to(3));
+        assert_eq!(network_state.nonce_epoch(), 3);
+        assert_eq!(network_state.get_claim("pubkey").unwrap().nonce, 4);
+        assert_eq!(NetworkState::restore(&db_path).nonce_epoch(), 3);
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_block_leaving_no_claim_a_pointer_moves_the_nonce_epoch() {
+        let db_path = temp_path("vrrb_test_nonce_epoch_in_block.db");
+        let _ = fs::remove_file(&db_path);
+        let mut claims = LinkedHashMap::new();
+        let claim = Claim::new("pubkey".to_string(), "address".to_string(), 1);
+        claims.insert(claim.pubkey.clone(), claim);
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.update_ledger(
+            Ledger {
+                credits: LinkedHashMap::new(),
+                debits: LinkedHashMap::new(),
+                claims,
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+
+        // The claim has a pointer for nonce 0, the ledger stays on its epoch.
+        network_state.dump(&test_block(1, "miner", 10, vec![]));
+        assert_eq!(network_state.nonce_epoch(), 0);
+        assert_eq!(network_state.get_claim("pubkey").unwrap().nonce, 1);
+
+        // Its hash at nonce 1 has no 'd', so it has no pointer for 0xd.
+        let mut block = test_block(2, "miner", 10, vec![]);
+        block.header.next_block_nonce = 0xd;
+        network_state.dump(&block);
+        assert_eq!(network_state.nonce_epoch(), 1);
+        let nonced_up = network_state.get_claim("pubkey").unwrap();
+        assert_eq!(nonced_up.nonce, 2);
+        assert!(nonced_up.get_pointer(0xd).is_some());
+
+        // Once the claim has a pointer again the epoch stays where it is.
+        let mut block = test_block(3, "miner", 10, vec![]);
+        block.header.claim = nonced_up;
+        block.header.next_block_nonce = 0xd;
+        network_state.dump(&block);
+        assert_eq!(network_state.nonce_epoch(), 1);
+        assert_eq!(NetworkState::restore(&db_path).nonce_epoch(), 1);
+        let _ = fs::remove_file(&db_path);
+    }
+
+    #[test]
+    fn test_total_supply_agrees_with_the_emission_schedule() {
+        let db_path = temp_path("vrrb_test_supply_audit.db");
+        let _ = fs::remove_file(&db_path);
+        let mut network_state = NetworkState::restore(&db_path);
+        assert!(network_state.audit_supply().is_none());
+
+        let miner = test_address("miner");
+        let other = test_address("other");
+        let mut genesis = test_block(0, &miner, 0, vec![]);
+        genesis.header.block_reward =
+            Reward::genesis(Some(miner.clone()), &ProtocolParams::default());
+        network_state.dump(&genesis);
+        network_state.slash_claims(vec!["pubkey".to_string()], 0);
+        // Transfers and a locked bond move coins without changing the supply.
+        (1..=5).for_each(|height| {
+            let txns = if height == 3 {
+                vec![test_reinstatement(&miner, "pubkey", 2000)]
+            } else {
+                vec![test_transfer(&miner, &other, 100)]
+            };
+            network_state.dump(&test_block(height, &miner, height + 1, txns));
+        });
+
+        let audit = network_state.audit_supply().unwrap();
+        assert_eq!(audit.height, 5);
+        assert_eq!(audit.total_supply, GENESIS_REWARD + 2 + 3 + 4 + 5 + 6);
+        assert!(audit.bounds.0 <= audit.expected && audit.expected <= audit.bounds.1);
+        assert!(!audit.diverged());
+
+        // A block's coinbase records its reward rather than minting it again.
+        network_state.dump(&test_block(
+            6,
+            &miner,
+            7,
+            vec![Txn::coinbase(miner.clone(), 7, 0)],
+        ));
+        let audit = network_state.audit_supply().unwrap();
+        assert_eq!(audit.height, 6);
+        assert_eq!(audit.total_supply, GENESIS_REWARD + 2 + 3 + 4 + 5 + 6 + 7);
+        assert!(!audit.diverged());
+
+        // Coins minted outside of block rewards are caught.
+        let mut credits = network_state.get_credits();
+        *credits.entry(other.clone()).or_insert(0) += 1_000_000;
+        network_state.update_ledger(
+            Ledger {
+                credits,
+                debits: network_state.get_debits(),
+                claims: network_state.get_claims(),
+                tokens: TokenLedger::default(),
+            },
+            network_state.get_reward_state(),
+        );
+        let audit = network_state.audit_supply().unwrap();
+        assert!(audit.diverged());
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_flushed_state_matches_the_last_block_after_a_restart() {
+        let db_path = temp_path("vrrb_test_flush_on_shutdown.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let mut network_state = NetworkState::restore(&db_path);
+        (0..3).for_each(|height| network_state.dump(&test_block(height, &miner, 10, vec![])));
+        network_state.flush().unwrap();
+
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(
+            restored.state_hash,
+            Some(test_block(2, &miner, 10, vec![]).hash)
+        );
+        assert_eq!(restored.state_hash, network_state.state_hash);
+        assert_eq!(restored.credits, network_state.credits);
+        assert_eq!(restored.debits, network_state.debits);
+        assert_eq!(restored.reward_state, network_state.reward_state);
+        assert_eq!(restored.get_balance(&miner), 30);
+
+        // A block applied after the flush leaves the flushed hashes stale.
+        network_state.dump(&test_block(3, &miner, 10, vec![]));
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(restored.state_hash, None);
+        assert_eq!(restored.get_balance(&miner), 40);
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_claim_transfer_txn_moves_the_claim_and_the_price_together() {
+        let db_path = temp_path("vrrb_test_claim_transfer_txn.db");
+        let _ = fs::remove_file(&db_path);
+        let (seller, mut buyer) = (WalletAccount::new(), WalletAccount::new());
+        let buyer_address = buyer.get_address(1);
+        let miner = test_address("miner");
+        let claim = owned_claim(&seller, "f".repeat(64));
+        let mut block = test_block(0, &buyer_address, 100, vec![]);
+        block.claims.insert(claim.pubkey.clone(), claim.clone());
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+
+        let sale = |price: u128| {
+            let payload = claim.transfer_payload(&claim.address, &buyer_address, price);
+            ClaimTransfer {
+                claim_pubkey: claim.pubkey.clone(),
+                from: ClaimSigner::sign(&seller, &claim.address, &payload),
+                to: ClaimSigner::sign(&buyer, &buyer_address, &payload),
+                price,
+            }
+        };
+        assert_eq!(
+            invalid_reason(&buyer.clone().buy_claim(&sale(1000), 1), &network_state),
+            InvalidTxnErrorReason::InsufficientBalance
+        );
+
+        let transfer = sale(40);
+        let txn = buyer.buy_claim(&transfer, 1);
+        assert!(txn.valid_txn(&network_state, None).is_ok());
+        let block = test_block(1, &miner, 0, vec![txn]);
+        let claim_map_hash = compute_expected_claim_map_hash(&network_state, &block);
+        network_state.dump(&block);
+        let claims = network_state.get_claims();
+        assert_eq!(
+            claim_map_hash,
+            ClaimMapHash::from_claim_map(&claims).digest()
+        );
+        assert!(!claims.contains_key(&claim.pubkey));
+        let transferred = claims.get(&buyer.get_pubkey()).unwrap();
+        assert_eq!(transferred.address, buyer_address);
+        assert_eq!(transferred.nonce, 2);
+        assert_eq!(network_state.get_balance(&buyer_address), 59);
+        assert_eq!(network_state.get_balance(&claim.address), 40);
+
+        // The seller no longer owns the claim, so the sale can't be paid again.
+        assert_eq!(
+            invalid_reason(&buyer.buy_claim(&transfer, 1), &network_state),
+            InvalidTxnErrorReason::InvalidClaimTransfer
+        );
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_claim_map_hash_after_a_block_keeps_a_slashed_claim_ineligible() {
+        let db_path = temp_path("vrrb_test_claim_map_hash_slashed.db");
+        let _ = fs::remove_file(&db_path);
+        let miner = test_address("miner");
+        let slashed = Claim::new("first".to_string(), "address_first".to_string(), 1);
+        let mut block = test_block(0, &miner, 100, vec![]);
+        block.claims.insert(slashed.pubkey.clone(), slashed.clone());
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+        network_state.slash_claims(vec![slashed.pubkey.clone()], 0);
+
+        // The slashed claim announces itself again in the header of its block.
+        let mut block = test_block(1, &miner, 0, vec![]);
+        block.header.claim = slashed.clone();
+        let claim_map_hash = compute_expected_claim_map_hash(&network_state, &block);
+        network_state.dump(&block);
+        let claims = network_state.get_claims();
+        assert!(!claims.get(&slashed.pubkey).unwrap().eligible);
+        assert_eq!(
+            claim_map_hash,
+            ClaimMapHash::from_claim_map(&claims).digest()
+        );
+        let _ = fs::remove_file(db_path);
+    }
+
+    // A claim owned by the wallet with its hash set, for claims that have to
+    // tie on their pointers.
+    fn owned_claim(wallet: &WalletAccount, hash: String) -> Claim {
+        Claim {
+            pubkey: wallet.get_pubkey(),
+            address: wallet.clone().get_address(1),
+            hash,
+            nonce: 1,
+            eligible: true,
+        }
+    }
+
+    #[test]
+    fn test_downtime_signed_by_a_non_owner_is_rejected() {
+        let db_path = temp_path("vrrb_test_downtime_signature.db");
+        let _ = fs::remove_file(&db_path);
+        let (owner, squatter) = (WalletAccount::new(), WalletAccount::new());
+        let miner = test_address("miner");
+        let mut block = test_block(0, &miner, 100, vec![]);
+        let claim = owned_claim(&owner, "f".repeat(64));
+        block.claims.insert(claim.pubkey.clone(), claim.clone());
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+
+        let mut forged = PlannedDowntime::sign(&squatter, 0, 100);
+        forged.claim_pubkey = claim.pubkey.clone();
+        assert_eq!(
+            invalid_reason(&Txn::planned_downtime(&forged), &network_state),
+            InvalidTxnErrorReason::InvalidPlannedDowntime
+        );
+        let unknown = Txn::planned_downtime(&PlannedDowntime::sign(&squatter, 0, 100));
+        assert_eq!(
+            invalid_reason(&unknown, &network_state),
+            InvalidTxnErrorReason::InvalidPlannedDowntime
+        );
+
+        let txn = Txn::planned_downtime(&PlannedDowntime::sign(&owner, 0, 100));
+        assert!(txn.valid_txn(&network_state, None).is_ok());
+        network_state.dump(&test_block(1, &miner, 0, vec![txn.clone()]));
+        assert_eq!(network_state.downtime_until(&claim.pubkey, 1), Some(100));
+        // An announcement that's already over, or replayed after it is, isn't valid.
+        let mut later = test_block(2, &miner, 0, vec![]);
+        later.header.timestamp = 100;
+        network_state.dump(&later);
+        assert_eq!(
+            invalid_reason(&txn, &network_state),
+            InvalidTxnErrorReason::InvalidPlannedDowntime
+        );
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_downtime_is_granted_from_the_block_timestamp_and_survives_a_restart() {
+        let db_path = temp_path("vrrb_test_downtime_clamped.db");
+        let _ = fs::remove_file(&db_path);
+        let owner = WalletAccount::new();
+        let miner = test_address("miner");
+        let mut block = test_block(0, &miner, 100, vec![]);
+        let claim = owned_claim(&owner, "f".repeat(64));
+        block.claims.insert(claim.pubkey.clone(), claim.clone());
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+
+        // Announced long before the block carrying it, the downtime is cut
+        // short at MAX_DOWNTIME from the block's timestamp.
+        let at = 1_000;
+        let downtime = PlannedDowntime::sign(&owner, 0, 3 * MAX_DOWNTIME);
+        let mut block = test_block(1, &miner, 0, vec![Txn::planned_downtime(&downtime)]);
+        block.header.timestamp = at;
+        network_state.dump(&block);
+        let until = at + MAX_DOWNTIME;
+
+        // Announcing again doesn't keep the claim away any longer.
+        let again = PlannedDowntime::sign(&owner, at, 3 * MAX_DOWNTIME);
+        let mut block = test_block(2, &miner, 0, vec![Txn::planned_downtime(&again)]);
+        block.header.timestamp = at + 1;
+        network_state.dump(&block);
+
+        let restored = NetworkState::restore(&db_path);
+        assert_eq!(restored.downtime_until(&claim.pubkey, at), Some(until));
+        assert_eq!(restored.downtime_until(&claim.pubkey, at - 1), None);
+        assert_eq!(restored.claims_away(until - 1), vec![claim.pubkey.clone()]);
+        assert_eq!(restored.downtime_until(&claim.pubkey, until), None);
+        assert!(restored.claims_away(until).is_empty());
+
+        let _ = fs::remove_file(db_path);
+    }
+
+    #[test]
+    fn test_claims_in_downtime_sit_out_the_election() {
+        let db_path = temp_path("vrrb_test_downtime_election.db");
+        let _ = fs::remove_file(&db_path);
+        let wallets = vec![WalletAccount::new(), WalletAccount::new()];
+        // For nonce 0xab both hashes have the lowest possible pointer, 1.
+        let tied: Vec<Claim> = ["ba1", "ba0"]
+            .iter()
+            .zip(wallets.iter())
+            .map(|(prefix, wallet)| owned_claim(wallet, format!("{}{}", prefix, "f".repeat(61))))
+            .collect();
+        let miner = test_address("miner");
+        let mut block = test_block(0, &miner, 100, vec![]);
+        tied.iter().for_each(|claim| {
+            block.claims.insert(claim.pubkey.clone(), claim.clone());
+        });
+        let mut network_state = NetworkState::restore(&db_path);
+        network_state.dump(&block);
+        network_state.abandoned_claim(block.header.claim.hash.clone());
+        assert_eq!(
+            network_state.get_lowest_pointer(0xab),
+            Some((tied[1].hash.clone(), 1))
+        );
+
+        // The winner's owner goes offline until after the next block.
+        let downtime = Txn::planned_downtime(&PlannedDowntime::sign(&wallets[1], 0, 10));
+        let mut block = test_block(1, &miner, 0, vec![downtime]);
+        block.header.claim = tied[0].clone();
+        block.header.next_block_nonce = 0xab;
+        network_state.dump(&block);
+        assert_eq!(
+            network_state.get_lowest_pointer(0xab),
+            Some((tied[0].hash.clone(), 1))
+        );
+        let mut mined = test_block(2, &miner, 100, vec![]);
+        mined.header.block_nonce = 0xab;
+        mined.header.claim = tied[0].clone();
+        assert!(mined.valid_claim_pointer(&network_state));
+        mined.header.timestamp = 10;
+        network_state.dump(&mined);
+
+        // Blocks after the downtime are the winner's again.
+        assert_eq!(
+            network_state.get_lowest_pointer(0xab),
+            Some((tied[1].hash.clone(), 1))
+        );
+
+        let _ = fs::remove_file(db_path);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::block::Block;
+use crate::claim::Claim;
+use crate::header::BlockHeader;
+use crate::reward::{Category, Reward};
+use ritelinked::LinkedHashMap;
+
+/// A path for a test's db or file in the system's temp dir, so tests don't
+/// leave files in the working directory.
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// An unsigned block with no txns at `block_height`, hashed to `hash` and
+/// built on `<hash>_parent`. Tests change the fields they care about.
+pub fn test_block(block_height: u128, hash: &str) -> Block {
+    let claim = Claim::new("pubkey".to_string(), "address".to_string(), 1);
+    let reward = Reward {
+        miner: None,
+        category: Category::Flake(Some(1)),
+        amount: 1,
+    };
+    Block {
+        header: BlockHeader {
+            last_hash: format!("{}_parent", hash),
+            block_nonce: 0,
+            next_block_nonce: 0,
+            block_height,
+            timestamp: 0,
+            txn_hash: String::new(),
+            block_cost: 0,
+            claim,
+            claim_map_hash: None,
+            block_reward: reward.clone(),
+            next_block_reward: reward,
+            neighbor_hash: None,
+            nonce_epoch: 0,
+            ledger_root: None,
+            signature: String::new(),
+        },
+        neighbors: None,
+        height: block_height,
+        txns: LinkedHashMap::new(),
+        claims: LinkedHashMap::new(),
+        hash: hash.to_string(),
+        received_at: None,
+        received_from: None,
+        abandoned_claim: None,
+    }
+}
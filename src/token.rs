@@ -0,0 +1,112 @@
+use crate::txn::{Txn, TxnKind};
+use crate::wallet::Address;
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+use sha256::digest_bytes;
+use std::collections::BTreeMap;
+
+// The id of the native coin, which can't be issued as a token.
+pub const NATIVE_TOKEN: &str = "VRRB";
+pub const MAX_TOKEN_DECIMALS: u8 = 18;
+
+/// The metadata an issuer attaches to a token issuance txn as its data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub decimals: u8,
+}
+
+/// A token issued on the network, stored in the ledger db's token registry.
+/// The whole supply is credited to the issuer when the token is issued.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub token_id: String,
+    pub name: String,
+    pub decimals: u8,
+    pub total_supply: u128,
+    pub issuer: String,
+}
+
+/// The token registry and the token balances, kept apart from the native
+/// balances. Credits and debits are keyed by token id and then by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenLedger {
+    pub tokens: LinkedHashMap<String, Token>,
+    pub credits: LinkedHashMap<String, LinkedHashMap<String, u128>>,
+    pub debits: LinkedHashMap<String, LinkedHashMap<String, u128>>,
+}
+
+impl TokenMetadata {
+    pub fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_string(data: &str) -> Option<TokenMetadata> {
+        serde_json::from_str::<TokenMetadata>(data).ok()
+    }
+}
+
+impl Token {
+    /// The token a valid issuance txn registers. Anyone may issue a token for
+    /// now, the sender is recorded as its issuer.
+    pub fn from_issuance(txn: &Txn) -> Option<Token> {
+        if txn.kind != TxnKind::TokenIssuance {
+            return None;
+        }
+
+        let token_id = txn.txn_token.clone()?;
+        let metadata = TokenMetadata::from_string(txn.txn_data.as_ref()?)?;
+        if token_id.is_empty() || token_id == NATIVE_TOKEN || metadata.decimals > MAX_TOKEN_DECIMALS
+        {
+            return None;
+        }
+
+        Some(Token {
+            token_id,
+            name: metadata.name,
+            decimals: metadata.decimals,
+            total_supply: txn.txn_amount,
+            issuer: Address::canonicalize(&txn.sender_address),
+        })
+    }
+}
+
+impl TokenLedger {
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty() && self.credits.is_empty() && self.debits.is_empty()
+    }
+
+    /// Hashes the registry and the balances with their entries sorted by
+    /// token id and address, so it only depends on the entries and not on the
+    /// order they were inserted in.
+    pub fn canonical_hash(&self) -> String {
+        let tokens: BTreeMap<&String, &Token> = self.tokens.iter().collect();
+        digest_bytes(
+            format!(
+                "tokens,{:?},{:?},{:?}",
+                tokens,
+                TokenLedger::sorted(&self.credits),
+                TokenLedger::sorted(&self.debits)
+            )
+            .as_bytes(),
+        )
+    }
+
+    /// The canonical hash, None while no token has been issued.
+    pub fn hash(&self) -> Option<String> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.canonical_hash())
+        }
+    }
+
+    fn sorted(
+        balances: &LinkedHashMap<String, LinkedHashMap<String, u128>>,
+    ) -> BTreeMap<&String, BTreeMap<&String, &u128>> {
+        balances
+            .iter()
+            .map(|(token_id, balances)| (token_id, balances.iter().collect()))
+            .collect()
+    }
+}
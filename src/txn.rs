@@ -1,30 +1,152 @@
+use crate::claim::{ClaimTransfer, PlannedDowntime};
+use crate::clock::{Clock, SystemClock};
+use crate::miner::QuorumConfig;
+use crate::network::topics::NETWORK_ID;
 use crate::pool::Pool;
+use crate::reward::Reward;
+use crate::slashing::SlashingEvidence;
 use crate::state::NetworkState;
+use crate::token::{Token, TokenMetadata};
 use crate::verifiable::Verifiable;
-use crate::wallet::WalletAccount;
+use crate::wallet::{Address, WalletAccount};
 use bytebuffer::ByteBuffer;
 use secp256k1::{Message, PublicKey, Secp256k1, Signature};
 use serde::{Deserialize, Serialize};
 use sha256::digest_bytes;
 use std::collections::HashMap;
+use std::error::Error;
 use std::fmt;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+// The lowest fee recommended for a txn, used when there are no recent fees to go by.
+pub const MIN_TXN_FEE: u128 = 1;
+// The cost of a txn in cost units, an accounting of the work a txn takes to
+// process ahead of programmable txns. A plain transfer costs a little over the
+// base cost.
+pub const BASE_TXN_COST: u64 = 1000;
+pub const TXN_COST_PER_BYTE: u64 = 1;
+pub const TXN_COST_PER_OUTPUT: u64 = 100;
+// The signature cache is cleared rather than growing past this many txns.
+pub const MAX_CACHED_SIGNATURES: usize = 100_000;
+
+static SIGNATURE_CACHE: Mutex<Option<SignatureCache>> = Mutex::new(None);
+
+/// The kind of operation a txn performs. Coinbase txns mint the block reward
+/// and have no sender, so they are validated differently to the others.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TxnKind {
+    Transfer,
+    Coinbase,
+    ClaimStake,
+    // Pays txn_amount, the price, from the buyer to the seller of the claim
+    // in the ClaimTransfer in txn_data, which is rekeyed to the buyer.
+    ClaimTransfer,
+    // Locks txn_amount as a bond to reinstate the sender's slashed claim, the
+    // bond is returned to the receiver (the sender's own address) on release.
+    ClaimReinstatement,
+    // Registers txn_token with the metadata in txn_data and credits txn_amount,
+    // the total supply, to the issuer.
+    TokenIssuance,
+    // Unsigned, carries evidence in txn_data against the claim whose pubkey is
+    // the sender's, slashing the claim when the block carrying it is applied.
+    SlashingEvidence,
+    // Unsigned, carries the PlannedDowntime in txn_data announced by the owner
+    // of the claim whose pubkey is the sender's, granted from the timestamp of
+    // the block carrying it.
+    PlannedDowntime,
+}
+
+impl TxnKind {
+    /// The name a txn's kind is signed under. Spelled out rather than taken
+    /// from the Debug output, so renaming a variant doesn't invalidate the
+    /// signatures of txns already signed.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxnKind::Transfer => "Transfer",
+            TxnKind::Coinbase => "Coinbase",
+            TxnKind::ClaimStake => "ClaimStake",
+            TxnKind::ClaimTransfer => "ClaimTransfer",
+            TxnKind::ClaimReinstatement => "ClaimReinstatement",
+            TxnKind::TokenIssuance => "TokenIssuance",
+            TxnKind::SlashingEvidence => "SlashingEvidence",
+            TxnKind::PlannedDowntime => "PlannedDowntime",
+        }
+    }
+}
+
+/// Why a txn failed validation.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum InvalidTxnErrorReason {
+    InvalidCoinbase,
+    InvalidSenderAddress,
+    InvalidReceiverAddress,
+    // The signed payload doesn't match the txn's fields, so the signature
+    // doesn't cover the sender address, amount or receiver the txn claims.
+    PayloadMismatch,
+    // The txn was signed for another chain, so it can't be replayed on this one.
+    WrongChainId,
+    InvalidSignature,
+    ZeroAmount,
+    InsufficientBalance,
+    DoubleSpend,
+    InvalidNonce,
+    // The txn moves a token that isn't in the registry.
+    UnknownToken,
+    // The token being issued is already registered or its metadata is invalid.
+    InvalidTokenIssuance,
+    // The claim transfer in the txn isn't the one it pays for, or isn't valid
+    // against the claims on the ledger.
+    InvalidClaimTransfer,
+    // The evidence doesn't prove an offense, or is against a claim the ledger
+    // doesn't hold as eligible.
+    InvalidSlashingEvidence,
+    // The downtime announcement isn't signed with the key of a claim on the
+    // ledger, or is already over.
+    InvalidPlannedDowntime,
+    General,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvalidTxnError {
+    pub details: InvalidTxnErrorReason,
+}
+
+/// The txns whose signature has been verified, keyed by txn id. A txn's
+/// signature is checked when it's voted on and again when a block including
+/// it is validated, the cache saves verifying it twice. Each entry keeps a
+/// hash of the signed payload, signature and public key, so a txn whose bytes
+/// change under the same id is verified again.
+#[derive(Debug, Default)]
+pub struct SignatureCache {
+    // txn id -> (signed bytes hash, hits)
+    verified: HashMap<String, (String, usize)>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Txn {
     pub txn_id: String,
+    pub kind: TxnKind,
     pub txn_timestamp: u128,
     pub sender_address: String,
     pub sender_public_key: String,
     pub receiver_address: String,
     pub txn_token: Option<String>,
     pub txn_amount: u128,
+    // fee offered to the miner that includes the txn
+    pub txn_fee: u128,
+    // optional data attached by the sender, covered by the signature
+    pub txn_data: Option<String>,
     pub txn_payload: String,
     pub txn_signature: String,
+    // the chain the txn was signed for, covered by the signature
+    #[serde(default)]
+    pub chain_id: String,
     pub validators: HashMap<String, bool>,
+    // each validator's signature over its vote, keyed by the validator's pubkey
+    #[serde(default)]
+    pub validator_signatures: HashMap<String, String>,
     pub nonce: u128,
 }
 
@@ -34,18 +156,113 @@ impl Txn {
         sender_address: String,
         receiver: String,
         amount: u128,
+        fee: u128,
         nonce: u128,
     ) -> Txn {
-        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Txn::new_with_data(sender, sender_address, receiver, amount, fee, nonce, None)
+    }
 
-        let payload = format!(
-            "{},{},{},{},{},{}",
-            &time.as_nanos().to_string(),
+    /// Creates a signed transfer carrying `data` from the sender.
+    pub fn new_with_data(
+        sender: Arc<Mutex<WalletAccount>>,
+        sender_address: String,
+        receiver: String,
+        amount: u128,
+        fee: u128,
+        nonce: u128,
+        data: Option<String>,
+    ) -> Txn {
+        Txn::new_with_token(
+            sender,
+            sender_address,
+            receiver,
+            None,
+            amount,
+            fee,
+            nonce,
+            data,
+        )
+    }
+
+    /// Creates a signed transfer of `amount` of `token`, or of the native coin
+    /// if there is no token.
+    pub fn new_with_token(
+        sender: Arc<Mutex<WalletAccount>>,
+        sender_address: String,
+        receiver: String,
+        token: Option<String>,
+        amount: u128,
+        fee: u128,
+        nonce: u128,
+        data: Option<String>,
+    ) -> Txn {
+        Txn::new_with_clock(
+            sender,
+            sender_address,
+            receiver,
+            token,
+            amount,
+            fee,
+            nonce,
+            data,
+            &SystemClock,
+        )
+    }
+
+    /// Creates a signed transfer timestamped with `clock`'s time.
+    pub fn new_with_clock(
+        sender: Arc<Mutex<WalletAccount>>,
+        sender_address: String,
+        receiver: String,
+        token: Option<String>,
+        amount: u128,
+        fee: u128,
+        nonce: u128,
+        data: Option<String>,
+        clock: &dyn Clock,
+    ) -> Txn {
+        Txn::new_of_kind(
+            TxnKind::Transfer,
+            sender,
+            sender_address,
+            receiver,
+            token,
+            amount,
+            fee,
+            nonce,
+            data,
+            clock,
+        )
+    }
+
+    /// Creates a txn of `kind` signed by the sender. The kind is part of the
+    /// signed payload, so it has to be known before the txn is signed.
+    fn new_of_kind(
+        kind: TxnKind,
+        sender: Arc<Mutex<WalletAccount>>,
+        sender_address: String,
+        receiver: String,
+        token: Option<String>,
+        amount: u128,
+        fee: u128,
+        nonce: u128,
+        data: Option<String>,
+        clock: &dyn Clock,
+    ) -> Txn {
+        let timestamp = clock.now();
+
+        let payload = Txn::signed_payload(
+            &kind,
+            NETWORK_ID,
+            timestamp,
             &sender_address,
             &sender.lock().unwrap().pubkey.clone(),
             &receiver,
-            &amount.to_string(),
-            &nonce
+            amount,
+            fee,
+            nonce,
+            &data,
+            &token,
         );
         let signature = sender.lock().unwrap().sign(&payload).unwrap();
         let uid_payload = format!(
@@ -57,19 +274,343 @@ impl Txn {
 
         Txn {
             txn_id: digest_bytes(uid_payload.as_bytes()),
-            txn_timestamp: time.as_nanos(),
+            kind,
+            txn_timestamp: timestamp,
             sender_address: sender_address,
             sender_public_key: sender.lock().unwrap().pubkey.clone(),
             receiver_address: receiver,
-            txn_token: None,
+            txn_token: token,
             txn_amount: amount,
+            txn_fee: fee,
+            txn_data: data,
             txn_payload: payload,
             txn_signature: signature.to_string(),
+            chain_id: NETWORK_ID.to_string(),
+            validators: HashMap::new(),
+            validator_signatures: HashMap::new(),
+            nonce,
+        }
+    }
+
+    /// Creates an unsigned txn minting `amount` to the receiver. Coinbase txns
+    /// have no sender address, public key or signature, and are only valid in
+    /// a block as the record of its reward.
+    pub fn coinbase(receiver: String, amount: u128, nonce: u128) -> Txn {
+        Txn::coinbase_with_clock(receiver, amount, nonce, &SystemClock)
+    }
+
+    /// Creates a coinbase txn timestamped with `clock`'s time.
+    pub fn coinbase_with_clock(
+        receiver: String,
+        amount: u128,
+        nonce: u128,
+        clock: &dyn Clock,
+    ) -> Txn {
+        let timestamp = clock.now();
+        let payload = format!(
+            "{},{},{},{}",
+            &timestamp.to_string(),
+            &receiver,
+            &amount.to_string(),
+            &nonce
+        );
+        let uid_payload = format!("{},{}", &payload, Uuid::new_v4().to_string());
+
+        Txn {
+            txn_id: digest_bytes(uid_payload.as_bytes()),
+            kind: TxnKind::Coinbase,
+            txn_timestamp: timestamp,
+            sender_address: String::new(),
+            sender_public_key: String::new(),
+            receiver_address: receiver,
+            txn_token: None,
+            txn_amount: amount,
+            txn_fee: 0,
+            txn_data: None,
+            txn_payload: payload,
+            txn_signature: String::new(),
+            chain_id: NETWORK_ID.to_string(),
             validators: HashMap::new(),
+            validator_signatures: HashMap::new(),
             nonce,
         }
     }
 
+    /// A request to reinstate the sender's slashed claim, locking `bond` from
+    /// `sender_address` until the reinstatement delay has passed.
+    pub fn claim_reinstatement(
+        sender: Arc<Mutex<WalletAccount>>,
+        sender_address: String,
+        bond: u128,
+        fee: u128,
+        nonce: u128,
+    ) -> Txn {
+        Txn::new_of_kind(
+            TxnKind::ClaimReinstatement,
+            sender,
+            sender_address.clone(),
+            sender_address,
+            None,
+            bond,
+            fee,
+            nonce,
+            None,
+            &SystemClock,
+        )
+    }
+
+    /// A buyer's payment of the price of the claim in `transfer`, signed by
+    /// both sides, from the buyer's address to the seller's.
+    pub fn claim_transfer(
+        buyer: Arc<Mutex<WalletAccount>>,
+        transfer: &ClaimTransfer,
+        fee: u128,
+        nonce: u128,
+    ) -> Txn {
+        Txn::new_of_kind(
+            TxnKind::ClaimTransfer,
+            buyer,
+            transfer.to.address.clone(),
+            transfer.from.address.clone(),
+            None,
+            transfer.price,
+            fee,
+            nonce,
+            Some(serde_json::to_string(transfer).unwrap()),
+            &SystemClock,
+        )
+    }
+
+    /// Creates an unsigned txn carrying `evidence` against a claim. It's built
+    /// from the evidence alone, so every node reporting the same offense
+    /// reports the same txn. It moves no coins and pays no fee.
+    pub fn slashing_evidence(evidence: &SlashingEvidence) -> Txn {
+        Txn::slashing_evidence_on_chain(evidence, NETWORK_ID)
+    }
+
+    fn slashing_evidence_on_chain(evidence: &SlashingEvidence, chain_id: &str) -> Txn {
+        Txn::unsigned_on_chain(
+            TxnKind::SlashingEvidence,
+            evidence.timestamp(),
+            evidence.offender(),
+            serde_json::to_string(evidence).unwrap(),
+            chain_id,
+        )
+    }
+
+    /// Creates an unsigned txn carrying a claim owner's announcement of
+    /// `downtime`, built from the announcement alone like evidence is. It moves
+    /// no coins and pays no fee.
+    pub fn planned_downtime(downtime: &PlannedDowntime) -> Txn {
+        Txn::planned_downtime_on_chain(downtime, NETWORK_ID)
+    }
+
+    fn planned_downtime_on_chain(downtime: &PlannedDowntime, chain_id: &str) -> Txn {
+        Txn::unsigned_on_chain(
+            TxnKind::PlannedDowntime,
+            downtime.announced_at,
+            &downtime.claim_pubkey,
+            serde_json::to_string(downtime).unwrap(),
+            chain_id,
+        )
+    }
+
+    // A txn with no sender address, amount, fee or signature, about the claim
+    // with `pubkey`. Its id is the digest of its payload, so it's the same
+    // txn whichever node builds it from `data`.
+    fn unsigned_on_chain(
+        kind: TxnKind,
+        timestamp: u128,
+        pubkey: &str,
+        data: String,
+        chain_id: &str,
+    ) -> Txn {
+        let data = Some(data);
+        let payload = Txn::signed_payload(
+            &kind, chain_id, timestamp, "", pubkey, "", 0, 0, 0, &data, &None,
+        );
+
+        Txn {
+            txn_id: digest_bytes(payload.as_bytes()),
+            kind,
+            txn_timestamp: timestamp,
+            sender_address: String::new(),
+            sender_public_key: pubkey.to_string(),
+            receiver_address: String::new(),
+            txn_token: None,
+            txn_amount: 0,
+            txn_fee: 0,
+            txn_data: data,
+            txn_payload: payload,
+            txn_signature: String::new(),
+            chain_id: chain_id.to_string(),
+            validators: HashMap::new(),
+            validator_signatures: HashMap::new(),
+            nonce: 0,
+        }
+    }
+
+    /// Issues `total_supply` of a new token to `sender_address`, which is
+    /// recorded as the token's issuer.
+    pub fn token_issuance(
+        sender: Arc<Mutex<WalletAccount>>,
+        sender_address: String,
+        token_id: String,
+        metadata: &TokenMetadata,
+        total_supply: u128,
+        fee: u128,
+        nonce: u128,
+    ) -> Txn {
+        Txn::new_of_kind(
+            TxnKind::TokenIssuance,
+            sender,
+            sender_address.clone(),
+            sender_address,
+            Some(token_id),
+            total_supply,
+            fee,
+            nonce,
+            Some(metadata.to_string()),
+            &SystemClock,
+        )
+    }
+
+    /// The payload the sender signs, built from the fields of the txn. It starts
+    /// with the chain id, so a txn signed on one network fails validation on
+    /// another that the same keys are used on. Data and the token are only
+    /// appended when present so native txns without data keep the same payload.
+    /// Any txn but a transfer is prefixed with its kind, so a signed transfer
+    /// can't be passed off as a claim or bond op, or one of those as a transfer.
+    pub fn signed_payload(
+        kind: &TxnKind,
+        chain_id: &str,
+        timestamp: u128,
+        sender_address: &str,
+        sender_public_key: &str,
+        receiver: &str,
+        amount: u128,
+        fee: u128,
+        nonce: u128,
+        data: &Option<String>,
+        token: &Option<String>,
+    ) -> String {
+        let mut payload = format!(
+            "{},{},{},{},{},{},{},{}",
+            chain_id, timestamp, sender_address, sender_public_key, receiver, amount, fee, nonce
+        );
+        if let Some(data) = data {
+            payload = format!("{},{}", payload, data);
+        }
+        if let Some(token) = token {
+            payload = format!("{},token:{}", payload, token);
+        }
+        if *kind != TxnKind::Transfer {
+            payload = format!("kind:{},{}", kind.as_str(), payload);
+        }
+
+        payload
+    }
+
+    /// The payload a validator signs to vote on the txn. It starts with
+    /// "vote", so a vote can't pass for the sender's signature over the txn.
+    pub fn vote_payload(&self, vote: bool) -> String {
+        Txn::vote_payload_for(&self.txn_id, &self.txn_payload, vote)
+    }
+
+    /// The payload a validator signs to vote on the txn with `txn_id` and
+    /// `txn_payload`.
+    pub fn vote_payload_for(txn_id: &str, txn_payload: &str, vote: bool) -> String {
+        format!("vote,{},{},{}", txn_id, txn_payload, vote)
+    }
+
+    /// Whether the vote recorded for `pubkey` carries that validator's
+    /// signature over it.
+    pub fn valid_vote_signature(&self, pubkey: &str) -> bool {
+        match (
+            self.validators.get(pubkey),
+            self.validator_signatures.get(pubkey),
+        ) {
+            (Some(vote), Some(signature)) => self.verify_vote(pubkey, *vote, signature),
+            _ => false,
+        }
+    }
+
+    pub fn verify_vote(&self, pubkey: &str, vote: bool, signature: &str) -> bool {
+        if let (Ok(signature), Ok(pubkey)) =
+            (Signature::from_str(signature), PublicKey::from_str(pubkey))
+        {
+            WalletAccount::verify(self.vote_payload(vote), signature, pubkey).unwrap_or(false)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.kind == TxnKind::Coinbase
+    }
+
+    pub fn is_claim_reinstatement(&self) -> bool {
+        self.kind == TxnKind::ClaimReinstatement
+    }
+
+    pub fn is_token_issuance(&self) -> bool {
+        self.kind == TxnKind::TokenIssuance
+    }
+
+    pub fn is_claim_transfer(&self) -> bool {
+        self.kind == TxnKind::ClaimTransfer
+    }
+
+    /// The claim transfer a claim transfer txn pays for, None for any other
+    /// txn or if its data isn't a claim transfer.
+    pub fn claim_transfer_data(&self) -> Option<ClaimTransfer> {
+        if !self.is_claim_transfer() {
+            return None;
+        }
+        serde_json::from_str(self.txn_data.as_ref()?).ok()
+    }
+
+    pub fn is_slashing_evidence(&self) -> bool {
+        self.kind == TxnKind::SlashingEvidence
+    }
+
+    /// The evidence an evidence txn carries, None for any other txn or if its
+    /// data isn't evidence.
+    pub fn evidence(&self) -> Option<SlashingEvidence> {
+        if !self.is_slashing_evidence() {
+            return None;
+        }
+        serde_json::from_str(self.txn_data.as_ref()?).ok()
+    }
+
+    pub fn is_planned_downtime(&self) -> bool {
+        self.kind == TxnKind::PlannedDowntime
+    }
+
+    /// The announcement a downtime txn carries, None for any other txn or if
+    /// its data isn't a downtime announcement.
+    pub fn downtime(&self) -> Option<PlannedDowntime> {
+        if !self.is_planned_downtime() {
+            return None;
+        }
+        serde_json::from_str(self.txn_data.as_ref()?).ok()
+    }
+
+    /// Whether the txn moves a token rather than the native coin.
+    pub fn is_token_txn(&self) -> bool {
+        self.txn_token.is_some()
+    }
+
+    /// The native coins the txn takes from the sender, its fee and, unless it
+    /// moves a token, its amount.
+    pub fn native_cost(&self) -> u128 {
+        if self.is_token_txn() {
+            self.txn_fee
+        } else {
+            self.txn_amount.saturating_add(self.txn_fee)
+        }
+    }
+
     // TODO: convert to_message into a function of the verifiable trait,
     // all verifiable objects need to be able to be converted to a message.
     pub fn to_string(&self) -> String {
@@ -89,77 +630,484 @@ impl Txn {
         serde_json::from_str::<Txn>(string).unwrap()
     }
 
+    /// Validates the txn as a validator on the chain `chain_id` would, txns
+    /// signed for any other chain are rejected.
+    pub fn valid_txn_on_chain(
+        &self,
+        chain_id: &str,
+        network_state: &NetworkState,
+        txn_pool: Option<&Pool<String, Txn>>,
+    ) -> Result<(), InvalidTxnError> {
+        match self.kind {
+            // Only valid in a block as its reward, see valid_coinbase_for.
+            TxnKind::Coinbase => {
+                println!("Coinbase txn outside of a block");
+                Err(InvalidTxnError {
+                    details: InvalidTxnErrorReason::InvalidCoinbase,
+                })
+            }
+            TxnKind::Transfer
+            | TxnKind::ClaimStake
+            | TxnKind::ClaimReinstatement
+            | TxnKind::TokenIssuance => self.valid_signed_txn(chain_id, network_state, txn_pool),
+            TxnKind::ClaimTransfer => {
+                self.valid_signed_txn(chain_id, network_state, txn_pool)?;
+                self.valid_claim_transfer(network_state)
+            }
+            TxnKind::SlashingEvidence => {
+                if self.chain_id != chain_id {
+                    return Err(InvalidTxnError {
+                        details: InvalidTxnErrorReason::WrongChainId,
+                    });
+                }
+                self.valid_slashing_evidence(network_state)
+            }
+            TxnKind::PlannedDowntime => {
+                if self.chain_id != chain_id {
+                    return Err(InvalidTxnError {
+                        details: InvalidTxnErrorReason::WrongChainId,
+                    });
+                }
+                self.valid_planned_downtime(network_state)
+            }
+        }
+    }
+
+    /// A claim transfer txn has to be signed by the buyer in the transfer it
+    /// carries, paying the price in the native coin to the seller, and the
+    /// transfer has to be valid against the claims on the ledger.
+    pub fn valid_claim_transfer(
+        &self,
+        network_state: &NetworkState,
+    ) -> Result<(), InvalidTxnError> {
+        let invalid = InvalidTxnError {
+            details: InvalidTxnErrorReason::InvalidClaimTransfer,
+        };
+        let transfer = self.claim_transfer_data().ok_or_else(|| invalid.clone())?;
+        if self.sender_public_key != transfer.to.pubkey
+            || self.sender_address != transfer.to.address
+            || self.receiver_address != transfer.from.address
+            || self.txn_amount != transfer.price
+            || self.is_token_txn()
+        {
+            return Err(invalid);
+        }
+        if let Err(e) = transfer.validate(|pubkey| network_state.get_claim_by_pubkey(pubkey)) {
+            println!("Invalid claim transfer {}: {}", self.txn_id, e);
+            return Err(invalid);
+        }
+
+        Ok(())
+    }
+
+    /// An evidence txn has no signature of its own, it has to be exactly the
+    /// txn built from the evidence it carries, the evidence has to prove the
+    /// offense and the claim has to be one the ledger still holds as eligible.
+    pub fn valid_slashing_evidence(
+        &self,
+        network_state: &NetworkState,
+    ) -> Result<(), InvalidTxnError> {
+        let invalid = InvalidTxnError {
+            details: InvalidTxnErrorReason::InvalidSlashingEvidence,
+        };
+        let evidence = self.evidence().ok_or_else(|| invalid.clone())?;
+        let mut unvoted = self.clone();
+        unvoted.validators.clear();
+        unvoted.validator_signatures.clear();
+        if unvoted.to_string()
+            != Txn::slashing_evidence_on_chain(&evidence, &self.chain_id).to_string()
+        {
+            return Err(invalid);
+        }
+        if let Err(e) = evidence.verify() {
+            println!("Invalid slashing evidence {}: {}", self.txn_id, e);
+            return Err(invalid);
+        }
+        if network_state.is_claim_eligible(evidence.offender()) != Some(true) {
+            println!(
+                "Slashing evidence {} against a claim that isn't eligible",
+                self.txn_id
+            );
+            return Err(invalid);
+        }
+        // A yes vote is only an offense if the txn was voted down by a quorum
+        // of the claims eligible for the next block, other than the voter's.
+        if let SlashingEvidence::RejectedTxnVote { .. } = evidence {
+            let eligible = network_state.eligible_validators(u128::MAX);
+            let rejections = evidence
+                .rejected_by()
+                .iter()
+                .filter(|pubkey| eligible.contains(*pubkey))
+                .count();
+            let active = QuorumConfig::active_validators(&eligible, evidence.offender());
+            if rejections < QuorumConfig::default().required(active) {
+                println!(
+                    "Slashing evidence {} without a quorum of no votes",
+                    self.txn_id
+                );
+                return Err(invalid);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A downtime txn has no signature of its own, it has to be exactly the
+    /// txn built from the announcement it carries, which has to be signed with
+    /// the key of a claim on the ledger and not be over as of the last block.
+    /// How much of it is granted is settled when a block carrying it is applied.
+    pub fn valid_planned_downtime(
+        &self,
+        network_state: &NetworkState,
+    ) -> Result<(), InvalidTxnError> {
+        let invalid = InvalidTxnError {
+            details: InvalidTxnErrorReason::InvalidPlannedDowntime,
+        };
+        let downtime = self.downtime().ok_or_else(|| invalid.clone())?;
+        let mut unvoted = self.clone();
+        unvoted.validators.clear();
+        unvoted.validator_signatures.clear();
+        if unvoted.to_string()
+            != Txn::planned_downtime_on_chain(&downtime, &self.chain_id).to_string()
+        {
+            return Err(invalid);
+        }
+        if !downtime.verify()
+            || network_state
+                .get_claim_by_pubkey(&downtime.claim_pubkey)
+                .is_none()
+        {
+            return Err(invalid);
+        }
+        let last_applied = network_state.last_applied_timestamp().unwrap_or(0);
+        if downtime.until_timestamp <= std::cmp::max(downtime.announced_at, last_applied) {
+            return Err(invalid);
+        }
+
+        Ok(())
+    }
+
+    fn valid_signed_txn(
+        &self,
+        chain_id: &str,
+        network_state: &NetworkState,
+        txn_pool: Option<&Pool<String, Txn>>,
+    ) -> Result<(), InvalidTxnError> {
+        if let Err(e) = Address::parse(&self.sender_address) {
+            println!("Invalid sender address: {}", e);
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidSenderAddress,
+            });
+        }
+
+        if let Err(e) = Address::parse(&self.receiver_address) {
+            println!("Invalid receiver address: {}", e);
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidReceiverAddress,
+            });
+        }
+
+        if self.chain_id != chain_id {
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::WrongChainId,
+            });
+        }
+
+        let payload = Txn::signed_payload(
+            &self.kind,
+            &self.chain_id,
+            self.txn_timestamp,
+            &self.sender_address,
+            &self.sender_public_key,
+            &self.receiver_address,
+            self.txn_amount,
+            self.txn_fee,
+            self.nonce,
+            &self.txn_data,
+            &self.txn_token,
+        );
+        if self.txn_payload != payload {
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::PayloadMismatch,
+            });
+        }
+
+        if !self.valid_txn_signature() {
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidSignature,
+            });
+        }
+
+        if self.txn_amount == 0 {
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::ZeroAmount,
+            });
+        }
+
+        self.valid_token(network_state)?;
+
+        if !self.valid_amount(network_state, txn_pool) {
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InsufficientBalance,
+            });
+        }
+
+        if let Some(txn_pool) = txn_pool {
+            if !self.check_double_spend(txn_pool) {
+                return Err(InvalidTxnError {
+                    details: InvalidTxnErrorReason::DoubleSpend,
+                });
+            }
+        }
+
+        if !self.check_txn_nonce(network_state) {
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidNonce,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn verify_signature(&self) -> bool {
+        let message = self.txn_payload.clone();
+        let message_bytes = message.as_bytes().to_owned();
+
+        let mut buffer = ByteBuffer::new();
+        buffer.write_bytes(&message_bytes);
+        while buffer.len() < 32 {
+            buffer.write_u8(0);
+        }
+        let new_message = buffer.to_bytes();
+        let message_hash = blake3::hash(&new_message);
+        let message_hash = Message::from_slice(message_hash.as_bytes()).unwrap();
+        let (signature, pubkey) = if let (Ok(signature), Ok(pubkey)) = (
+            Signature::from_str(&self.txn_signature),
+            PublicKey::from_str(&self.sender_public_key),
+        ) {
+            (signature, pubkey)
+        } else {
+            return false;
+        };
+        let secp = Secp256k1::new();
+        let valid = secp.verify(&message_hash, &signature, &pubkey);
+
+        match valid {
+            Ok(()) => return true,
+            _ => return false,
+        }
+    }
+
+    /// A token issuance must register a new token with valid metadata, and a
+    /// token transfer must move a registered token.
+    fn valid_token(&self, network_state: &NetworkState) -> Result<(), InvalidTxnError> {
+        if self.is_token_issuance() {
+            let token = Token::from_issuance(self).ok_or(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidTokenIssuance,
+            })?;
+            if network_state.get_token(&token.token_id).is_some() {
+                println!("Token {} is already registered", token.token_id);
+                return Err(InvalidTxnError {
+                    details: InvalidTxnErrorReason::InvalidTokenIssuance,
+                });
+            }
+        } else if let Some(token_id) = &self.txn_token {
+            if network_state.get_token(token_id).is_none() {
+                println!("Unknown token {}", token_id);
+                return Err(InvalidTxnError {
+                    details: InvalidTxnErrorReason::UnknownToken,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Coinbase txns carry no sender or signature, only a valid receiver, and
+    /// pay exactly `block_reward`, the reward of the block they are in.
+    pub fn valid_coinbase_for(&self, block_reward: &Reward) -> Result<(), InvalidTxnError> {
+        if !self.sender_address.is_empty()
+            || !self.sender_public_key.is_empty()
+            || !self.txn_signature.is_empty()
+        {
+            println!("Coinbase txn must not have a sender");
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidCoinbase,
+            });
+        }
+
+        if let Err(e) = Address::parse(&self.receiver_address) {
+            println!("Invalid receiver address: {}", e);
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidReceiverAddress,
+            });
+        }
+
+        let miner = block_reward.miner.as_deref().map(Address::canonicalize);
+        if miner != Some(Address::canonicalize(&self.receiver_address))
+            || self.txn_amount != block_reward.amount
+        {
+            println!("Coinbase txn doesn't pay the block reward");
+            return Err(InvalidTxnError {
+                details: InvalidTxnErrorReason::InvalidCoinbase,
+            });
+        }
+
+        Ok(())
+    }
+
     pub fn get_field_names(&self) -> Vec<String> {
         vec![
             "txn_id".to_string(),
+            "kind".to_string(),
             "txn_timestamp".to_string(),
             "sender_address".to_string(),
             "sender_public_key".to_string(),
             "receiver_address".to_string(),
             "txn_token".to_string(),
             "txn_amount".to_string(),
+            "txn_fee".to_string(),
+            "txn_data".to_string(),
             "txn_payload".to_string(),
             "txn_signature".to_string(),
             "txn_signature".to_string(),
+            "chain_id".to_string(),
             "validators".to_string(),
+            "validator_signatures".to_string(),
             "nonce".to_string(),
         ]
     }
 }
 
+impl SignatureCache {
+    // The hash of the fields the signature check depends on.
+    fn signed_bytes_hash(txn: &Txn) -> String {
+        digest_bytes(
+            format!(
+                "{},{},{}",
+                txn.txn_payload, txn.txn_signature, txn.sender_public_key
+            )
+            .as_bytes(),
+        )
+    }
+
+    fn with<T>(f: impl FnOnce(&mut SignatureCache) -> T) -> T {
+        let mut cache = SIGNATURE_CACHE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(cache.get_or_insert_with(SignatureCache::default))
+    }
+
+    /// Whether the txn's signature was verified with the txn's current bytes,
+    /// counting the hit if it was.
+    pub fn hit(txn: &Txn) -> bool {
+        let hash = SignatureCache::signed_bytes_hash(txn);
+        SignatureCache::with(|cache| match cache.verified.get_mut(&txn.txn_id) {
+            Some((verified_hash, hits)) if *verified_hash == hash => {
+                *hits += 1;
+                true
+            }
+            _ => false,
+        })
+    }
+
+    pub fn insert(txn: &Txn) {
+        let hash = SignatureCache::signed_bytes_hash(txn);
+        SignatureCache::with(|cache| {
+            if cache.verified.len() >= MAX_CACHED_SIGNATURES {
+                cache.verified.clear();
+            }
+            cache.verified.insert(txn.txn_id.clone(), (hash, 0));
+        })
+    }
+
+    /// How many times the txn's signature was found in the cache since it was
+    /// last verified.
+    pub fn hits(txn_id: &str) -> usize {
+        SignatureCache::with(|cache| {
+            cache
+                .verified
+                .get(txn_id)
+                .map(|(_, hits)| *hits)
+                .unwrap_or(0)
+        })
+    }
+}
+
+/// The cost of including a txn in a block: a base cost, a cost per byte of its
+/// signed payload and a cost per output. Every txn pays a single receiver for
+/// now, so has one output.
+pub fn txn_cost(txn: &Txn) -> u64 {
+    let n_outputs: u64 = 1;
+    BASE_TXN_COST
+        .saturating_add(TXN_COST_PER_BYTE.saturating_mul(txn.txn_payload.len() as u64))
+        .saturating_add(TXN_COST_PER_OUTPUT.saturating_mul(n_outputs))
+}
+
 impl Verifiable for Txn {
     fn verifiable(&self) -> bool {
         true
     }
 
-    fn valid_txn(&self, network_state: &NetworkState, txn_pool: &Pool<String, Txn>) -> bool {
-        if !self.valid_txn_signature() {
-            return false;
+    /// Validates a txn without a miner. Balances are checked against the
+    /// confirmed ledger, less the sender's pending txns when a pool is given.
+    fn valid_txn(
+        &self,
+        network_state: &NetworkState,
+        txn_pool: Option<&Pool<String, Txn>>,
+    ) -> Result<(), InvalidTxnError> {
+        self.valid_txn_on_chain(NETWORK_ID, network_state, txn_pool)
+    }
+
+    /// Verifies the signature over the payload, or finds it in the signature
+    /// cache if the txn was verified before.
+    fn valid_txn_signature(&self) -> bool {
+        if SignatureCache::hit(self) {
+            return true;
         }
 
-        if !self.valid_amount(network_state, txn_pool) {
+        let valid = self.verify_signature();
+        if valid {
+            SignatureCache::insert(self);
+        }
+        valid
+    }
+
+    fn valid_txn_addresses(&self) -> bool {
+        if let Err(e) = Address::parse(&self.sender_address) {
+            println!("Invalid sender address: {}", e);
             return false;
         }
 
-        if !self.check_double_spend(txn_pool) {
+        if let Err(e) = Address::parse(&self.receiver_address) {
+            println!("Invalid receiver address: {}", e);
             return false;
         }
 
         true
     }
 
-    fn valid_txn_signature(&self) -> bool {
-        let message = self.txn_payload.clone();
-        let message_bytes = message.as_bytes().to_owned();
-
-        let mut buffer = ByteBuffer::new();
-        buffer.write_bytes(&message_bytes);
-        while buffer.len() < 32 {
-            buffer.write_u8(0);
-        }
-        let new_message = buffer.to_bytes();
-        let message_hash = blake3::hash(&new_message);
-        let message_hash = Message::from_slice(message_hash.as_bytes()).unwrap();
-        let secp = Secp256k1::new();
-        let valid = secp.verify(
-            &message_hash,
-            &Signature::from_str(&self.txn_signature).unwrap(),
-            &PublicKey::from_str(&self.sender_public_key).unwrap(),
-        );
-
-        match valid {
-            Ok(()) => return true,
-            _ => return false,
-        }
-    }
+    /// Native txns need the amount in native balance. Token txns need the fee
+    /// in native balance and, unless they issue the token, the amount in the
+    /// token's balance.
+    fn valid_amount(
+        &self,
+        network_state: &NetworkState,
+        txn_pool: Option<&Pool<String, Txn>>,
+    ) -> bool {
+        // A txn being revalidated is already in the pool, don't count it twice.
+        let own_txn = txn_pool
+            .and_then(|txn_pool| txn_pool.pending.get(&self.txn_id))
+            .filter(|txn| txn.sender_address == self.sender_address);
 
-    fn valid_amount(&self, network_state: &NetworkState, txn_pool: &Pool<String, Txn>) -> bool {
-        let (_, pending_debits) = if let Some((credit_amount, debit_amount)) =
-            network_state.pending_balance(self.sender_address.clone(), txn_pool)
-        {
-            (credit_amount, debit_amount)
+        let pending_debits = if let Some(txn_pool) = txn_pool {
+            let (_, debit_amount) = network_state
+                .pending_balance(self.sender_address.clone(), txn_pool)
+                .unwrap_or((0, 0));
+            let own_amount = own_txn.map(|txn| txn.native_cost()).unwrap_or(0);
+            debit_amount.saturating_sub(own_amount)
         } else {
-            (0, 0)
+            0
         };
 
         let mut address_balance = network_state.get_balance(&self.sender_address);
@@ -171,10 +1119,37 @@ impl Verifiable for Txn {
             return false;
         };
 
-        if address_balance < self.txn_amount {
+        if address_balance < self.native_cost() {
             println!("Invalid balance, not enough coins");
             return false;
         }
+
+        if let Some(token_id) = &self.txn_token {
+            if self.is_token_issuance() {
+                return true;
+            }
+
+            let pending_token_debits = if let Some(txn_pool) = txn_pool {
+                let own_amount = own_txn
+                    .filter(|txn| txn.txn_token.as_ref() == Some(token_id))
+                    .map(|txn| txn.txn_amount)
+                    .unwrap_or(0);
+                network_state
+                    .pending_token_debits(&self.sender_address, token_id, txn_pool)
+                    .saturating_sub(own_amount)
+            } else {
+                0
+            };
+
+            let token_balance = network_state
+                .get_token_balance(&self.sender_address, token_id)
+                .saturating_sub(pending_token_debits);
+            if token_balance < self.txn_amount {
+                println!("Invalid balance, not enough {}", token_id);
+                return false;
+            }
+        }
+
         true
     }
 
@@ -198,27 +1173,548 @@ impl Verifiable for Txn {
     }
 }
 
+impl InvalidTxnErrorReason {
+    pub fn to_str(&self) -> &str {
+        match self {
+            Self::InvalidCoinbase => "invalid coinbase txn",
+            Self::InvalidSenderAddress => "invalid sender address",
+            Self::InvalidReceiverAddress => "invalid receiver address",
+            Self::PayloadMismatch => "payload doesn't match txn",
+            Self::WrongChainId => "txn signed for another chain",
+            Self::InvalidSignature => "invalid signature",
+            Self::ZeroAmount => "txn amount is zero",
+            Self::InsufficientBalance => "insufficient balance",
+            Self::DoubleSpend => "attempted double spend",
+            Self::InvalidNonce => "invalid txn nonce",
+            Self::UnknownToken => "unknown token",
+            Self::InvalidTokenIssuance => "invalid token issuance",
+            Self::InvalidClaimTransfer => "invalid claim transfer",
+            Self::InvalidSlashingEvidence => "invalid slashing evidence",
+            Self::InvalidPlannedDowntime => "invalid planned downtime",
+            Self::General => "general invalid txn",
+        }
+    }
+}
+
+impl fmt::Display for InvalidTxnErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_str())
+    }
+}
+
+impl fmt::Display for InvalidTxnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for InvalidTxnError {
+    fn description(&self) -> &str {
+        &self.details.to_str()
+    }
+}
+
 impl fmt::Display for Txn {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
             "Txn(\n \
             txn_id: {},\n \
+            kind: {:?},\n \
             txn_timestamp: {},\n \
             sender_address: {},\n \
             sender_public_key: {},\n \
             receiver_address: {},\n \
             txn_token: {:?},\n \
             txn_amount: {},\n \
+            txn_fee: {},\n \
             txn_signature: {}",
             self.txn_id,
+            self.kind,
             self.txn_timestamp.to_string(),
             self.sender_address,
             self.sender_public_key,
             self.receiver_address,
             self.txn_token,
             self.txn_amount,
+            self.txn_fee,
             self.txn_signature,
         )
     }
 }
+
+#[cfg(test)]
+impl Txn {
+    /// An unsigned transfer between two valid addresses, for tests that pass
+    /// txns around without validating them.
+    pub(crate) fn unsigned_transfer(amount: u128, nonce: u128) -> Txn {
+        let network = crate::wallet::AddressNetwork::default();
+        let sender = Address::from_pubkey(network, "sender", 1).to_string();
+        let receiver = Address::from_pubkey(network, "receiver", 1).to_string();
+        let timestamp = SystemClock.now();
+        let payload = Txn::signed_payload(
+            &TxnKind::Transfer,
+            NETWORK_ID,
+            timestamp,
+            &sender,
+            "",
+            &receiver,
+            amount,
+            0,
+            nonce,
+            &None,
+            &None,
+        );
+        let uid_payload = format!("{},{}", &payload, Uuid::new_v4().to_string());
+
+        Txn {
+            txn_id: digest_bytes(uid_payload.as_bytes()),
+            kind: TxnKind::Transfer,
+            txn_timestamp: timestamp,
+            sender_address: sender,
+            sender_public_key: String::new(),
+            receiver_address: receiver,
+            txn_token: None,
+            txn_amount: amount,
+            txn_fee: 0,
+            txn_data: None,
+            txn_payload: payload,
+            txn_signature: String::new(),
+            chain_id: NETWORK_ID.to_string(),
+            validators: HashMap::new(),
+            validator_signatures: HashMap::new(),
+            nonce,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::pool::PoolKind;
+    use crate::reward::{Category, RewardState};
+    use crate::state::Ledger;
+    use crate::token::TokenLedger;
+    use crate::validator::TxnValidator;
+    use crate::wallet::AddressNetwork;
+    use ritelinked::LinkedHashMap;
+    use std::env;
+
+    fn test_address(seed: &str) -> String {
+        Address::from_pubkey(AddressNetwork::default(), seed, 1).to_string()
+    }
+
+    fn test_state() -> NetworkState {
+        let path = env::temp_dir().join("vrrb_test_txn_kind.db");
+        NetworkState::restore(path.to_str().unwrap())
+    }
+
+    // A ledger crediting `balance` to the first address of a new wallet.
+    fn funded_state(
+        name: &str,
+        balance: u128,
+    ) -> (NetworkState, Arc<Mutex<WalletAccount>>, String) {
+        let path = env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let mut wallet = WalletAccount::new();
+        let address = wallet.get_address(1);
+        let mut credits = LinkedHashMap::new();
+        credits.insert(address.clone(), balance);
+        let mut network_state = NetworkState::restore(path.to_str().unwrap());
+        network_state.update_ledger(
+            Ledger {
+                credits,
+                debits: LinkedHashMap::new(),
+                claims: LinkedHashMap::new(),
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+        (network_state, Arc::new(Mutex::new(wallet)), address)
+    }
+
+    fn transfer(
+        wallet: &Arc<Mutex<WalletAccount>>,
+        sender_address: &str,
+        amount: u128,
+        nonce: u128,
+    ) -> Txn {
+        Txn::new(
+            wallet.clone(),
+            sender_address.to_string(),
+            test_address("receiver"),
+            amount,
+            1,
+            nonce,
+        )
+    }
+
+    fn invalid_reason(
+        txn: &Txn,
+        network_state: &NetworkState,
+        txn_pool: Option<&Pool<String, Txn>>,
+    ) -> InvalidTxnErrorReason {
+        txn.valid_txn(network_state, txn_pool).unwrap_err().details
+    }
+
+    fn block_reward(miner: &str, amount: u128) -> Reward {
+        Reward {
+            miner: Some(miner.to_string()),
+            category: Category::Flake(Some(amount)),
+            amount,
+        }
+    }
+
+    #[test]
+    fn coinbase_outside_a_block_is_rejected() {
+        let txn = Txn::coinbase(test_address("miner"), 100, 0);
+        let txn_pool = Pool::new(PoolKind::Txn);
+        assert_eq!(
+            txn.valid_txn(&test_state(), Some(&txn_pool))
+                .unwrap_err()
+                .details,
+            InvalidTxnErrorReason::InvalidCoinbase
+        );
+        let validator = TxnValidator::new("pubkey".to_string(), txn, &test_state(), &txn_pool);
+        assert!(!validator.vote);
+    }
+
+    #[test]
+    fn coinbase_has_to_pay_the_block_reward() {
+        let miner = test_address("miner");
+        let txn = Txn::coinbase(miner.clone(), 100, 0);
+        assert!(txn.valid_coinbase_for(&block_reward(&miner, 100)).is_ok());
+        assert!(txn.valid_coinbase_for(&block_reward(&miner, 99)).is_err());
+        assert!(txn
+            .valid_coinbase_for(&block_reward(&test_address("other"), 100))
+            .is_err());
+    }
+
+    #[test]
+    fn txns_are_timestamped_by_the_given_clock() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_clock.db", 100);
+        let clock = MockClock::new(42);
+        let txn = Txn::new_with_clock(
+            wallet,
+            address,
+            test_address("receiver"),
+            None,
+            10,
+            1,
+            0,
+            None,
+            &clock,
+        );
+        assert_eq!(txn.txn_timestamp, 42);
+        assert!(txn.valid_txn(&network_state, None).is_ok());
+
+        clock.advance(8);
+        let coinbase = Txn::coinbase_with_clock(test_address("miner"), 100, 0, &clock);
+        assert_eq!(coinbase.txn_timestamp, 50);
+    }
+
+    #[test]
+    fn transfer_without_signature_is_invalid() {
+        let txn = Txn::unsigned_transfer(100, 0);
+        let txn_pool = Pool::new(PoolKind::Txn);
+        assert!(txn.valid_txn(&test_state(), Some(&txn_pool)).is_err());
+    }
+
+    #[test]
+    fn coinbase_with_sender_is_invalid() {
+        let miner = test_address("miner");
+        let mut txn = Txn::coinbase(miner.clone(), 100, 0);
+        txn.sender_address = test_address("sender");
+        assert_eq!(
+            txn.valid_coinbase_for(&block_reward(&miner, 100))
+                .unwrap_err()
+                .details,
+            InvalidTxnErrorReason::InvalidCoinbase
+        );
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_addresses.db", 100);
+        let txn = Txn::new(
+            wallet.clone(),
+            "sender".to_string(),
+            test_address("receiver"),
+            10,
+            1,
+            0,
+        );
+        assert_eq!(
+            invalid_reason(&txn, &network_state, None),
+            InvalidTxnErrorReason::InvalidSenderAddress
+        );
+
+        let txn = Txn::new(wallet, address, "receiver".to_string(), 10, 1, 0);
+        assert_eq!(
+            invalid_reason(&txn, &network_state, None),
+            InvalidTxnErrorReason::InvalidReceiverAddress
+        );
+    }
+
+    #[test]
+    fn altered_fields_and_signatures_are_rejected() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_signature.db", 100);
+        let txn = transfer(&wallet, &address, 10, 0);
+        assert!(txn.valid_txn(&network_state, None).is_ok());
+
+        let mut altered = txn.clone();
+        altered.txn_amount = 20;
+        assert_eq!(
+            invalid_reason(&altered, &network_state, None),
+            InvalidTxnErrorReason::PayloadMismatch
+        );
+
+        // The kind is signed over, so a transfer can't be relabelled.
+        let mut relabelled = txn.clone();
+        relabelled.kind = TxnKind::ClaimReinstatement;
+        assert_eq!(
+            invalid_reason(&relabelled, &network_state, None),
+            InvalidTxnErrorReason::PayloadMismatch
+        );
+
+        let mut forged = txn;
+        forged.txn_signature = WalletAccount::new()
+            .sign(&forged.txn_payload)
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            invalid_reason(&forged, &network_state, None),
+            InvalidTxnErrorReason::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn txn_signed_for_another_chain_is_rejected() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_chain_id.db", 100);
+        let txn_pool = Pool::new(PoolKind::Txn);
+        let txn = transfer(&wallet, &address, 10, 0);
+        assert_eq!(txn.chain_id, NETWORK_ID);
+
+        let chain_a = TxnValidator::for_chain(
+            NETWORK_ID,
+            "validator".to_string(),
+            txn.clone(),
+            &network_state,
+            &txn_pool,
+        );
+        assert!(chain_a.vote);
+        let chain_b = TxnValidator::for_chain(
+            "other-net",
+            "validator".to_string(),
+            txn.clone(),
+            &network_state,
+            &txn_pool,
+        );
+        assert!(!chain_b.vote);
+        assert_eq!(
+            txn.valid_txn_on_chain("other-net", &network_state, None)
+                .unwrap_err()
+                .details,
+            InvalidTxnErrorReason::WrongChainId
+        );
+
+        // The chain id is signed over, so it can't be changed to replay the txn.
+        let mut replayed = txn;
+        replayed.chain_id = "other-net".to_string();
+        assert_eq!(
+            replayed
+                .valid_txn_on_chain("other-net", &network_state, None)
+                .unwrap_err()
+                .details,
+            InvalidTxnErrorReason::PayloadMismatch
+        );
+    }
+
+    #[test]
+    fn signed_payload_names_the_kind_by_its_stable_name() {
+        let payload = |kind: &TxnKind| {
+            Txn::signed_payload(
+                kind, NETWORK_ID, 1, "sender", "pubkey", "receiver", 10, 1, 0, &None, &None,
+            )
+        };
+        let transfer = payload(&TxnKind::Transfer);
+        assert!(!transfer.starts_with("kind:"));
+
+        [
+            (TxnKind::Coinbase, "Coinbase"),
+            (TxnKind::ClaimStake, "ClaimStake"),
+            (TxnKind::ClaimTransfer, "ClaimTransfer"),
+            (TxnKind::ClaimReinstatement, "ClaimReinstatement"),
+            (TxnKind::TokenIssuance, "TokenIssuance"),
+            (TxnKind::SlashingEvidence, "SlashingEvidence"),
+            (TxnKind::PlannedDowntime, "PlannedDowntime"),
+        ]
+        .iter()
+        .for_each(|(kind, name)| {
+            assert_eq!(kind.as_str(), *name);
+            assert_eq!(payload(kind), format!("kind:{},{}", name, transfer));
+        });
+    }
+
+    #[test]
+    fn second_signature_check_hits_the_cache() {
+        let (network_state, wallet, address) =
+            funded_state("vrrb_test_txn_signature_cache.db", 100);
+        let txn = transfer(&wallet, &address, 10, 0);
+        let txn_pool = Pool::new(PoolKind::Txn);
+        let validator = TxnValidator::new(
+            "validator".to_string(),
+            txn.clone(),
+            &network_state,
+            &txn_pool,
+        );
+        assert!(validator.vote);
+        assert_eq!(SignatureCache::hits(&txn.txn_id), 0);
+
+        assert!(txn.valid_txn_signature());
+        assert_eq!(SignatureCache::hits(&txn.txn_id), 1);
+
+        // A txn with the same id but other bytes is verified again.
+        let mut forged = txn.clone();
+        forged.txn_signature = WalletAccount::new()
+            .sign(&forged.txn_payload)
+            .unwrap()
+            .to_string();
+        assert!(!forged.valid_txn_signature());
+        assert_eq!(SignatureCache::hits(&txn.txn_id), 1);
+    }
+
+    #[test]
+    fn zero_amount_is_rejected() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_zero.db", 100);
+        let txn = transfer(&wallet, &address, 0, 0);
+        assert_eq!(
+            invalid_reason(&txn, &network_state, None),
+            InvalidTxnErrorReason::ZeroAmount
+        );
+    }
+
+    #[test]
+    fn pending_txns_count_against_the_balance() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_balance.db", 100);
+        let too_much = transfer(&wallet, &address, 101, 0);
+        assert_eq!(
+            invalid_reason(&too_much, &network_state, None),
+            InvalidTxnErrorReason::InsufficientBalance
+        );
+
+        let pending = transfer(&wallet, &address, 80, 0);
+        let mut txn_pool = Pool::new(PoolKind::Txn);
+        txn_pool
+            .pending
+            .insert(pending.txn_id.clone(), pending.clone());
+        // Revalidating a pending txn doesn't count it against itself.
+        assert!(pending.valid_txn(&network_state, Some(&txn_pool)).is_ok());
+
+        let txn = transfer(&wallet, &address, 50, 1);
+        assert!(txn.valid_txn(&network_state, None).is_ok());
+        assert_eq!(
+            invalid_reason(&txn, &network_state, Some(&txn_pool)),
+            InvalidTxnErrorReason::InsufficientBalance
+        );
+    }
+
+    #[test]
+    fn double_spend_is_rejected() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_double_spend.db", 100);
+        let txn = transfer(&wallet, &address, 50, 0);
+        let mut pending = txn.clone();
+        pending.txn_amount = 10;
+        let mut txn_pool = Pool::new(PoolKind::Txn);
+        txn_pool.pending.insert(pending.txn_id.clone(), pending);
+        assert_eq!(
+            invalid_reason(&txn, &network_state, Some(&txn_pool)),
+            InvalidTxnErrorReason::DoubleSpend
+        );
+    }
+
+    #[test]
+    fn validator_votes_match_validation() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_votes.db", 100);
+        let valid = transfer(&wallet, &address, 10, 0);
+        let mut altered = valid.clone();
+        altered.receiver_address = test_address("thief");
+        let corpus = vec![
+            (valid, true),
+            (Txn::coinbase(test_address("miner"), 100, 0), false),
+            (altered, false),
+            (transfer(&wallet, &address, 0, 1), false),
+            (transfer(&wallet, &address, 1000, 2), false),
+        ];
+
+        let txn_pool = Pool::new(PoolKind::Txn);
+        corpus.into_iter().for_each(|(txn, expected)| {
+            let validator =
+                TxnValidator::new("pubkey".to_string(), txn.clone(), &network_state, &txn_pool);
+            assert_eq!(validator.vote, expected, "{}", txn);
+            assert_eq!(
+                txn.valid_txn(&network_state, Some(&txn_pool)).is_ok(),
+                expected
+            );
+        });
+    }
+
+    #[test]
+    fn txn_cost_survives_serialization() {
+        let (_, wallet, address) = funded_state("vrrb_test_txn_cost.db", 100);
+        let txn = transfer(&wallet, &address, 10, 0);
+        let with_data = Txn::new_with_data(
+            wallet.clone(),
+            address.clone(),
+            test_address("receiver"),
+            10,
+            1,
+            1,
+            Some("data".repeat(100)),
+        );
+
+        assert_eq!(txn_cost(&Txn::from_bytes(&txn.as_bytes())), txn_cost(&txn));
+        assert_eq!(
+            txn_cost(&Txn::from_string(&with_data.to_string())),
+            txn_cost(&with_data)
+        );
+        assert!(txn_cost(&txn) > BASE_TXN_COST);
+        assert!(txn_cost(&with_data) >= txn_cost(&txn) + 400 * TXN_COST_PER_BYTE);
+    }
+
+    #[test]
+    fn token_transfers_need_a_registered_token() {
+        let (network_state, wallet, address) = funded_state("vrrb_test_txn_token.db", 100);
+        let txn = Txn::new_with_token(
+            wallet.clone(),
+            address.clone(),
+            test_address("receiver"),
+            Some("TKN".to_string()),
+            10,
+            1,
+            0,
+            None,
+        );
+        assert_eq!(
+            invalid_reason(&txn, &network_state, None),
+            InvalidTxnErrorReason::UnknownToken
+        );
+
+        // The token is covered by the signature.
+        let mut altered = txn;
+        altered.txn_token = Some("OTHER".to_string());
+        assert_eq!(
+            invalid_reason(&altered, &network_state, None),
+            InvalidTxnErrorReason::PayloadMismatch
+        );
+
+        let metadata = TokenMetadata {
+            name: "Token".to_string(),
+            decimals: 2,
+        };
+        let issuance =
+            Txn::token_issuance(wallet, address, "TKN".to_string(), &metadata, 1000, 1, 1);
+        assert!(issuance.valid_txn(&network_state, None).is_ok());
+    }
+}
@@ -1,9 +1,24 @@
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
 
+/// A numeric conversion that would have wrapped or truncated the value.
+#[derive(Debug)]
+pub struct ConversionError(String);
+
+/// The fraction of a reward category's remaining supply emitted in an epoch,
+/// log10(initial) / epochs. The rate is 0 when there is nothing to decay or
+/// no epochs to decay it over, and is clamped to 1 so an epoch never emits
+/// more than what remains.
 pub fn decay_calculator(initial: u128, epochs: u128) -> f64 {
+    if initial <= 1 || epochs == 0 {
+        return 0.0;
+    }
+
     let b: f64 = 1.0f64 / initial as f64;
     let ln_b = b.log10();
-    (ln_b / epochs as f64) * -1.0
+    ((ln_b / epochs as f64) * -1.0).min(1.0)
 }
 
 pub fn restore_db(path: &str) -> PickleDb {
@@ -15,4 +30,139 @@ pub fn restore_db(path: &str) -> PickleDb {
             SerializationMethod::Bin,
         )};
     db
-}
\ No newline at end of file
+}
+
+/// Removes every `flag <value>` pair from the arguments and returns the values
+/// in the order given. A flag given last without a value is removed as well
+/// and yields None.
+pub fn take_flags(args: &mut Vec<String>, flag: &str) -> Vec<Option<String>> {
+    let mut values = vec![];
+    let mut remaining = vec![];
+    let mut iter = std::mem::take(args).into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == flag {
+            values.push(iter.next());
+        } else {
+            remaining.push(arg);
+        }
+    }
+    *args = remaining;
+
+    values
+}
+
+/// Removes `flag <value>` from the arguments and returns the value, the last
+/// one if the flag is repeated.
+pub fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    take_flags(args, flag).pop().flatten()
+}
+
+/// Removes a flag that takes no value from the arguments and returns whether
+/// it was given.
+pub fn take_switch(args: &mut Vec<String>, flag: &str) -> bool {
+    let n_args = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != n_args
+}
+
+fn convert<T, U>(n: T, target: &str) -> Result<U, ConversionError>
+where
+    T: Copy + fmt::Display,
+    U: TryFrom<T>,
+{
+    U::try_from(n).map_err(|_| ConversionError(format!("{} is out of range for {}", n, target)))
+}
+
+pub fn to_u16<T>(n: T) -> Result<u16, ConversionError>
+where
+    T: Copy + fmt::Display,
+    u16: TryFrom<T>,
+{
+    convert(n, "u16")
+}
+
+pub fn to_u32<T>(n: T) -> Result<u32, ConversionError>
+where
+    T: Copy + fmt::Display,
+    u32: TryFrom<T>,
+{
+    convert(n, "u32")
+}
+
+pub fn to_u128<T>(n: T) -> Result<u128, ConversionError>
+where
+    T: Copy + fmt::Display,
+    u128: TryFrom<T>,
+{
+    convert(n, "u128")
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ConversionError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay_rate_for_known_inputs() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(decay_calculator(10, 1), 1.0));
+        assert!(close(decay_calculator(100, 4), 0.5));
+        assert!(close(decay_calculator(80000000, 300), 0.026343633289973144));
+        assert!(close(decay_calculator(1400000, 200), 0.03073064017839119));
+        assert!(close(decay_calculator(20000, 100), 0.04301029995663981));
+        assert!(close(decay_calculator(u128::MAX, 300), 0.128439464816632));
+    }
+
+    #[test]
+    fn decay_rate_is_bounded() {
+        assert_eq!(decay_calculator(0, 300), 0.0);
+        assert_eq!(decay_calculator(1, 300), 0.0);
+        assert_eq!(decay_calculator(80000000, 0), 0.0);
+        assert_eq!(decay_calculator(u128::MAX, 1), 1.0);
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_are_taken_out_of_the_arguments() {
+        let mut remaining = args(&["vrrb", "--a", "1", "peer", "--a", "2", "--b", "--c"]);
+        assert_eq!(take_flag(&mut remaining, "--a"), Some("2".to_string()));
+        assert!(take_switch(&mut remaining, "--b"));
+        assert!(!take_switch(&mut remaining, "--b"));
+        assert_eq!(take_flags(&mut remaining, "--c"), vec![None]);
+        assert_eq!(take_flag(&mut remaining, "--d"), None);
+        assert_eq!(remaining, args(&["vrrb", "peer"]));
+
+        // A value is taken even if it looks like a flag.
+        let mut remaining = args(&["vrrb", "--a", "--b"]);
+        assert_eq!(take_flag(&mut remaining, "--a"), Some("--b".to_string()));
+        assert_eq!(remaining, args(&["vrrb"]));
+    }
+
+    #[test]
+    fn out_of_range_conversions_error() {
+        assert_eq!(to_u16(19291i32).unwrap(), 19291);
+        assert!(to_u16(65536i32).is_err());
+        assert!(to_u16(-1i32).is_err());
+        assert!(to_u32(u64::MAX).is_err());
+        assert_eq!(to_u128(u64::MAX).unwrap(), u64::MAX as u128);
+        assert!(to_u128(-1i64).is_err());
+        assert_eq!(
+            to_u16(70000u32).unwrap_err().to_string(),
+            "70000 is out of range for u16"
+        );
+    }
+}
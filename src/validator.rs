@@ -1,19 +1,27 @@
 #![allow(unused_imports)]
+use crate::network::topics::NETWORK_ID;
 use crate::pool::Pool;
 use crate::verifiable::Verifiable;
 use crate::{
     account::AccountState, block::Block, claim::Claim, reward::RewardState, state::NetworkState,
     txn::Txn, wallet::WalletAccount,
 };
+use bytebuffer::ByteBuffer;
+use secp256k1::key::SecretKey;
+use secp256k1::{Message, Secp256k1};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TxnValidator {
     pub pubkey: String,
     pub vote: bool,
     pub txn: Txn,
+    // the validator's signature over the txn's vote payload
+    #[serde(default)]
+    pub signature: String,
 }
 
 impl TxnValidator {
@@ -22,11 +30,57 @@ impl TxnValidator {
         txn: Txn,
         network_state: &NetworkState,
         txn_pool: &Pool<String, Txn>,
+    ) -> TxnValidator {
+        TxnValidator::for_chain(NETWORK_ID, pubkey, txn, network_state, txn_pool)
+    }
+
+    /// Votes on the txn as a validator on the chain `chain_id`, voting against
+    /// txns signed for any other chain.
+    pub fn for_chain(
+        chain_id: &str,
+        pubkey: String,
+        txn: Txn,
+        network_state: &NetworkState,
+        txn_pool: &Pool<String, Txn>,
     ) -> TxnValidator {
         TxnValidator {
             pubkey,
-            vote: txn.clone().valid_txn(network_state, txn_pool),
+            vote: txn
+                .valid_txn_on_chain(chain_id, network_state, Some(txn_pool))
+                .is_ok(),
             txn,
+            signature: String::new(),
+        }
+    }
+
+    /// Signs the vote with the validator's secret key, peers drop votes whose
+    /// signature doesn't verify against the pubkey.
+    pub fn signed(mut self, secret_key: &str) -> TxnValidator {
+        self.signature = TxnValidator::sign_vote(&self.txn, self.vote, secret_key);
+        self
+    }
+
+    pub fn valid_signature(&self) -> bool {
+        self.txn
+            .verify_vote(&self.pubkey, self.vote, &self.signature)
+    }
+
+    /// The signature over the txn's vote payload, empty if the secret key
+    /// doesn't parse.
+    pub fn sign_vote(txn: &Txn, vote: bool, secret_key: &str) -> String {
+        let sk = if let Ok(sk) = SecretKey::from_str(secret_key) {
+            sk
+        } else {
+            return String::new();
+        };
+        let mut buffer = ByteBuffer::new();
+        buffer.write_bytes(txn.vote_payload(vote).as_bytes());
+        while buffer.len() < 32 {
+            buffer.write_u8(0);
         }
+        let message_hash = blake3::hash(&buffer.to_bytes());
+        let message_hash = Message::from_slice(message_hash.as_bytes()).unwrap();
+        let secp = Secp256k1::new();
+        secp.sign(&message_hash, &sk).to_string()
     }
 }
@@ -1,9 +1,10 @@
 use crate::block::Block;
 use crate::blockchain::{InvalidBlockError, InvalidBlockErrorReason};
+use crate::params::ProtocolParams;
 use crate::pool::Pool;
 use crate::reward::RewardState;
 use crate::state::NetworkState;
-use crate::txn::Txn;
+use crate::txn::{InvalidTxnError, InvalidTxnErrorReason, Txn};
 
 pub trait Verifiable {
     fn verifiable(&self) -> bool;
@@ -23,6 +24,10 @@ pub trait Verifiable {
         false
     }
 
+    fn valid_block_height(&self, _last_block: &Block) -> bool {
+        false
+    }
+
     fn valid_last_hash(&self, _last_block: &Block) -> bool {
         false
     }
@@ -31,15 +36,23 @@ pub trait Verifiable {
         false
     }
 
+    fn valid_ledger_root(&self, _network_state: &NetworkState) -> bool {
+        false
+    }
+
     fn valid_block_reward(&self, _reward_state: &RewardState) -> bool {
         false
     }
 
+    fn valid_genesis_reward(&self) -> bool {
+        false
+    }
+
     fn valid_next_block_reward(&self, _reward_state: &RewardState) -> bool {
         false
     }
 
-    fn valid_txns(&self) -> bool {
+    fn valid_txns(&self, _network_state: &NetworkState, _params: &ProtocolParams) -> bool {
         false
     }
 
@@ -47,6 +60,18 @@ pub trait Verifiable {
         false
     }
 
+    fn valid_nonce_epoch(&self, _network_state: &NetworkState) -> bool {
+        false
+    }
+
+    fn valid_claim_map_hash(&self, _network_state: &NetworkState) -> bool {
+        false
+    }
+
+    fn valid_block_cost(&self, _params: &ProtocolParams) -> bool {
+        false
+    }
+
     fn valid_claim_pointer(&self, _network_state: &NetworkState) -> bool {
         false
     }
@@ -59,15 +84,29 @@ pub trait Verifiable {
         false
     }
 
-    fn valid_txn(&self, _network_state: &NetworkState, _txn_pool: &Pool<String, Txn>) -> bool {
-        false
+    fn valid_txn(
+        &self,
+        _network_state: &NetworkState,
+        _txn_pool: Option<&Pool<String, Txn>>,
+    ) -> Result<(), InvalidTxnError> {
+        Err(InvalidTxnError {
+            details: InvalidTxnErrorReason::General,
+        })
     }
 
     fn valid_txn_signature(&self) -> bool {
         false
     }
 
-    fn valid_amount(&self, _network_state: &NetworkState, _txn_pool: &Pool<String, Txn>) -> bool {
+    fn valid_txn_addresses(&self) -> bool {
+        false
+    }
+
+    fn valid_amount(
+        &self,
+        _network_state: &NetworkState,
+        _txn_pool: Option<&Pool<String, Txn>>,
+    ) -> bool {
         false
     }
 
@@ -0,0 +1,119 @@
+use crate::block::Block;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum WalEntry {
+    // Written before a block is applied to the ledger db. The block is kept
+    // so it can be re-applied without the chain db.
+    Begin {
+        height: u128,
+        hash: String,
+        block: Block,
+    },
+    // Written once the ledger db with the block applied has been dumped.
+    Commit {
+        hash: String,
+    },
+}
+
+/// A write-ahead log of the blocks applied to a ledger db, kept next to it.
+/// Each block is logged before it's applied and marked committed once the db
+/// has been dumped, so blocks whose dump was interrupted can be found and
+/// re-applied on restart. Entries are one JSON object per line, a line torn
+/// by a crash is ignored.
+#[derive(Debug, Clone)]
+pub struct BlockWal {
+    pub path: String,
+}
+
+impl BlockWal {
+    pub fn for_db(db_path: &str) -> BlockWal {
+        BlockWal {
+            path: format!("{}.wal", db_path),
+        }
+    }
+
+    /// Logs that `block` is about to be applied, synced to disk before
+    /// returning.
+    pub fn begin(&self, block: &Block) -> Result<(), Box<dyn Error>> {
+        self.append(&WalEntry::Begin {
+            height: block.header.block_height,
+            hash: block.hash.clone(),
+            block: block.clone(),
+        })
+    }
+
+    /// Marks the block as persisted and drops the committed entries, the log
+    /// is removed once nothing in it is left uncommitted.
+    pub fn commit(&self, hash: &str) -> Result<(), Box<dyn Error>> {
+        self.append(&WalEntry::Commit {
+            hash: hash.to_string(),
+        })?;
+        self.truncate_committed()
+    }
+
+    /// The blocks logged without being committed, in the order they were
+    /// logged.
+    pub fn uncommitted(&self) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![];
+        self.entries().into_iter().for_each(|entry| match entry {
+            WalEntry::Begin { block, .. } => blocks.push(block),
+            WalEntry::Commit { hash } => blocks.retain(|block| block.hash != hash),
+        });
+        blocks
+    }
+
+    fn truncate_committed(&self) -> Result<(), Box<dyn Error>> {
+        let uncommitted = self.uncommitted();
+        if uncommitted.is_empty() {
+            if let Err(e) = fs::remove_file(&self.path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(Box::new(e));
+                }
+            }
+            return Ok(());
+        }
+
+        let mut data = vec![];
+        for block in uncommitted.iter() {
+            data.extend(serde_json::to_vec(&WalEntry::Begin {
+                height: block.header.block_height,
+                hash: block.hash.clone(),
+                block: block.clone(),
+            })?);
+            data.push(b'\n');
+        }
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn append(&self, entry: &WalEntry) -> Result<(), Box<dyn Error>> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(&line)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn entries(&self) -> Vec<WalEntry> {
+        match File::open(&self.path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .filter_map(|line| line.ok())
+                .filter_map(|line| serde_json::from_str::<WalEntry>(&line).ok())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+}
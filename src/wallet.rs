@@ -1,7 +1,12 @@
-use crate::block::Block;
-use crate::claim::Claim;
+use crate::block::{Block, SECOND};
+use crate::claim::{Claim, ClaimTransfer};
+use crate::network::topics::NETWORK_ID;
+use crate::params::MAINNET_ID;
 use crate::state::NetworkState;
-use crate::txn::Txn;
+use crate::token::TokenMetadata;
+use crate::txn::{Txn, MIN_TXN_FEE};
+use crate::utils::{take_flag, take_switch};
+use crate::validator::TxnValidator;
 use bytebuffer::ByteBuffer;
 use ritelinked::LinkedHashMap;
 use secp256k1::Error;
@@ -12,12 +17,286 @@ use secp256k1::{
 use secp256k1::{Message, Secp256k1};
 use serde::{Deserialize, Serialize};
 use sha256::digest_bytes;
+use std::collections::HashSet;
+use std::error::Error as StdError;
 use std::fmt;
+use std::fs;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use uuid::Uuid;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error as ThisError;
 
 const STARTING_BALANCE: u128 = 1000;
+const ADDRESS_HASH_LEN: usize = 32;
+const ADDRESS_CHECKSUM_LEN: usize = 4;
+
+pub const TESTNET_ADDRESS_PREFIX: &str = "0x192";
+pub const MAINNET_ADDRESS_PREFIX: &str = "0x191";
+// --reject-legacy-addresses turns off the migration shim that accepts the old
+// unchecksummed `0x192<hex>` addresses and resolves them to their canonical
+// form.
+pub const REJECT_LEGACY_ADDRESSES_FLAG: &str = "--reject-legacy-addresses";
+// Seconds a sent txn waits for its first validator before it times out.
+pub const PENDING_TXN_TIMEOUT: u128 = 120;
+// Prefixed to messages signed off-chain. A txn payload starts with its
+// timestamp, so a message signature never verifies as a txn signature.
+pub const MESSAGE_SIGNING_DOMAIN: &str = "VRRB Signed Message:\n";
+// Addresses are derived from the public key and a one byte address number.
+pub const MAX_ADDRESSES: u32 = u8::MAX as u32;
+// --addresses <n> derives the wallet's first n addresses on start.
+pub const ADDRESS_COUNT_FLAG: &str = "--addresses";
+
+static ACCEPT_LEGACY_ADDRESSES: AtomicBool = AtomicBool::new(true);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AddressNetwork {
+    Testnet,
+    Mainnet,
+}
+
+/// A wallet address. The canonical string form is the network prefix followed by
+/// the base58 encoding of the pubkey hash and a 4 byte checksum, so typos and
+/// addresses from the wrong network are caught before any coins move.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Address {
+    pub network: AddressNetwork,
+    pub hash: Vec<u8>,
+}
+
+#[derive(ThisError, Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("address has an unknown network prefix: {0}")]
+    UnknownPrefix(String),
+    #[error("address is for the {0:?} network, expected {1:?}")]
+    WrongNetwork(AddressNetwork, AddressNetwork),
+    #[error("address is not valid base58")]
+    InvalidEncoding,
+    #[error("address has an invalid length")]
+    InvalidLength,
+    #[error("address checksum doesn't match")]
+    InvalidChecksum,
+    #[error("legacy addresses are not accepted")]
+    LegacyAddress,
+}
+
+#[derive(ThisError, Debug, Clone, PartialEq, Eq)]
+pub enum WalletError {
+    #[error("txn is missing its {0}")]
+    MissingField(&'static str),
+    #[error("wallet has no address number {0}")]
+    UnknownAddress(u32),
+    #[error("txn amount must be greater than zero")]
+    ZeroAmount,
+    #[error("invalid receiver address: {0}")]
+    InvalidReceiver(#[from] AddressError),
+}
+
+/// Builds a signed transfer from a wallet, e.g.
+/// `TxnBuilder::new().to(receiver).amount(10).fee(1).build(&wallet)`. The
+/// receiver and amount are required, the txn is sent from address 1 with the
+/// minimum fee unless set.
+#[derive(Debug, Clone)]
+pub struct TxnBuilder {
+    address_number: u32,
+    receiver: Option<String>,
+    amount: Option<u128>,
+    fee: u128,
+    payload: Option<String>,
+    token: Option<String>,
+}
+
+/// Where a txn sent from the wallet is in its lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxnStatus {
+    Broadcast,
+    Validating {
+        confirmations: usize,
+        rejections: usize,
+    },
+    Confirmed {
+        block_height: u128,
+    },
+    Rejected {
+        reason: String,
+    },
+    TimedOut,
+}
+
+/// A txn sent from the wallet, kept in the wallet's outbox so that its
+/// confirmation, rejection or timeout can be tied back to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingTxn {
+    pub txn_id: String,
+    pub created_at: u128,
+    pub status: TxnStatus,
+    pub txn: Txn,
+    // When the status last changed, so a wallet synced from another device
+    // keeps whichever copy saw the txn last.
+    #[serde(default)]
+    pub updated_at: u128,
+}
+
+/// A named address in the wallet's address book.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contact {
+    pub address: String,
+    // When the contact was last set, the newer of two devices' edits is kept
+    // when their wallets are synced.
+    pub modified: u128,
+}
+
+impl AddressNetwork {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            AddressNetwork::Testnet => TESTNET_ADDRESS_PREFIX,
+            AddressNetwork::Mainnet => MAINNET_ADDRESS_PREFIX,
+        }
+    }
+
+    /// The address network of the network `network_id`. Only mainnet has its
+    /// own prefix, the test and local networks share the testnet one.
+    pub fn for_network(network_id: &str) -> AddressNetwork {
+        match network_id {
+            MAINNET_ID => AddressNetwork::Mainnet,
+            _ => AddressNetwork::Testnet,
+        }
+    }
+
+    pub fn from_address(address: &str) -> Option<AddressNetwork> {
+        if address.starts_with(TESTNET_ADDRESS_PREFIX) {
+            Some(AddressNetwork::Testnet)
+        } else if address.starts_with(MAINNET_ADDRESS_PREFIX) {
+            Some(AddressNetwork::Mainnet)
+        } else {
+            None
+        }
+    }
+}
+
+/// The address network of the network this node is built for.
+impl Default for AddressNetwork {
+    fn default() -> AddressNetwork {
+        AddressNetwork::for_network(NETWORK_ID)
+    }
+}
+
+impl Address {
+    pub fn new(network: AddressNetwork, hash: Vec<u8>) -> Address {
+        Address { network, hash }
+    }
+
+    /// Derives the n'th address for a public key.
+    pub fn from_pubkey(network: AddressNetwork, pubkey: &str, n: u8) -> Address {
+        let mut address_bytes = pubkey.as_bytes().to_vec();
+        address_bytes.push(n);
+        let hash = digest_bytes(digest_bytes(&address_bytes).as_bytes());
+        Address::new(network, hex::decode(hash).unwrap())
+    }
+
+    /// Parses an address for this node's network, legacy addresses only while
+    /// they're accepted.
+    pub fn parse(address: &str) -> Result<Address, AddressError> {
+        Address::parse_with(
+            address,
+            AddressNetwork::default(),
+            Address::accepts_legacy(),
+        )
+    }
+
+    /// Whether legacy addresses are resolved to their canonical form rather
+    /// than rejected, the default until a node is started with
+    /// --reject-legacy-addresses.
+    pub fn accepts_legacy() -> bool {
+        ACCEPT_LEGACY_ADDRESSES.load(Ordering::Relaxed)
+    }
+
+    pub fn set_accepts_legacy(accept_legacy: bool) {
+        ACCEPT_LEGACY_ADDRESSES.store(accept_legacy, Ordering::Relaxed);
+    }
+
+    /// Removes the reject legacy addresses flag from the command line
+    /// arguments and returns whether legacy addresses are still accepted
+    /// along with the remaining arguments.
+    pub fn accept_legacy_from_args(mut args: Vec<String>) -> (bool, Vec<String>) {
+        let reject = take_switch(&mut args, REJECT_LEGACY_ADDRESSES_FLAG);
+        (!reject, args)
+    }
+
+    pub fn parse_with(
+        address: &str,
+        network: AddressNetwork,
+        accept_legacy: bool,
+    ) -> Result<Address, AddressError> {
+        let address_network = if let Some(address_network) = AddressNetwork::from_address(address) {
+            address_network
+        } else {
+            return Err(AddressError::UnknownPrefix(address.to_string()));
+        };
+
+        if address_network != network {
+            return Err(AddressError::WrongNetwork(address_network, network));
+        }
+
+        let body = &address[network.prefix().len()..];
+
+        if Address::is_legacy(body) {
+            if !accept_legacy {
+                return Err(AddressError::LegacyAddress);
+            }
+            let hash = hex::decode(body).map_err(|_| AddressError::InvalidEncoding)?;
+            return Ok(Address::new(network, hash));
+        }
+
+        let bytes = bs58::decode(body)
+            .into_vec()
+            .map_err(|_| AddressError::InvalidEncoding)?;
+
+        if bytes.len() != ADDRESS_HASH_LEN + ADDRESS_CHECKSUM_LEN {
+            return Err(AddressError::InvalidLength);
+        }
+
+        let (hash, checksum) = bytes.split_at(ADDRESS_HASH_LEN);
+        if Address::checksum(network, hash) != checksum {
+            return Err(AddressError::InvalidChecksum);
+        }
+
+        Ok(Address::new(network, hash.to_vec()))
+    }
+
+    /// Returns the canonical form of an address string, or the string unchanged
+    /// if it can't be parsed.
+    pub fn canonicalize(address: &str) -> String {
+        if let Ok(address) = Address::parse(address) {
+            address.to_string()
+        } else {
+            address.to_string()
+        }
+    }
+
+    fn checksum(network: AddressNetwork, hash: &[u8]) -> Vec<u8> {
+        let mut payload = network.prefix().as_bytes().to_vec();
+        payload.extend(hash);
+        blake3::hash(&payload).as_bytes()[..ADDRESS_CHECKSUM_LEN].to_vec()
+    }
+
+    fn is_legacy(body: &str) -> bool {
+        body.len() == ADDRESS_HASH_LEN * 2 && body.chars().all(|c| c.is_ascii_hexdigit())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut bytes = self.hash.clone();
+        bytes.extend(Address::checksum(self.network, &self.hash));
+        write!(
+            f,
+            "{}{}",
+            self.network.prefix(),
+            bs58::encode(bytes).into_string()
+        )
+    }
+}
 
 /// The WalletAccount struct is the user/node wallet in which coins, tokens and contracts
 /// are held. The WalletAccount has a private/public keypair
@@ -35,6 +314,12 @@ pub struct WalletAccount {
     pub available_balances: LinkedHashMap<String, LinkedHashMap<String, u128>>,
     pub claims: LinkedHashMap<u128, Claim>,
     pub txn_nonce: u128,
+    // Txns sent from this wallet, keyed by txn id.
+    #[serde(default)]
+    pub outbox: LinkedHashMap<String, PendingTxn>,
+    // The address book, keyed by name.
+    #[serde(default)]
+    pub contacts: LinkedHashMap<String, Contact>,
 }
 
 impl WalletAccount {
@@ -48,23 +333,16 @@ impl WalletAccount {
         let mut rng = rand::thread_rng();
         // Generate a new secret/public key pair using the random seed.
         let (secret_key, public_key) = secp.generate_keypair(&mut rng);
-        // Generate 100 addresses by hashing a universally unique IDs + secret_key + public_key
-        let mut address_bytes = public_key.to_string().as_bytes().to_vec();
-        address_bytes.push(1u8);
-        let address = digest_bytes(digest_bytes(&address_bytes).as_bytes());
-        // add the testnet prefix to the wallet address (TODO: add handling of testnet/mainnet)
-        let mut address_prefix: String = "0x192".to_string();
-        // push the hashed uuid string to the end of the address prefix
-        address_prefix.push_str(&address);
+        // Derive the first address from the public key, encoded with the network prefix
+        // and a checksum.
+        let address_prefix =
+            Address::from_pubkey(AddressNetwork::default(), &public_key.to_string(), 1).to_string();
 
         // Print the private key string so that the user can save it.
         // TODO: require a confirmation the private key being saved by the user
         let welcome_message = format!(
             "{}\nSECRET KEY: {:?}\nPUBLIC KEY: {:?}\nADDRESS: {}\n",
-            "DO NOT SHARE OR LOSE YOUR SECRET KEY:",
-            &secret_key,
-            &public_key,
-            &address_prefix,
+            "DO NOT SHARE OR LOSE YOUR SECRET KEY:", &secret_key, &public_key, &address_prefix,
         );
         let mut addresses = LinkedHashMap::new();
         addresses.insert(1, address_prefix.clone());
@@ -84,6 +362,8 @@ impl WalletAccount {
             available_balances: total_balances,
             claims: LinkedHashMap::new(),
             txn_nonce: 0,
+            outbox: LinkedHashMap::new(),
+            contacts: LinkedHashMap::new(),
         };
 
         wallet
@@ -98,7 +378,6 @@ impl WalletAccount {
         let secp = Secp256k1::new();
         let pubkey = PublicKey::from_secret_key(&secp, &secretkey);
 
-
         let mut wallet = WalletAccount {
             secretkey: secretkey.to_string(),
             welcome_message: String::new(),
@@ -108,6 +387,8 @@ impl WalletAccount {
             available_balances: LinkedHashMap::new(),
             claims: LinkedHashMap::new(),
             txn_nonce: 0,
+            outbox: LinkedHashMap::new(),
+            contacts: LinkedHashMap::new(),
         };
 
         wallet.get_new_addresses(1);
@@ -133,12 +414,8 @@ impl WalletAccount {
     pub fn get_new_addresses(&mut self, number_of_addresses: u8) {
         let mut counter = 1u8;
         (counter..=number_of_addresses).for_each(|n| {
-            let mut address_bytes = self.pubkey.as_bytes().to_vec();
-            address_bytes.push(n);
-            let address = digest_bytes(digest_bytes(&address_bytes).as_bytes());
-            let mut address_prefix: String = "0x192".to_string();
-            address_prefix.push_str(&address);
-            self.addresses.insert(n as u32, address_prefix);
+            let address = Address::from_pubkey(AddressNetwork::default(), &self.pubkey, n);
+            self.addresses.insert(n as u32, address.to_string());
             counter += 1
         })
     }
@@ -147,6 +424,86 @@ impl WalletAccount {
         self.addresses.clone()
     }
 
+    /// Splits `--addresses <n>` out of the arguments, the number of addresses
+    /// to derive on start.
+    pub fn address_count_from_args(mut args: Vec<String>) -> (Option<u8>, Vec<String>) {
+        let address_count =
+            take_flag(&mut args, ADDRESS_COUNT_FLAG).and_then(|count| count.parse().ok());
+        (address_count, args)
+    }
+
+    /// Saves the wallet's addresses to `path` so they're derived again when
+    /// the wallet is restored from its key.
+    pub fn save_addresses(&self, path: &str) -> Result<(), Box<dyn StdError>> {
+        fs::write(path, serde_json::to_vec(&self.addresses)?)?;
+        Ok(())
+    }
+
+    /// Derives as many addresses as were saved at `path`. They're derived from
+    /// the wallet's key rather than read back, so a file saved for another
+    /// wallet can't add its addresses to this one.
+    pub fn restore_addresses(&mut self, path: &str) {
+        if let Ok(data) = fs::read(path) {
+            if let Ok(saved) = serde_json::from_slice::<LinkedHashMap<u32, String>>(&data) {
+                self.derive_addresses(saved.keys().max().copied().unwrap_or(0));
+            }
+        }
+    }
+
+    /// Derives addresses until the wallet has `count` of them, or
+    /// MAX_ADDRESSES.
+    pub fn derive_addresses(&mut self, count: u32) {
+        while self.addresses.len() < count.min(MAX_ADDRESSES) as usize {
+            if self.new_address().is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Adds `name` to the address book, or points it at a new address.
+    pub fn set_contact(&mut self, name: &str, address: &str, now: u128) -> Result<(), WalletError> {
+        let address = Address::parse(address)?;
+        self.contacts.insert(
+            name.to_string(),
+            Contact {
+                address: address.to_string(),
+                modified: now,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Saves the address book to `path`, the wallet itself is restored from
+    /// its key.
+    pub fn save_contacts(&self, path: &str) -> Result<(), Box<dyn StdError>> {
+        fs::write(path, serde_json::to_vec(&self.contacts)?)?;
+        Ok(())
+    }
+
+    pub fn restore_contacts(&mut self, path: &str) {
+        if let Ok(data) = fs::read(path) {
+            if let Ok(contacts) = serde_json::from_slice(&data) {
+                self.contacts = contacts;
+            }
+        }
+    }
+
+    /// Saves the txns sent from this wallet to `path`, with their statuses, so
+    /// a restart or a merged sync file doesn't lose them.
+    pub fn save_outbox(&self, path: &str) -> Result<(), Box<dyn StdError>> {
+        fs::write(path, serde_json::to_vec(&self.outbox)?)?;
+        Ok(())
+    }
+
+    pub fn restore_outbox(&mut self, path: &str) {
+        if let Ok(data) = fs::read(path) {
+            if let Ok(outbox) = serde_json::from_slice(&data) {
+                self.outbox = outbox;
+            }
+        }
+    }
+
     pub fn render_balances(&self) -> LinkedHashMap<String, LinkedHashMap<String, u128>> {
         self.total_balances.clone()
     }
@@ -175,6 +532,19 @@ impl WalletAccount {
         balance_map
     }
 
+    /// Iterates over the wallet's address numbers and addresses in the order
+    /// they were derived.
+    pub fn addresses_iter(&self) -> impl Iterator<Item = (&u32, &String)> {
+        self.addresses.iter()
+    }
+
+    /// Sums the balances of every address in the wallet.
+    pub fn total_balance(&self, network_state: &NetworkState) -> u128 {
+        self.addresses_iter()
+            .map(|(_, address)| network_state.get_balance(address))
+            .sum()
+    }
+
     pub fn get_address_balance(
         &mut self,
         network_state: NetworkState,
@@ -250,6 +620,31 @@ impl WalletAccount {
         }
     }
 
+    /// Signs a message off-chain to prove the wallet owns its key, e.g. to log
+    /// in to an application. The message is signed under its own domain so
+    /// the signature can't be replayed as a txn signature.
+    pub fn sign_message(&self, msg: &str) -> String {
+        self.sign(&WalletAccount::message_payload(msg))
+            .unwrap()
+            .to_string()
+    }
+
+    /// Checks a signature made with `sign_message` against the signer's
+    /// pubkey, false if either doesn't parse.
+    pub fn verify_message(pubkey: &str, msg: &str, sig: &str) -> bool {
+        if let (Ok(signature), Ok(pubkey)) = (Signature::from_str(sig), PublicKey::from_str(pubkey))
+        {
+            WalletAccount::verify(WalletAccount::message_payload(msg), signature, pubkey)
+                .unwrap_or(false)
+        } else {
+            false
+        }
+    }
+
+    fn message_payload(msg: &str) -> String {
+        format!("{}{}:{}", MESSAGE_SIGNING_DOMAIN, msg.len(), msg)
+    }
+
     pub fn txns_in_block(&mut self, block: &Block) {
         let _my_txns = {
             let mut some_txn = false;
@@ -271,36 +666,171 @@ impl WalletAccount {
         address_number: u32,
         receiver: String,
         amount: u128,
-    ) -> Result<Txn, Error> {
-        let txn = Txn::new(
+        fee: u128,
+    ) -> Result<PendingTxn, WalletError> {
+        let txn = TxnBuilder::new()
+            .from(address_number)
+            .to(&receiver)
+            .amount(amount)
+            .fee(fee)
+            .build(self)?;
+        self.txn_nonce += 1;
+
+        let pending = PendingTxn::new(txn);
+        self.outbox.insert(pending.txn_id.clone(), pending.clone());
+
+        Ok(pending)
+    }
+
+    /// Sends `amount` of a token, paying the minimum fee in the native coin.
+    pub fn send_token(
+        &mut self,
+        address_number: u32,
+        receiver: String,
+        token_id: &str,
+        amount: u128,
+    ) -> Result<PendingTxn, WalletError> {
+        let txn = TxnBuilder::new()
+            .from(address_number)
+            .to(&receiver)
+            .token(token_id)
+            .amount(amount)
+            .build(self)?;
+        self.txn_nonce += 1;
+
+        let pending = PendingTxn::new(txn);
+        self.outbox.insert(pending.txn_id.clone(), pending.clone());
+
+        Ok(pending)
+    }
+
+    /// Creates a txn issuing `total_supply` of a new token to the address,
+    /// which becomes the token's issuer.
+    pub fn issue_token(
+        &mut self,
+        address_number: u32,
+        token_id: &str,
+        metadata: &TokenMetadata,
+        total_supply: u128,
+        fee: u128,
+    ) -> Txn {
+        let txn = Txn::token_issuance(
             Arc::new(Mutex::new(self.clone())),
-            self.addresses.get(&address_number).unwrap().clone(),
-            receiver,
-            amount,
+            self.get_address(address_number),
+            token_id.to_string(),
+            metadata,
+            total_supply,
+            fee,
             self.txn_nonce,
         );
         self.txn_nonce += 1;
 
-        Ok(txn)
+        txn
+    }
+
+    /// Updates the status of a txn in the outbox, returning it if it was sent
+    /// from this wallet.
+    pub fn update_txn_status(&mut self, txn_id: &str, status: TxnStatus) -> Option<&PendingTxn> {
+        let pending = self.outbox.get_mut(txn_id)?;
+        pending.update(status);
+        Some(pending)
+    }
+
+    /// Moves a broadcast txn to validating once a miner acks it, counting the
+    /// acking miner's vote. The vote has to be a yes signed by one of the
+    /// `eligible` validators. Returns the txn if it was sent from this wallet
+    /// and hadn't heard from a validator yet.
+    pub fn ack_txn(
+        &mut self,
+        txn_validator: &TxnValidator,
+        eligible: &HashSet<String>,
+    ) -> Option<&PendingTxn> {
+        if !txn_validator.vote
+            || !eligible.contains(&txn_validator.pubkey)
+            || !txn_validator.valid_signature()
+        {
+            return None;
+        }
+        let pending = self.outbox.get_mut(&txn_validator.txn.txn_id)?;
+        if pending.status != TxnStatus::Broadcast {
+            return None;
+        }
+        pending.update(TxnStatus::Validating {
+            confirmations: 1,
+            rejections: 0,
+        });
+        Some(pending)
+    }
+
+    /// Times out the txns that have waited longer than PENDING_TXN_TIMEOUT
+    /// without any validators and returns their ids.
+    pub fn expire_pending_txns(&mut self, now: u128) -> Vec<String> {
+        self.outbox
+            .iter_mut()
+            .filter(|(_, pending)| pending.is_expired(now))
+            .map(|(txn_id, pending)| {
+                pending.update(TxnStatus::TimedOut);
+                txn_id.clone()
+            })
+            .collect()
     }
 
+    /// Creates a txn locking `bond` from the address to reinstate this wallet's
+    /// slashed claim.
+    pub fn request_reinstatement(&mut self, address_number: u32, bond: u128, fee: u128) -> Txn {
+        let txn = Txn::claim_reinstatement(
+            Arc::new(Mutex::new(self.clone())),
+            self.get_address(address_number),
+            bond,
+            fee,
+            self.txn_nonce,
+        );
+        self.txn_nonce += 1;
+
+        txn
+    }
+
+    /// Creates a txn paying the seller the price of the claim in `transfer`,
+    /// which both sides signed, from the buyer's address in it.
+    pub fn buy_claim(&mut self, transfer: &ClaimTransfer, fee: u128) -> Txn {
+        let txn = Txn::claim_transfer(
+            Arc::new(Mutex::new(self.clone())),
+            transfer,
+            fee,
+            self.txn_nonce,
+        );
+        self.txn_nonce += 1;
+
+        txn
+    }
+
+    /// The address numbered `address_number`, deriving the addresses up to it
+    /// if they haven't been. Empty past MAX_ADDRESSES.
     pub fn get_address(&mut self, address_number: u32) -> String {
-        if let Some(address) = self.addresses.get(&address_number) {
-            return address.to_string();
-        } else {
-            while self.addresses.len() < address_number as usize {
-                self.generate_new_address()
+        while !self.addresses.contains_key(&address_number) {
+            if address_number == 0 || self.new_address().is_none() {
+                return String::new();
             }
-            self.get_address(address_number)
         }
+        self.addresses[&address_number].clone()
     }
 
-    pub fn generate_new_address(&mut self) {
-        let uid = Uuid::new_v4().to_string();
-        let address_number: u32 = self.addresses.len() as u32 + 1u32;
-        let payload = format!("{},{},{}", &address_number, &uid, &self.pubkey);
-        let address = digest_bytes(payload.as_bytes());
-        self.addresses.insert(address_number, address);
+    /// Derives the address after the highest numbered one, returned with its
+    /// number. None once the wallet has MAX_ADDRESSES.
+    pub fn new_address(&mut self) -> Option<(u32, String)> {
+        let address_number = self.addresses.keys().max().map_or(1, |n| n + 1);
+        if address_number > MAX_ADDRESSES {
+            return None;
+        }
+        let address = Address::from_pubkey(
+            AddressNetwork::default(),
+            &self.pubkey,
+            address_number as u8,
+        )
+        .to_string();
+        self.addresses.insert(address_number, address.clone());
+
+        Some((address_number, address))
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -316,6 +846,143 @@ impl WalletAccount {
     }
 }
 
+impl TxnBuilder {
+    pub fn new() -> TxnBuilder {
+        TxnBuilder {
+            address_number: 1,
+            receiver: None,
+            amount: None,
+            fee: MIN_TXN_FEE,
+            payload: None,
+            token: None,
+        }
+    }
+
+    /// The number of the wallet address to send from.
+    pub fn from(mut self, address_number: u32) -> TxnBuilder {
+        self.address_number = address_number;
+        self
+    }
+
+    pub fn to(mut self, receiver: &str) -> TxnBuilder {
+        self.receiver = Some(receiver.to_string());
+        self
+    }
+
+    pub fn amount(mut self, amount: u128) -> TxnBuilder {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn fee(mut self, fee: u128) -> TxnBuilder {
+        self.fee = fee;
+        self
+    }
+
+    /// Data attached to the txn and covered by its signature.
+    pub fn payload(mut self, payload: &str) -> TxnBuilder {
+        self.payload = Some(payload.to_string());
+        self
+    }
+
+    /// The token to send instead of the native coin.
+    pub fn token(mut self, token_id: &str) -> TxnBuilder {
+        self.token = Some(token_id.to_string());
+        self
+    }
+
+    /// Checks the fields and signs the txn with the wallet's current txn nonce.
+    pub fn build(&self, wallet: &WalletAccount) -> Result<Txn, WalletError> {
+        let receiver = self
+            .receiver
+            .as_ref()
+            .ok_or(WalletError::MissingField("receiver"))?;
+        let amount = self.amount.ok_or(WalletError::MissingField("amount"))?;
+        if amount == 0 {
+            return Err(WalletError::ZeroAmount);
+        }
+
+        let receiver = Address::parse(receiver)?;
+        let sender_address = wallet
+            .addresses
+            .get(&self.address_number)
+            .ok_or(WalletError::UnknownAddress(self.address_number))?;
+
+        Ok(Txn::new_with_token(
+            Arc::new(Mutex::new(wallet.clone())),
+            sender_address.clone(),
+            receiver.to_string(),
+            self.token.clone(),
+            amount,
+            self.fee,
+            wallet.txn_nonce,
+            self.payload.clone(),
+        ))
+    }
+}
+
+impl TxnStatus {
+    /// Whether the txn's lifecycle is over. A txn that timed out locally can
+    /// still be confirmed if it turns up in a block.
+    pub fn is_final(&self) -> bool {
+        match self {
+            TxnStatus::Confirmed { .. } | TxnStatus::Rejected { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TxnStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxnStatus::Broadcast => write!(f, "Broadcast"),
+            TxnStatus::Validating {
+                confirmations,
+                rejections,
+            } => write!(
+                f,
+                "Validating ({} confirmations, {} rejections)",
+                confirmations, rejections
+            ),
+            TxnStatus::Confirmed { block_height } => {
+                write!(f, "Confirmed in block {}", block_height)
+            }
+            TxnStatus::Rejected { reason } => write!(f, "Rejected: {}", reason),
+            TxnStatus::TimedOut => write!(f, "Timed out"),
+        }
+    }
+}
+
+impl PendingTxn {
+    pub fn new(txn: Txn) -> PendingTxn {
+        PendingTxn {
+            txn_id: txn.txn_id.clone(),
+            created_at: txn.txn_timestamp,
+            status: TxnStatus::Broadcast,
+            updated_at: txn.txn_timestamp,
+            txn,
+        }
+    }
+
+    /// Moves the txn to `status` unless it is already confirmed or rejected.
+    pub fn update(&mut self, status: TxnStatus) {
+        if !self.status.is_final() && self.status != status {
+            self.status = status;
+            self.updated_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+        }
+    }
+
+    /// Whether the txn is still waiting on its first validator after
+    /// PENDING_TXN_TIMEOUT seconds.
+    pub fn is_expired(&self, now: u128) -> bool {
+        self.status == TxnStatus::Broadcast
+            && now.saturating_sub(self.created_at) / SECOND > PENDING_TXN_TIMEOUT
+    }
+}
+
 impl fmt::Display for WalletAccount {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -344,6 +1011,412 @@ impl Clone for WalletAccount {
             available_balances: self.available_balances.clone(),
             claims: self.claims.clone(),
             txn_nonce: self.txn_nonce.clone(),
+            outbox: self.outbox.clone(),
+            contacts: self.contacts.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reward::RewardState;
+    use crate::state::Ledger;
+    use crate::token::TokenLedger;
+    use crate::txn::InvalidTxnErrorReason;
+    use crate::verifiable::Verifiable;
+
+    const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    #[test]
+    fn test_address_round_trip() {
+        let address = Address::from_pubkey(AddressNetwork::Testnet, "pubkey", 1);
+        let parsed = Address::parse_with(&address.to_string(), AddressNetwork::Testnet, false);
+        assert_eq!(parsed, Ok(address));
+    }
+
+    #[test]
+    fn test_address_checksum_catches_single_character_typos() {
+        let address = Address::from_pubkey(AddressNetwork::Testnet, "pubkey", 1).to_string();
+        let prefix_len = TESTNET_ADDRESS_PREFIX.len();
+
+        for (idx, c) in address.char_indices().skip(prefix_len) {
+            let replacement = BASE58_ALPHABET.chars().find(|r| *r != c).unwrap();
+            let mut typo = address.clone();
+            typo.replace_range(idx..idx + 1, &replacement.to_string());
+            assert!(Address::parse_with(&typo, AddressNetwork::Testnet, false).is_err());
         }
     }
+
+    #[test]
+    fn test_address_wrong_network_rejected() {
+        let address = Address::from_pubkey(AddressNetwork::Mainnet, "pubkey", 1).to_string();
+        assert_eq!(
+            Address::parse_with(&address, AddressNetwork::Testnet, false),
+            Err(AddressError::WrongNetwork(
+                AddressNetwork::Mainnet,
+                AddressNetwork::Testnet
+            ))
+        );
+    }
+
+    #[test]
+    fn test_txn_builder_requires_receiver_and_amount() {
+        let wallet = WalletAccount::new();
+        let receiver = Address::from_pubkey(AddressNetwork::Testnet, "receiver", 1).to_string();
+
+        assert_eq!(
+            TxnBuilder::new().amount(10).build(&wallet).unwrap_err(),
+            WalletError::MissingField("receiver")
+        );
+        assert_eq!(
+            TxnBuilder::new().to(&receiver).build(&wallet).unwrap_err(),
+            WalletError::MissingField("amount")
+        );
+        assert_eq!(
+            TxnBuilder::new()
+                .to(&receiver)
+                .amount(0)
+                .build(&wallet)
+                .unwrap_err(),
+            WalletError::ZeroAmount
+        );
+        assert_eq!(
+            TxnBuilder::new()
+                .from(2)
+                .to(&receiver)
+                .amount(10)
+                .build(&wallet)
+                .unwrap_err(),
+            WalletError::UnknownAddress(2)
+        );
+        assert!(matches!(
+            TxnBuilder::new().to("receiver").amount(10).build(&wallet),
+            Err(WalletError::InvalidReceiver(_))
+        ));
+    }
+
+    #[test]
+    fn test_txn_builder_signs_payload() {
+        let mut wallet = WalletAccount::new();
+        let sender = wallet.get_address(1);
+        let receiver = Address::from_pubkey(AddressNetwork::Testnet, "receiver", 1).to_string();
+        let txn = TxnBuilder::new()
+            .to(&receiver)
+            .amount(10)
+            .fee(2)
+            .payload("invoice 42")
+            .build(&wallet)
+            .unwrap();
+
+        assert_eq!(txn.sender_address, sender);
+        assert_eq!(txn.receiver_address, receiver);
+        assert_eq!(txn.txn_fee, 2);
+        assert_eq!(txn.txn_data, Some("invoice 42".to_string()));
+        assert!(txn.txn_payload.ends_with(",invoice 42"));
+        assert!(txn.valid_txn_signature());
+
+        wallet.send_txn(1, receiver, 10, 1).unwrap();
+        assert_eq!(wallet.txn_nonce, 1);
+    }
+
+    #[test]
+    fn test_signed_message_round_trip() {
+        let wallet = WalletAccount::new();
+        let signature = wallet.sign_message("login nonce 42");
+
+        assert!(WalletAccount::verify_message(
+            &wallet.get_pubkey(),
+            "login nonce 42",
+            &signature
+        ));
+        assert!(!WalletAccount::verify_message(
+            &wallet.get_pubkey(),
+            "login nonce 43",
+            &signature
+        ));
+        assert!(!WalletAccount::verify_message(
+            &WalletAccount::new().get_pubkey(),
+            "login nonce 42",
+            &signature
+        ));
+        assert!(!WalletAccount::verify_message(
+            &wallet.get_pubkey(),
+            "login nonce 42",
+            "not a signature"
+        ));
+    }
+
+    #[test]
+    fn test_message_signature_rejected_as_txn_signature() {
+        let wallet = WalletAccount::new();
+        let receiver = Address::from_pubkey(AddressNetwork::Testnet, "receiver", 1).to_string();
+        let mut txn = TxnBuilder::new()
+            .to(&receiver)
+            .amount(10)
+            .build(&wallet)
+            .unwrap();
+        assert!(txn.valid_txn_signature());
+
+        // The same payload signed as a message doesn't verify as the txn,
+        // nor does the txn's signature verify as a message.
+        let txn_signature = txn.txn_signature.clone();
+        txn.txn_signature = wallet.sign_message(&txn.txn_payload);
+        assert!(!txn.valid_txn_signature());
+        assert!(!WalletAccount::verify_message(
+            &wallet.get_pubkey(),
+            &txn.txn_payload,
+            &txn_signature
+        ));
+    }
+
+    #[test]
+    fn test_total_balance_sums_every_address() {
+        let path = std::env::temp_dir()
+            .join("vrrb_test_wallet_total_balance.db")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let mut wallet = WalletAccount::new();
+        let first = wallet.get_address(1);
+        let second = wallet.get_address(2);
+        assert_eq!(
+            wallet.addresses_iter().collect::<Vec<_>>(),
+            vec![(&1, &first), (&2, &second)]
+        );
+
+        let mut credits = LinkedHashMap::new();
+        credits.insert(first.clone(), 500u128);
+        credits.insert(second.clone(), 300u128);
+        let mut debits = LinkedHashMap::new();
+        debits.insert(first, 100u128);
+        let mut network_state = NetworkState::restore(&path);
+        network_state.update_ledger(
+            Ledger {
+                credits,
+                debits,
+                claims: LinkedHashMap::new(),
+                tokens: TokenLedger::default(),
+            },
+            RewardState::start(),
+        );
+
+        assert_eq!(wallet.total_balance(&network_state), 700);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_new_addresses_are_derived_again_after_a_restore() {
+        let path = std::env::temp_dir()
+            .join("vrrb_test_wallet_new_addresses.json")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&path);
+
+        let mut wallet = WalletAccount::new();
+        let (second_number, second) = wallet.new_address().unwrap();
+        let (third_number, third) = wallet.new_address().unwrap();
+        assert_eq!((second_number, third_number), (2, 3));
+        assert_ne!(second, third);
+        assert_ne!(second, wallet.get_address(1));
+        wallet.save_addresses(&path).unwrap();
+
+        let mut restored = WalletAccount::restore_from_private_key(wallet.get_secretkey());
+        assert_eq!(restored.addresses.len(), 1);
+        restored.restore_addresses(&path);
+        assert_eq!(
+            restored.get_wallet_addresses(),
+            wallet.get_wallet_addresses()
+        );
+        assert_eq!(restored.new_address().unwrap().0, 4);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn sent_txn(wallet: &mut WalletAccount) -> PendingTxn {
+        let receiver = Address::from_pubkey(AddressNetwork::Testnet, "receiver", 1).to_string();
+        wallet.send_txn(1, receiver, 10, 1).unwrap()
+    }
+
+    #[test]
+    fn test_confirmed_txn_status_transitions() {
+        let mut wallet = WalletAccount::new();
+        let pending = sent_txn(&mut wallet);
+        assert_eq!(pending.status, TxnStatus::Broadcast);
+        assert_eq!(wallet.outbox[&pending.txn_id].status, TxnStatus::Broadcast);
+
+        let validating = TxnStatus::Validating {
+            confirmations: 2,
+            rejections: 1,
+        };
+        wallet.update_txn_status(&pending.txn_id, validating.clone());
+        assert_eq!(wallet.outbox[&pending.txn_id].status, validating);
+
+        let confirmed = TxnStatus::Confirmed { block_height: 7 };
+        wallet.update_txn_status(&pending.txn_id, confirmed.clone());
+        // Votes arriving after the txn was mined don't move it back.
+        wallet.update_txn_status(&pending.txn_id, validating);
+        assert_eq!(wallet.outbox[&pending.txn_id].status, confirmed);
+
+        assert!(wallet
+            .update_txn_status("unknown", TxnStatus::TimedOut)
+            .is_none());
+    }
+
+    // A yes vote on the txn signed by `validator`.
+    fn ack(validator: &WalletAccount, txn: &Txn) -> TxnValidator {
+        TxnValidator {
+            pubkey: validator.get_pubkey(),
+            vote: true,
+            txn: txn.clone(),
+            signature: String::new(),
+        }
+        .signed(&validator.get_secretkey())
+    }
+
+    #[test]
+    fn test_ack_moves_broadcast_txn_to_validating() {
+        let mut wallet = WalletAccount::new();
+        let pending = sent_txn(&mut wallet);
+        let validator = WalletAccount::new();
+        let eligible: HashSet<String> = vec![validator.get_pubkey()].into_iter().collect();
+
+        // Acks that aren't a signed yes vote from an eligible validator are
+        // ignored.
+        let mut unsigned = ack(&validator, &pending.txn);
+        unsigned.signature = String::new();
+        let mut no_vote = ack(&validator, &pending.txn);
+        no_vote.vote = false;
+        let outsider = ack(&WalletAccount::new(), &pending.txn);
+        [unsigned, no_vote, outsider]
+            .iter()
+            .for_each(|txn_validator| {
+                assert!(wallet.ack_txn(txn_validator, &eligible).is_none());
+            });
+        assert_eq!(wallet.outbox[&pending.txn_id].status, TxnStatus::Broadcast);
+
+        let acked = wallet
+            .ack_txn(&ack(&validator, &pending.txn), &eligible)
+            .unwrap();
+        assert_eq!(
+            acked.status,
+            TxnStatus::Validating {
+                confirmations: 1,
+                rejections: 0,
+            }
+        );
+
+        // Later acks don't reset the votes counted since.
+        let validating = TxnStatus::Validating {
+            confirmations: 3,
+            rejections: 0,
+        };
+        wallet.update_txn_status(&pending.txn_id, validating.clone());
+        assert!(wallet
+            .ack_txn(&ack(&validator, &pending.txn), &eligible)
+            .is_none());
+        assert_eq!(wallet.outbox[&pending.txn_id].status, validating);
+        let unknown = Txn::unsigned_transfer(1, 0);
+        assert!(wallet
+            .ack_txn(&ack(&validator, &unknown), &eligible)
+            .is_none());
+    }
+
+    #[test]
+    fn test_rejected_txn_carries_reason() {
+        let mut wallet = WalletAccount::new();
+        let pending = sent_txn(&mut wallet);
+        let rejected = TxnStatus::Rejected {
+            reason: InvalidTxnErrorReason::InsufficientBalance.to_string(),
+        };
+
+        let updated = wallet
+            .update_txn_status(&pending.txn_id, rejected.clone())
+            .unwrap();
+        assert_eq!(updated.status, rejected);
+        assert_eq!(
+            updated.status.to_string(),
+            format!("Rejected: {}", InvalidTxnErrorReason::InsufficientBalance)
+        );
+    }
+
+    #[test]
+    fn test_txn_without_validators_times_out() {
+        let mut wallet = WalletAccount::new();
+        let waiting = sent_txn(&mut wallet);
+        let validating = sent_txn(&mut wallet);
+        wallet.update_txn_status(
+            &validating.txn_id,
+            TxnStatus::Validating {
+                confirmations: 1,
+                rejections: 0,
+            },
+        );
+
+        assert!(wallet.expire_pending_txns(waiting.created_at).is_empty());
+        let now =
+            waiting.created_at.max(validating.created_at) + (PENDING_TXN_TIMEOUT + 1) * SECOND;
+        assert_eq!(
+            wallet.expire_pending_txns(now),
+            vec![waiting.txn_id.clone()]
+        );
+        assert_eq!(wallet.outbox[&waiting.txn_id].status, TxnStatus::TimedOut);
+        assert!(wallet.expire_pending_txns(now).is_empty());
+    }
+
+    #[test]
+    fn test_address_network_follows_the_network_id() {
+        use crate::params::{LOCAL_NET_ID, TESTNET_ID};
+
+        assert_eq!(
+            AddressNetwork::for_network(MAINNET_ID),
+            AddressNetwork::Mainnet
+        );
+        assert_eq!(
+            AddressNetwork::for_network(TESTNET_ID),
+            AddressNetwork::Testnet
+        );
+        assert_eq!(
+            AddressNetwork::for_network(LOCAL_NET_ID),
+            AddressNetwork::Testnet
+        );
+        assert_eq!(
+            AddressNetwork::default(),
+            AddressNetwork::for_network(NETWORK_ID)
+        );
+
+        let args = vec!["vrrb".to_string(), REJECT_LEGACY_ADDRESSES_FLAG.to_string()];
+        assert_eq!(
+            Address::accept_legacy_from_args(args),
+            (false, vec!["vrrb".to_string()])
+        );
+        let args = vec!["vrrb".to_string()];
+        assert_eq!(
+            Address::accept_legacy_from_args(args),
+            (true, vec!["vrrb".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_legacy_address_resolves_under_shim() {
+        let mut address_bytes = "pubkey".as_bytes().to_vec();
+        address_bytes.push(1u8);
+        let legacy = format!(
+            "{}{}",
+            TESTNET_ADDRESS_PREFIX,
+            digest_bytes(digest_bytes(&address_bytes).as_bytes())
+        );
+        let canonical = Address::from_pubkey(AddressNetwork::Testnet, "pubkey", 1);
+
+        assert_eq!(
+            Address::parse_with(&legacy, AddressNetwork::Testnet, true),
+            Ok(canonical)
+        );
+        assert_eq!(
+            Address::parse_with(&legacy, AddressNetwork::Testnet, false),
+            Err(AddressError::LegacyAddress)
+        );
+    }
 }
@@ -0,0 +1,236 @@
+use crate::schedule::Schedules;
+use crate::wallet::{Contact, PendingTxn, WalletAccount};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use ritelinked::LinkedHashMap;
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+pub const SYNC_BLOB_VERSION: u8 = 1;
+// Separates the sync key from anything else derived from the wallet's secret.
+const SYNC_KEY_CONTEXT: &str = "vrrb 2021-11-01 wallet sync blob";
+const SYNC_NONCE_LEN: usize = 24;
+
+#[derive(Debug, ThisError)]
+pub enum SyncError {
+    #[error("sync blob is truncated")]
+    Truncated,
+    #[error("sync blob has unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("sync blob wasn't exported by this wallet or the passphrase is wrong")]
+    Decryption,
+    #[error("sync blob is corrupt: {0}")]
+    Corrupt(#[from] serde_json::Error),
+}
+
+// What a wallet on one device shares with the same wallet on another. Every
+// entry carries when it was last modified, so a merge keeps the newer of two
+// edits instead of one device's state replacing the other's. Balances aren't
+// in it, each device reads those from the chain.
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncState {
+    address_count: u32,
+    contacts: LinkedHashMap<String, Contact>,
+    outbox: LinkedHashMap<String, PendingTxn>,
+    schedules: Schedules,
+}
+
+impl WalletAccount {
+    /// The wallet's addresses, contacts, outbox and the schedules it pays,
+    /// encrypted to a key only the same secret key and passphrase derive.
+    /// The blob is a version byte, the nonce and the ciphertext.
+    pub fn export_sync_blob(&self, schedules: &Schedules, passphrase: &str) -> Vec<u8> {
+        let state = SyncState {
+            address_count: self.addresses.len() as u32,
+            contacts: self.contacts.clone(),
+            outbox: self.outbox.clone(),
+            schedules: schedules.clone(),
+        };
+        let plaintext = serde_json::to_vec(&state).unwrap();
+        let mut nonce = [0u8; SYNC_NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .sync_cipher(passphrase)
+            .encrypt(XNonce::from_slice(&nonce), &plaintext[..])
+            .unwrap();
+
+        let mut blob = vec![SYNC_BLOB_VERSION];
+        blob.extend_from_slice(&nonce);
+        blob.extend(ciphertext);
+        blob
+    }
+
+    /// Merges a blob exported by the same wallet on another device. Addresses
+    /// are derived up to the other device's count, and of a contact, txn or
+    /// schedule both have, the more recently modified one is kept.
+    pub fn merge_sync_blob(
+        &mut self,
+        schedules: &mut Schedules,
+        blob: &[u8],
+        passphrase: &str,
+    ) -> Result<(), SyncError> {
+        if blob.len() < 1 + SYNC_NONCE_LEN {
+            return Err(SyncError::Truncated);
+        }
+        if blob[0] != SYNC_BLOB_VERSION {
+            return Err(SyncError::UnsupportedVersion(blob[0]));
+        }
+        let (nonce, ciphertext) = blob[1..].split_at(SYNC_NONCE_LEN);
+        let plaintext = self
+            .sync_cipher(passphrase)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SyncError::Decryption)?;
+        let state: SyncState = serde_json::from_slice(&plaintext)?;
+
+        self.derive_addresses(state.address_count);
+        for (name, theirs) in state.contacts {
+            match self.contacts.get(&name) {
+                Some(ours) if ours.modified >= theirs.modified => {}
+                _ => {
+                    self.contacts.insert(name, theirs);
+                }
+            }
+        }
+        for (txn_id, theirs) in state.outbox {
+            match self.outbox.get(&txn_id) {
+                Some(ours) if ours.updated_at >= theirs.updated_at => {}
+                _ => {
+                    self.outbox.insert(txn_id, theirs);
+                }
+            }
+        }
+        schedules.merge(state.schedules);
+
+        Ok(())
+    }
+
+    fn sync_cipher(&self, passphrase: &str) -> XChaCha20Poly1305 {
+        let mut key_material = self.get_secretkey().into_bytes();
+        key_material.extend(passphrase.as_bytes());
+        let mut key = [0u8; 32];
+        blake3::derive_key(SYNC_KEY_CONTEXT, &key_material, &mut key);
+        XChaCha20Poly1305::new(Key::from_slice(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::TxnStatus;
+
+    fn receiver() -> String {
+        WalletAccount::new().get_address(1)
+    }
+
+    #[test]
+    fn wallets_on_two_devices_converge() {
+        let mut desktop = WalletAccount::new();
+        let mut laptop = WalletAccount::restore_from_private_key(desktop.get_secretkey());
+        let mut desktop_schedules = Schedules::new();
+        let mut laptop_schedules = Schedules::new();
+
+        desktop.set_contact("alice", &receiver(), 1).unwrap();
+        desktop.new_address();
+        desktop_schedules.add(1, receiver(), 10, 1, 5, None, 0);
+        laptop.set_contact("bob", &receiver(), 2).unwrap();
+        let pending = laptop.send_txn(1, receiver(), 10, 1).unwrap();
+        laptop_schedules.add(1, receiver(), 20, 1, 5, None, 0);
+
+        let from_desktop = desktop.export_sync_blob(&desktop_schedules, "passphrase");
+        let from_laptop = laptop.export_sync_blob(&laptop_schedules, "passphrase");
+        desktop
+            .merge_sync_blob(&mut desktop_schedules, &from_laptop, "passphrase")
+            .unwrap();
+        laptop
+            .merge_sync_blob(&mut laptop_schedules, &from_desktop, "passphrase")
+            .unwrap();
+
+        for (wallet, schedules) in [(&desktop, &desktop_schedules), (&laptop, &laptop_schedules)] {
+            assert!(wallet.contacts.contains_key("alice"));
+            assert!(wallet.contacts.contains_key("bob"));
+            assert_eq!(wallet.addresses.len(), 2);
+            assert!(wallet.outbox.contains_key(&pending.txn_id));
+            let mut amounts = schedules
+                .iter()
+                .map(|schedule| schedule.amount)
+                .collect::<Vec<u128>>();
+            amounts.sort();
+            assert_eq!(amounts, vec![10, 20]);
+        }
+        for name in ["alice", "bob"].iter() {
+            assert_eq!(desktop.contacts[*name], laptop.contacts[*name]);
+        }
+        assert_eq!(desktop.addresses, laptop.addresses);
+        // Merging the same blob again changes nothing.
+        desktop
+            .merge_sync_blob(&mut desktop_schedules, &from_laptop, "passphrase")
+            .unwrap();
+        assert_eq!(desktop_schedules.iter().count(), 2);
+    }
+
+    #[test]
+    fn newer_edits_win() {
+        let mut desktop = WalletAccount::new();
+        let mut laptop = WalletAccount::restore_from_private_key(desktop.get_secretkey());
+        let mut desktop_schedules = Schedules::new();
+        let mut laptop_schedules = Schedules::new();
+        let (older, newer) = (receiver(), receiver());
+
+        desktop.set_contact("alice", &older, 1).unwrap();
+        laptop.set_contact("alice", &newer, 2).unwrap();
+        let pending = desktop.send_txn(1, receiver(), 10, 1).unwrap();
+        laptop
+            .merge_sync_blob(
+                &mut laptop_schedules,
+                &desktop.export_sync_blob(&desktop_schedules, "passphrase"),
+                "passphrase",
+            )
+            .unwrap();
+        assert_eq!(laptop.contacts["alice"].address, newer);
+
+        let confirmed = TxnStatus::Confirmed { block_height: 3 };
+        laptop.update_txn_status(&pending.txn_id, confirmed.clone());
+        let id = laptop_schedules.add(1, receiver(), 10, 1, 5, None, 0);
+        desktop
+            .merge_sync_blob(
+                &mut desktop_schedules,
+                &laptop.export_sync_blob(&laptop_schedules, "passphrase"),
+                "passphrase",
+            )
+            .unwrap();
+        assert_eq!(desktop.contacts["alice"].address, newer);
+        assert_eq!(desktop.outbox[&pending.txn_id].status, confirmed);
+
+        // A schedule cancelled on one device isn't brought back by the other.
+        laptop_schedules.cancel(id);
+        let stale = desktop.export_sync_blob(&desktop_schedules, "passphrase");
+        laptop
+            .merge_sync_blob(&mut laptop_schedules, &stale, "passphrase")
+            .unwrap();
+        assert!(laptop_schedules.iter().next().is_none());
+    }
+
+    #[test]
+    fn blob_from_another_key_is_rejected() {
+        let wallet = WalletAccount::new();
+        let blob = wallet.export_sync_blob(&Schedules::new(), "passphrase");
+
+        let mut other = WalletAccount::new();
+        let mut schedules = Schedules::new();
+        assert!(matches!(
+            other.merge_sync_blob(&mut schedules, &blob, "passphrase"),
+            Err(SyncError::Decryption)
+        ));
+
+        let mut same_key = WalletAccount::restore_from_private_key(wallet.get_secretkey());
+        assert!(matches!(
+            same_key.merge_sync_blob(&mut schedules, &blob, "wrong passphrase"),
+            Err(SyncError::Decryption)
+        ));
+        assert!(matches!(
+            same_key.merge_sync_blob(&mut schedules, &blob[..8], "passphrase"),
+            Err(SyncError::Truncated)
+        ));
+    }
+}